@@ -0,0 +1,59 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use quiz_backend::schema::QuizSchemas;
+use serde_json::json;
+
+const CONCURRENT_REQUESTS: usize = 8;
+const QUIZZES_PER_REQUEST: usize = 4;
+
+fn sample_quiz() -> serde_json::Value {
+    let questions: Vec<_> = (1..=10)
+        .map(|idx| {
+            json!({
+                "id": format!("q{idx}"),
+                "type": "single",
+                "prompt": format!("Вопрос {idx}"),
+                "options": [{ "id": "o1", "text": "Верно" }, { "id": "o2", "text": "Неверно" }],
+                "answer": { "optionId": "o1" }
+            })
+        })
+        .collect();
+    json!({ "title": "Квиз", "description": "Бенчмарк", "questions": questions })
+}
+
+// Simulates CONCURRENT_REQUESTS AI generations in flight, each validating a few attempts.
+fn run_concurrent<F>(validate: F)
+where
+    F: Fn(&serde_json::Value) -> bool + Sync,
+{
+    let quiz = sample_quiz();
+    std::thread::scope(|scope| {
+        for _ in 0..CONCURRENT_REQUESTS {
+            scope.spawn(|| {
+                for _ in 0..QUIZZES_PER_REQUEST {
+                    assert!(validate(&quiz));
+                }
+            });
+        }
+    });
+}
+
+fn schema_validation(c: &mut Criterion) {
+    let schemas = QuizSchemas::compile_builtin().expect("schemas");
+    let current = schemas.current();
+    let mut group = c.benchmark_group("ai_quiz_validation");
+    group.bench_function("compile_per_request", |b| {
+        b.iter(|| {
+            run_concurrent(|quiz| {
+                let validator = jsonschema::draft202012::new(&current.raw).expect("schema");
+                validator.is_valid(quiz)
+            })
+        })
+    });
+    group.bench_function("cached_validator", |b| {
+        b.iter(|| run_concurrent(|quiz| current.validator.is_valid(quiz)))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, schema_validation);
+criterion_main!(benches);
@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS local_snapshot_imports (
+  id BIGINT PRIMARY KEY AUTO_INCREMENT,
+  source VARCHAR(255) NOT NULL UNIQUE,
+  teachers_imported INT NOT NULL,
+  quizzes_imported INT NOT NULL,
+  imported_at DATETIME(3) NOT NULL DEFAULT CURRENT_TIMESTAMP(3)
+);
@@ -0,0 +1,2 @@
+ALTER TABLE quiz_questions MODIFY q_type VARCHAR(16) NOT NULL;
+ALTER TABLE quiz_answers ADD COLUMN answer_payload JSON NULL;
@@ -0,0 +1,4 @@
+ALTER TABLE quizzes ADD COLUMN difficulty VARCHAR(16) NULL;
+ALTER TABLE quizzes ADD COLUMN tags JSON NULL;
+ALTER TABLE quiz_questions ADD COLUMN difficulty VARCHAR(16) NULL;
+ALTER TABLE quiz_questions ADD COLUMN tags JSON NULL;
@@ -0,0 +1 @@
+ALTER TABLE quizzes ADD COLUMN settings JSON NULL;
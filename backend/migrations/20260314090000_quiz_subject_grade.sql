@@ -0,0 +1,4 @@
+ALTER TABLE quizzes ADD COLUMN subject VARCHAR(64) NULL;
+ALTER TABLE quizzes ADD COLUMN grade TINYINT UNSIGNED NULL;
+ALTER TABLE quizzes ADD COLUMN language CHAR(2) NULL;
+CREATE INDEX idx_quizzes_library_filters ON quizzes (is_published, subject, grade, language);
@@ -0,0 +1,22 @@
+CREATE TABLE IF NOT EXISTS teachers (
+  id INTEGER PRIMARY KEY,
+  login TEXT NOT NULL UNIQUE,
+  password_hash TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS quizzes (
+  id INTEGER PRIMARY KEY,
+  owner_teacher_id INTEGER NOT NULL,
+  title TEXT NOT NULL,
+  description TEXT NULL,
+  questions TEXT NOT NULL,
+  is_published BOOLEAN NOT NULL DEFAULT FALSE,
+  source_quiz_id INTEGER NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_quizzes_owner ON quizzes (owner_teacher_id);
+
+CREATE TABLE IF NOT EXISTS state_counters (
+  name TEXT PRIMARY KEY,
+  value INTEGER NOT NULL
+);
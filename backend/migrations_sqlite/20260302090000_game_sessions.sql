@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS game_sessions (
+  id INTEGER PRIMARY KEY,
+  teacher_id INTEGER NOT NULL,
+  quiz_id INTEGER NOT NULL,
+  status TEXT NOT NULL,
+  record TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_game_sessions_teacher ON game_sessions (teacher_id);
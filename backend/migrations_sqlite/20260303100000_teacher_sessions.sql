@@ -0,0 +1,8 @@
+CREATE TABLE IF NOT EXISTS teacher_sessions (
+  id TEXT PRIMARY KEY,
+  teacher_id INTEGER NOT NULL,
+  expires_at TEXT NOT NULL,
+  record TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_teacher_sessions_expires_at ON teacher_sessions (expires_at);
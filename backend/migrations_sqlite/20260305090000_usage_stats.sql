@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS usage_stats (
+  day TEXT NOT NULL,
+  feature TEXT NOT NULL,
+  count INTEGER NOT NULL,
+  PRIMARY KEY (day, feature)
+);
@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS quiz_revisions (
+  quiz_id INTEGER NOT NULL,
+  rev INTEGER NOT NULL,
+  record TEXT NOT NULL,
+  PRIMARY KEY (quiz_id, rev)
+);
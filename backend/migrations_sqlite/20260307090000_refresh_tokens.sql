@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS refresh_tokens (
+  token TEXT PRIMARY KEY,
+  teacher_id INTEGER NOT NULL,
+  expires_at TEXT NOT NULL,
+  record TEXT NOT NULL
+);
@@ -0,0 +1,2 @@
+-- Full teacher record as JSON; login and password_hash stay as columns for lookups and older readers.
+ALTER TABLE teachers ADD COLUMN record TEXT;
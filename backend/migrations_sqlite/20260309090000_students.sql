@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS students (
+  id INTEGER PRIMARY KEY,
+  login TEXT UNIQUE,
+  access_code TEXT UNIQUE,
+  record TEXT NOT NULL
+);
@@ -0,0 +1 @@
+ALTER TABLE quizzes ADD COLUMN metadata TEXT NULL;
@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS prompt_templates (
+  use_case TEXT NOT NULL,
+  version INTEGER NOT NULL,
+  record TEXT NOT NULL,
+  PRIMARY KEY (use_case, version)
+);
@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS ai_usage (
+  teacher_id INTEGER NOT NULL,
+  day TEXT NOT NULL,
+  record TEXT NOT NULL,
+  PRIMARY KEY (teacher_id, day)
+);
@@ -0,0 +1,5 @@
+CREATE TABLE IF NOT EXISTS classes (
+  id INTEGER PRIMARY KEY,
+  teacher_id INTEGER NOT NULL,
+  record TEXT NOT NULL
+);
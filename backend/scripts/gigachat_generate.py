@@ -14,16 +14,20 @@ def main() -> int:
         return 2
 
     parser = argparse.ArgumentParser()
-    parser.add_argument("--topic", required=True)
-    parser.add_argument("--grade", required=True)
-    parser.add_argument("--count", required=True, type=int)
+    parser.add_argument("--topic", default="")
+    parser.add_argument("--grade", default="не указан")
+    parser.add_argument("--count", default=1, type=int)
+    # Ready user prompt for requests other than quiz generation; replaces topic/grade/count.
+    parser.add_argument("--user-prompt", default="")
     parser.add_argument("--model", required=True)
     parser.add_argument("--base-url", required=True)
     parser.add_argument("--auth-url", required=True)
     parser.add_argument("--scope", required=True)
     parser.add_argument("--credentials", default="")
     parser.add_argument("--timeout", required=True, type=int)
-    parser.add_argument("--system-prompt-file", required=True)
+    # The backend passes the prompt text itself; the file is kept for running the script by hand.
+    parser.add_argument("--system-prompt", default="")
+    parser.add_argument("--system-prompt-file", default="")
     args = parser.parse_args()
 
     bearer = os.getenv("BEARER") or os.getenv("GIGACHAT_BEARER")
@@ -32,19 +36,26 @@ def main() -> int:
         sys.stderr.write("missing access token and credentials\n")
         return 2
 
-    try:
-        with open(args.system_prompt_file, "r", encoding="utf-8") as f:
-            system_prompt = f.read().strip()
-    except Exception as exc:
-        sys.stderr.write(f"cannot read system prompt: {exc}\n")
+    system_prompt = args.system_prompt.strip()
+    if not system_prompt:
+        try:
+            with open(args.system_prompt_file, "r", encoding="utf-8") as f:
+                system_prompt = f.read().strip()
+        except Exception as exc:
+            sys.stderr.write(f"cannot read system prompt: {exc}\n")
+            return 2
+
+    if not args.user_prompt and not args.topic:
+        sys.stderr.write("either --topic or --user-prompt is required\n")
         return 2
 
-    user_prompt = (
+    user_prompt = args.user_prompt or (
         f"Тема: {args.topic}. Класс: {args.grade}. "
         f"Количество вопросов: {max(args.count, 1)}. "
         "Верни только JSON по схеме. У КАЖДОГО вопроса обязательно должно быть поле answer. "
         "Для type=open: answer={\"text\":\"...\"}. Для type=single: answer={\"optionId\":\"...\"}. "
-        "Для type=multi: answer={\"optionIds\":[\"...\"]}."
+        "Для type=multi: answer={\"optionIds\":[\"...\"]}. "
+        "У каждого вопроса добавь explanation — короткое пояснение, почему ответ верный."
     )
 
     payload = Chat(
@@ -101,6 +112,10 @@ def main() -> int:
         sys.stderr.write("empty content in response\n")
         return 2
 
+    usage = getattr(response, "usage", None)
+    if usage is not None:
+        sys.stderr.write(f"usage {usage.prompt_tokens} {usage.completion_tokens}\n")
+
     sys.stdout.write(content.strip())
     return 0
 
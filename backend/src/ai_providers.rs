@@ -0,0 +1,954 @@
+use crate::models::{
+    normalize_open_answer, validate_quiz, AnswerKey, Difficulty, Question, QuestionType, Quiz, QuizLimits, QuizOption,
+    ValidationIssue, GRADE_RANGE,
+};
+use crate::moderation::Moderation;
+use crate::prompt_templates::{PromptSet, PromptUseCase, SystemPrompt};
+use crate::schema::QuizSchemas;
+use crate::state::{AiQuizClient, GigaChatAiClient, MockAiClient};
+use futures::future::BoxFuture;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AiProviderKind {
+    GigaChat,
+    OpenAi,
+    YandexGpt,
+    Ollama,
+}
+
+impl AiProviderKind {
+    pub const ALL: [Self; 4] = [Self::GigaChat, Self::OpenAi, Self::YandexGpt, Self::Ollama];
+
+    pub fn parse(value: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|k| k.name() == value.trim().to_ascii_lowercase())
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::GigaChat => "gigachat",
+            Self::OpenAi => "openai",
+            Self::YandexGpt => "yandexgpt",
+            Self::Ollama => "ollama",
+        }
+    }
+}
+
+fn env_opt(name: &str) -> Option<String> {
+    std::env::var(name).ok().map(|v| v.trim().to_string()).filter(|v| !v.is_empty())
+}
+
+fn env_or(name: &str, default: &str) -> String {
+    env_opt(name).unwrap_or_else(|| default.to_string())
+}
+
+fn user_prompt(topic: &str, grade: Option<&str>, question_count: usize) -> String {
+    format!(
+        "Тема: {}. Класс: {}. Количество вопросов: {}. \
+         Верни только JSON по схеме. У КАЖДОГО вопроса обязательно должно быть поле answer. \
+         Для type=open: answer={{\"text\":\"...\"}}. Для type=single: answer={{\"optionId\":\"...\"}}. \
+         Для type=multi: answer={{\"optionIds\":[\"...\"]}}. \
+         У каждого вопроса добавь explanation — короткое пояснение, почему ответ верный.",
+        topic,
+        grade.unwrap_or("не указан"),
+        question_count.max(1)
+    )
+}
+
+// Models like to wrap JSON in a markdown fence even when told not to.
+pub fn strip_code_fence(content: &str) -> String {
+    let trimmed = content.trim();
+    if trimmed.starts_with("```") {
+        trimmed
+            .trim_start_matches("```json")
+            .trim_start_matches("```")
+            .trim_end_matches("```")
+            .trim()
+            .to_string()
+    } else {
+        trimmed.to_string()
+    }
+}
+
+fn http_client(timeout_secs: u64) -> anyhow::Result<reqwest::Client> {
+    Ok(reqwest::Client::builder().timeout(Duration::from_secs(timeout_secs)).build()?)
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TokenUsage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+}
+
+impl TokenUsage {
+    // About four characters per token; for providers that do not report usage.
+    pub fn estimate(prompt: &str, completion: &str) -> Self {
+        let tokens = |s: &str| (s.chars().count() as u64).div_ceil(4);
+        Self { prompt_tokens: tokens(prompt), completion_tokens: tokens(completion) }
+    }
+}
+
+// Model text plus what it cost.
+#[derive(Debug, Clone)]
+pub struct AiCompletion {
+    pub text: String,
+    pub usage: TokenUsage,
+}
+
+impl AiCompletion {
+    pub fn estimated(prompt: &str, text: String) -> Self {
+        Self { usage: TokenUsage::estimate(prompt, &text), text }
+    }
+}
+
+// Sends the request and picks the model text out of the response with `pointer`, and the token
+// counts with `usage` (prompt, completion); counts the provider leaves out are estimated from `prompt`.
+async fn chat_request(
+    provider: &'static str,
+    request: reqwest::RequestBuilder,
+    pointer: &'static str,
+    usage: (&'static str, &'static str),
+    prompt: &str,
+) -> anyhow::Result<AiCompletion> {
+    let response = request.send().await?;
+    let status = response.status();
+    let body: Value = response.json().await.unwrap_or_default();
+    if !status.is_success() {
+        anyhow::bail!("{} responded with {}: {}", provider, status, body);
+    }
+    let content = body.pointer(pointer).and_then(Value::as_str).map(strip_code_fence).unwrap_or_default();
+    if content.is_empty() {
+        anyhow::bail!("{} returned empty content", provider);
+    }
+    // YandexGPT reports counts as strings.
+    let count = |p: &str| body.pointer(p).and_then(|v| v.as_u64().or_else(|| v.as_str()?.parse().ok()));
+    let estimate = TokenUsage::estimate(prompt, &content);
+    let usage = TokenUsage {
+        prompt_tokens: count(usage.0).unwrap_or(estimate.prompt_tokens),
+        completion_tokens: count(usage.1).unwrap_or(estimate.completion_tokens),
+    };
+    Ok(AiCompletion { text: content, usage })
+}
+
+#[derive(Clone)]
+pub struct OpenAiClient {
+    pub base_url: String,
+    pub api_key: String,
+    pub model: String,
+    pub timeout_secs: u64,
+}
+
+impl OpenAiClient {
+    pub fn from_env() -> Option<Self> {
+        Some(Self::with_key(env_opt("OPENAI_API_KEY")?))
+    }
+
+    pub fn with_key(api_key: String) -> Self {
+        Self {
+            base_url: env_or("OPENAI_BASE_URL", "https://api.openai.com/v1").trim_end_matches('/').to_string(),
+            api_key,
+            model: env_or("OPENAI_MODEL", "gpt-4o-mini"),
+            timeout_secs: ai_timeout_secs(),
+        }
+    }
+}
+
+impl AiQuizClient for OpenAiClient {
+    fn generate_quiz_json(
+        &self,
+        system: &SystemPrompt,
+        topic: &str,
+        grade: Option<&str>,
+        question_count: usize,
+    ) -> BoxFuture<'static, anyhow::Result<AiCompletion>> {
+        self.complete_json(system, &user_prompt(topic, grade, question_count))
+    }
+
+    fn complete_json(&self, system: &SystemPrompt, user_prompt: &str) -> BoxFuture<'static, anyhow::Result<AiCompletion>> {
+        let client = self.clone();
+        let system = system.text.clone();
+        let user = user_prompt.to_string();
+        Box::pin(async move {
+            let request = http_client(client.timeout_secs)?
+                .post(format!("{}/chat/completions", client.base_url))
+                .bearer_auth(&client.api_key)
+                .json(&json!({
+                    "model": client.model,
+                    "messages": [
+                        {"role": "system", "content": system.trim()},
+                        {"role": "user", "content": user}
+                    ],
+                    "response_format": {"type": "json_object"}
+                }));
+            let usage = ("/usage/prompt_tokens", "/usage/completion_tokens");
+            chat_request("openai", request, "/choices/0/message/content", usage, &format!("{}{}", system, user)).await
+        })
+    }
+}
+
+#[derive(Clone)]
+pub struct YandexGptClient {
+    pub base_url: String,
+    // Either an API key or a short-lived IAM token.
+    pub api_key: Option<String>,
+    pub iam_token: Option<String>,
+    pub folder_id: String,
+    pub model: String,
+    pub timeout_secs: u64,
+}
+
+impl YandexGptClient {
+    pub fn from_env() -> Option<Self> {
+        let api_key = env_opt("YANDEXGPT_API_KEY");
+        let iam_token = env_opt("YANDEXGPT_IAM_TOKEN");
+        if api_key.is_none() && iam_token.is_none() {
+            return None;
+        }
+        Some(Self { api_key, iam_token, ..Self::with_key(String::new(), env_opt("YANDEXGPT_FOLDER_ID")?) })
+    }
+
+    pub fn with_key(api_key: String, folder_id: String) -> Self {
+        Self {
+            base_url: env_or("YANDEXGPT_BASE_URL", "https://llm.api.cloud.yandex.net/foundationModels/v1")
+                .trim_end_matches('/')
+                .to_string(),
+            api_key: Some(api_key),
+            iam_token: None,
+            folder_id,
+            model: env_or("YANDEXGPT_MODEL", "yandexgpt-lite/latest"),
+            timeout_secs: ai_timeout_secs(),
+        }
+    }
+}
+
+impl AiQuizClient for YandexGptClient {
+    fn generate_quiz_json(
+        &self,
+        system: &SystemPrompt,
+        topic: &str,
+        grade: Option<&str>,
+        question_count: usize,
+    ) -> BoxFuture<'static, anyhow::Result<AiCompletion>> {
+        self.complete_json(system, &user_prompt(topic, grade, question_count))
+    }
+
+    fn complete_json(&self, system: &SystemPrompt, user_prompt: &str) -> BoxFuture<'static, anyhow::Result<AiCompletion>> {
+        let client = self.clone();
+        let system = system.text.clone();
+        let user = user_prompt.to_string();
+        Box::pin(async move {
+            let authorization = match (&client.api_key, &client.iam_token) {
+                (Some(key), _) => format!("Api-Key {}", key),
+                (None, Some(token)) => format!("Bearer {}", token),
+                (None, None) => anyhow::bail!("yandexgpt credentials are missing"),
+            };
+            let request = http_client(client.timeout_secs)?
+                .post(format!("{}/completion", client.base_url))
+                .header(reqwest::header::AUTHORIZATION, authorization)
+                .header("x-folder-id", &client.folder_id)
+                .json(&json!({
+                    "modelUri": format!("gpt://{}/{}", client.folder_id, client.model),
+                    "completionOptions": {"stream": false, "temperature": 0.3, "maxTokens": "8000"},
+                    "messages": [
+                        {"role": "system", "text": system.trim()},
+                        {"role": "user", "text": user}
+                    ]
+                }));
+            let usage = ("/result/usage/inputTextTokens", "/result/usage/completionTokens");
+            let prompt = format!("{}{}", system, user);
+            chat_request("yandexgpt", request, "/result/alternatives/0/message/text", usage, &prompt).await
+        })
+    }
+}
+
+#[derive(Clone)]
+pub struct OllamaClient {
+    pub base_url: String,
+    pub model: String,
+    pub timeout_secs: u64,
+}
+
+impl OllamaClient {
+    // Ollama has no authentication, so the server address is the whole configuration.
+    pub fn from_env() -> Option<Self> {
+        Some(Self {
+            base_url: env_opt("OLLAMA_BASE_URL")?.trim_end_matches('/').to_string(),
+            model: env_or("OLLAMA_MODEL", "llama3.1"),
+            // Local models are slow, so the default is generous.
+            timeout_secs: env_opt("OLLAMA_TIMEOUT_SECS").and_then(|v| v.parse().ok()).unwrap_or(120),
+        })
+    }
+}
+
+impl AiQuizClient for OllamaClient {
+    fn generate_quiz_json(
+        &self,
+        system: &SystemPrompt,
+        topic: &str,
+        grade: Option<&str>,
+        question_count: usize,
+    ) -> BoxFuture<'static, anyhow::Result<AiCompletion>> {
+        self.complete_json(system, &user_prompt(topic, grade, question_count))
+    }
+
+    fn complete_json(&self, system: &SystemPrompt, user_prompt: &str) -> BoxFuture<'static, anyhow::Result<AiCompletion>> {
+        let client = self.clone();
+        let system = system.text.clone();
+        let user = user_prompt.to_string();
+        Box::pin(async move {
+            let request = http_client(client.timeout_secs)?.post(format!("{}/api/chat", client.base_url)).json(&json!({
+                "model": client.model,
+                "stream": false,
+                "format": "json",
+                "messages": [
+                    {"role": "system", "content": system.trim()},
+                    {"role": "user", "content": user}
+                ]
+            }));
+            let usage = ("/prompt_eval_count", "/eval_count");
+            chat_request("ollama", request, "/message/content", usage, &format!("{}{}", system, user)).await
+        })
+    }
+}
+
+fn ai_timeout_secs() -> u64 {
+    env_opt("AI_TIMEOUT_SECS").and_then(|v| v.parse().ok()).unwrap_or(30)
+}
+
+// Per-request choice of provider. Without `apiKey` the server's configuration is used; with
+// it the teacher's own key (GigaChat authorization key, OpenAI or YandexGPT API key) is, and
+// it is never stored.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AiProviderChoice {
+    pub name: String,
+    #[serde(default)]
+    pub api_key: Option<String>,
+    #[serde(default)]
+    pub model: Option<String>,
+    // YandexGPT only: the cloud folder the key belongs to.
+    #[serde(default)]
+    pub folder_id: Option<String>,
+}
+
+#[derive(Clone)]
+pub struct AiRegistry {
+    default: Arc<dyn AiQuizClient>,
+    default_name: &'static str,
+    gigachat: Option<GigaChatAiClient>,
+    openai: Option<OpenAiClient>,
+    yandexgpt: Option<YandexGptClient>,
+    ollama: Option<OllamaClient>,
+    repair_attempts: usize,
+}
+
+fn issue(field: &str, issue: impl Into<String>) -> ValidationIssue {
+    ValidationIssue { field: field.into(), issue: issue.into() }
+}
+
+impl AiRegistry {
+    // `AI_PROVIDER` picks the default; without it GigaChat is used when configured and the mock
+    // generator otherwise. Every configured provider stays available for per-request overrides.
+    pub fn from_env() -> anyhow::Result<Self> {
+        let mut registry = Self {
+            gigachat: GigaChatAiClient::from_env(),
+            openai: OpenAiClient::from_env(),
+            yandexgpt: YandexGptClient::from_env(),
+            ollama: OllamaClient::from_env(),
+            repair_attempts: env_opt("AI_REPAIR_ATTEMPTS")
+                .and_then(|v| v.parse::<usize>().ok())
+                .map_or(DEFAULT_REPAIR_ATTEMPTS, |v| v.min(MAX_REPAIR_ATTEMPTS)),
+            ..Self::mock()
+        };
+        let kind = match env_opt("AI_PROVIDER").as_deref() {
+            None if registry.gigachat.is_some() => AiProviderKind::GigaChat,
+            None | Some("mock") => return Ok(registry),
+            Some(name) => AiProviderKind::parse(name).ok_or_else(|| {
+                anyhow::anyhow!("AI_PROVIDER must be gigachat, openai, yandexgpt, ollama or mock, got {}", name)
+            })?,
+        };
+        let (default, _) = registry
+            .client_for(Some(&AiProviderChoice { name: kind.name().into(), api_key: None, model: None, folder_id: None }))
+            .map_err(|_| anyhow::anyhow!("AI_PROVIDER={} but the provider is not configured", kind.name()))?;
+        registry.default = default;
+        registry.default_name = kind.name();
+        Ok(registry)
+    }
+
+    pub fn mock() -> Self {
+        Self {
+            default: Arc::new(MockAiClient),
+            default_name: "mock",
+            gigachat: None,
+            openai: None,
+            yandexgpt: None,
+            ollama: None,
+            repair_attempts: DEFAULT_REPAIR_ATTEMPTS,
+        }
+    }
+
+    // How many times an invalid quiz is sent back to the model to be fixed.
+    pub fn repair_attempts(&self) -> usize {
+        self.repair_attempts
+    }
+
+    pub fn default_name(&self) -> &'static str {
+        self.default_name
+    }
+
+    pub fn available(&self) -> Vec<&'static str> {
+        let configured = [self.gigachat.is_some(), self.openai.is_some(), self.yandexgpt.is_some(), self.ollama.is_some()];
+        AiProviderKind::ALL.into_iter().zip(configured).filter(|(_, on)| *on).map(|(k, _)| k.name()).collect()
+    }
+
+    // The client for a request together with its provider name for error messages.
+    pub fn client_for(
+        &self,
+        choice: Option<&AiProviderChoice>,
+    ) -> Result<(Arc<dyn AiQuizClient>, &'static str), Vec<ValidationIssue>> {
+        let Some(choice) = choice else {
+            return Ok((self.default.clone(), self.default_name));
+        };
+        let kind = AiProviderKind::parse(&choice.name)
+            .ok_or_else(|| vec![issue("provider.name", "must be gigachat, openai, yandexgpt or ollama")])?;
+        let api_key = choice.api_key.as_deref().map(str::trim).filter(|k| !k.is_empty()).map(str::to_string);
+        let model = choice.model.as_deref().map(str::trim).filter(|m| !m.is_empty()).map(str::to_string);
+        let not_configured = || vec![issue("provider.name", format!("{} is not configured on the server", kind.name()))];
+
+        let client: Arc<dyn AiQuizClient> = match (kind, api_key) {
+            (AiProviderKind::GigaChat, key) => {
+                let mut client = match key {
+                    Some(key) => GigaChatAiClient::with_credentials(key),
+                    None => self.gigachat.clone().ok_or_else(not_configured)?,
+                };
+                client.model = model.unwrap_or(client.model);
+                Arc::new(client)
+            }
+            (AiProviderKind::OpenAi, key) => {
+                let mut client = match key {
+                    Some(key) => OpenAiClient::with_key(key),
+                    None => self.openai.clone().ok_or_else(not_configured)?,
+                };
+                client.model = model.unwrap_or(client.model);
+                Arc::new(client)
+            }
+            (AiProviderKind::YandexGpt, key) => {
+                let folder_id = choice.folder_id.as_deref().map(str::trim).filter(|f| !f.is_empty());
+                let mut client = match (key, folder_id) {
+                    (Some(key), Some(folder_id)) => YandexGptClient::with_key(key, folder_id.to_string()),
+                    (Some(_), None) => return Err(vec![issue("provider.folderId", "is required with a YandexGPT api key")]),
+                    (None, _) => self.yandexgpt.clone().ok_or_else(not_configured)?,
+                };
+                client.model = model.unwrap_or(client.model);
+                Arc::new(client)
+            }
+            (AiProviderKind::Ollama, Some(_)) => {
+                return Err(vec![issue("provider.apiKey", "is not used by ollama")]);
+            }
+            (AiProviderKind::Ollama, None) => {
+                let mut client = self.ollama.clone().ok_or_else(not_configured)?;
+                client.model = model.unwrap_or(client.model);
+                Arc::new(client)
+            }
+        };
+        Ok((client, kind.name()))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AiRequest {
+    pub topic: String,
+    pub grade: Option<String>,
+    pub question_count: usize,
+    // Text of an uploaded document; questions are then asked about it rather than the topic.
+    pub source_text: Option<String>,
+    pub repair_attempts: usize,
+    // Questions wanted per difficulty and per type; empty when the model may choose.
+    pub difficulty_mix: Vec<(Difficulty, usize)>,
+    pub type_mix: Vec<(QuestionType, usize)>,
+}
+
+// A requested mix such as `{"easy": 5, "hard": 5}`: known names only, adding up to `question_count`.
+// Entries of zero are dropped.
+pub fn parse_mix<K: DeserializeOwned>(
+    field: &str,
+    raw: &BTreeMap<String, usize>,
+    question_count: usize,
+) -> Result<Vec<(K, usize)>, Vec<ValidationIssue>> {
+    let mut mix = Vec::new();
+    let mut issues = Vec::new();
+    for (name, count) in raw {
+        match serde_json::from_value::<K>(Value::String(name.clone())) {
+            Ok(key) if *count > 0 => mix.push((key, *count)),
+            Ok(_) => {}
+            Err(_) => issues.push(issue(&format!("{}.{}", field, name), "unknown value")),
+        }
+    }
+    let total: usize = mix.iter().map(|(_, n)| n).sum();
+    if issues.is_empty() && !raw.is_empty() && total != question_count {
+        issues.push(issue(field, format!("must add up to questionCount ({}), got {}", question_count, total)));
+    }
+    if issues.is_empty() {
+        Ok(mix)
+    } else {
+        Err(issues)
+    }
+}
+
+fn mix_name<K: Serialize>(key: &K) -> String {
+    serde_json::to_value(key).ok().and_then(|v| v.as_str().map(str::to_string)).unwrap_or_default()
+}
+
+fn mix_line<K: Serialize>(mix: &[(K, usize)]) -> String {
+    mix.iter().map(|(k, n)| format!("{}={}", mix_name(k), n)).collect::<Vec<_>>().join(", ")
+}
+
+// Extra lines for the user prompt when a mix was requested.
+fn mix_prompt(request: &AiRequest) -> String {
+    let mut out = String::new();
+    if !request.difficulty_mix.is_empty() {
+        out.push_str(&format!(
+            "\nСложность вопросов: {}\nУкажи difficulty (easy, medium или hard) у каждого вопроса.",
+            mix_line(&request.difficulty_mix)
+        ));
+    }
+    if !request.type_mix.is_empty() {
+        out.push_str(&format!("\nТипы вопросов: {}", mix_line(&request.type_mix)));
+    }
+    out
+}
+
+fn material_prompt(request: &AiRequest, material: &str) -> String {
+    let topic = if request.topic.trim().is_empty() { "по материалу" } else { request.topic.as_str() };
+    format!(
+        "{}{}\nВопросы должны проверять понимание материала ниже и опираться только на него; \
+         не спрашивай о номерах страниц, оформлении и заданиях из самого материала.\n\
+         Количество вопросов: {}\nМатериал:\n{}",
+        user_prompt(topic, request.grade.as_deref(), request.question_count),
+        mix_prompt(request),
+        request.question_count.max(1),
+        material
+    )
+}
+
+// Models rarely hit a mix exactly, so up to a fifth of the questions may sit in the wrong group.
+fn check_mix<K: PartialEq + Serialize>(
+    field: &str,
+    mix: &[(K, usize)],
+    quiz: &Quiz,
+    key_of: impl Fn(&Question) -> Option<K>,
+) -> Vec<ValidationIssue> {
+    if mix.is_empty() {
+        return Vec::new();
+    }
+    let wanted: usize = mix.iter().map(|(_, n)| n).sum();
+    let counts: Vec<usize> = mix
+        .iter()
+        .map(|(k, _)| quiz.questions.iter().filter(|q| key_of(q).as_ref() == Some(k)).count())
+        .collect();
+    let misplaced: usize = mix.iter().zip(&counts).map(|((_, n), got)| n.saturating_sub(*got)).sum();
+    if misplaced <= wanted / 5 {
+        return Vec::new();
+    }
+    mix.iter()
+        .zip(counts)
+        .filter(|((_, n), got)| n != got)
+        .map(|((k, n), got)| {
+            issue(&format!("{}.{}", field, mix_name(k)), format!("expected {} questions, got {}", n, got))
+        })
+        .collect()
+}
+
+// Why a generation produced no quiz, in the shape of the HTTP error it turns into.
+#[derive(Debug, Clone, Serialize)]
+pub struct AiFailure {
+    #[serde(skip)]
+    pub status: u16,
+    pub code: &'static str,
+    pub message: String,
+    pub details: Vec<ValidationIssue>,
+}
+
+impl AiFailure {
+    fn invalid(message: impl Into<String>, details: Vec<ValidationIssue>) -> Self {
+        Self { status: 422, code: "VALIDATION_ERROR", message: message.into(), details }
+    }
+}
+
+const GENERATION_ATTEMPTS: usize = 2;
+const DEFAULT_REPAIR_ATTEMPTS: usize = 1;
+const MAX_REPAIR_ATTEMPTS: usize = 5;
+// The invalid answer quoted back to the model is cut to this many characters.
+const MAX_REPAIR_ECHO_CHARS: usize = 20_000;
+const MAX_REPAIR_ISSUES: usize = 20;
+
+// Sends an answer that failed the checks back with what is wrong with it, so the model fixes its own
+// JSON instead of starting over.
+fn repair_prompt(request_prompt: &str, invalid: &str, failure: &AiFailure) -> String {
+    let mut errors = format!("- {}\n", failure.message);
+    for issue in failure.details.iter().take(MAX_REPAIR_ISSUES) {
+        let field = if issue.field.is_empty() { "/" } else { issue.field.as_str() };
+        errors.push_str(&format!("- {}: {}\n", field, issue.issue));
+    }
+    format!(
+        "{}\n\nТвой предыдущий ответ не прошёл проверку.\nОтвет:\n{}\n\nОшибки:\n{}\n\
+         Исправь эти ошибки и верни исправленный квиз целиком — только JSON по схеме.",
+        request_prompt,
+        invalid.chars().take(MAX_REPAIR_ECHO_CHARS).collect::<String>(),
+        errors
+    )
+}
+
+// Times one call to the provider for the Prometheus metrics.
+pub async fn timed_call(
+    provider: &str,
+    operation: &'static str,
+    call: BoxFuture<'static, anyhow::Result<AiCompletion>>,
+) -> anyhow::Result<AiCompletion> {
+    let started = std::time::Instant::now();
+    let result = call.await;
+    crate::metrics::record_ai_request(provider, operation, started.elapsed(), result.is_ok());
+    result
+}
+
+// Asks the model for a quiz and checks it against the schema, `validate_quiz` and moderation. An
+// answer that fails the schema or validation goes back to the model with the errors up to
+// `repair_attempts` times; a quiz rejected by moderation is generated afresh instead. Gives up with
+// the last problem found.
+pub async fn generate_quiz(
+    client: &dyn AiQuizClient,
+    provider: &str,
+    schemas: &QuizSchemas,
+    limits: &QuizLimits,
+    moderation: &Moderation,
+    prompts: &PromptSet,
+    request: &AiRequest,
+) -> Result<Quiz, AiFailure> {
+    let grade = request.grade.as_deref().unwrap_or("не указан");
+    let count = request.question_count.max(1).to_string();
+    let topic = if request.topic.trim().is_empty() { "по материалу" } else { request.topic.as_str() };
+    let system = prompts
+        .get(PromptUseCase::Generation)
+        .render(&[("topic", topic), ("grade", grade), ("count", count.as_str())]);
+    let moderation_prompt = prompts.get(PromptUseCase::Moderation).render(&[]);
+    let schema = schemas.current();
+    let compiled = &schema.validator;
+    let request_prompt = match &request.source_text {
+        Some(material) => material_prompt(request, material),
+        None => {
+            let prompt = user_prompt(&request.topic, request.grade.as_deref(), request.question_count);
+            format!("{}{}", prompt, mix_prompt(request))
+        }
+    };
+    // `generate_quiz_json` builds its own prompt from the topic, which has no room for a mix.
+    let plain = request.source_text.is_none() && request.difficulty_mix.is_empty() && request.type_mix.is_empty();
+    let mut failure = AiFailure::invalid("ai payload does not match schema", Vec::new());
+    // The last answer that failed the checks, to be repaired on the next attempt.
+    let mut invalid: Option<String> = None;
+
+    for _attempt in 0..=request.repair_attempts {
+        let call = match &invalid {
+            Some(answer) => client.complete_json(&system, &repair_prompt(&request_prompt, answer, &failure)),
+            None if plain => {
+                client.generate_quiz_json(&system, &request.topic, request.grade.as_deref(), request.question_count)
+            }
+            None => client.complete_json(&system, &request_prompt),
+        };
+        let raw = timed_call(provider, "quiz", call).await.map_err(|e| AiFailure {
+            status: 502,
+            code: "UPSTREAM_ERROR",
+            message: format!("{} failed: {}", provider, e),
+            details: Vec::new(),
+        })?;
+
+        let json_value: Value = match serde_json::from_str(&raw.text) {
+            Ok(v) => v,
+            Err(e) => {
+                failure = AiFailure::invalid(format!("ai result is not valid json: {}", e), Vec::new());
+                invalid = Some(raw.text);
+                continue;
+            }
+        };
+
+        if compiled.validate(&json_value).is_err() {
+            let details = compiled
+                .iter_errors(&json_value)
+                .map(|e| ValidationIssue { field: e.instance_path.to_string(), issue: e.to_string() })
+                .collect();
+            failure = AiFailure::invalid("ai payload does not match schema", details);
+            invalid = Some(raw.text);
+            continue;
+        }
+
+        let mut quiz: Quiz = match serde_json::from_value(json_value) {
+            Ok(v) => v,
+            Err(e) => {
+                failure = AiFailure::invalid(format!("cannot decode quiz: {}", e), Vec::new());
+                invalid = Some(raw.text);
+                continue;
+            }
+        };
+        // The requested school year becomes the quiz grade unless the model set one.
+        if quiz.grade.is_none() {
+            quiz.grade = request
+                .grade
+                .as_deref()
+                .and_then(|g| g.trim().parse().ok())
+                .filter(|g| GRADE_RANGE.contains(g));
+        }
+
+        if let Err(issues) = validate_quiz(&quiz, limits) {
+            failure = AiFailure::invalid("quiz validation failed", issues);
+            invalid = Some(raw.text);
+            continue;
+        }
+        let mut issues = check_mix("difficultyMix", &request.difficulty_mix, &quiz, |q| q.difficulty);
+        issues.extend(check_mix("typeMix", &request.type_mix, &quiz, |q| Some(q.q_type.clone())));
+        if !issues.is_empty() {
+            failure = AiFailure::invalid("quiz does not match the requested mix", issues);
+            invalid = Some(raw.text);
+            continue;
+        }
+        match moderation.check(client, provider, &moderation_prompt, &quiz).await {
+            Ok(()) => return Ok(quiz),
+            Err(rejected) if rejected.status == 422 => {
+                failure = rejected;
+                invalid = None;
+            }
+            Err(upstream) => return Err(upstream),
+        }
+    }
+    Err(failure)
+}
+
+// A question the class got wrong, with how many of the students who answered it missed it.
+pub struct MissedQuestion<'a> {
+    pub quiz_id: i64,
+    pub question: &'a Question,
+    pub misses: usize,
+    pub answered: usize,
+}
+
+// Material for a remedial quiz: the gaps the class showed, so new questions drill the same knowledge
+// without repeating the missed ones word for word.
+pub fn mistakes_material(title: &str, missed: &[MissedQuestion]) -> String {
+    let mut material = format!(
+        "Работа над ошибками: {}\nУченики чаще всего ошибались в вопросах ниже. Составь новые вопросы на те же \
+         знания и умения, не повторяя эти вопросы дословно.\n",
+        title
+    );
+    for (i, item) in missed.iter().enumerate() {
+        let answer = correct_answers(item.question).map(|answers| answers.join(" | "));
+        material.push_str(&format!(
+            "{}. {} (правильный ответ: {}; ошиблись {} из {})\n",
+            i + 1,
+            item.question.prompt,
+            answer.as_deref().unwrap_or("см. ключ вопроса"),
+            item.misses,
+            item.answered
+        ));
+    }
+    let mut topics: Vec<&str> = missed.iter().flat_map(|item| item.question.tags.iter().map(String::as_str)).collect();
+    topics.sort_unstable();
+    topics.dedup();
+    if !topics.is_empty() {
+        material.push_str(&format!("Темы: {}\n", topics.join(", ")));
+    }
+    material
+}
+
+pub const MAX_DISTRACTORS: usize = 6;
+
+// Texts a distractor must not repeat; None for question types that have no options to add.
+pub fn correct_answers(question: &Question) -> Option<Vec<String>> {
+    let option_text = |id: &String| question.options.iter().flatten().find(|o| &o.id == id).map(|o| o.text.clone());
+    match &question.answer {
+        AnswerKey::Single { option_id } => Some(option_text(option_id).into_iter().collect()),
+        AnswerKey::Multi { option_ids } => Some(option_ids.iter().filter_map(option_text).collect()),
+        AnswerKey::Open(key) => Some(std::iter::once(key.text.clone()).chain(key.accepted.iter().cloned()).collect()),
+        AnswerKey::Ordering { .. } | AnswerKey::Numeric(_) | AnswerKey::Cloze { .. } => None,
+    }
+}
+
+fn distractor_prompt(question: &Question, correct: &[String], count: usize) -> String {
+    let existing: Vec<&str> = question
+        .options
+        .iter()
+        .flatten()
+        .map(|o| o.text.as_str())
+        .filter(|t| !correct.iter().any(|c| c == t))
+        .collect();
+    format!(
+        "Вопрос: {}\nПравильный ответ: {}\nУже есть неверные варианты: {}\nКоличество: {}",
+        question.prompt,
+        correct.join(" | "),
+        if existing.is_empty() { "нет".to_string() } else { existing.join(" | ") },
+        count
+    )
+}
+
+#[derive(Deserialize)]
+struct DistractorsResponse {
+    distractors: Vec<String>,
+}
+
+// Plausible wrong options for the question, as new options with ids that do not clash with the
+// existing ones. Anything matching a correct answer or an existing option after normalization is
+// dropped, as are duplicates and texts over the option length limit.
+pub async fn generate_distractors(
+    client: &dyn AiQuizClient,
+    provider: &str,
+    limits: &QuizLimits,
+    system: &SystemPrompt,
+    question: &Question,
+    count: usize,
+) -> Result<Vec<QuizOption>, AiFailure> {
+    let system = system.render(&[("count", count.to_string().as_str())]);
+    let Some(correct) = correct_answers(question) else {
+        return Err(AiFailure {
+            status: 400,
+            code: "VALIDATION_ERROR",
+            message: "question type does not support distractors".into(),
+            details: vec![issue("type", "must be single, multi or open")],
+        });
+    };
+    let mut taken: Vec<String> = correct
+        .iter()
+        .map(String::as_str)
+        .chain(question.options.iter().flatten().map(|o| o.text.as_str()))
+        .map(normalize_open_answer)
+        .collect();
+    let prompt = distractor_prompt(question, &correct, count);
+    let mut failure = AiFailure::invalid("model returned no usable distractors", Vec::new());
+
+    for _attempt in 0..GENERATION_ATTEMPTS {
+        let raw = timed_call(provider, "distractors", client.complete_json(&system, &prompt))
+            .await
+            .map_err(|e| AiFailure {
+                status: 502,
+                code: "UPSTREAM_ERROR",
+                message: format!("{} failed: {}", provider, e),
+                details: Vec::new(),
+            })?;
+        let parsed: DistractorsResponse = match serde_json::from_str(&raw.text) {
+            Ok(v) => v,
+            Err(e) => {
+                failure = AiFailure::invalid(format!("ai result is not valid json: {}", e), Vec::new());
+                continue;
+            }
+        };
+        let mut texts = Vec::new();
+        for text in parsed.distractors {
+            let text = text.trim().to_string();
+            let normalized = normalize_open_answer(&text);
+            if normalized.is_empty() || text.chars().count() > limits.max_option_chars || taken.contains(&normalized) {
+                continue;
+            }
+            taken.push(normalized);
+            texts.push(text);
+            if texts.len() == count {
+                break;
+            }
+        }
+        if texts.is_empty() {
+            continue;
+        }
+        let mut next_id = question.options.iter().flatten().count() + 1;
+        let options = texts
+            .into_iter()
+            .map(|text| {
+                while question.options.iter().flatten().any(|o| o.id == format!("o{next_id}")) {
+                    next_id += 1;
+                }
+                next_id += 1;
+                QuizOption { id: format!("o{}", next_id - 1), text }
+            })
+            .collect();
+        return Ok(options);
+    }
+    Err(failure)
+}
+
+pub const MAX_HINT_CHARS: usize = 300;
+
+// Everything a hint must not spell out: option texts of the key, accepted spellings, the number
+// and the cloze blanks. Ordering keys are sequences of visible options and have nothing to leak.
+fn answer_texts(question: &Question) -> Vec<String> {
+    match &question.answer {
+        AnswerKey::Numeric(key) => vec![key.value.to_string()],
+        AnswerKey::Cloze { blanks } => blanks.iter().flatten().cloned().collect(),
+        _ => correct_answers(question).unwrap_or_default(),
+    }
+}
+
+// Whole-word match after normalization, so "4" is caught in "ответ 4" but not in "40".
+fn reveals_answer(hint: &str, answers: &[String]) -> bool {
+    let hint = format!(" {} ", normalize_open_answer(hint));
+    answers
+        .iter()
+        .map(|a| normalize_open_answer(a))
+        .filter(|a| !a.is_empty())
+        .any(|a| hint.contains(&format!(" {} ", a)))
+}
+
+fn hint_prompt(question: &Question, answers: &[String]) -> String {
+    let options: Vec<&str> = question.options.iter().flatten().map(|o| o.text.as_str()).collect();
+    format!(
+        "Вопрос: {}\nВарианты: {}\nПравильный ответ (не называть): {}",
+        question.prompt,
+        if options.is_empty() { "нет".to_string() } else { options.join(" | ") },
+        answers.join(" | ")
+    )
+}
+
+#[derive(Deserialize)]
+struct HintResponse {
+    hint: String,
+}
+
+// A nudge towards the answer for a student. Hints that name the answer, are empty or exceed
+// `MAX_HINT_CHARS` count as failed attempts.
+pub async fn generate_hint(
+    client: &dyn AiQuizClient,
+    provider: &str,
+    system: &SystemPrompt,
+    question: &Question,
+) -> Result<String, AiFailure> {
+    let system = system.render(&[]);
+    let answers = answer_texts(question);
+    let prompt = hint_prompt(question, &answers);
+    let mut failure = AiFailure::invalid("model returned no usable hint", Vec::new());
+
+    for _attempt in 0..GENERATION_ATTEMPTS {
+        let raw = timed_call(provider, "hint", client.complete_json(&system, &prompt))
+            .await
+            .map_err(|e| AiFailure {
+                status: 502,
+                code: "UPSTREAM_ERROR",
+                message: format!("{} failed: {}", provider, e),
+                details: Vec::new(),
+            })?;
+        let hint = match serde_json::from_str::<HintResponse>(&raw.text) {
+            Ok(v) => v.hint.trim().to_string(),
+            Err(e) => {
+                failure = AiFailure::invalid(format!("ai result is not valid json: {}", e), Vec::new());
+                continue;
+            }
+        };
+        if hint.is_empty() || hint.chars().count() > MAX_HINT_CHARS {
+            continue;
+        }
+        if reveals_answer(&hint, &answers) {
+            failure = AiFailure::invalid("hint reveals the answer", Vec::new());
+            continue;
+        }
+        return Ok(hint);
+    }
+    Err(failure)
+}
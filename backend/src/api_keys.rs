@@ -0,0 +1,58 @@
+use chrono::{DateTime, Utc};
+use data_encoding::HEXLOWER;
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+pub const API_KEY_HEADER: &str = "x-api-key";
+const KEY_PREFIX: &str = "hqk";
+
+// Keys look like `hqk_<id>_<secret>`. Only a SHA-256 of the secret is stored; the secret is
+// random enough that a slow password hash would only add latency to every request.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiKey {
+    pub id: String,
+    pub teacher_id: i64,
+    pub name: String,
+    pub secret_hash: String,
+    pub created_at: DateTime<Utc>,
+    #[serde(default)]
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+fn random(len: usize) -> String {
+    rand::thread_rng().sample_iter(&Alphanumeric).take(len).map(char::from).collect()
+}
+
+fn hash_secret(secret: &str) -> String {
+    HEXLOWER.encode(&Sha256::digest(secret.as_bytes()))
+}
+
+pub fn generate(teacher_id: i64, name: String) -> (String, ApiKey) {
+    let id = random(12);
+    let secret = random(32);
+    let key = format!("{}_{}_{}", KEY_PREFIX, id, secret);
+    let record = ApiKey {
+        id,
+        teacher_id,
+        name,
+        secret_hash: hash_secret(&secret),
+        created_at: Utc::now(),
+        last_used_at: None,
+    };
+    (key, record)
+}
+
+// Splits a presented key into (id, secret) without looking anything up.
+pub fn parse(key: &str) -> Option<(&str, &str)> {
+    let rest = key.trim().strip_prefix(KEY_PREFIX)?.strip_prefix('_')?;
+    let (id, secret) = rest.split_once('_')?;
+    (!id.is_empty() && !secret.is_empty()).then_some((id, secret))
+}
+
+impl ApiKey {
+    pub fn matches(&self, secret: &str) -> bool {
+        self.secret_hash == hash_secret(secret)
+    }
+}
@@ -0,0 +1,115 @@
+use crate::handlers::{QuizIdResponse, QuizListResponse, QuizSummary};
+use crate::models::{Difficulty, Question, QuizSettings};
+use crate::state::QuizRecord;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+// Response bodies of `/api/v2`. Every field is camelCase; storage records and v1 bodies are
+// converted here instead of being serialized as they are, so renaming a stored field cannot
+// change the API.
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QuizIdOut {
+    pub quiz_id: i64,
+}
+
+impl From<QuizIdResponse> for QuizIdOut {
+    fn from(value: QuizIdResponse) -> Self {
+        Self { quiz_id: value.quiz_id }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QuizOut {
+    pub id: i64,
+    pub owner_teacher_id: i64,
+    pub title: String,
+    pub description: Option<String>,
+    pub questions: Vec<Question>,
+    pub is_published: bool,
+    pub source_quiz_id: Option<i64>,
+    pub difficulty: Option<Difficulty>,
+    pub tags: Vec<String>,
+    pub settings: QuizSettings,
+    pub subject: Option<String>,
+    pub grade: Option<u8>,
+    pub language: Option<String>,
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+impl From<QuizRecord> for QuizOut {
+    fn from(quiz: QuizRecord) -> Self {
+        Self {
+            id: quiz.id,
+            owner_teacher_id: quiz.owner_teacher_id,
+            title: quiz.title,
+            description: quiz.description,
+            questions: quiz.questions,
+            is_published: quiz.is_published,
+            source_quiz_id: quiz.source_quiz_id,
+            difficulty: quiz.difficulty,
+            tags: quiz.tags,
+            settings: quiz.settings,
+            subject: quiz.subject,
+            grade: quiz.grade,
+            language: quiz.language,
+            created_at: quiz.created_at,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QuizSummaryOut {
+    pub id: i64,
+    pub title: String,
+    pub description: Option<String>,
+    pub is_published: bool,
+    pub difficulty: Option<Difficulty>,
+    pub tags: Vec<String>,
+    pub subject: Option<String>,
+    pub grade: Option<u8>,
+    pub language: Option<String>,
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+impl From<QuizSummary> for QuizSummaryOut {
+    fn from(quiz: QuizSummary) -> Self {
+        Self {
+            id: quiz.id,
+            title: quiz.title,
+            description: quiz.description,
+            is_published: quiz.is_published,
+            difficulty: quiz.difficulty,
+            tags: quiz.tags,
+            subject: quiz.subject,
+            grade: quiz.grade,
+            language: quiz.language,
+            created_at: quiz.created_at,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QuizListOut {
+    pub items: Vec<QuizSummaryOut>,
+    pub total: usize,
+    pub page: usize,
+    pub limit: usize,
+    pub next_cursor: Option<String>,
+}
+
+impl From<QuizListResponse> for QuizListOut {
+    fn from(list: QuizListResponse) -> Self {
+        Self {
+            items: list.items.into_iter().map(QuizSummaryOut::from).collect(),
+            total: list.total,
+            page: list.page,
+            limit: list.limit,
+            next_cursor: list.next_cursor,
+        }
+    }
+}
@@ -0,0 +1,51 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+// One sensitive action. Entries are only ever appended; nothing edits or removes them.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditEntry {
+    pub id: u64,
+    pub at: DateTime<Utc>,
+    // Teacher login, or what was typed into the login form for a failed attempt.
+    pub actor: String,
+    #[serde(default)]
+    pub actor_id: Option<i64>,
+    // Dotted name, `area.verb`: `auth.login`, `quiz.publish`, `session.end`, `admin.import`, ...
+    pub action: String,
+    #[serde(default)]
+    pub target: Option<String>,
+    pub request_id: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct AuditQuery {
+    pub actor: Option<String>,
+    // Exact action, or an area such as `quiz` for every `quiz.*` action.
+    pub action: Option<String>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub limit: Option<usize>,
+}
+
+pub const AUDIT_PAGE_LIMIT: usize = 1000;
+
+impl AuditQuery {
+    pub fn matches(&self, entry: &AuditEntry) -> bool {
+        let action = self.action.as_deref().map(str::trim).filter(|a| !a.is_empty());
+        let actor = self.actor.as_deref().map(str::trim).filter(|a| !a.is_empty());
+        actor.is_none_or(|actor| entry.actor.eq_ignore_ascii_case(actor))
+            && action.is_none_or(|action| {
+                entry.action == action
+                    || entry.action.strip_prefix(action).is_some_and(|rest| rest.starts_with('.'))
+            })
+            && self.from.is_none_or(|from| entry.at >= from)
+            && self.to.is_none_or(|to| entry.at < to)
+    }
+
+    // Newest first, so a dashboard sees recent activity without paging.
+    pub fn select<'a>(&self, log: &'a [AuditEntry]) -> Vec<&'a AuditEntry> {
+        let limit = self.limit.unwrap_or(100).clamp(1, AUDIT_PAGE_LIMIT);
+        log.iter().rev().filter(|entry| self.matches(entry)).take(limit).collect()
+    }
+}
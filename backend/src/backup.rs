@@ -0,0 +1,159 @@
+use crate::models::{validate_quiz, QuizLimits, ValidationIssue};
+use crate::schema::QuizSchemas;
+use crate::state::{InMemoryDb, QuizRecord, SessionRecord, Teacher};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+pub const BACKUP_FORMAT: &str = "hse-quiz-backup";
+pub const BACKUP_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BackupArchive {
+    pub format: String,
+    pub version: u32,
+    pub exported_at: DateTime<Utc>,
+    pub teachers: Vec<Teacher>,
+    pub quizzes: Vec<QuizRecord>,
+    #[serde(default)]
+    pub sessions: Vec<SessionRecord>,
+}
+
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportReport {
+    pub teachers_imported: usize,
+    pub teachers_matched: usize,
+    pub quizzes_imported: usize,
+    pub sessions_imported: usize,
+    pub sessions_skipped: usize,
+    #[serde(skip)]
+    pub imported_quizzes: Vec<(i64, i64)>,
+}
+
+pub async fn export(db: &InMemoryDb) -> BackupArchive {
+    let mut teachers: Vec<Teacher> = db.teachers.read().await.values().cloned().collect();
+    teachers.sort_by_key(|t| t.id);
+    let mut quizzes: Vec<QuizRecord> = db.quizzes.read().await.values().cloned().collect();
+    quizzes.sort_by_key(|q| q.id);
+    let mut sessions: Vec<SessionRecord> = db
+        .game_sessions
+        .read()
+        .await
+        .values()
+        .filter(|s| s.status == "finished")
+        .cloned()
+        .collect();
+    sessions.sort_by_key(|s| s.id);
+    BackupArchive {
+        format: BACKUP_FORMAT.to_string(),
+        version: BACKUP_VERSION,
+        exported_at: Utc::now(),
+        teachers,
+        quizzes,
+        sessions,
+    }
+}
+
+pub fn validate(archive: &BackupArchive, schemas: &QuizSchemas, limits: &QuizLimits) -> Result<(), Vec<ValidationIssue>> {
+    let mut issues = Vec::new();
+    if archive.format != BACKUP_FORMAT {
+        issues.push(ValidationIssue { field: "format".into(), issue: format!("must be {}", BACKUP_FORMAT) });
+    }
+    if archive.version == 0 || archive.version > BACKUP_VERSION {
+        issues.push(ValidationIssue { field: "version".into(), issue: format!("unsupported version {}", archive.version) });
+    }
+    let schema = schemas.current();
+    for (i, q) in archive.quizzes.iter().enumerate() {
+        let quiz = q.to_quiz();
+        let value = serde_json::to_value(&quiz).unwrap_or_default();
+        issues.extend(schema.validator.iter_errors(&value).map(|e| ValidationIssue {
+            field: format!("quizzes[{i}]{}", e.instance_path),
+            issue: e.to_string(),
+        }));
+        if let Err(quiz_issues) = validate_quiz(&quiz, limits) {
+            issues.extend(quiz_issues.into_iter().map(|issue| ValidationIssue {
+                field: format!("quizzes[{i}].{}", issue.field),
+                issue: issue.issue,
+            }));
+        }
+    }
+    if issues.is_empty() {
+        Ok(())
+    } else {
+        Err(issues)
+    }
+}
+
+// Merges the archive into the running data set. Every record gets a fresh id so nothing already on
+// this server is overwritten; teachers whose login already exists are matched instead of duplicated.
+pub async fn import(db: &InMemoryDb, archive: BackupArchive) -> ImportReport {
+    let mut report = ImportReport::default();
+
+    let mut teacher_ids = HashMap::new();
+    {
+        let mut teachers = db.teachers.write().await;
+        let mut by_login = db.teachers_by_login.write().await;
+        for t in archive.teachers {
+            if let Some(existing) = by_login.get(&t.login) {
+                teacher_ids.insert(t.id, *existing);
+                report.teachers_matched += 1;
+                continue;
+            }
+            let id = db.next_teacher_id();
+            teacher_ids.insert(t.id, id);
+            by_login.insert(t.login.clone(), id);
+            teachers.insert(id, Teacher { id, ..t });
+            report.teachers_imported += 1;
+        }
+    }
+
+    let mut quiz_ids = HashMap::new();
+    {
+        let mut source_quizzes = archive.quizzes;
+        source_quizzes.sort_by_key(|q| q.id);
+        let mut quizzes = db.quizzes.write().await;
+        db.versions.bump_quizzes();
+        for q in source_quizzes {
+            let Some(owner) = teacher_ids.get(&q.owner_teacher_id).copied() else { continue };
+            let id = db.next_quiz_id();
+            let source_quiz_id = q.source_quiz_id.and_then(|src| quiz_ids.get(&src).copied());
+            quiz_ids.insert(q.id, id);
+            quizzes.insert(id, QuizRecord { id, owner_teacher_id: owner, source_quiz_id, ..q });
+            report.imported_quizzes.push((id, owner));
+            report.quizzes_imported += 1;
+        }
+    }
+
+    let mut sessions = db.game_sessions.write().await;
+    db.versions.bump_sessions();
+    for mut s in archive.sessions {
+        let (Some(teacher_id), Some(quiz_id)) = (teacher_ids.get(&s.teacher_id), quiz_ids.get(&s.quiz_id)) else {
+            report.sessions_skipped += 1;
+            continue;
+        };
+        s.id = db.next_game_session_id();
+        s.teacher_id = *teacher_id;
+        s.quiz_id = *quiz_id;
+        s.status = "finished".into();
+        s.review_until = None;
+        s.playlist = s.playlist.iter().filter_map(|id| quiz_ids.get(id).copied()).collect();
+        for segment in &mut s.completed_segments {
+            segment.quiz_id = quiz_ids.get(&segment.quiz_id).copied().unwrap_or(segment.quiz_id);
+        }
+        for answers in s.answers.values_mut() {
+            for answer in answers {
+                answer.quiz_id = quiz_ids.get(&answer.quiz_id).copied().unwrap_or(answer.quiz_id);
+            }
+        }
+        for disruptions in s.disruptions.values_mut() {
+            for disruption in disruptions {
+                disruption.quiz_id = quiz_ids.get(&disruption.quiz_id).copied().unwrap_or(disruption.quiz_id);
+            }
+        }
+        sessions.insert(s.id, s);
+        report.sessions_imported += 1;
+    }
+    report
+}
@@ -0,0 +1,123 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+fn env_usize(name: &str) -> usize {
+    std::env::var(name).ok().and_then(|v| v.parse::<usize>().ok()).unwrap_or(0)
+}
+
+// Global admission control. A limit of 0 means unlimited.
+pub struct Admission {
+    pub max_ws_connections: usize,
+    pub max_active_sessions: usize,
+    pub queue_timeout: Duration,
+    ws_slots: Option<Arc<Semaphore>>,
+    ws_connections: Arc<AtomicUsize>,
+    queue: Mutex<VecDeque<u64>>,
+    next_ticket: AtomicU64,
+    pub ws_rejected: AtomicU64,
+    pub sessions_rejected: AtomicU64,
+}
+
+// Held for the lifetime of an admitted WebSocket; releases the slot on drop.
+pub struct WsPermit {
+    _slot: Option<OwnedSemaphorePermit>,
+    connections: Arc<AtomicUsize>,
+}
+
+impl Drop for WsPermit {
+    fn drop(&mut self) {
+        self.connections.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+pub struct QueueTicket<'a> {
+    id: u64,
+    admission: &'a Admission,
+}
+
+impl QueueTicket<'_> {
+    pub fn position(&self) -> usize {
+        let queue = self.admission.queue.lock().expect("admission queue lock");
+        queue.iter().position(|t| *t == self.id).map(|p| p + 1).unwrap_or(0)
+    }
+}
+
+impl Drop for QueueTicket<'_> {
+    fn drop(&mut self) {
+        self.admission.queue.lock().expect("admission queue lock").retain(|t| *t != self.id);
+    }
+}
+
+impl Admission {
+    pub fn from_env() -> Self {
+        Self::new(
+            env_usize("MAX_WS_CONNECTIONS"),
+            env_usize("MAX_ACTIVE_SESSIONS"),
+            Duration::from_secs(
+                std::env::var("ADMISSION_QUEUE_TIMEOUT_SECS")
+                    .ok()
+                    .and_then(|v| v.parse::<u64>().ok())
+                    .unwrap_or(60),
+            ),
+        )
+    }
+
+    pub fn new(max_ws_connections: usize, max_active_sessions: usize, queue_timeout: Duration) -> Self {
+        Self {
+            max_ws_connections,
+            max_active_sessions,
+            queue_timeout,
+            ws_slots: (max_ws_connections > 0).then(|| Arc::new(Semaphore::new(max_ws_connections))),
+            ws_connections: Arc::new(AtomicUsize::new(0)),
+            queue: Mutex::new(VecDeque::new()),
+            next_ticket: AtomicU64::new(1),
+            ws_rejected: AtomicU64::new(0),
+            sessions_rejected: AtomicU64::new(0),
+        }
+    }
+
+    pub fn ws_connections(&self) -> usize {
+        self.ws_connections.load(Ordering::SeqCst)
+    }
+
+    pub fn queue_len(&self) -> usize {
+        self.queue.lock().expect("admission queue lock").len()
+    }
+
+    fn permit(&self, slot: Option<OwnedSemaphorePermit>) -> WsPermit {
+        self.ws_connections.fetch_add(1, Ordering::SeqCst);
+        WsPermit { _slot: slot, connections: self.ws_connections.clone() }
+    }
+
+    pub fn try_admit_ws(&self) -> Option<WsPermit> {
+        match &self.ws_slots {
+            None => Some(self.permit(None)),
+            Some(slots) => slots.clone().try_acquire_owned().ok().map(|slot| self.permit(Some(slot))),
+        }
+    }
+
+    pub fn enqueue(&self) -> QueueTicket<'_> {
+        let id = self.next_ticket.fetch_add(1, Ordering::SeqCst);
+        self.queue.lock().expect("admission queue lock").push_back(id);
+        QueueTicket { id, admission: self }
+    }
+
+    // Waits for a free slot. The semaphore hands out permits in FIFO order, which keeps the
+    // reported queue positions honest.
+    pub async fn admit_ws(&self) -> WsPermit {
+        match &self.ws_slots {
+            None => self.permit(None),
+            Some(slots) => {
+                let slot = slots.clone().acquire_owned().await.expect("admission semaphore is never closed");
+                self.permit(Some(slot))
+            }
+        }
+    }
+
+    pub fn session_slot_available(&self, active_sessions: usize) -> bool {
+        self.max_active_sessions == 0 || active_sessions < self.max_active_sessions
+    }
+}
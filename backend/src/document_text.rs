@@ -0,0 +1,95 @@
+use quick_xml::events::Event;
+use std::io::{Cursor, Read};
+
+// Extracted text beyond this is cut off before it reaches the model prompt.
+pub const MAX_SOURCE_CHARS: usize = 20_000;
+// A DOCX is a zip archive; its main part is never unpacked beyond this.
+const MAX_DOCX_XML_BYTES: u64 = 50 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DocumentFormat {
+    Pdf,
+    Docx,
+}
+
+impl DocumentFormat {
+    // Same rule as spreadsheet imports: the extension decides, the content type is a fallback.
+    pub fn detect(file_name: Option<&str>, content_type: Option<&str>) -> Option<Self> {
+        let ext = file_name.and_then(|n| n.rsplit_once('.')).map(|(_, e)| e.to_ascii_lowercase());
+        match (ext.as_deref(), content_type) {
+            (Some("pdf"), _) => Some(Self::Pdf),
+            (Some("docx"), _) => Some(Self::Docx),
+            (_, Some("application/pdf")) => Some(Self::Pdf),
+            (_, Some("application/vnd.openxmlformats-officedocument.wordprocessingml.document")) => Some(Self::Docx),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExtractedText {
+    pub text: String,
+    pub truncated: bool,
+}
+
+// Blocking and CPU-bound for large files; call it from `spawn_blocking`.
+pub fn extract(bytes: &[u8], format: DocumentFormat) -> Result<ExtractedText, String> {
+    let raw = match format {
+        DocumentFormat::Pdf => pdf_extract::extract_text_from_mem(bytes).map_err(|e| format!("cannot read pdf: {}", e))?,
+        DocumentFormat::Docx => docx_text(bytes)?,
+    };
+    Ok(tidy(&raw))
+}
+
+fn docx_text(bytes: &[u8]) -> Result<String, String> {
+    let mut archive = zip::ZipArchive::new(Cursor::new(bytes)).map_err(|e| format!("cannot read docx: {}", e))?;
+    let document = archive
+        .by_name("word/document.xml")
+        .map_err(|_| "cannot read docx: word/document.xml is missing".to_string())?;
+    if document.size() > MAX_DOCX_XML_BYTES {
+        return Err("docx document is too large".into());
+    }
+    let mut xml = String::new();
+    document
+        .take(MAX_DOCX_XML_BYTES)
+        .read_to_string(&mut xml)
+        .map_err(|e| format!("cannot read docx: {}", e))?;
+
+    let mut reader = quick_xml::Reader::from_str(&xml);
+    let mut out = String::new();
+    let mut in_text = false;
+    loop {
+        match reader.read_event().map_err(|e| format!("cannot read docx: {}", e))? {
+            Event::Start(e) if e.local_name().as_ref() == b"t" => in_text = true,
+            Event::End(e) => match e.local_name().as_ref() {
+                b"t" => in_text = false,
+                b"p" => out.push('\n'),
+                _ => {}
+            },
+            Event::Empty(e) => match e.local_name().as_ref() {
+                b"tab" => out.push(' '),
+                b"br" | b"cr" => out.push('\n'),
+                _ => {}
+            },
+            Event::Text(t) if in_text => out.push_str(&t.unescape().map_err(|e| format!("cannot read docx: {}", e))?),
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    Ok(out)
+}
+
+// Collapses runs of spaces inside lines, drops empty lines and cuts to `MAX_SOURCE_CHARS`.
+fn tidy(raw: &str) -> ExtractedText {
+    let mut text = raw
+        .lines()
+        .map(|line| line.split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n");
+    let truncated = text.chars().count() > MAX_SOURCE_CHARS;
+    if truncated {
+        text = text.chars().take(MAX_SOURCE_CHARS).collect();
+    }
+    ExtractedText { text, truncated }
+}
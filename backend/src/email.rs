@@ -0,0 +1,523 @@
+use anyhow::{anyhow, bail};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use lettre::message::header::ContentType;
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::transport::smtp::extension::ClientId;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EmailMessage {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+// Password reset codes, reminders of scheduled sessions and results summaries go to teachers by email.
+#[async_trait]
+pub trait EmailSender: Send + Sync {
+    fn kind(&self) -> &'static str;
+    async fn send(&self, message: &EmailMessage) -> anyhow::Result<()>;
+}
+
+// Writes whole messages, reset codes included, to the server log. Only for development, and only
+// when asked for with EMAIL_BACKEND=log.
+pub struct LogSender;
+
+#[async_trait]
+impl EmailSender for LogSender {
+    fn kind(&self) -> &'static str {
+        "log"
+    }
+
+    async fn send(&self, message: &EmailMessage) -> anyhow::Result<()> {
+        info!("email to {}: {}\n{}", message.to, message.subject, message.body);
+        Ok(())
+    }
+}
+
+// Drops every message; the default until SMTP is configured.
+pub struct DisabledSender;
+
+#[async_trait]
+impl EmailSender for DisabledSender {
+    fn kind(&self) -> &'static str {
+        "disabled"
+    }
+
+    async fn send(&self, _message: &EmailMessage) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SmtpTls {
+    // Plain connection upgraded with STARTTLS, usually port 587.
+    StartTls,
+    // TLS from the first byte, usually port 465.
+    Implicit,
+    // No encryption; only for a relay on the same host or network.
+    None,
+}
+
+#[derive(Debug, Clone)]
+pub struct SmtpConfig {
+    pub host: String,
+    pub port: u16,
+    pub tls: SmtpTls,
+    pub credentials: Option<(String, String)>,
+    pub from: String,
+    pub helo: String,
+    pub timeout: Duration,
+}
+
+impl SmtpConfig {
+    // None when SMTP_HOST is not set.
+    pub fn from_env() -> anyhow::Result<Option<Self>> {
+        let var = |name: &str| std::env::var(name).ok().map(|v| v.trim().to_string()).filter(|v| !v.is_empty());
+        let Some(host) = var("SMTP_HOST") else { return Ok(None) };
+        let tls = match var("SMTP_TLS").unwrap_or_else(|| "starttls".into()).as_str() {
+            "starttls" => SmtpTls::StartTls,
+            "tls" => SmtpTls::Implicit,
+            "none" => SmtpTls::None,
+            other => bail!("unsupported SMTP_TLS: {}", other),
+        };
+        let default_port = match tls {
+            SmtpTls::StartTls => 587,
+            SmtpTls::Implicit => 465,
+            SmtpTls::None => 25,
+        };
+        let port = match var("SMTP_PORT") {
+            Some(port) => port.parse().map_err(|_| anyhow!("invalid SMTP_PORT: {}", port))?,
+            None => default_port,
+        };
+        let from = var("SMTP_FROM").ok_or_else(|| anyhow!("SMTP_FROM is required with SMTP_HOST"))?;
+        if !is_plain_address(&from) {
+            bail!("SMTP_FROM must be a bare address like quiz@example.org");
+        }
+        let credentials = match (var("SMTP_USERNAME"), var("SMTP_PASSWORD")) {
+            (Some(user), Some(password)) => Some((user, password)),
+            (None, None) => None,
+            _ => bail!("SMTP_USERNAME and SMTP_PASSWORD go together"),
+        };
+        Ok(Some(Self {
+            host,
+            port,
+            tls,
+            credentials,
+            from,
+            helo: var("SMTP_HELO_NAME").unwrap_or_else(|| "localhost".into()),
+            timeout: Duration::from_secs(
+                var("SMTP_TIMEOUT_SECS").and_then(|v| v.parse().ok()).filter(|v| *v > 0).unwrap_or(30),
+            ),
+        }))
+    }
+}
+
+// One SMTP transaction per message; the volume is a few messages per teacher a day, not worth a pool.
+pub struct SmtpSender {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+    timeout: Duration,
+}
+
+impl SmtpSender {
+    pub fn new(config: SmtpConfig) -> anyhow::Result<Self> {
+        let builder = match config.tls {
+            SmtpTls::StartTls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.host)?,
+            SmtpTls::Implicit => AsyncSmtpTransport::<Tokio1Executor>::relay(&config.host)?,
+            SmtpTls::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&config.host),
+        };
+        let mut builder =
+            builder.port(config.port).timeout(Some(config.timeout)).hello_name(ClientId::Domain(config.helo));
+        if let Some((user, password)) = config.credentials {
+            builder = builder.credentials(Credentials::new(user, password));
+        }
+        Ok(Self { transport: builder.build(), from: config.from.parse()?, timeout: config.timeout })
+    }
+}
+
+#[async_trait]
+impl EmailSender for SmtpSender {
+    fn kind(&self) -> &'static str {
+        "smtp"
+    }
+
+    async fn send(&self, message: &EmailMessage) -> anyhow::Result<()> {
+        if !is_plain_address(&message.to) {
+            bail!("invalid recipient address");
+        }
+        let email = Message::builder()
+            .from(self.from.clone())
+            .to(message.to.parse()?)
+            .subject(message.subject.as_str())
+            .header(ContentType::TEXT_PLAIN)
+            .body(message.body.clone())?;
+        // The transport timeout covers each reply; this one bounds the whole transaction.
+        tokio::time::timeout(self.timeout, self.transport.send(email))
+            .await
+            .map_err(|_| anyhow!("smtp server did not answer within {:?}", self.timeout))??;
+        Ok(())
+    }
+}
+
+// No whitespace, control characters or angle brackets, so an address cannot smuggle in a header
+// or an SMTP command.
+pub fn is_plain_address(address: &str) -> bool {
+    let valid_char = |c: char| !c.is_whitespace() && !c.is_control() && c != '<' && c != '>';
+    matches!(address.split_once('@'), Some((local, domain)) if !local.is_empty() && !domain.is_empty())
+        && address.chars().all(valid_char)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Locale {
+    Ru,
+    En,
+}
+
+impl Locale {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "ru" => Some(Self::Ru),
+            "en" => Some(Self::En),
+            _ => None,
+        }
+    }
+
+    pub fn code(self) -> &'static str {
+        match self {
+            Self::Ru => "ru",
+            Self::En => "en",
+        }
+    }
+
+    fn time(self, at: DateTime<Utc>) -> String {
+        match self {
+            Self::Ru => at.format("%d.%m.%Y %H:%M UTC").to_string(),
+            Self::En => at.format("%Y-%m-%d %H:%M UTC").to_string(),
+        }
+    }
+}
+
+pub enum EmailTemplate<'a> {
+    PasswordReset { login: &'a str, code: &'a str, expires_at: DateTime<Utc> },
+    SessionReminder { quiz_title: &'a str, room_code: &'a str, starts_at: DateTime<Utc>, link: Option<String> },
+    // `top` is the head of the leaderboard: nickname and points.
+    ResultsSummary {
+        quiz_title: &'a str,
+        room_code: &'a str,
+        participants: usize,
+        average_pct: f64,
+        top: Vec<(String, f64)>,
+        link: Option<String>,
+    },
+}
+
+impl EmailTemplate<'_> {
+    // Subject and body in the teacher's language.
+    pub fn render(&self, locale: Locale) -> (String, String) {
+        let link = |link: &Option<String>, label: &str| link.as_ref().map(|l| format!("\n{}: {}\n", label, l));
+        match (self, locale) {
+            (Self::PasswordReset { login, code, expires_at }, Locale::Ru) => (
+                "Сброс пароля".into(),
+                format!(
+                    "Для учётной записи {} запрошен сброс пароля.\n\nКод: {}\nКод действует до {}.\n\n\
+                     Если вы не запрашивали сброс, просто проигнорируйте это письмо.\n",
+                    login,
+                    code,
+                    locale.time(*expires_at)
+                ),
+            ),
+            (Self::PasswordReset { login, code, expires_at }, Locale::En) => (
+                "Password reset".into(),
+                format!(
+                    "A password reset was requested for the account {}.\n\nCode: {}\nThe code is valid until {}.\n\n\
+                     If you did not ask for it, just ignore this email.\n",
+                    login,
+                    code,
+                    locale.time(*expires_at)
+                ),
+            ),
+            (Self::SessionReminder { quiz_title, room_code, starts_at, link: url }, Locale::Ru) => (
+                format!("Скоро начнётся «{}»", quiz_title),
+                format!(
+                    "Сессия по квизу «{}» начнётся {}.\nКод комнаты: {}\n{}",
+                    quiz_title,
+                    locale.time(*starts_at),
+                    room_code,
+                    link(url, "Комната").unwrap_or_default()
+                ),
+            ),
+            (Self::SessionReminder { quiz_title, room_code, starts_at, link: url }, Locale::En) => (
+                format!("\"{}\" starts soon", quiz_title),
+                format!(
+                    "The session of \"{}\" starts at {}.\nRoom code: {}\n{}",
+                    quiz_title,
+                    locale.time(*starts_at),
+                    room_code,
+                    link(url, "Room").unwrap_or_default()
+                ),
+            ),
+            (Self::ResultsSummary { quiz_title, room_code, participants, average_pct, top, link: url }, _) => {
+                let ru = locale == Locale::Ru;
+                let places: String = top
+                    .iter()
+                    .enumerate()
+                    .map(|(i, (nickname, points))| format!("{}. {} — {:.0}\n", i + 1, nickname, points))
+                    .collect();
+                let subject = if ru {
+                    format!("Итоги «{}»", quiz_title)
+                } else {
+                    format!("Results of \"{}\"", quiz_title)
+                };
+                let body = if ru {
+                    format!(
+                        "Сессия {} по квизу «{}» завершена.\n\nУчастников: {}\nСредний процент верных ответов: {:.0}%\n\n\
+                         Лучшие результаты:\n{}{}",
+                        room_code,
+                        quiz_title,
+                        participants,
+                        average_pct,
+                        places,
+                        link(url, "Подробные результаты").unwrap_or_default()
+                    )
+                } else {
+                    format!(
+                        "Session {} of \"{}\" has finished.\n\nParticipants: {}\nAverage correct answers: {:.0}%\n\n\
+                         Top results:\n{}{}",
+                        room_code,
+                        quiz_title,
+                        participants,
+                        average_pct,
+                        places,
+                        link(url, "Full results").unwrap_or_default()
+                    )
+                };
+                (subject, body)
+            }
+        }
+    }
+}
+
+pub struct EmailSettings {
+    // Attempts per message, the first one included; the wait doubles after every failure.
+    pub max_attempts: u32,
+    pub retry_base: Duration,
+    pub queue_size: usize,
+    // For teachers who have not picked a language.
+    pub default_locale: Locale,
+    // How long before a scheduled start the teacher is reminded; zero turns reminders off.
+    pub reminder_lead: chrono::Duration,
+    pub results_summary: bool,
+    // Frontend address for links in messages; without it messages carry no links.
+    pub base_url: Option<String>,
+}
+
+impl EmailSettings {
+    pub fn from_env() -> Self {
+        let var = |name: &str| std::env::var(name).ok().map(|v| v.trim().to_string()).filter(|v| !v.is_empty());
+        let number = |name: &str, default: u64| var(name).and_then(|v| v.parse::<u64>().ok()).unwrap_or(default);
+        Self {
+            max_attempts: number("EMAIL_MAX_ATTEMPTS", 5).clamp(1, 20) as u32,
+            retry_base: Duration::from_secs(number("EMAIL_RETRY_BASE_SECS", 30).max(1)),
+            queue_size: number("EMAIL_QUEUE_SIZE", 1000).max(1) as usize,
+            default_locale: var("EMAIL_DEFAULT_LOCALE").and_then(|v| Locale::parse(&v)).unwrap_or(Locale::Ru),
+            reminder_lead: chrono::Duration::minutes(number("EMAIL_REMINDER_MINUTES", 15) as i64),
+            results_summary: var("EMAIL_RESULTS_SUMMARY").map(|v| v != "false" && v != "0").unwrap_or(true),
+            base_url: var("APP_BASE_URL").map(|v| v.trim_end_matches('/').to_string()),
+        }
+    }
+}
+
+struct Outgoing {
+    message: EmailMessage,
+    attempt: u32,
+}
+
+// Messages are queued and sent by one background worker, so a slow or failing SMTP server never
+// holds up a request. A failed message goes back on the queue after a growing delay.
+pub struct Mailer {
+    pub sender: Arc<dyn EmailSender>,
+    pub settings: EmailSettings,
+    queue: mpsc::Sender<Outgoing>,
+    receiver: std::sync::Mutex<Option<mpsc::Receiver<Outgoing>>>,
+}
+
+impl Mailer {
+    pub fn new(sender: Arc<dyn EmailSender>, settings: EmailSettings) -> Self {
+        let (queue, receiver) = mpsc::channel(settings.queue_size);
+        Self { sender, settings, queue, receiver: std::sync::Mutex::new(Some(receiver)) }
+    }
+
+    pub fn from_env() -> anyhow::Result<Self> {
+        let smtp = SmtpConfig::from_env()?;
+        let backend = std::env::var("EMAIL_BACKEND").unwrap_or_default().trim().to_string();
+        let sender: Arc<dyn EmailSender> = match (backend.as_str(), smtp) {
+            ("smtp" | "", Some(config)) => Arc::new(SmtpSender::new(config)?),
+            ("smtp", None) => bail!("EMAIL_BACKEND=smtp needs SMTP_HOST"),
+            ("log", _) => {
+                warn!("EMAIL_BACKEND=log writes emails and password reset codes to the log; do not use it in production");
+                Arc::new(LogSender)
+            }
+            ("disabled" | "none" | "", _) => Arc::new(DisabledSender),
+            (other, _) => bail!("unsupported EMAIL_BACKEND: {}", other),
+        };
+        Ok(Self::new(sender, EmailSettings::from_env()))
+    }
+
+    pub fn kind(&self) -> &'static str {
+        self.sender.kind()
+    }
+
+    pub fn locale(&self, preferred: Option<&str>) -> Locale {
+        preferred.and_then(Locale::parse).unwrap_or(self.settings.default_locale)
+    }
+
+    pub fn link(&self, path: &str) -> Option<String> {
+        self.settings.base_url.as_ref().map(|base| format!("{}{}", base, path))
+    }
+
+    // Renders the template for the recipient and queues it. False when the queue is full and the
+    // message was dropped.
+    pub fn send_template(&self, to: &str, locale: Option<&str>, template: EmailTemplate) -> bool {
+        let (subject, body) = template.render(self.locale(locale));
+        self.enqueue(EmailMessage { to: to.to_string(), subject, body })
+    }
+
+    pub fn enqueue(&self, message: EmailMessage) -> bool {
+        match self.queue.try_send(Outgoing { message, attempt: 0 }) {
+            Ok(()) => true,
+            Err(err) => {
+                let message = &err.into_inner().message;
+                warn!("email queue is full, dropping \"{}\" to {}", message.subject, message.to);
+                false
+            }
+        }
+    }
+
+    pub fn spawn_worker(self: &Arc<Self>) {
+        let Some(mut receiver) = self.receiver.lock().expect("email receiver lock").take() else { return };
+        let mailer = self.clone();
+        tokio::spawn(async move {
+            while let Some(mut outgoing) = receiver.recv().await {
+                outgoing.attempt += 1;
+                let EmailMessage { to, subject, .. } = &outgoing.message;
+                match mailer.sender.send(&outgoing.message).await {
+                    Ok(()) => info!("email \"{}\" to {} sent via {}", subject, to, mailer.kind()),
+                    Err(err) if outgoing.attempt < mailer.settings.max_attempts => {
+                        let delay = mailer.settings.retry_base * 2u32.saturating_pow(outgoing.attempt - 1);
+                        warn!(
+                            "email \"{}\" to {} failed (attempt {}), retrying in {:?}: {}",
+                            subject, to, outgoing.attempt, delay, err
+                        );
+                        let queue = mailer.queue.clone();
+                        tokio::spawn(async move {
+                            tokio::time::sleep(delay).await;
+                            let _ = queue.send(outgoing).await;
+                        });
+                    }
+                    Err(err) => {
+                        warn!("giving up on email \"{}\" to {} after {} attempts: {}", subject, to, outgoing.attempt, err)
+                    }
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::engine::general_purpose::STANDARD as B64;
+    use base64::Engine;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn smtp_sender_speaks_the_protocol() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let mut conn = BufReader::new(socket);
+            let mut transcript = Vec::new();
+            conn.get_mut().write_all(b"220 test ESMTP\r\n").await.unwrap();
+            let mut in_data = false;
+            loop {
+                let mut line = String::new();
+                if conn.read_line(&mut line).await.unwrap() == 0 {
+                    return transcript;
+                }
+                let line = line.trim_end().to_string();
+                let reply: &[u8] = match line.as_str() {
+                    "." => b"250 queued\r\n",
+                    _ if in_data => b"",
+                    l if l.starts_with("EHLO") => b"250-test\r\n250 AUTH PLAIN\r\n",
+                    l if l.starts_with("AUTH PLAIN") => b"235 ok\r\n",
+                    "DATA" => b"354 go on\r\n",
+                    "QUIT" => b"221 bye\r\n",
+                    _ => b"250 ok\r\n",
+                };
+                in_data = (in_data || line == "DATA") && line != ".";
+                let done = line == "QUIT";
+                transcript.push(line);
+                conn.get_mut().write_all(reply).await.unwrap();
+                if done {
+                    return transcript;
+                }
+            }
+        });
+        let sender = SmtpSender::new(SmtpConfig {
+            host: "127.0.0.1".into(),
+            port,
+            tls: SmtpTls::None,
+            credentials: Some(("quiz".into(), "secret".into())),
+            from: "quiz@example.org".into(),
+            helo: "backend".into(),
+            timeout: Duration::from_secs(5),
+        })
+        .unwrap();
+        let message =
+            EmailMessage { to: "teacher@example.org".into(), subject: "Итоги".into(), body: "Привет\n".into() };
+        sender.send(&message).await.unwrap();
+
+        let transcript = server.await.unwrap();
+        assert_eq!(transcript[0], "EHLO backend");
+        assert_eq!(transcript[1], format!("AUTH PLAIN {}", B64.encode("\0quiz\0secret")));
+        assert_eq!(transcript[2], "MAIL FROM:<quiz@example.org>");
+        assert_eq!(transcript[3], "RCPT TO:<teacher@example.org>");
+        assert!(transcript.contains(&format!("Subject: =?utf-8?b?{}?=", B64.encode("Итоги"))));
+        assert!(transcript.contains(&"Content-Type: text/plain; charset=utf-8".to_string()));
+        assert!(transcript.contains(&B64.encode("Привет\r\n")));
+        assert_eq!(transcript[transcript.len() - 2], ".");
+
+        let injected = EmailMessage { to: "a@b.c\r\nRCPT TO:<x@y.z>".into(), ..message };
+        assert!(sender.send(&injected).await.is_err());
+    }
+
+    #[test]
+    fn templates_follow_the_locale() {
+        let summary = EmailTemplate::ResultsSummary {
+            quiz_title: "Дроби",
+            room_code: "ABCD",
+            participants: 2,
+            average_pct: 75.0,
+            top: vec![("Лиза".into(), 20.0), ("Миша".into(), 10.0)],
+            link: Some("https://quiz.example.org/teacher/sessions/7/results".into()),
+        };
+        let (subject, body) = summary.render(Locale::Ru);
+        assert_eq!(subject, "Итоги «Дроби»");
+        assert!(body.contains("1. Лиза — 20\n2. Миша — 10\n"));
+        assert!(body.contains("https://quiz.example.org/teacher/sessions/7/results"));
+        let (subject, body) = summary.render(Locale::En);
+        assert_eq!(subject, "Results of \"Дроби\"");
+        assert!(body.contains("Average correct answers: 75%"));
+        assert_eq!(Locale::parse(" EN "), Some(Locale::En));
+        assert_eq!(Locale::parse("de"), None);
+    }
+}
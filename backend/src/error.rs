@@ -1,5 +1,20 @@
 use axum::{http::StatusCode, response::{IntoResponse, Response}, Json};
+use crate::storage::StorageError;
 use serde::Serialize;
+use std::future::Future;
+
+// Id of the request being handled, set by `handlers::request_id` around the whole handler.
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+pub async fn with_request_id<F: Future>(request_id: String, fut: F) -> F::Output {
+    REQUEST_ID.scope(request_id, fut).await
+}
+
+pub fn current_request_id() -> Option<String> {
+    REQUEST_ID.try_with(|id| id.clone()).ok()
+}
 
 #[derive(Debug, Clone, Serialize)]
 pub struct ErrorDetail {
@@ -21,42 +36,187 @@ pub struct ErrorPayload {
     pub request_id: String,
 }
 
+// Every error a handler can return. The JSON body and status follow from the variant; the request
+// id is the one of the request being handled, so errors do not have to carry it around.
 #[derive(Debug)]
-pub struct AppError {
-    pub status: StatusCode,
-    pub code: &'static str,
-    pub message: String,
-    pub details: Vec<ErrorDetail>,
-    pub request_id: String,
+pub enum AppError {
+    Unauthorized(String),
+    Forbidden(String),
+    NotFound(String),
+    Validation { message: String, details: Vec<ErrorDetail> },
+    Conflict(String),
+    // `details` names the exhausted quota, if any.
+    RateLimited { message: String, details: Vec<ErrorDetail> },
+    PayloadTooLarge(String),
+    // Storage or room capacity is not available right now; `code` tells which.
+    Unavailable { code: &'static str, message: String },
+    // The AI provider failed or returned something unusable; status and code come from `AiFailure`.
+    Upstream { status: StatusCode, code: &'static str, message: String, details: Vec<ErrorDetail> },
+    Internal(String),
+    // A rule of the flow that clients handle by its own code: TOTP_REQUIRED, HOMEWORK_CLOSED, ...
+    Rejected { status: StatusCode, code: &'static str, message: String },
 }
 
 impl AppError {
-    pub fn new(status: StatusCode, code: &'static str, message: impl Into<String>, request_id: impl Into<String>) -> Self {
-        Self {
-            status,
-            code,
-            message: message.into(),
-            details: Vec::new(),
-            request_id: request_id.into(),
-        }
+    pub fn unauthorized(message: impl Into<String>) -> Self {
+        Self::Unauthorized(message.into())
+    }
+
+    pub fn forbidden(message: impl Into<String>) -> Self {
+        Self::Forbidden(message.into())
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        Self::NotFound(message.into())
     }
 
-    pub fn with_details(mut self, details: Vec<ErrorDetail>) -> Self {
-        self.details = details;
+    pub fn validation(message: impl Into<String>) -> Self {
+        Self::Validation { message: message.into(), details: Vec::new() }
+    }
+
+    pub fn conflict(message: impl Into<String>) -> Self {
+        Self::Conflict(message.into())
+    }
+
+    pub fn rate_limited(message: impl Into<String>) -> Self {
+        Self::RateLimited { message: message.into(), details: Vec::new() }
+    }
+
+    pub fn payload_too_large(message: impl Into<String>) -> Self {
+        Self::PayloadTooLarge(message.into())
+    }
+
+    pub fn unavailable(code: &'static str, message: impl Into<String>) -> Self {
+        Self::Unavailable { code, message: message.into() }
+    }
+
+    pub fn internal(message: impl Into<String>) -> Self {
+        Self::Internal(message.into())
+    }
+
+    pub fn rejected(status: StatusCode, code: &'static str, message: impl Into<String>) -> Self {
+        Self::Rejected { status, code, message: message.into() }
+    }
+
+    // Only validation, rate-limit and upstream errors carry details; on other variants this does nothing.
+    pub fn with_details(mut self, new_details: Vec<ErrorDetail>) -> Self {
+        if let Self::Validation { details, .. } | Self::RateLimited { details, .. } | Self::Upstream { details, .. } =
+            &mut self
+        {
+            *details = new_details;
+        }
         self
     }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            Self::Forbidden(_) => StatusCode::FORBIDDEN,
+            Self::NotFound(_) => StatusCode::NOT_FOUND,
+            Self::Validation { .. } => StatusCode::BAD_REQUEST,
+            Self::Conflict(_) => StatusCode::CONFLICT,
+            Self::RateLimited { .. } => StatusCode::TOO_MANY_REQUESTS,
+            Self::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            Self::Unavailable { .. } => StatusCode::SERVICE_UNAVAILABLE,
+            Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::Upstream { status, .. } | Self::Rejected { status, .. } => *status,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Unauthorized(_) => "UNAUTHORIZED",
+            Self::Forbidden(_) => "FORBIDDEN",
+            Self::NotFound(_) => "NOT_FOUND",
+            Self::Validation { .. } => "VALIDATION_ERROR",
+            Self::Conflict(_) => "CONFLICT",
+            Self::RateLimited { .. } => "RATE_LIMITED",
+            Self::PayloadTooLarge(_) => "PAYLOAD_TOO_LARGE",
+            Self::Internal(_) => "INTERNAL_ERROR",
+            Self::Unavailable { code, .. } | Self::Upstream { code, .. } | Self::Rejected { code, .. } => code,
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            Self::Unauthorized(message)
+            | Self::Forbidden(message)
+            | Self::NotFound(message)
+            | Self::Conflict(message)
+            | Self::PayloadTooLarge(message)
+            | Self::Internal(message) => message,
+            Self::Validation { message, .. }
+            | Self::RateLimited { message, .. }
+            | Self::Unavailable { message, .. }
+            | Self::Upstream { message, .. }
+            | Self::Rejected { message, .. } => message,
+        }
+    }
+}
+
+// Paths, SQL and Redis details stay in the log, tagged with the request id so a client's report can be
+// matched to them; the client only learns which kind of failure it was.
+fn storage_failure(err: &StorageError) -> String {
+    tracing::error!(request_id = %current_request_id().unwrap_or_default(), "storage failure: {}", err);
+    "storage error".into()
+}
+
+impl From<StorageError> for AppError {
+    fn from(err: StorageError) -> Self {
+        match err {
+            StorageError::NotFound(_) => Self::NotFound(err.to_string()),
+            StorageError::Conflict(_) => Self::Conflict(storage_failure(&err)),
+            StorageError::Unavailable(_) => {
+                Self::Unavailable { code: "STORAGE_UNAVAILABLE", message: storage_failure(&err) }
+            }
+            StorageError::Corrupt(_) => Self::Internal(storage_failure(&err)),
+        }
+    }
+}
+
+impl From<sqlx::Error> for AppError {
+    fn from(err: sqlx::Error) -> Self {
+        StorageError::from(err).into()
+    }
+}
+
+// Unexpected failures; the cause goes to the log, not to the client.
+impl From<anyhow::Error> for AppError {
+    fn from(err: anyhow::Error) -> Self {
+        tracing::error!("internal error: {:#}", err);
+        Self::Internal("internal error".into())
+    }
 }
 
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
+        let status = self.status();
+        let code = self.code();
+        let (message, details) = match self {
+            Self::Validation { message, details }
+            | Self::RateLimited { message, details }
+            | Self::Upstream { message, details, .. } => (message, details),
+            other => (other.message().to_string(), Vec::new()),
+        };
         let payload = ErrorBody {
-            error: ErrorPayload {
-                code: self.code,
-                message: self.message,
-                details: self.details,
-                request_id: self.request_id,
-            },
+            error: ErrorPayload { code, message, details, request_id: current_request_id().unwrap_or_default() },
         };
-        (self.status, Json(payload)).into_response()
+        (status, Json(payload)).into_response()
+    }
+}
+
+// Rejection of the CSRF middleware; rendered in the regular error format.
+#[derive(Debug)]
+pub struct CsrfRejection;
+
+impl From<CsrfRejection> for AppError {
+    fn from(_: CsrfRejection) -> Self {
+        AppError::forbidden("csrf token invalid")
+    }
+}
+
+impl IntoResponse for CsrfRejection {
+    fn into_response(self) -> Response {
+        AppError::from(self).into_response()
     }
 }
@@ -0,0 +1,72 @@
+use crate::error::{AppError, ErrorDetail};
+use axum::body::Bytes;
+use axum::extract::{FromRequest, Request};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+// Drop-in for `axum::Json` whose rejections use the regular error body: a malformed body or a
+// field of the wrong shape is a `VALIDATION_ERROR` naming the field, a body over the route's
+// limit is `PAYLOAD_TOO_LARGE`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Json<T>(pub T);
+
+fn is_json(headers: &HeaderMap) -> bool {
+    let Some(content_type) = headers.get(header::CONTENT_TYPE).and_then(|v| v.to_str().ok()) else {
+        return false;
+    };
+    let mime = content_type.split(';').next().unwrap_or_default().trim().to_ascii_lowercase();
+    mime == "application/json" || (mime.starts_with("application/") && mime.ends_with("+json"))
+}
+
+fn invalid_body(field: &str, issue: impl Into<String>) -> AppError {
+    AppError::validation("invalid request body")
+        .with_details(vec![ErrorDetail { field: field.to_string(), issue: issue.into() }])
+}
+
+#[axum::async_trait]
+impl<T, S> FromRequest<S> for Json<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request(request: Request, state: &S) -> Result<Self, Self::Rejection> {
+        if !is_json(request.headers()) {
+            return Err(invalid_body("content-type", "expected application/json"));
+        }
+        let bytes = Bytes::from_request(request, state).await.map_err(|rejection| {
+            if rejection.status() == StatusCode::PAYLOAD_TOO_LARGE {
+                AppError::payload_too_large("request body is too large")
+            } else {
+                invalid_body("body", rejection.body_text())
+            }
+        })?;
+        let mut deserializer = serde_json::Deserializer::from_slice(&bytes);
+        match serde_path_to_error::deserialize(&mut deserializer) {
+            Ok(value) => {
+                deserializer.end().map_err(|err| invalid_body("body", format!("malformed JSON: {}", err)))?;
+                Ok(Json(value))
+            }
+            Err(err) => {
+                let path = err.path().to_string();
+                let err = err.into_inner();
+                if err.is_syntax() || err.is_eof() {
+                    return Err(invalid_body("body", format!("malformed JSON: {}", err)));
+                }
+                // serde_json appends the position, which means nothing to API clients.
+                let issue = err.to_string();
+                let issue = issue.split(" at line ").next().unwrap_or_default().to_string();
+                Err(invalid_body(if path == "." { "body" } else { &path }, issue))
+            }
+        }
+    }
+}
+
+impl<T: Serialize> IntoResponse for Json<T> {
+    fn into_response(self) -> Response {
+        axum::Json(self.0).into_response()
+    }
+}
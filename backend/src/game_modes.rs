@@ -0,0 +1,167 @@
+use crate::models::{answer_bonus, StudentStats};
+use crate::state::{GameModeConfig, ParticipantState};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+// A game mode decides how a participant moves through the quiz and what an answer costs or earns. The
+// WS session only calls these hooks, so a new mode is one more implementation registered in
+// `GameModes`. Every hook has the behaviour of a plain quiz driven by the session's `GameModeConfig`;
+// a mode overrides the ones it plays differently.
+pub trait GameMode: Send + Sync {
+    // The `gameMode` name sessions are created with.
+    fn name(&self) -> &'static str;
+
+    // Rules a session of this mode starts from unless the teacher passes `gameConfig`.
+    fn preset(&self) -> GameModeConfig;
+
+    // A nickname joins the session for the first time; a rejoin keeps the state it had.
+    fn on_join(&self, config: &GameModeConfig, participant: &mut ParticipantState) {
+        participant.lives = config.lives;
+    }
+
+    // Which question (an index into the quiz as written, after the participant's shuffle) to serve next.
+    fn next_question(
+        &self,
+        config: &GameModeConfig,
+        participant: &mut ParticipantState,
+        question_count: usize,
+    ) -> NextQuestion {
+        if self.is_out(participant) {
+            return NextQuestion::Out;
+        }
+        if participant.current_question_index >= question_count {
+            if !config.cycle_questions {
+                return NextQuestion::Finished;
+            }
+            // A new round: the questions may be answered (and scored) again.
+            participant.current_question_index = 0;
+            participant.answered.clear();
+        }
+        let position = participant.current_question_index;
+        NextQuestion::Question(participant.question_order.get(position).copied().unwrap_or(position))
+    }
+
+    // A question was settled for good: answered (retries used up) or timed out. Charges what a miss
+    // costs and returns the lives left when the mode has lives.
+    fn on_answer(
+        &self,
+        config: &GameModeConfig,
+        participant: &mut ParticipantState,
+        stats: Option<&mut StudentStats>,
+        correct: bool,
+    ) -> Option<u32> {
+        if !correct {
+            if let Some(stats) = stats {
+                stats.penalty += config.respawn_penalty;
+            }
+            if let Some(lives) = participant.lives.as_mut() {
+                *lives = lives.saturating_sub(1);
+            }
+        }
+        participant.lives
+    }
+
+    // Leaderboard bonus for a correct answer worth `points`; see `answer_bonus`.
+    fn bonus(&self, points: f64, latency_secs: Option<f64>, window_secs: u32, streak: u32) -> f64 {
+        answer_bonus(points, latency_secs, window_secs, streak)
+    }
+
+    // End condition: the participant takes no more questions or answers in this session.
+    fn is_out(&self, participant: &ParticipantState) -> bool {
+        participant.lives == Some(0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NextQuestion {
+    Question(usize),
+    // Every question is settled and the mode does not start over.
+    Finished,
+    Out,
+}
+
+pub struct Classic;
+
+impl GameMode for Classic {
+    fn name(&self) -> &'static str {
+        "classic"
+    }
+
+    fn preset(&self) -> GameModeConfig {
+        GameModeConfig::default()
+    }
+}
+
+// The mini-game modes play on the client; on the server they only differ from classic in cycling through
+// the questions for as long as the game runs.
+pub struct Platformer;
+pub struct Shooter;
+pub struct Tycoon;
+
+fn cycling() -> GameModeConfig {
+    GameModeConfig { cycle_questions: true, ..GameModeConfig::default() }
+}
+
+impl GameMode for Platformer {
+    fn name(&self) -> &'static str {
+        "platformer"
+    }
+
+    fn preset(&self) -> GameModeConfig {
+        cycling()
+    }
+}
+
+impl GameMode for Shooter {
+    fn name(&self) -> &'static str {
+        "shooter"
+    }
+
+    fn preset(&self) -> GameModeConfig {
+        cycling()
+    }
+}
+
+impl GameMode for Tycoon {
+    fn name(&self) -> &'static str {
+        "tycoon"
+    }
+
+    fn preset(&self) -> GameModeConfig {
+        cycling()
+    }
+}
+
+#[derive(Clone)]
+pub struct GameModes {
+    modes: HashMap<&'static str, Arc<dyn GameMode>>,
+}
+
+impl GameModes {
+    pub fn builtin() -> Self {
+        let mut modes = Self { modes: HashMap::new() };
+        modes.register(Arc::new(Classic));
+        modes.register(Arc::new(Platformer));
+        modes.register(Arc::new(Shooter));
+        modes.register(Arc::new(Tycoon));
+        modes
+    }
+
+    pub fn register(&mut self, mode: Arc<dyn GameMode>) {
+        self.modes.insert(mode.name(), mode);
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<dyn GameMode>> {
+        self.modes.get(name).cloned()
+    }
+
+    // The mode a stored session plays; one whose mode is no longer registered plays classic.
+    pub fn for_session(&self, name: &str) -> Arc<dyn GameMode> {
+        self.get(name).unwrap_or_else(|| Arc::new(Classic))
+    }
+}
+
+// Preset of a built-in mode, for sessions stored before they carried their own config.
+pub fn builtin_preset(name: &str) -> Option<GameModeConfig> {
+    GameModes::builtin().get(name).map(|mode| mode.preset())
+}
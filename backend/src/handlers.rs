@@ -1,63 +1,178 @@
-use crate::error::{AppError, ErrorDetail};
-use crate::models::{score_answer, validate_quiz, Quiz, StudentStats, SubmittedAnswer};
-use crate::state::{AppState, ParticipantState, QuizRecord, SessionRecord, Teacher, TeacherSession};
-use crate::ws_protocol::WsEnvelope;
-use argon2::{password_hash::SaltString, Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
+use crate::ai_providers::{self, AiFailure, AiProviderChoice, AiRequest};
+use crate::api_keys::{self, API_KEY_HEADER};
+use crate::api_v2;
+use crate::backup;
+use crate::document_text;
+use crate::email;
+use crate::capacity::WsPermit;
+use crate::error::{AppError, CsrfRejection, ErrorDetail};
+use crate::game_modes::{GameMode, NextQuestion};
+use crate::item_analysis;
+use crate::journal::JournalOp;
+use crate::media::{self, MediaError};
+use crate::models::{
+    grade_answer, question_order, shuffle_options, validate_quiz, Difficulty, QuestionType, Quiz, QuizSettings,
+    StudentStats, SubmittedAnswer, GRADE_RANGE, SPEED_BONUS_WINDOW_SECS,
+};
+use crate::password_reset;
+use crate::profanity::{ProfanityFilter, WordOverrides};
+use crate::prompt_templates::{self, PromptTemplateVersion, PromptUseCase};
+use crate::quiz_export;
+use crate::quiz_import;
+use crate::totp::{self, TotpState};
+use crate::state::{
+    short_hash, AiJob, AiQuizClient, AnswerRecord, AppState, ClassRecord, Disruption, HostQuestion, LtiGradeTarget,
+    ParticipantState, PlaylistSegment, GameModeConfig, HomeworkWindow, LobbySettings, QuizRecord, RosterEntry,
+    RoomChannels, SessionPacing, SessionRecord, Student, Teacher, COUNTDOWN_SECS_RANGE, NICKNAME_LENGTH_LIMIT,
+};
+use crate::storage::OrNotFound;
+use crate::web;
+use crate::ws_protocol::{
+    AnswerSubmit, ClientEvent, ClientMessage, JoinRole, ProtocolError, ServerEvent, WsEnvelope, WS_PROTOCOL_SCHEMA,
+};
 use axum::extract::ws::{Message, WebSocket};
-use axum::extract::{Path, State, WebSocketUpgrade};
-use axum::http::{HeaderMap, StatusCode};
-use axum::response::Response;
-use axum::Json;
-use axum_extra::extract::cookie::{Cookie, CookieJar, SameSite};
+use axum::extract::{Path, Request, State, WebSocketUpgrade};
+use axum::http::{HeaderMap, HeaderValue, Method, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use crate::extract::Json;
+use axum_extra::extract::cookie::CookieJar;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
 use chrono::Utc;
 use rand::distributions::Alphanumeric;
+use rand::seq::SliceRandom;
 use rand::Rng;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Arc;
 use std::time::{Duration, Instant};
-use tokio::sync::broadcast;
-use tracing::{info, warn};
-use once_cell::sync::Lazy;
-use dashmap::DashMap;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{mpsc, watch};
+use tracing::{debug_span, info, warn, Instrument};
 
 const SESSION_COOKIE: &str = "teacher_session";
-static RATE_LIMIT: Lazy<DashMap<String, (u32, Instant)>> = Lazy::new(DashMap::new);
-
-fn check_rate_limit(scope: &str, key: &str, limit_per_minute: u32) -> bool {
-    let now = Instant::now();
-    let full_key = format!("{scope}:{key}");
-    if let Some(mut entry) = RATE_LIMIT.get_mut(&full_key) {
-        if now.duration_since(entry.1) > Duration::from_secs(60) {
-            *entry = (1, now);
-            true
-        } else if entry.0 >= limit_per_minute {
-            false
-        } else {
-            entry.0 += 1;
-            true
+const LATE_ANSWER_GRACE: chrono::Duration = chrono::Duration::seconds(2);
+
+// Counts the hit and returns how long to wait when the limit is exceeded. Requests pass when the
+// counter store is unreachable, so a Redis outage does not take the API down.
+async fn rate_limit_exceeded(state: &AppState, bucket: &str, limit: u32, window: Duration) -> Option<Duration> {
+    match state.shared.hit_rate_limit(bucket, window).await {
+        Ok(hit) if hit.count > limit => Some(hit.resets_in),
+        Ok(_) => None,
+        Err(err) => {
+            warn!("rate limit store unavailable: {}", err);
+            None
         }
-    } else {
-        RATE_LIMIT.insert(full_key, (1, now));
-        true
     }
 }
 
-fn request_id_from_headers(headers: &HeaderMap) -> String {
-    headers
-        .get("x-request-id")
-        .and_then(|h| h.to_str().ok())
-        .map(|s| s.to_string())
-        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string())
+fn rate_limited(retry_after: Duration) -> Response {
+    let secs = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+    let mut response = AppError::rate_limited("too many requests").into_response();
+    response.headers_mut().insert(axum::http::header::RETRY_AFTER, HeaderValue::from(secs.max(1)));
+    response
+}
+
+// Client address for rate limiting, resolved through TRUSTED_PROXIES; "local" when the server
+// runs without connection info (tests).
+fn client_ip_key(state: &AppState, request: &Request) -> String {
+    let ip = state.web.client_ip(web::peer_ip(request.extensions()), request.headers());
+    ip.map(|ip| ip.to_string()).unwrap_or_else(|| "local".to_string())
+}
+
+// Ids from a proxy or client are kept if they are short printable ASCII, so they are safe to log
+// and echo back.
+fn incoming_request_id(headers: &HeaderMap) -> Option<String> {
+    let value = headers.get(REQUEST_ID_HEADER)?.to_str().ok()?.trim();
+    let valid = !value.is_empty() && value.len() <= 128 && value.bytes().all(|b| b.is_ascii_graphic());
+    valid.then(|| value.to_string())
+}
+
+const REQUEST_ID_HEADER: &str = "x-request-id";
+
+// The request id as a typed extension for handlers that take `Extension<RequestId>`.
+#[derive(Debug, Clone)]
+pub struct RequestId(pub String);
+
+// Outermost middleware: picks the request id once, writes it back into the request header so the
+// trace span and every handler see the same value, and echoes it in the response.
+pub async fn request_id(mut request: Request, next: Next) -> Response {
+    let id = incoming_request_id(request.headers()).unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+    let header = HeaderValue::from_str(&id).expect("request id is printable ascii");
+    request.headers_mut().insert(REQUEST_ID_HEADER, header.clone());
+    request.extensions_mut().insert(RequestId(id.clone()));
+    let mut response = crate::error::with_request_id(id, next.run(request)).await;
+    response.headers_mut().insert(REQUEST_ID_HEADER, header);
+    response
+}
+
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    let value = headers.get(axum::http::header::AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, token) = value.split_once(' ')?;
+    scheme.eq_ignore_ascii_case("bearer").then(|| token.trim())
+}
+
+// Access tokens issued before the teacher's last password change are rejected.
+async fn bearer_teacher_id(token: &str, state: &AppState) -> Option<i64> {
+    let token = state.jwt.verify_access_token(token)?;
+    let teachers = state.db.teachers.read().await;
+    let teacher = teachers.get(&token.teacher_id)?;
+    if teacher.password_changed_at.map(|t| token.issued_at < t.timestamp()).unwrap_or(false) {
+        return None;
+    }
+    Some(teacher.id)
+}
+
+fn api_key_header(headers: &HeaderMap) -> Option<&str> {
+    headers.get(API_KEY_HEADER).and_then(|v| v.to_str().ok())
+}
+
+async fn api_key_teacher_id(key: &str, state: &AppState) -> Option<i64> {
+    let (id, secret) = api_keys::parse(key)?;
+    let mut keys = state.db.api_keys.write().await;
+    let record = keys.get_mut(id).filter(|k| k.matches(secret))?;
+    // Usage time is informational only and is not journaled.
+    record.last_used_at = Some(Utc::now());
+    Some(record.teacher_id)
 }
 
-async fn auth_teacher_id(jar: &CookieJar, state: &AppState) -> Option<i64> {
+// A request that carries an API key or an Authorization header is authenticated by that
+// credential alone, even if a session cookie is present as well.
+async fn auth_teacher_id(headers: &HeaderMap, jar: &CookieJar, state: &AppState) -> Option<i64> {
+    if headers.contains_key(API_KEY_HEADER) {
+        return api_key_teacher_id(api_key_header(headers)?, state).await;
+    }
+    if headers.contains_key(axum::http::header::AUTHORIZATION) {
+        return bearer_teacher_id(bearer_token(headers)?, state).await;
+    }
     let sid = jar.get(SESSION_COOKIE)?.value().to_string();
-    let sessions = state.db.sessions.read().await;
-    sessions.get(&sid).map(|v| v.teacher_id)
+    state.touch_teacher_session(&sid).await
+}
+
+// Account security (password, email, 2FA, login sessions, API keys) is managed from an interactive
+// login only, so a leaked API key cannot mint more keys, enroll its own second factor or log the
+// teacher out everywhere.
+async fn interactive_teacher_id(headers: &HeaderMap, jar: &CookieJar, state: &AppState) -> Result<i64, AppError> {
+    if headers.contains_key(API_KEY_HEADER) {
+        return Err(AppError::forbidden("api keys cannot manage the account"));
+    }
+    auth_teacher_id(headers, jar, state)
+        .await
+        .ok_or_else(|| AppError::unauthorized("not logged in"))
 }
 
 async fn ensure_csrf(headers: &HeaderMap, jar: &CookieJar, state: &AppState) -> bool {
+    // Browsers never attach bearer tokens or API keys on their own, so they need no CSRF token.
+    if headers.contains_key(API_KEY_HEADER) {
+        return match api_key_header(headers) {
+            Some(key) => api_key_teacher_id(key, state).await.is_some(),
+            None => false,
+        };
+    }
+    if let Some(token) = bearer_token(headers) {
+        return bearer_teacher_id(token, state).await.is_some();
+    }
     let sid = match jar.get(SESSION_COOKIE) {
         Some(v) => v.value().to_string(),
         None => return false,
@@ -66,17 +181,134 @@ async fn ensure_csrf(headers: &HeaderMap, jar: &CookieJar, state: &AppState) ->
         Some(v) => v,
         None => return false,
     };
-    let sessions = state.db.sessions.read().await;
-    sessions
-        .get(&sid)
+    state
+        .teacher_session(&sid)
+        .await
         .map(|s| s.csrf_token == header)
         .unwrap_or(false)
 }
 
+// Applied to the mutating teacher routes in routes.rs; safe methods pass through untouched.
+pub async fn csrf_guard(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Result<Response, CsrfRejection> {
+    if !matches!(*request.method(), Method::GET | Method::HEAD | Method::OPTIONS) {
+        let jar = CookieJar::from_headers(request.headers());
+        if !ensure_csrf(request.headers(), &jar, &state).await {
+            return Err(CsrfRejection);
+        }
+    }
+    Ok(next.run(request).await)
+}
+
+// Counts and times every request for /metrics, labelled by the route pattern it matched.
+pub async fn track_http(request: Request, next: Next) -> Response {
+    let method = request.method().to_string();
+    let route = request
+        .extensions()
+        .get::<axum::extract::MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+    let started = Instant::now();
+    let response = next.run(request).await;
+    crate::metrics::record_http(&method, &route, response.status().as_u16(), started.elapsed());
+    response
+}
+
+// Below this a gzip round trip costs more than it saves.
+const GZIP_MIN_BYTES: usize = 8 * 1024;
+
+fn accepts_gzip(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(axum::http::header::ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|coding| {
+            let mut params = coding.split(';');
+            let name = params.next().unwrap_or_default().trim();
+            let refused = params.any(|p| matches!(p.trim(), "q=0" | "q=0.0" | "q=0.00" | "q=0.000"));
+            name.eq_ignore_ascii_case("gzip") && !refused
+        })
+}
+
+// Gzips large JSON bodies (whole quizzes, library pages) for clients that accept it. JSON
+// responses are already fully buffered, so collecting the body here costs nothing extra.
+pub async fn compress_json(request: Request, next: Next) -> Response {
+    use axum::http::header;
+    use std::io::Write;
+
+    let gzip = accepts_gzip(request.headers());
+    let response = next.run(request).await;
+    let is_json = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("application/json"));
+    if !gzip || !is_json || response.headers().contains_key(header::CONTENT_ENCODING) {
+        return response;
+    }
+    let (mut parts, body) = response.into_parts();
+    let bytes = match axum::body::to_bytes(body, usize::MAX).await {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            warn!("failed to buffer response for compression: {}", err);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    parts.headers.append(header::VARY, HeaderValue::from_static("accept-encoding"));
+    if bytes.len() < GZIP_MIN_BYTES {
+        return Response::from_parts(parts, axum::body::Body::from(bytes));
+    }
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+    let compressed = encoder.write_all(&bytes).and_then(|_| encoder.finish());
+    match compressed {
+        Ok(compressed) => {
+            parts.headers.insert(header::CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+            parts.headers.remove(header::CONTENT_LENGTH);
+            Response::from_parts(parts, axum::body::Body::from(compressed))
+        }
+        Err(err) => {
+            warn!("gzip failed, sending the body uncompressed: {}", err);
+            Response::from_parts(parts, axum::body::Body::from(bytes))
+        }
+    }
+}
+
+// Route policies from RateLimits count per client IP. Requests with an API key additionally get a
+// bucket per key, so a busy integration cannot starve interactive users behind the same proxy
+// address.
+pub async fn rate_limit(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let limits = &state.rate_limits;
+    if let Some((key_id, _)) = api_key_header(request.headers()).and_then(api_keys::parse) {
+        let bucket = format!("api_key:{key_id}");
+        if let Some(retry_after) =
+            rate_limit_exceeded(&state, &bucket, limits.api_key_per_minute, Duration::from_secs(60)).await
+        {
+            return rate_limited(retry_after);
+        }
+    }
+    let route = request.extensions().get::<axum::extract::MatchedPath>().map(|path| path.as_str());
+    if let Some(policy) = route.and_then(|route| limits.policy(request.method().as_str(), route)) {
+        let bucket = policy.bucket(&client_ip_key(&state, &request));
+        if let Some(retry_after) = rate_limit_exceeded(&state, &bucket, policy.limit, policy.window()).await
+        {
+            return rate_limited(retry_after);
+        }
+    }
+    next.run(request).await
+}
+
 #[derive(Debug, Deserialize)]
 pub struct AuthPayload {
     pub login: String,
     pub password: String,
+    #[serde(default, rename = "totpCode")]
+    pub totp_code: Option<String>,
+    #[serde(default, rename = "recoveryCode")]
+    pub recovery_code: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
@@ -87,57 +319,37 @@ pub struct TeacherOut {
 
 pub async fn register(
     State(state): State<AppState>,
-    headers: HeaderMap,
     Json(payload): Json<AuthPayload>,
 ) -> Result<(StatusCode, Json<TeacherOut>), AppError> {
-    let req_id = request_id_from_headers(&headers);
-    let ip = headers
-        .get("x-forwarded-for")
-        .and_then(|v| v.to_str().ok())
-        .unwrap_or("local");
-    if !check_rate_limit("auth_register", ip, 20) {
-        return Err(AppError::new(
-            StatusCode::TOO_MANY_REQUESTS,
-            "RATE_LIMITED",
-            "too many requests",
-            req_id,
-        ));
-    }
     let login = payload.login.trim().to_string();
     if login.len() < 3 || payload.password.len() < 8 {
-        return Err(AppError::new(
-            StatusCode::BAD_REQUEST,
-            "VALIDATION_ERROR",
-            "invalid login/password",
-            req_id,
-        ));
+        return Err(AppError::validation("invalid login/password"));
     }
 
     {
         let map = state.db.teachers_by_login.read().await;
         if map.contains_key(&login) {
-            return Err(AppError::new(
-                StatusCode::CONFLICT,
-                "CONFLICT",
-                "login already exists",
-                req_id,
-            ));
+            return Err(AppError::conflict("login already exists"));
         }
     }
 
-    let salt = SaltString::generate(&mut argon2::password_hash::rand_core::OsRng);
-    let hash = Argon2::default()
-        .hash_password(payload.password.as_bytes(), &salt)
-        .map_err(|_| AppError::new(StatusCode::INTERNAL_SERVER_ERROR, "INTERNAL_ERROR", "password hash failed", req_id.clone()))?
-        .to_string();
+    let hash = hash_password(&state, &payload.password)?;
 
     let id = state.db.next_teacher_id();
-    let teacher = Teacher { id, login: login.clone(), password_hash: hash };
-    state.db.teachers.write().await.insert(id, teacher);
+    let teacher = Teacher {
+        id,
+        login: login.clone(),
+        password_hash: hash,
+        password_changed_at: None,
+        totp: None,
+        email: None,
+        locale: None,
+        word_overrides: Default::default(),
+    };
+    state.db.teachers.write().await.insert(id, teacher.clone());
     state.db.teachers_by_login.write().await.insert(login.clone(), id);
-    if let Err(err) = state.persist_core_data().await {
-        warn!("failed to persist local state after register: {}", err);
-    }
+    state.log_change(JournalOp::TeacherUpserted { teacher }).await;
+    state.commit_changes()?;
 
     Ok((StatusCode::CREATED, Json(TeacherOut { id, login })))
 }
@@ -148,25 +360,88 @@ pub async fn login(
     jar: CookieJar,
     Json(payload): Json<AuthPayload>,
 ) -> Result<(CookieJar, Json<TeacherOut>), AppError> {
-    let req_id = request_id_from_headers(&headers);
-    let ip = headers
-        .get("x-forwarded-for")
-        .and_then(|v| v.to_str().ok())
-        .unwrap_or("local");
-    if !check_rate_limit("auth_login", ip, 30) {
-        return Err(AppError::new(
-            StatusCode::TOO_MANY_REQUESTS,
-            "RATE_LIMITED",
-            "too many requests",
-            req_id,
-        ));
+    let teacher = verify_credentials(
+        &state,
+        &payload.login,
+        &payload.password,
+        payload.totp_code.as_deref(),
+        payload.recovery_code.as_deref(),
+    )
+    .await?;
+    let id = teacher.id;
+
+    let (session_id, csrf_token) = state
+        .start_teacher_session(id, headers.get(axum::http::header::USER_AGENT).and_then(|v| v.to_str().ok()))
+        .await?;
+
+    state.audit(Some(id), "auth.login", None).await;
+
+    let cookie = state.web.cookie(SESSION_COOKIE, session_id, true);
+    let csrf_cookie = state.web.cookie("csrf_token", csrf_token, false);
+
+    Ok((jar.add(cookie).add(csrf_cookie), Json(TeacherOut { id, login: teacher.login })))
+}
+
+fn hash_password(state: &AppState, password: &str) -> Result<String, AppError> {
+    state
+        .passwords
+        .hash(password)
+        .map_err(|_| AppError::internal("password hash failed"))
+}
+
+fn password_matches(state: &AppState, teacher: &Teacher, password: &str) -> Result<bool, AppError> {
+    state
+        .passwords
+        .verify(&teacher.password_hash, password)
+        .map_err(|_| AppError::internal("bad hash"))
+}
+
+// The plain password is only available at login, so that is where hashes made with older, weaker
+// ARGON2_* settings get upgraded. A failure here never blocks the login.
+async fn rehash_if_needed(state: &AppState, teacher_id: i64, password: &str) {
+    let Ok(hash) = state.passwords.hash(password) else { return };
+    let updated = {
+        let mut teachers = state.db.teachers.write().await;
+        let Some(teacher) = teachers.get_mut(&teacher_id) else { return };
+        if !state.passwords.needs_rehash(&teacher.password_hash) {
+            return;
+        }
+        teacher.password_hash = hash;
+        teacher.clone()
+    };
+    info!("upgraded password hash parameters for teacher {}", teacher_id);
+    state.log_change(JournalOp::TeacherUpserted { teacher: updated }).await;
+    state.schedule_persist();
+}
+
+// Failed attempts go to the audit trail under the login that was tried.
+async fn verify_credentials(
+    state: &AppState,
+    login: &str,
+    password: &str,
+    totp_code: Option<&str>,
+    recovery_code: Option<&str>,
+) -> Result<Teacher, AppError> {
+    let result = check_credentials(state, login, password, totp_code, recovery_code).await;
+    if let Err(err) = &result {
+        state.audit_as(login.trim().to_string(), None, "auth.login_failed", Some(err.code().to_string())).await;
     }
-    let login = payload.login.trim().to_string();
+    result
+}
+
+async fn check_credentials(
+    state: &AppState,
+    login: &str,
+    password: &str,
+    totp_code: Option<&str>,
+    recovery_code: Option<&str>,
+) -> Result<Teacher, AppError> {
+    let login = login.trim().to_string();
     let id = {
         let by_login = state.db.teachers_by_login.read().await;
         by_login.get(&login).copied()
     }
-    .ok_or_else(|| AppError::new(StatusCode::UNAUTHORIZED, "UNAUTHORIZED", "invalid credentials", req_id.clone()))?;
+    .ok_or_else(|| AppError::unauthorized("invalid credentials"))?;
 
     let teacher = state
         .db
@@ -175,960 +450,5702 @@ pub async fn login(
         .await
         .get(&id)
         .cloned()
-        .ok_or_else(|| AppError::new(StatusCode::UNAUTHORIZED, "UNAUTHORIZED", "invalid credentials", req_id.clone()))?;
-
-    let parsed_hash = PasswordHash::new(&teacher.password_hash)
-        .map_err(|_| AppError::new(StatusCode::INTERNAL_SERVER_ERROR, "INTERNAL_ERROR", "bad hash", req_id.clone()))?;
-    let is_valid = Argon2::default()
-        .verify_password(payload.password.as_bytes(), &parsed_hash)
-        .is_ok();
-    if !is_valid {
-        return Err(AppError::new(
-            StatusCode::UNAUTHORIZED,
-            "UNAUTHORIZED",
-            "invalid credentials",
-            req_id,
-        ));
-    }
+        .ok_or_else(|| AppError::unauthorized("invalid credentials"))?;
 
-    let session_id = uuid::Uuid::new_v4().to_string();
-    let csrf_token = uuid::Uuid::new_v4().to_string();
-    state.db.sessions.write().await.insert(
-        session_id.clone(),
-        TeacherSession { teacher_id: id, csrf_token: csrf_token.clone() },
-    );
+    if !password_matches(state, &teacher, password)? {
+        return Err(AppError::unauthorized("invalid credentials"));
+    }
+    check_second_factor(state, &teacher, totp_code, recovery_code).await?;
+    if state.passwords.needs_rehash(&teacher.password_hash) {
+        rehash_if_needed(state, teacher.id, password).await;
+    }
+    Ok(teacher)
+}
 
-    let cookie = Cookie::build((SESSION_COOKIE, session_id))
-        .http_only(true)
-        .same_site(SameSite::Lax)
-        .path("/")
-        .build();
-    let csrf_cookie = Cookie::build(("csrf_token", csrf_token))
-        .http_only(false)
-        .same_site(SameSite::Lax)
-        .path("/")
-        .build();
+// Runs after the password was accepted. Teachers without enabled 2FA pass straight through.
+async fn check_second_factor(
+    state: &AppState,
+    teacher: &Teacher,
+    totp_code: Option<&str>,
+    recovery_code: Option<&str>,
+) -> Result<(), AppError> {
+    let Some(totp) = teacher.totp.as_ref().filter(|t| t.enabled) else {
+        return Ok(());
+    };
+    let invalid = || AppError::unauthorized("invalid one-time code");
+    let updated = if let Some(code) = totp_code {
+        // Checked under the write lock so the same code cannot be accepted twice.
+        let mut teachers = state.db.teachers.write().await;
+        let current = teachers.get_mut(&teacher.id).ok_or_else(invalid)?;
+        let totp = current.totp.as_mut().ok_or_else(invalid)?;
+        let step = totp::verify(&totp.secret, code, Utc::now().timestamp().max(0) as u64, totp.last_step)
+            .ok_or_else(invalid)?;
+        totp.last_step = step;
+        current.clone()
+    } else if let Some(code) = recovery_code {
+        let code = code.trim().to_lowercase();
+        let used = totp
+            .recovery_codes
+            .iter()
+            .find(|hash| {
+                state.passwords.verify(hash, &code).unwrap_or(false)
+            })
+            .cloned()
+            .ok_or_else(invalid)?;
+        let mut teachers = state.db.teachers.write().await;
+        let current = teachers.get_mut(&teacher.id).ok_or_else(invalid)?;
+        let totp = current.totp.as_mut().ok_or_else(invalid)?;
+        let before = totp.recovery_codes.len();
+        totp.recovery_codes.retain(|hash| *hash != used);
+        if totp.recovery_codes.len() == before {
+            return Err(invalid());
+        }
+        info!("teacher {} used a recovery code, {} left", teacher.id, totp.recovery_codes.len());
+        current.clone()
+    } else {
+        return Err(AppError::rejected(StatusCode::UNAUTHORIZED, "TOTP_REQUIRED", "one-time code required"));
+    };
+    state.log_change(JournalOp::TeacherUpserted { teacher: updated }).await;
+    state.commit_changes().map_err(AppError::from)
+}
 
-    Ok((jar.add(cookie).add(csrf_cookie), Json(TeacherOut { id, login: teacher.login })))
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TotpSetupOut {
+    pub secret: String,
+    pub otpauth_uri: String,
 }
 
-pub async fn logout(
+pub async fn totp_setup(
     State(state): State<AppState>,
     headers: HeaderMap,
     jar: CookieJar,
-) -> Result<(CookieJar, StatusCode), AppError> {
-    let req_id = request_id_from_headers(&headers);
-    let sid = jar
-        .get(SESSION_COOKIE)
-        .map(|v| v.value().to_string())
-        .ok_or_else(|| AppError::new(StatusCode::UNAUTHORIZED, "UNAUTHORIZED", "no session", req_id.clone()))?;
-    state.db.sessions.write().await.remove(&sid);
-    Ok((jar.remove(Cookie::from(SESSION_COOKIE)), StatusCode::NO_CONTENT))
+) -> Result<Json<TotpSetupOut>, AppError> {
+    let teacher_id = interactive_teacher_id(&headers, &jar, &state).await?;
+    let secret = totp::generate_secret();
+    let teacher = {
+        let mut teachers = state.db.teachers.write().await;
+        let teacher = teachers.get_mut(&teacher_id).or_not_found("teacher")?;
+        if teacher.totp.as_ref().map(|t| t.enabled).unwrap_or(false) {
+            return Err(AppError::conflict("two-factor authentication is already enabled"));
+        }
+        teacher.totp = Some(TotpState { secret: secret.clone(), ..TotpState::default() });
+        teacher.clone()
+    };
+    let otpauth_uri = totp::otpauth_uri(&state.totp_issuer, &teacher.login, &secret);
+    state.log_change(JournalOp::TeacherUpserted { teacher }).await;
+    state.commit_changes()?;
+    Ok(Json(TotpSetupOut { secret, otpauth_uri }))
 }
 
-pub async fn me(
+#[derive(Debug, Deserialize)]
+pub struct TotpVerifyPayload {
+    pub code: String,
+}
+
+// Confirms the pending secret from /2fa/setup and turns 2FA on. Recovery codes are returned
+// once in plain text; only their hashes are kept.
+pub async fn totp_verify(
     State(state): State<AppState>,
     headers: HeaderMap,
     jar: CookieJar,
-) -> Result<Json<TeacherOut>, AppError> {
-    let req_id = request_id_from_headers(&headers);
-    let teacher_id = auth_teacher_id(&jar, &state).await
-        .ok_or_else(|| AppError::new(StatusCode::UNAUTHORIZED, "UNAUTHORIZED", "not logged in", req_id.clone()))?;
-    let teacher = state
-        .db
-        .teachers
-        .read()
-        .await
-        .get(&teacher_id)
-        .cloned()
-        .ok_or_else(|| AppError::new(StatusCode::UNAUTHORIZED, "UNAUTHORIZED", "not logged in", req_id))?;
-    Ok(Json(TeacherOut { id: teacher.id, login: teacher.login }))
+    Json(payload): Json<TotpVerifyPayload>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let teacher_id = interactive_teacher_id(&headers, &jar, &state).await?;
+    let recovery_codes = totp::generate_recovery_codes();
+    let hashes = recovery_codes
+        .iter()
+        .map(|code| hash_password(&state, code))
+        .collect::<Result<Vec<_>, _>>()?;
+    let teacher = {
+        let mut teachers = state.db.teachers.write().await;
+        let teacher = teachers.get_mut(&teacher_id).or_not_found("teacher")?;
+        let totp = teacher.totp.as_mut().filter(|t| !t.enabled).ok_or_else(|| {
+            AppError::conflict("call /auth/2fa/setup first")
+        })?;
+        let step = totp::verify(&totp.secret, &payload.code, Utc::now().timestamp().max(0) as u64, totp.last_step)
+            .ok_or_else(|| AppError::validation("invalid one-time code"))?;
+        totp.enabled = true;
+        totp.last_step = step;
+        totp.recovery_codes = hashes;
+        teacher.clone()
+    };
+    state.log_change(JournalOp::TeacherUpserted { teacher }).await;
+    state.commit_changes()?;
+    info!("teacher {} enabled two-factor authentication", teacher_id);
+    Ok(Json(json!({ "enabled": true, "recoveryCodes": recovery_codes })))
 }
 
 #[derive(Debug, Deserialize)]
-pub struct CreateQuizPayload {
-    pub title: String,
-    pub description: Option<String>,
-    pub questions: Vec<crate::models::Question>,
+#[serde(tag = "grantType", rename_all = "snake_case")]
+pub enum TokenRequest {
+    #[serde(rename_all = "camelCase")]
+    Password {
+        login: String,
+        password: String,
+        #[serde(default)]
+        totp_code: Option<String>,
+        #[serde(default)]
+        recovery_code: Option<String>,
+    },
+    #[serde(rename_all = "camelCase")]
+    RefreshToken { refresh_token: String },
 }
 
 #[derive(Debug, Serialize)]
-pub struct QuizIdResponse {
-    pub quiz_id: i64,
+#[serde(rename_all = "camelCase")]
+pub struct TokenResponse {
+    pub access_token: String,
+    pub token_type: &'static str,
+    pub expires_in: i64,
+    pub refresh_token: String,
 }
 
-pub async fn create_quiz(
+pub async fn issue_token(
     State(state): State<AppState>,
-    headers: HeaderMap,
-    jar: CookieJar,
-    Json(payload): Json<CreateQuizPayload>,
-) -> Result<(StatusCode, Json<QuizIdResponse>), AppError> {
-    let req_id = request_id_from_headers(&headers);
-    if !ensure_csrf(&headers, &jar, &state).await {
-        return Err(AppError::new(StatusCode::FORBIDDEN, "FORBIDDEN", "csrf token invalid", req_id));
-    }
-    let teacher_id = auth_teacher_id(&jar, &state).await
-        .ok_or_else(|| AppError::new(StatusCode::UNAUTHORIZED, "UNAUTHORIZED", "not logged in", request_id_from_headers(&headers)))?;
-
-    let quiz = Quiz {
-        title: payload.title,
-        description: payload.description,
-        questions: payload.questions,
+    Json(payload): Json<TokenRequest>,
+) -> Result<Json<TokenResponse>, AppError> {
+    let teacher_id = match payload {
+        TokenRequest::Password { login, password, totp_code, recovery_code } => {
+            let id = verify_credentials(&state, &login, &password, totp_code.as_deref(), recovery_code.as_deref())
+                .await?
+                .id;
+            state.audit(Some(id), "auth.token", None).await;
+            id
+        }
+        TokenRequest::RefreshToken { refresh_token } => {
+            // Refresh tokens rotate: the presented one is consumed and a new one is issued below.
+            let record = state
+                .shared
+                .take_refresh_token(&refresh_token)
+                .await?
+                .filter(|r| r.expires_at > Utc::now())
+                .ok_or_else(|| AppError::unauthorized("invalid refresh token"))?;
+            state.log_change(JournalOp::RefreshTokenRemoved { token: refresh_token }).await;
+            if !state.db.teachers.read().await.contains_key(&record.teacher_id) {
+                return Err(AppError::unauthorized("invalid refresh token"));
+            }
+            record.teacher_id
+        }
     };
-    if let Err(issues) = validate_quiz(&quiz) {
-        return Err(AppError::new(
-            StatusCode::BAD_REQUEST,
-            "VALIDATION_ERROR",
-            "quiz validation failed",
-            request_id_from_headers(&headers),
-        )
-        .with_details(
-            issues
-                .into_iter()
-                .map(|i| ErrorDetail {
-                    field: i.field,
-                    issue: i.issue,
-                })
-                .collect(),
-        ));
+
+    let access_token = state
+        .jwt
+        .issue_access_token(teacher_id)
+        .map_err(|_| AppError::internal("token signing failed"))?;
+    let (refresh_token, record) = state.jwt.new_refresh_token(teacher_id);
+    state
+        .shared
+        .put_refresh_token(&refresh_token, &record)
+        .await?;
+    state
+        .log_change(JournalOp::RefreshTokenPut { token: refresh_token.clone(), record })
+        .await;
+    state.commit_changes()?;
+
+    Ok(Json(TokenResponse {
+        access_token,
+        token_type: "Bearer",
+        expires_in: state.jwt.access_ttl.num_seconds(),
+        refresh_token,
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RevokeTokenPayload {
+    pub refresh_token: String,
+}
+
+pub async fn revoke_token(
+    State(state): State<AppState>,
+    Json(payload): Json<RevokeTokenPayload>,
+) -> Result<StatusCode, AppError> {
+    let removed = state
+        .shared
+        .take_refresh_token(&payload.refresh_token)
+        .await?;
+    if removed.is_some() {
+        state
+            .log_change(JournalOp::RefreshTokenRemoved { token: payload.refresh_token })
+            .await;
+        state.commit_changes()?;
     }
+    Ok(StatusCode::NO_CONTENT)
+}
 
-    let id = state.create_quiz(teacher_id, quiz, None).await;
-    Ok((StatusCode::CREATED, Json(QuizIdResponse { quiz_id: id })))
+const MAX_API_KEYS_PER_TEACHER: usize = 20;
+
+fn api_key_out(key: &api_keys::ApiKey) -> serde_json::Value {
+    json!({
+        "id": key.id,
+        "name": key.name,
+        "createdAt": key.created_at,
+        "lastUsedAt": key.last_used_at,
+    })
 }
 
-#[derive(Debug, Serialize)]
-pub struct QuizSummary {
-    pub id: i64,
-    pub title: String,
-    pub description: Option<String>,
-    pub is_published: bool,
+#[derive(Debug, Deserialize)]
+pub struct ApiKeyPayload {
+    pub name: String,
 }
 
-#[derive(Debug, Serialize)]
-pub struct QuizListResponse {
-    pub items: Vec<QuizSummary>,
-    pub total: usize,
+pub async fn create_api_key(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    jar: CookieJar,
+    Json(payload): Json<ApiKeyPayload>,
+) -> Result<(StatusCode, Json<serde_json::Value>), AppError> {
+    let teacher_id = interactive_teacher_id(&headers, &jar, &state).await?;
+    let name = payload.name.trim().to_string();
+    if name.is_empty() || name.chars().count() > 64 {
+        return Err(AppError::validation("invalid api key")
+            .with_details(vec![ErrorDetail { field: "name".into(), issue: "must be 1..64 characters".into() }]));
+    }
+    let (key, record) = api_keys::generate(teacher_id, name);
+    {
+        let mut keys = state.db.api_keys.write().await;
+        if keys.values().filter(|k| k.teacher_id == teacher_id).count() >= MAX_API_KEYS_PER_TEACHER {
+            return Err(AppError::conflict("too many api keys"));
+        }
+        keys.insert(record.id.clone(), record.clone());
+    }
+    let mut out = api_key_out(&record);
+    out["key"] = json!(key);
+    state.audit(Some(teacher_id), "api_key.create", Some(record.id.clone())).await;
+    state.log_change(JournalOp::ApiKeyUpserted { key: record }).await;
+    state.commit_changes()?;
+    Ok((StatusCode::CREATED, Json(out)))
 }
 
-pub async fn list_quizzes(
+pub async fn list_api_keys(
     State(state): State<AppState>,
     headers: HeaderMap,
     jar: CookieJar,
-) -> Result<Json<QuizListResponse>, AppError> {
-    let req_id = request_id_from_headers(&headers);
-    let teacher_id = auth_teacher_id(&jar, &state).await
-        .ok_or_else(|| AppError::new(StatusCode::UNAUTHORIZED, "UNAUTHORIZED", "not logged in", req_id))?;
-    let quizzes = state.db.quizzes.read().await;
-    let items: Vec<QuizSummary> = quizzes
-        .values()
-        .filter(|q| q.owner_teacher_id == teacher_id)
-        .map(|q| QuizSummary {
-            id: q.id,
-            title: q.title.clone(),
-            description: q.description.clone(),
-            is_published: q.is_published,
-        })
-        .collect();
-    Ok(Json(QuizListResponse { total: items.len(), items }))
+) -> Result<Json<serde_json::Value>, AppError> {
+    let teacher_id = interactive_teacher_id(&headers, &jar, &state).await?;
+    let keys = state.db.api_keys.read().await;
+    let mut items: Vec<_> = keys.values().filter(|k| k.teacher_id == teacher_id).collect();
+    items.sort_by_key(|k| k.created_at);
+    Ok(Json(json!({ "items": items.into_iter().map(api_key_out).collect::<Vec<_>>() })))
 }
 
-pub async fn get_quiz(
+pub async fn revoke_api_key(
     State(state): State<AppState>,
     headers: HeaderMap,
     jar: CookieJar,
-    Path(id): Path<i64>,
-) -> Result<Json<QuizRecord>, AppError> {
-    let req_id = request_id_from_headers(&headers);
-    let teacher_id = auth_teacher_id(&jar, &state).await
-        .ok_or_else(|| AppError::new(StatusCode::UNAUTHORIZED, "UNAUTHORIZED", "not logged in", req_id.clone()))?;
-    let quiz = state
+    Path(id): Path<String>,
+) -> Result<StatusCode, AppError> {
+    let teacher_id = interactive_teacher_id(&headers, &jar, &state).await?;
+    {
+        let mut keys = state.db.api_keys.write().await;
+        match keys.get(&id) {
+            Some(key) if key.teacher_id == teacher_id => {
+                keys.remove(&id);
+            }
+            _ => return Err(AppError::not_found("api key not found")),
+        }
+    }
+    state.audit(Some(teacher_id), "api_key.revoke", Some(id.clone())).await;
+    state.log_change(JournalOp::ApiKeyRevoked { id }).await;
+    state.commit_changes()?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+fn validate_new_password(password: &str) -> Result<(), AppError> {
+    if password.len() < 8 {
+        return Err(AppError::validation("invalid login/password")
+            .with_details(vec![ErrorDetail { field: "newPassword".into(), issue: "must be at least 8 characters".into() }]));
+    }
+    Ok(())
+}
+
+// Stores the new hash and revokes every other credential of the teacher: sessions except
+// `keep_sid`, refresh tokens and (via password_changed_at) access tokens issued before now.
+async fn set_teacher_password(
+    state: &AppState,
+    teacher_id: i64,
+    password: &str,
+    keep_sid: Option<&str>,
+) -> Result<(), AppError> {
+    let hash = hash_password(state, password)?;
+    let teacher = {
+        let mut teachers = state.db.teachers.write().await;
+        let teacher = teachers.get_mut(&teacher_id).or_not_found("teacher")?;
+        teacher.password_hash = hash;
+        teacher.password_changed_at = Some(Utc::now());
+        teacher.clone()
+    };
+    state.log_change(JournalOp::TeacherUpserted { teacher }).await;
+    state
+        .shared
+        .revoke_teacher_credentials(teacher_id, keep_sid)
+        .await?;
+    state
+        .log_change(JournalOp::TeacherCredentialsRevoked { teacher_id, keep_sid: keep_sid.map(str::to_string) })
+        .await;
+    state.commit_changes().map_err(AppError::from)
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChangePasswordPayload {
+    pub old_password: String,
+    pub new_password: String,
+}
+
+pub async fn change_password(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    jar: CookieJar,
+    Json(payload): Json<ChangePasswordPayload>,
+) -> Result<StatusCode, AppError> {
+    let teacher_id = interactive_teacher_id(&headers, &jar, &state).await?;
+    let teacher = state
         .db
-        .quizzes
+        .teachers
         .read()
         .await
-        .get(&id)
+        .get(&teacher_id)
         .cloned()
-        .ok_or_else(|| AppError::new(StatusCode::NOT_FOUND, "NOT_FOUND", "quiz not found", req_id.clone()))?;
-    if quiz.owner_teacher_id != teacher_id {
-        return Err(AppError::new(StatusCode::FORBIDDEN, "FORBIDDEN", "access denied", req_id));
+        .or_not_found("teacher")?;
+    if !password_matches(&state, &teacher, &payload.old_password)? {
+        return Err(AppError::forbidden("current password is incorrect"));
     }
-    Ok(Json(quiz))
+    validate_new_password(&payload.new_password)?;
+    // The session that made the change stays logged in; bearer clients have to fetch a new token.
+    let keep_sid = if headers.contains_key(axum::http::header::AUTHORIZATION) {
+        None
+    } else {
+        jar.get(SESSION_COOKIE).map(|c| c.value().to_string())
+    };
+    set_teacher_password(&state, teacher_id, &payload.new_password, keep_sid.as_deref()).await?;
+    info!("teacher {} changed password", teacher_id);
+    state.audit(Some(teacher_id), "auth.password_change", None).await;
+    Ok(StatusCode::NO_CONTENT)
 }
 
-pub async fn update_quiz(
+#[derive(Debug, Serialize)]
+pub struct EmailSettingsOut {
+    pub email: Option<String>,
+    pub locale: &'static str,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EmailSettingsPayload {
+    pub email: Option<String>,
+    pub locale: Option<String>,
+    pub current_password: Option<String>,
+}
+
+pub async fn get_email_settings(
     State(state): State<AppState>,
     headers: HeaderMap,
     jar: CookieJar,
-    Path(id): Path<i64>,
-    Json(payload): Json<CreateQuizPayload>,
-) -> Result<Json<QuizIdResponse>, AppError> {
-    let req_id = request_id_from_headers(&headers);
-    if !ensure_csrf(&headers, &jar, &state).await {
-        return Err(AppError::new(StatusCode::FORBIDDEN, "FORBIDDEN", "csrf token invalid", req_id));
+) -> Result<Json<EmailSettingsOut>, AppError> {
+    let teacher_id = auth_teacher_id(&headers, &jar, &state).await
+        .ok_or_else(|| AppError::unauthorized("not logged in"))?;
+    let teacher = state.db.teachers.read().await.get(&teacher_id).cloned().or_not_found("teacher")?;
+    Ok(Json(EmailSettingsOut { locale: state.email.locale(teacher.locale.as_deref()).code(), email: teacher.email }))
+}
+
+// The address receives password reset codes, so changing it takes the current password, like a
+// password change does. `email: null` removes the address.
+pub async fn update_email_settings(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    jar: CookieJar,
+    Json(payload): Json<EmailSettingsPayload>,
+) -> Result<Json<EmailSettingsOut>, AppError> {
+    let teacher_id = interactive_teacher_id(&headers, &jar, &state).await?;
+    let email = payload.email.map(|e| e.trim().to_string()).filter(|e| !e.is_empty());
+    let mut details = Vec::new();
+    if let Some(email) = &email {
+        if email.len() > 254 || !email::is_plain_address(email) || !validator::ValidateEmail::validate_email(email) {
+            details.push(ErrorDetail { field: "email".into(), issue: "is not a valid email address".into() });
+        }
     }
-    let teacher_id = auth_teacher_id(&jar, &state).await
-        .ok_or_else(|| AppError::new(StatusCode::UNAUTHORIZED, "UNAUTHORIZED", "not logged in", request_id_from_headers(&headers)))?;
-    let quiz = Quiz {
-        title: payload.title,
-        description: payload.description,
-        questions: payload.questions,
-    };
-    if let Err(issues) = validate_quiz(&quiz) {
-        return Err(AppError::new(
-            StatusCode::BAD_REQUEST,
-            "VALIDATION_ERROR",
-            "quiz validation failed",
-            request_id_from_headers(&headers),
-        )
-        .with_details(
-            issues
-                .into_iter()
-                .map(|i| ErrorDetail {
-                    field: i.field,
-                    issue: i.issue,
-                })
-                .collect(),
-        ));
+    let locale = payload.locale.as_deref().map(email::Locale::parse);
+    if let Some(None) = locale {
+        details.push(ErrorDetail { field: "locale".into(), issue: "must be ru or en".into() });
     }
-    let mut quizzes = state.db.quizzes.write().await;
-    let item = quizzes
-        .get_mut(&id)
-        .ok_or_else(|| AppError::new(StatusCode::NOT_FOUND, "NOT_FOUND", "quiz not found", request_id_from_headers(&headers)))?;
-    if item.owner_teacher_id != teacher_id {
-        return Err(AppError::new(StatusCode::FORBIDDEN, "FORBIDDEN", "access denied", request_id_from_headers(&headers)));
+    if !details.is_empty() {
+        return Err(AppError::validation("invalid email settings").with_details(details));
     }
-    item.title = quiz.title;
-    item.description = quiz.description;
-    item.questions = quiz.questions;
-    drop(quizzes);
-    if let Err(err) = state.persist_core_data().await {
-        warn!("failed to persist local state after update_quiz: {}", err);
+    let current = state.db.teachers.read().await.get(&teacher_id).cloned().or_not_found("teacher")?;
+    let changed = current.email != email;
+    if changed && !password_matches(&state, &current, payload.current_password.as_deref().unwrap_or_default())? {
+        return Err(AppError::forbidden("current password is incorrect"));
     }
-    Ok(Json(QuizIdResponse { quiz_id: id }))
+    let teacher = {
+        let mut teachers = state.db.teachers.write().await;
+        let teacher = teachers.get_mut(&teacher_id).or_not_found("teacher")?;
+        teacher.email = email;
+        teacher.locale = locale.flatten().map(|l| l.code().to_string());
+        teacher.clone()
+    };
+    state.log_change(JournalOp::TeacherUpserted { teacher: teacher.clone() }).await;
+    state.commit_changes()?;
+    if changed {
+        info!("teacher {} changed email address", teacher_id);
+        state.audit(Some(teacher_id), "auth.email_change", None).await;
+    }
+    Ok(Json(EmailSettingsOut { locale: state.email.locale(teacher.locale.as_deref()).code(), email: teacher.email }))
 }
 
-pub async fn delete_quiz(
+const MAX_OVERRIDE_WORDS: usize = 200;
+const MAX_OVERRIDE_WORD_CHARS: usize = 50;
+
+pub async fn get_word_overrides(
     State(state): State<AppState>,
     headers: HeaderMap,
     jar: CookieJar,
-    Path(id): Path<i64>,
-) -> Result<StatusCode, AppError> {
-    let req_id = request_id_from_headers(&headers);
-    if !ensure_csrf(&headers, &jar, &state).await {
-        return Err(AppError::new(StatusCode::FORBIDDEN, "FORBIDDEN", "csrf token invalid", req_id));
+) -> Result<Json<WordOverrides>, AppError> {
+    let teacher_id = auth_teacher_id(&headers, &jar, &state).await
+        .ok_or_else(|| AppError::unauthorized("not logged in"))?;
+    let teacher = state.db.teachers.read().await.get(&teacher_id).cloned().or_not_found("teacher")?;
+    Ok(Json(teacher.word_overrides))
+}
+
+// Replaces the teacher's profanity filter corrections: `allow` lists whole words the filter lets through in
+// their sessions, `block` adds entries in the filter's syntax (`word`, `word*`, `*word*`).
+pub async fn update_word_overrides(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    jar: CookieJar,
+    Json(payload): Json<WordOverrides>,
+) -> Result<Json<WordOverrides>, AppError> {
+    let teacher_id = auth_teacher_id(&headers, &jar, &state).await
+        .ok_or_else(|| AppError::unauthorized("not logged in"))?;
+    let mut details = Vec::new();
+    let mut clean = |field: &str, words: Vec<String>| {
+        let mut kept: Vec<String> = Vec::new();
+        for word in words {
+            let word = word.trim().to_lowercase();
+            let core = word.trim_matches('*');
+            if core.chars().count() > MAX_OVERRIDE_WORD_CHARS || !core.chars().any(char::is_alphabetic) || core.contains('*') {
+                let issue = format!("\"{}\" must be a word of up to {} letters", word, MAX_OVERRIDE_WORD_CHARS);
+                details.push(ErrorDetail { field: field.into(), issue });
+            } else if !kept.contains(&word) {
+                kept.push(word);
+            }
+        }
+        if kept.len() > MAX_OVERRIDE_WORDS {
+            details.push(ErrorDetail { field: field.into(), issue: format!("at most {} words", MAX_OVERRIDE_WORDS) });
+        }
+        kept
+    };
+    let overrides = WordOverrides { allow: clean("allow", payload.allow), block: clean("block", payload.block) };
+    if let Some(word) = overrides.allow.iter().find(|w| w.contains('*')) {
+        let issue = format!("\"{}\": allowed entries are whole words", word);
+        details.push(ErrorDetail { field: "allow".into(), issue });
     }
-    let teacher_id = auth_teacher_id(&jar, &state).await
-        .ok_or_else(|| AppError::new(StatusCode::UNAUTHORIZED, "UNAUTHORIZED", "not logged in", request_id_from_headers(&headers)))?;
-    let mut quizzes = state.db.quizzes.write().await;
-    let existing = quizzes
-        .get(&id)
-        .cloned()
-        .ok_or_else(|| AppError::new(StatusCode::NOT_FOUND, "NOT_FOUND", "quiz not found", request_id_from_headers(&headers)))?;
-    if existing.owner_teacher_id != teacher_id {
-        return Err(AppError::new(StatusCode::FORBIDDEN, "FORBIDDEN", "access denied", request_id_from_headers(&headers)));
+    if !details.is_empty() {
+        return Err(AppError::validation("invalid word lists").with_details(details));
     }
-    quizzes.remove(&id);
-    drop(quizzes);
-    if let Err(err) = state.persist_core_data().await {
-        warn!("failed to persist local state after delete_quiz: {}", err);
+    let teacher = {
+        let mut teachers = state.db.teachers.write().await;
+        let teacher = teachers.get_mut(&teacher_id).or_not_found("teacher")?;
+        teacher.word_overrides = overrides;
+        teacher.clone()
+    };
+    state.log_change(JournalOp::TeacherUpserted { teacher: teacher.clone() }).await;
+    state.commit_changes()?;
+    state.audit(Some(teacher_id), "moderation.words_update", None).await;
+    Ok(Json(teacher.word_overrides))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PasswordResetRequest {
+    pub login: String,
+}
+
+async fn issue_reset_token(state: &AppState, login: &str) -> Result<Option<(String, chrono::DateTime<Utc>)>, AppError> {
+    let Some(teacher_id) = state.db.teachers_by_login.read().await.get(login.trim()).copied() else {
+        return Ok(None);
+    };
+    let (token, record) = password_reset::new_reset_token(teacher_id, state.password_reset_ttl);
+    state
+        .shared
+        .put_reset_token(&token, &record)
+        .await?;
+    Ok(Some((token, record.expires_at)))
+}
+
+// Always answers 202 so the endpoint cannot be used to probe which logins exist.
+pub async fn request_password_reset(
+    State(state): State<AppState>,
+    Json(payload): Json<PasswordResetRequest>,
+) -> Result<StatusCode, AppError> {
+    if let Some((token, expires_at)) = issue_reset_token(&state, &payload.login).await? {
+        let teacher_id = state.db.teachers_by_login.read().await.get(payload.login.trim()).copied();
+        let teacher = match teacher_id {
+            Some(id) => state.db.teachers.read().await.get(&id).cloned(),
+            None => None,
+        };
+        let Some(teacher) = teacher else { return Ok(StatusCode::ACCEPTED) };
+        if let Err(err) = state.reset_delivery.deliver(&teacher, &token, expires_at).await {
+            warn!("password reset delivery ({}) failed: {}", state.reset_delivery.kind(), err);
+        }
     }
+    Ok(StatusCode::ACCEPTED)
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PasswordResetConfirm {
+    pub token: String,
+    pub new_password: String,
+}
+
+pub async fn confirm_password_reset(
+    State(state): State<AppState>,
+    Json(payload): Json<PasswordResetConfirm>,
+) -> Result<StatusCode, AppError> {
+    validate_new_password(&payload.new_password)?;
+    let record = state
+        .shared
+        .take_reset_token(payload.token.trim())
+        .await?
+        .filter(|r| r.expires_at > Utc::now())
+        .ok_or_else(|| AppError::validation("reset token is invalid or expired"))?;
+    set_teacher_password(&state, record.teacher_id, &payload.new_password, None).await?;
+    info!("teacher {} reset password", record.teacher_id);
+    state.audit(Some(record.teacher_id), "auth.password_reset", None).await;
     Ok(StatusCode::NO_CONTENT)
 }
 
-pub async fn publish_quiz(
+pub async fn logout(
     State(state): State<AppState>,
-    headers: HeaderMap,
     jar: CookieJar,
-    Path(id): Path<i64>,
-) -> Result<Json<serde_json::Value>, AppError> {
-    let req_id = request_id_from_headers(&headers);
-    if !ensure_csrf(&headers, &jar, &state).await {
-        return Err(AppError::new(StatusCode::FORBIDDEN, "FORBIDDEN", "csrf token invalid", req_id));
-    }
-    let teacher_id = auth_teacher_id(&jar, &state).await
-        .ok_or_else(|| AppError::new(StatusCode::UNAUTHORIZED, "UNAUTHORIZED", "not logged in", request_id_from_headers(&headers)))?;
-    let mut quizzes = state.db.quizzes.write().await;
-    let q = quizzes
-        .get_mut(&id)
-        .ok_or_else(|| AppError::new(StatusCode::NOT_FOUND, "NOT_FOUND", "quiz not found", request_id_from_headers(&headers)))?;
-    if q.owner_teacher_id != teacher_id {
-        return Err(AppError::new(StatusCode::FORBIDDEN, "FORBIDDEN", "access denied", request_id_from_headers(&headers)));
-    }
-    q.is_published = true;
-    drop(quizzes);
-    if let Err(err) = state.persist_core_data().await {
-        warn!("failed to persist local state after publish_quiz: {}", err);
+) -> Result<(CookieJar, StatusCode), AppError> {
+    let sid = jar
+        .get(SESSION_COOKIE)
+        .map(|v| v.value().to_string())
+        .ok_or_else(|| AppError::unauthorized("no session"))?;
+    let teacher_id = state.teacher_session(&sid).await.map(|s| s.teacher_id);
+    state.shared.remove_teacher_session(&sid).await?;
+    if teacher_id.is_some() {
+        state.audit(teacher_id, "auth.logout", None).await;
     }
-    Ok(Json(json!({ "published": true })))
+    state.log_change(JournalOp::TeacherSessionRemoved { sid }).await;
+    state.commit_changes()?;
+    Ok((jar.remove(state.web.removal_cookie(SESSION_COOKIE)), StatusCode::NO_CONTENT))
 }
 
-pub async fn unpublish_quiz(
+pub async fn list_teacher_sessions(
     State(state): State<AppState>,
     headers: HeaderMap,
     jar: CookieJar,
-    Path(id): Path<i64>,
 ) -> Result<Json<serde_json::Value>, AppError> {
-    let req_id = request_id_from_headers(&headers);
-    if !ensure_csrf(&headers, &jar, &state).await {
-        return Err(AppError::new(StatusCode::FORBIDDEN, "FORBIDDEN", "csrf token invalid", req_id));
-    }
-    let teacher_id = auth_teacher_id(&jar, &state).await
-        .ok_or_else(|| AppError::new(StatusCode::UNAUTHORIZED, "UNAUTHORIZED", "not logged in", request_id_from_headers(&headers)))?;
-    let mut quizzes = state.db.quizzes.write().await;
-    let q = quizzes
-        .get_mut(&id)
-        .ok_or_else(|| AppError::new(StatusCode::NOT_FOUND, "NOT_FOUND", "quiz not found", request_id_from_headers(&headers)))?;
-    if q.owner_teacher_id != teacher_id {
-        return Err(AppError::new(StatusCode::FORBIDDEN, "FORBIDDEN", "access denied", request_id_from_headers(&headers)));
-    }
-    q.is_published = false;
-    drop(quizzes);
-    if let Err(err) = state.persist_core_data().await {
-        warn!("failed to persist local state after unpublish_quiz: {}", err);
-    }
-    Ok(Json(json!({ "published": false })))
+    let teacher_id = interactive_teacher_id(&headers, &jar, &state).await?;
+    let current = jar.get(SESSION_COOKIE).map(|c| c.value().to_string());
+    let mut sessions = state
+        .shared
+        .list_teacher_sessions(teacher_id)
+        .await?;
+    sessions.sort_by_key(|s| std::cmp::Reverse(s.1.created_at));
+    // Session ids double as cookie values, so only their hash leaves the server.
+    let items: Vec<_> = sessions
+        .iter()
+        .map(|(sid, s)| {
+            json!({
+                "id": short_hash(sid),
+                "createdAt": s.created_at,
+                "lastSeenAt": s.last_seen_at,
+                "expiresAt": s.expires_at,
+                "userAgentHash": s.user_agent_hash,
+                "current": current.as_deref() == Some(sid.as_str()),
+            })
+        })
+        .collect();
+    Ok(Json(json!({ "items": items })))
 }
 
-pub async fn clone_quiz(
+// "Log out everywhere": drops every other session and all refresh tokens of the teacher.
+pub async fn revoke_other_sessions(
     State(state): State<AppState>,
     headers: HeaderMap,
     jar: CookieJar,
-    Path(id): Path<i64>,
-) -> Result<(StatusCode, Json<serde_json::Value>), AppError> {
-    let req_id = request_id_from_headers(&headers);
-    let ip = headers
-        .get("x-forwarded-for")
-        .and_then(|v| v.to_str().ok())
-        .unwrap_or("local");
-    if !check_rate_limit("ai_generate", ip, 15) {
-        return Err(AppError::new(
-            StatusCode::TOO_MANY_REQUESTS,
-            "RATE_LIMITED",
-            "too many requests",
-            req_id,
-        ));
-    }
-    if !ensure_csrf(&headers, &jar, &state).await {
-        return Err(AppError::new(StatusCode::FORBIDDEN, "FORBIDDEN", "csrf token invalid", req_id));
-    }
-    let teacher_id = auth_teacher_id(&jar, &state).await
-        .ok_or_else(|| AppError::new(StatusCode::UNAUTHORIZED, "UNAUTHORIZED", "not logged in", request_id_from_headers(&headers)))?;
-    let source = state
+) -> Result<StatusCode, AppError> {
+    let teacher_id = interactive_teacher_id(&headers, &jar, &state).await?;
+    // With a bearer token the cookie is ignored, so no session counts as current.
+    let keep_sid = if headers.contains_key(axum::http::header::AUTHORIZATION) {
+        None
+    } else {
+        jar.get(SESSION_COOKIE).map(|c| c.value().to_string())
+    };
+    state
+        .shared
+        .revoke_teacher_credentials(teacher_id, keep_sid.as_deref())
+        .await?;
+    state
+        .log_change(JournalOp::TeacherCredentialsRevoked { teacher_id, keep_sid })
+        .await;
+    state.commit_changes()?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn me(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    jar: CookieJar,
+) -> Result<Json<TeacherOut>, AppError> {
+    let teacher_id = auth_teacher_id(&headers, &jar, &state).await
+        .ok_or_else(|| AppError::unauthorized("not logged in"))?;
+    let teacher = state
         .db
-        .quizzes
+        .teachers
         .read()
         .await
-        .get(&id)
+        .get(&teacher_id)
         .cloned()
-        .ok_or_else(|| AppError::new(StatusCode::NOT_FOUND, "NOT_FOUND", "quiz not found", request_id_from_headers(&headers)))?;
-    if !source.is_published {
-        return Err(AppError::new(StatusCode::CONFLICT, "CONFLICT", "quiz is not published", request_id_from_headers(&headers)));
-    }
-    let quiz_id = state
-        .create_quiz(
-            teacher_id,
-            Quiz {
-                title: source.title,
-                description: source.description,
-                questions: source.questions,
-            },
-            Some(id),
-        )
-        .await;
-    Ok((StatusCode::CREATED, Json(json!({ "quizId": quiz_id, "sourceQuizId": id }))))
+        .ok_or_else(|| AppError::unauthorized("not logged in"))?;
+    Ok(Json(TeacherOut { id: teacher.id, login: teacher.login }))
 }
 
-#[derive(Debug, Deserialize)]
-pub struct SearchQuery {
-    pub q: Option<String>,
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StudentOut {
+    pub id: i64,
+    pub display_name: String,
+    pub login: Option<String>,
 }
 
-pub async fn library_list(
-    State(state): State<AppState>,
-    jar: CookieJar,
-    query: axum::extract::Query<SearchQuery>,
-) -> Json<serde_json::Value> {
-    let term = query.q.clone().unwrap_or_default().to_lowercase();
-    let quizzes = state.db.quizzes.read().await;
-    let teacher_id = auth_teacher_id(&jar, &state).await;
+impl From<&Student> for StudentOut {
+    fn from(s: &Student) -> Self {
+        Self { id: s.id, display_name: s.display_name.clone(), login: s.login.clone() }
+    }
+}
 
-    let own_fingerprints = if let Some(tid) = teacher_id {
-        quizzes
-            .values()
-            .filter(|q| q.owner_teacher_id == tid)
-            .map(|q| quiz_fingerprint(&q.title, &q.description, &q.questions))
-            .collect::<std::collections::HashSet<_>>()
-    } else {
-        std::collections::HashSet::new()
-    };
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StudentTokenOut {
+    pub student: StudentOut,
+    pub student_token: String,
+}
 
-    let items: Vec<_> = quizzes
-        .values()
-        .filter(|q| q.is_published)
-        .filter(|q| {
-            term.is_empty()
-                || q.title.to_lowercase().contains(&term)
-                || q
-                    .description
-                    .as_ref()
-                    .map(|d| d.to_lowercase().contains(&term))
-                    .unwrap_or(false)
-        })
-        .map(|q| {
-            let already_owned = teacher_id
-                .map(|tid| {
-                    if q.owner_teacher_id == tid {
-                        true
-                    } else {
-                        let fp = quiz_fingerprint(&q.title, &q.description, &q.questions);
-                        own_fingerprints.contains(&fp)
-                    }
-                })
-                .unwrap_or(false);
-            json!({
-                "id": q.id,
-                "title": q.title,
-                "description": q.description,
-                "ownerTeacherId": q.owner_teacher_id,
-                "alreadyOwned": already_owned
-            })
-        })
-        .collect();
-    Json(json!({ "items": items, "total": items.len() }))
+fn student_token_out(state: &AppState, student: &Student) -> Result<StudentTokenOut, AppError> {
+    let student_token = state
+        .jwt
+        .issue_student_token(student.id)
+        .map_err(|_| AppError::internal("token signing failed"))?;
+    Ok(StudentTokenOut { student: student.into(), student_token })
 }
 
-fn quiz_fingerprint(title: &str, description: &Option<String>, questions: &[crate::models::Question]) -> String {
-    let normalized = json!({
-        "title": title.trim(),
-        "description": description.as_ref().map(|v| v.trim()),
-        "questions": questions,
-    });
-    normalized.to_string()
+async fn auth_student_id(headers: &HeaderMap, state: &AppState) -> Option<i64> {
+    let student_id = state.jwt.verify_student_token(bearer_token(headers)?)?;
+    state.db.students.read().await.contains_key(&student_id).then_some(student_id)
+}
+
+fn valid_display_name(name: &str) -> bool {
+    (2..=64).contains(&name.chars().count()) && !name.chars().any(char::is_control)
 }
 
 #[derive(Debug, Deserialize)]
-pub struct AiGeneratePayload {
-    pub topic: String,
-    pub grade: Option<String>,
-    #[serde(rename = "questionCount")]
-    pub question_count: usize,
+#[serde(rename_all = "camelCase")]
+pub struct StudentRegisterPayload {
+    pub login: String,
+    pub password: String,
+    pub display_name: Option<String>,
 }
 
-pub async fn ai_generate_quiz(
+pub async fn register_student(
     State(state): State<AppState>,
-    headers: HeaderMap,
-    jar: CookieJar,
-    Json(payload): Json<AiGeneratePayload>,
-) -> Result<(StatusCode, Json<serde_json::Value>), AppError> {
-    let req_id = request_id_from_headers(&headers);
-    if !ensure_csrf(&headers, &jar, &state).await {
-        return Err(AppError::new(StatusCode::FORBIDDEN, "FORBIDDEN", "csrf token invalid", req_id));
-    }
-    let teacher_id = auth_teacher_id(&jar, &state).await
-        .ok_or_else(|| AppError::new(StatusCode::UNAUTHORIZED, "UNAUTHORIZED", "not logged in", request_id_from_headers(&headers)))?;
-
-    let compiled = jsonschema::draft202012::new(&state.quiz_schema)
-        .map_err(|_| AppError::new(StatusCode::INTERNAL_SERVER_ERROR, "INTERNAL_ERROR", "schema build failed", request_id_from_headers(&headers)))?;
-    let mut last_validation_details: Vec<ErrorDetail> = Vec::new();
-    let mut last_message = "ai payload does not match schema".to_string();
-
-    for _attempt in 0..2 {
-        let raw = state
-            .ai_client
-            .generate_quiz_json(&payload.topic, payload.grade.as_deref(), payload.question_count)
-            .await
-            .map_err(|e| {
-                AppError::new(
-                    StatusCode::BAD_GATEWAY,
-                    "UPSTREAM_ERROR",
-                    format!("gigachat failed: {}", e),
-                    request_id_from_headers(&headers),
-                )
-            })?;
-
-        let json_value: serde_json::Value = match serde_json::from_str(&raw) {
-            Ok(v) => v,
-            Err(e) => {
-                last_message = format!("ai result is not valid json: {}", e);
-                last_validation_details.clear();
-                continue;
-            }
-        };
-
-        if compiled.validate(&json_value).is_err() {
-            last_validation_details = compiled
-                .iter_errors(&json_value)
-                .map(|e| ErrorDetail {
-                    field: e.instance_path.to_string(),
-                    issue: e.to_string(),
-                })
-                .collect();
-            last_message = "ai payload does not match schema".to_string();
-            continue;
-        }
+    Json(payload): Json<StudentRegisterPayload>,
+) -> Result<(StatusCode, Json<StudentTokenOut>), AppError> {
+    let login = payload.login.trim().to_string();
+    let display_name = payload
+        .display_name
+        .as_deref()
+        .map(str::trim)
+        .filter(|v| !v.is_empty())
+        .unwrap_or(login.as_str())
+        .to_string();
+    if login.len() < 3 || payload.password.len() < 8 || !valid_display_name(&display_name) {
+        return Err(AppError::validation("invalid login/password/displayName"));
+    }
+    if state.db.students_by_login.read().await.contains_key(&login) {
+        return Err(AppError::conflict("login already exists"));
+    }
+    let student = Student {
+        id: state.db.next_student_id(),
+        display_name,
+        login: Some(login),
+        password_hash: Some(hash_password(&state, &payload.password)?),
+        access_code: None,
+        issued_by_teacher_id: None,
+        created_at: Utc::now(),
+        lti_user: None,
+    };
+    state.db.upsert_student(student.clone()).await;
+    state.log_change(JournalOp::StudentUpserted { student: student.clone() }).await;
+    state.commit_changes()?;
+    Ok((StatusCode::CREATED, Json(student_token_out(&state, &student)?)))
+}
 
-        let quiz: Quiz = match serde_json::from_value(json_value) {
-            Ok(v) => v,
-            Err(e) => {
-                last_message = format!("cannot decode quiz: {}", e);
-                last_validation_details.clear();
-                continue;
-            }
-        };
+#[derive(Debug, Deserialize)]
+pub struct StudentLoginPayload {
+    pub login: Option<String>,
+    pub password: Option<String>,
+    pub code: Option<String>,
+}
 
-        if let Err(issues) = validate_quiz(&quiz) {
-            last_validation_details = issues
-                .into_iter()
-                .map(|i| ErrorDetail {
-                    field: i.field,
-                    issue: i.issue,
-                })
-                .collect();
-            last_message = "quiz validation failed".to_string();
-            continue;
+pub async fn login_student(
+    State(state): State<AppState>,
+    Json(payload): Json<StudentLoginPayload>,
+) -> Result<Json<StudentTokenOut>, AppError> {
+    let invalid = || AppError::unauthorized("invalid credentials");
+    let student_id = match (&payload.code, &payload.login, &payload.password) {
+        (Some(code), _, _) => state.db.students_by_code.read().await.get(&code.trim().to_uppercase()).copied(),
+        (None, Some(login), Some(_)) => state.db.students_by_login.read().await.get(login.trim()).copied(),
+        _ => None,
+    }
+    .ok_or_else(invalid)?;
+    let student = state.db.students.read().await.get(&student_id).cloned().ok_or_else(invalid)?;
+    if payload.code.is_none() {
+        let hash = student.password_hash.as_deref().ok_or_else(invalid)?;
+        let password = payload.password.as_deref().unwrap_or_default();
+        if !state.passwords.verify(hash, password).unwrap_or(false) {
+            return Err(invalid());
         }
-
-        let quiz_id = state.create_quiz(teacher_id, quiz, None).await;
-        return Ok((StatusCode::CREATED, Json(json!({ "quizId": quiz_id, "source": "ai" }))));
     }
-
-    Err(AppError::new(
-        StatusCode::UNPROCESSABLE_ENTITY,
-        "VALIDATION_ERROR",
-        last_message,
-        request_id_from_headers(&headers),
-    )
-    .with_details(last_validation_details))
+    Ok(Json(student_token_out(&state, &student)?))
 }
 
 #[derive(Debug, Deserialize)]
-pub struct CreateSessionPayload {
-    #[serde(rename = "quizId")]
-    pub quiz_id: i64,
-    #[serde(rename = "gameMode")]
-    pub game_mode: String,
+pub struct StudentCodesPayload {
+    pub names: Vec<String>,
 }
 
-pub async fn create_session(
+// Lets a teacher create accounts for a whole class at once; each student signs in with the code.
+pub async fn issue_student_codes(
     State(state): State<AppState>,
     headers: HeaderMap,
     jar: CookieJar,
-    Json(payload): Json<CreateSessionPayload>,
+    Json(payload): Json<StudentCodesPayload>,
 ) -> Result<(StatusCode, Json<serde_json::Value>), AppError> {
-    let req_id = request_id_from_headers(&headers);
-    if !ensure_csrf(&headers, &jar, &state).await {
-        return Err(AppError::new(StatusCode::FORBIDDEN, "FORBIDDEN", "csrf token invalid", req_id));
-    }
-    let teacher_id = auth_teacher_id(&jar, &state).await
-        .ok_or_else(|| AppError::new(StatusCode::UNAUTHORIZED, "UNAUTHORIZED", "not logged in", request_id_from_headers(&headers)))?;
-    if !["platformer", "shooter", "tycoon", "classic"].contains(&payload.game_mode.as_str()) {
-        return Err(AppError::new(
-            StatusCode::BAD_REQUEST,
-            "VALIDATION_ERROR",
-            "unsupported game mode",
-            request_id_from_headers(&headers),
-        ));
-    }
-    let quiz_exists = state.db.quizzes.read().await.contains_key(&payload.quiz_id);
-    if !quiz_exists {
-        return Err(AppError::new(StatusCode::NOT_FOUND, "NOT_FOUND", "quiz not found", request_id_from_headers(&headers)));
+    let teacher_id = auth_teacher_id(&headers, &jar, &state).await
+        .ok_or_else(|| AppError::unauthorized("not logged in"))?;
+    let names: Vec<String> = payload.names.iter().map(|n| n.trim().to_string()).collect();
+    let details: Vec<ErrorDetail> = names
+        .iter()
+        .enumerate()
+        .filter(|(_, n)| !valid_display_name(n))
+        .map(|(i, _)| ErrorDetail { field: format!("names[{i}]"), issue: "must be 2..64 characters".into() })
+        .collect();
+    if names.is_empty() || names.len() > 200 || !details.is_empty() {
+        return Err(AppError::validation("names must contain 1..200 valid names")
+            .with_details(details));
     }
 
-    let room_code: String = rand::thread_rng()
-        .sample_iter(&Alphanumeric)
-        .take(6)
-        .map(char::from)
-        .collect::<String>()
-        .to_uppercase();
-    let join_token = uuid::Uuid::new_v4().to_string();
-    let id = state.db.next_game_session_id();
+    let mut items = Vec::new();
+    for display_name in names {
+        let access_code = loop {
+            let code: String = rand::thread_rng()
+                .sample_iter(&Alphanumeric)
+                .take(8)
+                .map(char::from)
+                .collect::<String>()
+                .to_uppercase();
+            if !state.db.students_by_code.read().await.contains_key(&code) {
+                break code;
+            }
+        };
+        let student = Student {
+            id: state.db.next_student_id(),
+            display_name,
+            login: None,
+            password_hash: None,
+            access_code: Some(access_code.clone()),
+            issued_by_teacher_id: Some(teacher_id),
+            created_at: Utc::now(),
+            lti_user: None,
+        };
+        items.push(json!({ "id": student.id, "displayName": student.display_name, "accessCode": access_code }));
+        state.db.upsert_student(student.clone()).await;
+        state.log_change(JournalOp::StudentUpserted { student }).await;
+    }
+    state.commit_changes()?;
+    Ok((StatusCode::CREATED, Json(json!({ "items": items }))))
+}
 
-    let session = SessionRecord {
-        id,
-        room_code: room_code.clone(),
-        join_token: join_token.clone(),
+pub async fn student_me(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let student_id = auth_student_id(&headers, &state)
+        .await
+        .ok_or_else(|| AppError::unauthorized("student token required"))?;
+    let student = state
+        .db
+        .students
+        .read()
+        .await
+        .get(&student_id)
+        .map(StudentOut::from)
+        .or_not_found("student")?;
+    let sessions = state.db.game_sessions.read().await;
+    let mut history: Vec<_> = sessions
+        .values()
+        .filter_map(|s| {
+            let p = s.participants.values().find(|p| p.student_id == Some(student_id))?;
+            let stats = s.stats.get(&p.nickname);
+            Some(json!({
+                "sessionId": s.id,
+                "quizId": s.quiz_id,
+                "status": s.status,
+                "gameMode": s.game_mode,
+                "nickname": p.nickname,
+                "correct": stats.map(|x| x.correct).unwrap_or(0),
+                "wrong": stats.map(|x| x.wrong).unwrap_or(0),
+            }))
+        })
+        .collect();
+    history.sort_by_key(|h| std::cmp::Reverse(h["sessionId"].as_i64().unwrap_or(0)));
+    Ok(Json(json!({ "student": student, "history": history })))
+}
+
+// LTI 1.3 OIDC login initiation from a registered LMS; platforms send it as GET or as a form POST.
+pub async fn lti_login(
+    State(state): State<AppState>,
+    axum::extract::Form(params): axum::extract::Form<HashMap<String, String>>,
+) -> Result<axum::response::Redirect, AppError> {
+    let url = state.lti.begin_login(&params).ok_or_else(|| {
+        AppError::validation("unknown LTI platform or missing login_hint")
+    })?;
+    Ok(axum::response::Redirect::to(&url))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LtiLaunchForm {
+    pub id_token: String,
+    pub state: String,
+}
+
+// The resource link names the session in its custom parameters (`session_id`). Learners get a student
+// account tied to their LMS user and go on to the room; their final score is sent to the link's line item
+// when the session ends. Instructors go to the session's live page.
+pub async fn lti_launch(
+    State(state): State<AppState>,
+    axum::extract::Form(form): axum::extract::Form<LtiLaunchForm>,
+) -> Result<axum::response::Redirect, AppError> {
+    let launch = state
+        .lti
+        .verify_launch(&form.state, &form.id_token)
+        .await
+        .map_err(AppError::unauthorized)?;
+    let session_id = match launch.custom.get("session_id") {
+        Some(serde_json::Value::String(id)) => id.trim().parse::<i64>().ok(),
+        Some(id) => id.as_i64(),
+        None => None,
+    }
+    .ok_or_else(|| {
+        let message = "the link has no session_id custom parameter";
+        AppError::validation(message)
+    })?;
+    let session = state.game_session(session_id).await?;
+    if launch.instructor {
+        return Ok(axum::response::Redirect::to(&format!("http://localhost:5173/teacher/sessions/{}/live", session_id)));
+    }
+    if session.status == "finished" {
+        return Err(AppError::conflict("session is finished"));
+    }
+
+    let lti_user = format!("{} {}", launch.issuer, launch.user_id);
+    let existing = state.db.students.read().await.values().find(|s| s.lti_user.as_ref() == Some(&lti_user)).cloned();
+    let student = match existing {
+        Some(student) => student,
+        None => {
+            let display_name = launch
+                .name
+                .as_deref()
+                .map(str::trim)
+                .filter(|name| valid_display_name(name))
+                .unwrap_or("Ученик")
+                .to_string();
+            let student = Student {
+                id: state.db.next_student_id(),
+                display_name,
+                login: None,
+                password_hash: None,
+                access_code: None,
+                issued_by_teacher_id: None,
+                created_at: Utc::now(),
+                lti_user: Some(lti_user),
+            };
+            state.db.upsert_student(student.clone()).await;
+            state.log_change(JournalOp::StudentUpserted { student: student.clone() }).await;
+            student
+        }
+    };
+    if let Some(lineitem) = launch.lineitem {
+        let target = LtiGradeTarget {
+            issuer: launch.issuer,
+            client_id: launch.client_id,
+            user_id: launch.user_id,
+            lineitem,
+            score_given: None,
+            sent_at: None,
+            error: None,
+        };
+        if let Some(session) = state.db.game_sessions.write().await.get_mut(&session_id) {
+            session.lti_grades.insert(student.id, target);
+        }
+    }
+    state.commit_changes()?;
+
+    let token = student_token_out(&state, &student)?.student_token;
+    let mut url = reqwest::Url::parse(&join_url(&session.room_code, &session.join_token)).map_err(|_| {
+        AppError::internal("invalid join url")
+    })?;
+    url.query_pairs_mut().append_pair("studentToken", &token);
+    Ok(axum::response::Redirect::to(url.as_str()))
+}
+
+// Public keys of the tool for platforms that fetch them by URL; 404 when none are configured.
+pub async fn lti_jwks(State(state): State<AppState>) -> Result<Json<serde_json::Value>, AppError> {
+    state
+        .lti
+        .public_jwks
+        .clone()
+        .map(Json)
+        .ok_or_else(|| AppError::not_found("no tool keys configured"))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateQuizPayload {
+    pub title: String,
+    pub description: Option<String>,
+    pub questions: Vec<crate::models::Question>,
+    #[serde(default)]
+    pub difficulty: Option<Difficulty>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub settings: QuizSettings,
+    #[serde(default)]
+    pub subject: Option<String>,
+    #[serde(default)]
+    pub grade: Option<u8>,
+    #[serde(default)]
+    pub language: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct QuizIdResponse {
+    pub quiz_id: i64,
+}
+
+pub async fn create_quiz(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    jar: CookieJar,
+    Json(payload): Json<CreateQuizPayload>,
+) -> Result<(StatusCode, Json<QuizIdResponse>), AppError> {
+    let teacher_id = auth_teacher_id(&headers, &jar, &state).await
+        .ok_or_else(|| AppError::unauthorized("not logged in"))?;
+
+    let quiz = Quiz {
+        title: payload.title,
+        description: payload.description,
+        questions: payload.questions,
+        difficulty: payload.difficulty,
+        tags: payload.tags,
+        settings: payload.settings,
+        subject: payload.subject,
+        grade: payload.grade,
+        language: payload.language,
+    };
+    if let Err(issues) = validate_quiz(&quiz, &state.quiz_limits) {
+        return Err(AppError::validation("quiz validation failed")
+        .with_details(
+            issues
+                .into_iter()
+                .map(|i| ErrorDetail {
+                    field: i.field,
+                    issue: i.issue,
+                })
+                .collect(),
+        ));
+    }
+
+    let id = state.create_quiz(teacher_id, quiz, None).await?;
+    Ok((StatusCode::CREATED, Json(QuizIdResponse { quiz_id: id })))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct QuizImportQuery {
+    #[serde(default, rename = "dryRun")]
+    pub dry_run: bool,
+}
+
+// Multipart fields: `file` (CSV or XLSX, required), `title` (defaults to the file name) and
+// `description`. A dry run returns the parsed quiz without saving it.
+pub async fn import_quiz(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    jar: CookieJar,
+    query: axum::extract::Query<QuizImportQuery>,
+    mut multipart: axum::extract::Multipart,
+) -> Result<(StatusCode, Json<serde_json::Value>), AppError> {
+    let teacher_id = auth_teacher_id(&headers, &jar, &state).await
+        .ok_or_else(|| AppError::unauthorized("not logged in"))?;
+    let bad_request = |msg: String| AppError::validation(msg);
+
+    let mut file = None;
+    let mut title = None;
+    let mut description = None;
+    while let Some(field) = multipart.next_field().await.map_err(|e| bad_request(format!("invalid multipart body: {}", e)))? {
+        let name = field.name().unwrap_or_default().to_string();
+        match name.as_str() {
+            "file" => {
+                let format = quiz_import::SheetFormat::detect(field.file_name(), field.content_type())
+                    .ok_or_else(|| bad_request("file must be .csv or .xlsx".into()))?;
+                let name = field.file_name().map(str::to_string);
+                let bytes = field.bytes().await.map_err(|e| bad_request(format!("cannot read file: {}", e)))?;
+                file = Some((format, name, bytes));
+            }
+            "title" => title = Some(field.text().await.map_err(|e| bad_request(e.to_string()))?),
+            "description" => description = Some(field.text().await.map_err(|e| bad_request(e.to_string()))?),
+            _ => {}
+        }
+    }
+    let Some((format, file_name, bytes)) = file else {
+        return Err(bad_request("file is required".into()));
+    };
+    let title = title
+        .filter(|t| !t.trim().is_empty())
+        .or_else(|| file_name.as_deref().map(|n| n.rsplit_once('.').map(|(stem, _)| stem).unwrap_or(n).to_string()))
+        .unwrap_or_else(|| "Импорт".to_string());
+    let meta = quiz_import::ImportMeta { title, description: description.filter(|d| !d.trim().is_empty()) };
+    let quiz = quiz_import::parse(&bytes, format, meta, &state.quiz_limits).map_err(|issues| {
+        bad_request("quiz import failed".into())
+            .with_details(issues.into_iter().map(|i| ErrorDetail { field: i.field, issue: i.issue }).collect())
+    })?;
+
+    if query.dry_run {
+        return Ok((StatusCode::OK, Json(json!({ "dryRun": true, "quiz": quiz }))));
+    }
+    let question_count = quiz.questions.len();
+    let quiz_id = state.create_quiz(teacher_id, quiz, None).await?;
+    state.record_usage("quiz_import").await;
+    Ok((StatusCode::CREATED, Json(json!({ "quizId": quiz_id, "questionCount": question_count }))))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct QuizExportQuery {
+    pub format: Option<String>,
+}
+
+// `format` is gift, moodlexml or json (the default).
+pub async fn export_quiz(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    jar: CookieJar,
+    Path(id): Path<i64>,
+    query: axum::extract::Query<QuizExportQuery>,
+) -> Result<Response, AppError> {
+    let teacher_id = auth_teacher_id(&headers, &jar, &state).await
+        .ok_or_else(|| AppError::unauthorized("not logged in"))?;
+    let format = match query.format.as_deref() {
+        None => quiz_export::ExportFormat::Json,
+        Some(value) => quiz_export::ExportFormat::parse(value).ok_or_else(|| {
+            AppError::validation("invalid query").with_details(vec![
+                ErrorDetail { field: "format".into(), issue: "must be gift, moodlexml or json".into() },
+            ])
+        })?,
+    };
+    let quiz = owned_quiz(&state, teacher_id, id).await?;
+    let body = quiz_export::render(&quiz, format);
+    state.record_usage("quiz_export").await;
+    let filename = format!("quiz-{}.{}", quiz.id, format.extension());
+    Ok((
+        [
+            (axum::http::header::CONTENT_TYPE, format.content_type().to_string()),
+            (axum::http::header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", filename)),
+        ],
+        body,
+    )
+        .into_response())
+}
+
+// Difficulty and discrimination of each question over every session the owner ran with the quiz, to spot
+// questions that need fixing. Practice runs are left out.
+pub async fn quiz_item_analysis(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    jar: CookieJar,
+    Path(id): Path<i64>,
+) -> Result<Json<item_analysis::ItemAnalysis>, AppError> {
+    let teacher_id = auth_teacher_id(&headers, &jar, &state).await
+        .ok_or_else(|| AppError::unauthorized("not logged in"))?;
+    let quiz = owned_quiz(&state, teacher_id, id).await?;
+    let sessions = state.db.game_sessions.read().await;
+    let played = sessions
+        .values()
+        .filter(|s| s.teacher_id == teacher_id && (s.quiz_id == id || s.playlist.contains(&id)));
+    Ok(Json(item_analysis::analyze(&quiz, played)))
+}
+
+#[derive(Debug, Serialize)]
+pub struct QuizSummary {
+    pub id: i64,
+    pub title: String,
+    pub description: Option<String>,
+    pub is_published: bool,
+    pub difficulty: Option<Difficulty>,
+    pub tags: Vec<String>,
+    pub subject: Option<String>,
+    pub grade: Option<u8>,
+    pub language: Option<String>,
+    #[serde(rename = "createdAt")]
+    pub created_at: Option<chrono::DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct QuizListResponse {
+    pub items: Vec<QuizSummary>,
+    pub total: usize,
+    pub page: usize,
+    pub limit: usize,
+    #[serde(rename = "nextCursor")]
+    pub next_cursor: Option<String>,
+}
+
+// Shared by the teacher's quiz list and the library search.
+#[derive(Debug, Deserialize)]
+pub struct QuizFilterQuery {
+    pub tag: Option<String>,
+    pub difficulty: Option<Difficulty>,
+    pub subject: Option<String>,
+    pub grade: Option<u8>,
+    pub lang: Option<String>,
+}
+
+impl QuizFilterQuery {
+    fn matches(&self, quiz: &QuizRecord) -> bool {
+        let same_text = |wanted: &Option<String>, actual: &Option<String>| match wanted.as_deref().map(str::trim) {
+            None | Some("") => true,
+            Some(wanted) => actual.as_deref().map(|a| a.trim().to_lowercase() == wanted.to_lowercase()).unwrap_or(false),
+        };
+        self.tag.as_deref().map(|t| quiz.has_tag(t)).unwrap_or(true)
+            && self.difficulty.map(|d| quiz.has_difficulty(d)).unwrap_or(true)
+            && same_text(&self.subject, &quiz.subject)
+            && self.grade.map(|g| quiz.grade == Some(g)).unwrap_or(true)
+            && same_text(&self.lang, &quiz.language)
+    }
+}
+
+// Word search over the title, description, subject and tags: every word has to appear in one of them.
+fn matches_search(quiz: &QuizRecord, term: &str) -> bool {
+    let haystack: Vec<String> = [Some(&quiz.title), quiz.description.as_ref(), quiz.subject.as_ref()]
+        .into_iter()
+        .flatten()
+        .chain(quiz.tags.iter())
+        .map(|text| text.to_lowercase())
+        .collect();
+    term.to_lowercase().split_whitespace().all(|word| haystack.iter().any(|text| text.contains(word)))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QuizSort {
+    Title,
+    #[serde(alias = "createdAt")]
+    CreatedAt,
+    Popularity,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
+
+// Paging for the teacher's quiz list and the library. A `cursor` from a previous page continues right
+// after its last quiz and takes precedence over `page`, so walking a large library does not skip or repeat
+// quizzes published in the meantime.
+#[derive(Debug, Deserialize)]
+pub struct QuizPageQuery {
+    #[serde(default = "default_page")]
+    pub page: usize,
+    #[serde(default = "default_page_limit", alias = "perPage")]
+    pub limit: usize,
+    pub sort: Option<QuizSort>,
+    pub order: Option<SortOrder>,
+    pub cursor: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+enum QuizSortKey {
+    Text(String),
+    Time(Option<chrono::DateTime<Utc>>),
+    Count(usize),
+}
+
+#[derive(Serialize, Deserialize)]
+struct QuizCursor {
+    sort: QuizSort,
+    order: SortOrder,
+    key: QuizSortKey,
+    id: i64,
+}
+
+struct QuizPage<'a> {
+    items: Vec<&'a QuizRecord>,
+    total: usize,
+    next_cursor: Option<String>,
+}
+
+impl QuizPageQuery {
+    // Titles read A to Z by default, dates and popularity newest and most popular first. Ties are broken
+    // by id, so the order is the same on every request.
+    fn paginate<'a>(
+        &self,
+        quizzes: Vec<&'a QuizRecord>,
+        default_sort: QuizSort,
+        popularity: &HashMap<i64, usize>,
+    ) -> Result<QuizPage<'a>, AppError> {
+        let invalid = |field: &str, issue: String| {
+            AppError::validation("invalid paging")
+                .with_details(vec![ErrorDetail { field: field.into(), issue }])
+        };
+        if self.page == 0 || self.limit == 0 || self.limit > MAX_PAGE_LIMIT {
+            let issue = format!("page must be at least 1 and limit within 1..={}", MAX_PAGE_LIMIT);
+            return Err(invalid(if self.page == 0 { "page" } else { "limit" }, issue));
+        }
+        let sort = self.sort.unwrap_or(default_sort);
+        let order = self.order.unwrap_or(if sort == QuizSort::Title { SortOrder::Asc } else { SortOrder::Desc });
+        let key = |quiz: &QuizRecord| match sort {
+            QuizSort::Title => QuizSortKey::Text(quiz.title.trim().to_lowercase()),
+            QuizSort::CreatedAt => QuizSortKey::Time(quiz.created_at),
+            QuizSort::Popularity => QuizSortKey::Count(popularity.get(&quiz.id).copied().unwrap_or(0)),
+        };
+        let directed = |ordering: std::cmp::Ordering| match order {
+            SortOrder::Asc => ordering,
+            SortOrder::Desc => ordering.reverse(),
+        };
+        let mut keyed: Vec<(QuizSortKey, &QuizRecord)> = quizzes.into_iter().map(|q| (key(q), q)).collect();
+        keyed.sort_by(|a, b| directed((&a.0, a.1.id).cmp(&(&b.0, b.1.id))));
+
+        let start = match &self.cursor {
+            Some(cursor) => {
+                let cursor = URL_SAFE_NO_PAD
+                    .decode(cursor)
+                    .ok()
+                    .and_then(|raw| serde_json::from_slice::<QuizCursor>(&raw).ok())
+                    .ok_or_else(|| invalid("cursor", "malformed cursor".into()))?;
+                if cursor.sort != sort || cursor.order != order {
+                    return Err(invalid("cursor", "cursor belongs to a different sort or order".into()));
+                }
+                keyed.partition_point(|(key, q)| {
+                    directed((key, q.id).cmp(&(&cursor.key, cursor.id))) != std::cmp::Ordering::Greater
+                })
+            }
+            None => (self.page - 1).saturating_mul(self.limit),
+        };
+        let total = keyed.len();
+        let end = start.saturating_add(self.limit).min(total);
+        let page = keyed.get(start..end).unwrap_or_default();
+        let next_cursor = match page.last() {
+            Some((key, last)) if end < total => {
+                let cursor = QuizCursor { sort, order, key: key.clone(), id: last.id };
+                serde_json::to_vec(&cursor).ok().map(|raw| URL_SAFE_NO_PAD.encode(raw))
+            }
+            _ => None,
+        };
+        Ok(QuizPage { items: page.iter().map(|(_, q)| *q).collect(), total, next_cursor })
+    }
+}
+
+// Weak validator over the collection versions and whatever else shapes the body (caller, query).
+fn etag(versions: (u64, u64, u64), shape: impl std::hash::Hash) -> String {
+    use std::hash::Hasher;
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    std::hash::Hash::hash(&versions, &mut hasher);
+    shape.hash(&mut hasher);
+    format!("W/\"{:016x}\"", hasher.finish())
+}
+
+fn etag_matches(headers: &HeaderMap, tag: &str) -> bool {
+    let strip = |t: &str| t.trim().trim_start_matches("W/").to_string();
+    headers
+        .get_all(axum::http::header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|candidate| candidate.trim() == "*" || strip(candidate) == strip(tag))
+}
+
+// Checked before the body is built, so an unchanged collection costs no serialization.
+fn not_modified(headers: &HeaderMap, tag: &str) -> Option<Response> {
+    etag_matches(headers, tag).then(|| with_etag(StatusCode::NOT_MODIFIED.into_response(), tag))
+}
+
+// Browsers must revalidate, since the body depends on who is asking.
+fn with_etag(mut response: Response, tag: &str) -> Response {
+    if let Ok(value) = HeaderValue::from_str(tag) {
+        response.headers_mut().insert(axum::http::header::ETAG, value);
+    }
+    response
+        .headers_mut()
+        .insert(axum::http::header::CACHE_CONTROL, HeaderValue::from_static("private, no-cache"));
+    response
+}
+
+// Sessions played plus clones made of each quiz. Takes and releases each lock in turn, so callers must
+// not hold `quizzes`: everywhere else `game_sessions` is locked before `quizzes`.
+async fn quiz_popularity(state: &AppState) -> HashMap<i64, usize> {
+    let mut popularity: HashMap<i64, usize> = HashMap::new();
+    for session in state.db.game_sessions.read().await.values().filter(|s| s.practice_started_at.is_none()) {
+        let played: HashSet<i64> = session.playlist.iter().copied().chain([session.quiz_id]).collect();
+        for quiz_id in played {
+            *popularity.entry(quiz_id).or_default() += 1;
+        }
+    }
+    for source in state.db.quizzes.read().await.values().filter_map(|q| q.source_quiz_id) {
+        *popularity.entry(source).or_default() += 1;
+    }
+    popularity
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OwnQuizQuery {
+    pub search: Option<String>,
+    pub published: Option<bool>,
+}
+
+pub async fn list_quizzes(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    jar: CookieJar,
+    query: axum::extract::Query<QuizFilterQuery>,
+    own: axum::extract::Query<OwnQuizQuery>,
+    paging: axum::extract::Query<QuizPageQuery>,
+    uri: axum::http::Uri,
+) -> Result<Response, AppError> {
+    let teacher_id = auth_teacher_id(&headers, &jar, &state).await
+        .ok_or_else(|| AppError::unauthorized("not logged in"))?;
+    let tag = etag(state.db.versions.current(), ("quizzes", teacher_id, uri.query()));
+    if let Some(response) = not_modified(&headers, &tag) {
+        return Ok(response);
+    }
+    let list = own_quiz_list(&state, teacher_id, &query, &own, &paging).await?;
+    Ok(with_etag(Json(list).into_response(), &tag))
+}
+
+async fn own_quiz_list(
+    state: &AppState,
+    teacher_id: i64,
+    query: &QuizFilterQuery,
+    own: &OwnQuizQuery,
+    paging: &QuizPageQuery,
+) -> Result<QuizListResponse, AppError> {
+    let popularity = match paging.sort {
+        Some(QuizSort::Popularity) => quiz_popularity(state).await,
+        _ => HashMap::new(),
+    };
+    let quizzes = state.db.quizzes.read().await;
+    let search = own.search.as_deref().unwrap_or_default();
+    let owned: Vec<&QuizRecord> = quizzes
+        .values()
+        .filter(|q| q.owner_teacher_id == teacher_id && query.matches(q))
+        .filter(|q| own.published.is_none_or(|published| q.is_published == published))
+        .filter(|q| matches_search(q, search))
+        .collect();
+    let page = paging.paginate(owned, QuizSort::CreatedAt, &popularity)?;
+    let items: Vec<QuizSummary> = page
+        .items
+        .into_iter()
+        .map(|q| QuizSummary {
+            id: q.id,
+            title: q.title.clone(),
+            description: q.description.clone(),
+            is_published: q.is_published,
+            difficulty: q.difficulty,
+            tags: q.tags.clone(),
+            subject: q.subject.clone(),
+            grade: q.grade,
+            language: q.language.clone(),
+            created_at: q.created_at,
+        })
+        .collect();
+    Ok(QuizListResponse {
+        items,
+        total: page.total,
+        page: paging.page,
+        limit: paging.limit,
+        next_cursor: page.next_cursor,
+    })
+}
+
+pub async fn get_quiz(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    jar: CookieJar,
+    Path(id): Path<i64>,
+) -> Result<Response, AppError> {
+    let teacher_id = auth_teacher_id(&headers, &jar, &state).await
+        .ok_or_else(|| AppError::unauthorized("not logged in"))?;
+    let tag = etag(state.db.versions.current(), ("quiz", id));
+    let quiz = state.quiz(id).await?;
+    if quiz.owner_teacher_id != teacher_id {
+        return Err(AppError::forbidden("access denied"));
+    }
+    if let Some(response) = not_modified(&headers, &tag) {
+        return Ok(response);
+    }
+    Ok(with_etag(Json(quiz).into_response(), &tag))
+}
+
+pub async fn update_quiz(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    jar: CookieJar,
+    Path(id): Path<i64>,
+    Json(payload): Json<CreateQuizPayload>,
+) -> Result<Json<QuizIdResponse>, AppError> {
+    let teacher_id = auth_teacher_id(&headers, &jar, &state).await
+        .ok_or_else(|| AppError::unauthorized("not logged in"))?;
+    let quiz = Quiz {
+        title: payload.title,
+        description: payload.description,
+        questions: payload.questions,
+        difficulty: payload.difficulty,
+        tags: payload.tags,
+        settings: payload.settings,
+        subject: payload.subject,
+        grade: payload.grade,
+        language: payload.language,
+    };
+    if let Err(issues) = validate_quiz(&quiz, &state.quiz_limits) {
+        return Err(AppError::validation("quiz validation failed")
+        .with_details(
+            issues
+                .into_iter()
+                .map(|i| ErrorDetail {
+                    field: i.field,
+                    issue: i.issue,
+                })
+                .collect(),
+        ));
+    }
+    let mut quizzes = state.db.quizzes.write().await;
+    let item = quizzes
+        .get_mut(&id)
+        .or_not_found("quiz")?;
+    if item.owner_teacher_id != teacher_id {
+        return Err(AppError::forbidden("access denied"));
+    }
+    item.title = quiz.title;
+    item.description = quiz.description;
+    item.questions = quiz.questions;
+    item.difficulty = quiz.difficulty;
+    item.tags = quiz.tags;
+    item.settings = quiz.settings;
+    item.subject = quiz.subject;
+    item.grade = quiz.grade;
+    item.language = quiz.language;
+    state.db.versions.bump_quizzes();
+    drop(quizzes);
+    state.log_quiz(id).await;
+    state.record_quiz_revision(id, teacher_id, "updated", None).await;
+    state.commit_changes()?;
+    Ok(Json(QuizIdResponse { quiz_id: id }))
+}
+
+async fn owned_quiz(state: &AppState, teacher_id: i64, id: i64) -> Result<QuizRecord, AppError> {
+    let quiz = state.quiz(id).await?;
+    if quiz.owner_teacher_id != teacher_id {
+        return Err(AppError::forbidden("access denied"));
+    }
+    Ok(quiz)
+}
+
+// /api/v2 quiz routes: same rules as v1, bodies from `api_v2`. ETags carry the version so a client
+// switching over does not revalidate a v1 body.
+pub async fn v2_create_quiz(
+    state: State<AppState>,
+    headers: HeaderMap,
+    jar: CookieJar,
+    payload: Json<CreateQuizPayload>,
+) -> Result<(StatusCode, Json<api_v2::QuizIdOut>), AppError> {
+    let (status, Json(created)) = create_quiz(state, headers, jar, payload).await?;
+    Ok((status, Json(created.into())))
+}
+
+pub async fn v2_update_quiz(
+    state: State<AppState>,
+    headers: HeaderMap,
+    jar: CookieJar,
+    id: Path<i64>,
+    payload: Json<CreateQuizPayload>,
+) -> Result<Json<api_v2::QuizIdOut>, AppError> {
+    let Json(updated) = update_quiz(state, headers, jar, id, payload).await?;
+    Ok(Json(updated.into()))
+}
+
+pub async fn v2_get_quiz(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    jar: CookieJar,
+    Path(id): Path<i64>,
+) -> Result<Response, AppError> {
+    let teacher_id = auth_teacher_id(&headers, &jar, &state).await
+        .ok_or_else(|| AppError::unauthorized("not logged in"))?;
+    let tag = etag(state.db.versions.current(), ("quiz.v2", id));
+    let quiz = owned_quiz(&state, teacher_id, id).await?;
+    if let Some(response) = not_modified(&headers, &tag) {
+        return Ok(response);
+    }
+    Ok(with_etag(Json(api_v2::QuizOut::from(quiz)).into_response(), &tag))
+}
+
+pub async fn v2_list_quizzes(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    jar: CookieJar,
+    query: axum::extract::Query<QuizFilterQuery>,
+    own: axum::extract::Query<OwnQuizQuery>,
+    paging: axum::extract::Query<QuizPageQuery>,
+    uri: axum::http::Uri,
+) -> Result<Response, AppError> {
+    let teacher_id = auth_teacher_id(&headers, &jar, &state).await
+        .ok_or_else(|| AppError::unauthorized("not logged in"))?;
+    let tag = etag(state.db.versions.current(), ("quizzes.v2", teacher_id, uri.query()));
+    if let Some(response) = not_modified(&headers, &tag) {
+        return Ok(response);
+    }
+    let list = own_quiz_list(&state, teacher_id, &query, &own, &paging).await?;
+    Ok(with_etag(Json(api_v2::QuizListOut::from(list)).into_response(), &tag))
+}
+
+pub async fn list_quiz_revisions(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    jar: CookieJar,
+    Path(id): Path<i64>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let teacher_id = auth_teacher_id(&headers, &jar, &state).await
+        .ok_or_else(|| AppError::unauthorized("not logged in"))?;
+    owned_quiz(&state, teacher_id, id).await?;
+    let revisions = state.db.quiz_revisions.read().await;
+    let items: Vec<_> = revisions
+        .get(&id)
+        .map(|history| history.iter().rev().collect::<Vec<_>>())
+        .unwrap_or_default()
+        .into_iter()
+        .map(|r| {
+            json!({
+                "rev": r.rev,
+                "authorTeacherId": r.author_teacher_id,
+                "createdAt": r.created_at.to_rfc3339(),
+                "reason": r.reason,
+                "restoredFrom": r.restored_from,
+                "title": r.title,
+                "description": r.description,
+                "questions": r.questions,
+                "metadata": r.metadata,
+                "diff": r.diff
+            })
+        })
+        .collect();
+    Ok(Json(json!({ "items": items, "total": items.len() })))
+}
+
+pub async fn restore_quiz_revision(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    jar: CookieJar,
+    Path((id, rev)): Path<(i64, u32)>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let teacher_id = auth_teacher_id(&headers, &jar, &state).await
+        .ok_or_else(|| AppError::unauthorized("not logged in"))?;
+    owned_quiz(&state, teacher_id, id).await?;
+    let revision = state
+        .db
+        .quiz_revisions
+        .read()
+        .await
+        .get(&id)
+        .and_then(|history| history.iter().find(|r| r.rev == rev).cloned())
+        .or_not_found("revision")?;
+    {
+        let mut quizzes = state.db.quizzes.write().await;
+        let item = quizzes
+            .get_mut(&id)
+            .or_not_found("quiz")?;
+        item.restore_revision(revision);
+        state.db.versions.bump_quizzes();
+    }
+    state.log_quiz(id).await;
+    let new_rev = state.record_quiz_revision(id, teacher_id, "restored", Some(rev)).await;
+    state.commit_changes()?;
+    Ok(Json(json!({ "quizId": id, "rev": new_rev, "restoredFrom": rev })))
+}
+
+pub async fn delete_quiz(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    jar: CookieJar,
+    Path(id): Path<i64>,
+) -> Result<StatusCode, AppError> {
+    let teacher_id = auth_teacher_id(&headers, &jar, &state).await
+        .ok_or_else(|| AppError::unauthorized("not logged in"))?;
+    let mut quizzes = state.db.quizzes.write().await;
+    let existing = quizzes
+        .get(&id)
+        .cloned()
+        .or_not_found("quiz")?;
+    if existing.owner_teacher_id != teacher_id {
+        return Err(AppError::forbidden("access denied"));
+    }
+    quizzes.remove(&id);
+    state.db.versions.bump_quizzes();
+    drop(quizzes);
+    state.db.quiz_revisions.write().await.remove(&id);
+    state.audit(Some(teacher_id), "quiz.delete", Some(id.to_string())).await;
+    state.log_change(JournalOp::QuizDeleted { quiz_id: id }).await;
+    state.commit_changes()?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn publish_quiz(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    jar: CookieJar,
+    Path(id): Path<i64>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let teacher_id = auth_teacher_id(&headers, &jar, &state).await
+        .ok_or_else(|| AppError::unauthorized("not logged in"))?;
+    let mut quizzes = state.db.quizzes.write().await;
+    let q = quizzes
+        .get_mut(&id)
+        .or_not_found("quiz")?;
+    if q.owner_teacher_id != teacher_id {
+        return Err(AppError::forbidden("access denied"));
+    }
+    q.is_published = true;
+    state.db.versions.bump_quizzes();
+    drop(quizzes);
+    state.audit(Some(teacher_id), "quiz.publish", Some(id.to_string())).await;
+    state.log_quiz(id).await;
+    state.commit_changes()?;
+    Ok(Json(json!({ "published": true })))
+}
+
+pub async fn unpublish_quiz(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    jar: CookieJar,
+    Path(id): Path<i64>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let teacher_id = auth_teacher_id(&headers, &jar, &state).await
+        .ok_or_else(|| AppError::unauthorized("not logged in"))?;
+    let mut quizzes = state.db.quizzes.write().await;
+    let q = quizzes
+        .get_mut(&id)
+        .or_not_found("quiz")?;
+    if q.owner_teacher_id != teacher_id {
+        return Err(AppError::forbidden("access denied"));
+    }
+    q.is_published = false;
+    state.db.versions.bump_quizzes();
+    drop(quizzes);
+    state.audit(Some(teacher_id), "quiz.unpublish", Some(id.to_string())).await;
+    state.log_quiz(id).await;
+    state.commit_changes()?;
+    Ok(Json(json!({ "published": false })))
+}
+
+pub async fn clone_quiz(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    jar: CookieJar,
+    Path(id): Path<i64>,
+) -> Result<(StatusCode, Json<serde_json::Value>), AppError> {
+    let teacher_id = auth_teacher_id(&headers, &jar, &state).await
+        .ok_or_else(|| AppError::unauthorized("not logged in"))?;
+    let source = state.quiz(id).await?;
+    if !source.is_published {
+        return Err(AppError::conflict("quiz is not published"));
+    }
+    let quiz_id = state
+        .create_quiz(
+            teacher_id,
+            Quiz {
+                title: source.title,
+                description: source.description,
+                questions: source.questions,
+                difficulty: source.difficulty,
+                tags: source.tags,
+                settings: source.settings,
+                subject: source.subject,
+                grade: source.grade,
+                language: source.language,
+            },
+            Some(id),
+        )
+        .await?;
+    state.record_usage("library_clone").await;
+    Ok((StatusCode::CREATED, Json(json!({ "quizId": quiz_id, "sourceQuizId": id }))))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SearchQuery {
+    pub q: Option<String>,
+}
+
+pub async fn library_list(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    jar: CookieJar,
+    query: axum::extract::Query<SearchQuery>,
+    filter: axum::extract::Query<QuizFilterQuery>,
+    paging: axum::extract::Query<QuizPageQuery>,
+    uri: axum::http::Uri,
+) -> Result<Response, AppError> {
+    let term = query.q.clone().unwrap_or_default();
+    let teacher_id = auth_teacher_id(&headers, &jar, &state).await;
+    // alreadyOwned depends on the caller, so the tag does too.
+    let tag = etag(state.db.versions.current(), ("library", teacher_id, uri.query()));
+    if let Some(response) = not_modified(&headers, &tag) {
+        return Ok(response);
+    }
+    let popularity = quiz_popularity(&state).await;
+    let quizzes = state.db.quizzes.read().await;
+
+    let own_fingerprints = if let Some(tid) = teacher_id {
+        quizzes
+            .values()
+            .filter(|q| q.owner_teacher_id == tid)
+            .map(|q| quiz_fingerprint(&q.title, &q.description, &q.questions))
+            .collect::<std::collections::HashSet<_>>()
+    } else {
+        std::collections::HashSet::new()
+    };
+
+    let published: Vec<&QuizRecord> = quizzes
+        .values()
+        .filter(|q| q.is_published && filter.matches(q) && matches_search(q, &term))
+        .collect();
+    let page = paging.paginate(published, QuizSort::Popularity, &popularity)?;
+    let items: Vec<_> = page
+        .items
+        .into_iter()
+        .map(|q| {
+            let already_owned = teacher_id
+                .map(|tid| {
+                    if q.owner_teacher_id == tid {
+                        true
+                    } else {
+                        let fp = quiz_fingerprint(&q.title, &q.description, &q.questions);
+                        own_fingerprints.contains(&fp)
+                    }
+                })
+                .unwrap_or(false);
+            json!({
+                "id": q.id,
+                "title": q.title,
+                "description": q.description,
+                "ownerTeacherId": q.owner_teacher_id,
+                "difficulty": q.difficulty,
+                "tags": q.tags,
+                "subject": q.subject,
+                "grade": q.grade,
+                "language": q.language,
+                "createdAt": q.created_at.map(|t| t.to_rfc3339()),
+                "popularity": popularity.get(&q.id).copied().unwrap_or(0),
+                "alreadyOwned": already_owned
+            })
+        })
+        .collect();
+    let body = Json(json!({
+        "items": items,
+        "total": page.total,
+        "page": paging.page,
+        "limit": paging.limit,
+        "nextCursor": page.next_cursor
+    }));
+    Ok(with_etag(body.into_response(), &tag))
+}
+
+fn quiz_fingerprint(title: &str, description: &Option<String>, questions: &[crate::models::Question]) -> String {
+    let normalized = json!({
+        "title": title.trim(),
+        "description": description.as_ref().map(|v| v.trim()),
+        "questions": questions,
+    });
+    normalized.to_string()
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AiGeneratePayload {
+    pub topic: String,
+    pub grade: Option<String>,
+    #[serde(rename = "questionCount")]
+    pub question_count: usize,
+    #[serde(default)]
+    pub provider: Option<AiProviderChoice>,
+    // Questions per difficulty (`easy`, `medium`, `hard`) and per type; each must add up to
+    // `questionCount`.
+    #[serde(default, rename = "difficultyMix")]
+    pub difficulty_mix: BTreeMap<String, usize>,
+    #[serde(default, rename = "typeMix")]
+    pub type_mix: BTreeMap<String, usize>,
+}
+
+impl From<AiFailure> for AppError {
+    fn from(failure: AiFailure) -> Self {
+        AppError::Upstream {
+            status: StatusCode::from_u16(failure.status).unwrap_or(StatusCode::BAD_GATEWAY),
+            code: failure.code,
+            message: failure.message,
+            details: failure.details.into_iter().map(|i| ErrorDetail { field: i.field, issue: i.issue }).collect(),
+        }
+    }
+}
+
+// The chosen provider's client, metered against the teacher's daily quota.
+async fn ai_client(
+    state: &AppState,
+    teacher_id: i64,
+    choice: Option<&AiProviderChoice>,
+) -> Result<(Arc<dyn AiQuizClient>, &'static str), AppError> {
+    let (client, provider) = state.ai.client_for(choice).map_err(|issues| {
+        AppError::validation("invalid ai provider")
+            .with_details(issues.into_iter().map(|i| ErrorDetail { field: i.field, issue: i.issue }).collect())
+    })?;
+    let client = state.metered_ai_client(client, teacher_id).await.map_err(|issues| {
+        AppError::rate_limited("daily ai quota exceeded")
+            .with_details(issues.into_iter().map(|i| ErrorDetail { field: i.field, issue: i.issue }).collect())
+    })?;
+    Ok((client, provider))
+}
+
+async fn ai_request(
+    state: &AppState,
+    teacher_id: i64,
+    payload: AiGeneratePayload,
+) -> Result<(Arc<dyn AiQuizClient>, &'static str, AiRequest), AppError> {
+    let difficulty_mix = ai_providers::parse_mix::<Difficulty>("difficultyMix", &payload.difficulty_mix, payload.question_count);
+    let type_mix = ai_providers::parse_mix::<QuestionType>("typeMix", &payload.type_mix, payload.question_count);
+    let (difficulty_mix, type_mix) = match (difficulty_mix, type_mix) {
+        (Ok(difficulty_mix), Ok(type_mix)) => (difficulty_mix, type_mix),
+        (difficulty_mix, type_mix) => {
+            let issues = difficulty_mix.err().into_iter().chain(type_mix.err()).flatten();
+            return Err(AppError::validation("invalid question mix")
+                .with_details(issues.map(|i| ErrorDetail { field: i.field, issue: i.issue }).collect()));
+        }
+    };
+    let (client, provider) = ai_client(state, teacher_id, payload.provider.as_ref()).await?;
+    let request = AiRequest {
+        topic: payload.topic,
+        grade: payload.grade,
+        question_count: payload.question_count,
+        source_text: None,
+        repair_attempts: state.ai.repair_attempts(),
+        difficulty_mix,
+        type_mix,
+    };
+    Ok((client, provider, request))
+}
+
+pub async fn ai_generate_quiz(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    jar: CookieJar,
+    Json(payload): Json<AiGeneratePayload>,
+) -> Result<(StatusCode, Json<serde_json::Value>), AppError> {
+    let teacher_id = auth_teacher_id(&headers, &jar, &state).await
+        .ok_or_else(|| AppError::unauthorized("not logged in"))?;
+    let (ai_client, provider, request) = ai_request(&state, teacher_id, payload).await?;
+
+    let quiz = ai_providers::generate_quiz(
+        ai_client.as_ref(),
+        provider,
+        &state.quiz_schemas,
+        &state.quiz_limits,
+        &state.moderation,
+        &state.db.prompt_set().await,
+        &request,
+    )
+    .await?;
+    let quiz_id = state.create_quiz(teacher_id, quiz, None).await?;
+    state.record_usage("ai_generation").await;
+    Ok((StatusCode::CREATED, Json(json!({ "quizId": quiz_id, "source": "ai" }))))
+}
+
+// Same payload as `ai_generate_quiz`, but the generation runs in the background and the client
+// polls `GET /ai/jobs/:id`.
+pub async fn create_ai_job(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    jar: CookieJar,
+    Json(payload): Json<AiGeneratePayload>,
+) -> Result<(StatusCode, Json<AiJob>), AppError> {
+    let teacher_id = auth_teacher_id(&headers, &jar, &state).await
+        .ok_or_else(|| AppError::unauthorized("not logged in"))?;
+    let (ai_client, provider, request) = ai_request(&state, teacher_id, payload).await?;
+    let job = state.ai_jobs.enqueue(teacher_id, ai_client, provider, request).ok_or_else(|| {
+        AppError::rate_limited("ai job queue is full, try again later")
+    })?;
+    Ok((StatusCode::ACCEPTED, Json(job)))
+}
+
+// Multipart fields: `file` (PDF or DOCX, required), `questionCount` (required), `topic`, `grade`
+// and `provider` (the same object as in JSON requests, as a string). The text is extracted right
+// away so unreadable files fail here; generation goes through the job queue.
+fn multipart_error(e: axum::extract::multipart::MultipartError) -> AppError {
+    let message = format!("invalid multipart body: {}", e.body_text());
+    if e.status() == StatusCode::PAYLOAD_TOO_LARGE {
+        AppError::payload_too_large(message)
+    } else {
+        AppError::validation(message)
+    }
+}
+
+pub async fn ai_generate_from_file(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    jar: CookieJar,
+    mut multipart: axum::extract::Multipart,
+) -> Result<(StatusCode, Json<serde_json::Value>), AppError> {
+    let teacher_id = auth_teacher_id(&headers, &jar, &state).await
+        .ok_or_else(|| AppError::unauthorized("not logged in"))?;
+    let invalid = |field: &str, issue: String| {
+        AppError::validation("invalid upload")
+            .with_details(vec![ErrorDetail { field: field.into(), issue }])
+    };
+
+    let mut file = None;
+    let mut fields = HashMap::new();
+    while let Some(field) = multipart.next_field().await.map_err(multipart_error)? {
+        let name = field.name().unwrap_or_default().to_string();
+        if name == "file" {
+            let format = document_text::DocumentFormat::detect(field.file_name(), field.content_type())
+                .ok_or_else(|| invalid("file", "must be .pdf or .docx".into()))?;
+            file = Some((format, field.bytes().await.map_err(multipart_error)?));
+        } else {
+            fields.insert(name, field.text().await.map_err(multipart_error)?);
+        }
+    }
+    let Some((format, bytes)) = file else {
+        return Err(invalid("file", "is required".into()));
+    };
+    let question_count = fields
+        .get("questionCount")
+        .and_then(|v| v.trim().parse::<usize>().ok())
+        .filter(|n| *n > 0)
+        .ok_or_else(|| invalid("questionCount", "must be a positive number".into()))?;
+    let provider = match fields.get("provider").filter(|v| !v.trim().is_empty()) {
+        Some(raw) => Some(
+            serde_json::from_str::<AiProviderChoice>(raw).map_err(|e| invalid("provider", format!("invalid json: {}", e)))?,
+        ),
+        None => None,
+    };
+    let (client, provider) = ai_client(&state, teacher_id, provider.as_ref()).await?;
+
+    let extracted = tokio::task::spawn_blocking(move || document_text::extract(&bytes, format))
+        .await
+        .unwrap_or_else(|_| Err("cannot read file".into()))
+        .map_err(|e| invalid("file", e))?;
+    if extracted.text.trim().is_empty() {
+        return Err(AppError::rejected(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "VALIDATION_ERROR",
+            "no text found in the file; scanned documents are not supported",
+        ));
+    }
+    let source_chars = extracted.text.chars().count();
+    let request = AiRequest {
+        topic: fields.get("topic").map(|t| t.trim().to_string()).unwrap_or_default(),
+        grade: fields.get("grade").map(|g| g.trim().to_string()).filter(|g| !g.is_empty()),
+        question_count,
+        source_text: Some(extracted.text),
+        repair_attempts: state.ai.repair_attempts(),
+        difficulty_mix: Vec::new(),
+        type_mix: Vec::new(),
+    };
+    let job = state.ai_jobs.enqueue(teacher_id, client, provider, request).ok_or_else(|| {
+        AppError::rate_limited("ai job queue is full, try again later")
+    })?;
+    state.record_usage("ai_generation_from_file").await;
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(json!({ "job": job, "sourceChars": source_chars, "truncated": extracted.truncated })),
+    ))
+}
+
+impl From<MediaError> for AppError {
+    fn from(err: MediaError) -> Self {
+        match err {
+            MediaError::TooLarge(max) => {
+                AppError::payload_too_large(format!("file is larger than {} MB", max / 1024 / 1024))
+            }
+            MediaError::Unsupported(issue) => AppError::validation("unsupported file")
+                .with_details(vec![ErrorDetail { field: "file".into(), issue }]),
+            MediaError::Storage(err) => err.into(),
+        }
+    }
+}
+
+// One file in the `file` field. Identical bytes get the same id, so a repeated upload answers 200
+// with the existing file instead of 201.
+pub async fn upload_media(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    jar: CookieJar,
+    mut multipart: axum::extract::Multipart,
+) -> Result<(StatusCode, Json<serde_json::Value>), AppError> {
+    let teacher_id = auth_teacher_id(&headers, &jar, &state).await
+        .ok_or_else(|| AppError::unauthorized("not logged in"))?;
+    let mut upload = None;
+    while let Some(field) = multipart.next_field().await.map_err(multipart_error)? {
+        if field.name() == Some("file") {
+            let declared = field.content_type().map(str::to_string);
+            upload = Some((declared, field.bytes().await.map_err(multipart_error)?));
+        }
+    }
+    let Some((declared, bytes)) = upload else {
+        return Err(AppError::validation("invalid upload")
+            .with_details(vec![ErrorDetail { field: "file".into(), issue: "is required".into() }]));
+    };
+    let stored = state.media.upload(declared.as_deref(), bytes.to_vec()).await?;
+    if stored.created {
+        info!("teacher {} uploaded media {} ({}, {} bytes)", teacher_id, stored.id, stored.content_type, stored.size);
+    }
+    let (url, expires_at) = state.media.download_url(&stored.id);
+    Ok((
+        if stored.created { StatusCode::CREATED } else { StatusCode::OK },
+        Json(json!({
+            "id": stored.id,
+            "contentType": stored.content_type,
+            "size": stored.size,
+            "url": url,
+            "expiresAt": expires_at.to_rfc3339()
+        })),
+    ))
+}
+
+// A fresh download link for a file referenced by a quiz, e.g. when the previous one expired.
+pub async fn media_link(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    jar: CookieJar,
+    Path(id): Path<String>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    auth_teacher_id(&headers, &jar, &state).await.ok_or_else(|| AppError::unauthorized("not logged in"))?;
+    if !media::is_media_id(&id) || !state.media.store.exists(&id).await? {
+        return Err(AppError::not_found("media not found"));
+    }
+    let (url, expires_at) = state.media.download_url(&id);
+    Ok(Json(json!({ "id": id, "url": url, "expiresAt": expires_at.to_rfc3339() })))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MediaLinkQuery {
+    pub expires: Option<i64>,
+    pub sig: Option<String>,
+}
+
+// Needs no login: the signature proves the link was handed out by the backend and is still valid.
+pub async fn download_media(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    query: axum::extract::Query<MediaLinkQuery>,
+) -> Result<Response, AppError> {
+    let (Some(expires), Some(sig)) = (query.expires, query.sig.as_deref()) else {
+        return Err(AppError::forbidden("invalid or expired media link"));
+    };
+    if !media::is_media_id(&id) || !state.media.verify_link(&id, expires, sig) {
+        return Err(AppError::forbidden("invalid or expired media link"));
+    }
+    let object = state.media.store.get(&id).await?.ok_or_else(|| AppError::not_found("media not found"))?;
+    let max_age = (expires - Utc::now().timestamp()).max(0);
+    Ok((
+        [
+            (axum::http::header::CONTENT_TYPE, object.content_type),
+            (axum::http::header::CACHE_CONTROL, format!("private, max-age={}", max_age)),
+            (axum::http::header::X_CONTENT_TYPE_OPTIONS, "nosniff".to_string()),
+        ],
+        object.bytes,
+    )
+        .into_response())
+}
+
+pub async fn get_ai_job(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    jar: CookieJar,
+    Path(id): Path<String>,
+) -> Result<Json<AiJob>, AppError> {
+    let teacher_id = auth_teacher_id(&headers, &jar, &state).await
+        .ok_or_else(|| AppError::unauthorized("not logged in"))?;
+    // Other teachers' jobs look the same as missing ones.
+    state
+        .ai_jobs
+        .get(&id)
+        .filter(|job| job.teacher_id == teacher_id)
+        .map(Json)
+        .ok_or_else(|| AppError::not_found("ai job not found"))
+}
+
+// The caller's own AI usage per day (UTC) and how much of today's quota is left.
+pub async fn ai_usage(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    jar: CookieJar,
+    query: axum::extract::Query<UsageStatsQuery>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let teacher_id = auth_teacher_id(&headers, &jar, &state).await
+        .ok_or_else(|| AppError::unauthorized("not logged in"))?;
+    let days = query.days.unwrap_or(30).clamp(1, 90);
+    let since = (Utc::now().date_naive() - chrono::Duration::days(days - 1)).to_string();
+    let today = state.ai_usage_today(teacher_id).await;
+    let items: Vec<_> = state
+        .db
+        .ai_usage
+        .read()
+        .await
+        .get(&teacher_id)
+        .map(|log| {
+            log.range(since..)
+                .map(|(day, usage)| {
+                    json!({
+                        "date": day,
+                        "requests": usage.requests,
+                        "promptTokens": usage.prompt_tokens,
+                        "completionTokens": usage.completion_tokens,
+                    })
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    let quota = state.ai_quotas;
+    Ok(Json(json!({
+        "today": today,
+        "quota": quota,
+        "remaining": {
+            "requests": quota.daily_requests.map(|l| l.saturating_sub(today.requests)),
+            "tokens": quota.daily_tokens.map(|l| l.saturating_sub(today.tokens())),
+        },
+        "days": days,
+        "items": items,
+    })))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DistractorsPayload {
+    pub count: Option<usize>,
+    #[serde(default)]
+    pub provider: Option<AiProviderChoice>,
+}
+
+// Suggestions only: the quiz is not changed, the teacher adds the options they like via PUT.
+pub async fn ai_question_distractors(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    jar: CookieJar,
+    Path((quiz_id, question_id)): Path<(i64, String)>,
+    Json(payload): Json<DistractorsPayload>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let teacher_id = auth_teacher_id(&headers, &jar, &state).await
+        .ok_or_else(|| AppError::unauthorized("not logged in"))?;
+    let quiz = owned_quiz(&state, teacher_id, quiz_id).await?;
+    let question = quiz
+        .questions
+        .iter()
+        .find(|q| q.id == question_id)
+        .ok_or_else(|| AppError::not_found("question not found"))?;
+    // Never suggest more options than the question can still take.
+    let free_slots = state.quiz_limits.max_options.saturating_sub(question.options.as_ref().map_or(0, Vec::len));
+    let count = payload.count.unwrap_or(3);
+    if count == 0 || count > ai_providers::MAX_DISTRACTORS || count > free_slots {
+        return Err(AppError::validation("invalid distractor count")
+            .with_details(vec![ErrorDetail {
+                field: "count".into(),
+                issue: format!("must be between 1 and {}", ai_providers::MAX_DISTRACTORS.min(free_slots)),
+            }]));
+    }
+    let (ai_client, provider) = ai_client(&state, teacher_id, payload.provider.as_ref()).await?;
+    let system = state.db.prompt_set().await.get(PromptUseCase::Distractors);
+    let distractors =
+        ai_providers::generate_distractors(ai_client.as_ref(), provider, &state.quiz_limits, &system, question, count)
+            .await?;
+    state.record_usage("ai_distractors").await;
+    Ok(Json(json!({ "questionId": question.id, "distractors": distractors })))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FollowupQuizPayload {
+    #[serde(default, rename = "questionCount")]
+    pub question_count: Option<usize>,
+    #[serde(default)]
+    pub provider: Option<AiProviderChoice>,
+}
+
+// Most-missed questions handed to the model for a follow-up quiz.
+const MAX_FOLLOWUP_GAPS: usize = 5;
+
+// A remedial quiz on what the class got wrong: the most-missed questions of the session go to the model
+// as material, and the result is saved as the teacher's draft like any generated quiz.
+pub async fn session_followup_quiz(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    jar: CookieJar,
+    Path(id): Path<i64>,
+    Json(payload): Json<FollowupQuizPayload>,
+) -> Result<(StatusCode, Json<serde_json::Value>), AppError> {
+    let teacher_id = auth_teacher_id(&headers, &jar, &state).await
+        .ok_or_else(|| AppError::unauthorized("not logged in"))?;
+    let session = state.game_session(id).await?;
+    if session.teacher_id != teacher_id {
+        return Err(AppError::forbidden("access denied"));
+    }
+    let question_count = payload.question_count.unwrap_or(5);
+    if question_count == 0 || question_count > state.quiz_limits.max_questions {
+        return Err(AppError::validation("invalid question count")
+            .with_details(vec![ErrorDetail {
+                field: "questionCount".into(),
+                issue: format!("must be between 1 and {}", state.quiz_limits.max_questions),
+            }]));
+    }
+
+    // Students who missed each question, over every quiz of the playlist.
+    let mut missed_by: BTreeMap<(i64, &str), HashSet<&str>> = BTreeMap::new();
+    let segments = session.completed_segments.iter().map(|seg| (seg.quiz_id, &seg.mistakes));
+    for (quiz_id, mistakes) in segments.chain(std::iter::once((session.quiz_id, &session.mistakes))) {
+        for (nickname, questions) in mistakes {
+            for question_id in questions {
+                missed_by.entry((quiz_id, question_id.as_str())).or_default().insert(nickname.as_str());
+            }
+        }
+    }
+    let mut gaps: Vec<((i64, &str), usize)> = missed_by.into_iter().map(|(key, who)| (key, who.len())).collect();
+    gaps.sort_by_key(|gap| std::cmp::Reverse(gap.1));
+    gaps.truncate(MAX_FOLLOWUP_GAPS);
+    if gaps.is_empty() {
+        return Err(AppError::conflict("no mistakes to work on"));
+    }
+
+    let quizzes: HashMap<i64, QuizRecord> = {
+        let records = state.db.quizzes.read().await;
+        gaps.iter().filter_map(|((quiz_id, _), _)| Some((*quiz_id, records.get(quiz_id)?.clone()))).collect()
+    };
+    let missed: Vec<ai_providers::MissedQuestion> = gaps
+        .iter()
+        .filter_map(|((quiz_id, question_id), misses)| {
+            let question = quizzes.get(quiz_id)?.questions.iter().find(|q| q.id == *question_id)?;
+            let answered = session
+                .answers
+                .values()
+                .filter(|answers| answers.iter().any(|a| a.quiz_id == *quiz_id && a.question_id == *question_id))
+                .count();
+            let answered = answered.max(*misses);
+            Some(ai_providers::MissedQuestion { quiz_id: *quiz_id, question, misses: *misses, answered })
+        })
+        .collect();
+    // Questions deleted from their quiz since the session leave nothing to work from.
+    if missed.is_empty() {
+        return Err(AppError::conflict("no mistakes to work on"));
+    }
+
+    let main_quiz = quizzes.get(&session.quiz_id).or_else(|| quizzes.values().next());
+    let title = main_quiz.map_or_else(String::new, |quiz| quiz.title.clone());
+    let (ai_client, provider) = ai_client(&state, teacher_id, payload.provider.as_ref()).await?;
+    let request = AiRequest {
+        topic: format!("Работа над ошибками: {}", title),
+        grade: main_quiz.and_then(|quiz| quiz.grade).map(|grade| grade.to_string()),
+        question_count,
+        source_text: Some(ai_providers::mistakes_material(&title, &missed)),
+        repair_attempts: state.ai.repair_attempts(),
+        difficulty_mix: Vec::new(),
+        type_mix: Vec::new(),
+    };
+    let quiz = ai_providers::generate_quiz(
+        ai_client.as_ref(),
+        provider,
+        &state.quiz_schemas,
+        &state.quiz_limits,
+        &state.moderation,
+        &state.db.prompt_set().await,
+        &request,
+    )
+    .await?;
+    let quiz_id = state.create_quiz(teacher_id, quiz, None).await?;
+    state.record_usage("ai_followup_quiz").await;
+    let gaps: Vec<_> = missed
+        .iter()
+        .map(|item| {
+            json!({
+                "quizId": item.quiz_id,
+                "questionId": item.question.id,
+                "prompt": item.question.prompt,
+                "misses": item.misses,
+                "answered": item.answered,
+                "tags": item.question.tags
+            })
+        })
+        .collect();
+    Ok((StatusCode::CREATED, Json(json!({ "quizId": quiz_id, "source": "ai", "gaps": gaps }))))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ClassPayload {
+    pub name: String,
+    #[serde(default)]
+    pub grade: Option<u8>,
+    #[serde(default)]
+    pub roster: Vec<RosterEntry>,
+}
+
+const MAX_CLASS_NAME_CHARS: usize = 100;
+const MAX_ROSTER_SIZE: usize = 200;
+
+// Cleans up a class payload: trimmed names, roster members unique by nickname, accounts that exist. A
+// member given only by account plays under the account's display name.
+async fn class_fields(
+    state: &AppState,
+    payload: ClassPayload,
+) -> Result<(String, Option<u8>, Vec<RosterEntry>), AppError> {
+    let invalid = |message: String| AppError::validation(message);
+    let name = payload.name.trim().to_string();
+    if name.is_empty() || name.chars().count() > MAX_CLASS_NAME_CHARS {
+        return Err(invalid(format!("name must be 1 to {} characters long", MAX_CLASS_NAME_CHARS)));
+    }
+    if payload.grade.is_some_and(|grade| !GRADE_RANGE.contains(&grade)) {
+        return Err(invalid(format!("grade must be between {} and {}", GRADE_RANGE.start(), GRADE_RANGE.end())));
+    }
+    if payload.roster.len() > MAX_ROSTER_SIZE {
+        return Err(invalid(format!("a class has at most {} students", MAX_ROSTER_SIZE)));
+    }
+    let students = state.db.students.read().await;
+    let mut roster: Vec<RosterEntry> = Vec::with_capacity(payload.roster.len());
+    for member in payload.roster {
+        let mut nickname = member.nickname.trim().to_string();
+        if let Some(student_id) = member.student_id {
+            let Some(student) = students.get(&student_id) else {
+                return Err(invalid(format!("unknown student {}", student_id)));
+            };
+            if nickname.is_empty() {
+                nickname = student.display_name.clone();
+            }
+        }
+        if nickname.is_empty() || nickname.chars().count() > NICKNAME_LENGTH_LIMIT {
+            return Err(invalid(format!("roster nicknames must be 1 to {} characters long", NICKNAME_LENGTH_LIMIT)));
+        }
+        if roster.iter().any(|other| other.nickname.to_lowercase() == nickname.to_lowercase()) {
+            return Err(invalid(format!("{} is on the roster twice", nickname)));
+        }
+        roster.push(RosterEntry { nickname, student_id: member.student_id });
+    }
+    Ok((name, payload.grade, roster))
+}
+
+async fn owned_class(state: &AppState, teacher_id: i64, id: i64) -> Result<ClassRecord, AppError> {
+    let class = state.db.classes.read().await.get(&id).cloned().or_not_found("class")?;
+    if class.teacher_id != teacher_id {
+        return Err(AppError::forbidden("access denied"));
+    }
+    Ok(class)
+}
+
+fn class_json(class: &ClassRecord) -> serde_json::Value {
+    json!({
+        "id": class.id,
+        "name": class.name,
+        "grade": class.grade,
+        "roster": class.roster,
+        "createdAt": class.created_at.to_rfc3339()
+    })
+}
+
+async fn save_class(state: &AppState, class: ClassRecord) -> Result<(), AppError> {
+    state.db.classes.write().await.insert(class.id, class.clone());
+    state.log_change(JournalOp::ClassUpserted { class }).await;
+    state.commit_changes().map_err(AppError::from)
+}
+
+pub async fn create_class(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    jar: CookieJar,
+    Json(payload): Json<ClassPayload>,
+) -> Result<(StatusCode, Json<serde_json::Value>), AppError> {
+    let teacher_id = auth_teacher_id(&headers, &jar, &state).await
+        .ok_or_else(|| AppError::unauthorized("not logged in"))?;
+    let (name, grade, roster) = class_fields(&state, payload).await?;
+    let class = ClassRecord { id: state.db.next_class_id(), teacher_id, name, grade, roster, created_at: Utc::now() };
+    save_class(&state, class.clone()).await?;
+    Ok((StatusCode::CREATED, Json(class_json(&class))))
+}
+
+pub async fn list_classes(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    jar: CookieJar,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let teacher_id = auth_teacher_id(&headers, &jar, &state).await
+        .ok_or_else(|| AppError::unauthorized("not logged in"))?;
+    let classes = state.db.classes.read().await;
+    let mut owned: Vec<&ClassRecord> = classes.values().filter(|c| c.teacher_id == teacher_id).collect();
+    owned.sort_by(|a, b| a.name.cmp(&b.name).then(a.id.cmp(&b.id)));
+    let items: Vec<_> = owned.into_iter().map(class_json).collect();
+    Ok(Json(json!({ "items": items, "total": items.len() })))
+}
+
+pub async fn get_class(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    jar: CookieJar,
+    Path(id): Path<i64>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let teacher_id = auth_teacher_id(&headers, &jar, &state).await
+        .ok_or_else(|| AppError::unauthorized("not logged in"))?;
+    let class = owned_class(&state, teacher_id, id).await?;
+    Ok(Json(class_json(&class)))
+}
+
+// Replaces the name, grade and roster; sessions already bound keep the roster they were created with.
+pub async fn update_class(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    jar: CookieJar,
+    Path(id): Path<i64>,
+    Json(payload): Json<ClassPayload>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let teacher_id = auth_teacher_id(&headers, &jar, &state).await
+        .ok_or_else(|| AppError::unauthorized("not logged in"))?;
+    let existing = owned_class(&state, teacher_id, id).await?;
+    let (name, grade, roster) = class_fields(&state, payload).await?;
+    let class = ClassRecord { name, grade, roster, ..existing };
+    save_class(&state, class.clone()).await?;
+    Ok(Json(class_json(&class)))
+}
+
+pub async fn delete_class(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    jar: CookieJar,
+    Path(id): Path<i64>,
+) -> Result<StatusCode, AppError> {
+    let teacher_id = auth_teacher_id(&headers, &jar, &state).await
+        .ok_or_else(|| AppError::unauthorized("not logged in"))?;
+    owned_class(&state, teacher_id, id).await?;
+    state.db.classes.write().await.remove(&id);
+    state.log_change(JournalOp::ClassDeleted { class_id: id }).await;
+    state.commit_changes()?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateSessionPayload {
+    #[serde(rename = "quizId")]
+    pub quiz_id: i64,
+    #[serde(rename = "gameMode")]
+    pub game_mode: String,
+    #[serde(default)]
+    pub playlist: Vec<i64>,
+    #[serde(default)]
+    pub pacing: SessionPacing,
+    #[serde(default)]
+    pub lobby: LobbySettings,
+    // Replaces the mode's preset as a whole; fields left out take the classic values.
+    #[serde(default, rename = "gameConfig")]
+    pub game_config: Option<GameModeConfig>,
+    // Makes the session homework: it starts right away and is played over REST until the window closes.
+    #[serde(default)]
+    pub homework: Option<HomeworkWindow>,
+    // The room waits for this moment and then starts on its own; the teacher may still start it earlier.
+    #[serde(default, rename = "startsAt")]
+    pub starts_at: Option<chrono::DateTime<Utc>>,
+    // Binds the session to one of the teacher's classes; results then list who was absent.
+    #[serde(default, rename = "classId")]
+    pub class_id: Option<i64>,
+}
+
+pub async fn create_session(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    jar: CookieJar,
+    Json(payload): Json<CreateSessionPayload>,
+) -> Result<(StatusCode, Json<serde_json::Value>), AppError> {
+    let teacher_id = auth_teacher_id(&headers, &jar, &state).await
+        .ok_or_else(|| AppError::unauthorized("not logged in"))?;
+    let Some(preset) = state.game_modes.get(&payload.game_mode).map(|mode| mode.preset()) else {
+        return Err(AppError::validation("unsupported game mode"));
+    };
+    let game_config = payload.game_config.clone().unwrap_or(preset);
+    if let Err(message) = payload.lobby.validate().and_then(|()| game_config.validate()) {
+        return Err(AppError::validation(message));
+    }
+    if let Some(homework) = &payload.homework {
+        let problem = if payload.pacing == SessionPacing::Host {
+            Some("homework is self-paced".to_string())
+        } else if !payload.playlist.is_empty() {
+            Some("homework plays a single quiz".to_string())
+        } else {
+            homework.validate(Utc::now()).err()
+        };
+        if let Some(message) = problem {
+            return Err(AppError::validation(message));
+        }
+    }
+    if let Some(starts_at) = payload.starts_at {
+        let now = Utc::now();
+        let problem = if payload.homework.is_some() {
+            Some("homework starts when it opens".to_string())
+        } else if starts_at <= now {
+            Some("startsAt must be in the future".to_string())
+        } else if starts_at - now > state.room_code_ttl {
+            // The room code would expire before the start.
+            Some(format!("startsAt must be within {} hours", state.room_code_ttl.num_hours()))
+        } else {
+            None
+        };
+        if let Some(message) = problem {
+            return Err(AppError::validation(message));
+        }
+    }
+    if !state.admission.session_slot_available(state.active_session_count().await) {
+        state.admission.sessions_rejected.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        return Err(AppError::unavailable("ROOM_UNAVAILABLE", "active session limit reached, try again later"));
+    }
+    let roster = match payload.class_id {
+        Some(class_id) => owned_class(&state, teacher_id, class_id).await?.roster,
+        None => Vec::new(),
+    };
+    let mut playlist = vec![payload.quiz_id];
+    playlist.extend(payload.playlist.iter().copied());
+    {
+        let quizzes = state.db.quizzes.read().await;
+        for quiz_id in &playlist {
+            let quiz = quizzes.get(quiz_id).ok_or_else(|| AppError::not_found(format!("quiz {} not found", quiz_id)))?;
+            // Every quiz in the playlist, not only the first: another teacher's quiz only once it is published.
+            if quiz.owner_teacher_id != teacher_id && !quiz.is_published {
+                return Err(AppError::forbidden(format!("quiz {} is not yours and not published", quiz_id)));
+            }
+        }
+    }
+
+    let id = state.db.next_game_session_id();
+    let Some(room_code) = state.allocate_room(id).await? else {
+        return Err(AppError::unavailable("ROOM_UNAVAILABLE", "no free room code, try again later"));
+    };
+    let join_token = uuid::Uuid::new_v4().to_string();
+    let session_game_mode = payload.game_mode.clone();
+    let session_playlist_len = playlist.len();
+
+    let session = SessionRecord {
+        id,
+        room_code: room_code.clone(),
+        join_token: join_token.clone(),
         quiz_id: payload.quiz_id,
         teacher_id,
-        status: "waiting".into(),
+        status: if payload.homework.is_some() { "active".into() } else { "waiting".into() },
         game_mode: payload.game_mode,
         participants: HashMap::new(),
         stats: HashMap::new(),
         mistakes: HashMap::new(),
+        playlist,
+        playlist_position: 0,
+        completed_segments: Vec::new(),
+        answers: HashMap::new(),
+        review_until: None,
+        hints: HashMap::new(),
+        kicked: Vec::new(),
+        pacing: payload.pacing,
+        host_question: None,
+        paused_at: None,
+        lobby: payload.lobby.clone(),
+        game_config: Some(game_config.clone()),
+        homework: payload.homework.clone(),
+        practice_started_at: None,
+        starts_at: payload.starts_at,
+        review_position: None,
+        integrity: HashMap::new(),
+        disruptions: HashMap::new(),
+        created_at: Some(Utc::now()),
+        // Homework runs from the start.
+        started_at: payload.homework.is_some().then(Utc::now),
+        ended_at: None,
+        class_id: payload.class_id,
+        roster,
+        lti_grades: HashMap::new(),
+    };
+    state.db.game_sessions.write().await.insert(id, session);
+    state.db.versions.bump_sessions();
+    state.db.broadcasters.insert(room_code.clone(), RoomChannels::default());
+    if let Some(starts_at) = payload.starts_at {
+        state.schedule_start(id, starts_at);
+        state.remind_scheduled_start(id, starts_at);
+    }
+
+    state.record_usage(&format!("game_mode:{}", session_game_mode)).await;
+    if session_playlist_len > 1 {
+        state.record_usage("playlist_session").await;
+    }
+    state.audit(Some(teacher_id), "session.create", Some(id.to_string())).await;
+
+    let join_url = join_url(&room_code, &join_token);
+    Ok((
+        StatusCode::CREATED,
+        Json(json!({
+            "sessionId": id,
+            "roomCode": room_code,
+            "joinToken": join_token,
+            "pacing": payload.pacing,
+            "lobby": payload.lobby,
+            "gameConfig": game_config,
+            "homework": payload.homework,
+            "startsAt": payload.starts_at.map(|at| at.to_rfc3339()),
+            "classId": payload.class_id,
+            "joinUrl": join_url,
+            "qrPayload": join_url
+        })),
+    ))
+}
+
+// The room code alone is guessable; the link carries the session's join token as well.
+fn join_url(room_code: &str, join_token: &str) -> String {
+    format!("http://localhost:5173/join?room={room_code}&token={join_token}")
+}
+
+// Issues a new join token, so links shared earlier stop admitting anyone. Students already in the
+// room stay connected.
+pub async fn rotate_join_token(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    jar: CookieJar,
+    Path(id): Path<i64>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let teacher_id = auth_teacher_id(&headers, &jar, &state).await
+        .ok_or_else(|| AppError::unauthorized("not logged in"))?;
+    let mut sessions = state.db.game_sessions.write().await;
+    let session = sessions
+        .get_mut(&id)
+        .or_not_found("session")?;
+    if session.teacher_id != teacher_id {
+        return Err(AppError::forbidden("access denied"));
+    }
+    if session.status == "finished" {
+        return Err(AppError::conflict("session is finished"));
+    }
+    session.join_token = uuid::Uuid::new_v4().to_string();
+    let join_url = join_url(&session.room_code, &session.join_token);
+    Ok(Json(json!({ "joinToken": session.join_token, "joinUrl": join_url, "qrPayload": join_url })))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SessionListQuery {
+    #[serde(rename = "quizId")]
+    pub quiz_id: Option<i64>,
+    pub status: Option<String>,
+    #[serde(default = "default_page")]
+    pub page: usize,
+    #[serde(default = "default_page_limit")]
+    pub limit: usize,
+}
+
+fn default_page() -> usize {
+    1
+}
+
+fn default_page_limit() -> usize {
+    20
+}
+
+const MAX_PAGE_LIMIT: usize = 100;
+
+pub async fn list_sessions(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    jar: CookieJar,
+    query: axum::extract::Query<SessionListQuery>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let teacher_id = auth_teacher_id(&headers, &jar, &state).await
+        .ok_or_else(|| AppError::unauthorized("not logged in"))?;
+    if query.page == 0 || query.limit == 0 || query.limit > MAX_PAGE_LIMIT {
+        let message = format!("page must be at least 1 and limit within 1..={}", MAX_PAGE_LIMIT);
+        return Err(AppError::validation(message));
+    }
+    let sessions = state.db.game_sessions.read().await;
+    // Finished sessions come from the persisted storage, so the history survives restarts.
+    let mut owned: Vec<&SessionRecord> = sessions
+        .values()
+        .filter(|s| s.teacher_id == teacher_id)
+        .filter(|s| query.quiz_id.is_none_or(|quiz_id| s.quiz_id == quiz_id || s.playlist.contains(&quiz_id)))
+        .filter(|s| query.status.as_deref().is_none_or(|status| s.status == status))
+        .collect();
+    owned.sort_by_key(|s| std::cmp::Reverse(s.id));
+    let total = owned.len();
+    let items: Vec<_> = owned
+        .into_iter()
+        .skip((query.page - 1) * query.limit)
+        .take(query.limit)
+        .map(|s| {
+            let all_stats = || s.stats.values().chain(s.completed_segments.iter().flat_map(|seg| seg.stats.values()));
+            let correct: u32 = all_stats().map(|st| st.correct).sum();
+            let wrong: u32 = all_stats().map(|st| st.wrong).sum();
+            let total = correct + wrong;
+            let correct_pct = if total == 0 { 0.0 } else { correct as f64 * 100.0 / total as f64 };
+            // Per student over every quiz of the playlist.
+            let mut scores: HashMap<&str, f64> = HashMap::new();
+            for st in all_stats() {
+                *scores.entry(st.nickname.as_str()).or_default() += st.score;
+            }
+            let average_score = (!scores.is_empty()).then(|| scores.values().sum::<f64>() / scores.len() as f64);
+            json!({
+                "id": s.id,
+                "roomCode": s.room_code,
+                "quizId": s.quiz_id,
+                "playlist": s.playlist,
+                "status": s.status,
+                "gameMode": s.game_mode,
+                "participantCount": s.participants.len(),
+                "correctPct": correct_pct,
+                "averageScore": average_score,
+                "createdAt": s.created_at.map(|t| t.to_rfc3339()),
+                "startedAt": s.started_at.map(|t| t.to_rfc3339()),
+                "endedAt": s.ended_at.map(|t| t.to_rfc3339())
+            })
+        })
+        .collect();
+    Ok(Json(json!({ "items": items, "total": total, "page": query.page, "limit": query.limit })))
+}
+
+async fn require_admin(headers: &HeaderMap, jar: &CookieJar, state: &AppState) -> Result<i64, AppError> {
+    if headers.contains_key(API_KEY_HEADER) {
+        return Err(AppError::forbidden("admin only"));
+    }
+    let teacher_id = auth_teacher_id(headers, jar, state).await
+        .ok_or_else(|| AppError::unauthorized("not logged in"))?;
+    if !state.is_admin(teacher_id).await {
+        return Err(AppError::forbidden("admin only"));
+    }
+    Ok(teacher_id)
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CapacityQuery {
+    pub planned_sessions: Option<usize>,
+    pub planned_participants: Option<usize>,
+}
+
+pub async fn admin_capacity(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    jar: CookieJar,
+    query: axum::extract::Query<CapacityQuery>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    require_admin(&headers, &jar, &state).await?;
+    let admission = &state.admission;
+    let limit = |v: usize| if v == 0 { json!(null) } else { json!(v) };
+    let mut body = json!({
+        "limits": {
+            "maxWsConnections": limit(admission.max_ws_connections),
+            "maxActiveSessions": limit(admission.max_active_sessions),
+            "queueTimeoutSec": admission.queue_timeout.as_secs(),
+        },
+        "current": {
+            "wsConnections": admission.ws_connections(),
+            "activeSessions": state.active_session_count().await,
+            "queueLength": admission.queue_len(),
+        },
+        "rejected": {
+            "wsConnections": admission.ws_rejected.load(std::sync::atomic::Ordering::Relaxed),
+            "sessions": admission.sessions_rejected.load(std::sync::atomic::Ordering::Relaxed),
+        },
+    });
+    if query.planned_sessions.is_some() || query.planned_participants.is_some() {
+        let sessions = query.planned_sessions.unwrap_or(1);
+        let participants = query.planned_participants.unwrap_or(0);
+        // Every student and every teacher screen holds one WebSocket.
+        let ws_needed = participants + sessions;
+        let ws_fits = admission.max_ws_connections == 0 || ws_needed <= admission.max_ws_connections;
+        let sessions_fit = admission.max_active_sessions == 0 || sessions <= admission.max_active_sessions;
+        body["plan"] = json!({
+            "sessions": sessions,
+            "participants": participants,
+            "wsConnectionsNeeded": ws_needed,
+            "fits": ws_fits && sessions_fit,
+        });
+    }
+    Ok(Json(body))
+}
+
+pub async fn admin_metrics(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    jar: CookieJar,
+) -> Result<Json<serde_json::Value>, AppError> {
+    require_admin(&headers, &jar, &state).await?;
+    let mut rooms: Vec<_> = state
+        .db
+        .broadcasters
+        .iter()
+        .map(|room| {
+            json!({
+                "roomCode": room.key(),
+                "participants": room.participant_count(),
+                "capacity": room.capacity(),
+                "lagged": room.lagged.load(std::sync::atomic::Ordering::Relaxed),
+            })
+        })
+        .collect();
+    rooms.sort_by(|a, b| a["roomCode"].as_str().cmp(&b["roomCode"].as_str()));
+    Ok(Json(json!({ "answerPipeline": state.metrics.answer_pipeline.summary(), "rooms": rooms })))
+}
+
+// Prometheus scrape target, for internal networks only (METRICS_ALLOWED_IPS, loopback by default).
+pub async fn prometheus_metrics(State(state): State<AppState>, request: Request) -> Result<Response, AppError> {
+    let headers = request.headers();
+    if !state.web.may_scrape_metrics(web::peer_ip(request.extensions()), headers) {
+        return Err(AppError::forbidden("metrics are internal"));
+    }
+    for room in state.db.broadcasters.iter() {
+        let lagged = room.lagged.load(std::sync::atomic::Ordering::Relaxed);
+        crate::metrics::record_room(room.key(), room.socket_count(), lagged);
+    }
+    let content_type = [(axum::http::header::CONTENT_TYPE, "text/plain; version=0.0.4")];
+    Ok((content_type, crate::metrics::render_prometheus()).into_response())
+}
+
+pub async fn admin_password_reset(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    jar: CookieJar,
+    Json(payload): Json<PasswordResetRequest>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let admin_id = require_admin(&headers, &jar, &state).await?;
+    let (token, expires_at) = issue_reset_token(&state, &payload.login)
+        .await?
+        .ok_or_else(|| AppError::not_found("teacher not found"))?;
+    info!("admin {} issued a password reset code for {}", admin_id, payload.login.trim());
+    state.audit(Some(admin_id), "admin.password_reset", Some(payload.login.trim().to_string())).await;
+    Ok(Json(json!({ "login": payload.login.trim(), "resetToken": token, "expiresAt": expires_at.to_rfc3339() })))
+}
+
+pub async fn admin_export(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    jar: CookieJar,
+) -> Result<Response, AppError> {
+    let admin_id = require_admin(&headers, &jar, &state).await?;
+    let archive = backup::export(&state.db).await;
+    let body = serde_json::to_vec(&archive)
+        .map_err(|_| AppError::internal("export failed"))?;
+    let filename = format!("quiz-backup-{}.json", archive.exported_at.format("%Y%m%d-%H%M%S"));
+    info!(
+        "admin export: {} teachers, {} quizzes, {} sessions",
+        archive.teachers.len(),
+        archive.quizzes.len(),
+        archive.sessions.len()
+    );
+    state.audit(Some(admin_id), "admin.export", None).await;
+    Ok((
+        [
+            (axum::http::header::CONTENT_TYPE, "application/json".to_string()),
+            (axum::http::header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", filename)),
+        ],
+        body,
+    )
+        .into_response())
+}
+
+pub async fn admin_import(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    jar: CookieJar,
+    Json(archive): Json<backup::BackupArchive>,
+) -> Result<Json<backup::ImportReport>, AppError> {
+    let admin_id = require_admin(&headers, &jar, &state).await?;
+    if let Err(issues) = backup::validate(&archive, &state.quiz_schemas, &state.quiz_limits) {
+        return Err(AppError::validation("backup validation failed")
+            .with_details(issues.into_iter().map(|i| ErrorDetail { field: i.field, issue: i.issue }).collect()));
+    }
+    let report = backup::import(&state.db, archive).await;
+    for (quiz_id, owner) in &report.imported_quizzes {
+        state.record_quiz_revision(*quiz_id, *owner, "imported", None).await;
+    }
+    info!("admin {} imported backup: {:?}", admin_id, report);
+    state.audit(Some(admin_id), "admin.import", None).await;
+    // Imports are too large to journal record by record, so write the snapshot right away.
+    state.persist_core_data().await?;
+    Ok(Json(report))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UsageStatsQuery {
+    pub days: Option<i64>,
+}
+
+// Point-in-time counts for operational dashboards; practice runs are not sessions here.
+pub async fn admin_stats(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    jar: CookieJar,
+) -> Result<Json<serde_json::Value>, AppError> {
+    require_admin(&headers, &jar, &state).await?;
+    let teachers = state.db.teachers.read().await.len();
+    let (quizzes, published) = {
+        let quizzes = state.db.quizzes.read().await;
+        (quizzes.len(), quizzes.values().filter(|q| q.is_published).count())
+    };
+    let (active, finished) = {
+        let sessions = state.db.game_sessions.read().await;
+        let played: Vec<&SessionRecord> = sessions.values().filter(|s| s.practice_started_at.is_none()).collect();
+        let finished = played.iter().filter(|s| s.status == "finished").count();
+        (played.len() - finished, finished)
+    };
+    let today = Utc::now().date_naive().to_string();
+    let (ai_requests, ai_tokens) = state
+        .db
+        .ai_usage
+        .read()
+        .await
+        .values()
+        .filter_map(|days| days.get(&today))
+        .fold((0, 0), |(requests, tokens), day| {
+            (requests + day.requests, tokens + day.prompt_tokens + day.completion_tokens)
+        });
+    Ok(Json(json!({
+        "teachers": teachers,
+        "quizzes": { "total": quizzes, "published": published },
+        "sessions": { "active": active, "finished": finished },
+        "wsConnections": state.admission.ws_connections(),
+        "aiToday": { "requests": ai_requests, "tokens": ai_tokens },
+        "storage": {
+            "snapshot": state.store.kind(),
+            "shared": state.shared.kind(),
+            "media": state.media.store.kind()
+        },
+        "generatedAt": Utc::now().to_rfc3339(),
+    })))
+}
+
+pub async fn admin_audit(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    jar: CookieJar,
+    query: axum::extract::Query<crate::audit::AuditQuery>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    require_admin(&headers, &jar, &state).await?;
+    let log = state.db.audit_log.read().await;
+    let items = query.select(&log);
+    Ok(Json(json!({ "items": items, "count": items.len() })))
+}
+
+pub async fn admin_usage_stats(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    jar: CookieJar,
+    query: axum::extract::Query<UsageStatsQuery>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    require_admin(&headers, &jar, &state).await?;
+    let days = query.days.unwrap_or(30).clamp(1, 365);
+    let since = (Utc::now().date_naive() - chrono::Duration::days(days - 1)).to_string();
+    let stats = state.db.usage_stats.read().await;
+    let mut totals: std::collections::BTreeMap<String, u64> = std::collections::BTreeMap::new();
+    let items: Vec<_> = stats
+        .range(since..)
+        .map(|(day, features)| {
+            for (feature, count) in features {
+                *totals.entry(feature.clone()).or_default() += count;
+            }
+            json!({ "date": day, "features": features })
+        })
+        .collect();
+    Ok(Json(json!({ "enabled": state.usage_stats_enabled, "days": days, "items": items, "totals": totals })))
+}
+
+fn prompt_use_case(raw: &str) -> Result<PromptUseCase, AppError> {
+    PromptUseCase::parse(raw)
+        .ok_or_else(|| AppError::not_found("unknown prompt use case"))
+}
+
+fn prompt_template_out(use_case: PromptUseCase, versions: &[PromptTemplateVersion]) -> serde_json::Value {
+    let active = versions.last();
+    json!({
+        "useCase": use_case.name(),
+        "variables": use_case.variables(),
+        "version": active.map_or(0, |v| v.version),
+        "text": active.map_or(use_case.default_text(), |v| v.text.as_str()),
+        "updatedAt": active.map(|v| v.created_at),
+        "updatedBy": active.map(|v| v.created_by),
+    })
+}
+
+pub async fn admin_list_prompts(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    jar: CookieJar,
+) -> Result<Json<serde_json::Value>, AppError> {
+    require_admin(&headers, &jar, &state).await?;
+    let templates = state.db.prompt_templates.read().await;
+    let items: Vec<_> = PromptUseCase::ALL
+        .into_iter()
+        .map(|u| prompt_template_out(u, templates.get(&u).map_or(&[][..], Vec::as_slice)))
+        .collect();
+    Ok(Json(json!({ "items": items })))
+}
+
+pub async fn admin_get_prompt(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    jar: CookieJar,
+    Path(use_case): Path<String>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    require_admin(&headers, &jar, &state).await?;
+    let use_case = prompt_use_case(&use_case)?;
+    let templates = state.db.prompt_templates.read().await;
+    let versions = templates.get(&use_case).map_or(&[][..], Vec::as_slice);
+    let mut out = prompt_template_out(use_case, versions);
+    out["defaultText"] = json!(use_case.default_text());
+    out["versions"] = json!(versions.iter().rev().collect::<Vec<_>>());
+    Ok(Json(out))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PromptTemplatePayload {
+    pub text: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PromptRevertPayload {
+    pub version: u32,
+}
+
+async fn save_prompt_version(
+    state: &AppState,
+    teacher_id: i64,
+    use_case: PromptUseCase,
+    text: String,
+) -> Result<Json<serde_json::Value>, AppError> {
+    prompt_templates::validate_template(use_case, &text).map_err(|issues| {
+        AppError::validation("invalid prompt template")
+            .with_details(issues.into_iter().map(|i| ErrorDetail { field: i.field, issue: i.issue }).collect())
+    })?;
+    let version = state.db.add_prompt_version(use_case, text, teacher_id).await;
+    state.log_change(JournalOp::PromptTemplateSaved { use_case, version: version.clone() }).await;
+    state.commit_changes()?;
+    Ok(Json(prompt_template_out(use_case, &[version])))
+}
+
+// Every save is a new version; the previous ones stay available for `revert`.
+pub async fn admin_update_prompt(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    jar: CookieJar,
+    Path(use_case): Path<String>,
+    Json(payload): Json<PromptTemplatePayload>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let teacher_id = require_admin(&headers, &jar, &state).await?;
+    let use_case = prompt_use_case(&use_case)?;
+    let saved = save_prompt_version(&state, teacher_id, use_case, payload.text).await?;
+    state.audit(Some(teacher_id), "admin.prompt_update", Some(use_case.name().to_string())).await;
+    Ok(saved)
+}
+
+// Saves the text of an older version (0 — the built-in one) as the newest version.
+pub async fn admin_revert_prompt(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    jar: CookieJar,
+    Path(use_case): Path<String>,
+    Json(payload): Json<PromptRevertPayload>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let teacher_id = require_admin(&headers, &jar, &state).await?;
+    let use_case = prompt_use_case(&use_case)?;
+    let text = if payload.version == 0 {
+        use_case.default_text().to_string()
+    } else {
+        let templates = state.db.prompt_templates.read().await;
+        templates
+            .get(&use_case)
+            .and_then(|versions| versions.iter().find(|v| v.version == payload.version))
+            .map(|v| v.text.clone())
+            .ok_or_else(|| AppError::not_found("prompt version not found"))?
+    };
+    let saved = save_prompt_version(&state, teacher_id, use_case, text).await?;
+    state.audit(Some(teacher_id), "admin.prompt_revert", Some(use_case.name().to_string())).await;
+    Ok(saved)
+}
+
+pub async fn start_session(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    jar: CookieJar,
+    Path(id): Path<i64>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let teacher_id = auth_teacher_id(&headers, &jar, &state).await
+        .ok_or_else(|| AppError::unauthorized("not logged in"))?;
+    let (room_code, game_mode) = {
+        let mut sessions = state.db.game_sessions.write().await;
+        let session = sessions
+            .get_mut(&id)
+            .or_not_found("session")?;
+        if session.teacher_id != teacher_id {
+            return Err(AppError::forbidden("access denied"));
+        }
+        session.status = "active".into();
+        session.started_at.get_or_insert_with(Utc::now);
+        (session.room_code.clone(), session.game_mode.clone())
+    };
+
+    if let Some(sender) = state.db.broadcasters.get(&room_code) {
+        let _ = sender.send(WsEnvelope::new(
+            ServerEvent::StartQuiz,
+            json!({ "sessionId": id, "gameMode": game_mode, "startedAt": Utc::now().to_rfc3339() }),
+            None,
+        ));
+    }
+    state.audit(Some(teacher_id), "session.start", Some(id.to_string())).await;
+    Ok(Json(json!({ "status": "active" })))
+}
+
+pub async fn pause_session(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    jar: CookieJar,
+    Path(id): Path<i64>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    change_pause(state, headers, jar, id, true).await
+}
+
+pub async fn resume_session(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    jar: CookieJar,
+    Path(id): Path<i64>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    change_pause(state, headers, jar, id, false).await
+}
+
+async fn change_pause(
+    state: AppState,
+    headers: HeaderMap,
+    jar: CookieJar,
+    id: i64,
+    pause: bool,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let teacher_id = auth_teacher_id(&headers, &jar, &state).await
+        .ok_or_else(|| AppError::unauthorized("not logged in"))?;
+    {
+        let sessions = state.db.game_sessions.read().await;
+        let session = sessions.get(&id).or_not_found("session")?;
+        if session.teacher_id != teacher_id {
+            return Err(AppError::forbidden("access denied"));
+        }
+    }
+    set_paused(&state, id, pause, None).await.map_err(|status| {
+        AppError::conflict(format!("session is {}", status))
+    })?;
+    let action = if pause { "session.pause" } else { "session.resume" };
+    state.audit(Some(teacher_id), action, Some(id.to_string())).await;
+    Ok(Json(json!({ "status": if pause { "paused" } else { "active" } })))
+}
+
+// Pauses an active session or resumes a paused one and tells the room; Err carries the status that
+// did not allow it. Deadlines stand still while paused: timers that fire meanwhile do nothing, and on
+// resume every open deadline moves on by the pause and gets a fresh timer.
+async fn set_paused(state: &AppState, session_id: i64, pause: bool, request_id: Option<String>) -> Result<(), String> {
+    let mut sessions = state.db.game_sessions.write().await;
+    let Some(session) = sessions.get_mut(&session_id) else { return Err("gone".into()) };
+    let from = if pause { "active" } else { "paused" };
+    if session.status != from {
+        return Err(session.status.clone());
+    }
+    let now = Utc::now();
+    let room_code = session.room_code.clone();
+    let mut payload = json!({ "sessionId": session.id, "at": now.to_rfc3339() });
+    if pause {
+        session.status = "paused".into();
+        session.paused_at = Some(now);
+        if let Some(bc) = state.db.broadcasters.get(&room_code) {
+            let _ = bc.send(WsEnvelope::new(ServerEvent::SessionPaused, payload, request_id));
+        }
+        return Ok(());
+    }
+
+    session.status = "active".into();
+    let paused_for = session.paused_at.take().map_or_else(chrono::Duration::zero, |at| now - at);
+    let mut timers = Vec::new();
+    for p in session.participants.values_mut() {
+        if let Some((question_id, deadline)) = p.question_deadline.as_mut() {
+            *deadline += paused_for;
+            if p.pending_question.as_deref() == Some(question_id.as_str()) {
+                timers.push((p.nickname.clone(), question_id.clone(), *deadline));
+            }
+        }
+        // The pause does not count against the speed bonus either.
+        if let Some(pushed_at) = p.question_pushed_at.as_mut() {
+            *pushed_at += paused_for;
+        }
+    }
+    // Nor as time away for a participant who was disconnected during it.
+    for disruption in session.disruptions.values_mut().filter_map(|d| d.last_mut()) {
+        if disruption.reconnected_at.is_none() {
+            disruption.disconnected_at = (disruption.disconnected_at + paused_for).min(now);
+        }
+    }
+    payload["pausedMs"] = json!(paused_for.num_milliseconds());
+    match session.host_question.as_mut().filter(|open| !open.closed) {
+        Some(open) => {
+            open.opened_at += paused_for;
+            open.deadline += paused_for;
+            payload["deadline"] = json!(open.deadline.to_rfc3339());
+            payload["remainingMs"] = json!(remaining_ms(open.deadline));
+            spawn_host_question_timer(state, session_id, &room_code, open.position, open.deadline);
+        }
+        // Host-paced questions are closed for everyone at once, not timed out one by one.
+        None if session.pacing == SessionPacing::SelfPaced => {
+            for (nickname, question_id, deadline) in timers {
+                spawn_question_timer(state, session_id, &room_code, &nickname, &question_id, deadline);
+            }
+        }
+        None => {}
+    }
+    if let Some(bc) = state.db.broadcasters.get(&room_code) {
+        let _ = bc.send(WsEnvelope::new(ServerEvent::SessionResumed, payload, request_id));
+    }
+    Ok(())
+}
+
+pub async fn next_playlist_quiz(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    jar: CookieJar,
+    Path(id): Path<i64>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let teacher_id = auth_teacher_id(&headers, &jar, &state).await
+        .ok_or_else(|| AppError::unauthorized("not logged in"))?;
+    let (room_code, quiz_id, position, total) = {
+        let mut sessions = state.db.game_sessions.write().await;
+        let session = sessions
+            .get_mut(&id)
+            .or_not_found("session")?;
+        if session.teacher_id != teacher_id {
+            return Err(AppError::forbidden("access denied"));
+        }
+        if session.status == "finished" {
+            return Err(AppError::conflict("session is finished"));
+        }
+        let next_position = session.playlist_position + 1;
+        let Some(next_quiz_id) = session.playlist.get(next_position).copied() else {
+            return Err(AppError::conflict("playlist has no more quizzes"));
+        };
+
+        let finished = PlaylistSegment {
+            quiz_id: session.quiz_id,
+            stats: std::mem::take(&mut session.stats),
+            mistakes: std::mem::take(&mut session.mistakes),
+        };
+        session.completed_segments.push(finished);
+        for p in session.participants.values_mut() {
+            p.current_question_index = 0;
+            p.attempts = 0;
+            p.question_order.clear();
+            p.pending_question = None;
+            p.question_deadline = None;
+            session.stats.insert(
+                p.nickname.clone(),
+                StudentStats { nickname: p.nickname.clone(), ..Default::default() },
+            );
+        }
+        session.quiz_id = next_quiz_id;
+        session.playlist_position = next_position;
+        session.host_question = None;
+        (session.room_code.clone(), next_quiz_id, next_position, session.playlist.len())
+    };
+
+    if let Some(sender) = state.db.broadcasters.get(&room_code) {
+        let _ = sender.send(WsEnvelope::new(
+            ServerEvent::NextQuiz,
+            json!({ "sessionId": id, "quizId": quiz_id, "position": position, "total": total }),
+            None,
+        ));
+    }
+    Ok(Json(json!({ "quizId": quiz_id, "position": position, "total": total })))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EndSessionPayload {
+    #[serde(rename = "reviewMinutes")]
+    pub review_minutes: Option<u64>,
+}
+
+pub async fn end_session(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    jar: CookieJar,
+    Path(id): Path<i64>,
+    payload: Option<Json<EndSessionPayload>>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let teacher_id = auth_teacher_id(&headers, &jar, &state).await
+        .ok_or_else(|| AppError::unauthorized("not logged in"))?;
+    let review_minutes = payload
+        .and_then(|Json(p)| p.review_minutes)
+        .unwrap_or_else(|| {
+            std::env::var("SESSION_REVIEW_MINUTES")
+                .ok()
+                .and_then(|v| v.parse::<u64>().ok())
+                .unwrap_or(0)
+        })
+        .min(120);
+    let review_until = (review_minutes > 0).then(|| Utc::now() + chrono::Duration::minutes(review_minutes as i64));
+    let (room_code, status, podium) = {
+        let mut sessions = state.db.game_sessions.write().await;
+        let session = sessions
+            .get_mut(&id)
+            .or_not_found("session")?;
+        if session.teacher_id != teacher_id {
+            return Err(AppError::forbidden("access denied"));
+        }
+        session.status = if review_until.is_some() { "review".into() } else { "finished".into() };
+        session.review_until = review_until;
+        session.ended_at.get_or_insert_with(Utc::now);
+        (session.room_code.clone(), session.status.clone(), podium_payload(session))
+    };
+    if status == "finished" {
+        if let Ok(session) = state.game_session(id).await {
+            state.log_change(JournalOp::SessionFinished { session: Box::new(session) }).await;
+        }
+    }
+    state.audit(Some(teacher_id), "session.end", Some(id.to_string())).await;
+    state.commit_changes()?;
+    // Scores are final once the game ends; a review period does not change them.
+    state.send_lti_scores(id);
+    state.email_results_summary(id);
+
+    let ended = WsEnvelope::new(
+        ServerEvent::EndQuiz,
+        json!({
+            "sessionId": id,
+            "endedAt": Utc::now().to_rfc3339(),
+            "resultsReady": true,
+            "reviewUntil": review_until.map(|t| t.to_rfc3339())
+        }),
+        None,
+    );
+    let podium = WsEnvelope::new(ServerEvent::Podium, podium, None);
+    // Without a review period the room is done: end_quiz is the last thing its sockets get.
+    if status == "finished" {
+        if let Some(channels) = state.release_room(&room_code).await {
+            let _ = channels.send(podium);
+            let _ = channels.send(ended);
+        }
+    } else if let Some(sender) = state.db.broadcasters.get(&room_code) {
+        let _ = sender.send(podium);
+        let _ = sender.send(ended);
+    }
+    if review_minutes > 0 {
+        let review_state = state.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_secs(review_minutes * 60)).await;
+            review_state.close_review(id).await;
+        });
+    }
+    Ok(Json(json!({ "status": status, "reviewUntil": review_until.map(|t| t.to_rfc3339()) })))
+}
+
+// One played quiz of a session: its id, per-nickname stats and mistakes.
+type SegmentResults<'a> = (i64, &'a HashMap<String, StudentStats>, &'a HashMap<String, Vec<String>>);
+
+pub async fn session_results(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    jar: CookieJar,
+    Path(id): Path<i64>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let teacher_id = auth_teacher_id(&headers, &jar, &state).await
+        .ok_or_else(|| AppError::unauthorized("not logged in"))?;
+    let session = state.game_session(id).await?;
+    if session.teacher_id != teacher_id {
+        return Err(AppError::forbidden("access denied"));
+    }
+
+    let mut segments: Vec<SegmentResults> = session
+        .completed_segments
+        .iter()
+        .map(|s| (s.quiz_id, &s.stats, &s.mistakes))
+        .collect();
+    segments.push((session.quiz_id, &session.stats, &session.mistakes));
+
+    let passing_scores: HashMap<i64, f64> = {
+        let records = state.db.quizzes.read().await;
+        segments
+            .iter()
+            .filter_map(|(quiz_id, _, _)| Some((*quiz_id, records.get(quiz_id)?.passing_score()?)))
+            .collect()
+    };
+    let mut combined_stats: HashMap<String, StudentStats> = HashMap::new();
+    let mut combined_mistakes: HashMap<String, Vec<String>> = HashMap::new();
+    let mut quizzes = Vec::new();
+    for (quiz_id, stats, mistakes) in &segments {
+        for s in stats.values() {
+            let entry = combined_stats
+                .entry(s.nickname.clone())
+                .or_insert(StudentStats { nickname: s.nickname.clone(), ..Default::default() });
+            entry.correct += s.correct;
+            entry.wrong += s.wrong;
+            entry.score += s.score;
+            entry.bonus += s.bonus;
+            entry.penalty += s.penalty;
+            entry.best_streak = entry.best_streak.max(s.best_streak);
+        }
+        for (nick, qs) in mistakes.iter() {
+            combined_mistakes.entry(nick.clone()).or_default().extend(qs.iter().cloned());
+        }
+        let mut quiz_result = results_json(stats, mistakes, passing_scores.get(quiz_id).copied());
+        quiz_result["quizId"] = json!(quiz_id);
+        quizzes.push(quiz_result);
+    }
+
+    // Pass/fail is per quiz, so the playlist total only carries it when there is a single quiz.
+    let combined_passing = match segments.as_slice() {
+        [(quiz_id, _, _)] => passing_scores.get(quiz_id).copied(),
+        _ => None,
+    };
+    let mut body = results_json(&combined_stats, &combined_mistakes, combined_passing);
+    body["session"] = json!({
+        "id": session.id,
+        "roomCode": session.room_code,
+        "status": session.status,
+        "gameMode": session.game_mode,
+        "playlist": session.playlist,
+        "playlistPosition": session.playlist_position
+    });
+    body["quizzes"] = json!(quizzes);
+    body["questionTimes"] = json!(question_times(&session, None));
+    let now = Utc::now();
+    if let Some(students) = body["studentStats"].as_array_mut() {
+        for student in students {
+            let signals = student["nickname"].as_str().and_then(|nickname| session.integrity.get(nickname));
+            let signals = signals.cloned().unwrap_or_default();
+            student["integrity"] = json!({
+                "focusLost": signals.focus_lost,
+                "unfocusedMs": signals.unfocused_ms(now),
+                "fastAnswers": signals.fast_answers,
+                "longestFastStreak": signals.longest_fast_streak,
+                "fastestMs": signals.fastest_ms
+            });
+            // The seed behind the student's question and option order, so a disputed grade can be replayed
+            // exactly. A string, as 64-bit numbers do not survive JavaScript clients.
+            let participant = student["nickname"].as_str().and_then(|nickname| session.participants.get(nickname));
+            student["shuffleSeed"] = json!(participant.map(|p| p.shuffle_seed.to_string()));
+            let disruptions = student["nickname"].as_str().and_then(|nickname| session.disruptions.get(nickname));
+            student["disruptions"] = json!(disruptions
+                .into_iter()
+                .flatten()
+                .map(|d| json!({
+                    "quizId": d.quiz_id,
+                    "questionId": d.question_id,
+                    "disconnectedAt": d.disconnected_at.to_rfc3339(),
+                    "reconnectedAt": d.reconnected_at.map(|t| t.to_rfc3339()),
+                    "extendedMs": d.extended_ms
+                }))
+                .collect::<Vec<_>>());
+        }
+    }
+    if let Some(class_id) = session.class_id {
+        body["attendance"] = attendance_json(class_id, &session);
+    }
+    Ok(Json(body))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LiveQuery {
+    #[serde(default)]
+    pub cursor: u64,
+}
+
+// The teacher's live view without a socket: meant to be polled every couple of seconds, passing back the
+// `cursor` of the previous reply to get only the room events since then.
+pub async fn session_live(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    jar: CookieJar,
+    Path(id): Path<i64>,
+    query: axum::extract::Query<LiveQuery>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let teacher_id = auth_teacher_id(&headers, &jar, &state).await
+        .ok_or_else(|| AppError::unauthorized("not logged in"))?;
+    let session = state.game_session(id).await?;
+    if session.teacher_id != teacher_id {
+        return Err(AppError::forbidden("access denied"));
+    }
+    let mut participants: Vec<_> = session
+        .participants
+        .values()
+        .map(|p| {
+            let stats = session.stats.get(&p.nickname);
+            json!({
+                "nickname": p.nickname,
+                "state": p.join_state,
+                "questionIndex": p.current_question_index,
+                "pendingQuestion": p.pending_question,
+                "lives": p.lives,
+                "correct": stats.map_or(0, |s| s.correct),
+                "wrong": stats.map_or(0, |s| s.wrong),
+                "points": stats.map_or(0.0, |s| s.points()),
+                "focused": session.integrity.get(&p.nickname).is_none_or(|i| i.focus_lost_at.is_none())
+            })
+        })
+        .collect();
+    participants.sort_by(|a, b| a["nickname"].as_str().cmp(&b["nickname"].as_str()));
+    // A finished session has given up its room code, which another session may hold by now.
+    let room = (session.status != "finished").then(|| state.db.broadcasters.get(&session.room_code)).flatten();
+    let (events, cursor, missed) = match room {
+        Some(room) => room.events_since(query.cursor),
+        None => (Vec::new(), query.cursor, false),
+    };
+    let events: Vec<_> = events
+        .into_iter()
+        .map(|(cursor, env)| json!({ "cursor": cursor, "event": env.event, "payload": env.payload, "ts": env.ts }))
+        .collect();
+    Ok(Json(json!({
+        "sessionId": session.id,
+        "status": session.status,
+        "quizId": session.quiz_id,
+        "playlistPosition": session.playlist_position,
+        "participants": participants,
+        "stats": stats_payload(&session),
+        "events": events,
+        "cursor": cursor,
+        "missedEvents": missed
+    })))
+}
+
+// Roster members who took part, roster members who did not, and participants who are not on the roster.
+fn attendance_json(class_id: i64, session: &SessionRecord) -> serde_json::Value {
+    let (present, absent): (Vec<&RosterEntry>, Vec<&RosterEntry>) = session
+        .roster
+        .iter()
+        .partition(|member| session.participants.values().any(|p| member.matches(p)));
+    let mut guests: Vec<&str> = session
+        .participants
+        .values()
+        .filter(|p| !session.roster.iter().any(|member| member.matches(p)))
+        .map(|p| p.nickname.as_str())
+        .collect();
+    guests.sort_unstable();
+    json!({ "classId": class_id, "present": present, "absent": absent, "guests": guests })
+}
+
+fn results_json(
+    stats: &HashMap<String, StudentStats>,
+    mistakes: &HashMap<String, Vec<String>>,
+    passing_score: Option<f64>,
+) -> serde_json::Value {
+    let class_correct: u32 = stats.values().map(|s| s.correct).sum();
+    let class_wrong: u32 = stats.values().map(|s| s.wrong).sum();
+    let total = class_correct + class_wrong;
+    let class_pct = if total == 0 {
+        0.0
+    } else {
+        class_correct as f64 * 100.0 / total as f64
+    };
+
+    let students: Vec<_> = StudentStats::ranked(stats)
+        .into_iter()
+        .map(|s| {
+            let mut student = json!({
+                "nickname": s.nickname,
+                "correct": s.correct,
+                "wrong": s.wrong,
+                "score": s.score,
+                "points": s.points(),
+                "bonus": s.bonus,
+                "penalty": s.penalty,
+                "bestStreak": s.best_streak,
+                "correctPct": s.correct_pct()
+            });
+            if let Some(threshold) = passing_score {
+                student["passed"] = json!(s.score >= threshold);
+            }
+            student
+        })
+        .collect();
+
+    let mistakes: Vec<_> = mistakes
+        .iter()
+        .map(|(nick, qs)| json!({"nickname": nick, "questions": qs}))
+        .collect();
+
+    json!({
+        "classStats": {"correct": class_correct, "wrong": class_wrong, "correctPct": class_pct},
+        "studentStats": students,
+        "mistakesByStudent": mistakes
+    })
+}
+
+// Homework is played over REST: a student joins with the link's join token and then pulls questions and
+// submits answers with the participant token they were given, which is also their WS resume token.
+const PARTICIPANT_TOKEN_HEADER: &str = "x-participant-token";
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HomeworkJoinPayload {
+    pub join_token: String,
+    // May be left out by a student signing in with their student token; their display name is used.
+    #[serde(default)]
+    pub nickname: String,
+}
+
+// The homework session in `room_code`, provided it takes answers right now.
+async fn open_homework(state: &AppState, room_code: &str) -> Result<i64, AppError> {
+    let not_found = || AppError::not_found("no homework in this room");
+    let session_id = state.shared.room_session(room_code).await?.ok_or_else(not_found)?;
+    let sessions = state.db.game_sessions.read().await;
+    let session = sessions.get(&session_id).ok_or_else(not_found)?;
+    let homework = session.homework.as_ref().ok_or_else(not_found)?;
+    let now = Utc::now();
+    if session.status == "finished" || now >= homework.closes_at {
+        return Err(AppError::rejected(StatusCode::GONE, "HOMEWORK_CLOSED", "the homework is closed"));
+    }
+    if !homework.is_open(now) {
+        return Err(AppError::rejected(StatusCode::FORBIDDEN, "HOMEWORK_NOT_OPEN", "the homework is not open yet"));
+    }
+    if session.status == "paused" {
+        return Err(AppError::rejected(StatusCode::CONFLICT, "SESSION_PAUSED", "the teacher paused the session"));
+    }
+    Ok(session_id)
+}
+
+// The student signed in with a student token, if the request carries one.
+async fn optional_student(state: &AppState, headers: &HeaderMap) -> Result<Option<Student>, AppError> {
+    if bearer_token(headers).is_none() {
+        return Ok(None);
+    }
+    let student_id = auth_student_id(headers, state)
+        .await
+        .ok_or_else(|| AppError::unauthorized("invalid student token"))?;
+    Ok(state.db.students.read().await.get(&student_id).cloned())
+}
+
+// The nickname playing under the participant token sent with the request.
+async fn token_participant(
+    state: &AppState,
+    session_id: i64,
+    headers: &HeaderMap,
+) -> Result<String, AppError> {
+    let token = headers.get(PARTICIPANT_TOKEN_HEADER).and_then(|v| v.to_str().ok()).unwrap_or_default();
+    let sessions = state.db.game_sessions.read().await;
+    sessions
+        .get(&session_id)
+        .and_then(|s| s.participants.values().find(|p| !token.is_empty() && p.resume_token == token))
+        .map(|p| p.nickname.clone())
+        .ok_or_else(|| AppError::unauthorized("invalid participant token"))
+}
+
+pub async fn join_homework(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(room_code): Path<String>,
+    Json(payload): Json<HomeworkJoinPayload>,
+) -> Result<(StatusCode, Json<serde_json::Value>), AppError> {
+    let session_id = open_homework(&state, &room_code).await?;
+    let student = optional_student(&state, &headers).await?;
+    let mut nickname = payload.nickname.trim().to_string();
+    if nickname.is_empty() {
+        if let Some(student) = &student {
+            nickname = student.display_name.clone();
+        }
+    }
+
+    let words = state.session_word_overrides(session_id).await;
+    let mut sessions = state.db.game_sessions.write().await;
+    let session = sessions
+        .get_mut(&session_id)
+        .or_not_found("session")?;
+    if payload.join_token != session.join_token {
+        return Err(AppError::forbidden("invalid join token"));
+    }
+    if session.kicked.contains(&nickname) {
+        return Err(AppError::forbidden("removed from this session"));
+    }
+    // Taking over a nickname needs its participant token, which the REST flow only hands out once.
+    if session.participants.contains_key(&nickname) {
+        return Err(AppError::conflict("nickname is already taken"));
+    }
+    let student_id = student.as_ref().map(|s| s.id);
+    if let Some((reason, message)) = lobby_refusal(session, &nickname, student_id, &state.profanity, &words) {
+        return Err(match reason {
+            "invalid_nickname" | "inappropriate_nickname" => AppError::validation(message),
+            "late_join_closed" => AppError::rejected(StatusCode::CONFLICT, "LATE_JOIN_CLOSED", message),
+            _ => AppError::rejected(StatusCode::CONFLICT, "ROOM_FULL", message),
+        });
+    }
+    let participant_token = admit_participant(&state, session, &nickname, student_id);
+    let question_count = state.db.quizzes.read().await.get(&session.quiz_id).map_or(0, |q| q.questions.len());
+    if let Some(bc) = state.db.broadcasters.get(&room_code) {
+        let _ = bc.send(WsEnvelope::new(ServerEvent::WaitingRoomUpdate, waiting_room_payload(session), None));
+    }
+    let homework = session.homework.as_ref();
+    Ok((
+        StatusCode::CREATED,
+        Json(json!({
+            "sessionId": session.id,
+            "nickname": nickname,
+            "participantToken": participant_token,
+            "quizId": session.quiz_id,
+            "questionCount": question_count,
+            "closesAt": homework.map(|h| h.closes_at.to_rfc3339()),
+        })),
+    ))
+}
+
+// The question the participant is on; once every question is settled the participant's stats come back
+// instead.
+pub async fn homework_question(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(room_code): Path<String>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let session_id = open_homework(&state, &room_code).await?;
+    let nickname = token_participant(&state, session_id, &headers).await?;
+    match current_question(&state, session_id, &room_code, &nickname).await? {
+        Some(payload) => Ok(Json(payload)),
+        None => {
+            let sessions = state.db.game_sessions.read().await;
+            let stats = sessions.get(&session_id).and_then(|s| s.stats.get(&nickname));
+            Ok(Json(json!({ "finished": true, "stats": stats })))
+        }
+    }
+}
+
+pub async fn submit_homework_answer(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(room_code): Path<String>,
+    Json(submit): Json<AnswerSubmit>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let session_id = open_homework(&state, &room_code).await?;
+    let nickname = token_participant(&state, session_id, &headers).await?;
+    answer_over_rest(&state, session_id, &room_code, &nickname, submit).await.map(Json)
+}
+
+// The `question_push` payload for the question the participant is on, serving the next one when none
+// is open; None once the participant has no questions left. Asking again returns the open question with
+// the deadline it already had.
+async fn current_question(
+    state: &AppState,
+    session_id: i64,
+    room_code: &str,
+    nickname: &str,
+) -> Result<Option<serde_json::Value>, AppError> {
+    {
+        let sessions = state.db.game_sessions.read().await;
+        let session = sessions.get(&session_id).or_not_found("session")?;
+        if let Some(participant) = session.participants.get(nickname) {
+            if let (Some(question), deadline) = pending_question(state, session, participant).await {
+                let mut payload = json!({ "question": question, "reason": "pending" });
+                if let (Some(deadline), Some((_, at))) = (deadline, &participant.question_deadline) {
+                    payload["deadline"] = json!(deadline);
+                    payload["remainingMs"] = json!(remaining_ms(*at));
+                }
+                return Ok(Some(payload));
+            }
+        }
+    }
+    match serve_question(state, session_id, room_code, nickname, "next".into(), None).await {
+        Some(ServedQuestion::Question(payload)) => Ok(Some(payload)),
+        Some(ServedQuestion::Finished(_)) => Ok(None),
+        Some(ServedQuestion::Out) => Err(rest_out_of_lives()),
+        None => Err(AppError::not_found("quiz not found")),
+    }
+}
+
+// Scored like an answer sent over the socket; returns the `answer_result` payload.
+async fn answer_over_rest(
+    state: &AppState,
+    session_id: i64,
+    room_code: &str,
+    nickname: &str,
+    submit: AnswerSubmit,
+) -> Result<serde_json::Value, AppError> {
+    match answer_question(state, session_id, nickname, submit, None).await {
+        Some(AnswerOutcome::Result(result, updates)) => {
+            if let Some(bc) = state.db.broadcasters.get(room_code) {
+                for update in updates {
+                    let _ = bc.send(update);
+                }
+            }
+            Ok(result)
+        }
+        Some(AnswerOutcome::Out) => Err(rest_out_of_lives()),
+        None => Err(AppError::not_found("question not found")),
+    }
+}
+
+fn rest_out_of_lives() -> AppError {
+    AppError::rejected(StatusCode::CONFLICT, "OUT_OF_LIVES", "no lives left in this game")
+}
+
+// A solo run through a published quiz, scored the same way as a session. Nobody else sees it and
+// nothing is kept: the run has no teacher and no room, and is dropped once it gets old. The response
+// carries the first question; the run id and participant token drive the rest.
+pub async fn start_practice(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(quiz_id): Path<i64>,
+) -> Result<(StatusCode, Json<serde_json::Value>), AppError> {
+    let student = optional_student(&state, &headers).await?;
+    let quiz = state.quiz(quiz_id).await?;
+    if !quiz.is_published {
+        return Err(AppError::conflict("quiz is not published"));
+    }
+    let nickname = student.as_ref().map_or_else(|| "student".to_string(), |s| s.display_name.clone());
+    let id = state.db.next_game_session_id();
+    let mode = state.game_modes.for_session("classic");
+    let mut run = SessionRecord {
+        id,
+        room_code: String::new(),
+        join_token: String::new(),
+        quiz_id,
+        // Teacher ids start at 1, so no teacher endpoint reaches the run.
+        teacher_id: 0,
+        status: "active".into(),
+        game_mode: mode.name().into(),
+        participants: HashMap::new(),
+        stats: HashMap::new(),
+        mistakes: HashMap::new(),
+        playlist: vec![quiz_id],
+        playlist_position: 0,
+        completed_segments: Vec::new(),
+        answers: HashMap::new(),
+        review_until: None,
+        hints: HashMap::new(),
+        kicked: Vec::new(),
+        pacing: SessionPacing::SelfPaced,
+        host_question: None,
+        paused_at: None,
+        lobby: LobbySettings::default(),
+        game_config: Some(mode.preset()),
+        homework: None,
+        practice_started_at: Some(Utc::now()),
+        starts_at: None,
+        review_position: None,
+        integrity: HashMap::new(),
+        disruptions: HashMap::new(),
+        created_at: Some(Utc::now()),
+        started_at: Some(Utc::now()),
+        ended_at: None,
+        class_id: None,
+        roster: Vec::new(),
+        lti_grades: HashMap::new(),
+    };
+    let participant_token = admit_participant(&state, &mut run, &nickname, student.as_ref().map(|s| s.id));
+    state.db.game_sessions.write().await.insert(id, run);
+    let question = current_question(&state, id, "", &nickname).await?;
+    state.record_usage("practice_run").await;
+    Ok((
+        StatusCode::CREATED,
+        Json(json!({
+            "runId": id,
+            "participantToken": participant_token,
+            "quizId": quiz_id,
+            "questionCount": quiz.questions.len(),
+            "question": question,
+        })),
+    ))
+}
+
+// The question the run is on; once every question is settled, the summary of the run.
+pub async fn practice_question(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(run_id): Path<i64>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let nickname = practice_participant(&state, run_id, &headers).await?;
+    if let Some(payload) = current_question(&state, run_id, "", &nickname).await? {
+        return Ok(Json(payload));
+    }
+    let sessions = state.db.game_sessions.read().await;
+    let run = sessions.get(&run_id).or_not_found("practice run")?;
+    let quiz = state.quiz(run.quiz_id).await?;
+    Ok(Json(json!({ "finished": true, "summary": practice_summary(run, &quiz, &nickname) })))
+}
+
+pub async fn submit_practice_answer(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(run_id): Path<i64>,
+    Json(submit): Json<AnswerSubmit>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let nickname = practice_participant(&state, run_id, &headers).await?;
+    answer_over_rest(&state, run_id, "", &nickname, submit).await.map(Json)
+}
+
+async fn practice_participant(
+    state: &AppState,
+    run_id: i64,
+    headers: &HeaderMap,
+) -> Result<String, AppError> {
+    let is_run = state.db.game_sessions.read().await.get(&run_id).is_some_and(|s| s.practice_started_at.is_some());
+    if !is_run {
+        return Err(AppError::not_found("practice run not found"));
+    }
+    token_participant(state, run_id, headers).await
+}
+
+// The score of a finished run and every question missed, with the answer given and the right one.
+fn practice_summary(run: &SessionRecord, quiz: &QuizRecord, nickname: &str) -> serde_json::Value {
+    let stats = run.stats.get(nickname).cloned().unwrap_or_default();
+    let answers = run.answers.get(nickname).map(Vec::as_slice).unwrap_or_default();
+    let mut missed: Vec<&String> = Vec::new();
+    for question_id in run.mistakes.get(nickname).into_iter().flatten() {
+        if !missed.contains(&question_id) {
+            missed.push(question_id);
+        }
+    }
+    let mistakes: Vec<_> = missed
+        .into_iter()
+        .filter_map(|question_id| {
+            let question = quiz.questions.iter().find(|q| &q.id == question_id)?;
+            // A question that ran out of time has no answer.
+            let given = answers.iter().rev().find(|a| &a.question_id == question_id).map(|a| &a.answer);
+            Some(json!({
+                "questionId": question_id,
+                "prompt": question.prompt,
+                "yourAnswer": given,
+                "correctAnswer": question.answer,
+                "explanation": question.explanation,
+            }))
+        })
+        .collect();
+    json!({
+        "quizId": quiz.id,
+        "score": stats.score,
+        "maxScore": quiz.questions.iter().map(|q| q.points()).sum::<f64>(),
+        "points": stats.points(),
+        "correct": stats.correct,
+        "wrong": stats.wrong,
+        "bestStreak": stats.best_streak,
+        "mistakes": mistakes,
+    })
+}
+
+// Teacher credentials seen on the upgrade request. A cookie session also has to repeat its CSRF
+// token in `join_room`, because browsers attach cookies to cross-site upgrades too.
+struct WsTeacher {
+    teacher_id: i64,
+    csrf_token: Option<String>,
+}
+
+pub async fn ws_contract() -> impl IntoResponse {
+    ([(axum::http::header::CONTENT_TYPE, "application/schema+json")], WS_PROTOCOL_SCHEMA)
+}
+
+pub async fn ws_handler(
+    ws: WebSocketUpgrade,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    jar: CookieJar,
+    Path(room_code): Path<String>,
+) -> Response {
+    let teacher = match auth_teacher_id(&headers, &jar, &state).await {
+        Some(teacher_id) if headers.contains_key(API_KEY_HEADER) || bearer_token(&headers).is_some() => {
+            Some(WsTeacher { teacher_id, csrf_token: None })
+        }
+        Some(teacher_id) => {
+            let sid = jar.get(SESSION_COOKIE).map(|c| c.value().to_string()).unwrap_or_default();
+            let csrf_token = state.teacher_session(&sid).await.map(|s| s.csrf_token);
+            csrf_token.map(|csrf| WsTeacher { teacher_id, csrf_token: Some(csrf) })
+        }
+        None => None,
+    };
+    ws.on_upgrade(move |socket| ws_session(socket, state, room_code, teacher))
+}
+
+fn waiting_room_payload(session: &SessionRecord) -> serde_json::Value {
+    let participants: Vec<_> = session
+        .participants
+        .values()
+        .map(|p| json!({"nickname": p.nickname, "state": p.join_state}))
+        .collect();
+    json!({"sessionId": session.id, "participants": participants})
+}
+
+fn room_unavailable(room_code: &str, position: usize, queue_len: usize, retry: bool) -> Message {
+    let env = WsEnvelope::new(
+        ServerEvent::RoomUnavailable,
+        json!({
+            "roomCode": room_code,
+            "reason": "server_full",
+            "queuePosition": position,
+            "queueLength": queue_len,
+            "retry": retry,
+        }),
+        None,
+    );
+    Message::Text(serde_json::to_string(&env).unwrap_or_default())
+}
+
+// Holds an over-capacity connection in a FIFO queue, reporting its position until a slot frees up
+// or ADMISSION_QUEUE_TIMEOUT_SECS passes.
+async fn admit_ws(stream: &mut WebSocket, state: &AppState, room_code: &str) -> Option<WsPermit> {
+    if let Some(permit) = state.admission.try_admit_ws() {
+        return Some(permit);
+    }
+    let ticket = state.admission.enqueue();
+    let admit = state.admission.admit_ws();
+    tokio::pin!(admit);
+    let deadline = tokio::time::sleep(state.admission.queue_timeout);
+    tokio::pin!(deadline);
+    let mut ticker = tokio::time::interval(Duration::from_secs(2));
+    loop {
+        tokio::select! {
+            permit = &mut admit => return Some(permit),
+            _ = ticker.tick() => {
+                let update = room_unavailable(room_code, ticket.position(), state.admission.queue_len(), false);
+                if stream.send(update).await.is_err() {
+                    return None;
+                }
+            }
+            _ = &mut deadline => {
+                state.admission.ws_rejected.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                let _ = stream
+                    .send(room_unavailable(room_code, ticket.position(), state.admission.queue_len(), true))
+                    .await;
+                let _ = stream.send(Message::Close(None)).await;
+                return None;
+            }
+            incoming = stream.next() => {
+                if matches!(incoming, None | Some(Err(_)) | Some(Ok(Message::Close(_)))) {
+                    return None;
+                }
+            }
+        }
+    }
+}
+
+async fn ws_session(mut stream: WebSocket, state: AppState, room_code: String, teacher: Option<WsTeacher>) {
+    let session_id = match state.shared.room_session(&room_code).await {
+        Ok(Some(v)) => v,
+        Ok(None) => return,
+        Err(err) => {
+            warn!("failed to resolve room {}: {}", room_code, err);
+            return;
+        }
+    };
+    let Some(_permit) = admit_ws(&mut stream, &state, &room_code).await else { return };
+
+    let mut receiver = match state.db.broadcasters.get(&room_code) {
+        Some(sender) => sender.subscribe(),
+        None => return,
+    };
+
+    let (mut sender_ws, mut receiver_ws) = stream.split();
+    let mut current_nickname: Option<String> = None;
+    // Set once an authenticated owner of the session joins with the teacher role.
+    let mut is_teacher = false;
+    let (direct_tx, mut direct_rx) = mpsc::unbounded_channel::<WsEnvelope>();
+    let heartbeat = state.ws_heartbeat;
+    // Lets the send task build a `state_sync` for whoever this socket currently plays as.
+    let (nickname_tx, nickname_rx) = watch::channel(None::<String>);
+    let sync_state = state.clone();
+    let sync_room = room_code.clone();
+
+    let send_task = tokio::spawn(async move {
+        let start = tokio::time::Instant::now() + heartbeat.ping_interval;
+        let mut ping = tokio::time::interval_at(start, heartbeat.ping_interval);
+        loop {
+            // Biased so a reply to this socket (say, `answer_result`) goes out before the room updates
+            // that were broadcast after it.
+            let msg = tokio::select! {
+                biased;
+                direct = direct_rx.recv() => match direct {
+                    Some(msg) => msg,
+                    None => break,
+                },
+                _ = ping.tick() => {
+                    if sender_ws.send(Message::Ping(Vec::new())).await.is_err() {
+                        break;
+                    }
+                    continue;
+                }
+                recv = receiver.recv() => match recv {
+                    Ok(msg) => msg,
+                    // A socket that fell behind skips what it missed and gets the current state instead.
+                    Err(RecvError::Lagged(missed)) => {
+                        warn!("ws in room {} lagged behind by {} messages", sync_room, missed);
+                        if let Some(channels) = sync_state.db.broadcasters.get(&sync_room) {
+                            channels.lagged.fetch_add(missed, std::sync::atomic::Ordering::Relaxed);
+                        }
+                        let nickname = nickname_rx.borrow().clone();
+                        match state_sync(&sync_state, session_id, nickname.as_deref(), missed).await {
+                            Some(msg) => msg,
+                            None => break,
+                        }
+                    }
+                    // The room either closed or moved to a larger channel; the latter is followed
+                    // with a sync for whatever was sent in between.
+                    Err(RecvError::Closed) => {
+                        let resubscribed = sync_state.db.broadcasters.get(&sync_room).map(|c| c.subscribe());
+                        let Some(resubscribed) = resubscribed else { break };
+                        receiver = resubscribed;
+                        let nickname = nickname_rx.borrow().clone();
+                        match state_sync(&sync_state, session_id, nickname.as_deref(), 0).await {
+                            Some(msg) => msg,
+                            None => break,
+                        }
+                    }
+                },
+            };
+            if let Ok(text) = serde_json::to_string(&msg) {
+                if sender_ws.send(Message::Text(text)).await.is_err() {
+                    break;
+                }
+            }
+            if msg.event == ServerEvent::Kicked {
+                let _ = sender_ws.send(Message::Close(None)).await;
+                break;
+            }
+        }
+    });
+
+    // Any frame counts as a sign of life, pongs included.
+    let mut timed_out = false;
+    loop {
+        let message = match tokio::time::timeout(heartbeat.idle_timeout, receiver_ws.next()).await {
+            Ok(Some(Ok(message))) => message,
+            Ok(_) => break,
+            Err(_) => {
+                timed_out = true;
+                break;
+            }
+        };
+        let Message::Text(txt) = message else { continue };
+        let msg = match ClientMessage::parse(&txt) {
+            Ok(msg) => msg,
+            Err(err) => {
+                let _ = direct_tx.send(err.into_envelope());
+                continue;
+            }
+        };
+        let request_id = msg.request_id;
+        let error =
+            |code: &'static str, message: String| ProtocolError::new(code, message, request_id.clone()).into_envelope();
+        let not_joined = || error("not_joined", "join_room first".into());
+        if msg.event.teacher_only() && !is_teacher {
+            let _ = direct_tx.send(error("forbidden", "teacher only".into()));
+            continue;
+        }
+
+        match msg.event {
+            ClientEvent::JoinRoom(join) if join.role == JoinRole::Teacher => {
+                let sessions = state.db.game_sessions.read().await;
+                let Some(session) = sessions.get(&session_id) else { continue };
+                let owner = teacher.as_ref().is_some_and(|t| {
+                    t.teacher_id == session.teacher_id && (t.csrf_token.is_none() || t.csrf_token == join.csrf)
+                });
+                if !owner {
+                    let _ = direct_tx.send(WsEnvelope::new(
+                        ServerEvent::JoinRejected,
+                        json!({ "reason": "forbidden" }),
+                        request_id.clone(),
+                    ));
+                    continue;
+                }
+                is_teacher = true;
+                if let Some(channels) = state.db.broadcasters.get(&room_code) {
+                    channels.attach_teacher(direct_tx.clone());
+                }
+                let update = waiting_room_payload(session);
+                let _ = direct_tx.send(WsEnvelope::new(ServerEvent::WaitingRoomUpdate, update, request_id.clone()));
+            }
+
+            // A spectator (the classroom projector) sees the room and the rankings but is not a
+            // participant: no nickname, no questions, no answers.
+            ClientEvent::JoinRoom(join) if join.role == JoinRole::Spectator => {
+                let sessions = state.db.game_sessions.read().await;
+                let Some(session) = sessions.get(&session_id) else { continue };
+                if join.join_token.as_deref() != Some(session.join_token.as_str()) {
+                    let _ = direct_tx.send(WsEnvelope::new(
+                        ServerEvent::JoinRejected,
+                        json!({ "reason": "invalid_join_token" }),
+                        request_id.clone(),
+                    ));
+                    continue;
+                }
+                for (event, payload) in [
+                    (ServerEvent::WaitingRoomUpdate, waiting_room_payload(session)),
+                    (ServerEvent::StatsUpdate, stats_payload(session)),
+                    (ServerEvent::LeaderboardUpdate, leaderboard_payload(session)),
+                ] {
+                    let _ = direct_tx.send(WsEnvelope::new(event, payload, request_id.clone()));
+                }
+            }
+
+            ClientEvent::JoinRoom(join) => {
+                let token_ok = {
+                    let sessions = state.db.game_sessions.read().await;
+                    let expected = sessions.get(&session_id).map(|s| s.join_token.as_str());
+                    expected.is_some() && join.join_token.as_deref() == expected
+                };
+                if !token_ok {
+                    let _ = direct_tx.send(WsEnvelope::new(
+                        ServerEvent::JoinRejected,
+                        json!({ "reason": "invalid_join_token" }),
+                        request_id.clone(),
+                    ));
+                    continue;
+                }
+                if let Some(resume_token) = &join.resume_token {
+                    let Some((nickname, snapshot, update)) = resume_participant(&state, session_id, resume_token).await
+                    else {
+                        let _ = direct_tx.send(WsEnvelope::new(
+                            ServerEvent::JoinRejected,
+                            json!({ "reason": "invalid_resume_token" }),
+                            request_id.clone(),
+                        ));
+                        continue;
+                    };
+                    if let Some(channels) = state.db.broadcasters.get(&room_code) {
+                        if let Some(previous) = current_nickname.as_deref().filter(|p| *p != nickname) {
+                            channels.detach(previous, &direct_tx);
+                        }
+                        channels.attach(&nickname, direct_tx.clone());
+                        let _ = direct_tx.send(WsEnvelope::new(ServerEvent::Resumed, snapshot, request_id.clone()));
+                        let update = WsEnvelope::new(ServerEvent::WaitingRoomUpdate, update, request_id.clone());
+                        let _ = channels.send(update);
+                    }
+                    nickname_tx.send_replace(Some(nickname.clone()));
+                    current_nickname = Some(nickname);
+                    continue;
+                }
+                // A student token links the participant to a persistent account; without one the
+                // nickname is all we know.
+                let student = match &join.student_token {
+                    Some(token) => {
+                        let student = match state.jwt.verify_student_token(token) {
+                            Some(id) => state.db.students.read().await.get(&id).cloned(),
+                            None => None,
+                        };
+                        if student.is_none() {
+                            let _ = direct_tx.send(WsEnvelope::new(
+                                ServerEvent::JoinRejected,
+                                json!({ "reason": "invalid_student_token" }),
+                                request_id.clone(),
+                            ));
+                            continue;
+                        }
+                        student
+                    }
+                    None => None,
+                };
+                let mut nickname = join.nickname.trim().to_string();
+                if nickname.is_empty() {
+                    if let Some(student) = &student {
+                        nickname = student.display_name.clone();
+                    }
+                }
+                let words = state.session_word_overrides(session_id).await;
+                let student_id = student.as_ref().map(|s| s.id);
+                // Checked and admitted under one lock so two sockets cannot both take a free nickname.
+                let mut sessions = state.db.game_sessions.write().await;
+                let Some(session) = sessions.get_mut(&session_id) else { continue };
+                if session.kicked.contains(&nickname) {
+                    let _ = direct_tx.send(WsEnvelope::new(
+                        ServerEvent::JoinRejected,
+                        json!({ "reason": "kicked" }),
+                        request_id.clone(),
+                    ));
+                    continue;
+                }
+                let refusal = lobby_refusal(session, &nickname, student_id, &state.profanity, &words);
+                if let Some((reason, message)) = refusal {
+                    let _ = direct_tx.send(WsEnvelope::new(
+                        ServerEvent::JoinRejected,
+                        json!({ "reason": reason, "message": message }),
+                        request_id.clone(),
+                    ));
+                    continue;
+                }
+                if let Some(channels) = state.db.broadcasters.get(&room_code) {
+                    if let Some(previous) = current_nickname.as_deref().filter(|p| *p != nickname) {
+                        channels.detach(previous, &direct_tx);
+                    }
+                    channels.attach(&nickname, direct_tx.clone());
+                }
+                nickname_tx.send_replace(Some(nickname.clone()));
+                current_nickname = Some(nickname.clone());
+                let resume_token = admit_participant(&state, session, &nickname, student_id);
+                let joined = json!({ "sessionId": session.id, "nickname": nickname, "resumeToken": resume_token });
+                let _ = direct_tx.send(WsEnvelope::new(ServerEvent::Joined, joined, request_id.clone()));
+                if let Some(countdown) = session.start_countdown() {
+                    let _ = direct_tx.send(WsEnvelope::new(ServerEvent::StartCountdown, countdown, None));
+                }
+
+                if let Some(bc) = state.db.broadcasters.get(&room_code) {
+                    let update = waiting_room_payload(session);
+                    let _ = bc.send(WsEnvelope::new(ServerEvent::WaitingRoomUpdate, update, request_id.clone()));
+                    if let Some(countdown) = lobby_countdown(&state, session) {
+                        let _ = bc.send(WsEnvelope::new(ServerEvent::StartCountdown, countdown, None));
+                    }
+                }
+            }
+
+            ClientEvent::AnswerSubmit(submit) => {
+                let Some(nickname) = current_nickname.clone() else {
+                    let _ = direct_tx.send(not_joined());
+                    continue;
+                };
+                if session_paused(&state, session_id).await {
+                    let _ = direct_tx.send(paused(request_id.clone()));
+                    continue;
+                }
+                submit_answer(&state, session_id, &room_code, &nickname, submit, request_id.clone()).await;
+            }
+
+            ClientEvent::RequestHint(hint) => {
+                let Some(nickname) = current_nickname.clone() else {
+                    let _ = direct_tx.send(not_joined());
+                    continue;
+                };
+                let reply = request_hint(&state, session_id, &nickname, &hint.question_id, request_id.clone()).await;
+                let _ = direct_tx.send(reply);
+            }
+
+            ClientEvent::RequestStats {} => {
+                let sessions = state.db.game_sessions.read().await;
+                let Some(session) = sessions.get(&session_id) else { continue };
+                let stats = stats_payload(session);
+                let _ = direct_tx.send(WsEnvelope::new(ServerEvent::StatsUpdate, stats, request_id.clone()));
+            }
+
+            ClientEvent::ReviewRequest {} => {
+                let Some(nickname) = current_nickname.clone() else {
+                    let _ = direct_tx.send(not_joined());
+                    continue;
+                };
+                let sessions = state.db.game_sessions.read().await;
+                let Some(session) = sessions.get(&session_id) else { continue; };
+                if session.status != "review" {
+                    continue;
+                }
+                let quizzes = state.db.quizzes.read().await;
+                let explanation = |quiz_id: i64, question_id: &str| {
+                    quizzes
+                        .get(&quiz_id)
+                        .and_then(|q| q.questions.iter().find(|q| q.id == question_id))
+                        .and_then(|q| q.explanation.clone())
+                };
+                let answers: Vec<_> = session
+                    .answers
+                    .get(&nickname)
+                    .map(|items| {
+                        items
+                            .iter()
+                            .map(|a| json!({
+                                "quizId": a.quiz_id,
+                                "questionId": a.question_id,
+                                "answer": a.answer,
+                                "correct": a.correct,
+                                "explanation": explanation(a.quiz_id, &a.question_id),
+                                "answeredAt": a.answered_at.to_rfc3339()
+                            }))
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                let _ = direct_tx.send(WsEnvelope::new(
+                    ServerEvent::ReviewData,
+                    json!({
+                        "sessionId": session.id,
+                        "reviewUntil": session.review_until.map(|t| t.to_rfc3339()),
+                        "answers": answers
+                    }),
+                    request_id.clone(),
+                ));
+            }
+
+            ClientEvent::FocusChange(change) => {
+                let Some(nickname) = current_nickname.clone() else {
+                    let _ = direct_tx.send(not_joined());
+                    continue;
+                };
+                let mut sessions = state.db.game_sessions.write().await;
+                let Some(session) = sessions.get_mut(&session_id) else { continue };
+                // Only the game itself is of interest; the lobby and the review are not.
+                if matches!(session.status.as_str(), "active" | "paused") {
+                    session.integrity.entry(nickname).or_default().record_focus(change.focused, Utc::now());
+                }
+            }
+
+            ClientEvent::RequestQuestion(request) => {
+                let Some(nickname) = current_nickname.clone() else {
+                    let _ = direct_tx.send(not_joined());
+                    continue;
+                };
+                if session_paused(&state, session_id).await {
+                    let _ = direct_tx.send(paused(request_id.clone()));
+                    continue;
+                }
+                if let Some(reply) = host_question_for(&state, session_id, &nickname, request_id.clone()).await {
+                    let _ = direct_tx.send(reply);
+                    continue;
+                }
+                push_question(&state, session_id, &room_code, &nickname, request.reason, request_id.clone()).await;
+            }
+
+            ClientEvent::KickParticipant(kick) => {
+                let mut sessions = state.db.game_sessions.write().await;
+                let Some(session) = sessions.get_mut(&session_id) else { continue };
+                if session.participants.remove(&kick.nickname).is_none() {
+                    let message = format!("no participant {}", kick.nickname);
+                    let _ = direct_tx.send(error("unknown_participant", message));
+                    continue;
+                }
+                // Answers already given stay in the results.
+                session.kicked.push(kick.nickname.clone());
+                if let Some(bc) = state.db.broadcasters.get(&room_code) {
+                    let kicked = json!({ "sessionId": session.id, "nickname": kick.nickname });
+                    bc.kick(&kick.nickname, WsEnvelope::new(ServerEvent::Kicked, kicked, request_id.clone()));
+                    let update = waiting_room_payload(session);
+                    let _ = bc.send(WsEnvelope::new(ServerEvent::WaitingRoomUpdate, update, request_id.clone()));
+                }
+            }
+
+            event @ (ClientEvent::PauseSession {} | ClientEvent::ResumeSession {}) => {
+                let pause = matches!(event, ClientEvent::PauseSession {});
+                if let Err(status) = set_paused(&state, session_id, pause, request_id.clone()).await {
+                    let _ = direct_tx.send(error("invalid_state", format!("session is {}", status)));
+                }
+            }
+
+            ClientEvent::PushNextQuestion(push) => {
+                let targets: Vec<String> = {
+                    let sessions = state.db.game_sessions.read().await;
+                    let Some(session) = sessions.get(&session_id) else { continue };
+                    if session.status != "active" {
+                        let message = format!("session is {}", session.status);
+                        let _ = direct_tx.send(error("invalid_state", message));
+                        continue;
+                    }
+                    if session.pacing == SessionPacing::Host {
+                        if push.nickname.is_some() {
+                            let message = "a host-paced session moves everyone together".to_string();
+                            let _ = direct_tx.send(error("invalid_state", message));
+                            continue;
+                        }
+                        drop(sessions);
+                        advance_host_question(&state, session_id, &room_code, request_id.clone()).await;
+                        continue;
+                    }
+                    match push.nickname {
+                        Some(nickname) if session.participants.contains_key(&nickname) => vec![nickname],
+                        Some(nickname) => {
+                            let message = format!("no participant {}", nickname);
+                            let _ = direct_tx.send(error("unknown_participant", message));
+                            continue;
+                        }
+                        None => session
+                            .participants
+                            .values()
+                            .filter(|p| !matches!(p.join_state.as_str(), "left" | "disconnected"))
+                            .map(|p| p.nickname.clone())
+                            .collect(),
+                    }
+                };
+                for nickname in targets {
+                    let reason = "teacher".to_string();
+                    push_question(&state, session_id, &room_code, &nickname, reason, request_id.clone()).await;
+                }
+            }
+
+            ClientEvent::BeginCountdown(begin) => {
+                let mut sessions = state.db.game_sessions.write().await;
+                let Some(session) = sessions.get_mut(&session_id) else { continue };
+                let secs = begin.seconds.unwrap_or(session.lobby.countdown_secs);
+                if !COUNTDOWN_SECS_RANGE.contains(&secs) {
+                    let (min, max) = (COUNTDOWN_SECS_RANGE.start(), COUNTDOWN_SECS_RANGE.end());
+                    let message = format!("seconds must be within {}..={}", min, max);
+                    let _ = direct_tx.send(error("invalid_payload", message));
+                    continue;
+                }
+                let Some(countdown) = begin_countdown(&state, session, secs) else {
+                    let message = format!("session is {}", session.status);
+                    let _ = direct_tx.send(error("invalid_state", message));
+                    continue;
+                };
+                if let Some(bc) = state.db.broadcasters.get(&room_code) {
+                    let _ = bc.send(WsEnvelope::new(ServerEvent::StartCountdown, countdown, request_id.clone()));
+                }
+            }
+
+            ClientEvent::ReviewNext {} => {
+                let words = state.session_word_overrides(session_id).await;
+                let mut sessions = state.db.game_sessions.write().await;
+                let Some(session) = sessions.get_mut(&session_id) else { continue };
+                if session.status != "review" {
+                    let message = format!("session is {}", session.status);
+                    let _ = direct_tx.send(error("invalid_state", message));
+                    continue;
+                }
+                let quiz = state.db.quizzes.read().await.get(&session.quiz_id).cloned();
+                let Some(quiz) = quiz else { continue };
+                let position = session.review_position.map_or(0, |shown| shown + 1);
+                let Some(question) = quiz.questions.get(position) else {
+                    let _ = direct_tx.send(error("invalid_state", "every question was reviewed".into()));
+                    continue;
+                };
+                session.review_position = Some(position);
+                let since = chrono::DateTime::<Utc>::MIN_UTC;
+                let mut review = class_answers(session, &question.id, since, &state.profanity, &words);
+                review["sessionId"] = json!(session.id);
+                review["quizId"] = json!(session.quiz_id);
+                review["position"] = json!(position);
+                review["total"] = json!(quiz.questions.len());
+                review["question"] = json!(question);
+                review["correctAnswer"] = json!(question.answer);
+                review["explanation"] = json!(question.explanation);
+                if let Some(bc) = state.db.broadcasters.get(&room_code) {
+                    let _ = bc.send(WsEnvelope::new(ServerEvent::ReviewQuestion, review, request_id.clone()));
+                }
+            }
+        }
+    }
+
+    // A participant who already reconnected on another socket, or was kicked, keeps their state.
+    let owned = current_nickname.filter(|nickname| match state.db.broadcasters.get(&room_code) {
+        Some(channels) => channels.detach(nickname, &direct_tx),
+        None => true,
+    });
+    if let Some(nickname) = owned {
+        let mut sessions = state.db.game_sessions.write().await;
+        if let Some(session) = sessions.get_mut(&session_id) {
+            if let Some(p) = session.participants.get_mut(&nickname) {
+                p.join_state = if timed_out { "disconnected".into() } else { "left".into() };
+                let playing = matches!(session.status.as_str(), "active" | "paused");
+                if let Some(question_id) = p.pending_question.clone().filter(|_| playing) {
+                    session.disruptions.entry(nickname.clone()).or_default().push(Disruption {
+                        quiz_id: session.quiz_id,
+                        question_id,
+                        disconnected_at: Utc::now(),
+                        reconnected_at: None,
+                        extended_ms: 0,
+                    });
+                }
+                if let Some(bc) = state.db.broadcasters.get(&room_code) {
+                    let update = waiting_room_payload(session);
+                    let _ = bc.send(WsEnvelope::new(ServerEvent::WaitingRoomUpdate, update, None));
+                }
+            }
+        }
+    }
+
+    send_task.abort();
+    info!("ws disconnected for room {}", room_code);
+}
+
+// Why the lobby settings keep `nickname` out: a `join_rejected` reason and a message for the student.
+// A nickname already in the room is only given back to the same student account, which only has to follow
+// the naming rules; anyone else reconnects with the participant's resume token.
+fn lobby_refusal(
+    session: &SessionRecord,
+    nickname: &str,
+    student_id: Option<i64>,
+    profanity: &ProfanityFilter,
+    words: &WordOverrides,
+) -> Option<(&'static str, String)> {
+    let lobby = &session.lobby;
+    if let Some(problem) = lobby.nickname_problem(nickname) {
+        return Some(("invalid_nickname", problem));
+    }
+    // The room is shown on the projector; checked on every join so a word the teacher blocks mid-game
+    // also keeps a reconnecting participant out.
+    if profanity.is_offensive(nickname, words) {
+        return Some(("inappropriate_nickname", "please choose another nickname".into()));
+    }
+    if let Some(participant) = session.participants.get(nickname) {
+        if student_id.is_some() && participant.student_id == student_id {
+            return None;
+        }
+        return Some(("nickname_taken", "this nickname is already in the room".into()));
+    }
+    if !lobby.allow_late_join && session.status != "waiting" {
+        return Some(("late_join_closed", "the session has already started".into()));
+    }
+    let in_room = session.participants.keys().filter(|n| !session.kicked.contains(n)).count();
+    match lobby.max_participants {
+        Some(max) if in_room >= max as usize => Some(("room_full", format!("the room takes {} participants", max))),
+        _ => None,
+    }
+}
+
+// Begins the start countdown once `minPlayers` are in the waiting room, unless a start is already set.
+// Returns the `start_countdown` payload to broadcast.
+fn lobby_countdown(state: &AppState, session: &mut SessionRecord) -> Option<serde_json::Value> {
+    let min_players = session.lobby.min_players?;
+    let in_room = session
+        .participants
+        .values()
+        .filter(|p| !matches!(p.join_state.as_str(), "left" | "disconnected"))
+        .count();
+    if session.starts_at.is_some() || in_room < min_players as usize {
+        return None;
+    }
+    begin_countdown(state, session, session.lobby.countdown_secs)
+}
+
+// Sets the start `secs` from now, replacing any start set before, and hands it to the start timer.
+// None unless the session is still waiting.
+fn begin_countdown(state: &AppState, session: &mut SessionRecord, secs: u32) -> Option<serde_json::Value> {
+    if session.status != "waiting" {
+        return None;
+    }
+    let starts_at = Utc::now() + chrono::Duration::seconds(secs as i64);
+    session.starts_at = Some(starts_at);
+    state.schedule_start(session.id, starts_at);
+    session.start_countdown()
+}
+
+// Puts `nickname` in the session, or back in it. Returns the participant's new resume token.
+fn admit_participant(state: &AppState, session: &mut SessionRecord, nickname: &str, student_id: Option<i64>) -> String {
+    // Rejoining keeps the participant as they left it, like a resume: position in the quiz, the open
+    // question and its deadline, hints already charged, questions already scored, lives left and the
+    // resume token, so a reconnect neither refills anything nor lets answers count twice.
+    if let Some(resume_token) = return_participant(state, session, nickname) {
+        return resume_token;
+    }
+    let resume_token = uuid::Uuid::new_v4().to_string();
+    let mut participant = ParticipantState {
+        nickname: nickname.to_string(),
+        join_state: "waiting".into(),
+        current_question_index: 0,
+        student_id,
+        question_deadline: None,
+        attempts: 0,
+        question_order: Vec::new(),
+        hints: Vec::new(),
+        resume_token: resume_token.clone(),
+        pending_question: None,
+        question_pushed_at: None,
+        shuffle_seed: rand::random(),
+        lives: None,
+        answered: HashMap::new(),
+    };
+    let mode = state.game_modes.for_session(&session.game_mode);
+    mode.on_join(&session.game_config(), &mut participant);
+    session.participants.insert(nickname.to_string(), participant);
+    session
+        .stats
+        .entry(nickname.to_string())
+        .or_insert(StudentStats { nickname: nickname.to_string(), ..Default::default() });
+    resume_token
+}
+
+// Puts a participant already in the room back in play and returns their resume token. Closes the disruption
+// left open by their disconnect; under `disconnectGraceSecs` the time away, up to the grace, moves the open
+// question's deadline (and the push time the speed bonus counts from) on and restarts its timer.
+fn return_participant(state: &AppState, session: &mut SessionRecord, nickname: &str) -> Option<String> {
+    let grace = chrono::Duration::seconds(session.game_config().disconnect_grace_secs as i64);
+    let self_paced = session.pacing == SessionPacing::SelfPaced;
+    let p = session.participants.get_mut(nickname)?;
+    p.join_state = if session.status == "waiting" { "waiting".into() } else { "playing".into() };
+    let open = session.disruptions.get_mut(nickname).and_then(|d| d.last_mut()).filter(|d| d.reconnected_at.is_none());
+    let Some(disruption) = open else { return Some(p.resume_token.clone()) };
+    let now = Utc::now();
+    disruption.reconnected_at = Some(now);
+    let still_open = disruption.quiz_id == session.quiz_id
+        && p.pending_question.as_deref() == Some(disruption.question_id.as_str());
+    if !self_paced || !still_open || grace.is_zero() {
+        return Some(p.resume_token.clone());
+    }
+    if let Some((question_id, deadline)) = p.question_deadline.as_mut() {
+        let extension = (now - disruption.disconnected_at).clamp(chrono::Duration::zero(), grace);
+        *deadline += extension;
+        disruption.extended_ms = extension.num_milliseconds();
+        if let Some(pushed_at) = p.question_pushed_at.as_mut() {
+            *pushed_at += extension;
+        }
+        spawn_question_timer(state, session.id, &session.room_code, nickname, question_id, *deadline);
+    }
+    Some(p.resume_token.clone())
+}
+
+async fn session_paused(state: &AppState, session_id: i64) -> bool {
+    state.db.game_sessions.read().await.get(&session_id).is_some_and(|s| s.status == "paused")
+}
+
+fn paused(request_id: Option<String>) -> WsEnvelope {
+    ProtocolError::new("session_paused", "the teacher paused the session", request_id).into_envelope()
+}
+
+// Puts the participant holding `resume_token` back in the room as they left it. Returns their nickname,
+// the `resumed` snapshot and the `waiting_room_update` showing them back.
+async fn resume_participant(
+    state: &AppState,
+    session_id: i64,
+    resume_token: &str,
+) -> Option<(String, serde_json::Value, serde_json::Value)> {
+    let mut sessions = state.db.game_sessions.write().await;
+    let session = sessions.get_mut(&session_id)?;
+    let status = session.status.clone();
+    let nickname = session
+        .participants
+        .values()
+        .find(|p| !p.resume_token.is_empty() && p.resume_token == resume_token)?
+        .nickname
+        .clone();
+    return_participant(state, session, &nickname)?;
+    let participant = session.participants.get(&nickname)?.clone();
+    let (pending, deadline) = pending_question(state, session, &participant).await;
+    let snapshot = json!({
+        "sessionId": session.id,
+        "nickname": participant.nickname,
+        "resumeToken": participant.resume_token,
+        "status": status,
+        "gameMode": session.game_mode,
+        "quizId": session.quiz_id,
+        "currentQuestionIndex": participant.current_question_index,
+        "stats": session.stats.get(&participant.nickname),
+        "hintsUsed": participant.hints.len(),
+        "question": pending,
+        "deadline": deadline,
+    });
+    Some((participant.nickname, snapshot, waiting_room_payload(session)))
+}
+
+// The question pushed to the participant and not answered yet, with its answer deadline if it has one.
+async fn pending_question(
+    state: &AppState,
+    session: &SessionRecord,
+    participant: &ParticipantState,
+) -> (Option<crate::models::Question>, Option<String>) {
+    let Some(question_id) = &participant.pending_question else { return (None, None) };
+    let question = state.db.quizzes.read().await.get(&session.quiz_id).and_then(|quiz| {
+        let mut question = quiz.questions.iter().find(|q| &q.id == question_id).cloned()?;
+        if quiz.settings.shuffle_options && session.pacing == SessionPacing::SelfPaced {
+            shuffle_options(&mut question, participant.shuffle_seed);
+        }
+        Some(question)
+    });
+    let deadline = participant
+        .question_deadline
+        .as_ref()
+        .filter(|(id, _)| id == question_id)
+        .map(|(_, deadline)| deadline.to_rfc3339());
+    (question, deadline)
+}
+
+// Sent in place of the broadcasts a socket fell behind on: enough to redraw the room and the
+// participant's own screen.
+async fn state_sync(state: &AppState, session_id: i64, nickname: Option<&str>, missed: u64) -> Option<WsEnvelope> {
+    let sessions = state.db.game_sessions.read().await;
+    let session = sessions.get(&session_id)?;
+    let mut payload = json!({
+        "sessionId": session.id,
+        "status": session.status,
+        "quizId": session.quiz_id,
+        "missed": missed,
+        "participants": waiting_room_payload(session)["participants"],
+        "stats": stats_payload(session),
+    });
+    if let Some(participant) = nickname.and_then(|n| session.participants.get(n)) {
+        let (question, deadline) = pending_question(state, session, participant).await;
+        payload["participant"] = json!({
+            "nickname": participant.nickname,
+            "currentQuestionIndex": participant.current_question_index,
+            "question": question,
+            "deadline": deadline,
+        });
+    }
+    Some(WsEnvelope::new(ServerEvent::StateSync, payload, None))
+}
+
+// Pushes the participant's next question to their own socket, or announces the end of the quiz when
+// they have run out of questions.
+async fn push_question(
+    state: &AppState,
+    session_id: i64,
+    room_code: &str,
+    nickname: &str,
+    reason: String,
+    request_id: Option<String>,
+) {
+    let Some(served) = serve_question(state, session_id, room_code, nickname, reason, request_id.clone()).await
+    else {
+        return;
+    };
+    if let Some(bc) = state.db.broadcasters.get(room_code) {
+        match served {
+            ServedQuestion::Question(payload) => {
+                bc.send_to(nickname, WsEnvelope::new(ServerEvent::QuestionPush, payload, request_id));
+            }
+            ServedQuestion::Finished(end) => {
+                let _ = bc.send(end);
+            }
+            ServedQuestion::Out => {
+                bc.send_to(nickname, out_of_lives(request_id));
+            }
+        }
+    }
+}
+
+// The participant's next question, independent of how it reaches them.
+enum ServedQuestion {
+    // The `question_push` payload.
+    Question(serde_json::Value),
+    // What the room is told now that the participant has no questions left.
+    Finished(WsEnvelope),
+    Out,
+}
+
+// Moves the participant on to their next question and starts its timer. None when the session, the
+// participant or the quiz is gone.
+async fn serve_question(
+    state: &AppState,
+    session_id: i64,
+    room_code: &str,
+    nickname: &str,
+    reason: String,
+    request_id: Option<String>,
+) -> Option<ServedQuestion> {
+    let mut sessions = state.db.game_sessions.write().await;
+    let session = sessions.get_mut(&session_id)?;
+    let config = session.game_config();
+    let mode = state.game_modes.for_session(&session.game_mode);
+    let participant = session.participants.get_mut(nickname)?;
+    let quiz = {
+        let qmap = state.db.quizzes.read().await;
+        qmap.get(&session.quiz_id).cloned()
+    };
+    let quiz = quiz?;
+    if quiz.questions.is_empty() {
+        return None;
+    }
+    if quiz.settings.shuffle_questions && participant.question_order.len() != quiz.questions.len() {
+        participant.question_order = question_order(quiz.questions.len(), participant.shuffle_seed);
+    }
+    let mut question = match mode.next_question(&config, participant, quiz.questions.len()) {
+        NextQuestion::Question(idx) => quiz.questions[idx].clone(),
+        NextQuestion::Finished => return Some(ServedQuestion::Finished(out_of_questions(session, request_id))),
+        NextQuestion::Out => return Some(ServedQuestion::Out),
     };
-    state.db.game_sessions.write().await.insert(id, session);
-    state.db.rooms.write().await.insert(room_code.clone(), id);
-    let (tx, _) = broadcast::channel(200);
-    state.db.broadcasters.insert(room_code.clone(), tx);
 
-    let join_url = format!("http://localhost:5173/join?room={room_code}");
-    Ok((
-        StatusCode::CREATED,
-        Json(json!({ "sessionId": id, "roomCode": room_code, "joinUrl": join_url, "qrPayload": join_url })),
-    ))
+    if quiz.settings.shuffle_options {
+        shuffle_options(&mut question, participant.shuffle_seed);
+    }
+
+    // The deadline is enforced in submit_answer; the grace absorbs network latency.
+    question.time_limit_secs = config.time_limit(&question);
+    let deadline = question.time_limit_secs.map(|secs| {
+        Utc::now() + chrono::Duration::seconds(secs as i64) + LATE_ANSWER_GRACE
+    });
+    participant.question_deadline = deadline.map(|d| (question.id.clone(), d));
+    participant.pending_question = Some(question.id.clone());
+    participant.question_pushed_at = Some(Utc::now());
+    let mut payload = json!({ "question": question, "reason": reason });
+    if let Some(deadline) = deadline {
+        payload["deadline"] = json!(deadline.to_rfc3339());
+        payload["remainingMs"] = json!(remaining_ms(deadline));
+        spawn_question_timer(state, session_id, room_code, nickname, &question.id, deadline);
+    }
+    Some(ServedQuestion::Question(payload))
 }
 
-pub async fn start_session(
-    State(state): State<AppState>,
-    headers: HeaderMap,
-    jar: CookieJar,
-    Path(id): Path<i64>,
-) -> Result<Json<serde_json::Value>, AppError> {
-    let req_id = request_id_from_headers(&headers);
-    if !ensure_csrf(&headers, &jar, &state).await {
-        return Err(AppError::new(StatusCode::FORBIDDEN, "FORBIDDEN", "csrf token invalid", req_id));
+// Sent once the quiz has no questions left. More playlist quizzes may follow, in which case the room
+// waits for the teacher to advance instead of ending.
+fn out_of_lives(request_id: Option<String>) -> WsEnvelope {
+    ProtocolError::new("out_of_lives", "no lives left in this game", request_id).into_envelope()
+}
+
+fn out_of_questions(session: &SessionRecord, request_id: Option<String>) -> WsEnvelope {
+    if session.playlist_position + 1 < session.playlist.len() {
+        WsEnvelope::new(
+            ServerEvent::QuizFinished,
+            json!({ "sessionId": session.id, "quizId": session.quiz_id, "nextQuizPending": true }),
+            request_id,
+        )
+    } else {
+        WsEnvelope::new(
+            ServerEvent::EndQuiz,
+            json!({ "sessionId": session.id, "endedAt": Utc::now().to_rfc3339(), "resultsReady": true }),
+            request_id,
+        )
     }
-    let teacher_id = auth_teacher_id(&jar, &state).await
-        .ok_or_else(|| AppError::new(StatusCode::UNAUTHORIZED, "UNAUTHORIZED", "not logged in", request_id_from_headers(&headers)))?;
-    let (room_code, game_mode) = {
-        let mut sessions = state.db.game_sessions.write().await;
-        let session = sessions
-            .get_mut(&id)
-            .ok_or_else(|| AppError::new(StatusCode::NOT_FOUND, "NOT_FOUND", "session not found", request_id_from_headers(&headers)))?;
-        if session.teacher_id != teacher_id {
-            return Err(AppError::new(StatusCode::FORBIDDEN, "FORBIDDEN", "access denied", request_id_from_headers(&headers)));
+}
+
+// Answer time for host-paced questions without a time limit of their own.
+const HOST_QUESTION_SECS: u32 = 30;
+
+// Opens the next question for the whole room of a host-paced session. A question still open is closed
+// first, so the teacher gets its results even when moving on early.
+async fn advance_host_question(state: &AppState, session_id: i64, room_code: &str, request_id: Option<String>) {
+    close_host_question(state, session_id, room_code, None).await;
+    let mut sessions = state.db.game_sessions.write().await;
+    let Some(session) = sessions.get_mut(&session_id) else { return };
+    let quiz = state.db.quizzes.read().await.get(&session.quiz_id).cloned();
+    let Some(quiz) = quiz else { return };
+    let position = session.host_question.as_ref().map_or(0, |open| open.position + 1);
+    let Some(mut question) = quiz.questions.get(position).cloned() else {
+        if let Some(bc) = state.db.broadcasters.get(room_code) {
+            let _ = bc.send(out_of_questions(session, request_id));
         }
-        session.status = "active".into();
-        (session.room_code.clone(), session.game_mode.clone())
+        return;
     };
+    if quiz.settings.shuffle_options {
+        if let Some(options) = question.options.as_mut() {
+            options.shuffle(&mut rand::thread_rng());
+        }
+    }
 
-    if let Some(sender) = state.db.broadcasters.get(&room_code) {
-        let _ = sender.send(WsEnvelope {
-            event: "start_quiz".into(),
-            payload: json!({ "sessionId": id, "gameMode": game_mode, "startedAt": Utc::now().to_rfc3339() }),
-            request_id: None,
-            ts: Some(Utc::now().to_rfc3339()),
+    let opened_at = Utc::now();
+    let secs = session.game_config().time_limit(&question).unwrap_or(HOST_QUESTION_SECS);
+    let deadline = opened_at + chrono::Duration::seconds(secs as i64) + LATE_ANSWER_GRACE;
+    // Whoever is out of the game watches without a question of their own.
+    let mode = state.game_modes.for_session(&session.game_mode);
+    for p in session.participants.values_mut().filter(|p| !mode.is_out(p)) {
+        p.current_question_index = position;
+        p.attempts = 0;
+        p.question_deadline = Some((question.id.clone(), deadline));
+        p.pending_question = Some(question.id.clone());
+        p.question_pushed_at = Some(opened_at);
+    }
+    session.host_question =
+        Some(HostQuestion { position, question_id: question.id.clone(), opened_at, deadline, closed: false });
+    if let Some(bc) = state.db.broadcasters.get(room_code) {
+        let payload = json!({
+            "question": question,
+            "reason": "host",
+            "position": position,
+            "total": quiz.questions.len(),
+            "deadline": deadline.to_rfc3339(),
+            "remainingMs": remaining_ms(deadline),
         });
+        let _ = bc.send(WsEnvelope::new(ServerEvent::QuestionPush, payload, request_id));
     }
-    Ok(Json(json!({ "status": "active" })))
+    drop(sessions);
+    spawn_host_question_timer(state, session_id, room_code, position, deadline);
 }
 
-pub async fn end_session(
-    State(state): State<AppState>,
-    headers: HeaderMap,
-    jar: CookieJar,
-    Path(id): Path<i64>,
-) -> Result<Json<serde_json::Value>, AppError> {
-    let req_id = request_id_from_headers(&headers);
-    if !ensure_csrf(&headers, &jar, &state).await {
-        return Err(AppError::new(StatusCode::FORBIDDEN, "FORBIDDEN", "csrf token invalid", req_id));
+fn spawn_host_question_timer(
+    state: &AppState,
+    session_id: i64,
+    room_code: &str,
+    position: usize,
+    deadline: chrono::DateTime<Utc>,
+) {
+    let (state, room_code) = (state.clone(), room_code.to_string());
+    tokio::spawn(async move {
+        tokio::time::sleep((deadline - Utc::now()).to_std().unwrap_or_default()).await;
+        close_host_question(&state, session_id, &room_code, Some((position, deadline))).await;
+    });
+}
+
+// Stops taking answers to the open host-paced question and sends the teacher how the class answered.
+// A timer passes the position and deadline it was set for and only closes that question while the session
+// runs, so a timer outlived by a manual advance, a pause or a resume that moved the deadline does nothing.
+async fn close_host_question(
+    state: &AppState,
+    session_id: i64,
+    room_code: &str,
+    timer: Option<(usize, chrono::DateTime<Utc>)>,
+) {
+    let words = state.session_word_overrides(session_id).await;
+    let mut sessions = state.db.game_sessions.write().await;
+    let Some(session) = sessions.get_mut(&session_id) else { return };
+    if timer.is_some() && session.status == "paused" {
+        return;
     }
-    let teacher_id = auth_teacher_id(&jar, &state).await
-        .ok_or_else(|| AppError::new(StatusCode::UNAUTHORIZED, "UNAUTHORIZED", "not logged in", request_id_from_headers(&headers)))?;
-    let room_code = {
-        let mut sessions = state.db.game_sessions.write().await;
-        let session = sessions
-            .get_mut(&id)
-            .ok_or_else(|| AppError::new(StatusCode::NOT_FOUND, "NOT_FOUND", "session not found", request_id_from_headers(&headers)))?;
-        if session.teacher_id != teacher_id {
-            return Err(AppError::new(StatusCode::FORBIDDEN, "FORBIDDEN", "access denied", request_id_from_headers(&headers)));
+    let Some(open) = session.host_question.as_mut() else { return };
+    if open.closed || timer.is_some_and(|(position, deadline)| position != open.position || deadline != open.deadline) {
+        return;
+    }
+    open.closed = true;
+    let open = open.clone();
+    let mode = state.game_modes.for_session(&session.game_mode);
+    let nicknames: Vec<String> = session.participants.keys().cloned().collect();
+    let timeouts: Vec<_> = nicknames
+        .into_iter()
+        .filter_map(|nickname| {
+            let timeout = time_out_question(session, mode.as_ref(), &nickname, &open.question_id, open.deadline)?;
+            Some((nickname, timeout))
+        })
+        .collect();
+    let results = host_question_results(session, &open, &state.profanity, &words);
+    if let Some(bc) = state.db.broadcasters.get(room_code) {
+        if !timeouts.is_empty() {
+            for (nickname, timeout) in timeouts {
+                bc.send_to(&nickname, WsEnvelope::new(ServerEvent::QuestionTimeout, timeout, None));
+            }
+            let _ = bc.send(WsEnvelope::new(ServerEvent::StatsUpdate, stats_payload(session), None));
+            let _ = bc.send(WsEnvelope::new(ServerEvent::LeaderboardUpdate, leaderboard_payload(session), None));
         }
-        session.status = "finished".into();
-        session.room_code.clone()
-    };
+        bc.send_to_teachers(WsEnvelope::new(ServerEvent::QuestionResults, results, None));
+    }
+}
 
-    if let Some(sender) = state.db.broadcasters.get(&room_code) {
-        let _ = sender.send(WsEnvelope {
-            event: "end_quiz".into(),
-            payload: json!({ "sessionId": id, "endedAt": Utc::now().to_rfc3339(), "resultsReady": true }),
-            request_id: None,
-            ts: Some(Utc::now().to_rfc3339()),
+// Payload of `question_results`.
+fn host_question_results(
+    session: &SessionRecord,
+    open: &HostQuestion,
+    profanity: &ProfanityFilter,
+    words: &WordOverrides,
+) -> serde_json::Value {
+    let mut results = class_answers(session, &open.question_id, open.opened_at, profanity, words);
+    results["sessionId"] = json!(session.id);
+    results["quizId"] = json!(session.quiz_id);
+    results["questionId"] = json!(open.question_id);
+    results["position"] = json!(open.position);
+    results
+}
+
+// Most frequent open-text answers shown with a question's results.
+const OPEN_ANSWERS_SHOWN: usize = 20;
+
+// How the class answered a question of the current quiz since `since`. Each participant's last in-time
+// answer counts; earlier ones were retried. Choice questions also get how often each option was picked, open
+// questions the most frequent answers, which go up on the projector and so pass through the profanity filter.
+fn class_answers(
+    session: &SessionRecord,
+    question_id: &str,
+    since: chrono::DateTime<Utc>,
+    profanity: &ProfanityFilter,
+    words: &WordOverrides,
+) -> serde_json::Value {
+    let (mut answered, mut correct) = (0usize, 0usize);
+    let mut options: BTreeMap<String, u32> = BTreeMap::new();
+    // Keyed by the lowercased text; the first spelling seen is shown.
+    let mut open_answers: HashMap<String, (String, u32)> = HashMap::new();
+    for answers in session.answers.values() {
+        let last = answers.iter().rev().find(|a| {
+            a.quiz_id == session.quiz_id && a.question_id == question_id && a.answered_at >= since && !a.late
         });
+        let Some(last) = last else { continue };
+        answered += 1;
+        if last.correct {
+            correct += 1;
+        }
+        match &last.answer {
+            SubmittedAnswer::Single { option_id } => *options.entry(option_id.clone()).or_default() += 1,
+            SubmittedAnswer::Multi { option_ids } => {
+                for id in option_ids {
+                    *options.entry(id.clone()).or_default() += 1;
+                }
+            }
+            SubmittedAnswer::Open { text } if !text.trim().is_empty() => {
+                let text = text.trim();
+                open_answers.entry(text.to_lowercase()).or_insert_with(|| (text.to_string(), 0)).1 += 1;
+            }
+            _ => {}
+        }
     }
-    Ok(Json(json!({ "status": "finished" })))
+    let mut open_answers: Vec<(String, u32)> = open_answers.into_values().collect();
+    open_answers.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    let open_answers: Vec<serde_json::Value> = open_answers
+        .into_iter()
+        .take(OPEN_ANSWERS_SHOWN)
+        .map(|(text, count)| json!({ "text": profanity.mask(&text, words), "count": count }))
+        .collect();
+    let participants = session.participants.len();
+    json!({
+        "participants": participants,
+        "answered": answered,
+        "correct": correct,
+        "wrong": answered - correct,
+        "noAnswer": participants.saturating_sub(answered),
+        "options": options,
+        "openAnswers": open_answers,
+    })
 }
 
-pub async fn session_results(
-    State(state): State<AppState>,
-    headers: HeaderMap,
-    jar: CookieJar,
-    Path(id): Path<i64>,
-) -> Result<Json<serde_json::Value>, AppError> {
-    let req_id = request_id_from_headers(&headers);
-    let teacher_id = auth_teacher_id(&jar, &state).await
-        .ok_or_else(|| AppError::new(StatusCode::UNAUTHORIZED, "UNAUTHORIZED", "not logged in", req_id.clone()))?;
-    let session = state
-        .db
-        .game_sessions
-        .read()
-        .await
-        .get(&id)
-        .cloned()
-        .ok_or_else(|| AppError::new(StatusCode::NOT_FOUND, "NOT_FOUND", "session not found", req_id.clone()))?;
-    if session.teacher_id != teacher_id {
-        return Err(AppError::new(StatusCode::FORBIDDEN, "FORBIDDEN", "access denied", req_id));
+// In a host-paced session a student cannot move ahead alone: asking for a question returns the one open
+// for the room, as long as they have not answered it yet. None for self-paced sessions.
+async fn host_question_for(
+    state: &AppState,
+    session_id: i64,
+    nickname: &str,
+    request_id: Option<String>,
+) -> Option<WsEnvelope> {
+    let mut sessions = state.db.game_sessions.write().await;
+    let session = sessions.get_mut(&session_id)?;
+    if session.pacing != SessionPacing::Host {
+        return None;
     }
-
-    let class_correct: u32 = session.stats.values().map(|s| s.correct).sum();
-    let class_wrong: u32 = session.stats.values().map(|s| s.wrong).sum();
-    let total = class_correct + class_wrong;
-    let class_pct = if total == 0 {
-        0.0
-    } else {
-        class_correct as f64 * 100.0 / total as f64
+    let waiting = || ProtocolError::new("host_paced", "the teacher opens the next question", request_id.clone());
+    let Some(open) = session.host_question.clone().filter(|open| !open.closed) else {
+        return Some(waiting().into_envelope());
     };
+    let answered = session.answers.get(nickname).is_some_and(|answers| {
+        answers.iter().any(|a| a.question_id == open.question_id && a.answered_at >= open.opened_at)
+    });
+    let mode = state.game_modes.for_session(&session.game_mode);
+    let p = session.participants.get_mut(nickname)?;
+    if mode.is_out(p) {
+        return Some(out_of_lives(request_id.clone()));
+    }
+    // Late joiners get the open question too; whoever already answered it waits for the next one.
+    if p.pending_question.as_ref() != Some(&open.question_id) {
+        if answered {
+            return Some(waiting().into_envelope());
+        }
+        p.question_deadline = Some((open.question_id.clone(), open.deadline));
+        p.pending_question = Some(open.question_id.clone());
+        // Speed is measured from when the question opened for the room, so joining late is no head start.
+        p.question_pushed_at = Some(open.opened_at);
+        p.current_question_index = open.position;
+    }
+    let quizzes = state.db.quizzes.read().await;
+    let quiz = quizzes.get(&session.quiz_id)?;
+    let question = quiz.questions.get(open.position)?;
+    let total = quiz.questions.len();
+    let payload = json!({
+        "question": question,
+        "reason": "host",
+        "position": open.position,
+        "total": total,
+        "deadline": open.deadline.to_rfc3339(),
+        "remainingMs": remaining_ms(open.deadline),
+    });
+    Some(WsEnvelope::new(ServerEvent::QuestionPush, payload, request_id))
+}
 
-    let students: Vec<_> = session
-        .stats
-        .values()
-        .map(|s| json!({
-            "nickname": s.nickname,
-            "correct": s.correct,
-            "wrong": s.wrong,
-            "correctPct": s.correct_pct()
-        }))
-        .collect();
+// Sent with every deadline, so clients count down from the server's clock rather than their own.
+fn remaining_ms(deadline: chrono::DateTime<Utc>) -> i64 {
+    (deadline - Utc::now()).num_milliseconds().max(0)
+}
 
-    let mistakes: Vec<_> = session
-        .mistakes
-        .iter()
-        .map(|(nick, qs)| json!({"nickname": nick, "questions": qs}))
-        .collect();
+fn spawn_question_timer(
+    state: &AppState,
+    session_id: i64,
+    room_code: &str,
+    nickname: &str,
+    question_id: &str,
+    deadline: chrono::DateTime<Utc>,
+) {
+    let (state, room_code, nickname, question_id) =
+        (state.clone(), room_code.to_string(), nickname.to_string(), question_id.to_string());
+    tokio::spawn(async move {
+        let mut wake = deadline;
+        loop {
+            tokio::time::sleep((wake - Utc::now()).to_std().unwrap_or_default()).await;
+            let mut sessions = state.db.game_sessions.write().await;
+            let Some(session) = sessions.get_mut(&session_id) else { return };
+            // Resuming moves the deadline and sets a new timer, so this one then no longer matches.
+            if matches!(session.status.as_str(), "review" | "finished" | "paused") {
+                return;
+            }
+            // Coming back moves the deadline and sets a new timer as well; until then the question waits.
+            if let Some(held) = held_for_offline(session, &nickname, &question_id, deadline) {
+                if Utc::now() < held {
+                    wake = held;
+                    continue;
+                }
+            }
+            let mode = state.game_modes.for_session(&session.game_mode);
+            let Some(timeout) = time_out_question(session, mode.as_ref(), &nickname, &question_id, deadline) else {
+                return;
+            };
+            if let Some(bc) = state.db.broadcasters.get(&room_code) {
+                bc.send_to(&nickname, WsEnvelope::new(ServerEvent::QuestionTimeout, timeout, None));
+                let _ = bc.send(WsEnvelope::new(ServerEvent::StatsUpdate, stats_payload(session), None));
+                let _ = bc.send(WsEnvelope::new(ServerEvent::LeaderboardUpdate, leaderboard_payload(session), None));
+            }
+            return;
+        }
+    });
+}
 
-    Ok(Json(json!({
-        "session": {"id": session.id, "roomCode": session.room_code, "status": session.status, "gameMode": session.game_mode},
-        "classStats": {"correct": class_correct, "wrong": class_wrong, "correctPct": class_pct},
-        "studentStats": students,
-        "mistakesByStudent": mistakes
-    })))
+// How long a question pushed with `deadline` waits for a participant who dropped out while it was open:
+// the deadline plus `disconnectGraceSecs`, the most their return can add. None when the participant is
+// in the room or the session gives no grace.
+fn held_for_offline(
+    session: &SessionRecord,
+    nickname: &str,
+    question_id: &str,
+    deadline: chrono::DateTime<Utc>,
+) -> Option<chrono::DateTime<Utc>> {
+    let grace = session.game_config().disconnect_grace_secs;
+    let offline = session
+        .disruptions
+        .get(nickname)
+        .and_then(|d| d.last())
+        .is_some_and(|d| d.reconnected_at.is_none() && d.quiz_id == session.quiz_id && d.question_id == question_id);
+    (offline && grace > 0).then(|| deadline + chrono::Duration::seconds(grace as i64))
 }
 
-pub async fn ws_handler(
-    ws: WebSocketUpgrade,
-    State(state): State<AppState>,
-    Path(room_code): Path<String>,
-) -> Response {
-    ws.on_upgrade(move |socket| ws_session(socket, state, room_code))
+// Scores a question the participant let run out as wrong with no points, and remembers it as answered so
+// a submit arriving later is not scored again. Does nothing, returning None, unless this very push (same
+// question and deadline) is still unanswered. Otherwise returns the `question_timeout` payload.
+fn time_out_question(
+    session: &mut SessionRecord,
+    mode: &dyn GameMode,
+    nickname: &str,
+    question_id: &str,
+    deadline: chrono::DateTime<Utc>,
+) -> Option<serde_json::Value> {
+    let config = session.game_config();
+    let p = session.participants.get_mut(nickname)?;
+    let pushed = matches!(&p.question_deadline, Some((id, d)) if id == question_id && *d == deadline);
+    if !pushed || p.pending_question.as_deref() != Some(question_id) {
+        return None;
+    }
+    p.question_deadline = None;
+    p.pending_question = None;
+    p.attempts = 0;
+    p.current_question_index += 1;
+    let mut stats = session.stats.get_mut(nickname);
+    if let Some(stats) = stats.as_deref_mut() {
+        stats.wrong += 1;
+        stats.extend_streak(false);
+    }
+    let lives = mode.on_answer(&config, p, stats, false);
+    let next_action = next_action(false, mode.is_out(p));
+    let mut result = json!({
+        "questionId": question_id,
+        "correct": false,
+        "credit": 0.0,
+        "points": 0.0,
+        "late": true,
+        "nextAction": next_action
+    });
+    let mut timeout = json!({
+        "sessionId": session.id,
+        "questionId": question_id,
+        "deadline": deadline.to_rfc3339(),
+        "points": 0.0,
+        "nextAction": next_action
+    });
+    if let Some(lives) = lives {
+        result["livesLeft"] = json!(lives);
+        timeout["livesLeft"] = json!(lives);
+    }
+    p.answered.insert(format!("{}:{}", session.quiz_id, question_id), result);
+    session.mistakes.entry(nickname.to_string()).or_default().push(question_id.to_string());
+    Some(timeout)
+}
+
+fn next_action(retry: bool, out: bool) -> &'static str {
+    match (retry, out) {
+        (true, _) => "retry",
+        (false, true) => "out",
+        (false, false) => "continue",
+    }
 }
 
-async fn ws_session(stream: WebSocket, state: AppState, room_code: String) {
-    let session_id = {
-        let rooms = state.db.rooms.read().await;
-        match rooms.get(&room_code).copied() {
-            Some(v) => v,
-            None => return,
+// The answer goes only to the asking socket. A hint this participant already paid for is sent again
+// for free; a new one is generated once per question for the whole session, outside the sessions lock.
+async fn request_hint(
+    state: &AppState,
+    session_id: i64,
+    nickname: &str,
+    question_id: &str,
+    request_id: Option<String>,
+) -> WsEnvelope {
+    let reply = |event: ServerEvent, payload: serde_json::Value| WsEnvelope::new(event, payload, request_id.clone());
+    let rejected =
+        |reason: &str| reply(ServerEvent::HintRejected, json!({ "questionId": question_id, "reason": reason }));
+
+    let (quiz_id, teacher_id, cached, charged, used) = {
+        let sessions = state.db.game_sessions.read().await;
+        let Some(session) = sessions.get(&session_id) else { return rejected("not_active") };
+        let Some(p) = session.participants.get(nickname) else { return rejected("not_active") };
+        if session.status != "active" {
+            return rejected("not_active");
         }
+        let key = format!("{}:{}", session.quiz_id, question_id);
+        let cached = session.hints.get(&key).cloned();
+        (session.quiz_id, session.teacher_id, cached, p.hints.contains(&key), p.hints.len() as u32)
     };
-
-    let mut receiver = match state.db.broadcasters.get(&room_code) {
-        Some(sender) => sender.subscribe(),
-        None => return,
+    let key = format!("{}:{}", quiz_id, question_id);
+    let (budget, question) = {
+        let quizzes = state.db.quizzes.read().await;
+        let Some(quiz) = quizzes.get(&quiz_id) else { return rejected("not_active") };
+        (quiz.settings.hints_per_student, quiz.questions.iter().find(|q| q.id == question_id).cloned())
     };
+    if budget == 0 {
+        return rejected("disabled");
+    }
+    let Some(question) = question else { return rejected("unknown_question") };
+    if !charged && used >= budget {
+        return rejected("budget_exhausted");
+    }
 
-    let (mut sender_ws, mut receiver_ws) = stream.split();
-    let mut current_nickname: Option<String> = None;
-
-    let send_task = tokio::spawn(async move {
-        while let Ok(msg) = receiver.recv().await {
-            if let Ok(text) = serde_json::to_string(&msg) {
-                if sender_ws.send(Message::Text(text)).await.is_err() {
-                    break;
+    let hint = match cached {
+        Some(hint) => hint,
+        None => {
+            let Ok((client, provider)) = state.ai.client_for(None) else { return rejected("unavailable") };
+            // Hints are paid from the quota of the teacher running the session.
+            let Ok(client) = state.metered_ai_client(client, teacher_id).await else { return rejected("quota_exceeded") };
+            let system = state.db.prompt_set().await.get(PromptUseCase::Hints);
+            match ai_providers::generate_hint(client.as_ref(), provider, &system, &question).await {
+                Ok(hint) => {
+                    state.record_usage("ai_hint").await;
+                    hint
+                }
+                Err(failure) => {
+                    warn!("hint for {} failed: {}", key, failure.message);
+                    return rejected("unavailable");
                 }
             }
         }
-    });
+    };
 
-    while let Some(Ok(message)) = receiver_ws.next().await {
-        if let Message::Text(txt) = message {
-            let parsed: Result<WsEnvelope, _> = serde_json::from_str(&txt);
-            let Ok(env) = parsed else { continue; };
-
-            if env.event == "join_room" {
-                let role = env.payload.get("role").and_then(|v| v.as_str()).unwrap_or("student");
-                if role == "student" {
-                    let nickname = env
-                        .payload
-                        .get("nickname")
-                        .and_then(|v| v.as_str())
-                        .unwrap_or("")
-                        .trim()
-                        .to_string();
-                    if nickname.len() >= 2 {
-                        current_nickname = Some(nickname.clone());
-                        let mut sessions = state.db.game_sessions.write().await;
-                        if let Some(session) = sessions.get_mut(&session_id) {
-                            session.participants.insert(
-                                nickname.clone(),
-                                ParticipantState {
-                                    nickname: nickname.clone(),
-                                    join_state: "waiting".into(),
-                                    current_question_index: 0,
-                                },
-                            );
-                            session.stats.entry(nickname.clone()).or_insert(StudentStats {
-                                nickname: nickname.clone(),
-                                correct: 0,
-                                wrong: 0,
-                            });
-
-                            if let Some(bc) = state.db.broadcasters.get(&room_code) {
-                                let participants: Vec<_> = session
-                                    .participants
-                                    .values()
-                                    .map(|p| json!({"nickname": p.nickname, "state": p.join_state}))
-                                    .collect();
-                                let _ = bc.send(WsEnvelope {
-                                    event: "waiting_room_update".into(),
-                                    payload: json!({"sessionId": session.id, "participants": participants}),
-                                    request_id: env.request_id.clone(),
-                                    ts: Some(Utc::now().to_rfc3339()),
-                                });
-                            }
-                        }
-                    }
+    let mut sessions = state.db.game_sessions.write().await;
+    let Some(session) = sessions.get_mut(&session_id) else { return rejected("not_active") };
+    let hint = session.hints.entry(key.clone()).or_insert(hint).clone();
+    let Some(p) = session.participants.get_mut(nickname) else { return rejected("not_active") };
+    if !p.hints.contains(&key) {
+        if p.hints.len() as u32 >= budget {
+            return rejected("budget_exhausted");
+        }
+        p.hints.push(key);
+    }
+    let hints_left = budget.saturating_sub(p.hints.len() as u32);
+    reply(ServerEvent::HintPush, json!({ "questionId": question_id, "hint": hint, "hintsLeft": hints_left }))
+}
+
+// The write lock on game_sessions is shared by every room, so lock_wait is timed separately from the
+// work done while holding it.
+#[tracing::instrument(
+    name = "answer_submit",
+    skip_all,
+    fields(room = %room_code, nickname = %nickname, question_id = tracing::field::Empty, correct = tracing::field::Empty)
+)]
+async fn submit_answer(
+    state: &AppState,
+    session_id: i64,
+    room_code: &str,
+    nickname: &str,
+    submit: AnswerSubmit,
+    request_id: Option<String>,
+) {
+    let started = Instant::now();
+    let metrics = &state.metrics.answer_pipeline;
+    let Some(outcome) = answer_question(state, session_id, nickname, submit, request_id.clone()).await else {
+        return;
+    };
+    let Some(bc) = state.db.broadcasters.get(room_code) else { return };
+    match outcome {
+        AnswerOutcome::Out => {
+            bc.send_to(nickname, out_of_lives(request_id));
+        }
+        AnswerOutcome::Result(result, updates) if updates.is_empty() => {
+            bc.send_to(nickname, WsEnvelope::new(ServerEvent::AnswerResult, result, request_id));
+        }
+        AnswerOutcome::Result(result, updates) => {
+            let broadcast_started = Instant::now();
+            {
+                let _span = debug_span!("broadcast").entered();
+                bc.send_to(nickname, WsEnvelope::new(ServerEvent::AnswerResult, result, request_id));
+                for update in updates {
+                    let _ = bc.send(update);
                 }
-                continue;
             }
+            metrics.broadcast.record(broadcast_started.elapsed());
+            metrics.total.record(started.elapsed());
+        }
+    }
+}
 
-            if env.event == "answer_submit" {
-                let Some(nickname) = current_nickname.clone() else { continue; };
-                let question_id = env
-                    .payload
-                    .get("questionId")
-                    .and_then(|v| v.as_str())
-                    .unwrap_or_default()
-                    .to_string();
-                let answer_value = env.payload.get("answer").cloned().unwrap_or(json!({}));
-                let submitted: Result<SubmittedAnswer, _> = serde_json::from_value(answer_value);
-                let Ok(submitted) = submitted else { continue; };
+enum AnswerOutcome {
+    // The `answer_result` payload and, when the answer was scored just now, the room updates that follow.
+    Result(serde_json::Value, Vec<WsEnvelope>),
+    Out,
+}
 
-                let mut sessions = state.db.game_sessions.write().await;
-                let Some(session) = sessions.get_mut(&session_id) else { continue; };
-                let Some(p) = session.participants.get_mut(&nickname) else { continue; };
-                p.join_state = "playing".into();
+// Grades and records a submitted answer, independent of how it arrived. None when there is nothing to
+// answer: the session is over, or the participant or question is unknown.
+async fn answer_question(
+    state: &AppState,
+    session_id: i64,
+    nickname: &str,
+    submit: AnswerSubmit,
+    request_id: Option<String>,
+) -> Option<AnswerOutcome> {
+    let started = Instant::now();
+    let metrics = &state.metrics.answer_pipeline;
+    let AnswerSubmit { question_id, answer: submitted } = submit;
+    tracing::Span::current().record("question_id", question_id.as_str());
 
-                let quiz = {
-                    let qmap = state.db.quizzes.read().await;
-                    qmap.get(&session.quiz_id).cloned()
-                };
-                let Some(quiz) = quiz else { continue; };
-                let maybe_question = quiz.questions.iter().find(|q| q.id == question_id);
-                let Some(question) = maybe_question else { continue; };
-
-                let correct = score_answer(question, &submitted);
-                if let Some(s) = session.stats.get_mut(&nickname) {
-                    if correct {
-                        s.correct += 1;
-                    } else {
-                        s.wrong += 1;
-                        session
-                            .mistakes
-                            .entry(nickname.clone())
-                            .or_default()
-                            .push(question_id.clone());
-                    }
-                    // Move forward after any answer (no retry loop).
-                    p.current_question_index += 1;
-                }
+    let mut sessions = state.db.game_sessions.write().instrument(debug_span!("lock_wait")).await;
+    metrics.lock_wait.record(started.elapsed());
+    let session = sessions.get_mut(&session_id)?;
+    if session.status == "review" || session.status == "finished" {
+        return None;
+    }
+    let config = session.game_config();
+    let mode = state.game_modes.for_session(&session.game_mode);
+    let p = session.participants.get_mut(nickname)?;
+    p.join_state = "playing".into();
+    let answered_key = format!("{}:{}", session.quiz_id, question_id);
+    if let Some(result) = p.answered.get(&answered_key) {
+        let mut result = result.clone();
+        result["duplicate"] = json!(true);
+        return Some(AnswerOutcome::Result(result, Vec::new()));
+    }
+    if mode.is_out(p) {
+        return Some(AnswerOutcome::Out);
+    }
+
+    let lookup_started = Instant::now();
+    let quiz_id = session.quiz_id;
+    let quiz = async { state.db.quizzes.read().await.get(&quiz_id).cloned() }
+        .instrument(debug_span!("quiz_lookup"))
+        .await;
+    metrics.quiz_lookup.record(lookup_started.elapsed());
+    let quiz = quiz?;
+    let question = quiz.questions.iter().find(|q| q.id == question_id)?;
 
-                let class_correct: u32 = session.stats.values().map(|s| s.correct).sum();
-                let class_wrong: u32 = session.stats.values().map(|s| s.wrong).sum();
-                let total = class_correct + class_wrong;
-                let class_pct = if total == 0 {
-                    0.0
-                } else {
-                    class_correct as f64 * 100.0 / total as f64
+    let scoring_started = Instant::now();
+    let (grade, points, bonus, (lives, out), late, retries_left) = {
+        let _span = debug_span!("scoring").entered();
+        let now = Utc::now();
+        // A timed question only counts if it was pushed to this participant and answered in time. Every
+        // question of a host-paced session is timed.
+        let timed = config.time_limit(question).is_some() || session.pacing == SessionPacing::Host;
+        let late = timed
+            && !matches!(&p.question_deadline, Some((id, deadline)) if *id == question_id && now <= *deadline);
+        let mut grade = grade_answer(question, &submitted);
+        if late {
+            grade.correct = false;
+            grade.credit = 0.0;
+        }
+        // A wrong answer with attempts left is recorded but neither scored nor counted; the
+        // participant stays on the question and keeps its deadline.
+        let retries_left = quiz.settings.retries.saturating_sub(p.attempts);
+        let retry = !grade.correct && !late && retries_left > 0;
+        let points = if retry { 0.0 } else { grade.credit * question.points() };
+        let correct = grade.correct;
+        // Only answers to the question pushed to this participant have a latency to judge.
+        let latency_ms = p
+            .question_pushed_at
+            .filter(|_| p.pending_question.as_deref() == Some(question_id.as_str()))
+            .map(|pushed| (now - pushed).num_milliseconds().max(0));
+        session.answers.entry(nickname.to_string()).or_default().push(AnswerRecord {
+            quiz_id: session.quiz_id,
+            question_id: question_id.clone(),
+            answer: submitted.clone(),
+            correct,
+            credit: grade.credit,
+            points,
+            late,
+            answered_at: now,
+            latency_ms,
+        });
+        if let Some(latency_ms) = latency_ms {
+            session.integrity.entry(nickname.to_string()).or_default().record_latency(latency_ms);
+        }
+        let mut bonus = 0.0;
+        let mut lives = p.lives;
+        if retry {
+            p.attempts += 1;
+        } else if let Some(s) = session.stats.get_mut(nickname) {
+            if s.extend_streak(correct) > 0 {
+                // Latency counts from the push of this very question; one answered without a push gets
+                // only the streak part.
+                let latency = latency_ms.map(|ms| ms as f64 / 1000.0);
+                let window = match session.pacing {
+                    SessionPacing::Host => config.time_limit(question).unwrap_or(HOST_QUESTION_SECS),
+                    SessionPacing::SelfPaced => config.time_limit(question).unwrap_or(SPEED_BONUS_WINDOW_SECS),
                 };
+                bonus = mode.bonus(points, latency, window, s.streak);
+            }
+            s.score += points;
+            s.bonus += bonus;
+            if correct {
+                s.correct += 1;
+            } else {
+                s.wrong += 1;
+                session
+                    .mistakes
+                    .entry(nickname.to_string())
+                    .or_default()
+                    .push(question_id.clone());
+            }
+            lives = mode.on_answer(&config, p, Some(s), correct);
+            p.question_deadline = None;
+            p.pending_question = None;
+            p.question_pushed_at = None;
+            p.attempts = 0;
+            p.current_question_index += 1;
+        }
+        (grade, points, bonus, (lives, mode.is_out(p)), late, if retry { Some(retries_left - 1) } else { None })
+    };
+    let correct = grade.correct;
+    metrics.scoring.record(scoring_started.elapsed());
+    tracing::Span::current().record("correct", correct);
 
-                if let Some(bc) = state.db.broadcasters.get(&room_code) {
-                    let _ = bc.send(WsEnvelope {
-                        event: "answer_result".into(),
-                        payload: json!({
-                            "questionId": question_id,
-                            "correct": correct,
-                            "nextAction": "continue"
-                        }),
-                        request_id: env.request_id.clone(),
-                        ts: Some(Utc::now().to_rfc3339()),
-                    });
+    let mut result = json!({
+        "questionId": question_id,
+        "correct": correct,
+        "credit": grade.credit,
+        "points": points,
+        "nextAction": next_action(retries_left.is_some(), out)
+    });
+    if retries_left.is_none() {
+        result["bonus"] = json!(bonus);
+        result["streak"] = json!(session.stats.get(nickname).map_or(0, |s| s.streak));
+    }
+    if let Some(lives) = lives {
+        result["livesLeft"] = json!(lives);
+    }
+    if late {
+        result["late"] = json!(true);
+    }
+    match retries_left {
+        Some(left) => result["retriesLeft"] = json!(left),
+        None if quiz.settings.show_correct_answer => result["correctAnswer"] = json!(question.answer),
+        None => {}
+    }
+    if let Some(blanks) = &grade.blanks {
+        result["blanks"] = json!(blanks);
+    }
+    if let Some(explanation) = &question.explanation {
+        result["explanation"] = json!(explanation);
+    }
+    if retries_left.is_none() {
+        if let Some(p) = session.participants.get_mut(nickname) {
+            p.answered.insert(answered_key, result.clone());
+        }
+    }
 
-                    let students: Vec<_> = session
-                        .stats
-                        .values()
-                        .map(|s| json!({
-                            "nickname": s.nickname,
-                            "correct": s.correct,
-                            "wrong": s.wrong,
-                            "correctPct": s.correct_pct()
-                        }))
-                        .collect();
-                    let _ = bc.send(WsEnvelope {
-                        event: "stats_update".into(),
-                        payload: json!({
-                            "class": {"correctPct": class_pct, "wrongPct": 100.0 - class_pct},
-                            "students": students
-                        }),
-                        request_id: env.request_id.clone(),
-                        ts: Some(Utc::now().to_rfc3339()),
-                    });
+    let updates = vec![
+        WsEnvelope::new(ServerEvent::StatsUpdate, stats_payload(session), request_id.clone()),
+        WsEnvelope::new(ServerEvent::LeaderboardUpdate, leaderboard_payload(session), request_id),
+    ];
+    Some(AnswerOutcome::Result(result, updates))
+}
 
-                }
+const LEADERBOARD_SIZE: usize = 10;
+
+// Students in leaderboard order with their rank. Students with the same points and correct answers
+// share a rank.
+fn ranked_places(session: &SessionRecord) -> Vec<(usize, &StudentStats)> {
+    let ranked = StudentStats::ranked(&session.stats);
+    let mut rank = 0;
+    ranked
+        .iter()
+        .enumerate()
+        .map(|(i, s)| {
+            let tied = i > 0 && ranked[i - 1].points() == s.points() && ranked[i - 1].correct == s.correct;
+            if !tied {
+                rank = i + 1;
             }
+            (rank, *s)
+        })
+        .collect()
+}
 
-            if env.event == "request_question" {
-                let Some(nickname) = current_nickname.clone() else { continue; };
-                let reason = env
-                    .payload
-                    .get("reason")
-                    .and_then(|v| v.as_str())
-                    .unwrap_or("death")
-                    .to_string();
+// Payload of `leaderboard_update`: the top of the ranking, sized for a projector.
+fn leaderboard_payload(session: &SessionRecord) -> serde_json::Value {
+    let places = ranked_places(session);
+    let top: Vec<_> = places
+        .iter()
+        .take(LEADERBOARD_SIZE)
+        .map(|(rank, s)| {
+            json!({
+                "rank": rank,
+                "nickname": s.nickname,
+                "points": s.points(),
+                "score": s.score,
+                "bonus": s.bonus,
+                "streak": s.streak,
+                "correct": s.correct
+            })
+        })
+        .collect();
+    json!({ "sessionId": session.id, "top": top, "total": places.len() })
+}
 
-                let mut sessions = state.db.game_sessions.write().await;
-                let Some(session) = sessions.get_mut(&session_id) else { continue; };
-                let Some(participant) = session.participants.get_mut(&nickname) else { continue; };
-                let current_idx = participant.current_question_index;
-                let quiz = {
-                    let qmap = state.db.quizzes.read().await;
-                    qmap.get(&session.quiz_id).cloned()
-                };
-                let Some(quiz) = quiz else { continue; };
-                if quiz.questions.is_empty() {
-                    continue;
-                }
-                let question = if let Some(q) = quiz.questions.get(current_idx).cloned() {
-                    q
-                } else {
-                    // In game modes, continue cycling questions instead of ending immediately.
-                    if session.game_mode != "classic" {
-                        participant.current_question_index = 0;
-                        quiz.questions[0].clone()
-                    } else {
-                        if let Some(bc) = state.db.broadcasters.get(&room_code) {
-                            let _ = bc.send(WsEnvelope {
-                                event: "end_quiz".into(),
-                                payload: json!({ "sessionId": session.id, "endedAt": Utc::now().to_rfc3339(), "resultsReady": true }),
-                                request_id: env.request_id.clone(),
-                                ts: Some(Utc::now().to_rfc3339()),
-                            });
-                        }
-                        continue;
-                    }
-                };
+const PODIUM_SIZE: usize = 3;
+const PODIUM_REVEAL_STEP_MS: u64 = 2000;
 
-                if let Some(bc) = state.db.broadcasters.get(&room_code) {
-                    let _ = bc.send(WsEnvelope {
-                        event: "question_push".into(),
-                        payload: json!({ "question": question, "reason": reason }),
-                        request_id: env.request_id.clone(),
-                        ts: Some(Utc::now().to_rfc3339()),
-                    });
-                }
-            }
-        }
-    }
+// Payload of `podium`: the top three in reveal order, third place first, each with the delay after
+// which the client shows it and the student's final summary.
+fn podium_payload(session: &SessionRecord) -> serde_json::Value {
+    let places = ranked_places(session);
+    let podium: Vec<_> = places
+        .iter()
+        .take(PODIUM_SIZE)
+        .rev()
+        .enumerate()
+        .map(|(step, (rank, s))| {
+            json!({
+                "rank": rank,
+                "revealAfterMs": step as u64 * PODIUM_REVEAL_STEP_MS,
+                "nickname": s.nickname,
+                "points": s.points(),
+                "score": s.score,
+                "bonus": s.bonus,
+                "correct": s.correct,
+                "wrong": s.wrong,
+                "correctPct": s.correct_pct(),
+                "bestStreak": s.best_streak
+            })
+        })
+        .collect();
+    json!({ "sessionId": session.id, "places": podium, "total": places.len() })
+}
 
-    if let Some(nickname) = current_nickname {
-        let mut sessions = state.db.game_sessions.write().await;
-        if let Some(session) = sessions.get_mut(&session_id) {
-            if let Some(p) = session.participants.get_mut(&nickname) {
-                p.join_state = "left".into();
+// Payload of `stats_update`: class-wide share of correct answers and the ranked students.
+fn stats_payload(session: &SessionRecord) -> serde_json::Value {
+    let class_correct: u32 = session.stats.values().map(|s| s.correct).sum();
+    let class_wrong: u32 = session.stats.values().map(|s| s.wrong).sum();
+    let total = class_correct + class_wrong;
+    let class_pct = if total == 0 {
+        0.0
+    } else {
+        class_correct as f64 * 100.0 / total as f64
+    };
+    let students: Vec<_> = StudentStats::ranked(&session.stats)
+        .into_iter()
+        .map(|s| json!({
+            "nickname": s.nickname,
+            "correct": s.correct,
+            "wrong": s.wrong,
+            "score": s.score,
+            "points": s.points(),
+            "streak": s.streak,
+            "correctPct": s.correct_pct()
+        }))
+        .collect();
+    json!({
+        "class": {"correctPct": class_pct, "wrongPct": 100.0 - class_pct},
+        "students": students,
+        "questionTimes": question_times(session, Some(session.quiz_id))
+    })
+}
+
+// Time from `question_push` to the answer per question, over each participant's last timed answer to it
+// (retries included). `quiz_id` narrows it to one quiz of the playlist.
+fn question_times(session: &SessionRecord, quiz_id: Option<i64>) -> Vec<serde_json::Value> {
+    let mut latencies: BTreeMap<(i64, &str), Vec<i64>> = BTreeMap::new();
+    for answers in session.answers.values() {
+        let mut last: HashMap<(i64, &str), i64> = HashMap::new();
+        for answer in answers.iter().filter(|a| quiz_id.is_none_or(|id| a.quiz_id == id)) {
+            if let Some(ms) = answer.latency_ms {
+                last.insert((answer.quiz_id, answer.question_id.as_str()), ms);
             }
         }
+        for (key, ms) in last {
+            latencies.entry(key).or_default().push(ms);
+        }
     }
-
-    send_task.abort();
-    info!("ws disconnected for room {}", room_code);
+    latencies
+        .into_iter()
+        .map(|((quiz_id, question_id), mut times)| {
+            times.sort_unstable();
+            let percentile = |p: f64| times[((p * times.len() as f64).ceil() as usize).max(1) - 1];
+            json!({
+                "quizId": quiz_id,
+                "questionId": question_id,
+                "answers": times.len(),
+                "avgMs": times.iter().sum::<i64>() as f64 / times.len() as f64,
+                "p50Ms": percentile(0.5),
+                "p90Ms": percentile(0.9)
+            })
+        })
+        .collect()
 }
 
 use futures::{SinkExt, StreamExt};
@@ -0,0 +1,115 @@
+use crate::state::{QuizRecord, SessionRecord};
+use serde::Serialize;
+use std::collections::HashMap;
+
+// Share of respondents in each of the upper and lower groups (Kelley's 27%).
+const EXTREME_GROUP_SHARE: f64 = 0.27;
+// Below this many respondents to a question the groups are too small for a discrimination index.
+pub const MIN_RESPONDENTS: usize = 5;
+const TOO_HARD_BELOW: f64 = 0.2;
+const TOO_EASY_ABOVE: f64 = 0.95;
+const WEAK_DISCRIMINATION_BELOW: f64 = 0.2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ItemFlag {
+    // Strong students do worse on it than weak ones: usually a wrong answer key or a misleading prompt.
+    NegativeDiscrimination,
+    WeakDiscrimination,
+    TooHard,
+    TooEasy,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ItemStats {
+    pub question_id: String,
+    pub prompt: String,
+    pub respondents: usize,
+    // Mean credit, 0.0..=1.0: the classical difficulty index, where higher means easier.
+    pub difficulty: Option<f64>,
+    // Mean credit of the upper group minus that of the lower group, -1.0..=1.0.
+    pub discrimination: Option<f64>,
+    pub flags: Vec<ItemFlag>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ItemAnalysis {
+    pub quiz_id: i64,
+    pub sessions: usize,
+    pub respondents: usize,
+    pub items: Vec<ItemStats>,
+}
+
+// Every participant of every session that played the quiz is one respondent; a question counts with the
+// credit of their last answer to it, so retries and repeated rounds do not weigh twice.
+pub fn analyze<'a>(quiz: &QuizRecord, sessions: impl IntoIterator<Item = &'a SessionRecord>) -> ItemAnalysis {
+    let mut session_count = 0;
+    let mut respondents: Vec<HashMap<&str, f64>> = Vec::new();
+    for session in sessions {
+        let mut played = false;
+        for answers in session.answers.values() {
+            let mut credits: HashMap<&str, f64> = HashMap::new();
+            for answer in answers.iter().filter(|a| a.quiz_id == quiz.id) {
+                credits.insert(answer.question_id.as_str(), answer.credit);
+            }
+            if !credits.is_empty() {
+                played = true;
+                respondents.push(credits);
+            }
+        }
+        session_count += usize::from(played);
+    }
+
+    // Ranked by the share of the quiz they got right, so students who did not reach the end are comparable.
+    let question_count = quiz.questions.len().max(1) as f64;
+    let mut ranked: Vec<(f64, &HashMap<&str, f64>)> =
+        respondents.iter().map(|credits| (credits.values().sum::<f64>() / question_count, credits)).collect();
+    ranked.sort_by(|a, b| b.0.total_cmp(&a.0));
+    let group = ((ranked.len() as f64 * EXTREME_GROUP_SHARE).round() as usize).max(1);
+    let mean_credit = |members: &[(f64, &HashMap<&str, f64>)], question_id: &str| {
+        let credits: Vec<f64> = members.iter().filter_map(|(_, credits)| credits.get(question_id).copied()).collect();
+        (!credits.is_empty()).then(|| credits.iter().sum::<f64>() / credits.len() as f64)
+    };
+
+    let items = quiz
+        .questions
+        .iter()
+        .map(|question| {
+            let id = question.id.as_str();
+            let answered = ranked.iter().filter(|(_, credits)| credits.contains_key(id)).count();
+            let difficulty = mean_credit(&ranked, id);
+            let discrimination = if answered >= MIN_RESPONDENTS && ranked.len() >= 2 * group {
+                let upper = mean_credit(&ranked[..group], id);
+                let lower = mean_credit(&ranked[ranked.len() - group..], id);
+                upper.zip(lower).map(|(upper, lower)| upper - lower)
+            } else {
+                None
+            };
+            let mut flags = Vec::new();
+            match discrimination {
+                Some(d) if d < 0.0 => flags.push(ItemFlag::NegativeDiscrimination),
+                Some(d) if d < WEAK_DISCRIMINATION_BELOW => flags.push(ItemFlag::WeakDiscrimination),
+                _ => {}
+            }
+            if answered >= MIN_RESPONDENTS {
+                match difficulty {
+                    Some(p) if p < TOO_HARD_BELOW => flags.push(ItemFlag::TooHard),
+                    Some(p) if p > TOO_EASY_ABOVE => flags.push(ItemFlag::TooEasy),
+                    _ => {}
+                }
+            }
+            ItemStats {
+                question_id: question.id.clone(),
+                prompt: question.prompt.clone(),
+                respondents: answered,
+                difficulty,
+                discrimination,
+                flags,
+            }
+        })
+        .collect();
+
+    ItemAnalysis { quiz_id: quiz.id, sessions: session_count, respondents: respondents.len(), items }
+}
@@ -0,0 +1,167 @@
+use crate::api_keys::ApiKey;
+use crate::audit::AuditEntry;
+use crate::jwt::RefreshToken;
+use crate::prompt_templates::{PromptTemplateVersion, PromptUseCase};
+use crate::state::{ClassRecord, QuizRecord, QuizRevision, SessionRecord, Student, Teacher, TeacherSession};
+use crate::storage::{StorageError, StorageResult};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+use tracing::warn;
+
+// Every op carries the full record it touches, so replaying an entry that the snapshot already
+// contains is harmless.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum JournalOp {
+    TeacherUpserted { teacher: Teacher },
+    QuizUpserted { quiz: QuizRecord },
+    QuizDeleted { quiz_id: i64 },
+    QuizRevisionAdded { quiz_id: i64, revision: QuizRevision },
+    SessionFinished { session: Box<SessionRecord> },
+    TeacherSessionPut { sid: String, session: TeacherSession },
+    TeacherSessionRemoved { sid: String },
+    RefreshTokenPut { token: String, record: RefreshToken },
+    RefreshTokenRemoved { token: String },
+    TeacherCredentialsRevoked { teacher_id: i64, keep_sid: Option<String> },
+    StudentUpserted { student: Student },
+    ApiKeyUpserted { key: ApiKey },
+    ApiKeyRevoked { id: String },
+    PromptTemplateSaved { use_case: PromptUseCase, version: PromptTemplateVersion },
+    ClassUpserted { class: ClassRecord },
+    ClassDeleted { class_id: i64 },
+    AuditRecorded { entry: AuditEntry },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub seq: u64,
+    pub at: DateTime<Utc>,
+    #[serde(flatten)]
+    pub op: JournalOp,
+}
+
+pub struct Journal {
+    path: String,
+    fsync: bool,
+    last_seq: AtomicU64,
+    file: Mutex<Option<tokio::fs::File>>,
+}
+
+impl Journal {
+    pub fn from_env() -> Option<Self> {
+        let enabled = std::env::var("JOURNAL_ENABLED")
+            .map(|v| !matches!(v.trim().to_lowercase().as_str(), "0" | "false" | "no"))
+            .unwrap_or(true);
+        if !enabled {
+            return None;
+        }
+        let path = std::env::var("JOURNAL_PATH")
+            .ok()
+            .filter(|v| !v.trim().is_empty())
+            .unwrap_or_else(|| format!("{}/local_state.journal", env!("CARGO_MANIFEST_DIR")));
+        let fsync = std::env::var("JOURNAL_FSYNC")
+            .map(|v| matches!(v.trim().to_lowercase().as_str(), "1" | "true" | "yes"))
+            .unwrap_or(false);
+        Some(Self::new(path, fsync))
+    }
+
+    pub fn new(path: impl Into<String>, fsync: bool) -> Self {
+        Self { path: path.into(), fsync, last_seq: AtomicU64::new(0), file: Mutex::new(None) }
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn last_seq(&self) -> u64 {
+        self.last_seq.load(Ordering::SeqCst)
+    }
+
+    pub fn advance_to(&self, seq: u64) {
+        self.last_seq.fetch_max(seq, Ordering::SeqCst);
+    }
+
+    // Reads all entries. Only the last line may be unreadable: that is a write torn by a crash, so it
+    // is cut off and later appends start on a fresh line. An unreadable line anywhere else means the
+    // file is damaged, and replaying around it would silently lose changes, so that is an error.
+    pub async fn read_entries(&self) -> StorageResult<Vec<JournalEntry>> {
+        let raw = match tokio::fs::read_to_string(&self.path).await {
+            Ok(raw) => raw,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err.into()),
+        };
+        let lines: Vec<&str> = raw.split_inclusive('\n').collect();
+        let mut entries = Vec::new();
+        let mut intact_len = 0;
+        for (idx, line) in lines.iter().enumerate() {
+            if !line.trim().is_empty() {
+                match serde_json::from_str::<JournalEntry>(line) {
+                    Ok(entry) => entries.push(entry),
+                    Err(err) if idx + 1 == lines.len() => {
+                        warn!("dropping torn last line {} of journal {}: {}", idx + 1, self.path, err);
+                        tokio::fs::OpenOptions::new().write(true).open(&self.path).await?.set_len(intact_len).await?;
+                        break;
+                    }
+                    Err(err) => {
+                        return Err(StorageError::Corrupt(format!(
+                            "journal {} is damaged at line {} of {}: {}",
+                            self.path,
+                            idx + 1,
+                            lines.len(),
+                            err
+                        )))
+                    }
+                }
+            }
+            intact_len += line.len() as u64;
+        }
+        let max_seq = entries.iter().map(|e| e.seq).max().unwrap_or(0);
+        self.last_seq.fetch_max(max_seq, Ordering::SeqCst);
+        Ok(entries)
+    }
+
+    pub async fn append(&self, op: JournalOp) -> StorageResult<()> {
+        let mut guard = self.file.lock().await;
+        if guard.is_none() {
+            *guard = Some(
+                tokio::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&self.path)
+                    .await?,
+            );
+        }
+        let file = guard.as_mut().expect("journal file is open");
+        let entry = JournalEntry { seq: self.last_seq.fetch_add(1, Ordering::SeqCst) + 1, at: Utc::now(), op };
+        let mut line = serde_json::to_vec(&entry)?;
+        line.push(b'\n');
+        file.write_all(&line).await?;
+        if self.fsync {
+            file.sync_data().await?;
+        } else {
+            file.flush().await?;
+        }
+        Ok(())
+    }
+
+    // Drops entries already folded into a saved snapshot. Holds the append lock so no entry written
+    // in the meantime can be lost.
+    pub async fn compact(&self, up_to: u64) -> StorageResult<()> {
+        let mut guard = self.file.lock().await;
+        let remaining: Vec<JournalEntry> =
+            self.read_entries().await?.into_iter().filter(|e| e.seq > up_to).collect();
+        let mut body = Vec::new();
+        for entry in &remaining {
+            body.extend(serde_json::to_vec(entry)?);
+            body.push(b'\n');
+        }
+        let tmp_path = format!("{}.tmp", self.path);
+        tokio::fs::write(&tmp_path, &body).await?;
+        tokio::fs::rename(&tmp_path, &self.path).await?;
+        *guard = None;
+        Ok(())
+    }
+}
@@ -0,0 +1,103 @@
+use chrono::{DateTime, Utc};
+use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tracing::warn;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct AccessClaims {
+    sub: String,
+    iat: i64,
+    exp: i64,
+    typ: String,
+}
+
+pub struct VerifiedAccessToken {
+    pub teacher_id: i64,
+    pub issued_at: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RefreshToken {
+    pub teacher_id: i64,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Clone)]
+pub struct JwtConfig {
+    encoding: Arc<EncodingKey>,
+    decoding: Arc<DecodingKey>,
+    pub access_ttl: chrono::Duration,
+    pub refresh_ttl: chrono::Duration,
+    pub student_ttl: chrono::Duration,
+}
+
+impl JwtConfig {
+    pub fn from_env() -> Self {
+        let secret = std::env::var("JWT_SECRET")
+            .or_else(|_| std::env::var("SESSION_SECRET"))
+            .ok()
+            .filter(|v| !v.trim().is_empty())
+            .unwrap_or_else(|| {
+                warn!("JWT_SECRET is not set, bearer tokens will not survive a restart");
+                rand::thread_rng().sample_iter(&Alphanumeric).take(64).map(char::from).collect()
+            });
+        let minutes = |name: &str, default: i64| {
+            std::env::var(name)
+                .ok()
+                .and_then(|v| v.parse::<i64>().ok())
+                .filter(|v| *v > 0)
+                .unwrap_or(default)
+        };
+        Self {
+            encoding: Arc::new(EncodingKey::from_secret(secret.as_bytes())),
+            decoding: Arc::new(DecodingKey::from_secret(secret.as_bytes())),
+            access_ttl: chrono::Duration::minutes(minutes("JWT_ACCESS_TTL_MINUTES", 15)),
+            refresh_ttl: chrono::Duration::days(minutes("JWT_REFRESH_TTL_DAYS", 30)),
+            student_ttl: chrono::Duration::days(minutes("STUDENT_TOKEN_TTL_DAYS", 30)),
+        }
+    }
+
+    fn issue(&self, subject: i64, typ: &str, ttl: chrono::Duration) -> anyhow::Result<String> {
+        let now = Utc::now();
+        let claims = AccessClaims {
+            sub: subject.to_string(),
+            iat: now.timestamp(),
+            exp: (now + ttl).timestamp(),
+            typ: typ.into(),
+        };
+        Ok(encode(&Header::new(Algorithm::HS256), &claims, &self.encoding)?)
+    }
+
+    fn verify(&self, token: &str, typ: &str) -> Option<AccessClaims> {
+        let data = decode::<AccessClaims>(token, &self.decoding, &Validation::new(Algorithm::HS256)).ok()?;
+        (data.claims.typ == typ).then_some(data.claims)
+    }
+
+    pub fn issue_access_token(&self, teacher_id: i64) -> anyhow::Result<String> {
+        self.issue(teacher_id, "access", self.access_ttl)
+    }
+
+    pub fn verify_access_token(&self, token: &str) -> Option<VerifiedAccessToken> {
+        let claims = self.verify(token, "access")?;
+        Some(VerifiedAccessToken { teacher_id: claims.sub.parse().ok()?, issued_at: claims.iat })
+    }
+
+    // Student tokens use the same key but a different `typ`, so they never pass as teacher tokens.
+    pub fn issue_student_token(&self, student_id: i64) -> anyhow::Result<String> {
+        self.issue(student_id, "student", self.student_ttl)
+    }
+
+    pub fn verify_student_token(&self, token: &str) -> Option<i64> {
+        self.verify(token, "student")?.sub.parse().ok()
+    }
+
+    pub fn new_refresh_token(&self, teacher_id: i64) -> (String, RefreshToken) {
+        let token: String = rand::thread_rng().sample_iter(&Alphanumeric).take(48).map(char::from).collect();
+        let now = Utc::now();
+        (token, RefreshToken { teacher_id, created_at: now, expires_at: now + self.refresh_ttl })
+    }
+}
@@ -1,19 +1,40 @@
+pub mod ai_providers;
+pub mod api_keys;
+pub mod api_v2;
+pub mod audit;
+pub mod backup;
+pub mod capacity;
+pub mod document_text;
+pub mod email;
 pub mod error;
+pub mod extract;
+pub mod game_modes;
 pub mod handlers;
+pub mod item_analysis;
+pub mod journal;
+pub mod jwt;
+pub mod lti;
+pub mod media;
+pub mod metrics;
 pub mod models;
+pub mod moderation;
+pub mod password_reset;
+pub mod passwords;
+pub mod profanity;
+pub mod prompt_templates;
+pub mod quiz_export;
+pub mod quiz_import;
+pub mod rate_limit;
 pub mod routes;
+pub mod schema;
+pub mod shared;
 pub mod state;
+pub mod storage;
+pub mod totp;
+pub mod web;
 pub mod ws_protocol;
 
-use std::sync::Arc;
-
 pub fn build_state() -> anyhow::Result<state::AppState> {
-    let schema_raw = include_str!("../contracts/ai_quiz.schema.json");
-    let schema: serde_json::Value = serde_json::from_str(schema_raw)?;
-    let ai_client: Arc<dyn state::AiQuizClient> = if let Some(real) = state::GigaChatAiClient::from_env() {
-        Arc::new(real)
-    } else {
-        Arc::new(state::MockAiClient)
-    };
-    Ok(state::AppState::new(ai_client, schema))
+    let schemas = schema::QuizSchemas::compile_builtin()?;
+    state::AppState::new(ai_providers::AiRegistry::from_env()?, schemas)
 }
@@ -0,0 +1,346 @@
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{decode, decode_header, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::time::Duration;
+use tracing::warn;
+
+pub const CLAIM_MESSAGE_TYPE: &str = "https://purl.imsglobal.org/spec/lti/claim/message_type";
+pub const CLAIM_VERSION: &str = "https://purl.imsglobal.org/spec/lti/claim/version";
+pub const CLAIM_DEPLOYMENT_ID: &str = "https://purl.imsglobal.org/spec/lti/claim/deployment_id";
+pub const CLAIM_ROLES: &str = "https://purl.imsglobal.org/spec/lti/claim/roles";
+pub const CLAIM_CUSTOM: &str = "https://purl.imsglobal.org/spec/lti/claim/custom";
+pub const CLAIM_AGS_ENDPOINT: &str = "https://purl.imsglobal.org/spec/lti-ags/claim/endpoint";
+pub const SCOPE_SCORE: &str = "https://purl.imsglobal.org/spec/lti-ags/scope/score";
+const INSTRUCTOR_ROLES: [&str; 2] = [
+    "http://purl.imsglobal.org/vocab/lis/v2/membership#Instructor",
+    "http://purl.imsglobal.org/vocab/lis/v2/institution/person#Instructor",
+];
+
+// An OIDC login has to come back as a launch within this time.
+const LOGIN_TTL_MINUTES: i64 = 10;
+const CLIENT_ASSERTION_TTL_SECS: i64 = 300;
+
+// A registered LMS (Moodle, Canvas, ...), as the platform's tool registration page shows it.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LtiPlatform {
+    pub issuer: String,
+    pub client_id: String,
+    // Empty admits every deployment of the tool on the platform.
+    #[serde(default)]
+    pub deployment_ids: Vec<String>,
+    pub auth_login_url: String,
+    pub auth_token_url: String,
+    pub jwks_url: String,
+}
+
+pub struct LtiConfig {
+    pub platforms: Vec<LtiPlatform>,
+    // The tool's RSA private key (PEM) and its key id; signs the client assertions that get AGS access
+    // tokens. Without it launches work but scores are not sent back.
+    pub signing_key_pem: Option<String>,
+    pub key_id: String,
+    // Public half of the signing key as a JWKS, served for platforms that fetch keys by URL.
+    pub public_jwks: Option<Value>,
+    // Where platforms post the launch, as registered with them.
+    pub launch_url: String,
+}
+
+impl LtiConfig {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let read = |name: &str| -> anyhow::Result<Option<String>> {
+            match std::env::var(name).ok().filter(|v| !v.trim().is_empty()) {
+                Some(path) => std::fs::read_to_string(path.trim())
+                    .map(Some)
+                    .map_err(|e| anyhow::anyhow!("cannot read {} ({}): {}", name, path, e)),
+                None => Ok(None),
+            }
+        };
+        let platforms = match read("LTI_PLATFORMS_FILE")? {
+            Some(raw) => serde_json::from_str(&raw).map_err(|e| anyhow::anyhow!("invalid LTI_PLATFORMS_FILE: {}", e))?,
+            None => Vec::new(),
+        };
+        let public_jwks = match read("LTI_TOOL_JWKS_FILE")? {
+            Some(raw) => {
+                Some(serde_json::from_str(&raw).map_err(|e| anyhow::anyhow!("invalid LTI_TOOL_JWKS_FILE: {}", e))?)
+            }
+            None => None,
+        };
+        Ok(Self {
+            platforms,
+            signing_key_pem: read("LTI_TOOL_PRIVATE_KEY_FILE")?,
+            key_id: std::env::var("LTI_TOOL_KEY_ID")
+                .ok()
+                .filter(|v| !v.trim().is_empty())
+                .unwrap_or_else(|| "hse-quiz".into()),
+            public_jwks,
+            launch_url: std::env::var("LTI_LAUNCH_URL")
+                .ok()
+                .filter(|v| !v.trim().is_empty())
+                .unwrap_or_else(|| "http://localhost:8080/lti/launch".into()),
+        })
+    }
+}
+
+struct PendingLogin {
+    platform: usize,
+    nonce: String,
+    created_at: DateTime<Utc>,
+}
+
+// What a verified resource link launch carries that the tool acts on.
+#[derive(Debug, Clone)]
+pub struct LtiLaunch {
+    pub issuer: String,
+    pub client_id: String,
+    pub user_id: String,
+    pub name: Option<String>,
+    pub instructor: bool,
+    pub custom: serde_json::Map<String, Value>,
+    // The gradebook column scores go to, when the platform granted the score scope.
+    pub lineitem: Option<String>,
+}
+
+// A final score for one learner, sent to their line item.
+#[derive(Debug, Clone)]
+pub struct LtiScore {
+    pub issuer: String,
+    pub client_id: String,
+    pub user_id: String,
+    pub lineitem: String,
+    pub score_given: f64,
+    pub score_maximum: f64,
+}
+
+#[derive(Serialize)]
+struct ClientAssertion {
+    iss: String,
+    sub: String,
+    aud: String,
+    iat: i64,
+    exp: i64,
+    jti: String,
+}
+
+#[derive(Deserialize)]
+struct AccessToken {
+    access_token: String,
+}
+
+pub struct LtiTool {
+    pub platforms: Vec<LtiPlatform>,
+    signing_key: Option<(EncodingKey, String)>,
+    pub public_jwks: Option<Value>,
+    pub launch_url: String,
+    http: reqwest::Client,
+    // OIDC logins waiting for their launch, by `state`.
+    pending: DashMap<String, PendingLogin>,
+}
+
+impl LtiTool {
+    pub fn new(config: LtiConfig) -> anyhow::Result<Self> {
+        let signing_key = match &config.signing_key_pem {
+            Some(pem) => Some((
+                EncodingKey::from_rsa_pem(pem.as_bytes()).map_err(|e| anyhow::anyhow!("invalid LTI tool key: {}", e))?,
+                config.key_id.clone(),
+            )),
+            None => None,
+        };
+        Ok(Self {
+            platforms: config.platforms,
+            signing_key,
+            public_jwks: config.public_jwks,
+            launch_url: config.launch_url,
+            http: reqwest::Client::builder().timeout(Duration::from_secs(20)).build()?,
+            pending: DashMap::new(),
+        })
+    }
+
+    pub fn from_env() -> anyhow::Result<Self> {
+        Self::new(LtiConfig::from_env()?)
+    }
+
+    fn platform(&self, issuer: &str, client_id: Option<&str>) -> Option<usize> {
+        self.platforms
+            .iter()
+            .position(|p| p.issuer == issuer && client_id.is_none_or(|client_id| p.client_id == client_id))
+    }
+
+    // Step one of the LTI 1.3 launch (OIDC third-party initiated login): remembers a state and nonce and
+    // returns the platform URL to send the browser to. None for an unregistered platform.
+    pub fn begin_login(&self, params: &std::collections::HashMap<String, String>) -> Option<String> {
+        let index = self.platform(params.get("iss")?, params.get("client_id").map(String::as_str))?;
+        let platform = &self.platforms[index];
+        let now = Utc::now();
+        self.pending.retain(|_, login| now - login.created_at < chrono::Duration::minutes(LOGIN_TTL_MINUTES));
+        let random = || rand::thread_rng().sample_iter(&Alphanumeric).take(32).map(char::from).collect::<String>();
+        let (state, nonce) = (random(), random());
+        let mut url = reqwest::Url::parse(&platform.auth_login_url).ok()?;
+        {
+            let mut query = url.query_pairs_mut();
+            query
+                .append_pair("scope", "openid")
+                .append_pair("response_type", "id_token")
+                .append_pair("response_mode", "form_post")
+                .append_pair("prompt", "none")
+                .append_pair("client_id", &platform.client_id)
+                .append_pair("redirect_uri", &self.launch_url)
+                .append_pair("login_hint", params.get("login_hint")?)
+                .append_pair("state", &state)
+                .append_pair("nonce", &nonce);
+            if let Some(hint) = params.get("lti_message_hint") {
+                query.append_pair("lti_message_hint", hint);
+            }
+        }
+        self.pending.insert(state, PendingLogin { platform: index, nonce, created_at: now });
+        Some(url.to_string())
+    }
+
+    // Step two: checks the platform's signed id_token against the login it answers and its JWKS.
+    pub async fn verify_launch(&self, state: &str, id_token: &str) -> Result<LtiLaunch, String> {
+        let (_, login) = self.pending.remove(state).ok_or("unknown or used state")?;
+        if Utc::now() - login.created_at >= chrono::Duration::minutes(LOGIN_TTL_MINUTES) {
+            return Err("login expired".into());
+        }
+        let platform = &self.platforms[login.platform];
+        let header = decode_header(id_token).map_err(|e| format!("invalid id_token: {}", e))?;
+        let jwks: JwkSet = self
+            .http
+            .get(&platform.jwks_url)
+            .send()
+            .await
+            .and_then(|res| res.error_for_status())
+            .map_err(|e| format!("cannot fetch platform keys: {}", e))?
+            .json()
+            .await
+            .map_err(|e| format!("invalid platform keys: {}", e))?;
+        let jwk = match &header.kid {
+            Some(kid) => jwks.find(kid),
+            None => jwks.keys.first(),
+        }
+        .ok_or("id_token signed with an unknown key")?;
+        let key = DecodingKey::from_jwk(jwk).map_err(|e| format!("unusable platform key: {}", e))?;
+        let mut validation = Validation::new(Algorithm::RS256);
+        validation.set_audience(&[&platform.client_id]);
+        validation.set_issuer(&[&platform.issuer]);
+        let claims = decode::<Value>(id_token, &key, &validation)
+            .map_err(|e| format!("invalid id_token: {}", e))?
+            .claims;
+
+        if claims["nonce"].as_str() != Some(login.nonce.as_str()) {
+            return Err("nonce does not match the login".into());
+        }
+        if claims[CLAIM_MESSAGE_TYPE] != "LtiResourceLinkRequest" || claims[CLAIM_VERSION] != "1.3.0" {
+            return Err("only LTI 1.3 resource link launches are supported".into());
+        }
+        let deployment = claims[CLAIM_DEPLOYMENT_ID].as_str().unwrap_or_default();
+        if !platform.deployment_ids.is_empty() && !platform.deployment_ids.iter().any(|d| d == deployment) {
+            return Err("unknown deployment".into());
+        }
+        let user_id = claims["sub"].as_str().filter(|sub| !sub.is_empty()).ok_or("launch has no user")?;
+        let mut roles = claims[CLAIM_ROLES].as_array().into_iter().flatten().filter_map(Value::as_str);
+        let ags = &claims[CLAIM_AGS_ENDPOINT];
+        let can_score = ags["scope"].as_array().into_iter().flatten().any(|scope| scope == SCOPE_SCORE);
+        Ok(LtiLaunch {
+            issuer: platform.issuer.clone(),
+            client_id: platform.client_id.clone(),
+            user_id: user_id.to_string(),
+            name: ["name", "given_name"].iter().find_map(|claim| claims[*claim].as_str()).map(str::to_string),
+            instructor: roles.any(|role| INSTRUCTOR_ROLES.contains(&role)),
+            custom: claims[CLAIM_CUSTOM].as_object().cloned().unwrap_or_default(),
+            lineitem: ags["lineitem"].as_str().filter(|_| can_score).map(str::to_string),
+        })
+    }
+
+    pub fn can_send_scores(&self) -> bool {
+        self.signing_key.is_some()
+    }
+
+    // Client credentials grant with a signed JWT assertion (LTI security framework), scoped to scores.
+    async fn access_token(&self, platform: &LtiPlatform) -> anyhow::Result<String> {
+        let (key, kid) = self.signing_key.as_ref().ok_or_else(|| anyhow::anyhow!("no LTI tool key configured"))?;
+        let mut header = Header::new(Algorithm::RS256);
+        header.kid = Some(kid.clone());
+        let now = Utc::now().timestamp();
+        let assertion = ClientAssertion {
+            iss: platform.client_id.clone(),
+            sub: platform.client_id.clone(),
+            aud: platform.auth_token_url.clone(),
+            iat: now,
+            exp: now + CLIENT_ASSERTION_TTL_SECS,
+            jti: uuid::Uuid::new_v4().to_string(),
+        };
+        let assertion = encode(&header, &assertion, key)?;
+        let token: AccessToken = self
+            .http
+            .post(&platform.auth_token_url)
+            .form(&[
+                ("grant_type", "client_credentials"),
+                ("client_assertion_type", "urn:ietf:params:oauth:client-assertion-type:jwt-bearer"),
+                ("client_assertion", assertion.as_str()),
+                ("scope", SCOPE_SCORE),
+            ])
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(token.access_token)
+    }
+
+    // Posts each score to `{lineitem}/scores` (Assignment and Grade Services); one access token per
+    // platform. Returns the outcome per score, in order.
+    pub async fn send_scores(&self, scores: &[LtiScore]) -> Vec<Result<(), String>> {
+        let mut tokens: std::collections::HashMap<(String, String), Result<String, String>> = Default::default();
+        let mut outcomes = Vec::with_capacity(scores.len());
+        for score in scores {
+            let key = (score.issuer.clone(), score.client_id.clone());
+            if !tokens.contains_key(&key) {
+                let token = match self.platform(&score.issuer, Some(&score.client_id)) {
+                    Some(index) => self.access_token(&self.platforms[index]).await.map_err(|e| e.to_string()),
+                    None => Err("platform is no longer registered".to_string()),
+                };
+                tokens.insert(key.clone(), token);
+            }
+            let outcome = match &tokens[&key] {
+                Ok(token) => self.post_score(token, score).await.map_err(|e| e.to_string()),
+                Err(e) => Err(format!("no access token: {}", e)),
+            };
+            if let Err(e) = &outcome {
+                warn!("sending LTI score for {} to {} failed: {}", score.user_id, score.lineitem, e);
+            }
+            outcomes.push(outcome);
+        }
+        outcomes
+    }
+
+    async fn post_score(&self, token: &str, score: &LtiScore) -> anyhow::Result<()> {
+        // The scores endpoint sits under the line item path, before any query string.
+        let url = match score.lineitem.split_once('?') {
+            Some((path, query)) => format!("{}/scores?{}", path.trim_end_matches('/'), query),
+            None => format!("{}/scores", score.lineitem.trim_end_matches('/')),
+        };
+        let body = serde_json::json!({
+            "userId": score.user_id,
+            "scoreGiven": score.score_given,
+            "scoreMaximum": score.score_maximum,
+            "activityProgress": "Completed",
+            "gradingProgress": "FullyGraded",
+            "timestamp": Utc::now().to_rfc3339(),
+        });
+        self.http
+            .post(url)
+            .bearer_auth(token)
+            .header(reqwest::header::CONTENT_TYPE, "application/vnd.ims.lis.v1.score+json")
+            .body(body.to_string())
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
@@ -1,25 +1,53 @@
-use quiz_backend::{build_state, routes::build_router};
+use quiz_backend::{build_state, routes::build_router, storage};
 use sqlx::mysql::MySqlPoolOptions;
 use std::net::SocketAddr;
+use tracing_subscriber::fmt::format::FmtSpan;
 use tracing_subscriber::EnvFilter;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let _ = dotenvy::dotenv();
 
+    // With LOG_SPAN_TIMINGS=true every closed span is logged with its busy/idle time.
+    let span_events = if std::env::var("LOG_SPAN_TIMINGS").map(|v| v == "true" || v == "1").unwrap_or(false) {
+        FmtSpan::CLOSE
+    } else {
+        FmtSpan::NONE
+    };
     tracing_subscriber::fmt()
         .json()
+        .with_span_events(span_events)
         .with_env_filter(EnvFilter::from_default_env().add_directive("info".parse()?))
         .init();
 
-    let state = build_state()?;
-    let app = build_router(state);
+    let mut state = build_state()?;
+    state.restore().await?;
+    tracing::info!("core data storage: {}", state.store.kind());
+    tracing::info!("ai provider: {} (available: {:?})", state.ai.default_name(), state.ai.available());
+    tracing::info!(
+        "ai moderation: {} banned words, model check {}",
+        state.moderation.banned_count(),
+        if state.moderation.ai_check() { "on" } else { "off" }
+    );
+    let app = build_router(state.clone());
 
     if let Ok(db_url) = std::env::var("DATABASE_URL") {
         if !db_url.trim().is_empty() {
             match MySqlPoolOptions::new().max_connections(5).connect(&db_url).await {
                 Ok(pool) => match sqlx::migrate!("./migrations").run(&pool).await {
-                    Ok(_) => tracing::info!("mysql connected and migrations applied"),
+                    Ok(_) => {
+                        tracing::info!("mysql connected and migrations applied");
+                        let snapshot = state.db.snapshot().await;
+                        match storage::import_snapshot_into_mysql(&pool, &state.store.location(), &snapshot).await {
+                            Ok(Some((teachers, quizzes))) => tracing::info!(
+                                "imported local snapshot into mysql: {} teachers, {} quizzes",
+                                teachers,
+                                quizzes
+                            ),
+                            Ok(None) => {}
+                            Err(err) => tracing::warn!("failed to import local snapshot into mysql: {}", err),
+                        }
+                    }
                     Err(err) => tracing::warn!("mysql connected but migrations failed: {}", err),
                 },
                 Err(err) => {
@@ -41,6 +69,13 @@ async fn main() -> anyhow::Result<()> {
 
     let listener = tokio::net::TcpListener::bind(addr).await?;
     tracing::info!("backend listening on {}", addr);
-    axum::serve(listener, app).await?;
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
+        .with_graceful_shutdown(async {
+            let _ = tokio::signal::ctrl_c().await;
+        })
+        .await?;
+    if let Err(err) = state.persist_core_data().await {
+        tracing::warn!("failed to flush {} storage on shutdown: {}", state.store.kind(), err);
+    }
     Ok(())
 }
@@ -0,0 +1,166 @@
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
+use metrics_util::MetricKindMask;
+use once_cell::sync::Lazy;
+use serde_json::{json, Value};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+const WINDOW_SIZE: usize = 2048;
+
+// Keeps the most recent samples so percentiles follow current behaviour rather than the
+// whole uptime.
+#[derive(Default)]
+pub struct LatencyWindow {
+    samples: Mutex<VecDeque<u64>>,
+    count: AtomicU64,
+}
+
+impl LatencyWindow {
+    pub fn record(&self, elapsed: Duration) {
+        self.count.fetch_add(1, Ordering::Relaxed);
+        let mut samples = self.samples.lock().expect("latency window lock");
+        if samples.len() == WINDOW_SIZE {
+            samples.pop_front();
+        }
+        samples.push_back(elapsed.as_micros() as u64);
+    }
+
+    pub fn summary(&self) -> Value {
+        let mut sorted: Vec<u64> = self.samples.lock().expect("latency window lock").iter().copied().collect();
+        sorted.sort_unstable();
+        let pct = |p: f64| {
+            if sorted.is_empty() {
+                return 0.0;
+            }
+            let idx = ((sorted.len() as f64 * p).ceil() as usize).clamp(1, sorted.len()) - 1;
+            sorted[idx] as f64 / 1000.0
+        };
+        json!({
+            "count": self.count.load(Ordering::Relaxed),
+            "window": sorted.len(),
+            "p50Ms": pct(0.50),
+            "p95Ms": pct(0.95),
+            "p99Ms": pct(0.99),
+            "maxMs": sorted.last().map(|v| *v as f64 / 1000.0).unwrap_or(0.0),
+        })
+    }
+}
+
+#[derive(Default)]
+pub struct AnswerPipelineMetrics {
+    pub lock_wait: LatencyWindow,
+    pub quiz_lookup: LatencyWindow,
+    pub scoring: LatencyWindow,
+    pub broadcast: LatencyWindow,
+    pub total: LatencyWindow,
+}
+
+impl AnswerPipelineMetrics {
+    pub fn summary(&self) -> Value {
+        json!({
+            "lockWait": self.lock_wait.summary(),
+            "quizLookup": self.quiz_lookup.summary(),
+            "scoring": self.scoring.summary(),
+            "broadcast": self.broadcast.summary(),
+            "total": self.total.summary(),
+        })
+    }
+}
+
+#[derive(Default)]
+pub struct Metrics {
+    pub answer_pipeline: AnswerPipelineMetrics,
+}
+
+// Buckets for every `*_seconds` histogram: from a fast JSON handler to a slow AI provider.
+const DURATION_BUCKETS: [f64; 14] = [0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 15.0, 60.0];
+// Per-room gauges are set on every scrape; rooms that closed since drop out after this long.
+const ROOM_GAUGE_IDLE: Duration = Duration::from_secs(120);
+
+// The `metrics` recorder is global to the process while tests build many AppStates, so it is installed
+// once on first use. If another recorder got there first, the exposition stays empty.
+static PROMETHEUS: Lazy<PrometheusHandle> = Lazy::new(|| {
+    let recorder = PrometheusBuilder::new()
+        .set_buckets_for_metric(Matcher::Suffix("_seconds".into()), &DURATION_BUCKETS)
+        .expect("duration buckets are not empty")
+        .idle_timeout(MetricKindMask::GAUGE, Some(ROOM_GAUGE_IDLE))
+        .build_recorder();
+    let handle = recorder.handle();
+    let _ = metrics::set_global_recorder(recorder);
+    handle
+});
+
+pub fn install_prometheus() {
+    Lazy::force(&PROMETHEUS);
+}
+
+// Text exposition format for GET /metrics.
+pub fn render_prometheus() -> String {
+    PROMETHEUS.render()
+}
+
+// `route` is the matched route pattern, so ids and room codes in paths do not become label values.
+pub fn record_http(method: &str, route: &str, status: u16, elapsed: Duration) {
+    let labels = [("method", method.to_string()), ("route", route.to_string()), ("status", status.to_string())];
+    ::metrics::counter!("http_requests_total", &labels).increment(1);
+    ::metrics::histogram!("http_request_duration_seconds", &labels).record(elapsed.as_secs_f64());
+}
+
+// One call to an AI provider; `operation` is what it was for (quiz, distractors, hint).
+pub fn record_ai_request(provider: &str, operation: &'static str, elapsed: Duration, ok: bool) {
+    let labels = [("provider", provider.to_string()), ("operation", operation.to_string())];
+    ::metrics::histogram!("ai_request_duration_seconds", &labels).record(elapsed.as_secs_f64());
+    ::metrics::counter!("ai_requests_total", &labels).increment(1);
+    if !ok {
+        ::metrics::counter!("ai_request_failures_total", &labels).increment(1);
+    }
+}
+
+pub fn record_snapshot_write(store: &'static str, elapsed: Duration, ok: bool) {
+    ::metrics::histogram!("snapshot_write_duration_seconds", "store" => store).record(elapsed.as_secs_f64());
+    if !ok {
+        ::metrics::counter!("snapshot_write_failures_total", "store" => store).increment(1);
+    }
+}
+
+// Sockets open in a room and broadcast messages they skipped after falling behind, both since the
+// room opened.
+pub fn record_room(room_code: &str, sockets: usize, lagged: u64) {
+    let room = room_code.to_string();
+    ::metrics::gauge!("ws_connections", "room" => room.clone()).set(sockets as f64);
+    ::metrics::gauge!("ws_broadcast_lagged_messages", "room" => room).set(lagged as f64);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_single_sample_is_every_percentile() {
+        let window = LatencyWindow::default();
+        window.record(Duration::from_millis(5));
+        let summary = window.summary();
+        assert_eq!((summary["count"].clone(), summary["window"].clone()), (json!(1), json!(1)));
+        for key in ["p50Ms", "p95Ms", "p99Ms", "maxMs"] {
+            assert_eq!(summary[key], 5.0, "{}", key);
+        }
+    }
+
+    #[test]
+    fn percentiles_cover_only_the_latest_window() {
+        let window = LatencyWindow::default();
+        // 1ms..=2049ms; the first sample falls out, leaving 2ms..=2049ms.
+        for ms in 1..=WINDOW_SIZE as u64 + 1 {
+            window.record(Duration::from_millis(ms));
+        }
+        let summary = window.summary();
+        assert_eq!(summary["count"], WINDOW_SIZE as u64 + 1);
+        assert_eq!(summary["window"], WINDOW_SIZE);
+        assert_eq!(summary["p50Ms"], 1025.0);
+        assert_eq!(summary["p95Ms"], 1947.0);
+        assert_eq!(summary["p99Ms"], 2029.0);
+        assert_eq!(summary["maxMs"], 2049.0);
+    }
+}
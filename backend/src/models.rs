@@ -1,5 +1,5 @@
 use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 #[serde(rename_all = "lowercase")]
@@ -7,18 +7,29 @@ pub enum QuestionType {
     Open,
     Single,
     Multi,
+    Ordering,
+    Numeric,
+    Cloze,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Difficulty {
+    Easy,
+    Medium,
+    Hard,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct QuizOption {
     pub id: String,
     pub text: String,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(untagged)]
 pub enum AnswerKey {
-    Open { text: String },
+    Open(OpenAnswer),
     Single {
         #[serde(rename = "optionId")]
         option_id: String,
@@ -27,9 +38,195 @@ pub enum AnswerKey {
         #[serde(rename = "optionIds")]
         option_ids: Vec<String>,
     },
+    // Option ids from first to last.
+    Ordering { order: Vec<String> },
+    Numeric(NumericAnswer),
+    // Accepted spellings for each `{{n}}` placeholder of the prompt, in order.
+    Cloze { blanks: Vec<Vec<String>> },
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct OpenAnswer {
+    pub text: String,
+    // Other spellings accepted as fully correct.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub accepted: Vec<String>,
+    // Edit distance still accepted after normalization; exact match when absent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_typos: Option<u32>,
+}
+
+pub const MAX_TYPOS: u32 = 3;
+
+impl OpenAnswer {
+    pub fn new(text: impl Into<String>) -> Self {
+        Self { text: text.into(), accepted: Vec::new(), max_typos: None }
+    }
+
+    fn accepts(&self, value: &str) -> bool {
+        let value = normalize_open_answer(value);
+        if value.is_empty() {
+            return false;
+        }
+        let max_typos = self.max_typos.unwrap_or(0) as usize;
+        std::iter::once(&self.text).chain(self.accepted.iter()).any(|variant| {
+            let variant = normalize_open_answer(variant);
+            variant == value || (max_typos > 0 && levenshtein(&variant, &value) <= max_typos)
+        })
+    }
+}
+
+// Case-folded, ё read as е, punctuation treated as a separator and whitespace collapsed, so
+// "Пётр I." and "петр  i" compare equal.
+pub fn normalize_open_answer(value: &str) -> String {
+    value
+        .chars()
+        .flat_map(char::to_lowercase)
+        .map(|c| match c {
+            'ё' => 'е',
+            c if c.is_alphanumeric() => c,
+            _ => ' ',
+        })
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let above = row[j + 1];
+            row[j + 1] = if ca == *cb { diagonal } else { 1 + diagonal.min(above).min(row[j]) };
+            diagonal = above;
+        }
+    }
+    row[b.len()]
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct NumericAnswer {
+    pub value: f64,
+    // Absolute and relative (fraction of `value`) tolerance; an answer within either is accepted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tolerance: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub relative_tolerance: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unit: Option<String>,
+    // Other accepted units and their size in `unit`, e.g. {"cm": 0.01} when unit is "m".
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub unit_factors: BTreeMap<String, f64>,
+}
+
+impl NumericAnswer {
+    fn accepts_value(&self, value: f64) -> bool {
+        let diff = (value - self.value).abs();
+        let absolute = self.tolerance.unwrap_or(0.0);
+        let relative = self.relative_tolerance.unwrap_or(0.0) * self.value.abs();
+        // Some slack for values like 0.1 + 0.2 that have no exact binary form.
+        let epsilon = 1e-9 * self.value.abs().max(1.0);
+        diff <= absolute.max(relative) + epsilon
+    }
+
+    // A bare number is read in the main unit. Without a configured unit any suffix is ignored.
+    fn accepts_text(&self, text: &str) -> bool {
+        let Some((value, unit)) = parse_number_with_unit(text) else { return false };
+        let Some(main) = &self.unit else { return self.accepts_value(value) };
+        let unit = normalize_unit(&unit);
+        if unit.is_empty() || unit == normalize_unit(main) {
+            return self.accepts_value(value);
+        }
+        self.unit_factors
+            .iter()
+            .find(|(name, _)| normalize_unit(name) == unit)
+            .map(|(_, factor)| self.accepts_value(value * factor))
+            .unwrap_or(false)
+    }
+}
+
+fn normalize_unit(unit: &str) -> String {
+    unit.chars()
+        .filter(|c| !c.is_whitespace() && !matches!(c, '^' | '.' | '·' | '*'))
+        .map(|c| match c {
+            '²' => '2',
+            '³' => '3',
+            _ => c,
+        })
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+// Splits "1 234,5 м/с" into (1234.5, "м/с"). Accepts a comma or a dot as the decimal separator,
+// spaces as thousands separators and an exponent ("6,02e23").
+pub fn parse_number_with_unit(input: &str) -> Option<(f64, String)> {
+    let input = input.trim();
+    let chars: Vec<(usize, char)> = input.char_indices().collect();
+    let mut mantissa = String::new();
+    let mut exponent = String::new();
+    let mut split = input.len();
+    let mut i = 0;
+    while i < chars.len() {
+        let (pos, c) = chars[i];
+        let next = chars.get(i + 1).map(|(_, c)| *c);
+        let next_is_digit = next.map(|n| n.is_ascii_digit()).unwrap_or(false);
+        let has_digits = mantissa.chars().any(|c| c.is_ascii_digit());
+        if !exponent.is_empty() {
+            if c.is_ascii_digit() || (exponent == "e" && matches!(c, '+' | '-') && next_is_digit) {
+                exponent.push(c);
+                i += 1;
+                continue;
+            }
+            split = pos;
+            break;
+        }
+        match c {
+            '0'..='9' | '.' | ',' => mantissa.push(c),
+            '-' | '+' | '−' if i == 0 => mantissa.push(if c == '+' { '+' } else { '-' }),
+            ' ' | '\u{a0}' | '\u{202f}' if has_digits && next_is_digit => {}
+            'e' | 'E'
+                if has_digits
+                    && (next_is_digit
+                        || (matches!(next, Some('+' | '-'))
+                            && chars.get(i + 2).map(|(_, c)| c.is_ascii_digit()).unwrap_or(false))) =>
+            {
+                exponent.push('e')
+            }
+            _ => {
+                split = pos;
+                break;
+            }
+        }
+        i += 1;
+    }
+    let last_comma = mantissa.rfind(',');
+    let last_dot = mantissa.rfind('.');
+    let decimal = match (last_comma, last_dot) {
+        (Some(c), Some(d)) => Some(c.max(d)),
+        (Some(c), None) if mantissa.matches(',').count() == 1 => Some(c),
+        (None, Some(d)) if mantissa.matches('.').count() == 1 => Some(d),
+        _ => None,
+    };
+    let normalized: String = mantissa
+        .char_indices()
+        .filter_map(|(idx, c)| match c {
+            ',' | '.' if Some(idx) == decimal => Some('.'),
+            ',' | '.' => None,
+            _ => Some(c),
+        })
+        .chain(exponent.chars())
+        .collect();
+    let value = normalized.parse::<f64>().ok().filter(|v| v.is_finite())?;
+    Some((value, input[split..].trim().to_string()))
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct Question {
     pub id: String,
     #[serde(rename = "type")]
@@ -38,6 +235,35 @@ pub struct Question {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub options: Option<Vec<QuizOption>>,
     pub answer: AnswerKey,
+    #[serde(default, rename = "timeLimitSecs", skip_serializing_if = "Option::is_none")]
+    pub time_limit_secs: Option<u32>,
+    // Weight of a fully correct answer; 1 when absent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub points: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub difficulty: Option<Difficulty>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    // Shown to the student once the question is answered.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub explanation: Option<String>,
+}
+
+pub const TIME_LIMIT_RANGE_SECS: std::ops::RangeInclusive<u32> = 5..=600;
+pub const POINTS_RANGE: std::ops::RangeInclusive<u32> = 1..=100;
+pub const MAX_EXPLANATION_CHARS: usize = 1000;
+pub const MAX_TAGS: usize = 10;
+pub const MAX_TAG_CHARS: usize = 32;
+
+// Tags compare case-insensitively, so filters and duplicate checks go through this.
+pub fn normalize_tag(tag: &str) -> String {
+    tag.trim().to_lowercase()
+}
+
+impl Question {
+    pub fn points(&self) -> f64 {
+        self.points.unwrap_or(1) as f64
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -46,6 +272,79 @@ pub struct Quiz {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub description: Option<String>,
     pub questions: Vec<Question>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub difficulty: Option<Difficulty>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "QuizSettings::is_default")]
+    pub settings: QuizSettings,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subject: Option<String>,
+    // School year the quiz targets.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub grade: Option<u8>,
+    // ISO 639-1 code of the quiz text, e.g. "ru".
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+}
+
+// How a quiz is played; the defaults match the original behaviour (fixed order, one attempt).
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct QuizSettings {
+    #[serde(default)]
+    pub shuffle_questions: bool,
+    #[serde(default)]
+    pub shuffle_options: bool,
+    // Extra attempts after a wrong answer before moving on.
+    #[serde(default)]
+    pub retries: u32,
+    // Share of the maximum score needed to pass, in percent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub passing_pct: Option<u32>,
+    // Reveal the answer key in `answer_result` after the last attempt.
+    #[serde(default)]
+    pub show_correct_answer: bool,
+    // AI hints each student may request during a session; 0 turns hints off.
+    #[serde(default)]
+    pub hints_per_student: u32,
+}
+
+// Per-participant shuffling with `shuffleQuestions`/`shuffleOptions`. Everything derives from the seed kept
+// on the participant, so a resend or a reconnect shows the same order. Options keep their ids, so answers
+// are scored against the quiz as written whatever order they were shown in.
+pub fn question_order(len: usize, seed: u64) -> Vec<usize> {
+    use rand::{seq::SliceRandom, SeedableRng};
+    let mut order: Vec<usize> = (0..len).collect();
+    order.shuffle(&mut rand::rngs::StdRng::seed_from_u64(seed));
+    order
+}
+
+pub fn shuffle_options(question: &mut Question, seed: u64) {
+    use rand::{seq::SliceRandom, SeedableRng};
+    // FNV-1a of the id, so each question gets its own order.
+    let question_seed = question
+        .id
+        .bytes()
+        .fold(0xcbf2_9ce4_8422_2325_u64, |hash, b| (hash ^ b as u64).wrapping_mul(0x0100_0000_01b3));
+    if let Some(options) = question.options.as_mut() {
+        options.shuffle(&mut rand::rngs::StdRng::seed_from_u64(seed ^ question_seed));
+    }
+}
+
+pub const MAX_RETRIES: u32 = 5;
+pub const MAX_HINTS_PER_STUDENT: u32 = 10;
+pub const MAX_SUBJECT_CHARS: usize = 64;
+pub const GRADE_RANGE: std::ops::RangeInclusive<u8> = 1..=11;
+
+pub fn valid_language(code: &str) -> bool {
+    code.len() == 2 && code.chars().all(|c| c.is_ascii_lowercase())
+}
+
+impl QuizSettings {
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -60,6 +359,9 @@ pub enum SubmittedAnswer {
         #[serde(rename = "optionIds")]
         option_ids: Vec<String>,
     },
+    Ordering { order: Vec<String> },
+    Numeric { value: f64 },
+    Cloze { blanks: Vec<String> },
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
@@ -67,9 +369,64 @@ pub struct StudentStats {
     pub nickname: String,
     pub correct: u32,
     pub wrong: u32,
+    // Points earned: each answer's credit times its question's points.
+    #[serde(default)]
+    pub score: f64,
+    // Speed and streak bonuses on top of `score`. Kept apart so pass marks stay about accuracy.
+    #[serde(default)]
+    pub bonus: f64,
+    // Taken off for answers that cost a life in game modes with a respawn penalty.
+    #[serde(default)]
+    pub penalty: f64,
+    // Correct answers in a row so far, and the longest such run.
+    #[serde(default)]
+    pub streak: u32,
+    #[serde(default)]
+    pub best_streak: u32,
+}
+
+// Points engine for the leaderboard: an instant correct answer earns up to half its points again,
+// shrinking to nothing over the question's time limit (SPEED_BONUS_WINDOW_SECS when it has none),
+// and every correct answer in a row adds 10% up to 1.5x.
+pub const SPEED_BONUS_SHARE: f64 = 0.5;
+pub const SPEED_BONUS_WINDOW_SECS: u32 = 30;
+pub const STREAK_STEP: f64 = 0.1;
+pub const MAX_STREAK_MULTIPLIER: f64 = 1.5;
+
+// Bonus for a correct answer worth `points`, given `streak` correct answers in a row including this
+// one. Without a latency (the question was never pushed) there is no speed bonus.
+pub fn answer_bonus(points: f64, latency_secs: Option<f64>, window_secs: u32, streak: u32) -> f64 {
+    let speed = latency_secs
+        .map(|latency| points * SPEED_BONUS_SHARE * (1.0 - latency / window_secs.max(1) as f64).clamp(0.0, 1.0))
+        .unwrap_or(0.0);
+    let multiplier = (1.0 + STREAK_STEP * streak.saturating_sub(1) as f64).min(MAX_STREAK_MULTIPLIER);
+    (points + speed) * multiplier - points
 }
 
 impl StudentStats {
+    pub fn points(&self) -> f64 {
+        self.score + self.bonus - self.penalty
+    }
+
+    // Records a scored answer in the streak and returns it including this answer; 0 after a miss.
+    pub fn extend_streak(&mut self, correct: bool) -> u32 {
+        self.streak = if correct { self.streak + 1 } else { 0 };
+        self.best_streak = self.best_streak.max(self.streak);
+        self.streak
+    }
+
+    // Leaderboard order: points with bonuses, then correct answers, then nickname for a stable tie-break.
+    pub fn ranked(stats: &HashMap<String, StudentStats>) -> Vec<&StudentStats> {
+        let mut ranked: Vec<&StudentStats> = stats.values().collect();
+        ranked.sort_by(|a, b| {
+            b.points()
+                .total_cmp(&a.points())
+                .then_with(|| b.correct.cmp(&a.correct))
+                .then_with(|| a.nickname.cmp(&b.nickname))
+        });
+        ranked
+    }
+
     pub fn correct_pct(&self) -> f64 {
         let total = self.correct + self.wrong;
         if total == 0 {
@@ -80,14 +437,129 @@ impl StudentStats {
     }
 }
 
+// Structural bounds for a quiz; everything within them is stored and broadcast over WS as is.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuizLimits {
+    pub max_questions: usize,
+    pub max_options: usize,
+    pub max_prompt_chars: usize,
+    pub max_option_chars: usize,
+    // Size of the quiz serialized as JSON.
+    pub max_payload_bytes: usize,
+}
+
+impl Default for QuizLimits {
+    fn default() -> Self {
+        Self {
+            max_questions: 200,
+            max_options: 20,
+            max_prompt_chars: 2000,
+            max_option_chars: 300,
+            max_payload_bytes: 512 * 1024,
+        }
+    }
+}
+
+impl QuizLimits {
+    pub fn from_env() -> Self {
+        let env = |name: &str, default: usize| {
+            std::env::var(name)
+                .ok()
+                .and_then(|v| v.parse::<usize>().ok())
+                .filter(|v| *v > 0)
+                .unwrap_or(default)
+        };
+        let defaults = Self::default();
+        Self {
+            max_questions: env("QUIZ_MAX_QUESTIONS", defaults.max_questions),
+            max_options: env("QUIZ_MAX_OPTIONS", defaults.max_options),
+            max_prompt_chars: env("QUIZ_MAX_PROMPT_CHARS", defaults.max_prompt_chars),
+            max_option_chars: env("QUIZ_MAX_OPTION_CHARS", defaults.max_option_chars),
+            max_payload_bytes: env("QUIZ_MAX_PAYLOAD_BYTES", defaults.max_payload_bytes),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ValidationIssue {
     pub field: String,
     pub issue: String,
 }
 
-pub fn validate_quiz(quiz: &Quiz) -> Result<(), Vec<ValidationIssue>> {
+fn validate_tags(field: &str, tags: &[String], issues: &mut Vec<ValidationIssue>) {
+    if tags.len() > MAX_TAGS {
+        issues.push(ValidationIssue {
+            field: field.to_string(),
+            issue: format!("must contain at most {} tags", MAX_TAGS),
+        });
+    }
+    let mut seen = HashSet::new();
+    for (j, tag) in tags.iter().enumerate() {
+        let normalized = normalize_tag(tag);
+        if normalized.is_empty() || normalized.chars().count() > MAX_TAG_CHARS {
+            issues.push(ValidationIssue {
+                field: format!("{field}[{j}]"),
+                issue: format!("must be 1..{} characters", MAX_TAG_CHARS),
+            });
+        } else if !seen.insert(normalized) {
+            issues.push(ValidationIssue {
+                field: format!("{field}[{j}]"),
+                issue: "must be unique".into(),
+            });
+        }
+    }
+}
+
+fn validate_numeric(i: usize, key: &NumericAnswer, issues: &mut Vec<ValidationIssue>) {
+    if !key.value.is_finite() {
+        issues.push(ValidationIssue {
+            field: format!("questions[{i}].answer.value"),
+            issue: "must be a finite number".into(),
+        });
+    }
+    if key.tolerance.map(|t| !t.is_finite() || t < 0.0).unwrap_or(false) {
+        issues.push(ValidationIssue {
+            field: format!("questions[{i}].answer.tolerance"),
+            issue: "must be a non-negative number".into(),
+        });
+    }
+    if key.relative_tolerance.map(|t| !(0.0..=1.0).contains(&t)).unwrap_or(false) {
+        issues.push(ValidationIssue {
+            field: format!("questions[{i}].answer.relativeTolerance"),
+            issue: "must be between 0 and 1".into(),
+        });
+    }
+    if key.unit.as_ref().map(|u| normalize_unit(u).is_empty()).unwrap_or(false) {
+        issues.push(ValidationIssue {
+            field: format!("questions[{i}].answer.unit"),
+            issue: "must not be empty when present".into(),
+        });
+    }
+    if key.unit.is_none() && !key.unit_factors.is_empty() {
+        issues.push(ValidationIssue {
+            field: format!("questions[{i}].answer.unitFactors"),
+            issue: "requires unit".into(),
+        });
+    }
+    for (name, factor) in &key.unit_factors {
+        if normalize_unit(name).is_empty() || !factor.is_finite() || *factor <= 0.0 {
+            issues.push(ValidationIssue {
+                field: format!("questions[{i}].answer.unitFactors.{name}"),
+                issue: "must be a non-empty unit with a positive factor".into(),
+            });
+        }
+    }
+}
+
+pub fn validate_quiz(quiz: &Quiz, limits: &QuizLimits) -> Result<(), Vec<ValidationIssue>> {
     let mut issues = Vec::new();
+    let payload_bytes = serde_json::to_vec(quiz).map(|v| v.len()).unwrap_or(0);
+    if payload_bytes > limits.max_payload_bytes {
+        issues.push(ValidationIssue {
+            field: "quiz".into(),
+            issue: format!("must be at most {} bytes as JSON, got {}", limits.max_payload_bytes, payload_bytes),
+        });
+    }
     if quiz.title.trim().is_empty() {
         issues.push(ValidationIssue {
             field: "title".into(),
@@ -108,6 +580,51 @@ pub fn validate_quiz(quiz: &Quiz) -> Result<(), Vec<ValidationIssue>> {
             issue: "must contain at least one question".into(),
         });
     }
+    if quiz.questions.len() > limits.max_questions {
+        issues.push(ValidationIssue {
+            field: "questions".into(),
+            issue: format!("must contain at most {} questions", limits.max_questions),
+        });
+    }
+    validate_tags("tags", &quiz.tags, &mut issues);
+    if let Some(subject) = &quiz.subject {
+        if subject.trim().is_empty() || subject.chars().count() > MAX_SUBJECT_CHARS {
+            issues.push(ValidationIssue {
+                field: "subject".into(),
+                issue: format!("must be 1..{} characters when present", MAX_SUBJECT_CHARS),
+            });
+        }
+    }
+    if quiz.grade.map(|g| !GRADE_RANGE.contains(&g)).unwrap_or(false) {
+        issues.push(ValidationIssue {
+            field: "grade".into(),
+            issue: format!("must be between {} and {}", GRADE_RANGE.start(), GRADE_RANGE.end()),
+        });
+    }
+    if quiz.language.as_deref().map(|l| !valid_language(l)).unwrap_or(false) {
+        issues.push(ValidationIssue {
+            field: "language".into(),
+            issue: "must be a lowercase ISO 639-1 code".into(),
+        });
+    }
+    if quiz.settings.retries > MAX_RETRIES {
+        issues.push(ValidationIssue {
+            field: "settings.retries".into(),
+            issue: format!("must be at most {}", MAX_RETRIES),
+        });
+    }
+    if quiz.settings.hints_per_student > MAX_HINTS_PER_STUDENT {
+        issues.push(ValidationIssue {
+            field: "settings.hintsPerStudent".into(),
+            issue: format!("must be at most {}", MAX_HINTS_PER_STUDENT),
+        });
+    }
+    if quiz.settings.passing_pct.map(|p| p == 0 || p > 100).unwrap_or(false) {
+        issues.push(ValidationIssue {
+            field: "settings.passingPct".into(),
+            issue: "must be between 1 and 100".into(),
+        });
+    }
 
     let mut question_ids = HashSet::new();
     for (i, q) in quiz.questions.iter().enumerate() {
@@ -129,6 +646,33 @@ pub fn validate_quiz(quiz: &Quiz) -> Result<(), Vec<ValidationIssue>> {
                 issue: "must not be empty".into(),
             });
         }
+        if q.prompt.chars().count() > limits.max_prompt_chars {
+            issues.push(ValidationIssue {
+                field: format!("questions[{i}].prompt"),
+                issue: format!("must be at most {} characters", limits.max_prompt_chars),
+            });
+        }
+        if q.time_limit_secs.map(|t| !TIME_LIMIT_RANGE_SECS.contains(&t)).unwrap_or(false) {
+            issues.push(ValidationIssue {
+                field: format!("questions[{i}].timeLimitSecs"),
+                issue: format!("must be between {} and {}", TIME_LIMIT_RANGE_SECS.start(), TIME_LIMIT_RANGE_SECS.end()),
+            });
+        }
+        if q.points.map(|p| !POINTS_RANGE.contains(&p)).unwrap_or(false) {
+            issues.push(ValidationIssue {
+                field: format!("questions[{i}].points"),
+                issue: format!("must be between {} and {}", POINTS_RANGE.start(), POINTS_RANGE.end()),
+            });
+        }
+        validate_tags(&format!("questions[{i}].tags"), &q.tags, &mut issues);
+        if let Some(explanation) = &q.explanation {
+            if explanation.trim().is_empty() || explanation.chars().count() > MAX_EXPLANATION_CHARS {
+                issues.push(ValidationIssue {
+                    field: format!("questions[{i}].explanation"),
+                    issue: format!("must be 1..{} characters when present", MAX_EXPLANATION_CHARS),
+                });
+            }
+        }
 
         match q.q_type {
             QuestionType::Open => {
@@ -139,13 +683,27 @@ pub fn validate_quiz(quiz: &Quiz) -> Result<(), Vec<ValidationIssue>> {
                     });
                 }
                 match &q.answer {
-                    AnswerKey::Open { text } => {
-                        if text.trim().is_empty() {
+                    AnswerKey::Open(key) => {
+                        if key.text.trim().is_empty() {
                             issues.push(ValidationIssue {
                                 field: format!("questions[{i}].answer.text"),
                                 issue: "must not be empty".into(),
                             });
                         }
+                        for (j, variant) in key.accepted.iter().enumerate() {
+                            if normalize_open_answer(variant).is_empty() {
+                                issues.push(ValidationIssue {
+                                    field: format!("questions[{i}].answer.accepted[{j}]"),
+                                    issue: "must contain letters or digits".into(),
+                                });
+                            }
+                        }
+                        if key.max_typos.map(|t| t > MAX_TYPOS).unwrap_or(false) {
+                            issues.push(ValidationIssue {
+                                field: format!("questions[{i}].answer.maxTypos"),
+                                issue: format!("must be at most {}", MAX_TYPOS),
+                            });
+                        }
                     }
                     _ => issues.push(ValidationIssue {
                         field: format!("questions[{i}].answer"),
@@ -153,12 +711,65 @@ pub fn validate_quiz(quiz: &Quiz) -> Result<(), Vec<ValidationIssue>> {
                     }),
                 }
             }
-            QuestionType::Single | QuestionType::Multi => {
+            QuestionType::Cloze => {
+                if q.options.is_some() {
+                    issues.push(ValidationIssue {
+                        field: format!("questions[{i}].options"),
+                        issue: "must be absent for cloze question".into(),
+                    });
+                }
+                match &q.answer {
+                    AnswerKey::Cloze { blanks } => {
+                        if blanks.is_empty() {
+                            issues.push(ValidationIssue {
+                                field: format!("questions[{i}].answer.blanks"),
+                                issue: "must contain at least one blank".into(),
+                            });
+                        }
+                        for (k, accepted) in blanks.iter().enumerate() {
+                            if accepted.is_empty() || accepted.iter().any(|a| a.trim().is_empty()) {
+                                issues.push(ValidationIssue {
+                                    field: format!("questions[{i}].answer.blanks[{k}]"),
+                                    issue: "must list non-empty accepted answers".into(),
+                                });
+                            }
+                        }
+                        let mut placeholders = cloze_placeholders(&q.prompt);
+                        placeholders.sort_unstable();
+                        if placeholders != (1..=blanks.len()).collect::<Vec<_>>() {
+                            issues.push(ValidationIssue {
+                                field: format!("questions[{i}].prompt"),
+                                issue: format!("must contain placeholders {{{{1}}}}..{{{{{}}}}} once each", blanks.len()),
+                            });
+                        }
+                    }
+                    _ => issues.push(ValidationIssue {
+                        field: format!("questions[{i}].answer"),
+                        issue: "must match cloze format".into(),
+                    }),
+                }
+            }
+            QuestionType::Numeric => {
+                if q.options.is_some() {
+                    issues.push(ValidationIssue {
+                        field: format!("questions[{i}].options"),
+                        issue: "must be absent for numeric question".into(),
+                    });
+                }
+                match &q.answer {
+                    AnswerKey::Numeric(key) => validate_numeric(i, key, &mut issues),
+                    _ => issues.push(ValidationIssue {
+                        field: format!("questions[{i}].answer"),
+                        issue: "must match numeric format".into(),
+                    }),
+                }
+            }
+            QuestionType::Single | QuestionType::Multi | QuestionType::Ordering => {
                 let options = q.options.as_ref();
                 if options.is_none() {
                     issues.push(ValidationIssue {
                         field: format!("questions[{i}].options"),
-                        issue: "is required for single/multi".into(),
+                        issue: "is required for single/multi/ordering".into(),
                     });
                 }
                 let mut map = HashMap::new();
@@ -169,6 +780,12 @@ pub fn validate_quiz(quiz: &Quiz) -> Result<(), Vec<ValidationIssue>> {
                             issue: "must contain at least 2 options".into(),
                         });
                     }
+                    if opts.len() > limits.max_options {
+                        issues.push(ValidationIssue {
+                            field: format!("questions[{i}].options"),
+                            issue: format!("must contain at most {} options", limits.max_options),
+                        });
+                    }
                     for (j, opt) in opts.iter().enumerate() {
                         if opt.id.trim().is_empty() || opt.text.trim().is_empty() {
                             issues.push(ValidationIssue {
@@ -176,6 +793,12 @@ pub fn validate_quiz(quiz: &Quiz) -> Result<(), Vec<ValidationIssue>> {
                                 issue: "id/text must not be empty".into(),
                             });
                         }
+                        if opt.text.chars().count() > limits.max_option_chars {
+                            issues.push(ValidationIssue {
+                                field: format!("questions[{i}].options[{j}].text"),
+                                issue: format!("must be at most {} characters", limits.max_option_chars),
+                            });
+                        }
                         if map.insert(opt.id.clone(), true).is_some() {
                             issues.push(ValidationIssue {
                                 field: format!("questions[{i}].options[{j}].id"),
@@ -226,6 +849,33 @@ pub fn validate_quiz(quiz: &Quiz) -> Result<(), Vec<ValidationIssue>> {
                             }
                         }
                     }
+                    (QuestionType::Ordering, AnswerKey::Ordering { order }) => {
+                        let mut seen = HashSet::new();
+                        for (k, id) in order.iter().enumerate() {
+                            if !seen.insert(id.clone()) {
+                                issues.push(ValidationIssue {
+                                    field: format!("questions[{i}].answer.order[{k}]"),
+                                    issue: "must be unique".into(),
+                                });
+                            }
+                            if let Some(opts) = options {
+                                if !opts.iter().any(|o| o.id == *id) {
+                                    issues.push(ValidationIssue {
+                                        field: format!("questions[{i}].answer.order[{k}]"),
+                                        issue: "must reference existing option id".into(),
+                                    });
+                                }
+                            }
+                        }
+                        if let Some(opts) = options {
+                            if opts.iter().any(|o| !seen.contains(&o.id)) {
+                                issues.push(ValidationIssue {
+                                    field: format!("questions[{i}].answer.order"),
+                                    issue: "must reference every option exactly once".into(),
+                                });
+                            }
+                        }
+                    }
                     _ => issues.push(ValidationIssue {
                         field: format!("questions[{i}].answer"),
                         issue: "must match question type".into(),
@@ -242,11 +892,98 @@ pub fn validate_quiz(quiz: &Quiz) -> Result<(), Vec<ValidationIssue>> {
     }
 }
 
-pub fn score_answer(question: &Question, submitted: &SubmittedAnswer) -> bool {
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnswerGrade {
+    pub correct: bool,
+    // 0.0..=1.0; equals 1.0 exactly when `correct`.
+    pub credit: f64,
+    // Per-blank correctness for cloze questions.
+    pub blanks: Option<Vec<bool>>,
+}
+
+impl AnswerGrade {
+    fn exact(correct: bool) -> Self {
+        Self { correct, credit: if correct { 1.0 } else { 0.0 }, blanks: None }
+    }
+}
+
+pub fn grade_answer(question: &Question, submitted: &SubmittedAnswer) -> AnswerGrade {
     match (&question.answer, submitted) {
-        (AnswerKey::Open { text }, SubmittedAnswer::Open { text: value }) => {
-            text.trim().eq_ignore_ascii_case(value.trim())
+        (AnswerKey::Ordering { order }, SubmittedAnswer::Ordering { order: value }) => grade_ordering(order, value),
+        (AnswerKey::Cloze { blanks }, SubmittedAnswer::Cloze { blanks: values }) => grade_cloze(blanks, values),
+        _ => AnswerGrade::exact(score_answer(question, submitted)),
+    }
+}
+
+// Trimmed, case-insensitive (including Cyrillic) and with inner whitespace collapsed.
+fn normalize_blank(value: &str) -> String {
+    value.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase()
+}
+
+// Each blank is scored on its own; a missing blank counts as wrong.
+fn grade_cloze(expected: &[Vec<String>], submitted: &[String]) -> AnswerGrade {
+    let blanks: Vec<bool> = expected
+        .iter()
+        .enumerate()
+        .map(|(i, accepted)| {
+            submitted
+                .get(i)
+                .map(|value| accepted.iter().any(|a| normalize_blank(a) == normalize_blank(value)))
+                .unwrap_or(false)
+        })
+        .collect();
+    let hits = blanks.iter().filter(|b| **b).count();
+    let correct = !blanks.is_empty() && hits == blanks.len();
+    let credit = if blanks.is_empty() { 0.0 } else { hits as f64 / blanks.len() as f64 };
+    AnswerGrade { correct, credit, blanks: Some(blanks) }
+}
+
+// Placeholder numbers in order of appearance: "{{1}} и {{2}}" -> [1, 2].
+pub fn cloze_placeholders(prompt: &str) -> Vec<usize> {
+    let mut found = Vec::new();
+    let mut rest = prompt;
+    while let Some(start) = rest.find("{{") {
+        rest = &rest[start + 2..];
+        if let Some(end) = rest.find("}}") {
+            if let Ok(n) = rest[..end].trim().parse::<usize>() {
+                found.push(n);
+                rest = &rest[end + 2..];
+            }
+        }
+    }
+    found
+}
+
+// Partial credit is the share of option pairs the student put in the right relative order, so
+// one misplaced item costs little while a reversed list scores zero.
+fn grade_ordering(expected: &[String], submitted: &[String]) -> AnswerGrade {
+    let positions: HashMap<&str, usize> = submitted.iter().enumerate().map(|(i, id)| (id.as_str(), i)).collect();
+    let complete = submitted.len() == expected.len()
+        && positions.len() == expected.len()
+        && expected.iter().all(|id| positions.contains_key(id.as_str()));
+    if !complete {
+        return AnswerGrade::exact(false);
+    }
+    if expected == submitted {
+        return AnswerGrade::exact(true);
+    }
+    let mut pairs = 0u32;
+    let mut concordant = 0u32;
+    for (i, a) in expected.iter().enumerate() {
+        for b in &expected[i + 1..] {
+            pairs += 1;
+            if positions[a.as_str()] < positions[b.as_str()] {
+                concordant += 1;
+            }
         }
+    }
+    let credit = if pairs == 0 { 0.0 } else { concordant as f64 / pairs as f64 };
+    AnswerGrade { correct: false, credit, blanks: None }
+}
+
+pub fn score_answer(question: &Question, submitted: &SubmittedAnswer) -> bool {
+    match (&question.answer, submitted) {
+        (AnswerKey::Open(key), SubmittedAnswer::Open { text: value }) => key.accepts(value),
         (AnswerKey::Single { option_id }, SubmittedAnswer::Single { option_id: value }) => {
             option_id == value
         }
@@ -255,10 +992,49 @@ pub fn score_answer(question: &Question, submitted: &SubmittedAnswer) -> bool {
             let actual: HashSet<_> = value.iter().collect();
             expected == actual
         }
+        (AnswerKey::Ordering { order }, SubmittedAnswer::Ordering { order: value }) => order == value,
+        // Students type numbers as text; "9,81 м/с²" and {"value": 9.81} are both accepted.
+        (AnswerKey::Numeric(key), SubmittedAnswer::Open { text }) => key.accepts_text(text),
+        (AnswerKey::Numeric(key), SubmittedAnswer::Numeric { value }) => key.accepts_value(*value),
+        (AnswerKey::Cloze { blanks }, SubmittedAnswer::Cloze { blanks: values }) => grade_cloze(blanks, values).correct,
         _ => false,
     }
 }
 
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct QuestionDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub changed: Vec<String>,
+}
+
+impl QuestionDiff {
+    // Questions are matched by id; a question counts as changed if any field or its position differs.
+    pub fn between(before: &[Question], after: &[Question]) -> Self {
+        let old: HashMap<&str, (usize, &Question)> =
+            before.iter().enumerate().map(|(i, q)| (q.id.as_str(), (i, q))).collect();
+        let new_ids: HashSet<&str> = after.iter().map(|q| q.id.as_str()).collect();
+        let mut diff = QuestionDiff::default();
+        for (i, q) in after.iter().enumerate() {
+            match old.get(q.id.as_str()) {
+                None => diff.added.push(q.id.clone()),
+                Some((pos, prev)) if *pos != i || *prev != q => diff.changed.push(q.id.clone()),
+                Some(_) => {}
+            }
+        }
+        diff.removed = before
+            .iter()
+            .filter(|q| !new_ids.contains(q.id.as_str()))
+            .map(|q| q.id.clone())
+            .collect();
+        diff
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -273,7 +1049,12 @@ mod tests {
                     q_type: QuestionType::Open,
                     prompt: "2+2".into(),
                     options: None,
-                    answer: AnswerKey::Open { text: "4".into() },
+                    time_limit_secs: None,
+                    points: None,
+                    difficulty: None,
+                    tags: Vec::new(),
+                    explanation: None,
+                    answer: AnswerKey::Open(OpenAnswer::new("4")),
                 },
                 Question {
                     id: "q2".into(),
@@ -283,6 +1064,11 @@ mod tests {
                         QuizOption { id: "o1".into(), text: "Paris".into() },
                         QuizOption { id: "o2".into(), text: "Rome".into() },
                     ]),
+                    time_limit_secs: None,
+                    points: None,
+                    difficulty: None,
+                    tags: Vec::new(),
+                    explanation: None,
                     answer: AnswerKey::Single { option_id: "o1".into() },
                 },
                 Question {
@@ -294,16 +1080,27 @@ mod tests {
                         QuizOption { id: "o2".into(), text: "3".into() },
                         QuizOption { id: "o3".into(), text: "4".into() },
                     ]),
+                    time_limit_secs: None,
+                    points: None,
+                    difficulty: None,
+                    tags: Vec::new(),
+                    explanation: None,
                     answer: AnswerKey::Multi { option_ids: vec!["o1".into(), "o3".into()] },
                 },
             ],
+            difficulty: None,
+            tags: Vec::new(),
+            settings: QuizSettings::default(),
+            subject: None,
+            grade: None,
+            language: None,
         }
     }
 
     #[test]
     fn validate_quiz_ok() {
         let quiz = sample_quiz();
-        assert!(validate_quiz(&quiz).is_ok());
+        assert!(validate_quiz(&quiz, &QuizLimits::default()).is_ok());
     }
 
     #[test]
@@ -311,7 +1108,7 @@ mod tests {
         let mut quiz = sample_quiz();
         quiz.questions[0].options = Some(vec![]);
         quiz.questions[1].id = "q1".into();
-        let result = validate_quiz(&quiz);
+        let result = validate_quiz(&quiz, &QuizLimits::default());
         assert!(result.is_err());
         let issues = result.err().unwrap();
         assert!(issues.iter().any(|i| i.issue.contains("unique")));
@@ -338,13 +1135,350 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn open_answers_are_normalized_with_variants_and_typos() {
+        let mut quiz = sample_quiz();
+        quiz.questions[0].prompt = "Кто основал Санкт-Петербург?".into();
+        quiz.questions[0].answer = AnswerKey::Open(OpenAnswer {
+            text: "Пётр I".into(),
+            accepted: vec!["Петр Первый".into()],
+            max_typos: None,
+        });
+        let q = &quiz.questions[0];
+        let open = |text: &str| SubmittedAnswer::Open { text: text.into() };
+        assert!(score_answer(q, &open("  петр   i. ")));
+        assert!(score_answer(q, &open("ПЁТР I")));
+        assert!(score_answer(q, &open("Пётр, первый!")));
+        assert!(!score_answer(q, &open("Петр 1")));
+        assert!(!score_answer(q, &open("...")));
+
+        quiz.questions[0].answer = AnswerKey::Open(OpenAnswer { max_typos: Some(1), ..OpenAnswer::new("Пётр I") });
+        assert!(score_answer(&quiz.questions[0], &open("Петр 1")));
+        assert!(!score_answer(&quiz.questions[0], &open("Павел 1")));
+        assert!(validate_quiz(&quiz, &QuizLimits::default()).is_ok());
+
+        quiz.questions[0].answer = AnswerKey::Open(OpenAnswer {
+            text: "Пётр I".into(),
+            accepted: vec!["?!".into()],
+            max_typos: Some(MAX_TYPOS + 1),
+        });
+        let issues = validate_quiz(&quiz, &QuizLimits::default()).unwrap_err();
+        assert!(issues.iter().any(|i| i.field == "questions[0].answer.accepted[0]"));
+        assert!(issues.iter().any(|i| i.field == "questions[0].answer.maxTypos"));
+        assert_eq!(levenshtein("котёнок", "котенок"), 1);
+    }
+
+    #[test]
+    fn ordering_validation_and_partial_credit() {
+        let mut quiz = sample_quiz();
+        quiz.questions.push(Question {
+            id: "q4".into(),
+            q_type: QuestionType::Ordering,
+            prompt: "Oldest first".into(),
+            options: Some(vec![
+                QuizOption { id: "a".into(), text: "Rurik".into() },
+                QuizOption { id: "b".into(), text: "Peter I".into() },
+                QuizOption { id: "c".into(), text: "Lenin".into() },
+            ]),
+            time_limit_secs: None,
+            points: None,
+            difficulty: None,
+            tags: Vec::new(),
+            explanation: None,
+            answer: AnswerKey::Ordering { order: vec!["a".into(), "b".into(), "c".into()] },
+        });
+        assert!(validate_quiz(&quiz, &QuizLimits::default()).is_ok());
+
+        let q = &quiz.questions[3];
+        let order = |ids: &[&str]| SubmittedAnswer::Ordering { order: ids.iter().map(|s| s.to_string()).collect() };
+        assert_eq!(grade_answer(q, &order(&["a", "b", "c"])), AnswerGrade { correct: true, credit: 1.0, blanks: None });
+        let swapped = grade_answer(q, &order(&["b", "a", "c"]));
+        assert!(!swapped.correct);
+        assert!((swapped.credit - 2.0 / 3.0).abs() < 1e-9);
+        assert_eq!(grade_answer(q, &order(&["c", "b", "a"])).credit, 0.0);
+        assert_eq!(grade_answer(q, &order(&["a", "a", "c"])).credit, 0.0);
+        let parsed: SubmittedAnswer = serde_json::from_value(serde_json::json!({"order": ["a", "b", "c"]})).unwrap();
+        assert!(score_answer(q, &parsed));
+
+        let mut broken = quiz.clone();
+        broken.questions[3].answer = AnswerKey::Ordering { order: vec!["a".into(), "b".into(), "b".into()] };
+        let issues = validate_quiz(&broken, &QuizLimits::default()).unwrap_err();
+        assert!(issues.iter().any(|i| i.field == "questions[3].answer.order[2]"));
+        assert!(issues.iter().any(|i| i.issue.contains("exactly once")));
+    }
+
+    #[test]
+    fn numeric_parsing_tolerance_and_units() {
+        assert_eq!(parse_number_with_unit("9,81 м/с²"), Some((9.81, "м/с²".to_string())));
+        assert_eq!(parse_number_with_unit("1 234,5"), Some((1234.5, String::new())));
+        assert_eq!(parse_number_with_unit("1,234.5 kg"), Some((1234.5, "kg".to_string())));
+        assert_eq!(parse_number_with_unit("-6,02e23"), Some((-6.02e23, String::new())));
+        assert_eq!(parse_number_with_unit("−3"), Some((-3.0, String::new())));
+        assert_eq!(parse_number_with_unit("10 eggs"), Some((10.0, "eggs".to_string())));
+        assert!(parse_number_with_unit("abc").is_none());
+
+        let question = Question {
+            id: "q5".into(),
+            q_type: QuestionType::Numeric,
+            prompt: "g".into(),
+            options: None,
+            time_limit_secs: None,
+            points: None,
+            difficulty: None,
+            tags: Vec::new(),
+            explanation: None,
+            answer: AnswerKey::Numeric(NumericAnswer {
+                value: 9.81,
+                tolerance: Some(0.05),
+                relative_tolerance: None,
+                unit: Some("м/с²".into()),
+                unit_factors: BTreeMap::from([("см/с²".to_string(), 0.01)]),
+            }),
+        };
+        let text = |t: &str| SubmittedAnswer::Open { text: t.into() };
+        assert!(score_answer(&question, &text("9,8")));
+        assert!(score_answer(&question, &text("9.85 м/с^2")));
+        assert!(score_answer(&question, &text("981 см/с²")));
+        assert!(!score_answer(&question, &text("9,7")));
+        assert!(!score_answer(&question, &text("9,81 км")));
+        assert!(score_answer(&question, &SubmittedAnswer::Numeric { value: 9.81 }));
+
+        let mut quiz = sample_quiz();
+        quiz.questions.push(question);
+        assert!(validate_quiz(&quiz, &QuizLimits::default()).is_ok());
+        if let AnswerKey::Numeric(key) = &mut quiz.questions[3].answer {
+            key.relative_tolerance = Some(2.0);
+        }
+        let issues = validate_quiz(&quiz, &QuizLimits::default()).unwrap_err();
+        assert!(issues.iter().any(|i| i.field == "questions[3].answer.relativeTolerance"));
+    }
+
+    #[test]
+    fn cloze_blanks_are_scored_independently() {
+        let mut quiz = sample_quiz();
+        quiz.questions.push(Question {
+            id: "q6".into(),
+            q_type: QuestionType::Cloze,
+            prompt: "Столица России — {{1}}, она стоит на реке {{2}}.".into(),
+            options: None,
+            time_limit_secs: None,
+            points: None,
+            difficulty: None,
+            tags: Vec::new(),
+            explanation: None,
+            answer: AnswerKey::Cloze {
+                blanks: vec![vec!["Москва".into(), "Moscow".into()], vec!["Москва-река".into(), "Москва".into()]],
+            },
+        });
+        assert!(validate_quiz(&quiz, &QuizLimits::default()).is_ok());
+
+        let q = &quiz.questions[3];
+        let blanks = |v: &[&str]| SubmittedAnswer::Cloze { blanks: v.iter().map(|s| s.to_string()).collect() };
+        let full = grade_answer(q, &blanks(&["  МОСКВА ", "москва-река"]));
+        assert!(full.correct);
+        assert_eq!(full.blanks, Some(vec![true, true]));
+        let half = grade_answer(q, &blanks(&["moscow", "Нева"]));
+        assert!(!half.correct);
+        assert_eq!(half.credit, 0.5);
+        assert_eq!(half.blanks, Some(vec![true, false]));
+        assert_eq!(grade_answer(q, &blanks(&["Москва"])).blanks, Some(vec![true, false]));
+
+        assert_eq!(cloze_placeholders("{{2}} a {{ 1 }} {{x}}"), vec![2, 1]);
+        quiz.questions[3].prompt = "Столица — {{1}}".into();
+        let issues = validate_quiz(&quiz, &QuizLimits::default()).unwrap_err();
+        assert!(issues.iter().any(|i| i.field == "questions[3].prompt"));
+    }
+
+    #[test]
+    fn question_diff_tracks_added_removed_changed() {
+        let before = sample_quiz().questions;
+        let mut after = before.clone();
+        after.remove(0);
+        after[0].prompt = "Capital of France".into();
+        after.push(Question {
+            id: "q4".into(),
+            q_type: QuestionType::Open,
+            prompt: "3+3".into(),
+            options: None,
+            time_limit_secs: None,
+            points: None,
+            difficulty: None,
+            tags: Vec::new(),
+            explanation: None,
+            answer: AnswerKey::Open(OpenAnswer::new("6")),
+        });
+        let diff = QuestionDiff::between(&before, &after);
+        assert_eq!(diff.added, vec!["q4".to_string()]);
+        assert_eq!(diff.removed, vec!["q1".to_string()]);
+        assert_eq!(diff.changed, vec!["q2".to_string(), "q3".to_string()]);
+        assert!(QuestionDiff::between(&before, &before).is_empty());
+    }
+
+    #[test]
+    fn time_limit_and_points_ranges() {
+        let mut quiz = sample_quiz();
+        quiz.questions[0].time_limit_secs = Some(30);
+        quiz.questions[0].points = Some(5);
+        assert!(validate_quiz(&quiz, &QuizLimits::default()).is_ok());
+        assert_eq!(quiz.questions[0].points(), 5.0);
+        assert_eq!(quiz.questions[1].points(), 1.0);
+
+        quiz.questions[0].time_limit_secs = Some(1);
+        quiz.questions[1].points = Some(0);
+        let issues = validate_quiz(&quiz, &QuizLimits::default()).unwrap_err();
+        assert!(issues.iter().any(|i| i.field == "questions[0].timeLimitSecs"));
+        assert!(issues.iter().any(|i| i.field == "questions[1].points"));
+    }
+
+    #[test]
+    fn settings_are_validated_and_omitted_when_default() {
+        let mut quiz = sample_quiz();
+        assert!(serde_json::to_value(&quiz).unwrap().get("settings").is_none());
+        quiz.settings = QuizSettings { shuffle_questions: true, retries: 2, passing_pct: Some(60), ..Default::default() };
+        assert!(validate_quiz(&quiz, &QuizLimits::default()).is_ok());
+        assert_eq!(serde_json::to_value(&quiz).unwrap()["settings"]["passingPct"], 60);
+
+        quiz.settings.retries = MAX_RETRIES + 1;
+        quiz.settings.passing_pct = Some(0);
+        quiz.settings.hints_per_student = MAX_HINTS_PER_STUDENT + 1;
+        let issues = validate_quiz(&quiz, &QuizLimits::default()).unwrap_err();
+        assert!(issues.iter().any(|i| i.field == "settings.retries"));
+        assert!(issues.iter().any(|i| i.field == "settings.hintsPerStudent"));
+        assert!(issues.iter().any(|i| i.field == "settings.passingPct"));
+    }
+
+    #[test]
+    fn subject_grade_and_language_are_validated() {
+        let mut quiz = sample_quiz();
+        quiz.subject = Some("Математика".into());
+        quiz.grade = Some(5);
+        quiz.language = Some("ru".into());
+        assert!(validate_quiz(&quiz, &QuizLimits::default()).is_ok());
+
+        quiz.subject = Some("  ".into());
+        quiz.grade = Some(12);
+        quiz.language = Some("RUS".into());
+        let issues = validate_quiz(&quiz, &QuizLimits::default()).unwrap_err();
+        for field in ["subject", "grade", "language"] {
+            assert!(issues.iter().any(|i| i.field == field), "{field}");
+        }
+    }
+
+    #[test]
+    fn explanation_length_is_limited() {
+        let mut quiz = sample_quiz();
+        quiz.questions[0].explanation = Some("2 + 2 = 4".into());
+        assert!(validate_quiz(&quiz, &QuizLimits::default()).is_ok());
+        quiz.questions[0].explanation = Some(" ".into());
+        quiz.questions[1].explanation = Some("я".repeat(MAX_EXPLANATION_CHARS + 1));
+        let issues = validate_quiz(&quiz, &QuizLimits::default()).unwrap_err();
+        assert!(issues.iter().any(|i| i.field == "questions[0].explanation"));
+        assert!(issues.iter().any(|i| i.field == "questions[1].explanation"));
+    }
+
+    #[test]
+    fn tags_are_limited_and_unique() {
+        let mut quiz = sample_quiz();
+        quiz.difficulty = Some(Difficulty::Medium);
+        quiz.tags = vec!["Алгебра".into(), "дроби".into()];
+        quiz.questions[0].tags = vec!["устный счёт".into()];
+        assert!(validate_quiz(&quiz, &QuizLimits::default()).is_ok());
+
+        quiz.tags.push(" алгебра ".into());
+        quiz.questions[1].tags = vec!["".into(), "x".repeat(MAX_TAG_CHARS + 1)];
+        quiz.questions[2].tags = (0..=MAX_TAGS).map(|n| format!("t{n}")).collect();
+        let issues = validate_quiz(&quiz, &QuizLimits::default()).unwrap_err();
+        assert!(issues.iter().any(|i| i.field == "tags[2]" && i.issue == "must be unique"));
+        assert!(issues.iter().any(|i| i.field == "questions[1].tags[0]"));
+        assert!(issues.iter().any(|i| i.field == "questions[1].tags[1]"));
+        assert!(issues.iter().any(|i| i.field == "questions[2].tags"));
+        assert_eq!(serde_json::to_value(Difficulty::Hard).unwrap(), "hard");
+    }
+
+    #[test]
+    fn structural_limits_are_enforced() {
+        let limits = QuizLimits {
+            max_questions: 2,
+            max_options: 2,
+            max_prompt_chars: 10,
+            max_option_chars: 5,
+            ..QuizLimits::default()
+        };
+        let mut quiz = sample_quiz();
+        quiz.questions[1].prompt = "x".repeat(11);
+        quiz.questions[1].options.as_mut().unwrap()[1].text = "Roma, Italia".into();
+        let issues = validate_quiz(&quiz, &limits).unwrap_err();
+        let fields: Vec<&str> = issues.iter().map(|i| i.field.as_str()).collect();
+        assert_eq!(fields, vec!["questions", "questions[1].prompt", "questions[1].options[1].text", "questions[2].options"]);
+
+        let tight = QuizLimits { max_payload_bytes: 100, ..QuizLimits::default() };
+        let issues = validate_quiz(&sample_quiz(), &tight).unwrap_err();
+        assert_eq!(issues[0].field, "quiz");
+    }
+
     #[test]
     fn student_stats_pct() {
         let s = StudentStats {
             nickname: "N".into(),
             correct: 3,
             wrong: 1,
+            score: 3.0,
+            ..Default::default()
         };
         assert_eq!(s.correct_pct(), 75.0);
     }
+
+    #[test]
+    fn ranking_uses_points_before_correct_count() {
+        let stats: HashMap<String, StudentStats> = [("a", 3, 3.0), ("b", 1, 10.0), ("c", 3, 3.0)]
+            .into_iter()
+            .map(|(n, correct, score)| {
+                (n.to_string(), StudentStats { nickname: n.into(), correct, score, ..Default::default() })
+            })
+            .collect();
+        let order: Vec<&str> = StudentStats::ranked(&stats).iter().map(|s| s.nickname.as_str()).collect();
+        assert_eq!(order, vec!["b", "a", "c"]);
+    }
+
+    #[test]
+    fn bonuses_reward_speed_and_streaks() {
+        // Instant, first in a row: half the points again. Past the window: nothing.
+        assert_eq!(answer_bonus(2.0, Some(0.0), 20, 1), 1.0);
+        assert_eq!(answer_bonus(2.0, Some(10.0), 20, 1), 0.5);
+        assert_eq!(answer_bonus(2.0, Some(25.0), 20, 1), 0.0);
+        assert_eq!(answer_bonus(2.0, None, 20, 1), 0.0);
+        // The third in a row is worth 1.2x; the multiplier stops at 1.5x.
+        assert!((answer_bonus(1.0, None, 30, 3) - 0.2).abs() < 1e-9);
+        assert!((answer_bonus(1.0, None, 30, 20) - 0.5).abs() < 1e-9);
+
+        let mut s = StudentStats::default();
+        assert_eq!((s.extend_streak(true), s.extend_streak(true), s.extend_streak(false)), (1, 2, 0));
+        assert_eq!(s.best_streak, 2);
+        s.score = 3.0;
+        s.bonus = 1.5;
+        assert_eq!(s.points(), 4.5);
+        s.penalty = 2.0;
+        assert_eq!(s.points(), 2.5);
+    }
+
+    #[test]
+    fn shuffles_follow_the_participant_seed() {
+        let mut order = question_order(10, 7);
+        assert_eq!(order, question_order(10, 7));
+        order.sort_unstable();
+        assert_eq!(order, (0..10).collect::<Vec<_>>());
+
+        let quiz = sample_quiz();
+        let shown = |seed: u64| {
+            let mut question = quiz.questions[2].clone();
+            shuffle_options(&mut question, seed);
+            question.options.unwrap().into_iter().map(|o| o.id).collect::<Vec<_>>()
+        };
+        assert_eq!(shown(7), shown(7));
+        let mut ids = shown(7);
+        ids.sort();
+        assert_eq!(ids, vec!["o1", "o2", "o3"]);
+        // Across seeds the three options come out in more than one order.
+        assert!((0..20).any(|seed| shown(seed) != shown(7)));
+    }
 }
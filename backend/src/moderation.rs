@@ -0,0 +1,144 @@
+use crate::ai_providers::{timed_call, AiFailure};
+use crate::models::{normalize_open_answer, Quiz, ValidationIssue};
+use crate::prompt_templates::SystemPrompt;
+use crate::state::AiQuizClient;
+use serde::Deserialize;
+
+// Checks AI-generated quizzes before they are saved: a banned-word list from the environment and,
+// with `MODERATION_AI=true`, a second opinion from the same provider that wrote the quiz.
+#[derive(Debug, Clone, Default)]
+pub struct Moderation {
+    // Normalized words and phrases; matched as whole words.
+    banned: Vec<String>,
+    ai_check: bool,
+}
+
+impl Moderation {
+    // `MODERATION_BANNED_WORDS` is comma-separated, `MODERATION_BANNED_WORDS_FILE` has one entry per
+    // line; both lists are combined.
+    pub fn from_env() -> anyhow::Result<Self> {
+        let mut entries: Vec<String> = std::env::var("MODERATION_BANNED_WORDS")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::to_string)
+            .collect();
+        if let Some(path) = std::env::var("MODERATION_BANNED_WORDS_FILE").ok().filter(|p| !p.trim().is_empty()) {
+            let text = std::fs::read_to_string(&path)
+                .map_err(|e| anyhow::anyhow!("cannot read MODERATION_BANNED_WORDS_FILE {}: {}", path, e))?;
+            entries.extend(text.lines().map(str::to_string));
+        }
+        let ai_check = std::env::var("MODERATION_AI")
+            .map(|v| matches!(v.trim().to_lowercase().as_str(), "1" | "true" | "yes"))
+            .unwrap_or(false);
+        Ok(Self::new(entries, ai_check))
+    }
+
+    pub fn new(entries: impl IntoIterator<Item = String>, ai_check: bool) -> Self {
+        let mut banned: Vec<String> = entries
+            .into_iter()
+            .map(|e| normalize_open_answer(&e))
+            .filter(|e| !e.is_empty())
+            .collect();
+        banned.sort();
+        banned.dedup();
+        Self { banned, ai_check }
+    }
+
+    pub fn banned_count(&self) -> usize {
+        self.banned.len()
+    }
+
+    pub fn ai_check(&self) -> bool {
+        self.ai_check
+    }
+
+    // Every title, prompt and option text that contains a banned entry, as validation issues.
+    pub fn banned_words(&self, quiz: &Quiz) -> Vec<ValidationIssue> {
+        let mut issues = Vec::new();
+        let mut check = |field: String, text: &str| {
+            let text = format!(" {} ", normalize_open_answer(text));
+            if let Some(word) = self.banned.iter().find(|w| text.contains(&format!(" {} ", w))) {
+                issues.push(ValidationIssue { field, issue: format!("contains banned word \"{}\"", word) });
+            }
+        };
+        check("title".into(), &quiz.title);
+        for (i, q) in quiz.questions.iter().enumerate() {
+            check(format!("questions[{}].prompt", i), &q.prompt);
+            for (j, o) in q.options.iter().flatten().enumerate() {
+                check(format!("questions[{}].options[{}].text", i, j), &o.text);
+            }
+        }
+        issues
+    }
+
+    // Banned words first; only a quiz that passes them is sent to the model. A provider error fails
+    // the check rather than letting the quiz through.
+    pub async fn check(
+        &self,
+        client: &dyn AiQuizClient,
+        provider: &str,
+        system: &SystemPrompt,
+        quiz: &Quiz,
+    ) -> Result<(), AiFailure> {
+        let issues = self.banned_words(quiz);
+        if !issues.is_empty() {
+            return Err(rejected(issues));
+        }
+        if !self.ai_check {
+            return Ok(());
+        }
+        let raw = timed_call(provider, "moderation", client.complete_json(system, &moderation_prompt(quiz)))
+            .await
+            .map_err(|e| AiFailure {
+                status: 502,
+                code: "UPSTREAM_ERROR",
+                message: format!("{} moderation failed: {}", provider, e),
+                details: Vec::new(),
+            })?;
+        let verdict: ModerationResponse = serde_json::from_str(&raw.text).map_err(|e| AiFailure {
+            status: 502,
+            code: "UPSTREAM_ERROR",
+            message: format!("{} moderation returned invalid json: {}", provider, e),
+            details: Vec::new(),
+        })?;
+        let issues: Vec<_> = verdict
+            .flagged
+            .into_iter()
+            .filter(|f| f.question < quiz.questions.len())
+            .map(|f| ValidationIssue { field: format!("questions[{}]", f.question), issue: f.reason })
+            .collect();
+        if issues.is_empty() {
+            Ok(())
+        } else {
+            Err(rejected(issues))
+        }
+    }
+}
+
+fn rejected(details: Vec<ValidationIssue>) -> AiFailure {
+    AiFailure { status: 422, code: "VALIDATION_ERROR", message: "quiz failed moderation".into(), details }
+}
+
+// Questions are numbered from 0 so the model's answer maps straight onto `questions[i]`.
+fn moderation_prompt(quiz: &Quiz) -> String {
+    let mut out = format!("Название: {}\n", quiz.title);
+    for (i, q) in quiz.questions.iter().enumerate() {
+        out.push_str(&format!("[{}] {}\n", i, q.prompt));
+        for o in q.options.iter().flatten() {
+            out.push_str(&format!("  - {}\n", o.text));
+        }
+    }
+    out
+}
+
+#[derive(Deserialize)]
+struct ModerationResponse {
+    #[serde(default)]
+    flagged: Vec<FlaggedQuestion>,
+}
+
+#[derive(Deserialize)]
+struct FlaggedQuestion {
+    question: usize,
+    reason: String,
+}
@@ -0,0 +1,88 @@
+use crate::email::{EmailTemplate, Mailer};
+use crate::state::Teacher;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tracing::{info, warn};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PasswordResetToken {
+    pub teacher_id: i64,
+    pub expires_at: DateTime<Utc>,
+}
+
+pub fn new_reset_token(teacher_id: i64, ttl: chrono::Duration) -> (String, PasswordResetToken) {
+    let token: String = rand::thread_rng().sample_iter(&Alphanumeric).take(32).map(char::from).collect();
+    (token, PasswordResetToken { teacher_id, expires_at: Utc::now() + ttl })
+}
+
+// How a self-service reset code reaches the teacher.
+#[async_trait]
+pub trait ResetDelivery: Send + Sync {
+    fn kind(&self) -> &'static str;
+    async fn deliver(&self, teacher: &Teacher, token: &str, expires_at: DateTime<Utc>) -> anyhow::Result<()>;
+}
+
+// Writes the code to the server log, where an administrator can pick it up and hand it over.
+pub struct LogDelivery;
+
+#[async_trait]
+impl ResetDelivery for LogDelivery {
+    fn kind(&self) -> &'static str {
+        "log"
+    }
+
+    async fn deliver(&self, teacher: &Teacher, token: &str, expires_at: DateTime<Utc>) -> anyhow::Result<()> {
+        info!("password reset code for {}: {} (valid until {})", teacher.login, token, expires_at.to_rfc3339());
+        Ok(())
+    }
+}
+
+// Self-service requests are accepted but nothing is sent; only admins can issue codes.
+pub struct DisabledDelivery;
+
+#[async_trait]
+impl ResetDelivery for DisabledDelivery {
+    fn kind(&self) -> &'static str {
+        "disabled"
+    }
+
+    async fn deliver(&self, teacher: &Teacher, _token: &str, _expires_at: DateTime<Utc>) -> anyhow::Result<()> {
+        warn!("password reset requested for {} but PASSWORD_RESET_DELIVERY is disabled", teacher.login);
+        Ok(())
+    }
+}
+
+// Mails the code to the address the teacher set; teachers without one cannot reset by themselves.
+pub struct EmailDelivery {
+    pub mailer: Arc<Mailer>,
+}
+
+#[async_trait]
+impl ResetDelivery for EmailDelivery {
+    fn kind(&self) -> &'static str {
+        "email"
+    }
+
+    async fn deliver(&self, teacher: &Teacher, token: &str, expires_at: DateTime<Utc>) -> anyhow::Result<()> {
+        let Some(email) = teacher.email.as_deref() else {
+            anyhow::bail!("teacher {} has no email address", teacher.login);
+        };
+        let template = EmailTemplate::PasswordReset { login: &teacher.login, code: token, expires_at };
+        if !self.mailer.send_template(email, teacher.locale.as_deref(), template) {
+            anyhow::bail!("email queue is full");
+        }
+        Ok(())
+    }
+}
+
+pub fn delivery_from_env(mailer: Arc<Mailer>) -> Arc<dyn ResetDelivery> {
+    match std::env::var("PASSWORD_RESET_DELIVERY").unwrap_or_default().trim() {
+        "disabled" | "none" => Arc::new(DisabledDelivery),
+        "email" => Arc::new(EmailDelivery { mailer }),
+        _ => Arc::new(LogDelivery),
+    }
+}
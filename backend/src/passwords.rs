@@ -0,0 +1,69 @@
+use argon2::password_hash::{self, SaltString};
+use argon2::{Algorithm, Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier, Version};
+
+// Argon2id parameters for new hashes. The PHC string of every hash carries the parameters it was
+// made with, so older hashes keep verifying after the configuration changes.
+#[derive(Clone)]
+pub struct PasswordHashing {
+    params: Params,
+}
+
+fn env_u32(name: &str, default: u32) -> u32 {
+    std::env::var(name)
+        .ok()
+        .and_then(|v| v.parse::<u32>().ok())
+        .filter(|v| *v > 0)
+        .unwrap_or(default)
+}
+
+impl PasswordHashing {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let params = Params::new(
+            env_u32("ARGON2_MEMORY_KIB", Params::DEFAULT_M_COST),
+            env_u32("ARGON2_ITERATIONS", Params::DEFAULT_T_COST),
+            env_u32("ARGON2_PARALLELISM", Params::DEFAULT_P_COST),
+            None,
+        )
+        .map_err(|err| anyhow::anyhow!("invalid ARGON2_* settings: {}", err))?;
+        Ok(Self::new(params))
+    }
+
+    pub fn new(params: Params) -> Self {
+        Self { params }
+    }
+
+    pub fn params(&self) -> &Params {
+        &self.params
+    }
+
+    fn hasher(&self) -> Argon2<'static> {
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, self.params.clone())
+    }
+
+    pub fn hash(&self, password: &str) -> password_hash::Result<String> {
+        let salt = SaltString::generate(&mut password_hash::rand_core::OsRng);
+        Ok(self.hasher().hash_password(password.as_bytes(), &salt)?.to_string())
+    }
+
+    pub fn verify(&self, hash: &str, password: &str) -> password_hash::Result<bool> {
+        let parsed = PasswordHash::new(hash)?;
+        Ok(Argon2::default().verify_password(password.as_bytes(), &parsed).is_ok())
+    }
+
+    // True when the hash is weaker than the current settings in any dimension, or uses a
+    // different Argon2 variant/version.
+    pub fn needs_rehash(&self, hash: &str) -> bool {
+        let Ok(parsed) = PasswordHash::new(hash) else { return false };
+        if parsed.algorithm != Algorithm::Argon2id.ident() || parsed.version != Some(u32::from(Version::V0x13)) {
+            return true;
+        }
+        match Params::try_from(&parsed) {
+            Ok(stored) => {
+                stored.m_cost() < self.params.m_cost()
+                    || stored.t_cost() < self.params.t_cost()
+                    || stored.p_cost() < self.params.p_cost()
+            }
+            Err(_) => true,
+        }
+    }
+}
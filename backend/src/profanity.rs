@@ -0,0 +1,356 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+
+// Profanity filter for what students type where the class can see it: nicknames in the room and
+// open-text answers shown on the projector. Entries are words with `*` at either end: `word` matches a
+// whole word, `word*` a word starting with it, `*word*` any word containing it. Words are compared after
+// folding leet-speak and look-alike letters of the other alphabet, so "xyй", "fuсk" (Cyrillic "с") and
+// "sh1t" are caught, and a letter may be repeated ("fuuuck").
+const RU_WORDS: &[&str] = &[
+    "*хуй*", "*хуе*", "*хуя*", "*хую*", "хуи", "*пизд*", "*пезд*", "*ебал*", "*ебан*", "*ебат*", "*ебаш*",
+    "*ебуч*", "*ебну*", "*ебло*", "*еблан*", "*заеб*", "*наеб*", "*выеб*", "*отъеб*", "*долбоеб*", "уеб*",
+    "бля", "*бляд*", "*блят*", "сука", "суки", "сучар*", "мудак*", "*мудил*", "*пидор*", "*пидар*", "педик",
+    "*гандон*", "*гондон*", "*шлюх*", "*залуп*", "*дроч*", "говн*", "жоп*", "хер", "нахер", "похер", "чмо",
+    "ублюд*", "трахат*", "трахн*", "*мандавош*", "*pizd*", "huy*", "*xuy*", "*blyad*", "*blyat*", "*pidor*",
+    "*pidar*", "suka",
+];
+
+const EN_WORDS: &[&str] = &[
+    "*fuck*", "fuk", "fck", "*shit*", "*bitch*", "*cunt*", "dick", "dickhead*", "cock", "cocksuck*", "pussy",
+    "*whore*", "*slut*", "*nigger*", "*nigga*", "fag", "faggot*", "*asshole*", "ass", "arse", "*bastard*",
+    "wank*", "twat", "retard*", "bollock*", "dildo*", "porn*",
+];
+
+// Ordinary words that contain an entry above; they are cut out before matching.
+const EXCEPTIONS: &[&str] = &[
+    "колеб", "греб", "хлеб", "скипидар", "педикюр", "дрочен", "shiitake", "shitake", "scunthorpe", "niggard",
+    "retardant",
+];
+
+// A teacher's corrections for their own sessions: words the filter must let through, for example a
+// surname it mistakes for a swear word, and extra entries to block.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct WordOverrides {
+    #[serde(default)]
+    pub allow: Vec<String>,
+    #[serde(default)]
+    pub block: Vec<String>,
+}
+
+impl WordOverrides {
+    pub fn is_empty(&self) -> bool {
+        self.allow.is_empty() && self.block.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Script {
+    Cyrillic,
+    Latin,
+}
+
+// A word folded into each alphabet: Latin look-alikes and digits become Cyrillic letters for the Russian
+// entries and the other way round for the English ones.
+struct Folded {
+    cyrillic: String,
+    latin: String,
+}
+
+impl Folded {
+    fn new(word: &str) -> Self {
+        Self { cyrillic: fold(word, Script::Cyrillic), latin: fold(word, Script::Latin) }
+    }
+
+    fn get(&self, script: Script) -> &str {
+        match script {
+            Script::Cyrillic => &self.cyrillic,
+            Script::Latin => &self.latin,
+        }
+    }
+}
+
+fn fold(word: &str, script: Script) -> String {
+    word.chars()
+        .flat_map(char::to_lowercase)
+        .filter_map(|c| {
+            let c = if c == 'ё' { 'е' } else { c };
+            let folded = match script {
+                Script::Cyrillic => match c {
+                    'a' | '@' => 'а',
+                    'c' => 'с',
+                    'e' => 'е',
+                    'h' => 'н',
+                    'k' => 'к',
+                    'm' => 'м',
+                    'n' => 'п',
+                    'o' | '0' => 'о',
+                    'p' => 'р',
+                    'r' => 'г',
+                    't' => 'т',
+                    'u' => 'и',
+                    'x' => 'х',
+                    'y' => 'у',
+                    'z' | '3' => 'з',
+                    '4' => 'ч',
+                    '6' => 'б',
+                    '9' => 'я',
+                    c => c,
+                },
+                Script::Latin => match c {
+                    'а' | '@' | '4' => 'a',
+                    'в' | '8' => 'b',
+                    'с' => 'c',
+                    'е' | '3' => 'e',
+                    'н' => 'h',
+                    'і' | '1' => 'i',
+                    'к' => 'k',
+                    'м' => 'm',
+                    'о' | '0' => 'o',
+                    'р' => 'p',
+                    'т' | '7' => 't',
+                    'х' => 'x',
+                    'у' => 'y',
+                    '$' | '5' => 's',
+                    c => c,
+                },
+            };
+            folded.is_alphabetic().then_some(folded)
+        })
+        .collect()
+}
+
+// Lowercase letters only, for comparing a word with a teacher's allowed words.
+fn plain(word: &str) -> String {
+    word.chars()
+        .flat_map(char::to_lowercase)
+        .filter(|c| c.is_alphabetic())
+        .map(|c| if c == 'ё' { 'е' } else { c })
+        .collect()
+}
+
+// Russian entries are matched against the Cyrillic folding of a word, everything else against the Latin one.
+fn script_of(entry: &str) -> Script {
+    if entry.chars().any(|c| matches!(c, 'а'..='я')) { Script::Cyrillic } else { Script::Latin }
+}
+
+// Entries compiled to one regex per alphabet; every letter may repeat.
+struct Patterns {
+    cyrillic: Option<Regex>,
+    latin: Option<Regex>,
+}
+
+impl Patterns {
+    fn compile<'a>(entries: impl IntoIterator<Item = &'a str>) -> Self {
+        let (mut cyrillic, mut latin) = (Vec::new(), Vec::new());
+        for entry in entries {
+            let entry = entry.trim();
+            let (prefix, rest) = entry.strip_prefix('*').map_or((false, entry), |rest| (true, rest));
+            let (suffix, core) = rest.strip_suffix('*').map_or((false, rest), |core| (true, core));
+            let core = plain(core);
+            if core.is_empty() {
+                continue;
+            }
+            let letters: String = core.chars().map(|c| format!("{}+", regex::escape(&c.to_string()))).collect();
+            let pattern = format!("{}{}{}", if prefix { "" } else { "^" }, letters, if suffix { "" } else { "$" });
+            match script_of(&core) {
+                Script::Cyrillic => cyrillic.push(pattern),
+                Script::Latin => latin.push(pattern),
+            }
+        }
+        let join = |patterns: Vec<String>| {
+            (!patterns.is_empty()).then(|| Regex::new(&format!("(?:{})", patterns.join("|"))).expect("escaped entries"))
+        };
+        Self { cyrillic: join(cyrillic), latin: join(latin) }
+    }
+
+    fn matches(&self, word: &Folded) -> bool {
+        let check = |regex: &Option<Regex>, script| regex.as_ref().is_some_and(|r| r.is_match(word.get(script)));
+        check(&self.cyrillic, Script::Cyrillic) || check(&self.latin, Script::Latin)
+    }
+}
+
+pub struct ProfanityFilter {
+    // Built-in lists for the configured languages plus the server's own entries; None when turned off.
+    builtin: Option<Patterns>,
+    exceptions: Vec<(Script, String)>,
+}
+
+impl ProfanityFilter {
+    // `PROFANITY_FILTER=false` turns the built-in lists off; teachers' own blocked words still apply.
+    // `PROFANITY_LANGUAGES` picks the lists (ru, en), `PROFANITY_WORDS` (comma-separated) and
+    // `PROFANITY_WORDS_FILE` (one entry per line) add entries in the same syntax.
+    pub fn from_env() -> anyhow::Result<Self> {
+        let enabled = std::env::var("PROFANITY_FILTER")
+            .map(|v| !matches!(v.trim().to_lowercase().as_str(), "0" | "false" | "no" | "off"))
+            .unwrap_or(true);
+        if !enabled {
+            return Ok(Self::disabled());
+        }
+        let languages = std::env::var("PROFANITY_LANGUAGES").unwrap_or_else(|_| "ru,en".into());
+        let languages: Vec<String> =
+            languages.split(',').map(|l| l.trim().to_lowercase()).filter(|l| !l.is_empty()).collect();
+        if let Some(unknown) = languages.iter().find(|l| !matches!(l.as_str(), "ru" | "en")) {
+            anyhow::bail!("unsupported PROFANITY_LANGUAGES entry: {}", unknown);
+        }
+        let mut extra: Vec<String> =
+            std::env::var("PROFANITY_WORDS").unwrap_or_default().split(',').map(str::to_string).collect();
+        if let Some(path) = std::env::var("PROFANITY_WORDS_FILE").ok().filter(|p| !p.trim().is_empty()) {
+            let text = std::fs::read_to_string(&path)
+                .map_err(|e| anyhow::anyhow!("cannot read PROFANITY_WORDS_FILE {}: {}", path, e))?;
+            extra.extend(text.lines().map(str::to_string));
+        }
+        let languages: Vec<&str> = languages.iter().map(String::as_str).collect();
+        Ok(Self::new(&languages, extra))
+    }
+
+    pub fn new(languages: &[&str], extra: impl IntoIterator<Item = String>) -> Self {
+        let mut entries: Vec<String> = Vec::new();
+        for language in languages {
+            let words = if *language == "ru" { RU_WORDS } else { EN_WORDS };
+            entries.extend(words.iter().map(|w| w.to_string()));
+        }
+        entries.extend(extra);
+        let exceptions = EXCEPTIONS.iter().map(|e| (script_of(e), e.to_string())).collect();
+        Self { builtin: Some(Patterns::compile(entries.iter().map(String::as_str))), exceptions }
+    }
+
+    pub fn disabled() -> Self {
+        Self { builtin: None, exceptions: Vec::new() }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.builtin.is_some()
+    }
+
+    pub fn is_offensive(&self, text: &str, overrides: &WordOverrides) -> bool {
+        !self.offensive_spans(text, overrides).is_empty()
+    }
+
+    // `text` with every offending word replaced by as many asterisks.
+    pub fn mask(&self, text: &str, overrides: &WordOverrides) -> String {
+        let spans = self.offensive_spans(text, overrides);
+        if spans.is_empty() {
+            return text.to_string();
+        }
+        let mut out = String::with_capacity(text.len());
+        let mut last = 0;
+        for span in spans {
+            out.push_str(&text[last..span.start]);
+            out.extend(text[span.clone()].chars().map(|_| '*'));
+            last = span.end;
+        }
+        out.push_str(&text[last..]);
+        out
+    }
+
+    // Byte ranges of the offending words, in order. Letters spelled out one by one ("f u c k", "х.у.й")
+    // are also read as one word.
+    fn offensive_spans(&self, text: &str, overrides: &WordOverrides) -> Vec<Range<usize>> {
+        let blocked =
+            (!overrides.block.is_empty()).then(|| Patterns::compile(overrides.block.iter().map(String::as_str)));
+        if self.builtin.is_none() && blocked.is_none() {
+            return Vec::new();
+        }
+        let allowed: Vec<String> = overrides.allow.iter().map(|w| plain(w)).filter(|w| !w.is_empty()).collect();
+        let offensive = |word: &str| {
+            if allowed.contains(&plain(word)) {
+                return false;
+            }
+            let folded = Folded::new(word);
+            if blocked.as_ref().is_some_and(|b| b.matches(&folded)) {
+                return true;
+            }
+            self.builtin.as_ref().is_some_and(|builtin| builtin.matches(&self.without_exceptions(folded)))
+        };
+
+        let words = words(text);
+        let mut spans: Vec<Range<usize>> = Vec::new();
+        let mut i = 0;
+        while i < words.len() {
+            let single = |w: &Range<usize>| text[w.clone()].chars().count() == 1;
+            let run = words[i..].iter().take_while(|w| single(w)).count();
+            if run >= 2 {
+                let joined: String = words[i..i + run].iter().map(|w| &text[w.clone()]).collect();
+                if offensive(&joined) {
+                    spans.extend(words[i..i + run].iter().cloned());
+                }
+                i += run;
+                continue;
+            }
+            if offensive(&text[words[i].clone()]) {
+                spans.push(words[i].clone());
+            }
+            i += 1;
+        }
+        spans
+    }
+
+    // Cuts the innocent words that contain an entry out of the folded word, so the rest cannot match.
+    fn without_exceptions(&self, mut folded: Folded) -> Folded {
+        for (script, exception) in &self.exceptions {
+            let target = match script {
+                Script::Cyrillic => &mut folded.cyrillic,
+                Script::Latin => &mut folded.latin,
+            };
+            if target.contains(exception.as_str()) {
+                *target = target.replace(exception.as_str(), "_");
+            }
+        }
+        folded
+    }
+}
+
+// Byte ranges of the words of `text`: letters and digits, plus the `@` and `$` of leet-speak.
+fn words(text: &str) -> Vec<Range<usize>> {
+    let mut words = Vec::new();
+    let mut start = None;
+    for (i, c) in text.char_indices() {
+        let in_word = c.is_alphanumeric() || c == '@' || c == '$';
+        match (in_word, start) {
+            (true, None) => start = Some(i),
+            (false, Some(s)) => {
+                words.push(s..i);
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(s) = start {
+        words.push(s..text.len());
+    }
+    words
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn leet_and_look_alike_letters_are_caught() {
+        let filter = ProfanityFilter::new(&["ru", "en"], Vec::new());
+        let none = WordOverrides::default();
+        for nickname in ["xyй", "Fuсk", "sh1t", "fuuuck", "f u c k", "Х.у.й", "6лядь", "пиzдец", "$lut", "BITCH!"] {
+            assert!(filter.is_offensive(nickname, &none), "{} should be caught", nickname);
+        }
+        for nickname in ["Лиза", "колебание", "Хлебалов", "скипидар", "class", "Scunthorpe", "cocktail", "as"] {
+            assert!(!filter.is_offensive(nickname, &none), "{} should pass", nickname);
+        }
+        assert_eq!(filter.mask("ну ты и сука, Петя", &none), "ну ты и ****, Петя");
+        assert_eq!(filter.mask("f u c k off", &none), "* * * * off");
+    }
+
+    #[test]
+    fn teacher_overrides_allow_and_block_words() {
+        let filter = ProfanityFilter::new(&["ru"], vec!["*кринж*".to_string()]);
+        let overrides = WordOverrides { allow: vec!["Хер".into()], block: vec!["лох*".into()] };
+        assert!(!filter.is_offensive("Хер", &overrides));
+        assert!(filter.is_offensive("Хер", &WordOverrides::default()));
+        assert!(filter.is_offensive("лоховатый", &overrides));
+        assert!(filter.is_offensive("суперкринжовый", &overrides));
+
+        let disabled = ProfanityFilter::disabled();
+        assert!(!disabled.is_offensive("сука", &overrides));
+        assert!(disabled.is_offensive("лох", &overrides));
+    }
+}
@@ -0,0 +1,146 @@
+use crate::models::ValidationIssue;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+pub const MAX_TEMPLATE_CHARS: usize = 20_000;
+// Older versions beyond this are dropped when a new one is saved.
+pub const PROMPT_VERSION_LIMIT: usize = 50;
+
+// What a system prompt is used for. Each use case has a built-in text (the files in docs/) that
+// applies until an admin saves a version of their own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PromptUseCase {
+    Generation,
+    Distractors,
+    Hints,
+    Moderation,
+}
+
+impl PromptUseCase {
+    pub const ALL: [Self; 4] = [Self::Generation, Self::Distractors, Self::Hints, Self::Moderation];
+
+    pub fn parse(value: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|u| u.name() == value.trim().to_ascii_lowercase())
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Generation => "generation",
+            Self::Distractors => "distractors",
+            Self::Hints => "hints",
+            Self::Moderation => "moderation",
+        }
+    }
+
+    pub fn default_text(self) -> &'static str {
+        match self {
+            Self::Generation => include_str!("../../docs/gigachat_system_prompt.txt"),
+            Self::Distractors => include_str!("../../docs/ai_distractors_system_prompt.txt"),
+            Self::Hints => include_str!("../../docs/ai_hint_system_prompt.txt"),
+            Self::Moderation => include_str!("../../docs/ai_moderation_system_prompt.txt"),
+        }
+    }
+
+    // Placeholders a template of this use case may contain, written as `{{name}}`.
+    pub fn variables(self) -> &'static [&'static str] {
+        match self {
+            Self::Generation => &["topic", "grade", "count"],
+            Self::Distractors => &["count"],
+            Self::Hints | Self::Moderation => &[],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PromptTemplateVersion {
+    pub version: u32,
+    pub text: String,
+    pub created_at: DateTime<Utc>,
+    pub created_by: i64,
+}
+
+// Saved versions per use case, oldest first; the last one is active.
+pub type PromptTemplates = HashMap<PromptUseCase, Vec<PromptTemplateVersion>>;
+
+// The system prompt handed to a provider, tagged with its use case so clients that do not talk to
+// a model (the mock) can tell requests apart.
+#[derive(Debug, Clone)]
+pub struct SystemPrompt {
+    pub use_case: PromptUseCase,
+    pub text: String,
+}
+
+impl SystemPrompt {
+    pub fn builtin(use_case: PromptUseCase) -> Self {
+        Self { use_case, text: use_case.default_text().to_string() }
+    }
+
+    // Substitutes `{{name}}` placeholders; ones without a value are left as they are.
+    pub fn render(&self, vars: &[(&str, &str)]) -> Self {
+        let mut text = self.text.clone();
+        for (name, value) in vars {
+            text = text.replace(&format!("{{{{{}}}}}", name), value);
+        }
+        Self { use_case: self.use_case, text: text.trim().to_string() }
+    }
+}
+
+// Active text of every use case, taken once per request so a long generation is not affected by an
+// edit made while it runs.
+#[derive(Debug, Clone)]
+pub struct PromptSet(HashMap<PromptUseCase, String>);
+
+impl PromptSet {
+    pub fn active(templates: &PromptTemplates) -> Self {
+        Self(
+            templates
+                .iter()
+                .filter_map(|(use_case, versions)| Some((*use_case, versions.last()?.text.clone())))
+                .collect(),
+        )
+    }
+
+    pub fn get(&self, use_case: PromptUseCase) -> SystemPrompt {
+        match self.0.get(&use_case) {
+            Some(text) => SystemPrompt { use_case, text: text.clone() },
+            None => SystemPrompt::builtin(use_case),
+        }
+    }
+}
+
+fn placeholders(text: &str) -> Vec<&str> {
+    let mut out = Vec::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("{{") {
+        let Some(len) = rest[start + 2..].find("}}") else { break };
+        out.push(&rest[start + 2..start + 2 + len]);
+        rest = &rest[start + 2 + len + 2..];
+    }
+    out
+}
+
+pub fn validate_template(use_case: PromptUseCase, text: &str) -> Result<(), Vec<ValidationIssue>> {
+    let mut issues = Vec::new();
+    if text.trim().is_empty() || text.chars().count() > MAX_TEMPLATE_CHARS {
+        issues.push(ValidationIssue {
+            field: "text".into(),
+            issue: format!("must be 1..{} characters", MAX_TEMPLATE_CHARS),
+        });
+    }
+    for name in placeholders(text) {
+        if !use_case.variables().contains(&name) {
+            issues.push(ValidationIssue {
+                field: "text".into(),
+                issue: format!("unknown variable {{{{{}}}}}", name),
+            });
+        }
+    }
+    if issues.is_empty() {
+        Ok(())
+    } else {
+        Err(issues)
+    }
+}
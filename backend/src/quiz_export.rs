@@ -0,0 +1,313 @@
+use crate::models::{AnswerKey, NumericAnswer, Question, QuizOption};
+use crate::state::QuizRecord;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Gift,
+    MoodleXml,
+    Json,
+}
+
+impl ExportFormat {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "gift" => Some(Self::Gift),
+            "moodlexml" | "xml" => Some(Self::MoodleXml),
+            "json" => Some(Self::Json),
+            _ => None,
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Gift => "text/plain; charset=utf-8",
+            Self::MoodleXml => "application/xml; charset=utf-8",
+            Self::Json => "application/json",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Gift => "gift.txt",
+            Self::MoodleXml => "xml",
+            Self::Json => "json",
+        }
+    }
+}
+
+pub fn render(quiz: &QuizRecord, format: ExportFormat) -> String {
+    match format {
+        ExportFormat::Gift => gift(quiz),
+        ExportFormat::MoodleXml => moodle_xml(quiz),
+        ExportFormat::Json => serde_json::to_string_pretty(&quiz.to_quiz()).unwrap_or_default(),
+    }
+}
+
+fn options(question: &Question) -> &[QuizOption] {
+    question.options.as_deref().unwrap_or_default()
+}
+
+fn option_text<'a>(question: &'a Question, id: &str) -> Option<&'a str> {
+    options(question).iter().find(|o| o.id == id).map(|o| o.text.as_str())
+}
+
+// Moodle only accepts grade fractions from a fixed list; five decimals match its entries
+// (33.33333, 14.28571 and so on).
+fn fraction(value: f64) -> String {
+    let s = format!("{:.5}", value);
+    s.trim_end_matches('0').trim_end_matches('.').to_string()
+}
+
+// The widest of the absolute and relative tolerance, as Moodle has only an absolute one.
+fn tolerance(key: &NumericAnswer) -> f64 {
+    let relative = key.relative_tolerance.map(|r| r * key.value.abs()).unwrap_or(0.0);
+    key.tolerance.unwrap_or(0.0).max(relative)
+}
+
+fn multi_fractions(question: &Question, correct: &[String]) -> Vec<(String, f64)> {
+    let wrong = options(question).len().saturating_sub(correct.len()).max(1);
+    options(question)
+        .iter()
+        .map(|o| {
+            let weight = if correct.contains(&o.id) { 100.0 / correct.len() as f64 } else { -100.0 / wrong as f64 };
+            (o.text.clone(), weight)
+        })
+        .collect()
+}
+
+fn cloze_parts(prompt: &str) -> Vec<(String, Option<usize>)> {
+    let mut parts = Vec::new();
+    let mut rest = prompt;
+    while let Some(start) = rest.find("{{") {
+        let Some(len) = rest[start + 2..].find("}}") else { break };
+        match rest[start + 2..start + 2 + len].trim().parse::<usize>() {
+            Ok(n) => {
+                parts.push((rest[..start].to_string(), Some(n)));
+                rest = &rest[start + 4 + len..];
+            }
+            Err(_) => {
+                parts.push((rest[..start + 2].to_string(), None));
+                rest = &rest[start + 2..];
+            }
+        }
+    }
+    parts.push((rest.to_string(), None));
+    parts
+}
+
+// GIFT: https://docs.moodle.org/en/GIFT_format
+
+fn gift_escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '~' | '=' | '#' | '{' | '}' | ':' | '\\' => {
+                out.push('\\');
+                out.push(c);
+            }
+            '\n' => out.push_str("\\n"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+fn gift_question(question: &Question) -> Result<String, &'static str> {
+    let head = format!("::{}::", gift_escape(&question.id));
+    let feedback = question
+        .explanation
+        .as_deref()
+        .map(|e| format!("\n\t####{}", gift_escape(e)))
+        .unwrap_or_default();
+    let block = |items: Vec<String>| format!("{{\n\t{}{}\n}}", items.join("\n\t"), feedback);
+    let prompt = gift_escape(&question.prompt);
+    Ok(match &question.answer {
+        AnswerKey::Open(key) => {
+            let items = std::iter::once(&key.text).chain(&key.accepted).map(|a| format!("={}", gift_escape(a))).collect();
+            format!("{head}{prompt}{}", block(items))
+        }
+        AnswerKey::Single { option_id } => {
+            let items = options(question)
+                .iter()
+                .map(|o| format!("{}{}", if &o.id == option_id { '=' } else { '~' }, gift_escape(&o.text)))
+                .collect();
+            format!("{head}{prompt}{}", block(items))
+        }
+        AnswerKey::Multi { option_ids } => {
+            let items = multi_fractions(question, option_ids)
+                .into_iter()
+                .map(|(text, weight)| format!("~%{}%{}", fraction(weight), gift_escape(&text)))
+                .collect();
+            format!("{head}{prompt}{}", block(items))
+        }
+        // GIFT has no ordering type; a matching question pairs each item with its position.
+        AnswerKey::Ordering { order } => {
+            let items = order
+                .iter()
+                .enumerate()
+                .filter_map(|(i, id)| option_text(question, id).map(|t| format!("={} -> {}", gift_escape(t), i + 1)))
+                .collect();
+            format!("{head}{prompt}{}", block(items))
+        }
+        AnswerKey::Numeric(key) => {
+            format!("{head}{prompt}{{#{}:{}{}}}", key.value, tolerance(key), feedback)
+        }
+        // Only the missing word form with a single blank exists in GIFT.
+        AnswerKey::Cloze { blanks } => {
+            let parts = cloze_parts(&question.prompt);
+            let [variants] = blanks.as_slice() else {
+                return Err("cloze questions with several blanks cannot be expressed in GIFT");
+            };
+            let mut out = head;
+            for (text, blank) in parts {
+                out.push_str(&gift_escape(&text));
+                if blank.is_some() {
+                    out.push_str(&block(variants.iter().map(|v| format!("={}", gift_escape(v))).collect()));
+                }
+            }
+            out
+        }
+    })
+}
+
+fn gift(quiz: &QuizRecord) -> String {
+    let mut out = format!("// {}\n", quiz.title.replace('\n', " "));
+    if let Some(description) = &quiz.description {
+        out.push_str(&format!("// {}\n", description.replace('\n', " ")));
+    }
+    out.push_str(&format!("\n$CATEGORY: $course$/{}\n\n", quiz.title.replace(['/', '\n'], " ")));
+    for question in &quiz.questions {
+        match gift_question(question) {
+            Ok(text) => out.push_str(&text),
+            Err(reason) => out.push_str(&format!("// {}: skipped, {}", question.id, reason)),
+        }
+        out.push_str("\n\n");
+    }
+    out
+}
+
+// Moodle XML: https://docs.moodle.org/en/Moodle_XML_format
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+// Question texts are stored as plain text but Moodle renders them as HTML.
+fn html_text(text: &str) -> String {
+    xml_escape(&xml_escape(text).replace('\n', "<br>"))
+}
+
+fn xml_answer(fraction_pct: f64, text: &str, html: bool, extra: &str) -> String {
+    let (format, text) = if html { ("html", html_text(text)) } else { ("moodle_auto_format", xml_escape(text)) };
+    format!(
+        "    <answer fraction=\"{}\" format=\"{format}\">\n      <text>{text}</text>\n{extra}    </answer>\n",
+        fraction(fraction_pct)
+    )
+}
+
+// Moodle's multianswer syntax escapes these inside a subquestion.
+fn cloze_escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '~' | '}' | '#' | '\\' | '"') {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+fn moodle_question(question: &Question, shuffle: bool) -> String {
+    let (q_type, prompt, body) = match &question.answer {
+        AnswerKey::Open(key) => {
+            let mut body = String::from("    <usecase>0</usecase>\n");
+            for answer in std::iter::once(&key.text).chain(&key.accepted) {
+                body.push_str(&xml_answer(100.0, answer, false, ""));
+            }
+            ("shortanswer", html_text(&question.prompt), body)
+        }
+        AnswerKey::Single { option_id } => {
+            let mut body = format!("    <single>true</single>\n    <shuffleanswers>{}</shuffleanswers>\n", shuffle as u8);
+            body.push_str("    <answernumbering>abc</answernumbering>\n");
+            for option in options(question) {
+                body.push_str(&xml_answer(if &option.id == option_id { 100.0 } else { 0.0 }, &option.text, true, ""));
+            }
+            ("multichoice", html_text(&question.prompt), body)
+        }
+        AnswerKey::Multi { option_ids } => {
+            let mut body = format!("    <single>false</single>\n    <shuffleanswers>{}</shuffleanswers>\n", shuffle as u8);
+            body.push_str("    <answernumbering>abc</answernumbering>\n");
+            for (text, weight) in multi_fractions(question, option_ids) {
+                body.push_str(&xml_answer(weight, &text, true, ""));
+            }
+            ("multichoice", html_text(&question.prompt), body)
+        }
+        // qtype_ordering lists the items in the correct order and shuffles them itself.
+        AnswerKey::Ordering { order } => {
+            let mut body = String::from("    <layouttype>VERTICAL</layouttype>\n    <selecttype>ALL</selecttype>\n");
+            body.push_str("    <selectcount>0</selectcount>\n    <gradingtype>ABSOLUTE_POSITION</gradingtype>\n");
+            for (i, id) in order.iter().enumerate() {
+                if let Some(text) = option_text(question, id) {
+                    body.push_str(&xml_answer((i + 1) as f64, text, true, ""));
+                }
+            }
+            ("ordering", html_text(&question.prompt), body)
+        }
+        AnswerKey::Numeric(key) => {
+            let extra = format!("      <tolerance>{}</tolerance>\n", tolerance(key));
+            let mut body = xml_answer(100.0, &key.value.to_string(), false, &extra);
+            if let Some(unit) = &key.unit {
+                // Moodle multipliers convert from the main unit: value_in_unit = value * multiplier.
+                body.push_str("    <units>\n");
+                let units = std::iter::once((unit, 1.0)).chain(key.unit_factors.iter().map(|(u, f)| (u, 1.0 / f)));
+                for (name, multiplier) in units {
+                    body.push_str(&format!(
+                        "      <unit>\n        <multiplier>{}</multiplier>\n        <unit_name>{}</unit_name>\n      </unit>\n",
+                        multiplier,
+                        xml_escape(name)
+                    ));
+                }
+                body.push_str("    </units>\n    <unitgradingtype>0</unitgradingtype>\n    <showunits>3</showunits>\n");
+                body.push_str("    <unitsleft>0</unitsleft>\n");
+            }
+            ("numerical", html_text(&question.prompt), body)
+        }
+        AnswerKey::Cloze { blanks } => {
+            let mut text = String::new();
+            for (part, blank) in cloze_parts(&question.prompt) {
+                text.push_str(&xml_escape(&part).replace('\n', "<br>"));
+                if let Some(variants) = blank.and_then(|n| blanks.get(n.checked_sub(1)?)) {
+                    let variants: Vec<String> = variants.iter().map(|v| format!("={}", cloze_escape(v))).collect();
+                    text.push_str(&format!("{{1:SHORTANSWER:{}}}", variants.join("~")));
+                }
+            }
+            ("cloze", xml_escape(&text), String::new())
+        }
+    };
+    let feedback = question.explanation.as_deref().map(html_text).unwrap_or_default();
+    format!(
+        "  <question type=\"{q_type}\">\n    <name>\n      <text>{}</text>\n    </name>\n    \
+         <questiontext format=\"html\">\n      <text>{prompt}</text>\n    </questiontext>\n    \
+         <generalfeedback format=\"html\">\n      <text>{feedback}</text>\n    </generalfeedback>\n    \
+         <defaultgrade>{}</defaultgrade>\n{body}  </question>\n",
+        xml_escape(&question.id),
+        question.points(),
+    )
+}
+
+fn moodle_xml(quiz: &QuizRecord) -> String {
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<quiz>\n");
+    out.push_str(&format!(
+        "  <question type=\"category\">\n    <category>\n      <text>$course$/{}</text>\n    </category>\n  </question>\n",
+        xml_escape(&quiz.title.replace('/', " "))
+    ));
+    for question in &quiz.questions {
+        out.push_str(&moodle_question(question, quiz.settings.shuffle_options));
+    }
+    out.push_str("</quiz>\n");
+    out
+}
@@ -0,0 +1,261 @@
+use crate::models::{
+    parse_number_with_unit, validate_quiz, AnswerKey, NumericAnswer, OpenAnswer, Question, QuestionType,
+    Quiz, QuizLimits, QuizOption, QuizSettings, ValidationIssue,
+};
+use calamine::{Reader, Xlsx};
+use std::collections::HashMap;
+use std::io::Cursor;
+
+// One question per row after a header row. Columns are matched by header name, in any order:
+// type, prompt, options, answer (required) and id, points, time_limit, explanation, tags.
+// List cells use `|` as separator; cloze blanks take `;` between accepted spellings.
+const REQUIRED_COLUMNS: &[&str] = &["type", "prompt", "answer"];
+const LIST_SEPARATOR: char = '|';
+const VARIANT_SEPARATOR: char = ';';
+pub const MAX_IMPORT_ROWS: usize = 500;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SheetFormat {
+    Csv,
+    Xlsx,
+}
+
+impl SheetFormat {
+    // The file name decides; the content type is only used when there is no usable extension.
+    pub fn detect(file_name: Option<&str>, content_type: Option<&str>) -> Option<Self> {
+        let ext = file_name.and_then(|n| n.rsplit_once('.')).map(|(_, e)| e.to_ascii_lowercase());
+        match (ext.as_deref(), content_type) {
+            (Some("csv"), _) => Some(Self::Csv),
+            (Some("xlsx"), _) => Some(Self::Xlsx),
+            (_, Some("text/csv")) => Some(Self::Csv),
+            (_, Some("application/vnd.openxmlformats-officedocument.spreadsheetml.sheet")) => Some(Self::Xlsx),
+            _ => None,
+        }
+    }
+}
+
+fn issue(field: impl Into<String>, issue: impl Into<String>) -> ValidationIssue {
+    ValidationIssue { field: field.into(), issue: issue.into() }
+}
+
+// Rows with their 1-based line number in the file, header included.
+type Rows = Vec<(usize, Vec<String>)>;
+
+fn read_csv(bytes: &[u8]) -> Result<Rows, ValidationIssue> {
+    let bytes = bytes.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(bytes);
+    // Spreadsheet programs in a Russian locale export with `;`.
+    let first_line = bytes.split(|b| *b == b'\n').next().unwrap_or_default();
+    let semicolons = first_line.iter().filter(|b| **b == b';').count();
+    let commas = first_line.iter().filter(|b| **b == b',').count();
+    let delimiter = if semicolons > commas { b';' } else { b',' };
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .delimiter(delimiter)
+        .from_reader(bytes);
+    let mut rows = Vec::new();
+    for record in reader.records() {
+        let record = record.map_err(|e| {
+            let line = e.position().map(|p| p.line() as usize).unwrap_or(0);
+            issue(format!("rows[{line}]"), format!("cannot read csv: {}", e))
+        })?;
+        let line = record.position().map(|p| p.line() as usize).unwrap_or(rows.len() + 1);
+        rows.push((line, record.iter().map(str::to_string).collect()));
+    }
+    Ok(rows)
+}
+
+fn read_xlsx(bytes: &[u8]) -> Result<Rows, ValidationIssue> {
+    let mut workbook: Xlsx<_> =
+        calamine::open_workbook_from_rs(Cursor::new(bytes)).map_err(|e| issue("file", format!("cannot read xlsx: {}", e)))?;
+    let range = workbook
+        .worksheet_range_at(0)
+        .ok_or_else(|| issue("file", "workbook has no sheets"))?
+        .map_err(|e| issue("file", format!("cannot read first sheet: {}", e)))?;
+    let first_row = range.start().map(|(row, _)| row as usize).unwrap_or(0);
+    Ok(range
+        .rows()
+        .enumerate()
+        .map(|(i, cells)| (first_row + i + 1, cells.iter().map(|c| c.to_string()).collect()))
+        .collect())
+}
+
+fn split_list(cell: &str, separator: char) -> Vec<String> {
+    cell.split(separator).map(str::trim).filter(|v| !v.is_empty()).map(str::to_string).collect()
+}
+
+// Options are referenced by their text or by 1-based position.
+fn option_ref(options: &[QuizOption], value: &str) -> Option<String> {
+    let wanted = value.trim().to_lowercase();
+    options
+        .iter()
+        .find(|o| o.text.trim().to_lowercase() == wanted)
+        .or_else(|| wanted.parse::<usize>().ok().and_then(|n| options.get(n.checked_sub(1)?)))
+        .map(|o| o.id.clone())
+}
+
+fn parse_question_type(value: &str) -> Option<QuestionType> {
+    serde_json::from_value(serde_json::Value::String(value.trim().to_lowercase())).ok()
+}
+
+fn parse_row(index: usize, line: usize, cell: &dyn Fn(&str) -> String, issues: &mut Vec<ValidationIssue>) -> Option<Question> {
+    let field = |column: &str| format!("rows[{line}].{column}");
+    let errors_before = issues.len();
+    let q_type = parse_question_type(&cell("type"));
+    if q_type.is_none() {
+        issues.push(issue(field("type"), "must be one of open, single, multi, ordering, numeric, cloze"));
+    }
+    let options: Vec<QuizOption> = split_list(&cell("options"), LIST_SEPARATOR)
+        .into_iter()
+        .enumerate()
+        .map(|(i, text)| QuizOption { id: format!("o{}", i + 1), text })
+        .collect();
+    let answer_cell = cell("answer");
+    let answers = split_list(&answer_cell, LIST_SEPARATOR);
+
+    let answer = match q_type.as_ref()? {
+        QuestionType::Open => answers.split_first().map(|(text, accepted)| {
+            AnswerKey::Open(OpenAnswer { accepted: accepted.to_vec(), ..OpenAnswer::new(text.clone()) })
+        }),
+        QuestionType::Single => match answers.as_slice() {
+            [one] => option_ref(&options, one).map(|option_id| AnswerKey::Single { option_id }),
+            _ => None,
+        },
+        QuestionType::Multi => answers
+            .iter()
+            .map(|a| option_ref(&options, a))
+            .collect::<Option<Vec<_>>>()
+            .map(|option_ids| AnswerKey::Multi { option_ids }),
+        // Options are listed in the correct order; the answer cell may stay empty.
+        QuestionType::Ordering if answers.is_empty() => {
+            Some(AnswerKey::Ordering { order: options.iter().map(|o| o.id.clone()).collect() })
+        }
+        QuestionType::Ordering => answers
+            .iter()
+            .map(|a| option_ref(&options, a))
+            .collect::<Option<Vec<_>>>()
+            .map(|order| AnswerKey::Ordering { order }),
+        QuestionType::Numeric => parse_number_with_unit(&answer_cell).map(|(value, unit)| {
+            AnswerKey::Numeric(NumericAnswer {
+                value,
+                tolerance: None,
+                relative_tolerance: None,
+                unit: Some(unit).filter(|u| !u.is_empty()),
+                unit_factors: Default::default(),
+            })
+        }),
+        QuestionType::Cloze => Some(AnswerKey::Cloze {
+            blanks: answers.iter().map(|blank| split_list(blank, VARIANT_SEPARATOR)).collect(),
+        }),
+    };
+    if answer.is_none() {
+        issues.push(issue(field("answer"), "does not match the question type or options"));
+    }
+
+    let mut number = |column: &str| -> Option<u32> {
+        let value = cell(column);
+        if value.trim().is_empty() {
+            return None;
+        }
+        // Spreadsheet cells often carry numbers as "3.0".
+        let parsed = value.trim().parse::<f64>().ok().filter(|v| v.fract() == 0.0 && *v >= 0.0);
+        if parsed.is_none() {
+            issues.push(issue(field(column), "must be a whole number"));
+        }
+        parsed.map(|v| v as u32)
+    };
+    let points = number("points");
+    let time_limit_secs = number("time_limit");
+
+    if issues.len() > errors_before {
+        return None;
+    }
+    let id = cell("id").trim().to_string();
+    let explanation = cell("explanation").trim().to_string();
+    let q_type = q_type?;
+    Some(Question {
+        id: if id.is_empty() { format!("q{}", index + 1) } else { id },
+        options: (!matches!(q_type, QuestionType::Open | QuestionType::Numeric | QuestionType::Cloze)).then_some(options),
+        q_type,
+        prompt: cell("prompt").trim().to_string(),
+        answer: answer?,
+        time_limit_secs,
+        points,
+        difficulty: None,
+        tags: split_list(&cell("tags"), LIST_SEPARATOR),
+        explanation: (!explanation.is_empty()).then_some(explanation),
+    })
+}
+
+pub struct ImportMeta {
+    pub title: String,
+    pub description: Option<String>,
+}
+
+// Parses the sheet into a quiz and runs the usual quiz validation; issues from `validate_quiz` are
+// reported against the row the question came from.
+pub fn parse(bytes: &[u8], format: SheetFormat, meta: ImportMeta, limits: &QuizLimits) -> Result<Quiz, Vec<ValidationIssue>> {
+    let mut rows = match format {
+        SheetFormat::Csv => read_csv(bytes),
+        SheetFormat::Xlsx => read_xlsx(bytes),
+    }
+    .map_err(|e| vec![e])?
+    .into_iter()
+    .filter(|(_, cells)| cells.iter().any(|c| !c.trim().is_empty()));
+
+    let Some((_, header)) = rows.next() else {
+        return Err(vec![issue("file", "is empty")]);
+    };
+    let columns: HashMap<String, usize> = header.iter().enumerate().map(|(i, h)| (h.trim().to_lowercase(), i)).collect();
+    let missing: Vec<ValidationIssue> = REQUIRED_COLUMNS
+        .iter()
+        .filter(|c| !columns.contains_key(**c))
+        .map(|c| issue(format!("columns.{c}"), "is missing from the header row"))
+        .collect();
+    if !missing.is_empty() {
+        return Err(missing);
+    }
+
+    let rows: Rows = rows.collect();
+    if rows.len() > MAX_IMPORT_ROWS {
+        return Err(vec![issue("file", format!("must contain at most {} questions", MAX_IMPORT_ROWS))]);
+    }
+    let mut issues = Vec::new();
+    let mut questions = Vec::new();
+    let mut lines = Vec::new();
+    for (index, (line, cells)) in rows.iter().enumerate() {
+        let cell = |column: &str| columns.get(column).and_then(|i| cells.get(*i)).cloned().unwrap_or_default();
+        if let Some(question) = parse_row(index, *line, &cell, &mut issues) {
+            questions.push(question);
+            lines.push(*line);
+        }
+    }
+    if !issues.is_empty() {
+        return Err(issues);
+    }
+
+    let quiz = Quiz {
+        title: meta.title,
+        description: meta.description,
+        questions,
+        difficulty: None,
+        tags: Vec::new(),
+        settings: QuizSettings::default(),
+        subject: None,
+        grade: None,
+        language: None,
+    };
+    validate_quiz(&quiz, limits).map_err(|issues| {
+        issues
+            .into_iter()
+            .map(|i| match i.field.strip_prefix("questions[").and_then(|rest| rest.split_once(']')) {
+                Some((n, rest)) => match n.parse::<usize>().ok().and_then(|n| lines.get(n)) {
+                    Some(line) => issue(format!("rows[{line}]{rest}"), i.issue),
+                    None => i,
+                },
+                None => i,
+            })
+            .collect::<Vec<_>>()
+    })?;
+    Ok(quiz)
+}
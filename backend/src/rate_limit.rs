@@ -0,0 +1,95 @@
+use serde::Deserialize;
+use std::time::Duration;
+
+// One fixed-window limit on a route, counted per client IP.
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct RoutePolicy {
+    pub method: String,
+    // Route pattern as written in routes.rs, e.g. `/api/v1/homework/:room_code/join`.
+    pub route: String,
+    // 0 turns the limit off.
+    pub limit: u32,
+    #[serde(default = "default_window_secs")]
+    pub window_secs: u64,
+}
+
+fn default_window_secs() -> u64 {
+    60
+}
+
+impl RoutePolicy {
+    fn new(method: &str, route: &str, limit: u32) -> Self {
+        Self { method: method.into(), route: route.into(), limit, window_secs: default_window_secs() }
+    }
+
+    pub fn window(&self) -> Duration {
+        Duration::from_secs(self.window_secs.max(1))
+    }
+
+    // Counter key shared by all replicas that use the same Redis.
+    pub fn bucket(&self, client: &str) -> String {
+        format!("{} {}:{}", self.method, self.route, client)
+    }
+}
+
+// Unauthenticated endpoints that create accounts, issue credentials or start work for anyone
+// who asks; all other routes are only limited per API key.
+fn default_policies() -> Vec<RoutePolicy> {
+    vec![
+        RoutePolicy::new("POST", "/api/v1/auth/register", 20),
+        RoutePolicy::new("POST", "/api/v1/auth/login", 30),
+        RoutePolicy::new("POST", "/api/v1/auth/token", 30),
+        RoutePolicy::new("POST", "/api/v1/auth/password-reset", 5),
+        RoutePolicy::new("POST", "/api/v1/students/register", 20),
+        RoutePolicy::new("POST", "/api/v1/students/login", 30),
+        RoutePolicy::new("POST", "/api/v1/quizzes/:id/clone", 15),
+        RoutePolicy::new("POST", "/api/v1/homework/:room_code/join", 30),
+        RoutePolicy::new("POST", "/api/v1/practice/:quiz_id/start", 30),
+    ]
+}
+
+pub struct RateLimits {
+    policies: Vec<RoutePolicy>,
+    // Requests per minute for each API key, on any route.
+    pub api_key_per_minute: u32,
+}
+
+impl RateLimits {
+    pub fn new(policies: Vec<RoutePolicy>, api_key_per_minute: u32) -> Self {
+        Self { policies, api_key_per_minute }
+    }
+
+    // RATE_LIMITS_FILE is a JSON array of route policies; an entry replaces the built-in policy
+    // for the same method and route, or adds a new one.
+    pub fn from_env() -> anyhow::Result<Self> {
+        let mut policies = default_policies();
+        if let Some(path) = std::env::var("RATE_LIMITS_FILE").ok().filter(|v| !v.trim().is_empty()) {
+            let raw = std::fs::read_to_string(path.trim())
+                .map_err(|e| anyhow::anyhow!("cannot read RATE_LIMITS_FILE ({}): {}", path, e))?;
+            let overrides: Vec<RoutePolicy> =
+                serde_json::from_str(&raw).map_err(|e| anyhow::anyhow!("invalid RATE_LIMITS_FILE: {}", e))?;
+            for mut policy in overrides {
+                policy.method = policy.method.to_ascii_uppercase();
+                policies.retain(|p| p.method != policy.method || p.route != policy.route);
+                policies.push(policy);
+            }
+        }
+        let api_key_per_minute = std::env::var("API_KEY_RATE_LIMIT_PER_MINUTE")
+            .ok()
+            .and_then(|v| v.parse::<u32>().ok())
+            .filter(|v| *v > 0)
+            .unwrap_or(120);
+        Ok(Self::new(policies, api_key_per_minute))
+    }
+
+    pub fn policy(&self, method: &str, route: &str) -> Option<&RoutePolicy> {
+        self.policies.iter().find(|p| p.limit > 0 && p.method == method && p.route == route)
+    }
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        Self::new(default_policies(), 120)
+    }
+}
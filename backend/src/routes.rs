@@ -1,52 +1,229 @@
 use crate::handlers;
 use crate::state::AppState;
+use crate::web::CorsOrigins;
 use axum::http::{HeaderValue, Method};
-use axum::routing::{get, post};
+use axum::extract::DefaultBodyLimit;
+use axum::middleware;
+use axum::routing::{delete, get, post};
 use axum::Router;
-use tower_http::cors::CorsLayer;
+use tower_http::cors::{AllowOrigin, CorsLayer};
 use tower_http::trace::TraceLayer;
 
+fn import_body_limit() -> usize {
+    std::env::var("ADMIN_IMPORT_MAX_MB")
+        .ok()
+        .and_then(|v| v.parse::<usize>().ok())
+        .unwrap_or(50)
+        * 1024
+        * 1024
+}
+
+// JSON bodies of teacher routes carry whole quizzes; QUIZ_MAX_PAYLOAD_BYTES still applies to the
+// quiz itself.
+fn teacher_body_limit() -> usize {
+    std::env::var("TEACHER_BODY_MAX_KB")
+        .ok()
+        .and_then(|v| v.parse::<usize>().ok())
+        .unwrap_or(1024)
+        * 1024
+}
+
+// Everything else: logins, answers, joins, admin settings.
+fn public_body_limit() -> usize {
+    std::env::var("PUBLIC_BODY_MAX_KB")
+        .ok()
+        .and_then(|v| v.parse::<usize>().ok())
+        .unwrap_or(128)
+        * 1024
+}
+
+fn ai_upload_limit() -> usize {
+    std::env::var("AI_UPLOAD_MAX_MB")
+        .ok()
+        .and_then(|v| v.parse::<usize>().ok())
+        .unwrap_or(10)
+        * 1024
+        * 1024
+}
+
+// MEDIA_MAX_MB applies to the file itself; the rest is room for the multipart framing.
+fn media_upload_limit(state: &AppState) -> usize {
+    state.media.max_bytes + 64 * 1024
+}
+
+// Origins were validated when WebConfig was loaded. A wildcard cannot be combined with
+// credentials, so the dev mode echoes whatever origin asks.
+fn allowed_origins(origins: &CorsOrigins) -> AllowOrigin {
+    match origins {
+        CorsOrigins::List(list) => {
+            AllowOrigin::list(list.iter().filter_map(|origin| HeaderValue::from_str(origin).ok()))
+        }
+        CorsOrigins::Any => AllowOrigin::mirror_request(),
+    }
+}
+
+// /api/v2 keeps every response body camelCase. Routes whose v1 bodies already were are shared
+// with v1; v1 itself stays as it is until clients have moved over.
+fn v2_routes(state: &AppState) -> Router<AppState> {
+    Router::new()
+        .route("/api/v2/auth/me", get(handlers::me))
+        .route("/api/v2/quizzes", post(handlers::v2_create_quiz).get(handlers::v2_list_quizzes))
+        .route(
+            "/api/v2/quizzes/:id",
+            get(handlers::v2_get_quiz).put(handlers::v2_update_quiz).delete(handlers::delete_quiz),
+        )
+        .route("/api/v2/quizzes/:id/publish", post(handlers::publish_quiz))
+        .route("/api/v2/quizzes/:id/unpublish", post(handlers::unpublish_quiz))
+        .route("/api/v2/sessions", get(handlers::list_sessions))
+        .route_layer(middleware::from_fn_with_state(state.clone(), handlers::csrf_guard))
+        .layer(DefaultBodyLimit::max(teacher_body_limit()))
+}
+
 pub fn build_router(state: AppState) -> Router {
     let cors = CorsLayer::new()
         .allow_credentials(true)
-        .allow_origin([
-            HeaderValue::from_static("http://localhost:5173"),
-            HeaderValue::from_static("https://school-gaming-quiz.ru"),
-            HeaderValue::from_static("https://www.school-gaming-quiz.ru"),
-        ])
+        .allow_origin(allowed_origins(&state.web.cors_origins))
         .allow_methods([Method::GET, Method::POST, Method::PUT, Method::DELETE])
         .allow_headers([
             axum::http::header::CONTENT_TYPE,
             axum::http::header::ACCEPT,
             axum::http::header::COOKIE,
             axum::http::header::SET_COOKIE,
+            axum::http::header::AUTHORIZATION,
+            axum::http::HeaderName::from_static("x-api-key"),
             axum::http::HeaderName::from_static("x-csrf-token"),
+            axum::http::HeaderName::from_static("x-participant-token"),
             axum::http::HeaderName::from_static("x-request-id"),
             axum::http::HeaderName::from_static("x-forwarded-for"),
-        ]);
+        ])
+        .expose_headers([axum::http::HeaderName::from_static("x-request-id")]);
 
-    Router::new()
-        .route("/health", get(|| async { "ok" }))
-        .route("/api/v1/auth/register", post(handlers::register))
-        .route("/api/v1/auth/login", post(handlers::login))
+    // Every mutating route that acts on behalf of a teacher goes here so the CSRF check cannot
+    // be forgotten; `csrf_guard` lets GET requests through.
+    let teacher_routes = Router::new()
         .route("/api/v1/auth/logout", post(handlers::logout))
-        .route("/api/v1/auth/me", get(handlers::me))
+        .route("/api/v1/auth/change-password", post(handlers::change_password))
+        .route("/api/v1/auth/email", get(handlers::get_email_settings).put(handlers::update_email_settings))
+        .route("/api/v1/auth/2fa/setup", post(handlers::totp_setup))
+        .route("/api/v1/auth/2fa/verify", post(handlers::totp_verify))
+        .route("/api/v1/auth/api-keys", post(handlers::create_api_key).get(handlers::list_api_keys))
+        .route("/api/v1/auth/api-keys/:id", delete(handlers::revoke_api_key))
+        .route(
+            "/api/v1/auth/sessions",
+            get(handlers::list_teacher_sessions).delete(handlers::revoke_other_sessions),
+        )
+        .route(
+            "/api/v1/moderation/words",
+            get(handlers::get_word_overrides).put(handlers::update_word_overrides),
+        )
+        .route("/api/v1/students/codes", post(handlers::issue_student_codes))
         .route("/api/v1/quizzes", post(handlers::create_quiz).get(handlers::list_quizzes))
+        .route("/api/v1/quizzes/import", post(handlers::import_quiz))
         .route(
             "/api/v1/quizzes/:id",
             get(handlers::get_quiz).put(handlers::update_quiz).delete(handlers::delete_quiz),
         )
+        .route("/api/v1/quizzes/:id/revisions/:rev/restore", post(handlers::restore_quiz_revision))
         .route("/api/v1/quizzes/:id/publish", post(handlers::publish_quiz))
         .route("/api/v1/quizzes/:id/unpublish", post(handlers::unpublish_quiz))
         .route("/api/v1/quizzes/:id/clone", post(handlers::clone_quiz))
-        .route("/api/v1/library/quizzes", get(handlers::library_list))
+        .route("/api/v1/quizzes/:id/export", get(handlers::export_quiz))
+        .route("/api/v1/quizzes/:id/item-analysis", get(handlers::quiz_item_analysis))
         .route("/api/v1/ai/generate-quiz", post(handlers::ai_generate_quiz))
-        .route("/api/v1/sessions", post(handlers::create_session))
+        .route("/api/v1/ai/jobs", post(handlers::create_ai_job))
+        .route(
+            "/api/v1/ai/generate-from-file",
+            post(handlers::ai_generate_from_file).layer(DefaultBodyLimit::max(ai_upload_limit())),
+        )
+        .route("/api/v1/ai/jobs/:id", get(handlers::get_ai_job))
+        .route("/api/v1/ai/usage", get(handlers::ai_usage))
+        .route(
+            "/api/v1/ai/questions/:quiz_id/:question_id/distractors",
+            post(handlers::ai_question_distractors),
+        )
+        .route(
+            "/api/v1/media",
+            post(handlers::upload_media).layer(DefaultBodyLimit::max(media_upload_limit(&state))),
+        )
+        .route("/api/v1/media/:id/url", get(handlers::media_link))
+        .route("/api/v1/classes", post(handlers::create_class).get(handlers::list_classes))
+        .route(
+            "/api/v1/classes/:id",
+            get(handlers::get_class).put(handlers::update_class).delete(handlers::delete_class),
+        )
+        .route("/api/v1/sessions", post(handlers::create_session).get(handlers::list_sessions))
         .route("/api/v1/sessions/:id/start", post(handlers::start_session))
+        .route("/api/v1/sessions/:id/pause", post(handlers::pause_session))
+        .route("/api/v1/sessions/:id/resume", post(handlers::resume_session))
+        .route("/api/v1/sessions/:id/join-token", post(handlers::rotate_join_token))
+        .route("/api/v1/sessions/:id/next-quiz", post(handlers::next_playlist_quiz))
         .route("/api/v1/sessions/:id/end", post(handlers::end_session))
+        .route("/api/v1/sessions/:id/followup-quiz", post(handlers::session_followup_quiz))
+        .route("/api/v1/admin/password-reset", post(handlers::admin_password_reset))
+        .route("/api/v1/admin/prompts", get(handlers::admin_list_prompts))
+        .route(
+            "/api/v1/admin/prompts/:use_case",
+            get(handlers::admin_get_prompt).put(handlers::admin_update_prompt),
+        )
+        .route("/api/v1/admin/prompts/:use_case/revert", post(handlers::admin_revert_prompt))
+        .route(
+            "/api/v1/admin/import",
+            post(handlers::admin_import).layer(DefaultBodyLimit::max(import_body_limit())),
+        )
+        .route_layer(middleware::from_fn_with_state(state.clone(), handlers::csrf_guard))
+        .layer(DefaultBodyLimit::max(teacher_body_limit()));
+
+    Router::new()
+        .route("/health", get(|| async { "ok" }))
+        .route("/api/v1/auth/register", post(handlers::register))
+        .route("/api/v1/auth/login", post(handlers::login))
+        .route("/api/v1/auth/password-reset", post(handlers::request_password_reset))
+        .route("/api/v1/auth/password-reset/confirm", post(handlers::confirm_password_reset))
+        .route("/api/v1/auth/token", post(handlers::issue_token))
+        .route("/api/v1/auth/token/revoke", post(handlers::revoke_token))
+        .route("/api/v1/auth/me", get(handlers::me))
+        .route("/api/v1/students/register", post(handlers::register_student))
+        .route("/api/v1/students/login", post(handlers::login_student))
+        .route("/api/v1/students/me", get(handlers::student_me))
+        .route("/lti/login", get(handlers::lti_login).post(handlers::lti_login))
+        .route("/lti/launch", post(handlers::lti_launch))
+        .route("/lti/jwks", get(handlers::lti_jwks))
+        .route("/api/v1/homework/:room_code/join", post(handlers::join_homework))
+        .route("/api/v1/homework/:room_code/question", get(handlers::homework_question))
+        .route("/api/v1/homework/:room_code/answers", post(handlers::submit_homework_answer))
+        .route("/api/v1/practice/:quiz_id/start", post(handlers::start_practice))
+        .route("/api/v1/practice/runs/:run_id/question", get(handlers::practice_question))
+        .route("/api/v1/practice/runs/:run_id/answers", post(handlers::submit_practice_answer))
+        .route("/api/v1/quizzes/:id/revisions", get(handlers::list_quiz_revisions))
+        .route("/api/v1/library/quizzes", get(handlers::library_list))
+        .route("/api/v1/contracts/ws", get(handlers::ws_contract))
+        .route("/api/v1/media/:id", get(handlers::download_media))
         .route("/api/v1/sessions/:id/results", get(handlers::session_results))
+        .route("/api/v1/sessions/:id/live", get(handlers::session_live))
+        .route("/api/v1/admin/usage-stats", get(handlers::admin_usage_stats))
+        .route("/api/v1/admin/stats", get(handlers::admin_stats))
+        .route("/api/v1/admin/audit", get(handlers::admin_audit))
+        .route("/api/v1/admin/capacity", get(handlers::admin_capacity))
+        .route("/api/v1/admin/metrics", get(handlers::admin_metrics))
+        .route("/metrics", get(handlers::prometheus_metrics))
+        .route("/api/v1/admin/export", get(handlers::admin_export))
         .route("/ws/sessions/:room_code", get(handlers::ws_handler))
+        .layer(DefaultBodyLimit::max(public_body_limit()))
+        .merge(teacher_routes)
+        .merge(v2_routes(&state))
+        .layer(middleware::from_fn_with_state(state.clone(), handlers::rate_limit))
+        .layer(middleware::from_fn(handlers::compress_json))
+        .layer(middleware::from_fn(handlers::track_http))
         .with_state(state)
-        .layer(TraceLayer::new_for_http())
+        .layer(TraceLayer::new_for_http().make_span_with(|request: &axum::http::Request<_>| {
+            let request_id = request.headers().get("x-request-id").and_then(|value| value.to_str().ok());
+            tracing::info_span!(
+                "request",
+                method = %request.method(),
+                uri = %request.uri(),
+                request_id = request_id.unwrap_or_default(),
+            )
+        }))
+        .layer(middleware::from_fn(handlers::request_id))
         .layer(cors)
 }
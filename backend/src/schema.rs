@@ -0,0 +1,46 @@
+use jsonschema::Validator;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+pub const CURRENT_QUIZ_SCHEMA_VERSION: &str = "v1";
+
+const QUIZ_SCHEMAS: &[(&str, &str)] = &[("v1", include_str!("../contracts/ai_quiz.schema.json"))];
+
+pub struct CompiledSchema {
+    pub raw: serde_json::Value,
+    pub validator: Validator,
+}
+
+// Validators are compiled once at startup; compiling per request dominated AI endpoint latency.
+#[derive(Clone)]
+pub struct QuizSchemas {
+    compiled: Arc<HashMap<&'static str, Arc<CompiledSchema>>>,
+}
+
+impl QuizSchemas {
+    pub fn compile_builtin() -> anyhow::Result<Self> {
+        let mut compiled = HashMap::new();
+        for (version, raw) in QUIZ_SCHEMAS {
+            let raw: serde_json::Value = serde_json::from_str(raw)?;
+            let validator = jsonschema::draft202012::new(&raw)
+                .map_err(|e| anyhow::anyhow!("quiz schema {} does not compile: {}", version, e))?;
+            compiled.insert(*version, Arc::new(CompiledSchema { raw, validator }));
+        }
+        Ok(Self { compiled: Arc::new(compiled) })
+    }
+
+    pub fn get(&self, version: &str) -> Option<Arc<CompiledSchema>> {
+        self.compiled.get(version).cloned()
+    }
+
+    pub fn current(&self) -> Arc<CompiledSchema> {
+        self.get(CURRENT_QUIZ_SCHEMA_VERSION)
+            .expect("current quiz schema version is always compiled")
+    }
+
+    pub fn versions(&self) -> Vec<&'static str> {
+        let mut versions: Vec<_> = self.compiled.keys().copied().collect();
+        versions.sort();
+        versions
+    }
+}
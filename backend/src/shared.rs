@@ -0,0 +1,354 @@
+use crate::jwt::RefreshToken;
+use crate::password_reset::PasswordResetToken;
+use crate::state::{InMemoryDb, TeacherSession};
+use crate::storage::StorageResult;
+use async_trait::async_trait;
+use chrono::Utc;
+use dashmap::DashMap;
+use redis::aio::ConnectionManager;
+use redis::AsyncCommands;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// State that must be shared between backend replicas: teacher sessions, rate-limit
+// counters and the room code -> session id mapping.
+#[async_trait]
+pub trait SharedStore: Send + Sync {
+    fn kind(&self) -> &'static str;
+    async fn get_teacher_session(&self, sid: &str) -> StorageResult<Option<TeacherSession>>;
+    async fn put_teacher_session(&self, sid: &str, session: &TeacherSession) -> StorageResult<()>;
+    async fn remove_teacher_session(&self, sid: &str) -> StorageResult<()>;
+    async fn list_teacher_sessions(&self, teacher_id: i64) -> StorageResult<Vec<(String, TeacherSession)>>;
+    async fn evict_expired_sessions(&self) -> StorageResult<usize>;
+    async fn put_refresh_token(&self, token: &str, record: &RefreshToken) -> StorageResult<()>;
+    // Removes and returns the token in one step so a refresh token can only be redeemed once.
+    async fn take_refresh_token(&self, token: &str) -> StorageResult<Option<RefreshToken>>;
+    // Drops every session and refresh token of the teacher except the session `keep_sid`.
+    async fn revoke_teacher_credentials(&self, teacher_id: i64, keep_sid: Option<&str>) -> StorageResult<()>;
+    async fn put_reset_token(&self, token: &str, record: &PasswordResetToken) -> StorageResult<()>;
+    async fn take_reset_token(&self, token: &str) -> StorageResult<Option<PasswordResetToken>>;
+    async fn hit_rate_limit(&self, key: &str, window: Duration) -> StorageResult<RateLimitHit>;
+    // Drops counters whose window has passed; stores with native expiry have nothing to do.
+    async fn evict_rate_limits(&self) -> StorageResult<usize>;
+    async fn room_session(&self, room_code: &str) -> StorageResult<Option<i64>>;
+    // Binds the code only if no session holds it yet; false means it is taken.
+    async fn bind_room(&self, room_code: &str, session_id: i64) -> StorageResult<bool>;
+    async fn release_room(&self, room_code: &str) -> StorageResult<()>;
+}
+
+// Requests counted in the current window, and when that window ends.
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimitHit {
+    pub count: u32,
+    pub resets_in: Duration,
+}
+
+pub struct MemorySharedStore {
+    db: Arc<InMemoryDb>,
+    // Count and end of the window per key.
+    rate_limits: DashMap<String, (u32, Instant)>,
+}
+
+impl MemorySharedStore {
+    pub fn new(db: Arc<InMemoryDb>) -> Self {
+        Self { db, rate_limits: DashMap::new() }
+    }
+}
+
+#[async_trait]
+impl SharedStore for MemorySharedStore {
+    fn kind(&self) -> &'static str {
+        "memory"
+    }
+
+    async fn get_teacher_session(&self, sid: &str) -> StorageResult<Option<TeacherSession>> {
+        Ok(self.db.sessions.read().await.get(sid).cloned())
+    }
+
+    async fn put_teacher_session(&self, sid: &str, session: &TeacherSession) -> StorageResult<()> {
+        self.db.sessions.write().await.insert(sid.to_string(), session.clone());
+        Ok(())
+    }
+
+    async fn remove_teacher_session(&self, sid: &str) -> StorageResult<()> {
+        self.db.sessions.write().await.remove(sid);
+        Ok(())
+    }
+
+    async fn list_teacher_sessions(&self, teacher_id: i64) -> StorageResult<Vec<(String, TeacherSession)>> {
+        let now = Utc::now();
+        Ok(self
+            .db
+            .sessions
+            .read()
+            .await
+            .iter()
+            .filter(|(_, s)| s.teacher_id == teacher_id && s.expires_at > now)
+            .map(|(sid, s)| (sid.clone(), s.clone()))
+            .collect())
+    }
+
+    async fn evict_expired_sessions(&self) -> StorageResult<usize> {
+        let now = Utc::now();
+        let mut sessions = self.db.sessions.write().await;
+        let before = sessions.len();
+        sessions.retain(|_, s| s.expires_at > now);
+        let evicted = before - sessions.len();
+        let mut refresh_tokens = self.db.refresh_tokens.write().await;
+        let before = refresh_tokens.len();
+        refresh_tokens.retain(|_, t| t.expires_at > now);
+        let evicted = evicted + before - refresh_tokens.len();
+        let mut reset_tokens = self.db.password_resets.write().await;
+        let before = reset_tokens.len();
+        reset_tokens.retain(|_, t| t.expires_at > now);
+        Ok(evicted + before - reset_tokens.len())
+    }
+
+    async fn put_refresh_token(&self, token: &str, record: &RefreshToken) -> StorageResult<()> {
+        self.db.refresh_tokens.write().await.insert(token.to_string(), record.clone());
+        Ok(())
+    }
+
+    async fn take_refresh_token(&self, token: &str) -> StorageResult<Option<RefreshToken>> {
+        Ok(self.db.refresh_tokens.write().await.remove(token))
+    }
+
+    async fn revoke_teacher_credentials(&self, teacher_id: i64, keep_sid: Option<&str>) -> StorageResult<()> {
+        self.db.revoke_teacher_credentials(teacher_id, keep_sid).await;
+        Ok(())
+    }
+
+    async fn put_reset_token(&self, token: &str, record: &PasswordResetToken) -> StorageResult<()> {
+        self.db.password_resets.write().await.insert(token.to_string(), record.clone());
+        Ok(())
+    }
+
+    async fn take_reset_token(&self, token: &str) -> StorageResult<Option<PasswordResetToken>> {
+        Ok(self.db.password_resets.write().await.remove(token))
+    }
+
+    async fn hit_rate_limit(&self, key: &str, window: Duration) -> StorageResult<RateLimitHit> {
+        let now = Instant::now();
+        let mut entry = self.rate_limits.entry(key.to_string()).or_insert((0, now + window));
+        if entry.1 <= now {
+            *entry = (0, now + window);
+        }
+        entry.0 += 1;
+        Ok(RateLimitHit { count: entry.0, resets_in: entry.1 - now })
+    }
+
+    async fn evict_rate_limits(&self) -> StorageResult<usize> {
+        let now = Instant::now();
+        let before = self.rate_limits.len();
+        self.rate_limits.retain(|_, (_, resets_at)| *resets_at > now);
+        Ok(before - self.rate_limits.len())
+    }
+
+    async fn room_session(&self, room_code: &str) -> StorageResult<Option<i64>> {
+        Ok(self.db.rooms.read().await.get(room_code).copied())
+    }
+
+    async fn bind_room(&self, room_code: &str, session_id: i64) -> StorageResult<bool> {
+        let mut rooms = self.db.rooms.write().await;
+        if rooms.contains_key(room_code) {
+            return Ok(false);
+        }
+        rooms.insert(room_code.to_string(), session_id);
+        Ok(true)
+    }
+
+    async fn release_room(&self, room_code: &str) -> StorageResult<()> {
+        self.db.rooms.write().await.remove(room_code);
+        Ok(())
+    }
+}
+
+pub struct RedisSharedStore {
+    conn: ConnectionManager,
+    prefix: String,
+}
+
+impl RedisSharedStore {
+    pub async fn connect(url: &str, prefix: &str) -> anyhow::Result<Self> {
+        let client = redis::Client::open(url)?;
+        let conn = ConnectionManager::new(client).await?;
+        Ok(Self { conn, prefix: prefix.to_string() })
+    }
+
+    fn session_key(&self, sid: &str) -> String {
+        format!("{}teacher_session:{}", self.prefix, sid)
+    }
+
+    fn refresh_key(&self, token: &str) -> String {
+        format!("{}refresh_token:{}", self.prefix, token)
+    }
+
+    fn reset_key(&self, token: &str) -> String {
+        format!("{}password_reset:{}", self.prefix, token)
+    }
+
+    // Per-teacher indexes of session ids and refresh tokens, used to revoke them all at once.
+    fn teacher_sessions_key(&self, teacher_id: i64) -> String {
+        format!("{}teacher_sessions:{}", self.prefix, teacher_id)
+    }
+
+    fn teacher_refresh_key(&self, teacher_id: i64) -> String {
+        format!("{}teacher_refresh_tokens:{}", self.prefix, teacher_id)
+    }
+
+    fn rooms_key(&self) -> String {
+        format!("{}rooms", self.prefix)
+    }
+}
+
+#[async_trait]
+impl SharedStore for RedisSharedStore {
+    fn kind(&self) -> &'static str {
+        "redis"
+    }
+
+    async fn get_teacher_session(&self, sid: &str) -> StorageResult<Option<TeacherSession>> {
+        let mut conn = self.conn.clone();
+        let raw: Option<String> = conn.get(self.session_key(sid)).await?;
+        match raw {
+            Some(raw) => Ok(Some(serde_json::from_str(&raw)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn put_teacher_session(&self, sid: &str, session: &TeacherSession) -> StorageResult<()> {
+        let mut conn = self.conn.clone();
+        let ttl_secs = (session.expires_at - Utc::now()).num_seconds().max(1) as u64;
+        let _: () = conn
+            .set_ex(self.session_key(sid), serde_json::to_string(session)?, ttl_secs)
+            .await?;
+        let index = self.teacher_sessions_key(session.teacher_id);
+        let _: () = conn.sadd(&index, sid).await?;
+        let _: () = conn.expire(&index, ttl_secs as i64).await?;
+        Ok(())
+    }
+
+    async fn remove_teacher_session(&self, sid: &str) -> StorageResult<()> {
+        let mut conn = self.conn.clone();
+        let _: () = conn.del(self.session_key(sid)).await?;
+        Ok(())
+    }
+
+    // Also prunes ids of sessions that expired or were logged out from the per-teacher index.
+    async fn list_teacher_sessions(&self, teacher_id: i64) -> StorageResult<Vec<(String, TeacherSession)>> {
+        let mut conn = self.conn.clone();
+        let index = self.teacher_sessions_key(teacher_id);
+        let sids: Vec<String> = conn.smembers(&index).await?;
+        let mut sessions = Vec::new();
+        for sid in sids {
+            let raw: Option<String> = conn.get(self.session_key(&sid)).await?;
+            match raw {
+                Some(raw) => sessions.push((sid, serde_json::from_str(&raw)?)),
+                None => {
+                    let _: () = conn.srem(&index, &sid).await?;
+                }
+            }
+        }
+        Ok(sessions)
+    }
+
+    async fn evict_expired_sessions(&self) -> StorageResult<usize> {
+        // Redis expires session keys on its own.
+        Ok(0)
+    }
+
+    async fn put_refresh_token(&self, token: &str, record: &RefreshToken) -> StorageResult<()> {
+        let mut conn = self.conn.clone();
+        let ttl_secs = (record.expires_at - Utc::now()).num_seconds().max(1) as u64;
+        let _: () = conn
+            .set_ex(self.refresh_key(token), serde_json::to_string(record)?, ttl_secs)
+            .await?;
+        let index = self.teacher_refresh_key(record.teacher_id);
+        let _: () = conn.sadd(&index, token).await?;
+        let _: () = conn.expire(&index, ttl_secs as i64).await?;
+        Ok(())
+    }
+
+    async fn take_refresh_token(&self, token: &str) -> StorageResult<Option<RefreshToken>> {
+        let mut conn = self.conn.clone();
+        let raw: Option<String> = conn.get_del(self.refresh_key(token)).await?;
+        match raw {
+            Some(raw) => Ok(Some(serde_json::from_str(&raw)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn revoke_teacher_credentials(&self, teacher_id: i64, keep_sid: Option<&str>) -> StorageResult<()> {
+        let mut conn = self.conn.clone();
+        let sessions_index = self.teacher_sessions_key(teacher_id);
+        let sids: Vec<String> = conn.smembers(&sessions_index).await?;
+        for sid in sids.iter().filter(|sid| Some(sid.as_str()) != keep_sid) {
+            let _: () = conn.del(self.session_key(sid)).await?;
+            let _: () = conn.srem(&sessions_index, sid).await?;
+        }
+        let refresh_index = self.teacher_refresh_key(teacher_id);
+        let tokens: Vec<String> = conn.smembers(&refresh_index).await?;
+        for token in &tokens {
+            let _: () = conn.del(self.refresh_key(token)).await?;
+        }
+        let _: () = conn.del(&refresh_index).await?;
+        Ok(())
+    }
+
+    async fn put_reset_token(&self, token: &str, record: &PasswordResetToken) -> StorageResult<()> {
+        let mut conn = self.conn.clone();
+        let ttl_secs = (record.expires_at - Utc::now()).num_seconds().max(1) as u64;
+        let _: () = conn
+            .set_ex(self.reset_key(token), serde_json::to_string(record)?, ttl_secs)
+            .await?;
+        Ok(())
+    }
+
+    async fn take_reset_token(&self, token: &str) -> StorageResult<Option<PasswordResetToken>> {
+        let mut conn = self.conn.clone();
+        let raw: Option<String> = conn.get_del(self.reset_key(token)).await?;
+        match raw {
+            Some(raw) => Ok(Some(serde_json::from_str(&raw)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn hit_rate_limit(&self, key: &str, window: Duration) -> StorageResult<RateLimitHit> {
+        let mut conn = self.conn.clone();
+        let full_key = format!("{}rate:{}", self.prefix, key);
+        let count: u32 = conn.incr(&full_key, 1).await?;
+        let window_ms = window.as_millis() as i64;
+        if count == 1 {
+            let _: () = conn.pexpire(&full_key, window_ms).await?;
+            return Ok(RateLimitHit { count, resets_in: window });
+        }
+        // A key left without expiry (e.g. the process died between INCR and PEXPIRE) would block
+        // the client forever.
+        let ttl_ms: i64 = conn.pttl(&full_key).await?;
+        if ttl_ms < 0 {
+            let _: () = conn.pexpire(&full_key, window_ms).await?;
+        }
+        let resets_in = Duration::from_millis(if ttl_ms > 0 { ttl_ms as u64 } else { window_ms as u64 });
+        Ok(RateLimitHit { count, resets_in })
+    }
+
+    async fn evict_rate_limits(&self) -> StorageResult<usize> {
+        Ok(0)
+    }
+
+    async fn room_session(&self, room_code: &str) -> StorageResult<Option<i64>> {
+        let mut conn = self.conn.clone();
+        Ok(conn.hget(self.rooms_key(), room_code).await?)
+    }
+
+    async fn bind_room(&self, room_code: &str, session_id: i64) -> StorageResult<bool> {
+        let mut conn = self.conn.clone();
+        Ok(conn.hset_nx(self.rooms_key(), room_code, session_id).await?)
+    }
+
+    async fn release_room(&self, room_code: &str) -> StorageResult<()> {
+        let mut conn = self.conn.clone();
+        let _: () = conn.hdel(self.rooms_key(), room_code).await?;
+        Ok(())
+    }
+}
@@ -1,22 +1,115 @@
-use crate::models::{Quiz, StudentStats};
-use crate::ws_protocol::WsEnvelope;
+use crate::ai_providers::{self, AiCompletion, AiFailure, AiRegistry, AiRequest, TokenUsage};
+use crate::api_keys::ApiKey;
+use crate::audit::AuditEntry;
+use crate::capacity::Admission;
+use crate::email::{EmailTemplate, Mailer};
+use crate::game_modes::{builtin_preset, GameModes};
+use crate::metrics::Metrics;
+use crate::passwords::PasswordHashing;
+use crate::password_reset::{self, PasswordResetToken, ResetDelivery};
+use crate::moderation::Moderation;
+use crate::profanity::{ProfanityFilter, WordOverrides};
+use crate::prompt_templates::{
+    PromptSet, PromptTemplateVersion, PromptTemplates, PromptUseCase, SystemPrompt, PROMPT_VERSION_LIMIT,
+};
+use crate::models::{
+    normalize_tag, Difficulty, Question, QuestionDiff, Quiz, QuizLimits, QuizSettings, StudentStats, SubmittedAnswer,
+    ValidationIssue, TIME_LIMIT_RANGE_SECS,
+};
+use crate::journal::{Journal, JournalOp};
+use crate::jwt::{JwtConfig, RefreshToken};
+use crate::lti::{LtiScore, LtiTool};
+use crate::media::Media;
+use crate::schema::QuizSchemas;
+use crate::shared::{MemorySharedStore, RedisSharedStore, SharedStore};
+use crate::rate_limit::RateLimits;
+use crate::storage::{self, OrNotFound, PersistentSnapshot, SnapshotStore, StorageError, StorageResult};
+use crate::totp::TotpState;
+use crate::web::WebConfig;
+use crate::ws_protocol::{ServerEvent, WsEnvelope};
 use base64::{engine::general_purpose::STANDARD, Engine as _};
+use chrono::{DateTime, Utc};
 use dashmap::DashMap;
+use data_encoding::HEXLOWER;
 use futures::future::BoxFuture;
+use rand::seq::SliceRandom;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::sync::atomic::{AtomicI64, Ordering};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
 use std::sync::Arc;
-use std::{fs, path::Path};
 use tokio::process::Command;
-use tokio::sync::{broadcast, RwLock};
-use tracing::warn;
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc, Notify, RwLock, Semaphore};
+use tracing::{info, warn};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Teacher {
     pub id: i64,
     pub login: String,
     pub password_hash: String,
+    #[serde(default)]
+    pub password_changed_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub totp: Option<TotpState>,
+    // Where notification emails go and which language they are written in ("ru" or "en").
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub locale: Option<String>,
+    #[serde(default, skip_serializing_if = "WordOverrides::is_empty")]
+    pub word_overrides: WordOverrides,
+}
+
+// A student either registers with login + password or redeems an access code issued by a teacher.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Student {
+    pub id: i64,
+    pub display_name: String,
+    #[serde(default)]
+    pub login: Option<String>,
+    #[serde(default)]
+    pub password_hash: Option<String>,
+    #[serde(default)]
+    pub access_code: Option<String>,
+    #[serde(default)]
+    pub issued_by_teacher_id: Option<i64>,
+    pub created_at: DateTime<Utc>,
+    // "issuer subject" of the LMS user this account was created for by an LTI launch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lti_user: Option<String>,
+}
+
+// A teacher's class: the roster sessions can be bound to so their results show who was absent.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClassRecord {
+    pub id: i64,
+    pub teacher_id: i64,
+    pub name: String,
+    #[serde(default)]
+    pub grade: Option<u8>,
+    #[serde(default)]
+    pub roster: Vec<RosterEntry>,
+    pub created_at: DateTime<Utc>,
+}
+
+// A member of a class, known by the nickname they play under and, when they have one, their account.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RosterEntry {
+    pub nickname: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub student_id: Option<i64>,
+}
+
+impl RosterEntry {
+    // Whether `participant` is this member: by account when both have one, else by nickname.
+    pub fn matches(&self, participant: &ParticipantState) -> bool {
+        match (self.student_id, participant.student_id) {
+            (Some(member), Some(student)) => member == student,
+            _ => self.nickname.trim().to_lowercase() == participant.nickname.trim().to_lowercase(),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -28,13 +121,156 @@ pub struct QuizRecord {
     pub questions: Vec<crate::models::Question>,
     pub is_published: bool,
     pub source_quiz_id: Option<i64>,
+    #[serde(default)]
+    pub difficulty: Option<Difficulty>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub settings: QuizSettings,
+    #[serde(default)]
+    pub subject: Option<String>,
+    #[serde(default)]
+    pub grade: Option<u8>,
+    #[serde(default)]
+    pub language: Option<String>,
+    // Missing on quizzes created before it was recorded.
+    #[serde(default)]
+    pub created_at: Option<DateTime<Utc>>,
 }
 
+impl QuizRecord {
+    pub fn revision_metadata(&self) -> RevisionMetadata {
+        RevisionMetadata {
+            difficulty: self.difficulty,
+            tags: self.tags.clone(),
+            settings: self.settings.clone(),
+            subject: self.subject.clone(),
+            grade: self.grade,
+            language: self.language.clone(),
+        }
+    }
+
+    // Puts back the content of a revision: title, description, questions and, when the revision has
+    // them, the quiz-level fields.
+    pub fn restore_revision(&mut self, revision: QuizRevision) {
+        self.title = revision.title;
+        self.description = revision.description;
+        self.questions = revision.questions;
+        if let Some(metadata) = revision.metadata {
+            self.difficulty = metadata.difficulty;
+            self.tags = metadata.tags;
+            self.settings = metadata.settings;
+            self.subject = metadata.subject;
+            self.grade = metadata.grade;
+            self.language = metadata.language;
+        }
+    }
+
+    // A quiz matches by its own metadata or by that of any of its questions.
+    pub fn has_tag(&self, tag: &str) -> bool {
+        let tag = normalize_tag(tag);
+        self.tags
+            .iter()
+            .chain(self.questions.iter().flat_map(|q| q.tags.iter()))
+            .any(|t| normalize_tag(t) == tag)
+    }
+
+    pub fn has_difficulty(&self, difficulty: Difficulty) -> bool {
+        self.difficulty == Some(difficulty) || self.questions.iter().any(|q| q.difficulty == Some(difficulty))
+    }
+
+    // Score needed to pass: `passingPct` of the points for answering every question correctly.
+    pub fn passing_score(&self) -> Option<f64> {
+        let pct = self.settings.passing_pct?;
+        let max: f64 = self.questions.iter().map(Question::points).sum();
+        Some(max * pct as f64 / 100.0)
+    }
+
+    pub fn to_quiz(&self) -> Quiz {
+        Quiz {
+            title: self.title.clone(),
+            description: self.description.clone(),
+            questions: self.questions.clone(),
+            difficulty: self.difficulty,
+            tags: self.tags.clone(),
+            settings: self.settings.clone(),
+            subject: self.subject.clone(),
+            grade: self.grade,
+            language: self.language.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QuizRevision {
+    pub rev: u32,
+    pub author_teacher_id: i64,
+    pub created_at: DateTime<Utc>,
+    pub reason: String,
+    #[serde(default)]
+    pub restored_from: Option<u32>,
+    pub title: String,
+    pub description: Option<String>,
+    pub questions: Vec<Question>,
+    // Missing on revisions recorded before it was kept; restoring one of those leaves the current
+    // metadata as it is.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<RevisionMetadata>,
+    pub diff: QuestionDiff,
+}
+
+// The quiz-level fields a teacher edits along with the questions. Publication, ownership and the
+// clone source are not part of a revision.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RevisionMetadata {
+    pub difficulty: Option<Difficulty>,
+    pub tags: Vec<String>,
+    pub settings: QuizSettings,
+    pub subject: Option<String>,
+    pub grade: Option<u8>,
+    pub language: Option<String>,
+}
+
+const QUIZ_REVISION_LIMIT: usize = 50;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ParticipantState {
     pub nickname: String,
     pub join_state: String,
     pub current_question_index: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub student_id: Option<i64>,
+    // Set when a timed question is pushed to this participant: (question id, answer deadline).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub question_deadline: Option<(String, DateTime<Utc>)>,
+    // Wrong answers already given to the current question.
+    #[serde(default)]
+    pub attempts: u32,
+    // Question indexes in the order this participant sees them; empty means quiz order.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub question_order: Vec<usize>,
+    // "quizId:questionId" of every hint already charged to the hint budget.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hints: Vec<String>,
+    // Handed to the participant's client on join; presenting it again restores this state on reconnect.
+    #[serde(default)]
+    pub resume_token: String,
+    // The question last pushed to the participant and not yet answered.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pending_question: Option<String>,
+    // When `pending_question` was pushed; answer latency for the speed bonus is measured from here.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub question_pushed_at: Option<DateTime<Utc>>,
+    // Seeds this participant's question and option order when the quiz shuffles them.
+    #[serde(default)]
+    pub shuffle_seed: u64,
+    // Lives left when the game mode has lives; at 0 the participant is out of the game.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lives: Option<u32>,
+    // Final `answer_result` payload by "quizId:questionId"; submitting the question again gets it back
+    // instead of being scored twice.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub answered: HashMap<String, serde_json::Value>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -49,50 +285,718 @@ pub struct SessionRecord {
     pub participants: HashMap<String, ParticipantState>,
     pub stats: HashMap<String, StudentStats>,
     pub mistakes: HashMap<String, Vec<String>>,
+    #[serde(default)]
+    pub playlist: Vec<i64>,
+    #[serde(default)]
+    pub playlist_position: usize,
+    #[serde(default)]
+    pub completed_segments: Vec<PlaylistSegment>,
+    #[serde(default)]
+    pub answers: HashMap<String, Vec<AnswerRecord>>,
+    #[serde(default)]
+    pub review_until: Option<DateTime<Utc>>,
+    // AI hints by "quizId:questionId", generated once and shared by every participant.
+    #[serde(default)]
+    pub hints: HashMap<String, String>,
+    // Nicknames the teacher removed from the room; they cannot join again.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub kicked: Vec<String>,
+    #[serde(default)]
+    pub pacing: SessionPacing,
+    // The question the teacher last pushed to everyone in a host-paced session.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host_question: Option<HostQuestion>,
+    // Set while the session is paused; on resume every running deadline moves on by the time paused.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub paused_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub lobby: LobbySettings,
+    // Set at creation; sessions stored before it existed fall back to their mode's preset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub game_config: Option<GameModeConfig>,
+    // Set for homework: students play over REST on their own time while the window is open.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub homework: Option<HomeworkWindow>,
+    // Set for a solo practice run: one participant, no teacher and no room. Runs are never stored and
+    // are dropped `PRACTICE_RUN_TTL_HOURS` after they started.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub practice_started_at: Option<DateTime<Utc>>,
+    // Set when the teacher scheduled the start; the room waits until then, see `AppState::schedule_start`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub starts_at: Option<DateTime<Utc>>,
+    // The question the teacher last showed the class in the review after the game (`review_next`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub review_position: Option<usize>,
+    // Anti-cheat signals by nickname, for the whole session including every playlist quiz.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub integrity: HashMap<String, IntegrityStats>,
+    // Connection drops while a question was open, by nickname, so the teacher can judge whether a late
+    // or timed-out answer was fair.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub disruptions: HashMap<String, Vec<Disruption>>,
+    // Set when the session was bound to a class; the roster is copied so later edits of the class
+    // leave the session's attendance as it was.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub class_id: Option<i64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub roster: Vec<RosterEntry>,
+    // When the session was created, first started and ended (the end of the game, before any review).
+    // Sessions stored before these existed have none.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub started_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ended_at: Option<DateTime<Utc>>,
+    // Gradebook columns of students who came in through an LMS launch, by student id.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub lti_grades: HashMap<i64, LtiGradeTarget>,
+}
+
+// Where an LMS-launched student's final score goes (LTI Assignment and Grade Services), and how sending
+// it went.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LtiGradeTarget {
+    pub issuer: String,
+    pub client_id: String,
+    pub user_id: String,
+    pub lineitem: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub score_given: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sent_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+pub const PRACTICE_RUN_TTL_HOURS: i64 = 6;
+
+impl SessionRecord {
+    pub fn game_config(&self) -> GameModeConfig {
+        self.game_config.clone().unwrap_or_else(|| builtin_preset(&self.game_mode).unwrap_or_default())
+    }
+
+    // Payload of `start_countdown` while a scheduled session is waiting for its start.
+    pub fn start_countdown(&self) -> Option<serde_json::Value> {
+        let starts_at = self.starts_at.filter(|_| self.status == "waiting")?;
+        Some(serde_json::json!({
+            "sessionId": self.id,
+            "startsAt": starts_at.to_rfc3339(),
+            "remainingMs": (starts_at - Utc::now()).num_milliseconds().max(0),
+        }))
+    }
+}
+
+// How a game mode plays, as data: each mode starts from its preset (see `GameMode::preset`), which the
+// teacher may replace when creating the session. The defaults are the classic quiz.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub struct GameModeConfig {
+    // Lives each participant starts with; a wrong or timed-out answer costs one and at 0 they are out.
+    // None means unlimited.
+    pub lives: Option<u32>,
+    // Start over from the first question after the last one instead of finishing the quiz.
+    pub cycle_questions: bool,
+    // Points taken off the leaderboard for every wrong or timed-out answer.
+    pub respawn_penalty: f64,
+    // Time limit for questions that have none of their own.
+    pub time_pressure_secs: Option<u32>,
+    // Time a participant spends disconnected with a question open is added to that question's deadline
+    // once they are back, up to this much; 0 keeps the deadline running. Self-paced sessions only.
+    pub disconnect_grace_secs: u32,
+}
+
+pub const MAX_LIVES: u32 = 99;
+pub const MAX_RESPAWN_PENALTY: f64 = 100.0;
+pub const MAX_DISCONNECT_GRACE_SECS: u32 = 300;
+
+impl GameModeConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.lives.is_some_and(|lives| lives == 0 || lives > MAX_LIVES) {
+            return Err(format!("lives must be within 1..={}", MAX_LIVES));
+        }
+        if !(0.0..=MAX_RESPAWN_PENALTY).contains(&self.respawn_penalty) {
+            return Err(format!("respawnPenalty must be within 0..={}", MAX_RESPAWN_PENALTY));
+        }
+        if self.time_pressure_secs.is_some_and(|secs| !TIME_LIMIT_RANGE_SECS.contains(&secs)) {
+            let (min, max) = (TIME_LIMIT_RANGE_SECS.start(), TIME_LIMIT_RANGE_SECS.end());
+            return Err(format!("timePressureSecs must be within {}..={}", min, max));
+        }
+        if self.disconnect_grace_secs > MAX_DISCONNECT_GRACE_SECS {
+            return Err(format!("disconnectGraceSecs must be within 0..={}", MAX_DISCONNECT_GRACE_SECS));
+        }
+        Ok(())
+    }
+
+    // The limit a question is played with in this mode.
+    pub fn time_limit(&self, question: &Question) -> Option<u32> {
+        question.time_limit_secs.or(self.time_pressure_secs)
+    }
+}
+
+// Self-paced students pull their own questions; host-paced sessions move through the quiz together
+// at the teacher's command.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SessionPacing {
+    #[default]
+    #[serde(rename = "self")]
+    SelfPaced,
+    Host,
+}
+
+// Who may enter the room, set by the teacher when creating the session.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct LobbySettings {
+    // Participants the room takes; reconnecting to a seat already in the room always works.
+    pub max_participants: Option<u32>,
+    // Whether new nicknames may join once the session has started.
+    pub allow_late_join: bool,
+    // Nickname length in characters.
+    pub nickname_min_length: usize,
+    pub nickname_max_length: usize,
+    pub nickname_charset: NicknameCharset,
+    // Once this many participants are in the waiting room, the start countdown begins by itself.
+    pub min_players: Option<u32>,
+    // Length of the start countdown, whether begun by `min_players` or by the teacher.
+    pub countdown_secs: u32,
+}
+
+impl Default for LobbySettings {
+    fn default() -> Self {
+        Self {
+            max_participants: None,
+            allow_late_join: true,
+            nickname_min_length: 2,
+            nickname_max_length: 32,
+            nickname_charset: NicknameCharset::Any,
+            min_players: None,
+            countdown_secs: 10,
+        }
+    }
+}
+
+pub const NICKNAME_LENGTH_LIMIT: usize = 64;
+pub const COUNTDOWN_SECS_RANGE: std::ops::RangeInclusive<u32> = 3..=300;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NicknameCharset {
+    #[default]
+    Any,
+    // Letters and digits of any script, spaces, '-' and '_'.
+    Alphanumeric,
+}
+
+impl LobbySettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.max_participants == Some(0) {
+            return Err("maxParticipants must be at least 1".into());
+        }
+        if self.nickname_min_length == 0 || self.nickname_max_length > NICKNAME_LENGTH_LIMIT {
+            return Err(format!("nickname length must be within 1..={}", NICKNAME_LENGTH_LIMIT));
+        }
+        if self.nickname_min_length > self.nickname_max_length {
+            return Err("nicknameMinLength is greater than nicknameMaxLength".into());
+        }
+        if self.min_players == Some(0) {
+            return Err("minPlayers must be at least 1".into());
+        }
+        if self.min_players.zip(self.max_participants).is_some_and(|(min, max)| min > max) {
+            return Err("minPlayers is greater than maxParticipants".into());
+        }
+        if !COUNTDOWN_SECS_RANGE.contains(&self.countdown_secs) {
+            let (min, max) = (COUNTDOWN_SECS_RANGE.start(), COUNTDOWN_SECS_RANGE.end());
+            return Err(format!("countdownSecs must be within {}..={}", min, max));
+        }
+        Ok(())
+    }
+
+    // Why `nickname` (already trimmed) breaks the room's rules, if it does.
+    pub fn nickname_problem(&self, nickname: &str) -> Option<String> {
+        let length = nickname.chars().count();
+        if length < self.nickname_min_length || length > self.nickname_max_length {
+            let (min, max) = (self.nickname_min_length, self.nickname_max_length);
+            return Some(format!("nickname must be {} to {} characters long", min, max));
+        }
+        let allowed = |c: char| c.is_alphanumeric() || matches!(c, ' ' | '-' | '_');
+        if self.nickname_charset == NicknameCharset::Alphanumeric && !nickname.chars().all(allowed) {
+            return Some("nickname may only contain letters, digits, spaces, '-' and '_'".into());
+        }
+        None
+    }
+}
+
+// When a homework session takes answers. It opens at creation unless `opens_at` says later, and the
+// session finishes once `closes_at` has passed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HomeworkWindow {
+    #[serde(default)]
+    pub opens_at: Option<DateTime<Utc>>,
+    pub closes_at: DateTime<Utc>,
+}
+
+impl HomeworkWindow {
+    pub fn validate(&self, now: DateTime<Utc>) -> Result<(), String> {
+        if self.closes_at <= now {
+            return Err("closesAt must be in the future".into());
+        }
+        if self.opens_at.is_some_and(|opens_at| opens_at >= self.closes_at) {
+            return Err("opensAt must be before closesAt".into());
+        }
+        Ok(())
+    }
+
+    pub fn is_open(&self, now: DateTime<Utc>) -> bool {
+        self.opens_at.is_none_or(|opens_at| opens_at <= now) && now < self.closes_at
+    }
+}
+
+// Signals a teacher may want to look at after the game; nothing here changes a score.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct IntegrityStats {
+    // Times the client reported losing focus (a tab or app switch) and how long it stayed away.
+    #[serde(default)]
+    pub focus_lost: u32,
+    #[serde(default)]
+    pub unfocused_ms: i64,
+    // Set while the client is away; closed by the next focus report.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub focus_lost_at: Option<DateTime<Utc>>,
+    // Answers given within FAST_ANSWER_MS of the question's push, and the longest run of them in a row.
+    #[serde(default)]
+    pub fast_answers: u32,
+    #[serde(default)]
+    pub fast_streak: u32,
+    #[serde(default)]
+    pub longest_fast_streak: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fastest_ms: Option<i64>,
+}
+
+// Faster than anyone reads a question.
+pub const FAST_ANSWER_MS: i64 = 1000;
+
+impl IntegrityStats {
+    pub fn record_focus(&mut self, focused: bool, at: DateTime<Utc>) {
+        match (focused, self.focus_lost_at) {
+            (false, None) => {
+                self.focus_lost += 1;
+                self.focus_lost_at = Some(at);
+            }
+            (true, Some(lost_at)) => {
+                self.unfocused_ms += (at - lost_at).num_milliseconds().max(0);
+                self.focus_lost_at = None;
+            }
+            // Repeated reports of the same state.
+            _ => {}
+        }
+    }
+
+    pub fn record_latency(&mut self, latency_ms: i64) {
+        self.fastest_ms = Some(self.fastest_ms.map_or(latency_ms, |fastest| fastest.min(latency_ms)));
+        if latency_ms < FAST_ANSWER_MS {
+            self.fast_answers += 1;
+            self.fast_streak += 1;
+            self.longest_fast_streak = self.longest_fast_streak.max(self.fast_streak);
+        } else {
+            self.fast_streak = 0;
+        }
+    }
+
+    // Time away so far, counting a focus loss still open at `now`.
+    pub fn unfocused_ms(&self, now: DateTime<Utc>) -> i64 {
+        self.unfocused_ms + self.focus_lost_at.map_or(0, |lost_at| (now - lost_at).num_milliseconds().max(0))
+    }
+}
+
+// A participant's socket went away while `question_id` was open.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Disruption {
+    pub quiz_id: i64,
+    pub question_id: String,
+    pub disconnected_at: DateTime<Utc>,
+    // None while the participant is still away.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reconnected_at: Option<DateTime<Utc>>,
+    // How far the question's deadline moved on when they came back, under `disconnect_grace_secs`.
+    #[serde(default)]
+    pub extended_ms: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HostQuestion {
+    // Index into the quiz's questions.
+    pub position: usize,
+    pub question_id: String,
+    pub opened_at: DateTime<Utc>,
+    pub deadline: DateTime<Utc>,
+    // Set once the aggregated results went to the teacher; later answers are late.
+    #[serde(default)]
+    pub closed: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AnswerRecord {
+    pub quiz_id: i64,
+    pub question_id: String,
+    pub answer: SubmittedAnswer,
+    pub correct: bool,
+    // 0.0..=1.0; records written before partial credit read as 0.0.
+    #[serde(default)]
+    pub credit: f64,
+    // credit times the question's points; 0 for late answers.
+    #[serde(default)]
+    pub points: f64,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub late: bool,
+    pub answered_at: DateTime<Utc>,
+    // Milliseconds from the `question_push` of this question; none for answers sent without one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub latency_ms: Option<i64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlaylistSegment {
+    pub quiz_id: i64,
+    pub stats: HashMap<String, StudentStats>,
+    pub mistakes: HashMap<String, Vec<String>>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TeacherSession {
     pub teacher_id: i64,
     pub csrf_token: String,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    #[serde(default)]
+    pub last_seen_at: Option<DateTime<Utc>>,
+    // Only a hash, so the session list can tell devices apart without storing what they are.
+    #[serde(default)]
+    pub user_agent_hash: Option<String>,
+}
+
+// Truncated SHA-256, used where a stable identifier must not reveal the value behind it.
+pub fn short_hash(value: &str) -> String {
+    let digest = HEXLOWER.encode(&Sha256::digest(value.as_bytes()));
+    digest[..16].to_string()
+}
+
+// day (YYYY-MM-DD) -> feature -> count; only aggregate counters, nothing tied to a teacher or student.
+pub type UsageStats = BTreeMap<String, BTreeMap<String, u64>>;
+
+const USAGE_STATS_RETENTION_DAYS: i64 = 365;
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AiUsageDay {
+    pub requests: u64,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+}
+
+impl AiUsageDay {
+    pub fn tokens(&self) -> u64 {
+        self.prompt_tokens + self.completion_tokens
+    }
+}
+
+// teacher id -> day (YYYY-MM-DD, UTC) -> AI calls made on that teacher's behalf.
+pub type AiUsageLog = HashMap<i64, BTreeMap<String, AiUsageDay>>;
+
+const AI_USAGE_RETENTION_DAYS: i64 = 90;
+
+// Daily per-teacher limits on AI calls; `None` means unlimited.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AiQuotas {
+    pub daily_requests: Option<u64>,
+    pub daily_tokens: Option<u64>,
+}
+
+impl AiQuotas {
+    pub fn from_env() -> Self {
+        let env = |name: &str| std::env::var(name).ok().and_then(|v| v.trim().parse::<u64>().ok()).filter(|v| *v > 0);
+        Self { daily_requests: env("AI_DAILY_REQUEST_QUOTA"), daily_tokens: env("AI_DAILY_TOKEN_QUOTA") }
+    }
+
+    // Every limit `usage` has reached; empty while another request is allowed.
+    pub fn exceeded(&self, usage: &AiUsageDay) -> Vec<ValidationIssue> {
+        let mut issues = Vec::new();
+        if let Some(limit) = self.daily_requests.filter(|l| usage.requests >= *l) {
+            issues.push(ValidationIssue {
+                field: "requests".into(),
+                issue: format!("daily quota of {} requests reached ({} used)", limit, usage.requests),
+            });
+        }
+        if let Some(limit) = self.daily_tokens.filter(|l| usage.tokens() >= *l) {
+            issues.push(ValidationIssue {
+                field: "tokens".into(),
+                issue: format!("daily quota of {} tokens reached ({} used)", limit, usage.tokens()),
+            });
+        }
+        issues
+    }
+}
+
+// Room sockets are pinged every `ping_interval`; one that sends nothing, not even a pong, for
+// `idle_timeout` is dropped and its participant marked `disconnected`.
+#[derive(Debug, Clone, Copy)]
+pub struct WsHeartbeat {
+    pub ping_interval: Duration,
+    pub idle_timeout: Duration,
+}
+
+impl WsHeartbeat {
+    pub fn from_env() -> Self {
+        let secs = |name: &str, default: u64| {
+            let value = std::env::var(name).ok().and_then(|v| v.trim().parse::<u64>().ok()).filter(|v| *v > 0);
+            Duration::from_secs(value.unwrap_or(default))
+        };
+        let ping_interval = secs("WS_PING_INTERVAL_SECS", 20);
+        // A timeout shorter than the ping interval would drop quiet but healthy clients.
+        let idle_timeout = secs("WS_IDLE_TIMEOUT_SECS", 60).max(ping_interval * 2);
+        Self { ping_interval, idle_timeout }
+    }
+}
+
+// Seconds before a scheduled start at which the room is reminded of the countdown.
+const START_COUNTDOWN_MARKS_SECS: [i64; 3] = [300, 60, 10];
+
+// Letters and digits without the look-alikes O/0 and I/1, so a code read off the board is typed right.
+pub const ROOM_CODE_ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+pub const ROOM_CODE_LENGTH_RANGE: std::ops::RangeInclusive<usize> = 4..=10;
+// Even with 4 characters a free code is found long before this while the server has few rooms.
+const ROOM_CODE_ATTEMPTS: usize = 16;
+
+pub fn room_code(length: usize) -> String {
+    let mut rng = rand::thread_rng();
+    (0..length).map(|_| char::from(*ROOM_CODE_ALPHABET.choose(&mut rng).expect("alphabet is not empty"))).collect()
+}
+
+const ROOM_CHANNEL_MIN_CAPACITY: usize = 256;
+// Every answer is broadcast as a `stats_update`, so the backlog a slow socket must be able to absorb
+// grows with the class.
+const ROOM_CHANNEL_SLOTS_PER_PARTICIPANT: usize = 8;
+// Room and teacher events kept for dashboards that poll instead of holding a socket.
+pub const ROOM_RECENT_EVENTS: usize = 200;
+
+// Outbound side of a room: one broadcast every socket subscribes to, plus a direct line to each
+// joined participant for events that concern only them.
+pub struct RoomChannels {
+    // The sender and the capacity it was created with.
+    broadcast: std::sync::RwLock<(broadcast::Sender<WsEnvelope>, usize)>,
+    participants: DashMap<String, mpsc::UnboundedSender<WsEnvelope>>,
+    // Sockets of the session owner; dropped once they close.
+    teachers: std::sync::Mutex<Vec<mpsc::UnboundedSender<WsEnvelope>>>,
+    // Broadcast messages skipped by sockets that fell behind.
+    pub lagged: AtomicU64,
+    // Unix millis of the last event sent or participant joining; the room sweeper expires rooms by it.
+    last_activity: AtomicI64,
+    // Unix millis of when the room was opened, for the room code TTL.
+    opened_at: i64,
+    // The last ROOM_RECENT_EVENTS room and teacher events with their cursor, and the next cursor.
+    recent: std::sync::Mutex<(u64, VecDeque<(u64, WsEnvelope)>)>,
+}
+
+impl Default for RoomChannels {
+    fn default() -> Self {
+        let (tx, _) = broadcast::channel(ROOM_CHANNEL_MIN_CAPACITY);
+        Self {
+            broadcast: std::sync::RwLock::new((tx, ROOM_CHANNEL_MIN_CAPACITY)),
+            participants: DashMap::new(),
+            teachers: std::sync::Mutex::new(Vec::new()),
+            lagged: AtomicU64::new(0),
+            last_activity: AtomicI64::new(Utc::now().timestamp_millis()),
+            opened_at: Utc::now().timestamp_millis(),
+            recent: std::sync::Mutex::new((1, VecDeque::new())),
+        }
+    }
+}
+
+impl RoomChannels {
+    pub fn subscribe(&self) -> broadcast::Receiver<WsEnvelope> {
+        self.broadcast.read().expect("room channel lock").0.subscribe()
+    }
+
+    pub fn send(&self, env: WsEnvelope) -> Result<usize, broadcast::error::SendError<WsEnvelope>> {
+        self.touch();
+        self.remember(&env);
+        self.broadcast.read().expect("room channel lock").0.send(env)
+    }
+
+    fn remember(&self, env: &WsEnvelope) {
+        let mut recent = self.recent.lock().expect("room recent events lock");
+        let cursor = recent.0;
+        recent.0 += 1;
+        if recent.1.len() == ROOM_RECENT_EVENTS {
+            recent.1.pop_front();
+        }
+        recent.1.push_back((cursor, env.clone()));
+    }
+
+    // Events after `cursor` (0 for all that are kept), the cursor to poll from next, and whether events
+    // after `cursor` were already dropped.
+    pub fn events_since(&self, cursor: u64) -> (Vec<(u64, WsEnvelope)>, u64, bool) {
+        let recent = self.recent.lock().expect("room recent events lock");
+        let events: Vec<_> = recent.1.iter().filter(|(at, _)| *at > cursor).cloned().collect();
+        let missed = recent.1.front().is_some_and(|(oldest, _)| *oldest > cursor + 1);
+        (events, recent.0 - 1, missed)
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.broadcast.read().expect("room channel lock").1
+    }
+
+    pub fn participant_count(&self) -> usize {
+        self.participants.len()
+    }
+
+    fn touch(&self) {
+        self.last_activity.store(Utc::now().timestamp_millis(), Ordering::Relaxed);
+    }
+
+    pub fn idle_for(&self) -> chrono::Duration {
+        chrono::Duration::milliseconds(Utc::now().timestamp_millis() - self.last_activity.load(Ordering::Relaxed))
+    }
+
+    pub fn age(&self) -> chrono::Duration {
+        chrono::Duration::milliseconds(Utc::now().timestamp_millis() - self.opened_at)
+    }
+
+    // Every open socket of the room subscribes to its broadcast, teachers and not yet joined ones too.
+    pub fn socket_count(&self) -> usize {
+        self.broadcast.read().expect("room channel lock").0.receiver_count()
+    }
+
+    // A resume from another socket takes over the nickname.
+    pub fn attach(&self, nickname: &str, tx: mpsc::UnboundedSender<WsEnvelope>) {
+        self.participants.insert(nickname.to_string(), tx);
+        self.touch();
+        self.grow(self.participants.len());
+    }
+
+    // Broadcast channels cannot be resized, so a larger room gets a new one. Sockets on the old channel
+    // drain it, see it closed and resubscribe.
+    fn grow(&self, participants: usize) {
+        let wanted = (participants * ROOM_CHANNEL_SLOTS_PER_PARTICIPANT).next_power_of_two();
+        let mut channel = self.broadcast.write().expect("room channel lock");
+        if wanted > channel.1 {
+            *channel = (broadcast::channel(wanted).0, wanted);
+            info!("room channel grown to {} slots for {} participants", wanted, participants);
+        }
+    }
+
+    // Only drops the line if it still belongs to `tx`, so a stale socket closing late does not cut off
+    // the one that replaced it. True when it did.
+    pub fn detach(&self, nickname: &str, tx: &mpsc::UnboundedSender<WsEnvelope>) -> bool {
+        self.participants.remove_if(nickname, |_, current| current.same_channel(tx)).is_some()
+    }
+
+    // Cuts the participant's line after delivering `env`; their socket closes once it has sent it.
+    pub fn kick(&self, nickname: &str, env: WsEnvelope) {
+        if let Some((_, tx)) = self.participants.remove(nickname) {
+            let _ = tx.send(env);
+        }
+    }
+
+    // False when the participant has no open socket in the room.
+    pub fn send_to(&self, nickname: &str, env: WsEnvelope) -> bool {
+        self.touch();
+        match self.participants.get(nickname) {
+            Some(tx) => tx.send(env).is_ok(),
+            None => false,
+        }
+    }
+
+    pub fn attach_teacher(&self, tx: mpsc::UnboundedSender<WsEnvelope>) {
+        self.teachers.lock().expect("room teachers lock").push(tx);
+    }
+
+    // False when no teacher socket is open.
+    pub fn send_to_teachers(&self, env: WsEnvelope) -> bool {
+        self.remember(&env);
+        let mut teachers = self.teachers.lock().expect("room teachers lock");
+        teachers.retain(|tx| tx.send(env.clone()).is_ok());
+        !teachers.is_empty()
+    }
 }
 
 pub struct InMemoryDb {
     pub teachers: RwLock<HashMap<i64, Teacher>>,
     pub teachers_by_login: RwLock<HashMap<String, i64>>,
     pub sessions: RwLock<HashMap<String, TeacherSession>>,
+    pub refresh_tokens: RwLock<HashMap<String, RefreshToken>>,
+    // Reset codes live for minutes and are deliberately not persisted.
+    pub password_resets: RwLock<HashMap<String, PasswordResetToken>>,
     pub quizzes: RwLock<HashMap<i64, QuizRecord>>,
     pub game_sessions: RwLock<HashMap<i64, SessionRecord>>,
     pub rooms: RwLock<HashMap<String, i64>>,
-    pub broadcasters: DashMap<String, broadcast::Sender<WsEnvelope>>,
+    pub broadcasters: DashMap<String, RoomChannels>,
+    pub usage_stats: RwLock<UsageStats>,
+    pub quiz_revisions: RwLock<HashMap<i64, Vec<QuizRevision>>>,
+    pub students: RwLock<HashMap<i64, Student>>,
+    // Lookup indexes over `students`, keyed by login and by access code.
+    pub students_by_login: RwLock<HashMap<String, i64>>,
+    pub students_by_code: RwLock<HashMap<String, i64>>,
+    pub api_keys: RwLock<HashMap<String, ApiKey>>,
+    pub prompt_templates: RwLock<PromptTemplates>,
+    pub ai_usage: RwLock<AiUsageLog>,
+    pub classes: RwLock<HashMap<i64, ClassRecord>>,
+    // Append-only, oldest first; ids are increasing.
+    pub audit_log: RwLock<Vec<AuditEntry>>,
     next_teacher_id: AtomicI64,
     next_quiz_id: AtomicI64,
     next_session_id: AtomicI64,
+    next_student_id: AtomicI64,
+    next_class_id: AtomicI64,
+    pub versions: CollectionVersions,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct PersistentSnapshot {
-    teachers: HashMap<i64, Teacher>,
-    teachers_by_login: HashMap<String, i64>,
-    quizzes: HashMap<i64, QuizRecord>,
-    next_teacher_id: i64,
-    next_quiz_id: i64,
-    next_session_id: i64,
+// Change counters behind the ETags of quiz reads: `quizzes` moves on every quiz write, `sessions`
+// when a session is added (library popularity). `epoch` is new per process, so tags issued
+// before a restart never match.
+pub struct CollectionVersions {
+    epoch: u64,
+    quizzes: AtomicU64,
+    sessions: AtomicU64,
 }
 
-impl InMemoryDb {
-    pub fn new(snapshot_path: Option<&str>) -> Self {
-        let snapshot = snapshot_path.and_then(|path| {
-            let raw = fs::read_to_string(path).ok()?;
-            match serde_json::from_str::<PersistentSnapshot>(&raw) {
-                Ok(s) => Some(s),
-                Err(err) => {
-                    warn!("failed to read local snapshot {}: {}", path, err);
-                    None
-                }
-            }
-        });
+impl Default for CollectionVersions {
+    fn default() -> Self {
+        Self { epoch: uuid::Uuid::new_v4().as_u64_pair().0, quizzes: AtomicU64::new(0), sessions: AtomicU64::new(0) }
+    }
+}
+
+impl CollectionVersions {
+    pub fn bump_quizzes(&self) {
+        self.quizzes.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub fn bump_sessions(&self) {
+        self.sessions.fetch_add(1, Ordering::SeqCst);
+    }
+
+    // (epoch, quizzes, sessions); read before the data so a tag is never newer than its body.
+    pub fn current(&self) -> (u64, u64, u64) {
+        (self.epoch, self.quizzes.load(Ordering::SeqCst), self.sessions.load(Ordering::SeqCst))
+    }
+}
 
+fn push_prompt_version(history: &mut Vec<PromptTemplateVersion>, version: PromptTemplateVersion) {
+    if history.last().map(|v| v.version < version.version).unwrap_or(true) {
+        history.push(version);
+    }
+    if history.len() > PROMPT_VERSION_LIMIT {
+        let excess = history.len() - PROMPT_VERSION_LIMIT;
+        history.drain(..excess);
+    }
+}
+
+impl InMemoryDb {
+    pub fn new(snapshot: Option<PersistentSnapshot>) -> Self {
         let teachers = snapshot
             .as_ref()
             .map(|s| s.teachers.clone())
@@ -111,22 +1015,135 @@ impl InMemoryDb {
         let next_quiz_id = snapshot.as_ref().map(|s| s.next_quiz_id).unwrap_or(1).max(
             quizzes.keys().max().copied().unwrap_or(0) + 1,
         );
-        let next_session_id = snapshot.as_ref().map(|s| s.next_session_id).unwrap_or(1).max(1);
+        let finished_sessions = snapshot
+            .as_ref()
+            .map(|s| s.finished_sessions.clone())
+            .unwrap_or_default();
+        let now = Utc::now();
+        let teacher_sessions: HashMap<String, TeacherSession> = snapshot
+            .as_ref()
+            .map(|s| {
+                s.teacher_sessions
+                    .iter()
+                    .filter(|(_, ts)| ts.expires_at > now)
+                    .map(|(sid, ts)| (sid.clone(), ts.clone()))
+                    .collect()
+            })
+            .unwrap_or_default();
+        let refresh_tokens: HashMap<String, RefreshToken> = snapshot
+            .as_ref()
+            .map(|s| {
+                s.refresh_tokens
+                    .iter()
+                    .filter(|(_, t)| t.expires_at > now)
+                    .map(|(token, t)| (token.clone(), t.clone()))
+                    .collect()
+            })
+            .unwrap_or_default();
+        let usage_stats = snapshot
+            .as_ref()
+            .map(|s| s.usage_stats.clone())
+            .unwrap_or_default();
+        let quiz_revisions = snapshot
+            .as_ref()
+            .map(|s| s.quiz_revisions.clone())
+            .unwrap_or_default();
+        let next_session_id = snapshot.as_ref().map(|s| s.next_session_id).unwrap_or(1).max(
+            finished_sessions.keys().max().copied().unwrap_or(0) + 1,
+        );
+        let students = snapshot
+            .as_ref()
+            .map(|s| s.students.clone())
+            .unwrap_or_default();
+        let students_by_login = students
+            .values()
+            .filter_map(|s| s.login.clone().map(|login| (login, s.id)))
+            .collect();
+        let students_by_code = students
+            .values()
+            .filter_map(|s| s.access_code.clone().map(|code| (code, s.id)))
+            .collect();
+        let api_keys = snapshot
+            .as_ref()
+            .map(|s| s.api_keys.clone())
+            .unwrap_or_default();
+        let prompt_templates = snapshot
+            .as_ref()
+            .map(|s| s.prompt_templates.clone())
+            .unwrap_or_default();
+        let ai_usage = snapshot
+            .as_ref()
+            .map(|s| s.ai_usage.clone())
+            .unwrap_or_default();
+        let next_student_id = snapshot.as_ref().map(|s| s.next_student_id).unwrap_or(1).max(
+            students.keys().max().copied().unwrap_or(0) + 1,
+        );
+        let classes = snapshot
+            .as_ref()
+            .map(|s| s.classes.clone())
+            .unwrap_or_default();
+        let next_class_id = snapshot.as_ref().map(|s| s.next_class_id).unwrap_or(1).max(
+            classes.keys().max().copied().unwrap_or(0) + 1,
+        );
+        let audit_log = snapshot
+            .as_ref()
+            .map(|s| s.audit_log.clone())
+            .unwrap_or_default();
 
         Self {
             teachers: RwLock::new(teachers),
             teachers_by_login: RwLock::new(teachers_by_login),
-            sessions: RwLock::new(HashMap::new()),
+            sessions: RwLock::new(teacher_sessions),
+            refresh_tokens: RwLock::new(refresh_tokens),
+            password_resets: RwLock::new(HashMap::new()),
             quizzes: RwLock::new(quizzes),
-            game_sessions: RwLock::new(HashMap::new()),
+            game_sessions: RwLock::new(finished_sessions),
             rooms: RwLock::new(HashMap::new()),
             broadcasters: DashMap::new(),
+            usage_stats: RwLock::new(usage_stats),
+            quiz_revisions: RwLock::new(quiz_revisions),
+            students: RwLock::new(students),
+            students_by_login: RwLock::new(students_by_login),
+            students_by_code: RwLock::new(students_by_code),
+            api_keys: RwLock::new(api_keys),
+            prompt_templates: RwLock::new(prompt_templates),
+            ai_usage: RwLock::new(ai_usage),
+            classes: RwLock::new(classes),
+            audit_log: RwLock::new(audit_log),
             next_teacher_id: AtomicI64::new(next_teacher_id),
             next_quiz_id: AtomicI64::new(next_quiz_id),
             next_session_id: AtomicI64::new(next_session_id),
+            next_student_id: AtomicI64::new(next_student_id),
+            next_class_id: AtomicI64::new(next_class_id),
+            versions: CollectionVersions::default(),
         }
     }
 
+    pub async fn apply_snapshot(&self, snapshot: PersistentSnapshot) {
+        let fresh = Self::new(Some(snapshot));
+        *self.teachers.write().await = fresh.teachers.into_inner();
+        *self.teachers_by_login.write().await = fresh.teachers_by_login.into_inner();
+        *self.sessions.write().await = fresh.sessions.into_inner();
+        *self.refresh_tokens.write().await = fresh.refresh_tokens.into_inner();
+        *self.quizzes.write().await = fresh.quizzes.into_inner();
+        *self.game_sessions.write().await = fresh.game_sessions.into_inner();
+        *self.usage_stats.write().await = fresh.usage_stats.into_inner();
+        self.versions.bump_quizzes();
+        self.versions.bump_sessions();
+        *self.quiz_revisions.write().await = fresh.quiz_revisions.into_inner();
+        self.next_teacher_id.store(fresh.next_teacher_id.into_inner(), Ordering::SeqCst);
+        self.next_quiz_id.store(fresh.next_quiz_id.into_inner(), Ordering::SeqCst);
+        self.next_session_id.store(fresh.next_session_id.into_inner(), Ordering::SeqCst);
+        *self.students.write().await = fresh.students.into_inner();
+        *self.students_by_login.write().await = fresh.students_by_login.into_inner();
+        *self.students_by_code.write().await = fresh.students_by_code.into_inner();
+        *self.api_keys.write().await = fresh.api_keys.into_inner();
+        *self.prompt_templates.write().await = fresh.prompt_templates.into_inner();
+        self.next_student_id.store(fresh.next_student_id.into_inner(), Ordering::SeqCst);
+        *self.classes.write().await = fresh.classes.into_inner();
+        self.next_class_id.store(fresh.next_class_id.into_inner(), Ordering::SeqCst);
+    }
+
     pub fn next_teacher_id(&self) -> i64 {
         self.next_teacher_id.fetch_add(1, Ordering::SeqCst)
     }
@@ -139,11 +1156,157 @@ impl InMemoryDb {
         self.next_session_id.fetch_add(1, Ordering::SeqCst)
     }
 
-    async fn snapshot(&self) -> PersistentSnapshot {
+    pub fn next_student_id(&self) -> i64 {
+        self.next_student_id.fetch_add(1, Ordering::SeqCst)
+    }
+
+    pub fn next_class_id(&self) -> i64 {
+        self.next_class_id.fetch_add(1, Ordering::SeqCst)
+    }
+
+    pub async fn upsert_student(&self, student: Student) {
+        if let Some(login) = &student.login {
+            self.students_by_login.write().await.insert(login.clone(), student.id);
+        }
+        if let Some(code) = &student.access_code {
+            self.students_by_code.write().await.insert(code.clone(), student.id);
+        }
+        self.students.write().await.insert(student.id, student);
+    }
+
+    pub async fn prompt_set(&self) -> PromptSet {
+        PromptSet::active(&*self.prompt_templates.read().await)
+    }
+
+    // Numbers the new version after the latest one; version 0 stands for the built-in text.
+    pub async fn add_prompt_version(&self, use_case: PromptUseCase, text: String, created_by: i64) -> PromptTemplateVersion {
+        let mut templates = self.prompt_templates.write().await;
+        let history = templates.entry(use_case).or_default();
+        let version = PromptTemplateVersion {
+            version: history.last().map_or(1, |v| v.version + 1),
+            text,
+            created_at: Utc::now(),
+            created_by,
+        };
+        push_prompt_version(history, version.clone());
+        version
+    }
+
+    pub async fn apply_journal_op(&self, op: JournalOp) {
+        match op {
+            JournalOp::TeacherUpserted { teacher } => {
+                self.next_teacher_id.fetch_max(teacher.id + 1, Ordering::SeqCst);
+                self.teachers_by_login.write().await.insert(teacher.login.clone(), teacher.id);
+                self.teachers.write().await.insert(teacher.id, teacher);
+            }
+            JournalOp::QuizUpserted { quiz } => {
+                self.next_quiz_id.fetch_max(quiz.id + 1, Ordering::SeqCst);
+                self.quizzes.write().await.insert(quiz.id, quiz);
+                self.versions.bump_quizzes();
+            }
+            JournalOp::QuizDeleted { quiz_id } => {
+                self.quizzes.write().await.remove(&quiz_id);
+                self.quiz_revisions.write().await.remove(&quiz_id);
+                self.versions.bump_quizzes();
+            }
+            JournalOp::QuizRevisionAdded { quiz_id, revision } => {
+                let mut revisions = self.quiz_revisions.write().await;
+                let history = revisions.entry(quiz_id).or_default();
+                if history.last().map(|r| r.rev < revision.rev).unwrap_or(true) {
+                    history.push(revision);
+                }
+                if history.len() > QUIZ_REVISION_LIMIT {
+                    let excess = history.len() - QUIZ_REVISION_LIMIT;
+                    history.drain(..excess);
+                }
+            }
+            JournalOp::SessionFinished { session } => {
+                self.next_session_id.fetch_max(session.id + 1, Ordering::SeqCst);
+                self.game_sessions.write().await.insert(session.id, *session);
+                self.versions.bump_sessions();
+            }
+            JournalOp::TeacherSessionPut { sid, session } => {
+                if session.expires_at > Utc::now() {
+                    self.sessions.write().await.insert(sid, session);
+                }
+            }
+            JournalOp::TeacherSessionRemoved { sid } => {
+                self.sessions.write().await.remove(&sid);
+            }
+            JournalOp::RefreshTokenPut { token, record } => {
+                if record.expires_at > Utc::now() {
+                    self.refresh_tokens.write().await.insert(token, record);
+                }
+            }
+            JournalOp::RefreshTokenRemoved { token } => {
+                self.refresh_tokens.write().await.remove(&token);
+            }
+            JournalOp::StudentUpserted { student } => {
+                self.next_student_id.fetch_max(student.id + 1, Ordering::SeqCst);
+                self.upsert_student(student).await;
+            }
+            JournalOp::ApiKeyUpserted { key } => {
+                self.api_keys.write().await.insert(key.id.clone(), key);
+            }
+            JournalOp::ApiKeyRevoked { id } => {
+                self.api_keys.write().await.remove(&id);
+            }
+            JournalOp::PromptTemplateSaved { use_case, version } => {
+                push_prompt_version(self.prompt_templates.write().await.entry(use_case).or_default(), version);
+            }
+            JournalOp::ClassUpserted { class } => {
+                self.next_class_id.fetch_max(class.id + 1, Ordering::SeqCst);
+                self.classes.write().await.insert(class.id, class);
+            }
+            JournalOp::ClassDeleted { class_id } => {
+                self.classes.write().await.remove(&class_id);
+            }
+            JournalOp::AuditRecorded { entry } => {
+                let mut log = self.audit_log.write().await;
+                if log.last().is_none_or(|last| last.id < entry.id) {
+                    log.push(entry);
+                }
+            }
+            JournalOp::TeacherCredentialsRevoked { teacher_id, keep_sid } => {
+                self.revoke_teacher_credentials(teacher_id, keep_sid.as_deref()).await;
+            }
+        }
+    }
+
+    pub async fn revoke_teacher_credentials(&self, teacher_id: i64, keep_sid: Option<&str>) {
+        self.sessions
+            .write()
+            .await
+            .retain(|sid, s| s.teacher_id != teacher_id || Some(sid.as_str()) == keep_sid);
+        self.refresh_tokens.write().await.retain(|_, t| t.teacher_id != teacher_id);
+    }
+
+    pub async fn snapshot(&self) -> PersistentSnapshot {
         PersistentSnapshot {
             teachers: self.teachers.read().await.clone(),
             teachers_by_login: self.teachers_by_login.read().await.clone(),
             quizzes: self.quizzes.read().await.clone(),
+            teacher_sessions: self.sessions.read().await.clone(),
+            refresh_tokens: self.refresh_tokens.read().await.clone(),
+            finished_sessions: self
+                .game_sessions
+                .read()
+                .await
+                .iter()
+                .filter(|(_, s)| s.status == "finished")
+                .map(|(id, s)| (*id, s.clone()))
+                .collect(),
+            usage_stats: self.usage_stats.read().await.clone(),
+            quiz_revisions: self.quiz_revisions.read().await.clone(),
+            students: self.students.read().await.clone(),
+            api_keys: self.api_keys.read().await.clone(),
+            prompt_templates: self.prompt_templates.read().await.clone(),
+            ai_usage: self.ai_usage.read().await.clone(),
+            next_student_id: self.next_student_id.load(Ordering::SeqCst),
+            classes: self.classes.read().await.clone(),
+            next_class_id: self.next_class_id.load(Ordering::SeqCst),
+            audit_log: self.audit_log.read().await.clone(),
+            journal_seq: 0,
             next_teacher_id: self.next_teacher_id.load(Ordering::SeqCst),
             next_quiz_id: self.next_quiz_id.load(Ordering::SeqCst),
             next_session_id: self.next_session_id.load(Ordering::SeqCst),
@@ -154,44 +1317,146 @@ impl InMemoryDb {
 pub trait AiQuizClient: Send + Sync {
     fn generate_quiz_json(
         &self,
+        system: &SystemPrompt,
         topic: &str,
         grade: Option<&str>,
         question_count: usize,
-    ) -> BoxFuture<'static, anyhow::Result<String>>;
+    ) -> BoxFuture<'static, anyhow::Result<AiCompletion>>;
+
+    // Free-form request that must be answered with a JSON object.
+    fn complete_json(&self, system: &SystemPrompt, user_prompt: &str) -> BoxFuture<'static, anyhow::Result<AiCompletion>>;
+}
+
+#[derive(Clone)]
+pub struct MockAiClient;
+
+impl MockAiClient {
+    // `difficulties` and `types` are mix lines such as "easy=2, hard=1"; questions take them in
+    // order, the rest are single-choice questions without a difficulty.
+    fn quiz_json(topic: &str, question_count: usize, difficulties: &str, types: &str) -> String {
+        let expand = |line: &str| -> Vec<String> {
+            line.split(',')
+                .filter_map(|entry| entry.split_once('='))
+                .flat_map(|(name, n)| std::iter::repeat_n(name.trim().to_string(), n.trim().parse().unwrap_or(0)))
+                .collect()
+        };
+        let (difficulties, types) = (expand(difficulties), expand(types));
+        let mut questions = Vec::new();
+        for idx in 0..question_count.max(1) {
+            let mut question = match types.get(idx).map(String::as_str) {
+                Some("open") => serde_json::json!({"type": "open", "answer": {"text": "Верно"}}),
+                Some("multi") => serde_json::json!({
+                    "type": "multi",
+                    "options": [
+                        {"id": "o1", "text": "Верно"},
+                        {"id": "o2", "text": "Тоже верно"},
+                        {"id": "o3", "text": "Неверно"}
+                    ],
+                    "answer": {"optionIds": ["o1", "o2"]}
+                }),
+                Some("ordering") => serde_json::json!({
+                    "type": "ordering",
+                    "options": [{"id": "o1", "text": "Сначала"}, {"id": "o2", "text": "Потом"}],
+                    "answer": {"order": ["o1", "o2"]}
+                }),
+                Some("numeric") => serde_json::json!({"type": "numeric", "answer": {"value": 42}}),
+                Some("cloze") => serde_json::json!({
+                    "type": "cloze",
+                    "prompt": format!("{}: вопрос {}, ответ — {{{{1}}}}", topic, idx + 1),
+                    "answer": {"blanks": [["верно"]]}
+                }),
+                _ => serde_json::json!({
+                    "type": "single",
+                    "options": [
+                        {"id": "o1", "text": "Верно"},
+                        {"id": "o2", "text": "Неверно"}
+                    ],
+                    "answer": {"optionId": "o1"}
+                }),
+            };
+            question["id"] = format!("q{}", idx + 1).into();
+            if question.get("prompt").is_none() {
+                question["prompt"] = format!("{}: вопрос {}", topic, idx + 1).into();
+            }
+            question["explanation"] = format!("{}: пояснение к вопросу {}", topic, idx + 1).into();
+            if let Some(difficulty) = difficulties.get(idx) {
+                question["difficulty"] = difficulty.as_str().into();
+            }
+            questions.push(question);
+        }
+        let payload = serde_json::json!({
+            "title": format!("Квиз: {}", topic),
+            "description": "Сгенерировано ИИ",
+            "questions": questions
+        });
+        payload.to_string()
+    }
+
+    // Moderation passes everything and hints are a fixed phrase. Quizzes from a document are named
+    // after the first line of the material. For distractors the mock, like a careless model, repeats
+    // the correct answer and a duplicate, which the caller has to filter out.
+    fn respond(use_case: PromptUseCase, user_prompt: &str) -> String {
+        let line_after = |prefix: &str| user_prompt.lines().find_map(|l| l.strip_prefix(prefix)).map(str::to_string);
+        match use_case {
+            PromptUseCase::Moderation => serde_json::json!({ "flagged": [] }).to_string(),
+            PromptUseCase::Hints => {
+                serde_json::json!({ "hint": "Перечитай вопрос и вспомни правило по этой теме." }).to_string()
+            }
+            PromptUseCase::Generation => {
+                let count = user_prompt
+                    .split_once("Количество вопросов: ")
+                    .and_then(|(_, rest)| rest.split(|c: char| !c.is_ascii_digit()).next()?.parse().ok())
+                    .unwrap_or(1);
+                let topic = match user_prompt.split_once("Материал:\n") {
+                    Some((_, material)) => material.lines().next().unwrap_or("Материал"),
+                    None => user_prompt
+                        .strip_prefix("Тема: ")
+                        .and_then(|rest| rest.split_once(". Класс:"))
+                        .map_or("Материал", |(topic, _)| topic),
+                };
+                let difficulties = line_after("Сложность вопросов: ").unwrap_or_default();
+                let types = line_after("Типы вопросов: ").unwrap_or_default();
+                Self::quiz_json(&topic.chars().take(60).collect::<String>(), count, &difficulties, &types)
+            }
+            PromptUseCase::Distractors => {
+                let correct = line_after("Правильный ответ: ")
+                    .and_then(|l| l.split(" | ").next().map(str::to_string))
+                    .unwrap_or_default();
+                serde_json::json!({
+                    "distractors": [correct, "Неверный вариант", "неверный  вариант", "Похожий ответ", "Частая ошибка", "Другой ответ"]
+                })
+                .to_string()
+            }
+        }
+    }
 }
 
-#[derive(Clone)]
-pub struct MockAiClient;
-
+// Token counts are estimated, so usage accounting and quotas work offline too.
 impl AiQuizClient for MockAiClient {
     fn generate_quiz_json(
         &self,
+        _system: &SystemPrompt,
         topic: &str,
         _grade: Option<&str>,
         question_count: usize,
-    ) -> BoxFuture<'static, anyhow::Result<String>> {
-        let topic = topic.to_string();
-        Box::pin(async move {
-            let mut questions = Vec::new();
-            for idx in 0..question_count.max(1) {
-                questions.push(serde_json::json!({
-                    "id": format!("q{}", idx + 1),
-                    "type": "single",
-                    "prompt": format!("{}: вопрос {}", topic, idx + 1),
-                    "options": [
-                        {"id": "o1", "text": "Верно"},
-                        {"id": "o2", "text": "Неверно"}
-                    ],
-                    "answer": {"optionId": "o1"}
-                }));
+    ) -> BoxFuture<'static, anyhow::Result<AiCompletion>> {
+        let mut quiz = Self::quiz_json(topic, question_count, "", "");
+        // A "черновик" topic comes back without answers, like a careless model; asked to repair it
+        // (through `complete_json`) the mock returns the full quiz.
+        if topic.contains("черновик") {
+            let mut value: serde_json::Value = serde_json::from_str(&quiz).unwrap_or_default();
+            for question in value["questions"].as_array_mut().into_iter().flatten().filter_map(|q| q.as_object_mut()) {
+                question.remove("answer");
             }
-            let payload = serde_json::json!({
-                "title": format!("Квиз: {}", topic),
-                "description": "Сгенерировано ИИ",
-                "questions": questions
-            });
-            Ok(payload.to_string())
-        })
+            quiz = value.to_string();
+        }
+        let completion = AiCompletion::estimated(topic, quiz);
+        Box::pin(async move { Ok(completion) })
+    }
+
+    fn complete_json(&self, system: &SystemPrompt, user_prompt: &str) -> BoxFuture<'static, anyhow::Result<AiCompletion>> {
+        let completion = AiCompletion::estimated(user_prompt, Self::respond(system.use_case, user_prompt));
+        Box::pin(async move { Ok(completion) })
     }
 }
 
@@ -206,7 +1471,6 @@ pub struct GigaChatAiClient {
     pub scope: String,
     pub model: String,
     pub timeout_secs: u64,
-    pub system_prompt_path: String,
 }
 
 impl GigaChatAiClient {
@@ -231,6 +1495,15 @@ impl GigaChatAiClient {
         if bearer.is_none() && credentials.is_none() {
             return None;
         }
+        Some(Self::configured(bearer, credentials))
+    }
+
+    // A teacher's own authorization key; everything else comes from the server configuration.
+    pub fn with_credentials(credentials: String) -> Self {
+        Self::configured(None, Some(credentials))
+    }
+
+    fn configured(bearer: Option<String>, credentials: Option<String>) -> Self {
         let mut base_url = std::env::var("GIGACHAT_BASE_URL")
             .unwrap_or_else(|_| "https://gigachat.devices.sberbank.ru".to_string());
         if !base_url.contains("/api/v1") {
@@ -246,9 +1519,8 @@ impl GigaChatAiClient {
             .unwrap_or(30);
         let python_bin = std::env::var("PYTHON_BIN").unwrap_or_else(|_| "python3".to_string());
         let script_path = format!("{}/scripts/gigachat_generate.py", env!("CARGO_MANIFEST_DIR"));
-        let system_prompt_path = format!("{}/../docs/gigachat_system_prompt.txt", env!("CARGO_MANIFEST_DIR"));
 
-        Some(Self {
+        Self {
             python_bin,
             script_path,
             base_url,
@@ -258,18 +1530,13 @@ impl GigaChatAiClient {
             scope,
             model,
             timeout_secs,
-            system_prompt_path,
-        })
+        }
     }
 }
 
-impl AiQuizClient for GigaChatAiClient {
-    fn generate_quiz_json(
-        &self,
-        topic: &str,
-        grade: Option<&str>,
-        question_count: usize,
-    ) -> BoxFuture<'static, anyhow::Result<String>> {
+impl GigaChatAiClient {
+    // Runs the SDK script; `prompt_args` either describe the quiz or carry a ready user prompt.
+    fn run_script(&self, system_prompt: String, prompt_args: Vec<String>) -> BoxFuture<'static, anyhow::Result<AiCompletion>> {
         let python_bin = self.python_bin.clone();
         let script_path = self.script_path.clone();
         let base_url = self.base_url.clone();
@@ -278,21 +1545,12 @@ impl AiQuizClient for GigaChatAiClient {
         let auth_url = self.auth_url.clone();
         let scope = self.scope.clone();
         let model = self.model.clone();
-        let system_prompt_path = self.system_prompt_path.clone();
         let timeout_secs = self.timeout_secs;
-        let grade_text = grade.unwrap_or("не указан").to_string();
-        let topic_text = topic.to_string();
-        let count = question_count.max(1);
 
         Box::pin(async move {
             let mut cmd = Command::new(&python_bin);
             cmd.arg(&script_path)
-                .arg("--topic")
-                .arg(&topic_text)
-                .arg("--grade")
-                .arg(&grade_text)
-                .arg("--count")
-                .arg(count.to_string())
+                .args(&prompt_args)
                 .arg("--model")
                 .arg(&model)
                 .arg("--base-url")
@@ -303,8 +1561,8 @@ impl AiQuizClient for GigaChatAiClient {
                 .arg(&scope)
                 .arg("--timeout")
                 .arg(timeout_secs.to_string())
-                .arg("--system-prompt-file")
-                .arg(&system_prompt_path);
+                .arg("--system-prompt")
+                .arg(&system_prompt);
 
             if let Some(credentials) = credentials {
                 cmd.arg("--credentials").arg(credentials);
@@ -321,48 +1579,346 @@ impl AiQuizClient for GigaChatAiClient {
             }
 
             let stdout = String::from_utf8_lossy(&output.stdout).to_string();
-            let trimmed = stdout.trim();
-            let cleaned = if trimmed.starts_with("```") {
-                trimmed
-                    .trim_start_matches("```json")
-                    .trim_start_matches("```")
-                    .trim_end_matches("```")
-                    .trim()
-                    .to_string()
-            } else {
-                trimmed.to_string()
-            };
+            let cleaned = crate::ai_providers::strip_code_fence(&stdout);
             if cleaned.is_empty() {
                 anyhow::bail!("gigachat returned empty content");
             }
-            Ok(cleaned)
+            // The script reports token counts on stderr as `usage <prompt> <completion>`.
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let reported = stderr.lines().find_map(|line| {
+                let mut parts = line.strip_prefix("usage ")?.split_whitespace().map(str::parse::<u64>);
+                Some(TokenUsage { prompt_tokens: parts.next()?.ok()?, completion_tokens: parts.next()?.ok()? })
+            });
+            let usage = reported.unwrap_or_else(|| {
+                TokenUsage::estimate(&format!("{}{}", system_prompt, prompt_args.join(" ")), &cleaned)
+            });
+            Ok(AiCompletion { text: cleaned, usage })
+        })
+    }
+}
+
+impl AiQuizClient for GigaChatAiClient {
+    fn generate_quiz_json(
+        &self,
+        system: &SystemPrompt,
+        topic: &str,
+        grade: Option<&str>,
+        question_count: usize,
+    ) -> BoxFuture<'static, anyhow::Result<AiCompletion>> {
+        let prompt_args = vec![
+            "--topic".to_string(),
+            topic.to_string(),
+            "--grade".to_string(),
+            grade.unwrap_or("не указан").to_string(),
+            "--count".to_string(),
+            question_count.max(1).to_string(),
+        ];
+        self.run_script(system.text.clone(), prompt_args)
+    }
+
+    fn complete_json(&self, system: &SystemPrompt, user_prompt: &str) -> BoxFuture<'static, anyhow::Result<AiCompletion>> {
+        self.run_script(system.text.clone(), vec!["--user-prompt".to_string(), user_prompt.to_string()])
+    }
+}
+
+// Wraps a provider client so every call is counted against the teacher it is made for. Failed calls
+// count as requests too, since the provider may have spent tokens on them.
+pub struct MeteredAiClient {
+    inner: Arc<dyn AiQuizClient>,
+    teacher_id: i64,
+    state: AppState,
+}
+
+impl MeteredAiClient {
+    fn meter(
+        &self,
+        call: BoxFuture<'static, anyhow::Result<AiCompletion>>,
+    ) -> BoxFuture<'static, anyhow::Result<AiCompletion>> {
+        let state = self.state.clone();
+        let teacher_id = self.teacher_id;
+        Box::pin(async move {
+            let result = call.await;
+            let usage = result.as_ref().map(|c| c.usage).unwrap_or_default();
+            state.record_ai_usage(teacher_id, usage).await;
+            result
         })
     }
 }
 
+impl AiQuizClient for MeteredAiClient {
+    fn generate_quiz_json(
+        &self,
+        system: &SystemPrompt,
+        topic: &str,
+        grade: Option<&str>,
+        question_count: usize,
+    ) -> BoxFuture<'static, anyhow::Result<AiCompletion>> {
+        self.meter(self.inner.generate_quiz_json(system, topic, grade, question_count))
+    }
+
+    fn complete_json(&self, system: &SystemPrompt, user_prompt: &str) -> BoxFuture<'static, anyhow::Result<AiCompletion>> {
+        self.meter(self.inner.complete_json(system, user_prompt))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AiJobStatus {
+    Queued,
+    Running,
+    Succeeded,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AiJob {
+    pub id: String,
+    #[serde(skip)]
+    pub teacher_id: i64,
+    pub status: AiJobStatus,
+    pub provider: &'static str,
+    pub created_at: DateTime<Utc>,
+    pub started_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub quiz_id: Option<i64>,
+    pub error: Option<AiFailure>,
+}
+
+struct AiJobTask {
+    id: String,
+    client: Arc<dyn AiQuizClient>,
+    request: AiRequest,
+}
+
+// Background AI generation. Jobs wait in a bounded queue and at most `AI_JOB_CONCURRENCY` of them
+// talk to providers at once; finished jobs are kept in memory for `AI_JOB_TTL_MINUTES`.
+pub struct AiJobs {
+    jobs: DashMap<String, AiJob>,
+    sender: mpsc::Sender<AiJobTask>,
+    receiver: std::sync::Mutex<Option<mpsc::Receiver<AiJobTask>>>,
+    concurrency: usize,
+    ttl: chrono::Duration,
+}
+
+impl AiJobs {
+    pub fn from_env() -> Self {
+        let env = |name: &str, default: usize| {
+            std::env::var(name).ok().and_then(|v| v.parse::<usize>().ok()).filter(|v| *v > 0).unwrap_or(default)
+        };
+        let (sender, receiver) = mpsc::channel(env("AI_JOB_QUEUE_SIZE", 100));
+        Self {
+            jobs: DashMap::new(),
+            sender,
+            receiver: std::sync::Mutex::new(Some(receiver)),
+            concurrency: env("AI_JOB_CONCURRENCY", 2),
+            ttl: chrono::Duration::minutes(env("AI_JOB_TTL_MINUTES", 60) as i64),
+        }
+    }
+
+    // None when the queue is full.
+    pub fn enqueue(
+        &self,
+        teacher_id: i64,
+        client: Arc<dyn AiQuizClient>,
+        provider: &'static str,
+        request: AiRequest,
+    ) -> Option<AiJob> {
+        let now = Utc::now();
+        self.jobs.retain(|_, job| job.finished_at.map(|t| now - t < self.ttl).unwrap_or(true));
+        let job = AiJob {
+            id: uuid::Uuid::new_v4().to_string(),
+            teacher_id,
+            status: AiJobStatus::Queued,
+            provider,
+            created_at: now,
+            started_at: None,
+            finished_at: None,
+            quiz_id: None,
+            error: None,
+        };
+        self.sender.try_send(AiJobTask { id: job.id.clone(), client, request }).ok()?;
+        self.jobs.insert(job.id.clone(), job.clone());
+        Some(job)
+    }
+
+    pub fn get(&self, id: &str) -> Option<AiJob> {
+        self.jobs.get(id).map(|job| job.clone())
+    }
+
+    fn update(&self, id: &str, change: impl FnOnce(&mut AiJob)) {
+        if let Some(mut job) = self.jobs.get_mut(id) {
+            change(&mut job);
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct AppState {
     pub db: Arc<InMemoryDb>,
-    pub ai_client: Arc<dyn AiQuizClient>,
-    pub quiz_schema: Arc<serde_json::Value>,
-    pub local_state_path: Option<String>,
+    pub ai: AiRegistry,
+    pub quiz_schemas: QuizSchemas,
+    pub store: Arc<dyn SnapshotStore>,
+    pub shared: Arc<dyn SharedStore>,
+    pub persist_notify: Arc<Notify>,
+    pub persist_debounce: Duration,
+    pub persist_failure: Arc<std::sync::Mutex<Option<String>>>,
+    pub journal: Option<Arc<Journal>>,
+    pub session_ttl: chrono::Duration,
+    pub usage_stats_enabled: bool,
+    pub admin_logins: Arc<HashSet<String>>,
+    pub jwt: JwtConfig,
+    pub admission: Arc<Admission>,
+    pub metrics: Arc<Metrics>,
+    pub reset_delivery: Arc<dyn ResetDelivery>,
+    pub email: Arc<Mailer>,
+    pub password_reset_ttl: chrono::Duration,
+    pub totp_issuer: String,
+    pub passwords: PasswordHashing,
+    pub rate_limits: Arc<RateLimits>,
+    pub web: Arc<WebConfig>,
+    pub quiz_limits: QuizLimits,
+    pub ai_jobs: Arc<AiJobs>,
+    pub moderation: Arc<Moderation>,
+    pub profanity: Arc<ProfanityFilter>,
+    pub ai_quotas: AiQuotas,
+    pub ws_heartbeat: WsHeartbeat,
+    // Rooms without any activity for this long are closed by the room sweeper.
+    pub room_idle_ttl: chrono::Duration,
+    // Room codes are this many characters of ROOM_CODE_ALPHABET, and rooms older than the TTL are
+    // closed by the room sweeper whatever happens in them.
+    pub room_code_length: usize,
+    pub room_code_ttl: chrono::Duration,
+    pub game_modes: Arc<GameModes>,
+    pub lti: Arc<LtiTool>,
+    pub media: Arc<Media>,
 }
 
 impl AppState {
-    pub fn new(ai_client: Arc<dyn AiQuizClient>, quiz_schema: serde_json::Value) -> Self {
-        let local_state_path = std::env::var("LOCAL_STATE_PATH")
-            .ok()
-            .filter(|v| !v.trim().is_empty())
-            .or_else(|| Some(format!("{}/local_state.json", env!("CARGO_MANIFEST_DIR"))));
-        Self {
-            db: Arc::new(InMemoryDb::new(local_state_path.as_deref())),
-            ai_client,
-            quiz_schema: Arc::new(quiz_schema),
-            local_state_path,
+    pub fn new(ai: AiRegistry, quiz_schemas: QuizSchemas) -> anyhow::Result<Self> {
+        let db = Arc::new(InMemoryDb::new(None));
+        crate::metrics::install_prometheus();
+        let email = Arc::new(Mailer::from_env()?);
+        Ok(Self {
+            shared: Arc::new(MemorySharedStore::new(db.clone())),
+            db,
+            ai,
+            quiz_schemas,
+            store: storage::store_from_env()?,
+            persist_notify: Arc::new(Notify::new()),
+            persist_failure: Arc::new(std::sync::Mutex::new(None)),
+            journal: Journal::from_env().map(Arc::new),
+            persist_debounce: Duration::from_millis(
+                std::env::var("PERSIST_DEBOUNCE_MS")
+                    .ok()
+                    .and_then(|v| v.parse::<u64>().ok())
+                    .unwrap_or(1000),
+            ),
+            session_ttl: chrono::Duration::minutes(
+                std::env::var("TEACHER_SESSION_TTL_MINUTES")
+                    .ok()
+                    .and_then(|v| v.parse::<i64>().ok())
+                    .filter(|v| *v > 0)
+                    .unwrap_or(12 * 60),
+            ),
+            usage_stats_enabled: std::env::var("USAGE_STATS_ENABLED")
+                .map(|v| matches!(v.trim().to_lowercase().as_str(), "1" | "true" | "yes"))
+                .unwrap_or(false),
+            admin_logins: Arc::new(
+                std::env::var("ADMIN_LOGINS")
+                    .unwrap_or_default()
+                    .split(',')
+                    .map(|v| v.trim().to_string())
+                    .filter(|v| !v.is_empty())
+                    .collect(),
+            ),
+            jwt: JwtConfig::from_env(),
+            admission: Arc::new(Admission::from_env()),
+            metrics: Arc::new(Metrics::default()),
+            reset_delivery: password_reset::delivery_from_env(email.clone()),
+            email,
+            password_reset_ttl: chrono::Duration::minutes(
+                std::env::var("PASSWORD_RESET_TTL_MINUTES")
+                    .ok()
+                    .and_then(|v| v.parse::<i64>().ok())
+                    .filter(|v| *v > 0)
+                    .unwrap_or(30),
+            ),
+            totp_issuer: std::env::var("TOTP_ISSUER")
+                .ok()
+                .filter(|v| !v.trim().is_empty())
+                .unwrap_or_else(|| "HSE Quiz".to_string()),
+            passwords: PasswordHashing::from_env()?,
+            rate_limits: Arc::new(RateLimits::from_env()?),
+            web: Arc::new(WebConfig::from_env()?),
+            quiz_limits: QuizLimits::from_env(),
+            ai_jobs: Arc::new(AiJobs::from_env()),
+            moderation: Arc::new(Moderation::from_env()?),
+            profanity: Arc::new(ProfanityFilter::from_env()?),
+            ai_quotas: AiQuotas::from_env(),
+            ws_heartbeat: WsHeartbeat::from_env(),
+            room_idle_ttl: chrono::Duration::minutes(
+                std::env::var("ROOM_IDLE_TTL_MINUTES")
+                    .ok()
+                    .and_then(|v| v.parse::<i64>().ok())
+                    .filter(|v| *v > 0)
+                    .unwrap_or(120),
+            ),
+            room_code_length: std::env::var("ROOM_CODE_LENGTH")
+                .ok()
+                .and_then(|v| v.parse::<usize>().ok())
+                .filter(|v| ROOM_CODE_LENGTH_RANGE.contains(v))
+                .unwrap_or(6),
+            room_code_ttl: chrono::Duration::hours(
+                std::env::var("ROOM_CODE_TTL_HOURS")
+                    .ok()
+                    .and_then(|v| v.parse::<i64>().ok())
+                    .filter(|v| *v > 0)
+                    .unwrap_or(24),
+            ),
+            game_modes: Arc::new(GameModes::builtin()),
+            lti: Arc::new(LtiTool::from_env()?),
+            media: Arc::new(Media::from_env()?),
+        })
+    }
+
+    pub async fn restore(&mut self) -> anyhow::Result<()> {
+        let mut snapshot_seq = 0;
+        match self.store.load().await {
+            Ok(Some(snapshot)) => {
+                snapshot_seq = snapshot.journal_seq;
+                self.db.apply_snapshot(snapshot).await
+            }
+            Ok(None) => {}
+            Err(StorageError::Corrupt(reason)) => {
+                warn!("ignoring unreadable {} snapshot, starting empty: {}", self.store.kind(), reason)
+            }
+            Err(err) => return Err(err.into()),
+        }
+        if let Some(journal) = &self.journal {
+            journal.advance_to(snapshot_seq);
+            let pending: Vec<_> = journal.read_entries().await?.into_iter().filter(|e| e.seq > snapshot_seq).collect();
+            if !pending.is_empty() {
+                info!("replaying {} journal entries from {}", pending.len(), journal.path());
+                for entry in pending {
+                    self.db.apply_journal_op(entry.op).await;
+                }
+                self.schedule_persist();
+            }
+        }
+        if let Some(url) = std::env::var("REDIS_URL").ok().filter(|v| !v.trim().is_empty()) {
+            let prefix = std::env::var("REDIS_KEY_PREFIX").unwrap_or_else(|_| "quiz:".to_string());
+            self.shared = Arc::new(RedisSharedStore::connect(&url, &prefix).await?);
         }
+        self.spawn_persistence_task();
+        self.spawn_session_sweeper();
+        self.spawn_room_sweeper();
+        self.spawn_ai_job_worker();
+        self.email.spawn_worker();
+        Ok(())
     }
 
-    pub async fn create_quiz(&self, teacher_id: i64, quiz: Quiz, source_quiz_id: Option<i64>) -> i64 {
+    pub async fn create_quiz(&self, teacher_id: i64, quiz: Quiz, source_quiz_id: Option<i64>) -> StorageResult<i64> {
         let id = self.db.next_quiz_id();
         let record = QuizRecord {
             id,
@@ -372,24 +1928,709 @@ impl AppState {
             questions: quiz.questions,
             is_published: false,
             source_quiz_id,
+            difficulty: quiz.difficulty,
+            tags: quiz.tags,
+            settings: quiz.settings,
+            subject: quiz.subject,
+            grade: quiz.grade,
+            language: quiz.language,
+            created_at: Some(Utc::now()),
+        };
+        self.db.quizzes.write().await.insert(id, record.clone());
+        self.db.versions.bump_quizzes();
+        self.log_change(JournalOp::QuizUpserted { quiz: record }).await;
+        self.record_quiz_revision(id, teacher_id, "created", None).await;
+        self.commit_changes()?;
+        Ok(id)
+    }
+
+    // Snapshots the current quiz content as a new immutable revision; no-op edits are not recorded.
+    pub async fn record_quiz_revision(
+        &self,
+        quiz_id: i64,
+        author_teacher_id: i64,
+        reason: &str,
+        restored_from: Option<u32>,
+    ) -> Option<u32> {
+        let quiz = self.db.quizzes.read().await.get(&quiz_id).cloned()?;
+        let revision = {
+            let mut revisions = self.db.quiz_revisions.write().await;
+            let history = revisions.entry(quiz_id).or_default();
+            let diff = QuestionDiff::between(
+                history.last().map(|r| r.questions.as_slice()).unwrap_or_default(),
+                &quiz.questions,
+            );
+            let metadata = quiz.revision_metadata();
+            if let Some(last) = history.last() {
+                let unchanged = diff.is_empty()
+                    && last.title == quiz.title
+                    && last.description == quiz.description
+                    && last.metadata.as_ref() == Some(&metadata);
+                if unchanged {
+                    return Some(last.rev);
+                }
+            }
+            let revision = QuizRevision {
+                rev: history.last().map(|r| r.rev + 1).unwrap_or(1),
+                author_teacher_id,
+                created_at: Utc::now(),
+                reason: reason.to_string(),
+                restored_from,
+                title: quiz.title,
+                description: quiz.description,
+                questions: quiz.questions,
+                metadata: Some(metadata),
+                diff,
+            };
+            history.push(revision.clone());
+            if history.len() > QUIZ_REVISION_LIMIT {
+                let excess = history.len() - QUIZ_REVISION_LIMIT;
+                history.drain(..excess);
+            }
+            revision
+        };
+        let rev = revision.rev;
+        self.log_change(JournalOp::QuizRevisionAdded { quiz_id, revision }).await;
+        Some(rev)
+    }
+
+    // Appends to the audit trail; the request id comes from the request being handled.
+    pub async fn audit(&self, actor_id: Option<i64>, action: &str, target: Option<String>) {
+        let actor = match actor_id {
+            Some(id) => self.db.teachers.read().await.get(&id).map(|t| t.login.clone()).unwrap_or_default(),
+            None => String::new(),
+        };
+        self.audit_as(actor, actor_id, action, target).await;
+    }
+
+    pub async fn audit_as(&self, actor: String, actor_id: Option<i64>, action: &str, target: Option<String>) {
+        let entry = {
+            let mut log = self.db.audit_log.write().await;
+            let entry = AuditEntry {
+                id: log.last().map(|e| e.id + 1).unwrap_or(1),
+                at: Utc::now(),
+                actor,
+                actor_id,
+                action: action.to_string(),
+                target,
+                request_id: crate::error::current_request_id().unwrap_or_default(),
+            };
+            log.push(entry.clone());
+            entry
+        };
+        info!(action = %entry.action, actor = %entry.actor, target = ?entry.target, "audit");
+        self.log_change(JournalOp::AuditRecorded { entry }).await;
+        self.schedule_persist();
+    }
+
+    pub async fn log_quiz(&self, quiz_id: i64) {
+        let quiz = self.db.quizzes.read().await.get(&quiz_id).cloned();
+        if let Some(quiz) = quiz {
+            self.log_change(JournalOp::QuizUpserted { quiz }).await;
+        }
+    }
+
+    async fn log_teacher_session(&self, sid: &str, session: &TeacherSession) {
+        // Redis keeps its own copy of teacher sessions.
+        if self.shared.kind() == "memory" {
+            self.log_change(JournalOp::TeacherSessionPut { sid: sid.to_string(), session: session.clone() }).await;
+        }
+    }
+
+    pub async fn record_usage(&self, feature: &str) {
+        if !self.usage_stats_enabled {
+            return;
+        }
+        let today = Utc::now().date_naive();
+        let cutoff = (today - chrono::Duration::days(USAGE_STATS_RETENTION_DAYS)).to_string();
+        {
+            let mut stats = self.db.usage_stats.write().await;
+            *stats.entry(today.to_string()).or_default().entry(feature.to_string()).or_default() += 1;
+            stats.retain(|day, _| *day >= cutoff);
+        }
+        self.schedule_persist();
+    }
+
+    pub async fn record_ai_usage(&self, teacher_id: i64, usage: TokenUsage) {
+        let today = Utc::now().date_naive();
+        let cutoff = (today - chrono::Duration::days(AI_USAGE_RETENTION_DAYS)).to_string();
+        {
+            let mut log = self.db.ai_usage.write().await;
+            let days = log.entry(teacher_id).or_default();
+            let day = days.entry(today.to_string()).or_default();
+            day.requests += 1;
+            day.prompt_tokens += usage.prompt_tokens;
+            day.completion_tokens += usage.completion_tokens;
+            days.retain(|day, _| *day >= cutoff);
+        }
+        self.schedule_persist();
+    }
+
+    pub async fn ai_usage_today(&self, teacher_id: i64) -> AiUsageDay {
+        let today = Utc::now().date_naive().to_string();
+        self.db
+            .ai_usage
+            .read()
+            .await
+            .get(&teacher_id)
+            .and_then(|days| days.get(&today).copied())
+            .unwrap_or_default()
+    }
+
+    // The provider client for a teacher, metered; quota issues instead once today's quota is used up.
+    pub async fn metered_ai_client(
+        &self,
+        client: Arc<dyn AiQuizClient>,
+        teacher_id: i64,
+    ) -> Result<Arc<dyn AiQuizClient>, Vec<ValidationIssue>> {
+        let issues = self.ai_quotas.exceeded(&self.ai_usage_today(teacher_id).await);
+        if !issues.is_empty() {
+            return Err(issues);
+        }
+        Ok(Arc::new(MeteredAiClient { inner: client, teacher_id, state: self.clone() }))
+    }
+
+    pub async fn is_admin(&self, teacher_id: i64) -> bool {
+        self.db
+            .teachers
+            .read()
+            .await
+            .get(&teacher_id)
+            .map(|t| self.admin_logins.contains(&t.login))
+            .unwrap_or(false)
+    }
+
+    // Sends the final scores of the session's LMS-launched students to their gradebooks in the background
+    // and records on the session how it went. Students who launched but never played get nothing.
+    pub fn send_lti_scores(&self, session_id: i64) {
+        let state = self.clone();
+        tokio::spawn(async move {
+            let Ok(session) = state.game_session(session_id).await else { return };
+            if session.lti_grades.is_empty() {
+                return;
+            }
+            let mut played: Vec<i64> = session.completed_segments.iter().map(|s| s.quiz_id).collect();
+            played.push(session.quiz_id);
+            let maximum: f64 = {
+                let quizzes = state.db.quizzes.read().await;
+                let played = played.iter().filter_map(|id| quizzes.get(id));
+                played.flat_map(|q| q.questions.iter()).map(|q| q.points()).sum()
+            };
+            let scores: Vec<(i64, LtiScore)> = session
+                .lti_grades
+                .iter()
+                .filter_map(|(student_id, target)| {
+                    let player = session.participants.values().find(|p| p.student_id == Some(*student_id))?;
+                    let stats = session.completed_segments.iter().map(|s| &s.stats).chain([&session.stats]);
+                    let given = stats.filter_map(|stats| stats.get(&player.nickname)).map(|s| s.score).sum();
+                    let score = LtiScore {
+                        issuer: target.issuer.clone(),
+                        client_id: target.client_id.clone(),
+                        user_id: target.user_id.clone(),
+                        lineitem: target.lineitem.clone(),
+                        score_given: given,
+                        score_maximum: maximum,
+                    };
+                    Some((*student_id, score))
+                })
+                .collect();
+            let to_send: Vec<LtiScore> = scores.iter().map(|(_, score)| score.clone()).collect();
+            let outcomes = state.lti.send_scores(&to_send).await;
+            {
+                let mut sessions = state.db.game_sessions.write().await;
+                let Some(session) = sessions.get_mut(&session_id) else { return };
+                for ((student_id, score), outcome) in scores.iter().zip(outcomes) {
+                    if let Some(target) = session.lti_grades.get_mut(student_id) {
+                        target.score_given = Some(score.score_given);
+                        target.sent_at = outcome.is_ok().then(Utc::now);
+                        target.error = outcome.err();
+                    }
+                }
+            }
+            state.schedule_persist();
+        });
+    }
+
+    // Email address and language of a teacher who set an address.
+    async fn teacher_contact(&self, teacher_id: i64) -> Option<(String, Option<String>)> {
+        let teachers = self.db.teachers.read().await;
+        let teacher = teachers.get(&teacher_id)?;
+        Some((teacher.email.clone()?, teacher.locale.clone()))
+    }
+
+    // The profanity filter corrections of the teacher running `session_id`.
+    pub async fn session_word_overrides(&self, session_id: i64) -> WordOverrides {
+        let teacher_id = match self.db.game_sessions.read().await.get(&session_id) {
+            Some(session) => session.teacher_id,
+            None => return WordOverrides::default(),
         };
-        self.db.quizzes.write().await.insert(id, record);
-        if let Err(err) = self.persist_core_data().await {
-            warn!("failed to persist local state after create_quiz: {}", err);
+        let teachers = self.db.teachers.read().await;
+        teachers.get(&teacher_id).map(|t| t.word_overrides.clone()).unwrap_or_default()
+    }
+
+    // Emails the teacher `reminder_lead` before a start they scheduled. Nothing is sent when the start is
+    // already closer than that, or when it was moved or the session began or ended in the meantime.
+    pub fn remind_scheduled_start(&self, session_id: i64, starts_at: DateTime<Utc>) {
+        let lead = self.email.settings.reminder_lead;
+        let remind_at = starts_at - lead;
+        if lead <= chrono::Duration::zero() || remind_at <= Utc::now() {
+            return;
+        }
+        let state = self.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep((remind_at - Utc::now()).to_std().unwrap_or_default()).await;
+            let Ok(session) = state.game_session(session_id).await else { return };
+            if session.status != "waiting" || session.starts_at != Some(starts_at) {
+                return;
+            }
+            let Some((email, locale)) = state.teacher_contact(session.teacher_id).await else { return };
+            let Ok(quiz) = state.quiz(session.quiz_id).await else { return };
+            let template = EmailTemplate::SessionReminder {
+                quiz_title: &quiz.title,
+                room_code: &session.room_code,
+                starts_at,
+                link: state.email.link(&format!("/teacher/sessions/{}/waiting", session_id)),
+            };
+            state.email.send_template(&email, locale.as_deref(), template);
+        });
+    }
+
+    // Emails the teacher the outcome of a session once it is over: how many played, the average share of
+    // correct answers and the top of the ranking over every quiz of the playlist. Practice runs and
+    // sessions nobody played are skipped.
+    pub fn email_results_summary(&self, session_id: i64) {
+        if !self.email.settings.results_summary {
+            return;
         }
-        id
+        let state = self.clone();
+        tokio::spawn(async move {
+            let Ok(session) = state.game_session(session_id).await else { return };
+            if session.practice_started_at.is_some() {
+                return;
+            }
+            let mut totals: HashMap<String, StudentStats> = HashMap::new();
+            for stats in session.completed_segments.iter().map(|s| &s.stats).chain([&session.stats]) {
+                for s in stats.values() {
+                    let entry = totals
+                        .entry(s.nickname.clone())
+                        .or_insert_with(|| StudentStats { nickname: s.nickname.clone(), ..Default::default() });
+                    entry.correct += s.correct;
+                    entry.wrong += s.wrong;
+                    entry.score += s.score;
+                    entry.bonus += s.bonus;
+                    entry.penalty += s.penalty;
+                }
+            }
+            if totals.is_empty() {
+                return;
+            }
+            let Some((email, locale)) = state.teacher_contact(session.teacher_id).await else { return };
+            let first_quiz = session.completed_segments.first().map(|s| s.quiz_id).unwrap_or(session.quiz_id);
+            let Ok(quiz) = state.quiz(first_quiz).await else { return };
+            let ranked = StudentStats::ranked(&totals);
+            let template = EmailTemplate::ResultsSummary {
+                quiz_title: &quiz.title,
+                room_code: &session.room_code,
+                participants: ranked.len(),
+                average_pct: ranked.iter().map(|s| s.correct_pct()).sum::<f64>() / ranked.len() as f64,
+                top: ranked.iter().take(3).map(|s| (s.nickname.clone(), s.points())).collect(),
+                link: state.email.link(&format!("/teacher/sessions/{}/results", session_id)),
+            };
+            state.email.send_template(&email, locale.as_deref(), template);
+        });
     }
 
-    pub async fn persist_core_data(&self) -> anyhow::Result<()> {
-        let Some(path) = self.local_state_path.as_ref() else {
-            return Ok(());
+    pub async fn close_review(&self, session_id: i64) {
+        let finished = {
+            let mut sessions = self.db.game_sessions.write().await;
+            let Some(session) = sessions.get_mut(&session_id) else { return; };
+            if session.status != "review" {
+                return;
+            }
+            session.status = "finished".into();
+            session.review_until = None;
+            session.ended_at.get_or_insert_with(Utc::now);
+            session.clone()
         };
-        let snapshot = self.db.snapshot().await;
-        let serialized = serde_json::to_vec_pretty(&snapshot)?;
-        if let Some(parent) = Path::new(path).parent() {
-            tokio::fs::create_dir_all(parent).await?;
+        let room_code = finished.room_code.clone();
+        self.log_change(JournalOp::SessionFinished { session: Box::new(finished) }).await;
+        if let Some(sender) = self.release_room(&room_code).await {
+            let _ = sender.send(WsEnvelope::new(
+                ServerEvent::ReviewClosed,
+                serde_json::json!({ "sessionId": session_id }),
+                None,
+            ));
         }
-        tokio::fs::write(path, serialized).await?;
-        Ok(())
+        self.schedule_persist();
+    }
+
+    // Frees the room code of a session that is over. Sockets still in the room get whatever was sent on
+    // the returned channels before it is dropped, then see the room closed.
+    pub async fn release_room(&self, room_code: &str) -> Option<RoomChannels> {
+        let channels = self.db.broadcasters.remove(room_code).map(|(_, channels)| channels);
+        if let Err(err) = self.shared.release_room(room_code).await {
+            warn!("failed to release room {}: {}", room_code, err);
+        }
+        channels
+    }
+
+    // Starts the waiting session at `starts_at`, reminding the room with `start_countdown` at the marks
+    // on the way. The task gives up once the session was started, ended or dropped in the meantime.
+    pub fn schedule_start(&self, session_id: i64, starts_at: DateTime<Utc>) {
+        let state = self.clone();
+        tokio::spawn(async move {
+            let marks = START_COUNTDOWN_MARKS_SECS.iter().map(|secs| starts_at - chrono::Duration::seconds(*secs));
+            for at in marks.filter(|at| *at > Utc::now()).chain([starts_at]) {
+                tokio::time::sleep((at - Utc::now()).to_std().unwrap_or_default()).await;
+                let mut sessions = state.db.game_sessions.write().await;
+                let Some(session) = sessions.get_mut(&session_id) else { return };
+                if session.status != "waiting" || session.starts_at != Some(starts_at) {
+                    return;
+                }
+                let Some(channels) = state.db.broadcasters.get(&session.room_code) else { return };
+                if at < starts_at {
+                    if let Some(countdown) = session.start_countdown() {
+                        let _ = channels.send(WsEnvelope::new(ServerEvent::StartCountdown, countdown, None));
+                    }
+                    continue;
+                }
+                session.status = "active".into();
+                session.started_at.get_or_insert_with(Utc::now);
+                info!("session {} started on schedule", session_id);
+                let _ = channels.send(WsEnvelope::new(
+                    ServerEvent::StartQuiz,
+                    serde_json::json!({
+                        "sessionId": session_id,
+                        "gameMode": session.game_mode,
+                        "startedAt": Utc::now().to_rfc3339(),
+                        "scheduled": true
+                    }),
+                    None,
+                ));
+            }
+        });
+    }
+
+    // Binds a fresh room code to the session. None when every attempt hit a code in use, which only
+    // happens once the code space is nearly full.
+    pub async fn allocate_room(&self, session_id: i64) -> StorageResult<Option<String>> {
+        for _ in 0..ROOM_CODE_ATTEMPTS {
+            let code = room_code(self.room_code_length);
+            if self.shared.bind_room(&code, session_id).await? {
+                return Ok(Some(code));
+            }
+        }
+        Ok(None)
+    }
+
+    // Finishes the sessions of rooms idle for longer than `room_idle_ttl` and releases the rooms.
+    // Returns how many were closed.
+    pub async fn expire_idle_rooms(&self) -> usize {
+        let idle = self.rooms_where(|room| room.idle_for() > self.room_idle_ttl).await;
+        self.close_rooms(&idle, "idle").await
+    }
+
+    // Same for rooms opened longer than `room_code_ttl` ago, busy or not, so no code is held for good.
+    pub async fn expire_old_rooms(&self) -> usize {
+        let old = self.rooms_where(|room| room.age() > self.room_code_ttl).await;
+        self.close_rooms(&old, "expired").await
+    }
+
+    // Codes of the rooms matching `filter`. Homework is played without sockets and closes with its
+    // window, so its rooms are left out.
+    async fn rooms_where(&self, filter: impl Fn(&RoomChannels) -> bool) -> Vec<String> {
+        let mut rooms: Vec<String> = self
+            .db
+            .broadcasters
+            .iter()
+            .filter(|room| filter(room.value()))
+            .map(|room| room.key().clone())
+            .collect();
+        let sessions = self.db.game_sessions.read().await;
+        let homework: HashSet<&str> = sessions
+            .values()
+            .filter(|s| s.homework.is_some() && s.status != "finished")
+            .map(|s| s.room_code.as_str())
+            .collect();
+        rooms.retain(|room_code| !homework.contains(room_code.as_str()));
+        rooms
+    }
+
+    // Finishes homework sessions whose window has closed. Returns how many were closed.
+    pub async fn close_due_homework(&self) -> usize {
+        let now = Utc::now();
+        let due: Vec<String> = {
+            let sessions = self.db.game_sessions.read().await;
+            sessions
+                .values()
+                .filter(|s| s.status != "finished" && s.homework.as_ref().is_some_and(|h| h.closes_at <= now))
+                .map(|s| s.room_code.clone())
+                .collect()
+        };
+        self.close_rooms(&due, "homework_closed").await
+    }
+
+    // Finishes the sessions of `rooms` and releases them; `reason` goes out with the `end_quiz`.
+    async fn close_rooms(&self, rooms: &[String], reason: &str) -> usize {
+        for room_code in rooms {
+            let finished = {
+                let mut sessions = self.db.game_sessions.write().await;
+                let session = sessions.values_mut().find(|s| &s.room_code == room_code && s.status != "finished");
+                session.map(|session| {
+                    session.status = "finished".into();
+                    session.review_until = None;
+                    session.host_question = None;
+                    session.ended_at.get_or_insert_with(Utc::now);
+                    session.clone()
+                })
+            };
+            let channels = self.release_room(room_code).await;
+            let Some(finished) = finished else { continue };
+            info!("closing room {} of session {} ({})", room_code, finished.id, reason);
+            if let Some(channels) = channels {
+                let _ = channels.send(WsEnvelope::new(
+                    ServerEvent::EndQuiz,
+                    serde_json::json!({
+                        "sessionId": finished.id,
+                        "endedAt": Utc::now().to_rfc3339(),
+                        "resultsReady": true,
+                        "reason": reason
+                    }),
+                    None,
+                ));
+            }
+            let finished_id = finished.id;
+            self.log_change(JournalOp::SessionFinished { session: Box::new(finished) }).await;
+            self.send_lti_scores(finished_id);
+            self.email_results_summary(finished_id);
+        }
+        if !rooms.is_empty() {
+            self.schedule_persist();
+        }
+        rooms.len()
+    }
+
+    pub async fn quiz(&self, id: i64) -> StorageResult<QuizRecord> {
+        self.db.quizzes.read().await.get(&id).cloned().or_not_found("quiz")
+    }
+
+    pub async fn game_session(&self, id: i64) -> StorageResult<SessionRecord> {
+        self.db.game_sessions.read().await.get(&id).cloned().or_not_found("session")
+    }
+
+    pub async fn active_session_count(&self) -> usize {
+        let sessions = self.db.game_sessions.read().await;
+        sessions.values().filter(|s| s.status != "finished" && s.practice_started_at.is_none()).count()
+    }
+
+    // Drops practice runs older than `PRACTICE_RUN_TTL_HOURS`. Returns how many were dropped.
+    pub async fn expire_practice_runs(&self) -> usize {
+        let now = Utc::now();
+        let ttl = chrono::Duration::hours(PRACTICE_RUN_TTL_HOURS);
+        let mut sessions = self.db.game_sessions.write().await;
+        let before = sessions.len();
+        sessions.retain(|_, s| s.practice_started_at.is_none_or(|started| now - started < ttl));
+        before - sessions.len()
+    }
+
+    pub async fn start_teacher_session(
+        &self,
+        teacher_id: i64,
+        user_agent: Option<&str>,
+    ) -> StorageResult<(String, String)> {
+        let session_id = uuid::Uuid::new_v4().to_string();
+        let csrf_token = uuid::Uuid::new_v4().to_string();
+        let now = Utc::now();
+        let session = TeacherSession {
+            teacher_id,
+            csrf_token: csrf_token.clone(),
+            created_at: now,
+            expires_at: now + self.session_ttl,
+            last_seen_at: Some(now),
+            user_agent_hash: user_agent.map(short_hash),
+        };
+        self.shared.put_teacher_session(&session_id, &session).await?;
+        self.log_teacher_session(&session_id, &session).await;
+        self.schedule_persist();
+        Ok((session_id, csrf_token))
+    }
+
+    pub async fn teacher_session(&self, sid: &str) -> Option<TeacherSession> {
+        match self.shared.get_teacher_session(sid).await {
+            Ok(session) => session.filter(|s| s.expires_at > Utc::now()),
+            Err(err) => {
+                warn!("failed to read teacher session from {} store: {}", self.shared.kind(), err);
+                None
+            }
+        }
+    }
+
+    // Resolves a session cookie and slides its expiry forward; renewals are batched to once a minute.
+    pub async fn touch_teacher_session(&self, sid: &str) -> Option<i64> {
+        let now = Utc::now();
+        let mut session = self.teacher_session(sid).await?;
+        let renewed = now + self.session_ttl;
+        if renewed - session.expires_at > chrono::Duration::minutes(1) {
+            session.expires_at = renewed;
+            session.last_seen_at = Some(now);
+            if let Err(err) = self.shared.put_teacher_session(sid, &session).await {
+                warn!("failed to renew teacher session in {} store: {}", self.shared.kind(), err);
+            }
+            self.log_teacher_session(sid, &session).await;
+            self.schedule_persist();
+        }
+        Some(session.teacher_id)
+    }
+
+    // Takes jobs off the queue in order and runs each once a concurrency slot is free.
+    fn spawn_ai_job_worker(&self) {
+        let Some(mut receiver) = self.ai_jobs.receiver.lock().expect("ai job receiver lock").take() else { return };
+        let state = self.clone();
+        tokio::spawn(async move {
+            let slots = Arc::new(Semaphore::new(state.ai_jobs.concurrency));
+            while let Some(task) = receiver.recv().await {
+                let Ok(permit) = slots.clone().acquire_owned().await else { break };
+                let state = state.clone();
+                tokio::spawn(async move {
+                    state.run_ai_job(task).await;
+                    drop(permit);
+                });
+            }
+        });
+    }
+
+    async fn run_ai_job(&self, task: AiJobTask) {
+        let Some(job) = self.ai_jobs.get(&task.id) else { return };
+        self.ai_jobs.update(&task.id, |job| {
+            job.status = AiJobStatus::Running;
+            job.started_at = Some(Utc::now());
+        });
+        let generated = ai_providers::generate_quiz(
+            task.client.as_ref(),
+            job.provider,
+            &self.quiz_schemas,
+            &self.quiz_limits,
+            &self.moderation,
+            &self.db.prompt_set().await,
+            &task.request,
+        )
+        .await;
+        let result = match generated {
+            Ok(quiz) => self.create_quiz(job.teacher_id, quiz, None).await.map_err(|err| AiFailure {
+                status: 503,
+                code: "STORAGE_UNAVAILABLE",
+                message: err.to_string(),
+                details: Vec::new(),
+            }),
+            Err(failure) => Err(failure),
+        };
+        if result.is_ok() {
+            self.record_usage("ai_generation").await;
+        }
+        self.ai_jobs.update(&task.id, |job| {
+            job.finished_at = Some(Utc::now());
+            match result {
+                Ok(quiz_id) => {
+                    job.status = AiJobStatus::Succeeded;
+                    job.quiz_id = Some(quiz_id);
+                }
+                Err(failure) => {
+                    warn!("ai job {} failed: {}", job.id, failure.message);
+                    job.status = AiJobStatus::Failed;
+                    job.error = Some(failure);
+                }
+            }
+        });
+    }
+
+    fn spawn_session_sweeper(&self) {
+        let state = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(60));
+            loop {
+                interval.tick().await;
+                match state.shared.evict_expired_sessions().await {
+                    Ok(0) => {}
+                    Ok(evicted) => {
+                        info!("evicted {} expired teacher sessions", evicted);
+                        state.schedule_persist();
+                    }
+                    Err(err) => warn!("failed to evict expired teacher sessions: {}", err),
+                }
+                if let Err(err) = state.shared.evict_rate_limits().await {
+                    warn!("failed to evict rate limit counters: {}", err);
+                }
+            }
+        });
+    }
+
+    fn spawn_room_sweeper(&self) {
+        let state = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(60));
+            loop {
+                interval.tick().await;
+                state.expire_idle_rooms().await;
+                state.expire_old_rooms().await;
+                state.close_due_homework().await;
+                state.expire_practice_runs().await;
+            }
+        });
+    }
+
+    // Appends the change to the journal so it survives a crash before the next snapshot write.
+    pub async fn log_change(&self, op: JournalOp) {
+        let Some(journal) = &self.journal else { return };
+        if let Err(err) = journal.append(op).await {
+            warn!("failed to append to journal {}: {}", journal.path(), err);
+            *self.persist_failure.lock().expect("persist failure lock") = Some(format!("journal: {}", err));
+        }
+    }
+
+    pub fn schedule_persist(&self) {
+        self.persist_notify.notify_one();
+    }
+
+    // Used after REST mutations: schedules a write and reports the last failed flush so the caller
+    // learns its change may not survive a restart instead of the error only reaching the logs.
+    pub fn commit_changes(&self) -> StorageResult<()> {
+        self.schedule_persist();
+        match self.persist_failure.lock().expect("persist failure lock").as_ref() {
+            Some(reason) => Err(StorageError::Unavailable(format!(
+                "last {} write failed: {}",
+                self.store.kind(),
+                reason
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    pub async fn persist_core_data(&self) -> StorageResult<()> {
+        // Read the journal position first: every entry up to it is already reflected in memory.
+        let journal_seq = self.journal.as_ref().map(|j| j.last_seq()).unwrap_or(0);
+        let mut snapshot = self.db.snapshot().await;
+        snapshot.journal_seq = journal_seq;
+        let started = std::time::Instant::now();
+        let mut result = self.store.save(&snapshot).await;
+        crate::metrics::record_snapshot_write(self.store.kind(), started.elapsed(), result.is_ok());
+        if let (Ok(()), Some(journal)) = (&result, &self.journal) {
+            result = journal.compact(journal_seq).await;
+        }
+        *self.persist_failure.lock().expect("persist failure lock") = result.as_ref().err().map(|e| e.to_string());
+        result
+    }
+
+    // Coalesces bursts of mutations into a single write after the debounce window.
+    fn spawn_persistence_task(&self) {
+        let state = self.clone();
+        tokio::spawn(async move {
+            loop {
+                state.persist_notify.notified().await;
+                tokio::time::sleep(state.persist_debounce).await;
+                if let Err(err) = state.persist_core_data().await {
+                    warn!("failed to persist {} storage: {}", state.store.kind(), err);
+                }
+            }
+        });
     }
 }
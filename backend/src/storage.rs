@@ -0,0 +1,746 @@
+use crate::api_keys::ApiKey;
+use crate::audit::AuditEntry;
+use crate::jwt::RefreshToken;
+use crate::models::{AnswerKey, Difficulty, QuizSettings};
+use crate::prompt_templates::{PromptTemplates, PromptUseCase};
+use crate::state::{
+    AiUsageLog, ClassRecord, QuizRecord, QuizRevision, SessionRecord, Student, Teacher, TeacherSession, UsageStats,
+};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::mysql::MySqlPool;
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePool, SqlitePoolOptions};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::OnceCell;
+use tracing::warn;
+
+#[derive(Debug, thiserror::Error)]
+pub enum StorageError {
+    #[error("{0} not found")]
+    NotFound(&'static str),
+    #[error("{0}")]
+    Conflict(String),
+    #[error("storage unavailable: {0}")]
+    Unavailable(String),
+    #[error("stored data is corrupt: {0}")]
+    Corrupt(String),
+}
+
+pub type StorageResult<T> = Result<T, StorageError>;
+
+impl From<std::io::Error> for StorageError {
+    fn from(err: std::io::Error) -> Self {
+        StorageError::Unavailable(err.to_string())
+    }
+}
+
+impl From<serde_json::Error> for StorageError {
+    fn from(err: serde_json::Error) -> Self {
+        StorageError::Corrupt(err.to_string())
+    }
+}
+
+impl From<sqlx::Error> for StorageError {
+    fn from(err: sqlx::Error) -> Self {
+        match err {
+            sqlx::Error::RowNotFound => StorageError::NotFound("row"),
+            sqlx::Error::Database(db) if db.is_unique_violation() => StorageError::Conflict(db.to_string()),
+            sqlx::Error::Decode(_) | sqlx::Error::ColumnDecode { .. } => StorageError::Corrupt(err.to_string()),
+            other => StorageError::Unavailable(other.to_string()),
+        }
+    }
+}
+
+impl From<sqlx::migrate::MigrateError> for StorageError {
+    fn from(err: sqlx::migrate::MigrateError) -> Self {
+        StorageError::Unavailable(err.to_string())
+    }
+}
+
+impl From<redis::RedisError> for StorageError {
+    fn from(err: redis::RedisError) -> Self {
+        StorageError::Unavailable(err.to_string())
+    }
+}
+
+pub trait OrNotFound<T> {
+    fn or_not_found(self, entity: &'static str) -> StorageResult<T>;
+}
+
+impl<T> OrNotFound<T> for Option<T> {
+    fn or_not_found(self, entity: &'static str) -> StorageResult<T> {
+        self.ok_or(StorageError::NotFound(entity))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PersistentSnapshot {
+    pub teachers: HashMap<i64, Teacher>,
+    pub teachers_by_login: HashMap<String, i64>,
+    pub quizzes: HashMap<i64, QuizRecord>,
+    #[serde(default)]
+    pub teacher_sessions: HashMap<String, TeacherSession>,
+    #[serde(default)]
+    pub refresh_tokens: HashMap<String, RefreshToken>,
+    #[serde(default)]
+    pub finished_sessions: HashMap<i64, SessionRecord>,
+    #[serde(default)]
+    pub usage_stats: UsageStats,
+    #[serde(default)]
+    pub quiz_revisions: HashMap<i64, Vec<QuizRevision>>,
+    #[serde(default)]
+    pub students: HashMap<i64, Student>,
+    #[serde(default)]
+    pub next_student_id: i64,
+    #[serde(default)]
+    pub api_keys: HashMap<String, ApiKey>,
+    #[serde(default)]
+    pub prompt_templates: PromptTemplates,
+    #[serde(default)]
+    pub ai_usage: AiUsageLog,
+    #[serde(default)]
+    pub classes: HashMap<i64, ClassRecord>,
+    #[serde(default)]
+    pub next_class_id: i64,
+    #[serde(default)]
+    pub audit_log: Vec<AuditEntry>,
+    #[serde(default)]
+    pub journal_seq: u64,
+    pub next_teacher_id: i64,
+    pub next_quiz_id: i64,
+    pub next_session_id: i64,
+}
+
+#[async_trait]
+pub trait SnapshotStore: Send + Sync {
+    fn kind(&self) -> &'static str;
+    fn location(&self) -> String;
+    async fn load(&self) -> StorageResult<Option<PersistentSnapshot>>;
+    async fn save(&self, snapshot: &PersistentSnapshot) -> StorageResult<()>;
+}
+
+pub fn store_from_env() -> anyhow::Result<Arc<dyn SnapshotStore>> {
+    let backend = std::env::var("STORAGE_BACKEND").unwrap_or_else(|_| "json".to_string());
+    match backend.trim().to_lowercase().as_str() {
+        "sqlite" => {
+            let url = std::env::var("SQLITE_URL")
+                .ok()
+                .filter(|v| !v.trim().is_empty())
+                .unwrap_or_else(|| format!("sqlite://{}/local_state.db", env!("CARGO_MANIFEST_DIR")));
+            Ok(Arc::new(SqliteStore::connect_lazy(&url)?))
+        }
+        "json" | "" => {
+            let path = std::env::var("LOCAL_STATE_PATH")
+                .ok()
+                .filter(|v| !v.trim().is_empty())
+                .unwrap_or_else(|| format!("{}/local_state.json", env!("CARGO_MANIFEST_DIR")));
+            let backups = std::env::var("LOCAL_STATE_BACKUPS")
+                .ok()
+                .and_then(|v| v.parse::<usize>().ok())
+                .unwrap_or(0);
+            Ok(Arc::new(JsonFileStore { path, backups }))
+        }
+        other => anyhow::bail!("unsupported STORAGE_BACKEND: {}", other),
+    }
+}
+
+pub struct JsonFileStore {
+    pub path: String,
+    pub backups: usize,
+}
+
+impl JsonFileStore {
+    async fn rotate_backups(&self) -> StorageResult<()> {
+        if tokio::fs::metadata(&self.path).await.is_err() {
+            return Ok(());
+        }
+        for idx in (1..self.backups).rev() {
+            let from = format!("{}.{}", self.path, idx);
+            if tokio::fs::metadata(&from).await.is_ok() {
+                tokio::fs::rename(&from, format!("{}.{}", self.path, idx + 1)).await?;
+            }
+        }
+        tokio::fs::copy(&self.path, format!("{}.1", self.path)).await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl SnapshotStore for JsonFileStore {
+    fn kind(&self) -> &'static str {
+        "json"
+    }
+
+    fn location(&self) -> String {
+        self.path.clone()
+    }
+
+    async fn load(&self) -> StorageResult<Option<PersistentSnapshot>> {
+        let raw = match tokio::fs::read_to_string(&self.path).await {
+            Ok(raw) => raw,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        serde_json::from_str::<PersistentSnapshot>(&raw)
+            .map(Some)
+            .map_err(|err| StorageError::Corrupt(format!("{}: {}", self.path, err)))
+    }
+
+    async fn save(&self, snapshot: &PersistentSnapshot) -> StorageResult<()> {
+        let serialized = serde_json::to_vec_pretty(snapshot)?;
+        if let Some(parent) = Path::new(&self.path).parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        // Write to a sibling temp file and rename so a crash never leaves a half-written snapshot.
+        let tmp_path = format!("{}.tmp", self.path);
+        let mut file = tokio::fs::File::create(&tmp_path).await?;
+        file.write_all(&serialized).await?;
+        file.sync_all().await?;
+        drop(file);
+        if self.backups > 0 {
+            self.rotate_backups().await?;
+        }
+        tokio::fs::rename(&tmp_path, &self.path).await?;
+        Ok(())
+    }
+}
+
+// Quiz-level difficulty and tags, stored as one JSON column in SQLite.
+#[derive(Default, Serialize, Deserialize)]
+struct QuizMetadata {
+    #[serde(default)]
+    difficulty: Option<Difficulty>,
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(default)]
+    settings: QuizSettings,
+    #[serde(default)]
+    subject: Option<String>,
+    #[serde(default)]
+    grade: Option<u8>,
+    #[serde(default)]
+    language: Option<String>,
+    #[serde(default)]
+    created_at: Option<DateTime<Utc>>,
+}
+
+pub struct SqliteStore {
+    url: String,
+    pool: SqlitePool,
+    migrated: OnceCell<()>,
+}
+
+impl SqliteStore {
+    pub fn connect_lazy(url: &str) -> anyhow::Result<Self> {
+        let options = SqliteConnectOptions::from_str(url)?.create_if_missing(true);
+        let pool = SqlitePoolOptions::new().max_connections(1).connect_lazy_with(options);
+        Ok(Self { url: url.to_string(), pool, migrated: OnceCell::new() })
+    }
+
+    async fn ensure_migrated(&self) -> StorageResult<()> {
+        self.migrated
+            .get_or_try_init(|| async {
+                sqlx::migrate!("./migrations_sqlite").run(&self.pool).await?;
+                Ok::<(), StorageError>(())
+            })
+            .await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl SnapshotStore for SqliteStore {
+    fn kind(&self) -> &'static str {
+        "sqlite"
+    }
+
+    fn location(&self) -> String {
+        self.url.clone()
+    }
+
+    async fn load(&self) -> StorageResult<Option<PersistentSnapshot>> {
+        self.ensure_migrated().await?;
+        let teacher_rows: Vec<(i64, String, String, Option<String>)> =
+            sqlx::query_as("SELECT id, login, password_hash, record FROM teachers")
+                .fetch_all(&self.pool)
+                .await?;
+        let quiz_rows: Vec<(i64, i64, String, Option<String>, String, bool, Option<i64>, Option<String>)> = sqlx::query_as(
+            "SELECT id, owner_teacher_id, title, description, questions, is_published, source_quiz_id, metadata FROM quizzes",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        let session_rows: Vec<(i64, String)> = sqlx::query_as("SELECT id, record FROM game_sessions")
+            .fetch_all(&self.pool)
+            .await?;
+        let teacher_session_rows: Vec<(String, String)> = sqlx::query_as("SELECT id, record FROM teacher_sessions")
+            .fetch_all(&self.pool)
+            .await?;
+        let refresh_token_rows: Vec<(String, String)> = sqlx::query_as("SELECT token, record FROM refresh_tokens")
+            .fetch_all(&self.pool)
+            .await?;
+        let student_rows: Vec<(String,)> = sqlx::query_as("SELECT record FROM students")
+            .fetch_all(&self.pool)
+            .await?;
+        let api_key_rows: Vec<(String,)> = sqlx::query_as("SELECT record FROM api_keys")
+            .fetch_all(&self.pool)
+            .await?;
+        let prompt_rows: Vec<(String, String)> =
+            sqlx::query_as("SELECT use_case, record FROM prompt_templates ORDER BY use_case, version")
+                .fetch_all(&self.pool)
+                .await?;
+        let counters: HashMap<String, i64> = sqlx::query_as::<_, (String, i64)>("SELECT name, value FROM state_counters")
+            .fetch_all(&self.pool)
+            .await?
+            .into_iter()
+            .collect();
+        let revision_rows: Vec<(i64, String)> =
+            sqlx::query_as("SELECT quiz_id, record FROM quiz_revisions ORDER BY quiz_id, rev")
+                .fetch_all(&self.pool)
+                .await?;
+        let usage_rows: Vec<(String, String, i64)> = sqlx::query_as("SELECT day, feature, count FROM usage_stats")
+            .fetch_all(&self.pool)
+            .await?;
+        let ai_usage_rows: Vec<(i64, String, String)> = sqlx::query_as("SELECT teacher_id, day, record FROM ai_usage")
+            .fetch_all(&self.pool)
+            .await?;
+        let class_rows: Vec<(String,)> = sqlx::query_as("SELECT record FROM classes")
+            .fetch_all(&self.pool)
+            .await?;
+        let audit_rows: Vec<(String,)> = sqlx::query_as("SELECT record FROM audit_log ORDER BY id")
+            .fetch_all(&self.pool)
+            .await?;
+        if teacher_rows.is_empty() && quiz_rows.is_empty() && session_rows.is_empty() && counters.is_empty() {
+            return Ok(None);
+        }
+
+        let mut teachers = HashMap::new();
+        let mut teachers_by_login = HashMap::new();
+        for (id, login, password_hash, record) in teacher_rows {
+            teachers_by_login.insert(login.clone(), id);
+            let teacher = match record {
+                Some(record) => serde_json::from_str::<Teacher>(&record)?,
+                None => Teacher {
+                    id,
+                    login,
+                    password_hash,
+                    password_changed_at: None,
+                    totp: None,
+                    email: None,
+                    locale: None,
+                    word_overrides: Default::default(),
+                },
+            };
+            teachers.insert(id, teacher);
+        }
+        let mut quizzes = HashMap::new();
+        for (id, owner_teacher_id, title, description, questions, is_published, source_quiz_id, metadata) in quiz_rows {
+            let metadata = match metadata {
+                Some(raw) => serde_json::from_str::<QuizMetadata>(&raw)?,
+                None => QuizMetadata::default(),
+            };
+            quizzes.insert(
+                id,
+                QuizRecord {
+                    id,
+                    owner_teacher_id,
+                    title,
+                    description,
+                    questions: serde_json::from_str(&questions)?,
+                    is_published,
+                    source_quiz_id,
+                    difficulty: metadata.difficulty,
+                    tags: metadata.tags,
+                    settings: metadata.settings,
+                    subject: metadata.subject,
+                    grade: metadata.grade,
+                    language: metadata.language,
+                    created_at: metadata.created_at,
+                },
+            );
+        }
+
+        let mut teacher_sessions = HashMap::new();
+        for (id, record) in teacher_session_rows {
+            teacher_sessions.insert(id, serde_json::from_str::<TeacherSession>(&record)?);
+        }
+        let mut refresh_tokens = HashMap::new();
+        for (token, record) in refresh_token_rows {
+            refresh_tokens.insert(token, serde_json::from_str::<RefreshToken>(&record)?);
+        }
+        let mut finished_sessions = HashMap::new();
+        for (id, record) in session_rows {
+            finished_sessions.insert(id, serde_json::from_str::<SessionRecord>(&record)?);
+        }
+        let mut quiz_revisions: HashMap<i64, Vec<QuizRevision>> = HashMap::new();
+        for (quiz_id, record) in revision_rows {
+            quiz_revisions.entry(quiz_id).or_default().push(serde_json::from_str(&record)?);
+        }
+        let mut students = HashMap::new();
+        for (record,) in student_rows {
+            let student = serde_json::from_str::<Student>(&record)?;
+            students.insert(student.id, student);
+        }
+        let mut api_keys = HashMap::new();
+        for (record,) in api_key_rows {
+            let key = serde_json::from_str::<ApiKey>(&record)?;
+            api_keys.insert(key.id.clone(), key);
+        }
+        let mut prompt_templates = PromptTemplates::new();
+        for (use_case, record) in prompt_rows {
+            // Rows of a use case this build no longer knows are skipped rather than failing the load.
+            let Some(use_case) = PromptUseCase::parse(&use_case) else { continue };
+            prompt_templates.entry(use_case).or_default().push(serde_json::from_str(&record)?);
+        }
+        let mut usage_stats = UsageStats::new();
+        for (day, feature, count) in usage_rows {
+            usage_stats.entry(day).or_default().insert(feature, count.max(0) as u64);
+        }
+        let mut ai_usage = AiUsageLog::new();
+        for (teacher_id, day, record) in ai_usage_rows {
+            ai_usage.entry(teacher_id).or_default().insert(day, serde_json::from_str(&record)?);
+        }
+        let mut classes = HashMap::new();
+        for (record,) in class_rows {
+            let class = serde_json::from_str::<ClassRecord>(&record)?;
+            classes.insert(class.id, class);
+        }
+
+        Ok(Some(PersistentSnapshot {
+            teachers,
+            teachers_by_login,
+            quizzes,
+            teacher_sessions,
+            refresh_tokens,
+            finished_sessions,
+            usage_stats,
+            quiz_revisions,
+            students,
+            next_student_id: counters.get("next_student_id").copied().unwrap_or(1),
+            api_keys,
+            prompt_templates,
+            ai_usage,
+            classes,
+            next_class_id: counters.get("next_class_id").copied().unwrap_or(1),
+            audit_log: audit_rows
+                .into_iter()
+                .map(|(record,)| serde_json::from_str(&record))
+                .collect::<Result<_, _>>()?,
+            journal_seq: counters.get("journal_seq").copied().unwrap_or(0).max(0) as u64,
+            next_teacher_id: counters.get("next_teacher_id").copied().unwrap_or(1),
+            next_quiz_id: counters.get("next_quiz_id").copied().unwrap_or(1),
+            next_session_id: counters.get("next_session_id").copied().unwrap_or(1),
+        }))
+    }
+
+    async fn save(&self, snapshot: &PersistentSnapshot) -> StorageResult<()> {
+        self.ensure_migrated().await?;
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("DELETE FROM teacher_sessions").execute(&mut *tx).await?;
+        sqlx::query("DELETE FROM refresh_tokens").execute(&mut *tx).await?;
+        sqlx::query("DELETE FROM game_sessions").execute(&mut *tx).await?;
+        sqlx::query("DELETE FROM quizzes").execute(&mut *tx).await?;
+        sqlx::query("DELETE FROM teachers").execute(&mut *tx).await?;
+        sqlx::query("DELETE FROM usage_stats").execute(&mut *tx).await?;
+        sqlx::query("DELETE FROM quiz_revisions").execute(&mut *tx).await?;
+        sqlx::query("DELETE FROM students").execute(&mut *tx).await?;
+        sqlx::query("DELETE FROM api_keys").execute(&mut *tx).await?;
+        sqlx::query("DELETE FROM prompt_templates").execute(&mut *tx).await?;
+        sqlx::query("DELETE FROM ai_usage").execute(&mut *tx).await?;
+        sqlx::query("DELETE FROM classes").execute(&mut *tx).await?;
+        for t in snapshot.teachers.values() {
+            sqlx::query("INSERT INTO teachers (id, login, password_hash, record) VALUES (?, ?, ?, ?)")
+                .bind(t.id)
+                .bind(t.login.as_str())
+                .bind(t.password_hash.as_str())
+                .bind(serde_json::to_string(t)?)
+                .execute(&mut *tx)
+                .await?;
+        }
+        for q in snapshot.quizzes.values() {
+            sqlx::query(
+                "INSERT INTO quizzes (id, owner_teacher_id, title, description, questions, is_published, source_quiz_id, metadata) \
+                 VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+            )
+            .bind(q.id)
+            .bind(q.owner_teacher_id)
+            .bind(q.title.as_str())
+            .bind(q.description.as_deref())
+            .bind(serde_json::to_string(&q.questions)?)
+            .bind(q.is_published)
+            .bind(q.source_quiz_id)
+            .bind(serde_json::to_string(&QuizMetadata {
+                difficulty: q.difficulty,
+                tags: q.tags.clone(),
+                settings: q.settings.clone(),
+                subject: q.subject.clone(),
+                grade: q.grade,
+                language: q.language.clone(),
+                created_at: q.created_at,
+            })?)
+            .execute(&mut *tx)
+            .await?;
+        }
+        for (id, session) in &snapshot.teacher_sessions {
+            sqlx::query("INSERT INTO teacher_sessions (id, teacher_id, expires_at, record) VALUES (?, ?, ?, ?)")
+                .bind(id.as_str())
+                .bind(session.teacher_id)
+                .bind(session.expires_at)
+                .bind(serde_json::to_string(session)?)
+                .execute(&mut *tx)
+                .await?;
+        }
+        for (token, record) in &snapshot.refresh_tokens {
+            sqlx::query("INSERT INTO refresh_tokens (token, teacher_id, expires_at, record) VALUES (?, ?, ?, ?)")
+                .bind(token.as_str())
+                .bind(record.teacher_id)
+                .bind(record.expires_at)
+                .bind(serde_json::to_string(record)?)
+                .execute(&mut *tx)
+                .await?;
+        }
+        for session in snapshot.finished_sessions.values() {
+            sqlx::query("INSERT INTO game_sessions (id, teacher_id, quiz_id, status, record) VALUES (?, ?, ?, ?, ?)")
+                .bind(session.id)
+                .bind(session.teacher_id)
+                .bind(session.quiz_id)
+                .bind(session.status.as_str())
+                .bind(serde_json::to_string(session)?)
+                .execute(&mut *tx)
+                .await?;
+        }
+        for (quiz_id, history) in &snapshot.quiz_revisions {
+            for revision in history {
+                sqlx::query("INSERT INTO quiz_revisions (quiz_id, rev, record) VALUES (?, ?, ?)")
+                    .bind(*quiz_id)
+                    .bind(revision.rev as i64)
+                    .bind(serde_json::to_string(revision)?)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+        }
+        for student in snapshot.students.values() {
+            sqlx::query("INSERT INTO students (id, login, access_code, record) VALUES (?, ?, ?, ?)")
+                .bind(student.id)
+                .bind(student.login.as_deref())
+                .bind(student.access_code.as_deref())
+                .bind(serde_json::to_string(student)?)
+                .execute(&mut *tx)
+                .await?;
+        }
+        for key in snapshot.api_keys.values() {
+            sqlx::query("INSERT INTO api_keys (id, teacher_id, record) VALUES (?, ?, ?)")
+                .bind(key.id.as_str())
+                .bind(key.teacher_id)
+                .bind(serde_json::to_string(key)?)
+                .execute(&mut *tx)
+                .await?;
+        }
+        for (use_case, versions) in &snapshot.prompt_templates {
+            for version in versions {
+                sqlx::query("INSERT INTO prompt_templates (use_case, version, record) VALUES (?, ?, ?)")
+                    .bind(use_case.name())
+                    .bind(version.version as i64)
+                    .bind(serde_json::to_string(version)?)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+        }
+        for (teacher_id, days) in &snapshot.ai_usage {
+            for (day, usage) in days {
+                sqlx::query("INSERT INTO ai_usage (teacher_id, day, record) VALUES (?, ?, ?)")
+                    .bind(*teacher_id)
+                    .bind(day.as_str())
+                    .bind(serde_json::to_string(usage)?)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+        }
+        for class in snapshot.classes.values() {
+            sqlx::query("INSERT INTO classes (id, teacher_id, record) VALUES (?, ?, ?)")
+                .bind(class.id)
+                .bind(class.teacher_id)
+                .bind(serde_json::to_string(class)?)
+                .execute(&mut *tx)
+                .await?;
+        }
+        // The audit trail is append-only: rows are never deleted, only new ids are added.
+        for entry in &snapshot.audit_log {
+            sqlx::query("INSERT OR IGNORE INTO audit_log (id, at, actor, action, record) VALUES (?, ?, ?, ?, ?)")
+                .bind(entry.id as i64)
+                .bind(entry.at)
+                .bind(entry.actor.as_str())
+                .bind(entry.action.as_str())
+                .bind(serde_json::to_string(entry)?)
+                .execute(&mut *tx)
+                .await?;
+        }
+        for (day, features) in &snapshot.usage_stats {
+            for (feature, count) in features {
+                sqlx::query("INSERT INTO usage_stats (day, feature, count) VALUES (?, ?, ?)")
+                    .bind(day.as_str())
+                    .bind(feature.as_str())
+                    .bind(*count as i64)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+        }
+        for (name, value) in [
+            ("next_teacher_id", snapshot.next_teacher_id),
+            ("next_quiz_id", snapshot.next_quiz_id),
+            ("next_session_id", snapshot.next_session_id),
+            ("next_student_id", snapshot.next_student_id),
+            ("next_class_id", snapshot.next_class_id),
+            ("journal_seq", snapshot.journal_seq as i64),
+        ] {
+            sqlx::query(
+                "INSERT INTO state_counters (name, value) VALUES (?, ?) \
+                 ON CONFLICT(name) DO UPDATE SET value = excluded.value",
+            )
+            .bind(name)
+            .bind(value)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+}
+
+fn difficulty_column(difficulty: Option<Difficulty>) -> StorageResult<Option<String>> {
+    Ok(difficulty
+        .map(serde_json::to_value)
+        .transpose()?
+        .and_then(|v| v.as_str().map(str::to_string)))
+}
+
+// Copies teachers and quizzes from the local snapshot into the MySQL schema. Runs at most once per
+// snapshot source: the import is recorded in `local_snapshot_imports` and skipped afterwards.
+pub async fn import_snapshot_into_mysql(
+    pool: &MySqlPool,
+    source: &str,
+    snapshot: &PersistentSnapshot,
+) -> StorageResult<Option<(usize, usize)>> {
+    let already: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM local_snapshot_imports WHERE source = ?")
+        .bind(source)
+        .fetch_one(pool)
+        .await?;
+    if already > 0 || (snapshot.teachers.is_empty() && snapshot.quizzes.is_empty()) {
+        return Ok(None);
+    }
+
+    let mut tx = pool.begin().await?;
+    let mut teacher_ids = HashSet::new();
+    let mut teachers: Vec<&Teacher> = snapshot.teachers.values().collect();
+    teachers.sort_by_key(|t| t.id);
+    for t in teachers {
+        let inserted = sqlx::query("INSERT IGNORE INTO teachers (id, login, password_hash) VALUES (?, ?, ?)")
+            .bind(t.id)
+            .bind(t.login.as_str())
+            .bind(t.password_hash.as_str())
+            .execute(&mut *tx)
+            .await?;
+        if inserted.rows_affected() > 0 {
+            teacher_ids.insert(t.id);
+        }
+    }
+
+    let mut quiz_ids = HashSet::new();
+    let mut quizzes: Vec<&QuizRecord> = snapshot.quizzes.values().collect();
+    quizzes.sort_by_key(|q| q.id);
+    for q in quizzes {
+        if !teacher_ids.contains(&q.owner_teacher_id) {
+            warn!("skipping quiz {} during import: owner {} was not imported", q.id, q.owner_teacher_id);
+            continue;
+        }
+        let source_quiz_id = q.source_quiz_id.filter(|id| quiz_ids.contains(id));
+        sqlx::query(
+            "INSERT INTO quizzes \
+             (id, owner_teacher_id, title, description, is_published, source_quiz_id, difficulty, tags, settings, subject, grade, language) \
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(q.id)
+        .bind(q.owner_teacher_id)
+        .bind(q.title.as_str())
+        .bind(q.description.as_deref())
+        .bind(q.is_published)
+        .bind(source_quiz_id)
+        .bind(difficulty_column(q.difficulty)?)
+        .bind(serde_json::to_value(&q.tags)?)
+        .bind(serde_json::to_value(&q.settings)?)
+        .bind(q.subject.as_deref())
+        .bind(q.grade)
+        .bind(q.language.as_deref())
+        .execute(&mut *tx)
+        .await?;
+
+        for (position, question) in q.questions.iter().enumerate() {
+            let q_type = serde_json::to_value(&question.q_type)?;
+            let question_row = sqlx::query(
+                "INSERT INTO quiz_questions (quiz_id, external_id, q_type, prompt, position, difficulty, tags) \
+                 VALUES (?, ?, ?, ?, ?, ?, ?)",
+            )
+            .bind(q.id)
+            .bind(question.id.as_str())
+            .bind(q_type.as_str().unwrap_or_default())
+            .bind(question.prompt.as_str())
+            .bind(position as i32)
+            .bind(difficulty_column(question.difficulty)?)
+            .bind(serde_json::to_value(&question.tags)?)
+            .execute(&mut *tx)
+            .await?;
+            let question_row_id = question_row.last_insert_id();
+
+            for (opt_position, option) in question.options.iter().flatten().enumerate() {
+                sqlx::query("INSERT INTO quiz_options (question_id, external_id, text, position) VALUES (?, ?, ?, ?)")
+                    .bind(question_row_id)
+                    .bind(option.id.as_str())
+                    .bind(option.text.as_str())
+                    .bind(opt_position as i32)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+
+            let (open_text, single_option, multi_options) = match &question.answer {
+                AnswerKey::Open(key) => (Some(key.text.clone()), None, None),
+                AnswerKey::Single { option_id } => (None, Some(option_id.clone()), None),
+                AnswerKey::Multi { option_ids } => (None, None, Some(serde_json::to_value(option_ids)?)),
+                AnswerKey::Ordering { .. } | AnswerKey::Numeric(_) | AnswerKey::Cloze { .. } => (None, None, None),
+            };
+            // answer_payload keeps the full key, including types without a dedicated column.
+            sqlx::query(
+                "INSERT INTO quiz_answers (question_id, open_text, single_option_external_id, multi_option_external_ids, answer_payload) \
+                 VALUES (?, ?, ?, ?, ?)",
+            )
+            .bind(question_row_id)
+            .bind(open_text)
+            .bind(single_option)
+            .bind(multi_options)
+            .bind(serde_json::to_value(&question.answer)?)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        if q.is_published {
+            sqlx::query("INSERT INTO quiz_publications (quiz_id, published_by_teacher_id) VALUES (?, ?)")
+                .bind(q.id)
+                .bind(q.owner_teacher_id)
+                .execute(&mut *tx)
+                .await?;
+        }
+        quiz_ids.insert(q.id);
+    }
+
+    sqlx::query("INSERT INTO local_snapshot_imports (source, teachers_imported, quizzes_imported) VALUES (?, ?, ?)")
+        .bind(source)
+        .bind(teacher_ids.len() as i64)
+        .bind(quiz_ids.len() as i64)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    Ok(Some((teacher_ids.len(), quiz_ids.len())))
+}
@@ -0,0 +1,90 @@
+use data_encoding::BASE32_NOPAD;
+use hmac::{Hmac, Mac};
+use rand::distributions::Alphanumeric;
+use rand::{Rng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+
+// RFC 6238 defaults, which is what every authenticator app expects.
+const STEP_SECS: u64 = 30;
+const DIGITS: u32 = 6;
+const RECOVERY_CODES: usize = 8;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TotpState {
+    // Base32 secret as shown to the user.
+    pub secret: String,
+    pub enabled: bool,
+    // Last accepted time step; a code is never accepted twice.
+    #[serde(default)]
+    pub last_step: u64,
+    // Argon2 hashes of the unused recovery codes.
+    #[serde(default)]
+    pub recovery_codes: Vec<String>,
+}
+
+pub fn generate_secret() -> String {
+    let mut bytes = [0u8; 20];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    BASE32_NOPAD.encode(&bytes)
+}
+
+pub fn generate_recovery_codes() -> Vec<String> {
+    (0..RECOVERY_CODES)
+        .map(|_| {
+            rand::thread_rng()
+                .sample_iter(&Alphanumeric)
+                .take(10)
+                .map(char::from)
+                .collect::<String>()
+                .to_lowercase()
+        })
+        .collect()
+}
+
+pub fn otpauth_uri(issuer: &str, login: &str, secret: &str) -> String {
+    let label = format!("{}:{}", issuer, login);
+    format!(
+        "otpauth://totp/{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        urlencode(&label),
+        secret,
+        urlencode(issuer),
+        DIGITS,
+        STEP_SECS
+    )
+}
+
+fn urlencode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+fn code_for_step(key: &[u8], step: u64) -> String {
+    let mut mac = Hmac::<Sha1>::new_from_slice(key).expect("hmac accepts keys of any length");
+    mac.update(&step.to_be_bytes());
+    let digest = mac.finalize().into_bytes();
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([digest[offset] & 0x7f, digest[offset + 1], digest[offset + 2], digest[offset + 3]]);
+    format!("{:0width$}", binary % 10u32.pow(DIGITS), width = DIGITS as usize)
+}
+
+pub fn code_at(secret: &str, unix_time: u64) -> Option<String> {
+    let key = BASE32_NOPAD.decode(secret.as_bytes()).ok()?;
+    Some(code_for_step(&key, unix_time / STEP_SECS))
+}
+
+// Accepts the current step and one step either side for clock drift. Returns the matched step,
+// which must be newer than `last_step`.
+pub fn verify(secret: &str, code: &str, unix_time: u64, last_step: u64) -> Option<u64> {
+    let key = BASE32_NOPAD.decode(secret.as_bytes()).ok()?;
+    let code = code.trim();
+    let current = unix_time / STEP_SECS;
+    [current.saturating_sub(1), current, current + 1]
+        .into_iter()
+        .find(|step| *step > last_step && code_for_step(&key, *step) == code)
+}
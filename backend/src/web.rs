@@ -0,0 +1,227 @@
+use axum::extract::ConnectInfo;
+use axum::http::HeaderMap;
+use axum_extra::extract::cookie::{Cookie, SameSite};
+use std::net::{IpAddr, SocketAddr};
+
+// An address range from TRUSTED_PROXIES or METRICS_ALLOWED_IPS: a single IP or CIDR notation.
+#[derive(Debug, Clone, Copy)]
+pub struct IpRange {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl IpRange {
+    pub fn parse(value: &str) -> Option<Self> {
+        let (addr, prefix) = match value.split_once('/') {
+            Some((addr, prefix)) => (addr.parse::<IpAddr>().ok()?, Some(prefix.parse::<u8>().ok()?)),
+            None => (value.parse::<IpAddr>().ok()?, None),
+        };
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = prefix.unwrap_or(max);
+        (prefix <= max).then_some(Self { addr, prefix })
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+// Browser origins allowed to call the API with credentials.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CorsOrigins {
+    List(Vec<String>),
+    // Development only: any origin is echoed back. Refused together with COOKIE_SECURE.
+    Any,
+}
+
+impl CorsOrigins {
+    fn defaults() -> Self {
+        Self::List(
+            ["http://localhost:5173", "https://school-gaming-quiz.ru", "https://www.school-gaming-quiz.ru"]
+                .map(String::from)
+                .to_vec(),
+        )
+    }
+
+    // Entries must be bare origins exactly as browsers send them: scheme, host and optional port,
+    // without a path or trailing slash.
+    pub fn parse_origin(value: &str) -> Option<String> {
+        let url = reqwest::Url::parse(value).ok()?;
+        if !matches!(url.scheme(), "http" | "https") {
+            return None;
+        }
+        let origin = url.origin().ascii_serialization();
+        (origin == value).then_some(origin)
+    }
+}
+
+// Cookie attributes, CORS and reverse-proxy trust, configured per deployment.
+#[derive(Debug, Clone)]
+pub struct WebConfig {
+    pub trusted_proxies: Vec<IpRange>,
+    pub cors_origins: CorsOrigins,
+    // Who may scrape /metrics, matched against the client address.
+    pub metrics_allowed: Vec<IpRange>,
+    pub cookie_secure: bool,
+    pub cookie_domain: Option<String>,
+    pub cookie_same_site: SameSite,
+}
+
+impl Default for WebConfig {
+    fn default() -> Self {
+        Self {
+            trusted_proxies: Vec::new(),
+            cors_origins: CorsOrigins::defaults(),
+            metrics_allowed: loopback(),
+            cookie_secure: false,
+            cookie_domain: None,
+            cookie_same_site: SameSite::Lax,
+        }
+    }
+}
+
+fn loopback() -> Vec<IpRange> {
+    ["127.0.0.0/8", "::1"].into_iter().filter_map(IpRange::parse).collect()
+}
+
+// A comma separated list of ranges; None when the variable is unset or blank.
+fn ip_ranges(name: &str) -> anyhow::Result<Option<Vec<IpRange>>> {
+    let raw = std::env::var(name).unwrap_or_default();
+    if raw.trim().is_empty() {
+        return Ok(None);
+    }
+    raw.split(',')
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .map(|value| IpRange::parse(value).ok_or_else(|| anyhow::anyhow!("invalid {} entry: {}", name, value)))
+        .collect::<anyhow::Result<Vec<_>>>()
+        .map(Some)
+}
+
+fn env_flag(name: &str) -> bool {
+    std::env::var(name)
+        .map(|v| matches!(v.trim().to_lowercase().as_str(), "1" | "true" | "yes"))
+        .unwrap_or(false)
+}
+
+// CORS_ORIGINS lists every frontend of the deployment (e.g. production and staging domains);
+// CORS_ORIGIN is the older single-origin name.
+fn cors_origins(cookie_secure: bool) -> anyhow::Result<CorsOrigins> {
+    if env_flag("CORS_ALLOW_ANY_ORIGIN") {
+        if cookie_secure {
+            anyhow::bail!("CORS_ALLOW_ANY_ORIGIN is for local development and cannot be used with COOKIE_SECURE=true");
+        }
+        tracing::warn!("CORS_ALLOW_ANY_ORIGIN is set: any website can call the API with the user's cookies");
+        return Ok(CorsOrigins::Any);
+    }
+    let (name, raw) = ["CORS_ORIGINS", "CORS_ORIGIN"]
+        .into_iter()
+        .find_map(|name| std::env::var(name).ok().filter(|v| !v.trim().is_empty()).map(|v| (name, v)))
+        .unwrap_or(("CORS_ORIGINS", String::new()));
+    if raw.trim().is_empty() {
+        return Ok(CorsOrigins::defaults());
+    }
+    raw.split(',')
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .map(|value| {
+            CorsOrigins::parse_origin(value)
+                .ok_or_else(|| anyhow::anyhow!("invalid {} entry (expected scheme://host[:port]): {}", name, value))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()
+        .map(CorsOrigins::List)
+}
+
+impl WebConfig {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let trusted_proxies = ip_ranges("TRUSTED_PROXIES")?.unwrap_or_default();
+        let cookie_secure = env_flag("COOKIE_SECURE");
+        let cookie_same_site = match std::env::var("COOKIE_SAME_SITE").unwrap_or_default().trim().to_lowercase().as_str() {
+            "" | "lax" => SameSite::Lax,
+            "strict" => SameSite::Strict,
+            "none" => SameSite::None,
+            other => anyhow::bail!("invalid COOKIE_SAME_SITE: {}", other),
+        };
+        // Browsers drop SameSite=None cookies that are not Secure.
+        if cookie_same_site == SameSite::None && !cookie_secure {
+            anyhow::bail!("COOKIE_SAME_SITE=none requires COOKIE_SECURE=true");
+        }
+        Ok(Self {
+            trusted_proxies,
+            cors_origins: cors_origins(cookie_secure)?,
+            metrics_allowed: ip_ranges("METRICS_ALLOWED_IPS")?.unwrap_or_else(loopback),
+            cookie_secure,
+            cookie_domain: std::env::var("COOKIE_DOMAIN").ok().filter(|v| !v.trim().is_empty()),
+            cookie_same_site,
+        })
+    }
+
+    fn is_trusted(&self, ip: IpAddr) -> bool {
+        self.trusted_proxies.iter().any(|range| range.contains(ip))
+    }
+
+    // x-forwarded-for is only believed when the peer is a trusted proxy. The list is walked from
+    // the right, skipping further trusted hops, so a client cannot prepend a fake address.
+    pub fn client_ip(&self, peer: Option<IpAddr>, headers: &HeaderMap) -> Option<IpAddr> {
+        let peer = peer?;
+        if !self.is_trusted(peer) {
+            return Some(peer);
+        }
+        let forwarded: Vec<&str> = headers
+            .get_all("x-forwarded-for")
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .collect();
+        let mut client = peer;
+        for entry in forwarded.iter().rev() {
+            // Anything left of a malformed entry was not written by our proxies.
+            let Ok(ip) = entry.trim().parse::<IpAddr>() else { break };
+            client = ip;
+            if !self.is_trusted(ip) {
+                break;
+            }
+        }
+        Some(client)
+    }
+
+    // The server runs without connection info only when embedded (tests), where every caller is local.
+    pub fn may_scrape_metrics(&self, peer: Option<IpAddr>, headers: &HeaderMap) -> bool {
+        match self.client_ip(peer, headers) {
+            Some(ip) => self.metrics_allowed.iter().any(|range| range.contains(ip)),
+            None => true,
+        }
+    }
+
+    pub fn cookie(&self, name: &'static str, value: String, http_only: bool) -> Cookie<'static> {
+        let mut cookie = Cookie::build((name, value))
+            .http_only(http_only)
+            .secure(self.cookie_secure)
+            .same_site(self.cookie_same_site)
+            .path("/")
+            .build();
+        if let Some(domain) = &self.cookie_domain {
+            cookie.set_domain(domain.clone());
+        }
+        cookie
+    }
+
+    // Removal has to name the same path and domain the cookie was set with.
+    pub fn removal_cookie(&self, name: &'static str) -> Cookie<'static> {
+        self.cookie(name, String::new(), true)
+    }
+}
+
+pub fn peer_ip(extensions: &axum::http::Extensions) -> Option<IpAddr> {
+    extensions.get::<ConnectInfo<SocketAddr>>().map(|info| info.0.ip())
+}
@@ -1,9 +1,80 @@
+use crate::models::SubmittedAnswer;
+use chrono::Utc;
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
+use serde_json::{json, Value};
+
+// JSON Schema of every message below, served at GET /api/v1/contracts/ws for client codegen. The tests
+// keep its event lists in step with the enums.
+pub const WS_PROTOCOL_SCHEMA: &str = include_str!("../contracts/ws_protocol.schema.json");
+
+// Everything the server sends over a room socket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ServerEvent {
+    WaitingRoomUpdate,
+    JoinRejected,
+    StartQuiz,
+    QuestionPush,
+    AnswerResult,
+    StatsUpdate,
+    NextQuiz,
+    QuizFinished,
+    EndQuiz,
+    ReviewData,
+    ReviewClosed,
+    HintPush,
+    HintRejected,
+    RoomUnavailable,
+    Error,
+    Kicked,
+    SessionPaused,
+    SessionResumed,
+    Joined,
+    Resumed,
+    StateSync,
+    LeaderboardUpdate,
+    QuestionResults,
+    QuestionTimeout,
+    StartCountdown,
+    Podium,
+    ReviewQuestion,
+}
+
+impl ServerEvent {
+    pub const ALL: [Self; 27] = [
+        Self::WaitingRoomUpdate,
+        Self::JoinRejected,
+        Self::StartQuiz,
+        Self::QuestionPush,
+        Self::AnswerResult,
+        Self::StatsUpdate,
+        Self::NextQuiz,
+        Self::QuizFinished,
+        Self::EndQuiz,
+        Self::ReviewData,
+        Self::ReviewClosed,
+        Self::HintPush,
+        Self::HintRejected,
+        Self::RoomUnavailable,
+        Self::Error,
+        Self::Kicked,
+        Self::SessionPaused,
+        Self::SessionResumed,
+        Self::Joined,
+        Self::Resumed,
+        Self::StateSync,
+        Self::LeaderboardUpdate,
+        Self::QuestionResults,
+        Self::QuestionTimeout,
+        Self::StartCountdown,
+        Self::Podium,
+        Self::ReviewQuestion,
+    ];
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WsEnvelope {
-    pub event: String,
+    pub event: ServerEvent,
     pub payload: Value,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub request_id: Option<String>,
@@ -11,6 +82,189 @@ pub struct WsEnvelope {
     pub ts: Option<String>,
 }
 
+impl WsEnvelope {
+    pub fn new(event: ServerEvent, payload: Value, request_id: Option<String>) -> Self {
+        Self { event, payload, request_id, ts: Some(Utc::now().to_rfc3339()) }
+    }
+}
+
+// Everything a client may send, tagged the same way as `WsEnvelope`: `{"event": "...", "payload": {...}}`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "event", content = "payload", rename_all = "snake_case")]
+pub enum ClientEvent {
+    JoinRoom(JoinRoom),
+    AnswerSubmit(AnswerSubmit),
+    RequestQuestion(RequestQuestion),
+    RequestHint(RequestHint),
+    RequestStats {},
+    ReviewRequest {},
+    FocusChange(FocusChange),
+    // Teacher-only controls.
+    KickParticipant(KickParticipant),
+    PauseSession {},
+    ResumeSession {},
+    PushNextQuestion(PushNextQuestion),
+    ReviewNext {},
+    BeginCountdown(BeginCountdown),
+}
+
+impl ClientEvent {
+    pub const NAMES: [&'static str; 13] = [
+        "join_room",
+        "answer_submit",
+        "request_question",
+        "request_hint",
+        "request_stats",
+        "review_request",
+        "focus_change",
+        "kick_participant",
+        "pause_session",
+        "resume_session",
+        "push_next_question",
+        "review_next",
+        "begin_countdown",
+    ];
+
+    pub fn teacher_only(&self) -> bool {
+        matches!(
+            self,
+            Self::KickParticipant(_)
+                | Self::PauseSession {}
+                | Self::ResumeSession {}
+                | Self::PushNextQuestion(_)
+                | Self::ReviewNext {}
+                | Self::BeginCountdown(_)
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JoinRole {
+    #[default]
+    Student,
+    Teacher,
+    Spectator,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JoinRoom {
+    #[serde(default)]
+    pub role: JoinRole,
+    #[serde(default)]
+    pub nickname: String,
+    pub student_token: Option<String>,
+    // Must match the session's current join token from the join link.
+    pub join_token: Option<String>,
+    // From an earlier `joined`/`resumed`; restores that participant instead of starting afresh.
+    pub resume_token: Option<String>,
+    // Teachers echo the CSRF token of their cookie session, so another site cannot open the socket for them.
+    pub csrf: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AnswerSubmit {
+    pub question_id: String,
+    pub answer: SubmittedAnswer,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RequestQuestion {
+    #[serde(default = "default_question_reason")]
+    pub reason: String,
+}
+
+fn default_question_reason() -> String {
+    "death".into()
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RequestHint {
+    pub question_id: String,
+}
+
+// The client's window or tab gained or lost focus.
+#[derive(Debug, Clone, Deserialize)]
+pub struct FocusChange {
+    pub focused: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct KickParticipant {
+    pub nickname: String,
+}
+
+// Without a nickname the question goes to every participant still in the room.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct PushNextQuestion {
+    #[serde(default)]
+    pub nickname: Option<String>,
+}
+
+// Without seconds the countdown takes the lobby's `countdownSecs`.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct BeginCountdown {
+    #[serde(default)]
+    pub seconds: Option<u32>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ClientMessage {
+    pub event: ClientEvent,
+    pub request_id: Option<String>,
+}
+
+// Why a client message was not handled; sent back as an `error` event.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProtocolError {
+    pub code: &'static str,
+    pub message: String,
+    pub request_id: Option<String>,
+}
+
+impl ProtocolError {
+    pub fn new(code: &'static str, message: impl Into<String>, request_id: Option<String>) -> Self {
+        Self { code, message: message.into(), request_id }
+    }
+
+    pub fn into_envelope(self) -> WsEnvelope {
+        WsEnvelope::new(ServerEvent::Error, json!({ "code": self.code, "message": self.message }), self.request_id)
+    }
+}
+
+#[derive(Deserialize)]
+struct RawClientMessage {
+    event: String,
+    #[serde(default)]
+    payload: Value,
+    #[serde(default, alias = "requestId")]
+    request_id: Option<String>,
+}
+
+impl ClientMessage {
+    // The envelope is read first so the error can name the event and echo the request id.
+    pub fn parse(text: &str) -> Result<Self, ProtocolError> {
+        let raw: RawClientMessage = serde_json::from_str(text)
+            .map_err(|e| ProtocolError::new("invalid_message", format!("not a ws envelope: {}", e), None))?;
+        if !ClientEvent::NAMES.contains(&raw.event.as_str()) {
+            return Err(ProtocolError::new("unknown_event", format!("unknown event {}", raw.event), raw.request_id));
+        }
+        // Events without fields may be sent without a payload.
+        let payload = if raw.payload.is_null() { json!({}) } else { raw.payload };
+        match serde_json::from_value(json!({ "event": raw.event, "payload": payload })) {
+            Ok(event) => Ok(Self { event, request_id: raw.request_id }),
+            Err(e) => Err(ProtocolError::new(
+                "invalid_payload",
+                format!("invalid {} payload: {}", raw.event, e),
+                raw.request_id,
+            )),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -18,14 +272,77 @@ mod tests {
     #[test]
     fn ws_serialization_roundtrip() {
         let env = WsEnvelope {
-            event: "waiting_room_update".into(),
+            event: ServerEvent::WaitingRoomUpdate,
             payload: serde_json::json!({"participants": [{"nickname": "A"}] }),
             request_id: Some("abc".into()),
             ts: Some("2026-01-01T00:00:00Z".into()),
         };
         let raw = serde_json::to_string(&env).unwrap();
+        assert!(raw.contains("\"event\":\"waiting_room_update\""));
         let parsed: WsEnvelope = serde_json::from_str(&raw).unwrap();
-        assert_eq!(parsed.event, "waiting_room_update");
+        assert_eq!(parsed.event, ServerEvent::WaitingRoomUpdate);
         assert_eq!(parsed.request_id.unwrap(), "abc");
     }
+
+    #[test]
+    fn client_events_parse_into_typed_payloads() {
+        let msg = ClientMessage::parse(
+            r#"{"event":"answer_submit","payload":{"questionId":"q1","answer":{"optionId":"o1"}},"request_id":"r1"}"#,
+        )
+        .unwrap();
+        assert_eq!(msg.request_id.as_deref(), Some("r1"));
+        let ClientEvent::AnswerSubmit(submit) = msg.event else { panic!("expected answer_submit") };
+        assert_eq!(submit.question_id, "q1");
+
+        let msg = ClientMessage::parse(r#"{"event":"join_room","payload":{"nickname":"Оля"}}"#).unwrap();
+        assert!(matches!(msg.event, ClientEvent::JoinRoom(JoinRoom { role: JoinRole::Student, .. })));
+        let msg = ClientMessage::parse(r#"{"event":"request_question","payload":{}}"#).unwrap();
+        assert!(matches!(msg.event, ClientEvent::RequestQuestion(RequestQuestion { ref reason }) if reason == "death"));
+        let msg = ClientMessage::parse(r#"{"event":"review_request","requestId":"r2"}"#).unwrap();
+        assert!(matches!(msg.event, ClientEvent::ReviewRequest {}));
+        assert_eq!(msg.request_id.as_deref(), Some("r2"));
+        let msg = ClientMessage::parse(r#"{"event":"push_next_question"}"#).unwrap();
+        assert!(matches!(msg.event, ClientEvent::PushNextQuestion(PushNextQuestion { nickname: None })));
+        assert!(msg.event.teacher_only());
+        let msg = ClientMessage::parse(r#"{"event":"kick_participant","payload":{"nickname":"Петя"}}"#).unwrap();
+        assert!(matches!(msg.event, ClientEvent::KickParticipant(ref kick) if kick.nickname == "Петя"));
+    }
+
+    #[test]
+    fn bad_client_messages_are_reported() {
+        let code = |text: &str| ClientMessage::parse(text).unwrap_err().code;
+        assert_eq!(code("not json"), "invalid_message");
+        assert_eq!(code(r#"{"event":"dance","payload":{}}"#), "unknown_event");
+        assert_eq!(code(r#"{"event":"request_hint","payload":{}}"#), "invalid_payload");
+        assert_eq!(code(r#"{"event":"join_room","payload":{"role":"admin"}}"#), "invalid_payload");
+        let err = ClientMessage::parse(r#"{"event":"dance","request_id":"r3"}"#).unwrap_err();
+        assert_eq!(err.into_envelope().request_id.as_deref(), Some("r3"));
+    }
+
+    #[test]
+    fn contract_lists_every_event_and_matches_messages() {
+        let schema: Value = serde_json::from_str(WS_PROTOCOL_SCHEMA).unwrap();
+        let names = |def: &str| -> Vec<String> {
+            let list = schema["$defs"][def]["enum"].as_array().unwrap();
+            list.iter().map(|v| v.as_str().unwrap().to_string()).collect()
+        };
+        let server: Vec<String> =
+            ServerEvent::ALL.iter().map(|e| serde_json::to_value(e).unwrap().as_str().unwrap().to_string()).collect();
+        assert_eq!(names("serverEvent"), server);
+        assert_eq!(names("clientEvent"), ClientEvent::NAMES);
+
+        let validator = jsonschema::draft202012::new(&schema).unwrap();
+        let result = WsEnvelope::new(
+            ServerEvent::AnswerResult,
+            json!({"questionId": "q1", "correct": true, "credit": 1.0, "points": 1.0, "nextAction": "continue"}),
+            Some("r1".into()),
+        );
+        assert!(validator.is_valid(&serde_json::to_value(&result).unwrap()));
+        let broken = WsEnvelope::new(ServerEvent::AnswerResult, json!({"questionId": "q1"}), None);
+        assert!(!validator.is_valid(&serde_json::to_value(&broken).unwrap()));
+        let submit = json!({"event": "answer_submit", "payload": {"questionId": "q1", "answer": {"optionId": "o1"}}});
+        assert!(validator.is_valid(&submit));
+        assert!(!validator.is_valid(&json!({"event": "answer_submit", "payload": {"questionId": "q1"}})));
+        assert!(!validator.is_valid(&json!({"event": "dance"})));
+    }
 }
@@ -1,13 +1,35 @@
 use futures::{SinkExt, StreamExt};
-use quiz_backend::{build_state, routes::build_router};
+use quiz_backend::journal::Journal;
+use quiz_backend::storage::{JsonFileStore, SnapshotStore};
+use quiz_backend::{build_state, routes::build_router, state::AppState};
 use reqwest::header::{HeaderMap, HeaderValue};
 use serde_json::json;
+use std::sync::Arc;
 use tokio_tungstenite::tungstenite::Message;
 
 async fn spawn_server() -> (String, reqwest::Client) {
     std::env::remove_var("BEARER");
     std::env::remove_var("GIGACHAT_BEARER");
-    let state = build_state().expect("state");
+    for name in ["AI_PROVIDER", "OPENAI_API_KEY", "YANDEXGPT_API_KEY", "YANDEXGPT_IAM_TOKEN", "OLLAMA_BASE_URL"] {
+        std::env::remove_var(name);
+    }
+    let mut state = scratch_state();
+    state.restore().await.expect("restore");
+    serve(state).await
+}
+
+// A state whose snapshot and journal live in a fresh temp directory, so test runs never write to
+// the tracked local_state.json.
+fn scratch_state() -> AppState {
+    let dir = std::env::temp_dir().join(format!("quiz_test_{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let mut state = build_state().expect("state");
+    state.store = Arc::new(JsonFileStore { path: dir.join("state.json").display().to_string(), backups: 0 });
+    state.journal = Some(Arc::new(Journal::new(dir.join("state.journal").display().to_string(), false)));
+    state
+}
+
+async fn serve(state: AppState) -> (String, reqwest::Client) {
     let app = build_router(state);
     let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
     let addr = listener.local_addr().unwrap();
@@ -22,6 +44,24 @@ async fn spawn_server() -> (String, reqwest::Client) {
     (format!("http://{}", addr), client)
 }
 
+// A state over the given snapshot store and journal, so a test can stop and start the server over
+// the same files. Snapshots are only written when the test
+// asks for one.
+async fn restart_with(store: Arc<dyn SnapshotStore>, journal: Option<Journal>) -> anyhow::Result<AppState> {
+    let mut state = build_state().expect("state");
+    state.store = store;
+    state.journal = journal.map(Arc::new);
+    state.persist_debounce = std::time::Duration::from_secs(3600);
+    state.restore().await?;
+    Ok(state)
+}
+
+// JSON snapshot and journal in `dir`.
+async fn restart_in(dir: &std::path::Path) -> anyhow::Result<AppState> {
+    let store = JsonFileStore { path: dir.join("state.json").display().to_string(), backups: 0 };
+    restart_with(Arc::new(store), Some(Journal::new(dir.join("state.journal").display().to_string(), false))).await
+}
+
 async fn auth(base: &str, client: &reqwest::Client, login: &str) -> String {
     client
         .post(format!("{}/api/v1/auth/register", base))
@@ -87,6 +127,40 @@ fn sample_quiz_payload() -> serde_json::Value {
     })
 }
 
+// Polls an AI job until it succeeds or fails.
+async fn wait_ai_job(base: &str, client: &reqwest::Client, id: &str) -> serde_json::Value {
+    for _ in 0..50 {
+        let job = client
+            .get(format!("{}/api/v1/ai/jobs/{}", base, id))
+            .send()
+            .await
+            .unwrap()
+            .json::<serde_json::Value>()
+            .await
+            .unwrap();
+        if job["status"] == "succeeded" || job["status"] == "failed" {
+            return job;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    }
+    panic!("ai job {} did not finish", id);
+}
+
+// Reads socket messages until the given event arrives and returns its payload.
+async fn next_event<S>(ws: &mut S, event: &str) -> serde_json::Value
+where
+    S: StreamExt<Item = Result<Message, tokio_tungstenite::tungstenite::Error>> + Unpin,
+{
+    loop {
+        // Skips control frames such as the server's heartbeat pings.
+        let Message::Text(text) = ws.next().await.unwrap().unwrap() else { continue };
+        let value: serde_json::Value = serde_json::from_str(&text).unwrap();
+        if value["event"] == event {
+            return value["payload"].clone();
+        }
+    }
+}
+
 #[tokio::test]
 async fn register_login_quiz_publish_search_clone_flow() {
     let (base, client1) = spawn_server().await;
@@ -130,102 +204,5471 @@ async fn register_login_quiz_publish_search_clone_flow() {
 }
 
 #[tokio::test]
-async fn ai_generate_and_save() {
+async fn quiz_tag_and_difficulty_filters() {
     let (base, client) = spawn_server().await;
-    let csrf = auth(&base, &client, "ai_teacher").await;
+    let csrf = auth(&base, &client, "tag_teacher").await;
 
-    let resp = client
-        .post(format!("{}/api/v1/ai/generate-quiz", base))
+    let mut tagged = sample_quiz_payload();
+    tagged["difficulty"] = json!("easy");
+    tagged["tags"] = json!(["Арифметика"]);
+    tagged["questions"][1]["difficulty"] = json!("hard");
+    tagged["questions"][1]["tags"] = json!(["география"]);
+    let mut plain = sample_quiz_payload();
+    plain["title"] = json!("Без тегов");
+    let mut ids = Vec::new();
+    for quiz in [tagged, plain] {
+        let created = client
+            .post(format!("{}/api/v1/quizzes", base))
+            .headers(csrf_headers(&csrf))
+            .json(&quiz)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(created.status(), 201);
+        let id = created.json::<serde_json::Value>().await.unwrap()["quiz_id"].as_i64().unwrap();
+        client
+            .post(format!("{}/api/v1/quizzes/{}/publish", base, id))
+            .headers(csrf_headers(&csrf))
+            .send()
+            .await
+            .unwrap();
+        ids.push(id);
+    }
+
+    for filter in ["tag=арифметика", "tag=География", "difficulty=hard", "difficulty=easy"] {
+        let list = client
+            .get(format!("{}/api/v1/quizzes?{}", base, filter))
+            .send()
+            .await
+            .unwrap()
+            .json::<serde_json::Value>()
+            .await
+            .unwrap();
+        assert_eq!(list["total"], 1, "{filter}");
+        assert_eq!(list["items"][0]["id"], ids[0]);
+    }
+    let all = client.get(format!("{}/api/v1/quizzes", base)).send().await.unwrap();
+    assert_eq!(all.json::<serde_json::Value>().await.unwrap()["total"], 2);
+
+    let library = client
+        .get(format!("{}/api/v1/library/quizzes?difficulty=easy&tag=арифметика", base))
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    assert_eq!(library["total"], 1);
+    assert_eq!(library["items"][0]["tags"], json!(["Арифметика"]));
+    let none = client
+        .get(format!("{}/api/v1/library/quizzes?difficulty=medium", base))
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    assert_eq!(none["total"], 0);
+
+    let mut invalid = sample_quiz_payload();
+    invalid["tags"] = json!(["a", "A"]);
+    let rejected = client
+        .post(format!("{}/api/v1/quizzes", base))
         .headers(csrf_headers(&csrf))
-        .json(&json!({"topic": "История", "grade": "8", "questionCount": 3}))
+        .json(&invalid)
         .send()
         .await
         .unwrap();
-    assert_eq!(resp.status(), 201);
-    let body = resp.json::<serde_json::Value>().await.unwrap();
-    assert_eq!(body["source"], "ai");
+    assert_eq!(rejected.status(), 400);
 }
 
 #[tokio::test]
-async fn session_ws_start_submit_stats_end_results() {
+async fn library_subject_grade_language_filters() {
     let (base, client) = spawn_server().await;
-    let csrf = auth(&base, &client, "live_teacher").await;
+    let csrf = auth(&base, &client, "subject_teacher").await;
 
-    let create_quiz = client
+    let mut algebra = sample_quiz_payload();
+    algebra["title"] = json!("Уравнения");
+    algebra["subject"] = json!("Алгебра");
+    algebra["grade"] = json!(7);
+    algebra["language"] = json!("ru");
+    algebra["tags"] = json!(["линейные"]);
+    let mut english = sample_quiz_payload();
+    english["title"] = json!("Equations");
+    english["subject"] = json!("Algebra");
+    english["grade"] = json!(8);
+    english["language"] = json!("en");
+    for quiz in [algebra, english] {
+        let id = client
+            .post(format!("{}/api/v1/quizzes", base))
+            .headers(csrf_headers(&csrf))
+            .json(&quiz)
+            .send()
+            .await
+            .unwrap()
+            .json::<serde_json::Value>()
+            .await
+            .unwrap()["quiz_id"]
+            .as_i64()
+            .unwrap();
+        client
+            .post(format!("{}/api/v1/quizzes/{}/publish", base, id))
+            .headers(csrf_headers(&csrf))
+            .send()
+            .await
+            .unwrap();
+    }
+
+    let search = |query: &'static str| {
+        let client = client.clone();
+        let url = format!("{}/api/v1/library/quizzes?{}", base, query);
+        async move { client.get(url).send().await.unwrap().json::<serde_json::Value>().await.unwrap() }
+    };
+    let by_subject = search("subject=алгебра").await;
+    assert_eq!(by_subject["total"], 1);
+    assert_eq!(by_subject["items"][0]["grade"], 7);
+    assert_eq!(search("grade=8&lang=en").await["items"][0]["title"], "Equations");
+    assert_eq!(search("grade=8&lang=ru").await["total"], 0);
+    assert_eq!(search("q=алгебра линейные").await["total"], 1);
+    assert_eq!(search("q=equations&subject=algebra").await["total"], 1);
+
+    let mut invalid = sample_quiz_payload();
+    invalid["grade"] = json!(0);
+    invalid["language"] = json!("Russian");
+    let rejected = client
+        .post(format!("{}/api/v1/quizzes", base))
+        .headers(csrf_headers(&csrf))
+        .json(&invalid)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(rejected.status(), 400);
+}
+
+#[tokio::test]
+async fn quiz_list_and_library_are_sorted_and_paged() {
+    let (base, client) = spawn_server().await;
+    let csrf = auth(&base, &client, "paging_teacher").await;
+    let mut ids = Vec::new();
+    for title in ["Бета", "Альфа", "Гамма"] {
+        let mut quiz = sample_quiz_payload();
+        quiz["title"] = json!(title);
+        quiz["subject"] = json!("Пагинация");
+        let id = client
+            .post(format!("{}/api/v1/quizzes", base))
+            .headers(csrf_headers(&csrf))
+            .json(&quiz)
+            .send()
+            .await
+            .unwrap()
+            .json::<serde_json::Value>()
+            .await
+            .unwrap()["quiz_id"]
+            .as_i64()
+            .unwrap();
+        client
+            .post(format!("{}/api/v1/quizzes/{}/publish", base, id))
+            .headers(csrf_headers(&csrf))
+            .send()
+            .await
+            .unwrap();
+        ids.push(id);
+    }
+    client
+        .post(format!("{}/api/v1/quizzes/{}/unpublish", base, ids[2]))
+        .headers(csrf_headers(&csrf))
+        .send()
+        .await
+        .unwrap();
+
+    let get = |url: String| {
+        let client = client.clone();
+        async move { client.get(url).send().await.unwrap().json::<serde_json::Value>().await.unwrap() }
+    };
+    let titles = |page: &serde_json::Value| -> Vec<String> {
+        page["items"].as_array().unwrap().iter().map(|q| q["title"].as_str().unwrap().to_string()).collect()
+    };
+
+    let newest = get(format!("{}/api/v1/quizzes", base)).await;
+    assert_eq!(titles(&newest), ["Гамма", "Альфа", "Бета"]);
+    assert!(newest["items"][0]["createdAt"].is_string());
+
+    let first = get(format!("{}/api/v1/quizzes?sort=title&limit=2", base)).await;
+    assert_eq!(first["total"], 3);
+    assert_eq!(titles(&first), ["Альфа", "Бета"]);
+    let cursor = first["nextCursor"].as_str().unwrap().to_string();
+    let rest = get(format!("{}/api/v1/quizzes?sort=title&limit=2&cursor={}", base, cursor)).await;
+    assert_eq!(titles(&rest), ["Гамма"]);
+    assert_eq!(rest["nextCursor"], json!(null));
+    let by_page = get(format!("{}/api/v1/quizzes?sort=title&order=desc&page=2&limit=2", base)).await;
+    assert_eq!(titles(&by_page), ["Альфа"]);
+    assert_eq!(get(format!("{}/api/v1/quizzes?published=false", base)).await["total"], 1);
+    assert_eq!(get(format!("{}/api/v1/quizzes?search=альф", base)).await["total"], 1);
+
+    let too_large = client.get(format!("{}/api/v1/quizzes?limit=101", base)).send().await.unwrap();
+    assert_eq!(too_large.status(), 400);
+    let wrong_sort = client
+        .get(format!("{}/api/v1/quizzes?sort=created_at&limit=2&cursor={}", base, cursor))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(wrong_sort.status(), 400);
+
+    let other = reqwest::Client::builder().cookie_store(true).build().unwrap();
+    let other_csrf = auth(&base, &other, "paging_cloner").await;
+    other
+        .post(format!("{}/api/v1/quizzes/{}/clone", base, ids[0]))
+        .headers(csrf_headers(&other_csrf))
+        .send()
+        .await
+        .unwrap();
+    let popular = get(format!("{}/api/v1/library/quizzes?subject=пагинация", base)).await;
+    assert_eq!(titles(&popular), ["Бета", "Альфа"]);
+    assert_eq!(popular["items"][0]["popularity"], 1);
+}
+
+#[tokio::test]
+async fn csv_quiz_import_with_dry_run_and_row_errors() {
+    let (base, client) = spawn_server().await;
+    let csrf = auth(&base, &client, "import_teacher").await;
+    let csv = "\u{feff}type;prompt;options;answer;points;explanation\n\
+               single;Столица Франции;Париж|Берлин;Париж;2;\n\
+               multi;Чётные числа;2|3|4;2|4;;\n\
+               open;Кто основал Петербург;;Пётр I|Петр Первый;;Город основан в 1703 году\n\
+               numeric;Ускорение свободного падения;;9,81 м/с²;;\n\
+               ordering;По возрастанию;1|2|3;;;\n\
+               cloze;{{1}} — столица России;;\"Москва;Moscow\";;\n";
+    let form = |body: &str| {
+        reqwest::multipart::Form::new()
+            .part("file", reqwest::multipart::Part::bytes(body.as_bytes().to_vec()).file_name("Банк вопросов.csv"))
+    };
+
+    let dry_run = client
+        .post(format!("{}/api/v1/quizzes/import?dryRun=true", base))
+        .headers(csrf_headers(&csrf))
+        .multipart(form(csv))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(dry_run.status(), 200);
+    let parsed = dry_run.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(parsed["quiz"]["title"], "Банк вопросов");
+    let questions = parsed["quiz"]["questions"].as_array().unwrap();
+    assert_eq!(questions.len(), 6);
+    assert_eq!(questions[0]["answer"], json!({"optionId": "o1"}));
+    assert_eq!(questions[0]["points"], 2);
+    assert_eq!(questions[1]["answer"], json!({"optionIds": ["o1", "o3"]}));
+    assert_eq!(questions[2]["answer"]["accepted"], json!(["Петр Первый"]));
+    assert_eq!(questions[3]["answer"]["unit"], "м/с²");
+    assert_eq!(questions[4]["answer"], json!({"order": ["o1", "o2", "o3"]}));
+    assert_eq!(questions[5]["answer"], json!({"blanks": [["Москва", "Moscow"]]}));
+    let listed = client.get(format!("{}/api/v1/quizzes", base)).send().await.unwrap();
+    assert_eq!(listed.json::<serde_json::Value>().await.unwrap()["total"], 0);
+
+    let saved = client
+        .post(format!("{}/api/v1/quizzes/import", base))
+        .headers(csrf_headers(&csrf))
+        .multipart(form(csv).text("title", "Импорт из таблицы"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(saved.status(), 201);
+    let saved = saved.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(saved["questionCount"], 6);
+    let quiz = client
+        .get(format!("{}/api/v1/quizzes/{}", base, saved["quizId"]))
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    assert_eq!(quiz["title"], "Импорт из таблицы");
+
+    let broken = "type,prompt,options,answer\nsingle,Столица,Париж|Берлин,Рим\nessay,Опишите,,\n";
+    let rejected = client
+        .post(format!("{}/api/v1/quizzes/import", base))
+        .headers(csrf_headers(&csrf))
+        .multipart(form(broken))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(rejected.status(), 400);
+    let body = rejected.json::<serde_json::Value>().await.unwrap();
+    let fields: Vec<_> = body["error"]["details"].as_array().unwrap().iter().map(|d| d["field"].as_str().unwrap()).collect();
+    assert_eq!(fields, ["rows[2].answer", "rows[3].type"]);
+}
+
+#[tokio::test]
+async fn quiz_export_to_gift_and_moodle_xml() {
+    let (base, client) = spawn_server().await;
+    let csrf = auth(&base, &client, "export_teacher").await;
+    let mut payload = sample_quiz_payload();
+    payload["settings"] = json!({"shuffleOptions": true});
+    let questions = payload["questions"].as_array_mut().unwrap();
+    questions.push(json!({
+        "id": "q4",
+        "type": "numeric",
+        "prompt": "Ускорение свободного падения",
+        "answer": {"value": 9.81, "tolerance": 0.1, "unit": "м/с²"},
+        "points": 2,
+        "explanation": "g = 9,81 м/с² у поверхности Земли"
+    }));
+    questions.push(json!({
+        "id": "q5",
+        "type": "cloze",
+        "prompt": "{{1}} стоит на реке {{2}}",
+        "answer": {"blanks": [["Москва"], ["Москва-река", "Москве"]]}
+    }));
+    let create = client
+        .post(format!("{}/api/v1/quizzes", base))
+        .headers(csrf_headers(&csrf))
+        .json(&payload)
+        .send()
+        .await
+        .unwrap();
+    let quiz_id = create.json::<serde_json::Value>().await.unwrap()["quiz_id"].as_i64().unwrap();
+    let export = |format: &str| client.get(format!("{}/api/v1/quizzes/{}/export?format={}", base, quiz_id, format)).send();
+
+    let gift = export("gift").await.unwrap();
+    assert_eq!(gift.status(), 200);
+    assert!(gift.headers()["content-disposition"].to_str().unwrap().contains(&format!("quiz-{}.gift.txt", quiz_id)));
+    let gift = gift.text().await.unwrap();
+    assert!(gift.contains("$CATEGORY: $course$/Математика"));
+    assert!(gift.contains("::q1::2+2{\n\t=4\n}"));
+    assert!(gift.contains("=Париж\n\t~Берлин"));
+    assert!(gift.contains("~%50%2") && gift.contains("~%-100%3"));
+    assert!(gift.contains("{#9.81:0.1"));
+    assert!(gift.contains("####g \\= 9,81 м/с² у поверхности Земли"));
+    assert!(gift.contains("// q5: skipped"));
+
+    let xml = export("moodlexml").await.unwrap();
+    assert_eq!(xml.status(), 200);
+    assert!(xml.headers()["content-type"].to_str().unwrap().starts_with("application/xml"));
+    let xml = xml.text().await.unwrap();
+    assert!(xml.starts_with("<?xml"));
+    assert_eq!(xml.matches("<question type=\"multichoice\">").count(), 2);
+    assert!(xml.contains("<shuffleanswers>1</shuffleanswers>"));
+    assert!(xml.contains("<question type=\"numerical\">") && xml.contains("<tolerance>0.1</tolerance>"));
+    assert!(xml.contains("<defaultgrade>2</defaultgrade>"));
+    assert!(xml.contains("{1:SHORTANSWER:=Москва-река~=Москве}"));
+
+    let as_json = export("json").await.unwrap().json::<serde_json::Value>().await.unwrap();
+    assert_eq!(as_json["title"], "Математика");
+    assert_eq!(as_json["questions"].as_array().unwrap().len(), 5);
+
+    let bad = export("docx").await.unwrap();
+    assert_eq!(bad.status(), 400);
+    assert_eq!(bad.json::<serde_json::Value>().await.unwrap()["error"]["details"][0]["field"], "format");
+
+    let other = reqwest::Client::builder().cookie_store(true).build().unwrap();
+    auth(&base, &other, "export_stranger").await;
+    let forbidden = other.get(format!("{}/api/v1/quizzes/{}/export?format=gift", base, quiz_id)).send().await.unwrap();
+    assert_eq!(forbidden.status(), 403);
+}
+
+#[tokio::test]
+async fn quiz_revisions_and_restore() {
+    let (base, client) = spawn_server().await;
+    let csrf = auth(&base, &client, "rev_teacher").await;
+
+    let mut original = sample_quiz_payload();
+    original["tags"] = json!(["дроби"]);
+    original["subject"] = json!("Математика");
+    original["difficulty"] = json!("easy");
+    original["grade"] = json!(5);
+    let create = client
         .post(format!("{}/api/v1/quizzes", base))
         .headers(csrf_headers(&csrf))
+        .json(&original)
+        .send()
+        .await
+        .unwrap();
+    let quiz_id = create.json::<serde_json::Value>().await.unwrap()["quiz_id"].as_i64().unwrap();
+
+    let mut edited = original.clone();
+    edited["questions"].as_array_mut().unwrap().remove(0);
+    edited["tags"] = json!(["проценты"]);
+    edited["subject"] = json!("Алгебра");
+    edited["difficulty"] = json!("hard");
+    edited["grade"] = json!(7);
+    let update = client
+        .put(format!("{}/api/v1/quizzes/{}", base, quiz_id))
+        .headers(csrf_headers(&csrf))
+        .json(&edited)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(update.status(), 200);
+
+    let revisions = client
+        .get(format!("{}/api/v1/quizzes/{}/revisions", base, quiz_id))
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    assert_eq!(revisions["total"], 2);
+    assert_eq!(revisions["items"][0]["rev"], 2);
+    assert_eq!(revisions["items"][0]["diff"]["removed"], json!(["q1"]));
+    assert_eq!(revisions["items"][1]["metadata"]["tags"], json!(["дроби"]));
+
+    // Publication is not part of a revision: restoring keeps the quiz published.
+    client
+        .post(format!("{}/api/v1/quizzes/{}/publish", base, quiz_id))
+        .headers(csrf_headers(&csrf))
+        .send()
+        .await
+        .unwrap();
+
+    let restore = client
+        .post(format!("{}/api/v1/quizzes/{}/revisions/1/restore", base, quiz_id))
+        .headers(csrf_headers(&csrf))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(restore.status(), 200);
+    assert_eq!(restore.json::<serde_json::Value>().await.unwrap()["rev"], 3);
+
+    let quiz = client
+        .get(format!("{}/api/v1/quizzes/{}", base, quiz_id))
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    assert_eq!(quiz["questions"].as_array().unwrap().len(), 3);
+    assert_eq!(quiz["tags"], json!(["дроби"]));
+    assert_eq!((quiz["subject"].clone(), quiz["difficulty"].clone()), (json!("Математика"), json!("easy")));
+    assert_eq!(quiz["grade"], 5);
+    assert_eq!(quiz["is_published"], true);
+
+    // A change to the quiz-level fields alone is a revision too.
+    original["tags"] = json!(["дроби", "повторение"]);
+    let update = client
+        .put(format!("{}/api/v1/quizzes/{}", base, quiz_id))
+        .headers(csrf_headers(&csrf))
+        .json(&original)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(update.status(), 200);
+    let revisions = client
+        .get(format!("{}/api/v1/quizzes/{}/revisions", base, quiz_id))
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    assert_eq!(revisions["items"][0]["rev"], 4);
+    assert_eq!(revisions["items"][0]["diff"]["changed"], json!([]));
+}
+
+#[tokio::test]
+async fn bearer_token_auth_and_refresh() {
+    let (base, cookie_client) = spawn_server().await;
+    cookie_client
+        .post(format!("{}/api/v1/auth/register", base))
+        .json(&json!({"login": "token_teacher", "password": "password123"}))
+        .send()
+        .await
+        .unwrap();
+    let client = reqwest::Client::new();
+
+    let issued = client
+        .post(format!("{}/api/v1/auth/token", base))
+        .json(&json!({"grantType": "password", "login": "token_teacher", "password": "password123"}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(issued.status(), 200);
+    let issued = issued.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(issued["tokenType"], "Bearer");
+    let access = issued["accessToken"].as_str().unwrap().to_string();
+    let refresh = issued["refreshToken"].as_str().unwrap().to_string();
+
+    let create = client
+        .post(format!("{}/api/v1/quizzes", base))
+        .bearer_auth(&access)
         .json(&sample_quiz_payload())
         .send()
         .await
         .unwrap();
-    let quiz_id = create_quiz.json::<serde_json::Value>().await.unwrap()["quiz_id"].as_i64().unwrap();
+    assert_eq!(create.status(), 201);
 
-    let session = client
-        .post(format!("{}/api/v1/sessions", base))
+    let list = client
+        .get(format!("{}/api/v1/quizzes", base))
+        .bearer_auth("not-a-token")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(list.status(), 401);
+
+    let refreshed = client
+        .post(format!("{}/api/v1/auth/token", base))
+        .json(&json!({"grantType": "refresh_token", "refreshToken": refresh}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(refreshed.status(), 200);
+    let new_access = refreshed.json::<serde_json::Value>().await.unwrap()["accessToken"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    let me = client
+        .get(format!("{}/api/v1/auth/me", base))
+        .bearer_auth(&new_access)
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    assert_eq!(me["login"], "token_teacher");
+
+    let reused = client
+        .post(format!("{}/api/v1/auth/token", base))
+        .json(&json!({"grantType": "refresh_token", "refreshToken": refresh}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(reused.status(), 401);
+}
+
+#[tokio::test]
+async fn password_change_and_admin_reset() {
+    std::env::set_var("ADMIN_LOGINS", "backup_admin");
+    let (base, client) = spawn_server().await;
+    let csrf = auth(&base, &client, "pw_teacher").await;
+    let other_device = reqwest::Client::builder().cookie_store(true).build().unwrap();
+    auth(&base, &other_device, "pw_teacher").await;
+
+    let wrong = client
+        .post(format!("{}/api/v1/auth/change-password", base))
         .headers(csrf_headers(&csrf))
-        .json(&json!({"quizId": quiz_id, "gameMode": "platformer"}))
+        .json(&json!({"oldPassword": "not-the-password", "newPassword": "newpassword123"}))
         .send()
         .await
         .unwrap();
-    assert_eq!(session.status(), 201);
-    let session_json = session.json::<serde_json::Value>().await.unwrap();
-    let session_id = session_json["sessionId"].as_i64().unwrap();
-    let room = session_json["roomCode"].as_str().unwrap().to_string();
+    assert_eq!(wrong.status(), 403);
 
-    let ws_url = base.replace("http://", "ws://");
-    let (mut ws, _) = tokio_tungstenite::connect_async(format!("{}/ws/sessions/{}", ws_url, room))
+    let changed = client
+        .post(format!("{}/api/v1/auth/change-password", base))
+        .headers(csrf_headers(&csrf))
+        .json(&json!({"oldPassword": "password123", "newPassword": "newpassword123"}))
+        .send()
         .await
         .unwrap();
+    assert_eq!(changed.status(), 204);
+    let me = client.get(format!("{}/api/v1/auth/me", base)).send().await.unwrap();
+    assert_eq!(me.status(), 200);
+    let other_me = other_device.get(format!("{}/api/v1/auth/me", base)).send().await.unwrap();
+    assert_eq!(other_me.status(), 401);
 
-    ws.send(Message::Text(
-        json!({"event":"join_room","payload":{"role":"student","nickname":"Ира"}}).to_string(),
-    ))
-    .await
-    .unwrap();
+    let admin = reqwest::Client::builder().cookie_store(true).build().unwrap();
+    let admin_csrf = auth(&base, &admin, "backup_admin").await;
+    let issued = admin
+        .post(format!("{}/api/v1/admin/password-reset", base))
+        .headers(csrf_headers(&admin_csrf))
+        .json(&json!({"login": "pw_teacher"}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(issued.status(), 200);
+    let token = issued.json::<serde_json::Value>().await.unwrap()["resetToken"].as_str().unwrap().to_string();
 
-    let _waiting = ws.next().await.unwrap().unwrap();
+    let confirm = |password: &'static str| {
+        admin
+            .post(format!("{}/api/v1/auth/password-reset/confirm", base))
+            .json(&json!({"token": token, "newPassword": password}))
+            .send()
+    };
+    assert_eq!(confirm("resetpassword1").await.unwrap().status(), 204);
+    assert_eq!(confirm("resetpassword2").await.unwrap().status(), 400);
 
-    let started = client
-        .post(format!("{}/api/v1/sessions/{}/start", base, session_id))
+    let me = client.get(format!("{}/api/v1/auth/me", base)).send().await.unwrap();
+    assert_eq!(me.status(), 401);
+    let login = reqwest::Client::new()
+        .post(format!("{}/api/v1/auth/login", base))
+        .json(&json!({"login": "pw_teacher", "password": "resetpassword1"}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(login.status(), 200);
+}
+
+#[tokio::test]
+async fn totp_two_factor_login() {
+    let (base, client) = spawn_server().await;
+    let csrf = auth(&base, &client, "totp_teacher").await;
+
+    let setup = client
+        .post(format!("{}/api/v1/auth/2fa/setup", base))
         .headers(csrf_headers(&csrf))
         .send()
         .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
         .unwrap();
-    assert_eq!(started.status(), 200);
+    let secret = setup["secret"].as_str().unwrap().to_string();
+    assert!(setup["otpauthUri"].as_str().unwrap().starts_with("otpauth://totp/"));
 
-    let _start_msg = ws.next().await.unwrap().unwrap();
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let verify = client
+        .post(format!("{}/api/v1/auth/2fa/verify", base))
+        .headers(csrf_headers(&csrf))
+        .json(&json!({"code": quiz_backend::totp::code_at(&secret, now).unwrap()}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(verify.status(), 200);
+    let recovery = verify.json::<serde_json::Value>().await.unwrap()["recoveryCodes"][0]
+        .as_str()
+        .unwrap()
+        .to_string();
 
-    ws.send(Message::Text(
-        json!({
-            "event":"answer_submit",
-            "payload": {"questionId": "q1", "answer": {"text": "5"}}
-        })
-        .to_string(),
-    ))
-    .await
-    .unwrap();
+    let login = |extra: serde_json::Value| {
+        let mut body = json!({"login": "totp_teacher", "password": "password123"});
+        body.as_object_mut().unwrap().extend(extra.as_object().unwrap().clone());
+        reqwest::Client::new().post(format!("{}/api/v1/auth/login", base)).json(&body).send()
+    };
+    let missing = login(json!({})).await.unwrap();
+    assert_eq!(missing.status(), 401);
+    assert_eq!(missing.json::<serde_json::Value>().await.unwrap()["error"]["code"], "TOTP_REQUIRED");
 
-    let msg1 = ws.next().await.unwrap().unwrap();
-    let msg2 = ws.next().await.unwrap().unwrap();
-    let txt1 = msg1.into_text().unwrap();
-    let txt2 = msg2.into_text().unwrap();
-    assert!(txt1.contains("answer_result") || txt2.contains("answer_result"));
-    assert!(txt1.contains("stats_update") || txt2.contains("stats_update"));
+    let next_code = quiz_backend::totp::code_at(&secret, now + 30).unwrap();
+    assert_eq!(login(json!({"totpCode": next_code})).await.unwrap().status(), 200);
+    assert_eq!(login(json!({"totpCode": next_code})).await.unwrap().status(), 401);
+    assert_eq!(login(json!({"recoveryCode": recovery})).await.unwrap().status(), 200);
+    assert_eq!(login(json!({"recoveryCode": recovery})).await.unwrap().status(), 401);
+}
 
-    let ended = client
-        .post(format!("{}/api/v1/sessions/{}/end", base, session_id))
+#[tokio::test]
+async fn mutating_routes_require_csrf() {
+    let (base, client) = spawn_server().await;
+    let csrf = auth(&base, &client, "csrf_teacher").await;
+
+    let list = client.get(format!("{}/api/v1/quizzes", base)).send().await.unwrap();
+    assert_eq!(list.status(), 200);
+    let create = client
+        .post(format!("{}/api/v1/quizzes", base))
+        .json(&sample_quiz_payload())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(create.status(), 403);
+    let body = create.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(body["error"]["code"], "FORBIDDEN");
+    assert!(body["error"]["request_id"].is_string());
+
+    let logout = client.post(format!("{}/api/v1/auth/logout", base)).send().await.unwrap();
+    assert_eq!(logout.status(), 403);
+    let logout = client
+        .post(format!("{}/api/v1/auth/logout", base))
         .headers(csrf_headers(&csrf))
         .send()
         .await
         .unwrap();
-    assert_eq!(ended.status(), 200);
+    assert_eq!(logout.status(), 204);
+}
 
-    let results = client
-        .get(format!("{}/api/v1/sessions/{}/results", base, session_id))
+#[tokio::test]
+async fn session_list_and_logout_everywhere() {
+    let (base, laptop) = spawn_server().await;
+    let csrf = auth(&base, &laptop, "multi_device_teacher").await;
+    let phone = reqwest::Client::builder().cookie_store(true).user_agent("phone/1.0").build().unwrap();
+    let login = phone
+        .post(format!("{}/api/v1/auth/login", base))
+        .json(&json!({"login": "multi_device_teacher", "password": "password123"}))
         .send()
         .await
         .unwrap();
-    assert_eq!(results.status(), 200);
-    let r = results.text().await.unwrap();
-    assert!(r.contains("mistakesByStudent"));
+    assert_eq!(login.status(), 200);
+
+    let sessions = laptop
+        .get(format!("{}/api/v1/auth/sessions", base))
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    let items = sessions["items"].as_array().unwrap();
+    assert_eq!(items.len(), 2);
+    assert_eq!(items.iter().filter(|s| s["current"] == true).count(), 1);
+    assert!(items.iter().any(|s| s["userAgentHash"].is_string()));
+
+    let revoked = laptop
+        .delete(format!("{}/api/v1/auth/sessions", base))
+        .headers(csrf_headers(&csrf))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(revoked.status(), 204);
+    let phone_me = phone.get(format!("{}/api/v1/auth/me", base)).send().await.unwrap();
+    assert_eq!(phone_me.status(), 401);
+    let laptop_me = laptop.get(format!("{}/api/v1/auth/me", base)).send().await.unwrap();
+    assert_eq!(laptop_me.status(), 200);
+}
+
+#[tokio::test]
+async fn api_key_quiz_management() {
+    let (base, client) = spawn_server().await;
+    let csrf = auth(&base, &client, "api_key_teacher").await;
+    let created = client
+        .post(format!("{}/api/v1/auth/api-keys", base))
+        .headers(csrf_headers(&csrf))
+        .json(&json!({"name": "lms sync"}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(created.status(), 201);
+    let created = created.json::<serde_json::Value>().await.unwrap();
+    let key = created["key"].as_str().unwrap().to_string();
+    let key_id = created["id"].as_str().unwrap().to_string();
+
+    let script = reqwest::Client::new();
+    let quiz = script
+        .post(format!("{}/api/v1/quizzes", base))
+        .header("x-api-key", &key)
+        .json(&sample_quiz_payload())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(quiz.status(), 201);
+    let list = script
+        .get(format!("{}/api/v1/quizzes", base))
+        .header("x-api-key", &key)
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    assert_eq!(list["items"].as_array().unwrap().len(), 1);
+    let nested = script
+        .post(format!("{}/api/v1/auth/api-keys", base))
+        .header("x-api-key", &key)
+        .json(&json!({"name": "nested"}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(nested.status(), 403);
+
+    let keys = client
+        .get(format!("{}/api/v1/auth/api-keys", base))
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    assert_eq!(keys["items"][0]["id"], key_id.as_str());
+    assert!(keys["items"][0].get("key").is_none());
+    assert!(!keys["items"][0]["lastUsedAt"].is_null());
+
+    let revoked = client
+        .delete(format!("{}/api/v1/auth/api-keys/{}", base, key_id))
+        .headers(csrf_headers(&csrf))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(revoked.status(), 204);
+    let after = script
+        .get(format!("{}/api/v1/quizzes", base))
+        .header("x-api-key", &key)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(after.status(), 401);
+}
+
+#[tokio::test]
+async fn api_keys_cannot_touch_account_security() {
+    let (base, client) = spawn_server().await;
+    let csrf = auth(&base, &client, "api_key_security_teacher").await;
+    let key = client
+        .post(format!("{}/api/v1/auth/api-keys", base))
+        .headers(csrf_headers(&csrf))
+        .json(&json!({"name": "lms sync"}))
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap()["key"]
+        .as_str()
+        .unwrap()
+        .to_string();
+
+    let script = reqwest::Client::new();
+    let attempts = [
+        script.post(format!("{}/api/v1/auth/2fa/setup", base)),
+        script.post(format!("{}/api/v1/auth/2fa/verify", base)).json(&json!({"code": "000000"})),
+        script
+            .post(format!("{}/api/v1/auth/change-password", base))
+            .json(&json!({"oldPassword": "password123", "newPassword": "password456"})),
+        script.put(format!("{}/api/v1/auth/email", base)).json(&json!({"email": "a@example.org", "currentPassword": "password123"})),
+        script.get(format!("{}/api/v1/auth/sessions", base)),
+        script.delete(format!("{}/api/v1/auth/sessions", base)),
+        script.get(format!("{}/api/v1/auth/api-keys", base)),
+    ];
+    for attempt in attempts {
+        let resp = attempt.header("x-api-key", &key).send().await.unwrap();
+        assert_eq!(resp.status(), 403, "{}", resp.url());
+    }
+
+    // Nothing was enrolled or revoked: the cookie session still works and 2FA can be set up by it.
+    let me = client.get(format!("{}/api/v1/auth/me", base)).send().await.unwrap();
+    assert_eq!(me.status(), 200);
+    let setup = client
+        .post(format!("{}/api/v1/auth/2fa/setup", base))
+        .headers(csrf_headers(&csrf))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(setup.status(), 200);
+}
+
+#[test]
+fn password_hashes_are_upgraded_to_stronger_params() {
+    use argon2::Params;
+    use quiz_backend::passwords::PasswordHashing;
+
+    let weak = PasswordHashing::new(Params::new(8, 1, 1, None).unwrap());
+    let strong = PasswordHashing::new(Params::new(64, 2, 1, None).unwrap());
+    let weak_hash = weak.hash("password123").unwrap();
+    let strong_hash = strong.hash("password123").unwrap();
+
+    assert!(strong.verify(&weak_hash, "password123").unwrap());
+    assert!(!strong.verify(&weak_hash, "wrong-password").unwrap());
+    assert!(strong.needs_rehash(&weak_hash));
+    assert!(!strong.needs_rehash(&strong_hash));
+    assert!(!weak.needs_rehash(&strong_hash));
+}
+
+#[test]
+fn forwarded_for_is_only_trusted_from_known_proxies() {
+    use quiz_backend::web::{IpRange, WebConfig};
+    use std::net::IpAddr;
+
+    let config = WebConfig {
+        trusted_proxies: vec![IpRange::parse("10.0.0.0/8").unwrap(), IpRange::parse("::1").unwrap()],
+        ..WebConfig::default()
+    };
+    let ip = |v: &str| v.parse::<IpAddr>().unwrap();
+    let mut headers = HeaderMap::new();
+    headers.insert("x-forwarded-for", HeaderValue::from_static("6.6.6.6, 203.0.113.7, 10.1.2.3"));
+
+    assert_eq!(config.client_ip(Some(ip("198.51.100.1")), &headers), Some(ip("198.51.100.1")));
+    assert_eq!(config.client_ip(Some(ip("10.0.0.1")), &headers), Some(ip("203.0.113.7")));
+    assert_eq!(config.client_ip(Some(ip("::1")), &HeaderMap::new()), Some(ip("::1")));
+    headers.insert("x-forwarded-for", HeaderValue::from_static("6.6.6.6, garbage, 10.1.2.3"));
+    assert_eq!(config.client_ip(Some(ip("10.0.0.1")), &headers), Some(ip("10.1.2.3")));
+    assert_eq!(config.client_ip(None, &headers), None);
+    assert!(IpRange::parse("10.0.0.0/33").is_none());
+}
+
+#[tokio::test]
+async fn admin_export_import_roundtrip() {
+    std::env::set_var("ADMIN_LOGINS", "backup_admin");
+    let (base, client) = spawn_server().await;
+    let csrf = auth(&base, &client, "backup_admin").await;
+
+    client
+        .post(format!("{}/api/v1/quizzes", base))
+        .headers(csrf_headers(&csrf))
+        .json(&sample_quiz_payload())
+        .send()
+        .await
+        .unwrap();
+
+    let export = client.get(format!("{}/api/v1/admin/export", base)).send().await.unwrap();
+    assert_eq!(export.status(), 200);
+    let mut archive = export.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(archive["format"], "hse-quiz-backup");
+    let quiz_count = archive["quizzes"].as_array().unwrap().len();
+    assert!(quiz_count >= 1);
+
+    let import = client
+        .post(format!("{}/api/v1/admin/import", base))
+        .headers(csrf_headers(&csrf))
+        .json(&archive)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(import.status(), 200);
+    let report = import.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(report["teachersImported"], 0);
+    assert_eq!(report["quizzesImported"].as_u64().unwrap() as usize, quiz_count);
+
+    archive["quizzes"][0]["questions"] = json!([]);
+    let invalid = client
+        .post(format!("{}/api/v1/admin/import", base))
+        .headers(csrf_headers(&csrf))
+        .json(&archive)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(invalid.status(), 400);
+
+    let other = reqwest::Client::builder().cookie_store(true).build().unwrap();
+    auth(&base, &other, "not_admin").await;
+    let forbidden = other
+        .get(format!("{}/api/v1/admin/export", base))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(forbidden.status(), 403);
+}
+
+#[tokio::test]
+async fn admin_capacity_plan() {
+    std::env::set_var("ADMIN_LOGINS", "backup_admin");
+    let (base, client) = spawn_server().await;
+    auth(&base, &client, "backup_admin").await;
+
+    let capacity = client
+        .get(format!("{}/api/v1/admin/capacity?plannedSessions=3&plannedParticipants=90", base))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(capacity.status(), 200);
+    let body = capacity.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(body["plan"]["wsConnectionsNeeded"], 93);
+    assert_eq!(body["plan"]["fits"], true);
+    assert!(body["current"]["activeSessions"].is_number());
+}
+
+#[tokio::test]
+async fn ai_generate_and_save() {
+    let (base, client) = spawn_server().await;
+    let csrf = auth(&base, &client, "ai_teacher").await;
+
+    let resp = client
+        .post(format!("{}/api/v1/ai/generate-quiz", base))
+        .headers(csrf_headers(&csrf))
+        .json(&json!({"topic": "История", "grade": "8", "questionCount": 3}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 201);
+    let body = resp.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(body["source"], "ai");
+    let quiz = client
+        .get(format!("{}/api/v1/quizzes/{}", base, body["quizId"]))
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    assert!(quiz["questions"][0]["explanation"].as_str().is_some_and(|e| !e.is_empty()));
+
+    let generate = |provider: serde_json::Value| {
+        client
+            .post(format!("{}/api/v1/ai/generate-quiz", base))
+            .headers(csrf_headers(&csrf))
+            .json(&json!({"topic": "История", "questionCount": 1, "provider": provider}))
+            .send()
+    };
+    for (provider, field) in [
+        (json!({"name": "openai"}), "provider.name"),
+        (json!({"name": "claude"}), "provider.name"),
+        (json!({"name": "yandexgpt", "apiKey": "key"}), "provider.folderId"),
+        (json!({"name": "ollama", "apiKey": "key"}), "provider.apiKey"),
+    ] {
+        let resp = generate(provider).await.unwrap();
+        assert_eq!(resp.status(), 400);
+        let body = resp.json::<serde_json::Value>().await.unwrap();
+        assert_eq!(body["error"]["details"][0]["field"], field);
+    }
+}
+
+#[tokio::test]
+async fn ai_generation_follows_requested_mix() {
+    let (base, client) = spawn_server().await;
+    let csrf = auth(&base, &client, "ai_mix_teacher").await;
+    let generate = |payload: serde_json::Value| {
+        client.post(format!("{}/api/v1/ai/generate-quiz", base)).headers(csrf_headers(&csrf)).json(&payload).send()
+    };
+
+    let resp = generate(json!({
+        "topic": "Биология",
+        "questionCount": 4,
+        "difficultyMix": {"easy": 2, "hard": 2},
+        "typeMix": {"single": 1, "multi": 1, "open": 1, "numeric": 1}
+    }))
+    .await
+    .unwrap();
+    assert_eq!(resp.status(), 201);
+    let quiz_id = resp.json::<serde_json::Value>().await.unwrap()["quizId"].clone();
+    let quiz = client
+        .get(format!("{}/api/v1/quizzes/{}", base, quiz_id))
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    let questions = quiz["questions"].as_array().unwrap();
+    let count = |field: &str, value: &str| questions.iter().filter(|q| q[field] == value).count();
+    assert_eq!((count("difficulty", "easy"), count("difficulty", "hard")), (2, 2));
+    for kind in ["single", "multi", "open", "numeric"] {
+        assert_eq!(count("type", kind), 1, "{}", kind);
+    }
+
+    let resp = generate(json!({"topic": "Биология", "questionCount": 4, "difficultyMix": {"easy": 1, "extreme": 3}}))
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 400);
+    let body = resp.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(body["error"]["details"][0]["field"], "difficultyMix.extreme");
+
+    let resp = generate(json!({"topic": "Биология", "questionCount": 4, "typeMix": {"single": 3}})).await.unwrap();
+    assert_eq!(resp.status(), 400);
+    let body = resp.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(body["error"]["details"][0]["field"], "typeMix");
+}
+
+#[tokio::test]
+async fn ai_invalid_quiz_is_repaired_by_the_model() {
+    let (base, client) = spawn_server().await;
+    let csrf = auth(&base, &client, "ai_repair_teacher").await;
+
+    // The mock leaves out every answer for a "черновик" topic until it is asked to fix the errors.
+    let resp = client
+        .post(format!("{}/api/v1/ai/generate-quiz", base))
+        .headers(csrf_headers(&csrf))
+        .json(&json!({"topic": "Физика черновик", "questionCount": 2}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 201);
+    let quiz_id = resp.json::<serde_json::Value>().await.unwrap()["quizId"].clone();
+    let quiz = client
+        .get(format!("{}/api/v1/quizzes/{}", base, quiz_id))
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    assert_eq!(quiz["title"], "Квиз: Физика черновик");
+    let questions = quiz["questions"].as_array().unwrap();
+    assert_eq!(questions.len(), 2);
+    assert!(questions.iter().all(|q| q["answer"]["optionId"] == "o1"));
+
+    let usage = client.get(format!("{}/api/v1/ai/usage", base)).send().await.unwrap();
+    assert_eq!(usage.json::<serde_json::Value>().await.unwrap()["today"]["requests"], 2);
+}
+
+#[tokio::test]
+async fn ai_quiz_with_banned_words_is_rejected() {
+    std::env::set_var("MODERATION_BANNED_WORDS", "запрещенка, ещё одно слово");
+    let (base, client) = spawn_server().await;
+    let csrf = auth(&base, &client, "moderated_teacher").await;
+
+    let resp = client
+        .post(format!("{}/api/v1/ai/generate-quiz", base))
+        .headers(csrf_headers(&csrf))
+        .json(&json!({"topic": "Запрещёнка", "questionCount": 2}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 422);
+    let body = resp.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(body["error"]["code"], "VALIDATION_ERROR");
+    let fields: Vec<_> = body["error"]["details"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|d| d["field"].as_str().unwrap().to_string())
+        .collect();
+    assert!(fields.contains(&"questions[1].prompt".to_string()));
+    assert!(!fields.iter().any(|f| f.contains("options")));
+
+    let resp = client
+        .post(format!("{}/api/v1/ai/generate-quiz", base))
+        .headers(csrf_headers(&csrf))
+        .json(&json!({"topic": "Запрещённые реакции", "questionCount": 1}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 201);
+}
+
+#[tokio::test]
+async fn admin_prompt_templates_are_versioned() {
+    std::env::set_var("ADMIN_LOGINS", "backup_admin");
+    let (base, client) = spawn_server().await;
+    let csrf = auth(&base, &client, "backup_admin").await;
+    let teacher = reqwest::Client::builder().cookie_store(true).build().unwrap();
+    let teacher_csrf = auth(&base, &teacher, "prompt_teacher").await;
+
+    let forbidden = teacher
+        .put(format!("{}/api/v1/admin/prompts/hints", base))
+        .headers(csrf_headers(&teacher_csrf))
+        .json(&json!({"text": "Намёк"}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(forbidden.status(), 403);
+
+    let list = client
+        .get(format!("{}/api/v1/admin/prompts", base))
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    assert_eq!(list["items"].as_array().unwrap().len(), 4);
+    assert_eq!(list["items"][0]["useCase"], "generation");
+    assert_eq!(list["items"][0]["variables"], json!(["topic", "grade", "count"]));
+    assert_eq!(list["items"][0]["version"], 0);
+
+    // Prompt edits need the CSRF token like every other admin change.
+    let no_csrf = client
+        .put(format!("{}/api/v1/admin/prompts/generation", base))
+        .json(&json!({"text": "Чужой промпт про {{topic}}"}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(no_csrf.status(), 403);
+    let no_csrf = client
+        .post(format!("{}/api/v1/admin/prompts/generation/revert", base))
+        .json(&json!({"version": 0}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(no_csrf.status(), 403);
+
+    let put = |use_case: &str, text: &str| {
+        client
+            .put(format!("{}/api/v1/admin/prompts/{}", base, use_case))
+            .headers(csrf_headers(&csrf))
+            .json(&json!({ "text": text }))
+            .send()
+    };
+    let invalid = put("hints", "Подсказка по теме {{topic}}").await.unwrap();
+    assert_eq!(invalid.status(), 400);
+    let body = invalid.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(body["error"]["details"][0]["issue"], "unknown variable {{topic}}");
+    assert_eq!(put("feedback", "Текст").await.unwrap().status(), 404);
+
+    let saved = put("generation", "Квиз на тему {{topic}} для {{grade}} класса, вопросов: {{count}}.")
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    assert_eq!(saved["version"], 1);
+    let generated = client
+        .post(format!("{}/api/v1/ai/generate-quiz", base))
+        .headers(csrf_headers(&csrf))
+        .json(&json!({"topic": "Дроби", "questionCount": 2}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(generated.status(), 201);
+
+    put("generation", "Вторая версия про {{topic}}.").await.unwrap();
+    let reverted = client
+        .post(format!("{}/api/v1/admin/prompts/generation/revert", base))
+        .headers(csrf_headers(&csrf))
+        .json(&json!({"version": 0}))
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    assert_eq!(reverted["version"], 3);
+
+    let history = client
+        .get(format!("{}/api/v1/admin/prompts/generation", base))
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    assert_eq!(history["text"], history["defaultText"]);
+    let versions: Vec<_> = history["versions"].as_array().unwrap().iter().map(|v| v["version"].clone()).collect();
+    assert_eq!(versions, vec![json!(3), json!(2), json!(1)]);
+    assert_eq!(history["versions"][2]["text"], "Квиз на тему {{topic}} для {{grade}} класса, вопросов: {{count}}.");
+
+    let missing = client
+        .post(format!("{}/api/v1/admin/prompts/generation/revert", base))
+        .headers(csrf_headers(&csrf))
+        .json(&json!({"version": 7}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(missing.status(), 404);
+}
+
+#[tokio::test]
+async fn ai_distractors_for_question() {
+    let (base, client) = spawn_server().await;
+    let csrf = auth(&base, &client, "distractor_teacher").await;
+    let create = client
+        .post(format!("{}/api/v1/quizzes", base))
+        .headers(csrf_headers(&csrf))
+        .json(&sample_quiz_payload())
+        .send()
+        .await
+        .unwrap();
+    let quiz_id = create.json::<serde_json::Value>().await.unwrap()["quiz_id"].as_i64().unwrap();
+    let distractors = |question_id: &str, payload: serde_json::Value| {
+        client
+            .post(format!("{}/api/v1/ai/questions/{}/{}/distractors", base, quiz_id, question_id))
+            .headers(csrf_headers(&csrf))
+            .json(&payload)
+            .send()
+    };
+
+    // The mock answers with the correct option and a near-duplicate, both must be dropped.
+    let resp = distractors("q2", json!({})).await.unwrap();
+    assert_eq!(resp.status(), 200);
+    let body = resp.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(
+        body["distractors"],
+        json!([
+            {"id": "o3", "text": "Неверный вариант"},
+            {"id": "o4", "text": "Похожий ответ"},
+            {"id": "o5", "text": "Частая ошибка"}
+        ])
+    );
+    let resp = distractors("q3", json!({"count": 1})).await.unwrap();
+    assert_eq!(resp.json::<serde_json::Value>().await.unwrap()["distractors"], json!([{"id": "o4", "text": "Неверный вариант"}]));
+
+    let resp = distractors("q2", json!({"count": 7})).await.unwrap();
+    assert_eq!(resp.status(), 400);
+    assert_eq!(resp.json::<serde_json::Value>().await.unwrap()["error"]["details"][0]["field"], "count");
+    assert_eq!(distractors("q9", json!({})).await.unwrap().status(), 404);
+
+    // Suggestions do not change the quiz.
+    let quiz = client.get(format!("{}/api/v1/quizzes/{}", base, quiz_id)).send().await.unwrap();
+    assert_eq!(quiz.json::<serde_json::Value>().await.unwrap()["questions"][1]["options"].as_array().unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn ai_generate_from_docx_upload() {
+    let (base, client) = spawn_server().await;
+    let csrf = auth(&base, &client, "docx_teacher").await;
+    let mut docx = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+    docx.start_file("word/document.xml", zip::write::SimpleFileOptions::default()).unwrap();
+    std::io::Write::write_all(
+        &mut docx,
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<w:document xmlns:w="http://schemas.openxmlformats.org/wordprocessingml/2006/main"><w:body>
+<w:p><w:r><w:t>Фотосинтез</w:t></w:r><w:r><w:t xml:space="preserve"> у растений</w:t></w:r></w:p>
+<w:p><w:r><w:t>Растения используют энергию света &amp; углекислый газ.</w:t></w:r></w:p>
+</w:body></w:document>"#
+            .as_bytes(),
+    )
+    .unwrap();
+    let docx = docx.finish().unwrap().into_inner();
+    let upload = |file: reqwest::multipart::Part, count: &str| {
+        client
+            .post(format!("{}/api/v1/ai/generate-from-file", base))
+            .headers(csrf_headers(&csrf))
+            .multipart(reqwest::multipart::Form::new().part("file", file).text("questionCount", count.to_string()))
+            .send()
+    };
+
+    let resp = upload(reqwest::multipart::Part::bytes(docx.clone()).file_name("Лист.docx"), "2").await.unwrap();
+    assert_eq!(resp.status(), 202);
+    let body = resp.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(body["sourceChars"], "Фотосинтез у растений\nРастения используют энергию света & углекислый газ.".chars().count());
+    assert_eq!(body["truncated"], false);
+    let job = wait_ai_job(&base, &client, body["job"]["id"].as_str().unwrap()).await;
+    assert_eq!(job["status"], "succeeded");
+    let quiz = client
+        .get(format!("{}/api/v1/quizzes/{}", base, job["quizId"]))
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    assert_eq!(quiz["title"], "Квиз: Фотосинтез у растений");
+    assert_eq!(quiz["questions"].as_array().unwrap().len(), 2);
+
+    let resp = upload(reqwest::multipart::Part::bytes(b"text".to_vec()).file_name("notes.txt"), "2").await.unwrap();
+    assert_eq!(resp.status(), 400);
+    assert_eq!(resp.json::<serde_json::Value>().await.unwrap()["error"]["details"][0]["field"], "file");
+    let resp = upload(reqwest::multipart::Part::bytes(docx).file_name("Лист.docx"), "0").await.unwrap();
+    assert_eq!(resp.json::<serde_json::Value>().await.unwrap()["error"]["details"][0]["field"], "questionCount");
+    let resp = upload(reqwest::multipart::Part::bytes(b"not a zip".to_vec()).file_name("broken.docx"), "2").await.unwrap();
+    assert_eq!(resp.status(), 400);
+}
+
+#[tokio::test]
+async fn ai_jobs_run_in_background() {
+    let (base, client) = spawn_server().await;
+    let csrf = auth(&base, &client, "ai_jobs_teacher").await;
+    let enqueue = |payload: serde_json::Value| {
+        client.post(format!("{}/api/v1/ai/jobs", base)).headers(csrf_headers(&csrf)).json(&payload).send()
+    };
+    let resp = enqueue(json!({"topic": "География", "questionCount": 2})).await.unwrap();
+    assert_eq!(resp.status(), 202);
+    let job = resp.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(job["status"], "queued");
+    assert_eq!(job["provider"], "mock");
+    let job = wait_ai_job(&base, &client, job["id"].as_str().unwrap()).await;
+    assert_eq!(job["status"], "succeeded");
+    assert!(job["error"].is_null());
+    let quiz = client.get(format!("{}/api/v1/quizzes/{}", base, job["quizId"])).send().await.unwrap();
+    assert_eq!(quiz.json::<serde_json::Value>().await.unwrap()["questions"].as_array().unwrap().len(), 2);
+
+    // More questions than QUIZ_MAX_QUESTIONS allows fails validation inside the job.
+    let resp = enqueue(json!({"topic": "География", "questionCount": 500})).await.unwrap();
+    let failed = wait_ai_job(&base, &client, resp.json::<serde_json::Value>().await.unwrap()["id"].as_str().unwrap()).await;
+    assert_eq!(failed["status"], "failed");
+    assert_eq!(failed["error"]["code"], "VALIDATION_ERROR");
+    assert_eq!(failed["error"]["details"][0]["field"], "questions");
+    assert!(failed["quizId"].is_null());
+
+    let resp = enqueue(json!({"topic": "География", "questionCount": 1, "provider": {"name": "openai"}})).await.unwrap();
+    assert_eq!(resp.status(), 400);
+
+    let other = reqwest::Client::builder().cookie_store(true).build().unwrap();
+    auth(&base, &other, "ai_jobs_stranger").await;
+    let hidden = other.get(format!("{}/api/v1/ai/jobs/{}", base, job["id"].as_str().unwrap())).send().await.unwrap();
+    assert_eq!(hidden.status(), 404);
+}
+
+#[tokio::test]
+async fn ai_usage_is_counted_against_daily_quota() {
+    // Per teacher, so other tests sharing the process stay well below it.
+    std::env::set_var("AI_DAILY_REQUEST_QUOTA", "20");
+    let (base, client) = spawn_server().await;
+    let csrf = auth(&base, &client, "ai_quota_teacher").await;
+    let usage = || async {
+        client.get(format!("{}/api/v1/ai/usage", base)).send().await.unwrap().json::<serde_json::Value>().await.unwrap()
+    };
+    let generate = || {
+        client
+            .post(format!("{}/api/v1/ai/generate-quiz", base))
+            .headers(csrf_headers(&csrf))
+            .json(&json!({"topic": "Химия", "questionCount": 1}))
+            .send()
+    };
+
+    let body = usage().await;
+    assert_eq!(body["today"]["requests"], 0);
+    assert_eq!(body["quota"]["dailyRequests"], 20);
+    assert!(body["quota"]["dailyTokens"].is_null());
+
+    assert_eq!(generate().await.unwrap().status(), 201);
+    let body = usage().await;
+    assert_eq!(body["today"]["requests"], 1);
+    assert!(body["today"]["promptTokens"].as_u64().unwrap() > 0);
+    assert!(body["today"]["completionTokens"].as_u64().unwrap() > 0);
+    assert_eq!(body["remaining"]["requests"], 19);
+    assert_eq!(body["items"].as_array().unwrap().len(), 1);
+
+    for _ in 1..20 {
+        assert_eq!(generate().await.unwrap().status(), 201);
+    }
+    let resp = generate().await.unwrap();
+    assert_eq!(resp.status(), 429);
+    let body = resp.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(body["error"]["code"], "RATE_LIMITED");
+    assert_eq!(body["error"]["details"][0]["field"], "requests");
+    assert_eq!(usage().await["remaining"]["requests"], 0);
+
+    let other = reqwest::Client::builder().cookie_store(true).build().unwrap();
+    let other_csrf = auth(&base, &other, "ai_quota_other").await;
+    let resp = other
+        .post(format!("{}/api/v1/ai/generate-quiz", base))
+        .headers(csrf_headers(&other_csrf))
+        .json(&json!({"topic": "Химия", "questionCount": 1}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 201);
+}
+
+#[tokio::test]
+async fn ws_reports_unknown_and_invalid_messages() {
+    let (base, client) = spawn_server().await;
+    let csrf = auth(&base, &client, "ws_protocol_teacher").await;
+    let create_quiz = client
+        .post(format!("{}/api/v1/quizzes", base))
+        .headers(csrf_headers(&csrf))
+        .json(&sample_quiz_payload())
+        .send()
+        .await
+        .unwrap();
+    let quiz_id = create_quiz.json::<serde_json::Value>().await.unwrap()["quiz_id"].as_i64().unwrap();
+    let session = client
+        .post(format!("{}/api/v1/sessions", base))
+        .headers(csrf_headers(&csrf))
+        .json(&json!({"quizId": quiz_id, "gameMode": "platformer"}))
+        .send()
+        .await
+        .unwrap();
+    let session = session.json::<serde_json::Value>().await.unwrap();
+    let room = session["roomCode"].as_str().unwrap().to_string();
+    let join_token = session["joinToken"].as_str().unwrap().to_string();
+    let ws_url = base.replace("http://", "ws://");
+    let (mut ws, _) = tokio_tungstenite::connect_async(format!("{}/ws/sessions/{}", ws_url, room)).await.unwrap();
+
+    ws.send(Message::Text("not json".into())).await.unwrap();
+    assert_eq!(next_event(&mut ws, "error").await["code"], "invalid_message");
+
+    ws.send(Message::Text(json!({"event":"dance","payload":{},"request_id":"r1"}).to_string())).await.unwrap();
+    let msg = ws.next().await.unwrap().unwrap().into_text().unwrap();
+    let msg: serde_json::Value = serde_json::from_str(&msg).unwrap();
+    assert_eq!(msg["event"], "error");
+    assert_eq!(msg["payload"]["code"], "unknown_event");
+    assert_eq!(msg["request_id"], "r1");
+
+    let submit = json!({"event":"answer_submit","payload":{"questionId":"q1","answer":{"text":"4"}}}).to_string();
+    ws.send(Message::Text(submit)).await.unwrap();
+    assert_eq!(next_event(&mut ws, "error").await["code"], "not_joined");
+
+    ws.send(Message::Text(json!({"event":"join_room","payload":{"nickname":" ","joinToken": join_token}}).to_string())).await.unwrap();
+    assert_eq!(next_event(&mut ws, "join_rejected").await["reason"], "invalid_nickname");
+    ws.send(Message::Text(json!({"event":"join_room","payload":{"nickname":"Юля","joinToken": join_token}}).to_string())).await.unwrap();
+    next_event(&mut ws, "waiting_room_update").await;
+
+    ws.send(Message::Text(json!({"event":"answer_submit","payload":{"questionId":"q1"}}).to_string())).await.unwrap();
+    let error = next_event(&mut ws, "error").await;
+    assert_eq!(error["code"], "invalid_payload");
+    assert!(error["message"].as_str().unwrap().contains("answer"));
+
+    ws.send(Message::Text(json!({"event":"request_stats"}).to_string())).await.unwrap();
+    let stats = next_event(&mut ws, "stats_update").await;
+    assert_eq!(stats["students"][0]["nickname"], "Юля");
+}
+
+#[tokio::test]
+async fn ws_contract_is_published() {
+    let (base, client) = spawn_server().await;
+    let res = client.get(format!("{}/api/v1/contracts/ws", base)).send().await.unwrap();
+    assert_eq!(res.status(), 200);
+    assert_eq!(res.headers()["content-type"], "application/schema+json");
+    let schema = res.json::<serde_json::Value>().await.unwrap();
+    let events = schema["$defs"]["serverEvent"]["enum"].as_array().unwrap();
+    assert!(events.contains(&json!("question_push")));
+    assert!(schema["$defs"]["answerSubmit"]["required"].as_array().unwrap().contains(&json!("answer")));
+}
+
+#[tokio::test]
+async fn joining_requires_the_current_join_token() {
+    let (base, client) = spawn_server().await;
+    let csrf = auth(&base, &client, "join_token_teacher").await;
+    let create_quiz = client
+        .post(format!("{}/api/v1/quizzes", base))
+        .headers(csrf_headers(&csrf))
+        .json(&sample_quiz_payload())
+        .send()
+        .await
+        .unwrap();
+    let quiz_id = create_quiz.json::<serde_json::Value>().await.unwrap()["quiz_id"].as_i64().unwrap();
+    let session = client
+        .post(format!("{}/api/v1/sessions", base))
+        .headers(csrf_headers(&csrf))
+        .json(&json!({"quizId": quiz_id, "gameMode": "classic"}))
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    let session_id = session["sessionId"].as_i64().unwrap();
+    let room = session["roomCode"].as_str().unwrap().to_string();
+    let old_token = session["joinToken"].as_str().unwrap().to_string();
+    assert!(session["joinUrl"].as_str().unwrap().ends_with(&format!("token={}", old_token)));
+    let ws_url = base.replace("http://", "ws://");
+    let (mut ws, _) = tokio_tungstenite::connect_async(format!("{}/ws/sessions/{}", ws_url, room)).await.unwrap();
+
+    ws.send(Message::Text(json!({"event":"join_room","payload":{"nickname":"Оля"}}).to_string())).await.unwrap();
+    assert_eq!(next_event(&mut ws, "join_rejected").await["reason"], "invalid_join_token");
+    let guessed = json!({"event":"join_room","payload":{"nickname":"Оля","joinToken":"guess"}}).to_string();
+    ws.send(Message::Text(guessed)).await.unwrap();
+    assert_eq!(next_event(&mut ws, "join_rejected").await["reason"], "invalid_join_token");
+
+    let rotated = client
+        .post(format!("{}/api/v1/sessions/{}/join-token", base, session_id))
+        .headers(csrf_headers(&csrf))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(rotated.status(), 200);
+    let new_token = rotated.json::<serde_json::Value>().await.unwrap()["joinToken"].as_str().unwrap().to_string();
+    assert_ne!(new_token, old_token);
+
+    let stale = json!({"event":"join_room","payload":{"nickname":"Оля","joinToken": old_token}}).to_string();
+    ws.send(Message::Text(stale)).await.unwrap();
+    assert_eq!(next_event(&mut ws, "join_rejected").await["reason"], "invalid_join_token");
+    let fresh = json!({"event":"join_room","payload":{"nickname":"Оля","joinToken": new_token}}).to_string();
+    ws.send(Message::Text(fresh)).await.unwrap();
+    let update = next_event(&mut ws, "waiting_room_update").await;
+    assert_eq!(update["participants"][0]["nickname"], "Оля");
+}
+
+#[tokio::test]
+async fn teacher_controls_the_room_over_websocket() {
+    use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+
+    let (base, client) = spawn_server().await;
+    auth(&base, &client, "ws_teacher").await;
+    // Log in once more by hand to learn the session cookie the browser would send on the upgrade.
+    let login = client
+        .post(format!("{}/api/v1/auth/login", base))
+        .json(&json!({"login": "ws_teacher", "password": "password123"}))
+        .send()
+        .await
+        .unwrap();
+    let cookie = |name: &str| login.cookies().find(|c| c.name() == name).map(|c| c.value().to_string()).unwrap();
+    let (sid, csrf) = (cookie("teacher_session"), cookie("csrf_token"));
+    let create_quiz = client
+        .post(format!("{}/api/v1/quizzes", base))
+        .headers(csrf_headers(&csrf))
+        .json(&sample_quiz_payload())
+        .send()
+        .await
+        .unwrap();
+    let quiz_id = create_quiz.json::<serde_json::Value>().await.unwrap()["quiz_id"].as_i64().unwrap();
+    let session = client
+        .post(format!("{}/api/v1/sessions", base))
+        .headers(csrf_headers(&csrf))
+        .json(&json!({"quizId": quiz_id, "gameMode": "classic"}))
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    let session_id = session["sessionId"].as_i64().unwrap();
+    let room = session["roomCode"].as_str().unwrap().to_string();
+    let join_token = session["joinToken"].as_str().unwrap().to_string();
+    let ws_url = format!("{}/ws/sessions/{}", base.replace("http://", "ws://"), room);
+
+    let mut request = ws_url.as_str().into_client_request().unwrap();
+    request.headers_mut().insert("cookie", format!("teacher_session={}", sid).parse().unwrap());
+    let (mut teacher, _) = tokio_tungstenite::connect_async(request).await.unwrap();
+    let (mut olya, _) = tokio_tungstenite::connect_async(&ws_url).await.unwrap();
+    let (mut petya, _) = tokio_tungstenite::connect_async(&ws_url).await.unwrap();
+
+    // The cookie alone is not enough: the CSRF token has to be repeated in the payload.
+    teacher.send(Message::Text(json!({"event":"join_room","payload":{"role":"teacher"}}).to_string())).await.unwrap();
+    assert_eq!(next_event(&mut teacher, "join_rejected").await["reason"], "forbidden");
+    let join_teacher = json!({"event":"join_room","payload":{"role":"teacher","csrf": csrf}}).to_string();
+    olya.send(Message::Text(join_teacher.clone())).await.unwrap();
+    assert_eq!(next_event(&mut olya, "join_rejected").await["reason"], "forbidden");
+    teacher.send(Message::Text(join_teacher)).await.unwrap();
+    next_event(&mut teacher, "waiting_room_update").await;
+
+    for (ws, nickname) in [(&mut olya, "Оля"), (&mut petya, "Петя")] {
+        let join = json!({"event":"join_room","payload":{"nickname": nickname,"joinToken": join_token}});
+        ws.send(Message::Text(join.to_string())).await.unwrap();
+        next_event(ws, "waiting_room_update").await;
+    }
+    petya.send(Message::Text(json!({"event":"pause_session"}).to_string())).await.unwrap();
+    assert_eq!(next_event(&mut petya, "error").await["code"], "forbidden");
+
+    teacher.send(Message::Text(json!({"event":"kick_participant","payload":{"nickname":"Петя"}}).to_string())).await.unwrap();
+    assert_eq!(next_event(&mut petya, "kicked").await["nickname"], "Петя");
+    assert!(matches!(petya.next().await, None | Some(Err(_)) | Some(Ok(Message::Close(_)))));
+    // Two updates from the students joining, then the one without Петя.
+    next_event(&mut teacher, "waiting_room_update").await;
+    next_event(&mut teacher, "waiting_room_update").await;
+    let update = next_event(&mut teacher, "waiting_room_update").await;
+    assert_eq!(update["participants"], json!([{"nickname": "Оля", "state": "waiting"}]));
+    let (mut petya, _) = tokio_tungstenite::connect_async(&ws_url).await.unwrap();
+    let rejoin = json!({"event":"join_room","payload":{"nickname":"Петя","joinToken": join_token}});
+    petya.send(Message::Text(rejoin.to_string())).await.unwrap();
+    assert_eq!(next_event(&mut petya, "join_rejected").await["reason"], "kicked");
+
+    client
+        .post(format!("{}/api/v1/sessions/{}/start", base, session_id))
+        .headers(csrf_headers(&csrf))
+        .send()
+        .await
+        .unwrap();
+    teacher.send(Message::Text(json!({"event":"pause_session"}).to_string())).await.unwrap();
+    next_event(&mut olya, "session_paused").await;
+    olya.send(Message::Text(json!({"event":"request_question"}).to_string())).await.unwrap();
+    assert_eq!(next_event(&mut olya, "error").await["code"], "session_paused");
+    teacher.send(Message::Text(json!({"event":"pause_session"}).to_string())).await.unwrap();
+    assert_eq!(next_event(&mut teacher, "error").await["code"], "invalid_state");
+    teacher.send(Message::Text(json!({"event":"resume_session"}).to_string())).await.unwrap();
+    next_event(&mut olya, "session_resumed").await;
+
+    teacher.send(Message::Text(json!({"event":"push_next_question"}).to_string())).await.unwrap();
+    let pushed = next_event(&mut olya, "question_push").await;
+    assert_eq!(pushed["reason"], "teacher");
+    assert_eq!(pushed["question"]["id"], "q1");
+}
+
+#[tokio::test]
+async fn lobby_settings_limit_who_joins() {
+    let (base, client) = spawn_server().await;
+    let csrf = auth(&base, &client, "lobby_teacher").await;
+    let quiz_id = client
+        .post(format!("{}/api/v1/quizzes", base))
+        .headers(csrf_headers(&csrf))
+        .json(&sample_quiz_payload())
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap()["quiz_id"]
+        .as_i64()
+        .unwrap();
+    let create = |lobby: serde_json::Value| {
+        client
+            .post(format!("{}/api/v1/sessions", base))
+            .headers(csrf_headers(&csrf))
+            .json(&json!({"quizId": quiz_id, "gameMode": "classic", "lobby": lobby}))
+            .send()
+    };
+    let invalid = create(json!({"nicknameMinLength": 10, "nicknameMaxLength": 5})).await.unwrap();
+    assert_eq!(invalid.status(), 400);
+    let lobby = json!({"maxParticipants": 2, "allowLateJoin": false, "nicknameMaxLength": 10, "nicknameCharset": "alphanumeric"});
+    let session = create(lobby).await.unwrap().json::<serde_json::Value>().await.unwrap();
+    assert_eq!(session["lobby"]["maxParticipants"], 2);
+    assert_eq!(session["lobby"]["nicknameMinLength"], 2);
+    let session_id = session["sessionId"].as_i64().unwrap();
+    let room = session["roomCode"].as_str().unwrap().to_string();
+    let join_token = session["joinToken"].as_str().unwrap().to_string();
+    let ws_url = format!("{}/ws/sessions/{}", base.replace("http://", "ws://"), room);
+    let join = |nickname: &str| {
+        Message::Text(json!({"event":"join_room","payload":{"nickname": nickname,"joinToken": join_token}}).to_string())
+    };
+
+    let (mut ws, _) = tokio_tungstenite::connect_async(&ws_url).await.unwrap();
+    for nickname in ["Оля!", "Очень длинный ник"] {
+        ws.send(join(nickname)).await.unwrap();
+        let rejected = next_event(&mut ws, "join_rejected").await;
+        assert_eq!(rejected["reason"], "invalid_nickname");
+        assert!(rejected["message"].is_string());
+    }
+    ws.send(join("Оля")).await.unwrap();
+    let resume_token = next_event(&mut ws, "joined").await["resumeToken"].clone();
+    let (mut petya, _) = tokio_tungstenite::connect_async(&ws_url).await.unwrap();
+    petya.send(join("Петя")).await.unwrap();
+    next_event(&mut petya, "joined").await;
+    let (mut vika, _) = tokio_tungstenite::connect_async(&ws_url).await.unwrap();
+    vika.send(join("Вика")).await.unwrap();
+    assert_eq!(next_event(&mut vika, "join_rejected").await["reason"], "room_full");
+
+    client
+        .post(format!("{}/api/v1/sessions/{}/start", base, session_id))
+        .headers(csrf_headers(&csrf))
+        .send()
+        .await
+        .unwrap();
+    vika.send(join("Вика")).await.unwrap();
+    assert_eq!(next_event(&mut vika, "join_rejected").await["reason"], "late_join_closed");
+    // A nickname in the room cannot be taken over from another socket; its owner gets back in with the
+    // resume token, which keeps working.
+    let (mut olya, _) = tokio_tungstenite::connect_async(&ws_url).await.unwrap();
+    olya.send(join("Оля")).await.unwrap();
+    assert_eq!(next_event(&mut olya, "join_rejected").await["reason"], "nickname_taken");
+    let resume = json!({"event":"join_room","payload":{"joinToken": join_token,"resumeToken": resume_token}});
+    olya.send(Message::Text(resume.to_string())).await.unwrap();
+    let resumed = next_event(&mut olya, "resumed").await;
+    assert_eq!((resumed["nickname"].clone(), resumed["resumeToken"].clone()), (json!("Оля"), resume_token));
+}
+
+#[tokio::test]
+async fn game_config_sets_lives_penalties_and_time_pressure() {
+    let (base, client) = spawn_server().await;
+    let csrf = auth(&base, &client, "game_config_teacher").await;
+    let quiz_id = client
+        .post(format!("{}/api/v1/quizzes", base))
+        .headers(csrf_headers(&csrf))
+        .json(&sample_quiz_payload())
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap()["quiz_id"]
+        .as_i64()
+        .unwrap();
+    let create = |config: serde_json::Value| {
+        client
+            .post(format!("{}/api/v1/sessions", base))
+            .headers(csrf_headers(&csrf))
+            .json(&json!({"quizId": quiz_id, "gameMode": "shooter", "gameConfig": config}))
+            .send()
+    };
+    assert_eq!(create(json!({"lives": 0})).await.unwrap().status(), 400);
+    let config = json!({
+        "lives": 2, "respawnPenalty": 0.5, "cycleQuestions": true, "timePressureSecs": 10, "disconnectGraceSecs": 0
+    });
+    let session = create(config.clone()).await.unwrap().json::<serde_json::Value>().await.unwrap();
+    assert_eq!(session["gameConfig"], config);
+    let session_id = session["sessionId"].as_i64().unwrap();
+    let room = session["roomCode"].as_str().unwrap().to_string();
+    let join_token = session["joinToken"].as_str().unwrap().to_string();
+
+    let ws_url = format!("{}/ws/sessions/{}", base.replace("http://", "ws://"), room);
+    let (mut ws, _) = tokio_tungstenite::connect_async(&ws_url).await.unwrap();
+    let join = json!({"event":"join_room","payload":{"nickname":"Оля","joinToken": join_token}});
+    ws.send(Message::Text(join.to_string())).await.unwrap();
+    next_event(&mut ws, "joined").await;
+    client
+        .post(format!("{}/api/v1/sessions/{}/start", base, session_id))
+        .headers(csrf_headers(&csrf))
+        .send()
+        .await
+        .unwrap();
+
+    // Questions without a limit of their own get the mode's.
+    let request = Message::Text(json!({"event":"request_question"}).to_string());
+    ws.send(request.clone()).await.unwrap();
+    let pushed = next_event(&mut ws, "question_push").await;
+    assert_eq!(pushed["question"]["timeLimitSecs"], 10);
+    assert!(pushed["deadline"].is_string());
+    let wrong = |question: &str, answer: serde_json::Value| {
+        Message::Text(json!({"event":"answer_submit","payload":{"questionId": question,"answer": answer}}).to_string())
+    };
+    ws.send(wrong("q1", json!({"text": "5"}))).await.unwrap();
+    let first = next_event(&mut ws, "answer_result").await;
+    assert_eq!((&first["livesLeft"], &first["nextAction"]), (&json!(1), &json!("continue")));
+    ws.send(request.clone()).await.unwrap();
+    next_event(&mut ws, "question_push").await;
+    ws.send(wrong("q2", json!({"optionId": "o2"}))).await.unwrap();
+    let second = next_event(&mut ws, "answer_result").await;
+    assert_eq!((&second["livesLeft"], &second["nextAction"]), (&json!(0), &json!("out")));
+    ws.send(request).await.unwrap();
+    assert_eq!(next_event(&mut ws, "error").await["code"], "out_of_lives");
+    ws.send(wrong("q3", json!({"optionIds": ["o1", "o3"]}))).await.unwrap();
+    assert_eq!(next_event(&mut ws, "error").await["code"], "out_of_lives");
+
+    let results = client
+        .get(format!("{}/api/v1/sessions/{}/results", base, session_id))
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    let student = &results["studentStats"][0];
+    assert_eq!((&student["wrong"], &student["penalty"], &student["points"]), (&json!(2), &json!(1.0), &json!(-1.0)));
+}
+
+#[tokio::test]
+async fn homework_is_played_over_rest() {
+    let (base, client) = spawn_server().await;
+    let csrf = auth(&base, &client, "homework_teacher").await;
+    let quiz_id = client
+        .post(format!("{}/api/v1/quizzes", base))
+        .headers(csrf_headers(&csrf))
+        .json(&sample_quiz_payload())
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap()["quiz_id"]
+        .as_i64()
+        .unwrap();
+    let create = |body: serde_json::Value| {
+        client
+            .post(format!("{}/api/v1/sessions", base))
+            .headers(csrf_headers(&csrf))
+            .json(&body)
+            .send()
+    };
+    let now = chrono::Utc::now();
+    let window = |opens: chrono::Duration, closes: chrono::Duration| {
+        json!({"opensAt": (now + opens).to_rfc3339(), "closesAt": (now + closes).to_rfc3339()})
+    };
+    let past = window(chrono::Duration::hours(-2), chrono::Duration::hours(-1));
+    let body = json!({"quizId": quiz_id, "gameMode": "classic", "homework": past});
+    assert_eq!(create(body).await.unwrap().status(), 400);
+    let open = window(chrono::Duration::zero(), chrono::Duration::days(7));
+    let body = json!({"quizId": quiz_id, "gameMode": "classic", "pacing": "host", "homework": open});
+    assert_eq!(create(body).await.unwrap().status(), 400);
+
+    let later = window(chrono::Duration::days(1), chrono::Duration::days(7));
+    let session = create(json!({"quizId": quiz_id, "gameMode": "classic", "homework": later}))
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    let not_open = client
+        .post(format!("{}/api/v1/homework/{}/join", base, session["roomCode"].as_str().unwrap()))
+        .json(&json!({"joinToken": session["joinToken"], "nickname": "Оля"}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(not_open.status(), 403);
+
+    let session = create(json!({"quizId": quiz_id, "gameMode": "classic", "homework": open}))
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    let session_id = session["sessionId"].as_i64().unwrap();
+    let homework = format!("{}/api/v1/homework/{}", base, session["roomCode"].as_str().unwrap());
+    let join = |join_token: &serde_json::Value| {
+        client
+            .post(format!("{}/join", homework))
+            .json(&json!({"joinToken": join_token, "nickname": "Оля"}))
+            .send()
+    };
+    assert_eq!(join(&json!("wrong")).await.unwrap().status(), 403);
+    let joined = join(&session["joinToken"]).await.unwrap();
+    assert_eq!(joined.status(), 201);
+    let joined = joined.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(joined["questionCount"], 3);
+    assert_eq!(join(&session["joinToken"]).await.unwrap().status(), 409);
+    let token = joined["participantToken"].as_str().unwrap().to_string();
+
+    let unauthorized = client.get(format!("{}/question", homework)).send().await.unwrap();
+    assert_eq!(unauthorized.status(), 401);
+    let question = || {
+        client
+            .get(format!("{}/question", homework))
+            .header("x-participant-token", &token)
+            .send()
+    };
+    let answer = |question: &str, answer: serde_json::Value| {
+        client
+            .post(format!("{}/answers", homework))
+            .header("x-participant-token", &token)
+            .json(&json!({"questionId": question, "answer": answer}))
+            .send()
+    };
+    let first = question().await.unwrap().json::<serde_json::Value>().await.unwrap();
+    assert_eq!(first["question"]["id"], "q1");
+    // Asking again does not skip or restart the question.
+    let again = question().await.unwrap().json::<serde_json::Value>().await.unwrap();
+    assert_eq!((&again["question"]["id"], &again["reason"]), (&json!("q1"), &json!("pending")));
+    for (id, submitted) in [
+        ("q1", json!({"text": "4"})),
+        ("q2", json!({"optionId": "o2"})),
+        ("q3", json!({"optionIds": ["o1", "o3"]})),
+    ] {
+        if id != "q1" {
+            let pushed = question().await.unwrap().json::<serde_json::Value>().await.unwrap();
+            assert_eq!(pushed["question"]["id"], id);
+        }
+        let result = answer(id, submitted).await.unwrap().json::<serde_json::Value>().await.unwrap();
+        assert_eq!(result["correct"], id != "q2");
+    }
+    let duplicate = answer("q1", json!({"text": "4"})).await.unwrap().json::<serde_json::Value>().await.unwrap();
+    assert_eq!(duplicate["duplicate"], true);
+    let finished = question().await.unwrap().json::<serde_json::Value>().await.unwrap();
+    assert_eq!(finished["finished"], true);
+
+    let results = client
+        .get(format!("{}/api/v1/sessions/{}/results", base, session_id))
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    let student = &results["studentStats"][0];
+    assert_eq!((&student["nickname"], &student["correct"], &student["wrong"]), (&json!("Оля"), &json!(2), &json!(1)));
+}
+
+#[tokio::test]
+async fn practice_runs_score_a_published_quiz_and_sum_up_mistakes() {
+    let (base, client) = spawn_server().await;
+    let csrf = auth(&base, &client, "practice_teacher").await;
+    let quiz_id = client
+        .post(format!("{}/api/v1/quizzes", base))
+        .headers(csrf_headers(&csrf))
+        .json(&sample_quiz_payload())
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap()["quiz_id"]
+        .as_i64()
+        .unwrap();
+    let start = || client.post(format!("{}/api/v1/practice/{}/start", base, quiz_id)).send();
+    assert_eq!(start().await.unwrap().status(), 409);
+    client
+        .post(format!("{}/api/v1/quizzes/{}/publish", base, quiz_id))
+        .headers(csrf_headers(&csrf))
+        .send()
+        .await
+        .unwrap();
+    let run = start().await.unwrap();
+    assert_eq!(run.status(), 201);
+    let run = run.json::<serde_json::Value>().await.unwrap();
+    assert_eq!((&run["questionCount"], &run["question"]["question"]["id"]), (&json!(3), &json!("q1")));
+    let runs = format!("{}/api/v1/practice/runs/{}", base, run["runId"]);
+    let token = run["participantToken"].as_str().unwrap().to_string();
+
+    let stranger = client.get(format!("{}/question", runs)).header("x-participant-token", "nope").send().await;
+    assert_eq!(stranger.unwrap().status(), 401);
+    for (id, submitted) in [
+        ("q1", json!({"text": "5"})),
+        ("q2", json!({"optionId": "o1"})),
+        ("q3", json!({"optionIds": ["o1", "o3"]})),
+    ] {
+        let pushed = client
+            .get(format!("{}/question", runs))
+            .header("x-participant-token", &token)
+            .send()
+            .await
+            .unwrap()
+            .json::<serde_json::Value>()
+            .await
+            .unwrap();
+        assert_eq!(pushed["question"]["id"], id);
+        let result = client
+            .post(format!("{}/answers", runs))
+            .header("x-participant-token", &token)
+            .json(&json!({"questionId": id, "answer": submitted}))
+            .send()
+            .await
+            .unwrap()
+            .json::<serde_json::Value>()
+            .await
+            .unwrap();
+        assert_eq!(result["correct"], id != "q1");
+    }
+    let finished = client
+        .get(format!("{}/question", runs))
+        .header("x-participant-token", &token)
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    assert_eq!(finished["finished"], true);
+    let summary = &finished["summary"];
+    assert_eq!((&summary["correct"], &summary["wrong"], &summary["maxScore"]), (&json!(2), &json!(1), &json!(3.0)));
+    let mistakes = summary["mistakes"].as_array().unwrap();
+    assert_eq!(mistakes.len(), 1);
+    assert_eq!(mistakes[0]["questionId"], "q1");
+    assert_eq!(mistakes[0]["yourAnswer"], json!({"text": "5"}));
+    assert_eq!(mistakes[0]["correctAnswer"]["text"], "4");
+
+    // The run belongs to nobody's session list.
+    let sessions = client.get(format!("{}/api/v1/sessions", base)).send().await.unwrap();
+    assert_eq!(sessions.json::<serde_json::Value>().await.unwrap()["total"], 0);
+}
+
+#[tokio::test]
+async fn pausing_over_http_freezes_question_deadlines() {
+    let (base, client) = spawn_server().await;
+    let csrf = auth(&base, &client, "pause_teacher").await;
+    let mut quiz = sample_quiz_payload();
+    quiz["questions"][0]["timeLimitSecs"] = json!(5);
+    let quiz_id = client
+        .post(format!("{}/api/v1/quizzes", base))
+        .headers(csrf_headers(&csrf))
+        .json(&quiz)
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap()["quiz_id"]
+        .as_i64()
+        .unwrap();
+    let session = client
+        .post(format!("{}/api/v1/sessions", base))
+        .headers(csrf_headers(&csrf))
+        .json(&json!({"quizId": quiz_id, "gameMode": "classic"}))
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    let session_id = session["sessionId"].as_i64().unwrap();
+    let room = session["roomCode"].as_str().unwrap().to_string();
+    let join_token = session["joinToken"].as_str().unwrap().to_string();
+    let ws_url = format!("{}/ws/sessions/{}", base.replace("http://", "ws://"), room);
+    let session_url = format!("{}/api/v1/sessions/{}", base, session_id);
+
+    let (mut ws, _) = tokio_tungstenite::connect_async(&ws_url).await.unwrap();
+    let join = json!({"event":"join_room","payload":{"nickname":"Оля","joinToken": join_token}});
+    ws.send(Message::Text(join.to_string())).await.unwrap();
+    let resume_token = next_event(&mut ws, "joined").await["resumeToken"].as_str().unwrap().to_string();
+    let pause = client.post(format!("{}/pause", session_url)).headers(csrf_headers(&csrf)).send().await.unwrap();
+    assert_eq!(pause.status(), 409);
+    client.post(format!("{}/start", session_url)).headers(csrf_headers(&csrf)).send().await.unwrap();
+    ws.send(Message::Text(json!({"event":"request_question"}).to_string())).await.unwrap();
+    let pushed = next_event(&mut ws, "question_push").await;
+    let deadline = chrono::DateTime::parse_from_rfc3339(pushed["deadline"].as_str().unwrap()).unwrap();
+
+    let pause = client.post(format!("{}/pause", session_url)).headers(csrf_headers(&csrf)).send().await.unwrap();
+    assert_eq!(pause.status(), 200);
+    assert_eq!(pause.json::<serde_json::Value>().await.unwrap()["status"], "paused");
+    next_event(&mut ws, "session_paused").await;
+    let submit = json!({"event":"answer_submit","payload":{"questionId":"q1","answer":{"text":"4"}}}).to_string();
+    ws.send(Message::Text(submit.clone())).await.unwrap();
+    assert_eq!(next_event(&mut ws, "error").await["code"], "session_paused");
+    tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+
+    let resume = client.post(format!("{}/resume", session_url)).headers(csrf_headers(&csrf)).send().await.unwrap();
+    assert_eq!(resume.json::<serde_json::Value>().await.unwrap()["status"], "active");
+    let resumed = next_event(&mut ws, "session_resumed").await;
+    let paused_ms = resumed["pausedMs"].as_i64().unwrap();
+    assert!(paused_ms >= 300);
+    let resume = client.post(format!("{}/resume", session_url)).headers(csrf_headers(&csrf)).send().await.unwrap();
+    assert_eq!(resume.status(), 409);
+
+    // The pause moved the deadline on; a reconnect sees the new one.
+    let (mut again, _) = tokio_tungstenite::connect_async(&ws_url).await.unwrap();
+    let rejoin = json!({"event":"join_room","payload":{"joinToken": join_token,"resumeToken": resume_token}});
+    again.send(Message::Text(rejoin.to_string())).await.unwrap();
+    let snapshot = next_event(&mut again, "resumed").await;
+    let moved = chrono::DateTime::parse_from_rfc3339(snapshot["deadline"].as_str().unwrap()).unwrap();
+    assert_eq!((moved - deadline).num_milliseconds(), paused_ms);
+    again.send(Message::Text(submit)).await.unwrap();
+    let result = next_event(&mut again, "answer_result").await;
+    assert_eq!(result["correct"], true);
+    assert!(result.get("late").is_none());
+}
+
+#[tokio::test]
+async fn disconnect_grace_holds_the_question_and_records_the_drop() {
+    let (base, client) = spawn_server().await;
+    let csrf = auth(&base, &client, "disconnect_grace_teacher").await;
+    let mut quiz = sample_quiz_payload();
+    quiz["questions"][0]["timeLimitSecs"] = json!(5);
+    let quiz_id = client
+        .post(format!("{}/api/v1/quizzes", base))
+        .headers(csrf_headers(&csrf))
+        .json(&quiz)
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap()["quiz_id"]
+        .as_i64()
+        .unwrap();
+    let create = |grace: u32| {
+        client
+            .post(format!("{}/api/v1/sessions", base))
+            .headers(csrf_headers(&csrf))
+            .json(&json!({"quizId": quiz_id, "gameMode": "classic", "gameConfig": {"disconnectGraceSecs": grace}}))
+            .send()
+    };
+    assert_eq!(create(301).await.unwrap().status(), 400);
+    let session = create(30).await.unwrap().json::<serde_json::Value>().await.unwrap();
+    assert_eq!(session["gameConfig"]["disconnectGraceSecs"], 30);
+    let session_url = format!("{}/api/v1/sessions/{}", base, session["sessionId"]);
+    let ws_url = format!("{}/ws/sessions/{}", base.replace("http://", "ws://"), session["roomCode"].as_str().unwrap());
+    let (mut ws, _) = tokio_tungstenite::connect_async(&ws_url).await.unwrap();
+    let join = json!({"event":"join_room","payload":{"nickname":"Оля","joinToken": session["joinToken"]}});
+    ws.send(Message::Text(join.to_string())).await.unwrap();
+    let resume_token = next_event(&mut ws, "joined").await["resumeToken"].clone();
+    client.post(format!("{}/start", session_url)).headers(csrf_headers(&csrf)).send().await.unwrap();
+    ws.send(Message::Text(json!({"event":"request_question"}).to_string())).await.unwrap();
+    let pushed = next_event(&mut ws, "question_push").await;
+    let deadline = chrono::DateTime::parse_from_rfc3339(pushed["deadline"].as_str().unwrap()).unwrap();
+    drop(ws);
+
+    // Away past the deadline: the question waits instead of timing out, and the deadline moves on by the
+    // time away once she is back.
+    let past_deadline = deadline.with_timezone(&chrono::Utc) - chrono::Utc::now() + chrono::Duration::seconds(1);
+    tokio::time::sleep(past_deadline.to_std().unwrap()).await;
+    let (mut ws, _) = tokio_tungstenite::connect_async(&ws_url).await.unwrap();
+    let resume = json!({"event":"join_room","payload":{"joinToken": session["joinToken"],"resumeToken": resume_token}});
+    ws.send(Message::Text(resume.to_string())).await.unwrap();
+    let snapshot = next_event(&mut ws, "resumed").await;
+    assert_eq!(snapshot["question"]["id"], "q1");
+    let moved = chrono::DateTime::parse_from_rfc3339(snapshot["deadline"].as_str().unwrap()).unwrap();
+    assert!(moved > chrono::Utc::now());
+    let submit = json!({"event":"answer_submit","payload":{"questionId":"q1","answer":{"text":"4"}}});
+    ws.send(Message::Text(submit.to_string())).await.unwrap();
+    let result = next_event(&mut ws, "answer_result").await;
+    assert_eq!(result["correct"], true);
+    assert!(result.get("late").is_none());
+
+    let results = client.get(format!("{}/results", session_url)).send().await.unwrap();
+    let results = results.json::<serde_json::Value>().await.unwrap();
+    let disruptions = results["studentStats"][0]["disruptions"].as_array().unwrap().clone();
+    assert_eq!(disruptions.len(), 1);
+    assert_eq!(disruptions[0]["questionId"], "q1");
+    assert!(disruptions[0]["reconnectedAt"].is_string());
+    assert_eq!(disruptions[0]["extendedMs"].as_i64().unwrap(), (moved - deadline).num_milliseconds());
+}
+
+#[tokio::test]
+async fn without_disconnect_grace_the_deadline_keeps_running() {
+    let (base, client) = spawn_server().await;
+    let csrf = auth(&base, &client, "no_grace_teacher").await;
+    let mut quiz = sample_quiz_payload();
+    quiz["questions"][0]["timeLimitSecs"] = json!(5);
+    let quiz_id = client
+        .post(format!("{}/api/v1/quizzes", base))
+        .headers(csrf_headers(&csrf))
+        .json(&quiz)
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap()["quiz_id"]
+        .as_i64()
+        .unwrap();
+    let session = client
+        .post(format!("{}/api/v1/sessions", base))
+        .headers(csrf_headers(&csrf))
+        .json(&json!({"quizId": quiz_id, "gameMode": "classic"}))
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    assert_eq!(session["gameConfig"]["disconnectGraceSecs"], 0);
+    let session_url = format!("{}/api/v1/sessions/{}", base, session["sessionId"]);
+    let ws_url = format!("{}/ws/sessions/{}", base.replace("http://", "ws://"), session["roomCode"].as_str().unwrap());
+    let (mut ws, _) = tokio_tungstenite::connect_async(&ws_url).await.unwrap();
+    let join = json!({"event":"join_room","payload":{"nickname":"Оля","joinToken": session["joinToken"]}});
+    ws.send(Message::Text(join.to_string())).await.unwrap();
+    let resume_token = next_event(&mut ws, "joined").await["resumeToken"].clone();
+    client.post(format!("{}/start", session_url)).headers(csrf_headers(&csrf)).send().await.unwrap();
+    ws.send(Message::Text(json!({"event":"request_question"}).to_string())).await.unwrap();
+    let pushed = next_event(&mut ws, "question_push").await;
+    let deadline = chrono::DateTime::parse_from_rfc3339(pushed["deadline"].as_str().unwrap()).unwrap();
+    drop(ws);
+
+    let past_deadline = deadline.with_timezone(&chrono::Utc) - chrono::Utc::now() + chrono::Duration::seconds(1);
+    tokio::time::sleep(past_deadline.to_std().unwrap()).await;
+    let (mut ws, _) = tokio_tungstenite::connect_async(&ws_url).await.unwrap();
+    let resume = json!({"event":"join_room","payload":{"joinToken": session["joinToken"],"resumeToken": resume_token}});
+    ws.send(Message::Text(resume.to_string())).await.unwrap();
+    let snapshot = next_event(&mut ws, "resumed").await;
+    assert!(snapshot["question"].is_null());
+    assert_eq!(snapshot["stats"]["wrong"], 1);
+
+    // The drop is on record all the same, with nothing added to the deadline.
+    let results = client.get(format!("{}/results", session_url)).send().await.unwrap();
+    let results = results.json::<serde_json::Value>().await.unwrap();
+    let disruption = &results["studentStats"][0]["disruptions"][0];
+    assert_eq!(disruption["questionId"], "q1");
+    assert_eq!(disruption["extendedMs"], 0);
+}
+
+#[tokio::test]
+async fn host_paced_sessions_move_through_questions_together() {
+    use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+
+    let (base, client) = spawn_server().await;
+    auth(&base, &client, "host_teacher").await;
+    let login = client
+        .post(format!("{}/api/v1/auth/login", base))
+        .json(&json!({"login": "host_teacher", "password": "password123"}))
+        .send()
+        .await
+        .unwrap();
+    let cookie = |name: &str| login.cookies().find(|c| c.name() == name).map(|c| c.value().to_string()).unwrap();
+    let (sid, csrf) = (cookie("teacher_session"), cookie("csrf_token"));
+    let create_quiz = client
+        .post(format!("{}/api/v1/quizzes", base))
+        .headers(csrf_headers(&csrf))
+        .json(&sample_quiz_payload())
+        .send()
+        .await
+        .unwrap();
+    let quiz_id = create_quiz.json::<serde_json::Value>().await.unwrap()["quiz_id"].as_i64().unwrap();
+    let session = client
+        .post(format!("{}/api/v1/sessions", base))
+        .headers(csrf_headers(&csrf))
+        .json(&json!({"quizId": quiz_id, "gameMode": "classic", "pacing": "host"}))
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    assert_eq!(session["pacing"], "host");
+    let session_id = session["sessionId"].as_i64().unwrap();
+    let room = session["roomCode"].as_str().unwrap().to_string();
+    let join_token = session["joinToken"].as_str().unwrap().to_string();
+    let ws_url = format!("{}/ws/sessions/{}", base.replace("http://", "ws://"), room);
+
+    let mut request = ws_url.as_str().into_client_request().unwrap();
+    request.headers_mut().insert("cookie", format!("teacher_session={}", sid).parse().unwrap());
+    let (mut teacher, _) = tokio_tungstenite::connect_async(request).await.unwrap();
+    teacher.send(Message::Text(json!({"event":"join_room","payload":{"role":"teacher","csrf": csrf}}).to_string())).await.unwrap();
+    next_event(&mut teacher, "waiting_room_update").await;
+    let (mut olya, _) = tokio_tungstenite::connect_async(&ws_url).await.unwrap();
+    let (mut petya, _) = tokio_tungstenite::connect_async(&ws_url).await.unwrap();
+    for (ws, nickname) in [(&mut olya, "Оля"), (&mut petya, "Петя")] {
+        let join = json!({"event":"join_room","payload":{"nickname": nickname,"joinToken": join_token}});
+        ws.send(Message::Text(join.to_string())).await.unwrap();
+        next_event(ws, "joined").await;
+    }
+    client
+        .post(format!("{}/api/v1/sessions/{}/start", base, session_id))
+        .headers(csrf_headers(&csrf))
+        .send()
+        .await
+        .unwrap();
+
+    // Students cannot pull questions on their own.
+    olya.send(Message::Text(json!({"event":"request_question"}).to_string())).await.unwrap();
+    assert_eq!(next_event(&mut olya, "error").await["code"], "host_paced");
+    let push_one = json!({"event":"push_next_question","payload":{"nickname":"Оля"}}).to_string();
+    teacher.send(Message::Text(push_one)).await.unwrap();
+    assert_eq!(next_event(&mut teacher, "error").await["code"], "invalid_state");
+
+    let push_next = json!({"event":"push_next_question"}).to_string();
+    teacher.send(Message::Text(push_next.clone())).await.unwrap();
+    for ws in [&mut olya, &mut petya] {
+        let pushed = next_event(ws, "question_push").await;
+        assert_eq!(pushed["reason"], "host");
+        assert_eq!(pushed["question"]["id"], "q1");
+        assert!(pushed["deadline"].is_string());
+        assert!(pushed["remainingMs"].as_i64().unwrap() > 25_000);
+    }
+    let submit = json!({"event":"answer_submit","payload":{"questionId":"q1","answer":{"text":"4"}}}).to_string();
+    olya.send(Message::Text(submit)).await.unwrap();
+    assert_eq!(next_event(&mut olya, "answer_result").await["correct"], true);
+    olya.send(Message::Text(json!({"event":"request_question"}).to_string())).await.unwrap();
+    assert_eq!(next_event(&mut olya, "error").await["code"], "host_paced");
+
+    // Moving on closes the open question and reports it to the teacher.
+    teacher.send(Message::Text(push_next.clone())).await.unwrap();
+    let results = next_event(&mut teacher, "question_results").await;
+    assert_eq!(results["questionId"], "q1");
+    assert_eq!((results["answered"].clone(), results["correct"].clone()), (json!(1), json!(1)));
+    assert_eq!(results["noAnswer"], 1);
+    // Петя let the question run out: it counts as wrong, and a late answer is not scored.
+    let timeout = next_event(&mut petya, "question_timeout").await;
+    assert_eq!((timeout["questionId"].clone(), timeout["points"].clone()), (json!("q1"), json!(0.0)));
+    for (ws, option) in [(&mut olya, "o1"), (&mut petya, "o2")] {
+        assert_eq!(next_event(ws, "question_push").await["question"]["id"], "q2");
+        let submit = json!({"event":"answer_submit","payload":{"questionId":"q2","answer":{"optionId": option}}});
+        ws.send(Message::Text(submit.to_string())).await.unwrap();
+        next_event(ws, "answer_result").await;
+    }
+    teacher.send(Message::Text(push_next)).await.unwrap();
+    let results = next_event(&mut teacher, "question_results").await;
+    assert_eq!(results["questionId"], "q2");
+    assert_eq!(results["options"], json!({"o1": 1, "o2": 1}));
+    assert_eq!(results["wrong"], 1);
+}
+
+#[tokio::test]
+async fn silent_sockets_time_out_and_are_shown_as_disconnected() {
+    use quiz_backend::state::WsHeartbeat;
+    use std::time::Duration;
+
+    let mut state = scratch_state();
+    state.restore().await.expect("restore");
+    state.ws_heartbeat = WsHeartbeat { ping_interval: Duration::from_millis(200), idle_timeout: Duration::from_secs(1) };
+    let (base, client) = serve(state).await;
+    let csrf = auth(&base, &client, "heartbeat_teacher").await;
+    let create_quiz = client
+        .post(format!("{}/api/v1/quizzes", base))
+        .headers(csrf_headers(&csrf))
+        .json(&sample_quiz_payload())
+        .send()
+        .await
+        .unwrap();
+    let quiz_id = create_quiz.json::<serde_json::Value>().await.unwrap()["quiz_id"].as_i64().unwrap();
+    let session = client
+        .post(format!("{}/api/v1/sessions", base))
+        .headers(csrf_headers(&csrf))
+        .json(&json!({"quizId": quiz_id, "gameMode": "classic"}))
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    let room = session["roomCode"].as_str().unwrap().to_string();
+    let join_token = session["joinToken"].as_str().unwrap().to_string();
+    let ws_url = format!("{}/ws/sessions/{}", base.replace("http://", "ws://"), room);
+    let (mut olya, _) = tokio_tungstenite::connect_async(&ws_url).await.unwrap();
+    let (mut petya, _) = tokio_tungstenite::connect_async(&ws_url).await.unwrap();
+    for (ws, nickname) in [(&mut olya, "Оля"), (&mut petya, "Петя")] {
+        let join = json!({"event":"join_room","payload":{"nickname": nickname,"joinToken": join_token}});
+        ws.send(Message::Text(join.to_string())).await.unwrap();
+        next_event(ws, "waiting_room_update").await;
+    }
+
+    // Оля stops reading, so her client never answers the server's pings; Петя keeps reading and
+    // answering them, and sees her presence change.
+    let disconnected = tokio::time::timeout(Duration::from_secs(10), async {
+        loop {
+            let update = next_event(&mut petya, "waiting_room_update").await;
+            let participants = update["participants"].as_array().unwrap().clone();
+            if participants.iter().any(|p| p["nickname"] == "Оля" && p["state"] == "disconnected") {
+                return participants;
+            }
+        }
+    })
+    .await
+    .expect("Оля is marked disconnected");
+    assert!(disconnected.iter().any(|p| p["nickname"] == "Петя" && p["state"] == "waiting"));
+    drop(olya);
+}
+
+#[tokio::test]
+async fn students_resume_where_they_left_off_after_reconnecting() {
+    let (base, client) = spawn_server().await;
+    let csrf = auth(&base, &client, "resume_teacher").await;
+    let create_quiz = client
+        .post(format!("{}/api/v1/quizzes", base))
+        .headers(csrf_headers(&csrf))
+        .json(&sample_quiz_payload())
+        .send()
+        .await
+        .unwrap();
+    let quiz_id = create_quiz.json::<serde_json::Value>().await.unwrap()["quiz_id"].as_i64().unwrap();
+    let session = client
+        .post(format!("{}/api/v1/sessions", base))
+        .headers(csrf_headers(&csrf))
+        .json(&json!({"quizId": quiz_id, "gameMode": "classic"}))
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    let session_id = session["sessionId"].as_i64().unwrap();
+    let room = session["roomCode"].as_str().unwrap().to_string();
+    let join_token = session["joinToken"].as_str().unwrap().to_string();
+    let ws_url = format!("{}/ws/sessions/{}", base.replace("http://", "ws://"), room);
+
+    let (mut ws, _) = tokio_tungstenite::connect_async(&ws_url).await.unwrap();
+    let join = json!({"event":"join_room","payload":{"nickname":"Оля","joinToken": join_token}});
+    ws.send(Message::Text(join.to_string())).await.unwrap();
+    let resume_token = next_event(&mut ws, "joined").await["resumeToken"].as_str().unwrap().to_string();
+    client
+        .post(format!("{}/api/v1/sessions/{}/start", base, session_id))
+        .headers(csrf_headers(&csrf))
+        .send()
+        .await
+        .unwrap();
+    ws.send(Message::Text(json!({"event":"request_question"}).to_string())).await.unwrap();
+    assert_eq!(next_event(&mut ws, "question_push").await["question"]["id"], "q1");
+    ws.close(None).await.unwrap();
+
+    let (mut ws, _) = tokio_tungstenite::connect_async(&ws_url).await.unwrap();
+    let bad = json!({"event":"join_room","payload":{"joinToken": join_token,"resumeToken":"stolen"}});
+    ws.send(Message::Text(bad.to_string())).await.unwrap();
+    assert_eq!(next_event(&mut ws, "join_rejected").await["reason"], "invalid_resume_token");
+    let resume = json!({"event":"join_room","payload":{"joinToken": join_token,"resumeToken": resume_token}});
+    ws.send(Message::Text(resume.to_string())).await.unwrap();
+    let resumed = next_event(&mut ws, "resumed").await;
+    assert_eq!(resumed["nickname"], "Оля");
+    assert_eq!(resumed["status"], "active");
+    assert_eq!(resumed["currentQuestionIndex"], 0);
+    assert_eq!(resumed["question"]["id"], "q1");
+
+    // The restored participant answers the pending question and carries on from there.
+    let submit = json!({"event":"answer_submit","payload":{"questionId":"q1","answer":{"text":"4"}}});
+    ws.send(Message::Text(submit.to_string())).await.unwrap();
+    assert_eq!(next_event(&mut ws, "answer_result").await["correct"], true);
+    ws.close(None).await.unwrap();
+    let (mut ws, _) = tokio_tungstenite::connect_async(&ws_url).await.unwrap();
+    ws.send(Message::Text(resume.to_string())).await.unwrap();
+    let resumed = next_event(&mut ws, "resumed").await;
+    assert_eq!(resumed["currentQuestionIndex"], 1);
+    assert_eq!(resumed["stats"]["correct"], 1);
+    assert!(resumed["question"].is_null());
+}
+
+#[tokio::test]
+async fn admin_metrics_report_room_channels() {
+    std::env::set_var("ADMIN_LOGINS", "backup_admin");
+    let (base, client) = spawn_server().await;
+    let csrf = auth(&base, &client, "backup_admin").await;
+    let create_quiz = client
+        .post(format!("{}/api/v1/quizzes", base))
+        .headers(csrf_headers(&csrf))
+        .json(&sample_quiz_payload())
+        .send()
+        .await
+        .unwrap();
+    let quiz_id = create_quiz.json::<serde_json::Value>().await.unwrap()["quiz_id"].as_i64().unwrap();
+    let session = client
+        .post(format!("{}/api/v1/sessions", base))
+        .headers(csrf_headers(&csrf))
+        .json(&json!({"quizId": quiz_id, "gameMode": "classic"}))
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    let room = session["roomCode"].as_str().unwrap().to_string();
+    let join_token = session["joinToken"].as_str().unwrap().to_string();
+    let ws_url = format!("{}/ws/sessions/{}", base.replace("http://", "ws://"), room);
+    let (mut ws, _) = tokio_tungstenite::connect_async(&ws_url).await.unwrap();
+    let join = json!({"event":"join_room","payload":{"nickname":"Оля","joinToken": join_token}});
+    ws.send(Message::Text(join.to_string())).await.unwrap();
+    next_event(&mut ws, "joined").await;
+
+    let metrics = client.get(format!("{}/api/v1/admin/metrics", base)).send().await.unwrap();
+    assert_eq!(metrics.status(), 200);
+    let metrics = metrics.json::<serde_json::Value>().await.unwrap();
+    let rooms = metrics["rooms"].as_array().unwrap();
+    let entry = rooms.iter().find(|r| r["roomCode"] == room.as_str()).unwrap();
+    assert_eq!(entry["participants"], 1);
+    assert_eq!(entry["capacity"], 256);
+    assert_eq!(entry["lagged"], 0);
+}
+
+#[tokio::test]
+async fn admin_metrics_time_the_answer_pipeline() {
+    std::env::set_var("ADMIN_LOGINS", "backup_admin");
+    let (base, client) = spawn_server().await;
+    let csrf = auth(&base, &client, "backup_admin").await;
+    let quiz_id = client
+        .post(format!("{}/api/v1/quizzes", base))
+        .headers(csrf_headers(&csrf))
+        .json(&sample_quiz_payload())
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap()["quiz_id"]
+        .as_i64()
+        .unwrap();
+    let session = client
+        .post(format!("{}/api/v1/sessions", base))
+        .headers(csrf_headers(&csrf))
+        .json(&json!({"quizId": quiz_id, "gameMode": "classic"}))
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    let ws_url = format!("{}/ws/sessions/{}", base.replace("http://", "ws://"), session["roomCode"].as_str().unwrap());
+    let (mut ws, _) = tokio_tungstenite::connect_async(&ws_url).await.unwrap();
+    let join = json!({"event":"join_room","payload":{"nickname":"Оля","joinToken": session["joinToken"]}});
+    ws.send(Message::Text(join.to_string())).await.unwrap();
+    next_event(&mut ws, "joined").await;
+    client
+        .post(format!("{}/api/v1/sessions/{}/start", base, session["sessionId"]))
+        .headers(csrf_headers(&csrf))
+        .send()
+        .await
+        .unwrap();
+    let pipeline = || async {
+        let metrics = client.get(format!("{}/api/v1/admin/metrics", base)).send().await.unwrap();
+        metrics.json::<serde_json::Value>().await.unwrap()["answerPipeline"].clone()
+    };
+    let before = pipeline().await["total"]["count"].as_u64().unwrap();
+
+    ws.send(Message::Text(json!({"event":"request_question"}).to_string())).await.unwrap();
+    next_event(&mut ws, "question_push").await;
+    let submit = json!({"event":"answer_submit","payload":{"questionId":"q1","answer":{"text":"4"}}});
+    ws.send(Message::Text(submit.to_string())).await.unwrap();
+    next_event(&mut ws, "answer_result").await;
+
+    let after = pipeline().await;
+    assert_eq!(after["total"]["count"].as_u64().unwrap(), before + 1);
+    for stage in ["lockWait", "quizLookup", "scoring", "broadcast", "total"] {
+        let summary = &after[stage];
+        assert!(summary["count"].as_u64().unwrap() >= 1, "{}", stage);
+        for key in ["p50Ms", "p95Ms", "p99Ms", "maxMs"] {
+            assert!(summary[key].as_f64().is_some(), "{} {}", stage, key);
+        }
+        assert!(summary["p50Ms"].as_f64() <= summary["p95Ms"].as_f64());
+        assert!(summary["p95Ms"].as_f64() <= summary["p99Ms"].as_f64());
+    }
+}
+
+#[tokio::test]
+async fn rooms_are_released_when_sessions_end_or_go_idle() {
+    let mut state = scratch_state();
+    state.restore().await.expect("restore");
+    // Only the direct expire_idle_rooms call below sees the zero TTL; the background sweeper keeps its own.
+    state.room_idle_ttl = chrono::Duration::zero();
+    let (base, client) = serve(state.clone()).await;
+    let csrf = auth(&base, &client, "room_cleanup_teacher").await;
+    let create_quiz = client
+        .post(format!("{}/api/v1/quizzes", base))
+        .headers(csrf_headers(&csrf))
+        .json(&sample_quiz_payload())
+        .send()
+        .await
+        .unwrap();
+    let quiz_id = create_quiz.json::<serde_json::Value>().await.unwrap()["quiz_id"].as_i64().unwrap();
+    let mut rooms = Vec::new();
+    for _ in 0..2 {
+        let session = client
+            .post(format!("{}/api/v1/sessions", base))
+            .headers(csrf_headers(&csrf))
+            .json(&json!({"quizId": quiz_id, "gameMode": "classic"}))
+            .send()
+            .await
+            .unwrap()
+            .json::<serde_json::Value>()
+            .await
+            .unwrap();
+        let room = session["roomCode"].as_str().unwrap().to_string();
+        let ws_url = format!("{}/ws/sessions/{}", base.replace("http://", "ws://"), room);
+        let (mut ws, _) = tokio_tungstenite::connect_async(&ws_url).await.unwrap();
+        let join = json!({"event":"join_room","payload":{"nickname":"Оля","joinToken": session["joinToken"]}});
+        ws.send(Message::Text(join.to_string())).await.unwrap();
+        next_event(&mut ws, "joined").await;
+        rooms.push((session["sessionId"].as_i64().unwrap(), room, ws_url, ws));
+    }
+    let (ended_id, ended_room, ended_url, mut ended_ws) = rooms.remove(0);
+    let (idle_id, idle_room, _, mut idle_ws) = rooms.remove(0);
+
+    client
+        .post(format!("{}/api/v1/sessions/{}/end", base, ended_id))
+        .headers(csrf_headers(&csrf))
+        .send()
+        .await
+        .unwrap();
+    next_event(&mut ended_ws, "end_quiz").await;
+    assert!(state.db.broadcasters.get(&ended_room).is_none());
+    assert_eq!(state.shared.room_session(&ended_room).await.unwrap(), None);
+    // The old room code no longer leads anywhere: the socket is closed straight away.
+    let (mut late, _) = tokio_tungstenite::connect_async(&ended_url).await.unwrap();
+    assert!(matches!(late.next().await, None | Some(Err(_)) | Some(Ok(Message::Close(_)))));
+
+    tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    assert_eq!(state.expire_idle_rooms().await, 1);
+    assert_eq!(next_event(&mut idle_ws, "end_quiz").await["reason"], "idle");
+    assert_eq!(state.shared.room_session(&idle_room).await.unwrap(), None);
+    let sessions = client.get(format!("{}/api/v1/sessions", base)).send().await.unwrap();
+    let sessions = sessions.json::<serde_json::Value>().await.unwrap();
+    let idle = sessions["items"].as_array().unwrap().iter().find(|s| s["id"] == idle_id).unwrap().clone();
+    assert_eq!(idle["status"], "finished");
+}
+
+#[tokio::test]
+async fn room_codes_are_unambiguous_unique_and_expire() {
+    let mut state = scratch_state();
+    state.restore().await.expect("restore");
+    // As above, only the direct calls below see these settings.
+    state.room_code_length = 8;
+    state.room_code_ttl = chrono::Duration::zero();
+    let (base, client) = serve(state.clone()).await;
+    let csrf = auth(&base, &client, "room_code_teacher").await;
+    let quiz_id = client
+        .post(format!("{}/api/v1/quizzes", base))
+        .headers(csrf_headers(&csrf))
+        .json(&sample_quiz_payload())
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap()["quiz_id"]
+        .as_i64()
+        .unwrap();
+    let session = client
+        .post(format!("{}/api/v1/sessions", base))
+        .headers(csrf_headers(&csrf))
+        .json(&json!({"quizId": quiz_id, "gameMode": "classic"}))
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    let room = session["roomCode"].as_str().unwrap().to_string();
+    assert_eq!(room.len(), 8);
+    assert!(room.bytes().all(|c| quiz_backend::state::ROOM_CODE_ALPHABET.contains(&c)));
+    assert!(!room.contains(['O', '0', 'I', '1']));
+    // A code in use is never handed to another session.
+    assert!(!state.shared.bind_room(&room, i64::MAX).await.unwrap());
+    assert_eq!(state.shared.room_session(&room).await.unwrap(), session["sessionId"].as_i64());
+
+    let ws_url = format!("{}/ws/sessions/{}", base.replace("http://", "ws://"), room);
+    let (mut ws, _) = tokio_tungstenite::connect_async(&ws_url).await.unwrap();
+    let join = json!({"event":"join_room","payload":{"nickname":"Оля","joinToken": session["joinToken"]}});
+    ws.send(Message::Text(join.to_string())).await.unwrap();
+    next_event(&mut ws, "joined").await;
+    // The room is busy, but older than the TTL.
+    tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    assert_eq!(state.expire_old_rooms().await, 1);
+    assert_eq!(next_event(&mut ws, "end_quiz").await["reason"], "expired");
+    assert_eq!(state.shared.room_session(&room).await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn scheduled_sessions_count_down_and_start_on_their_own() {
+    let (base, client) = spawn_server().await;
+    let csrf = auth(&base, &client, "scheduled_teacher").await;
+    let quiz_id = client
+        .post(format!("{}/api/v1/quizzes", base))
+        .headers(csrf_headers(&csrf))
+        .json(&sample_quiz_payload())
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap()["quiz_id"]
+        .as_i64()
+        .unwrap();
+    let past = client
+        .post(format!("{}/api/v1/sessions", base))
+        .headers(csrf_headers(&csrf))
+        .json(&json!({"quizId": quiz_id, "gameMode": "classic", "startsAt": "2020-01-01T00:00:00Z"}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(past.status(), reqwest::StatusCode::BAD_REQUEST);
+
+    let starts_at = (chrono::Utc::now() + chrono::Duration::milliseconds(1500)).to_rfc3339();
+    let session = client
+        .post(format!("{}/api/v1/sessions", base))
+        .headers(csrf_headers(&csrf))
+        .json(&json!({"quizId": quiz_id, "gameMode": "classic", "startsAt": starts_at}))
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    assert!(session["startsAt"].is_string());
+    let session_id = session["sessionId"].as_i64().unwrap();
+
+    let ws_url = format!("{}/ws/sessions/{}", base.replace("http://", "ws://"), session["roomCode"].as_str().unwrap());
+    let (mut ws, _) = tokio_tungstenite::connect_async(&ws_url).await.unwrap();
+    let join = json!({"event":"join_room","payload":{"nickname":"Лиза","joinToken": session["joinToken"]}});
+    ws.send(Message::Text(join.to_string())).await.unwrap();
+    next_event(&mut ws, "joined").await;
+    let countdown = next_event(&mut ws, "start_countdown").await;
+    assert_eq!(countdown["sessionId"], session_id);
+    assert!(countdown["remainingMs"].as_i64().unwrap() <= 1500);
+    let started = next_event(&mut ws, "start_quiz").await;
+    assert_eq!(started["scheduled"], true);
+
+    let sessions = client.get(format!("{}/api/v1/sessions", base)).send().await.unwrap();
+    let sessions = sessions.json::<serde_json::Value>().await.unwrap();
+    let scheduled = sessions["items"].as_array().unwrap().iter().find(|s| s["id"] == session_id).unwrap().clone();
+    assert_eq!(scheduled["status"], "active");
+}
+
+#[tokio::test]
+async fn a_full_enough_lobby_counts_down_and_starts() {
+    let (base, client) = spawn_server().await;
+    let csrf = auth(&base, &client, "lobby_countdown_teacher").await;
+    let quiz_id = client
+        .post(format!("{}/api/v1/quizzes", base))
+        .headers(csrf_headers(&csrf))
+        .json(&sample_quiz_payload())
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap()["quiz_id"]
+        .as_i64()
+        .unwrap();
+    let invalid = client
+        .post(format!("{}/api/v1/sessions", base))
+        .headers(csrf_headers(&csrf))
+        .json(&json!({"quizId": quiz_id, "gameMode": "classic", "lobby": {"minPlayers": 3, "maxParticipants": 2}}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(invalid.status(), reqwest::StatusCode::BAD_REQUEST);
+
+    let session = client
+        .post(format!("{}/api/v1/sessions", base))
+        .headers(csrf_headers(&csrf))
+        .json(&json!({"quizId": quiz_id, "gameMode": "classic", "lobby": {"minPlayers": 2, "countdownSecs": 3}}))
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    let ws_url = format!("{}/ws/sessions/{}", base.replace("http://", "ws://"), session["roomCode"].as_str().unwrap());
+    let mut sockets = Vec::new();
+    for nickname in ["Аня", "Борис"] {
+        let (mut ws, _) = tokio_tungstenite::connect_async(&ws_url).await.unwrap();
+        let join = json!({"event":"join_room","payload":{"nickname": nickname,"joinToken": session["joinToken"]}});
+        ws.send(Message::Text(join.to_string())).await.unwrap();
+        next_event(&mut ws, "joined").await;
+        sockets.push(ws);
+    }
+    // The second player completes the lobby; both see the countdown and then the start.
+    for ws in &mut sockets {
+        let countdown = next_event(ws, "start_countdown").await;
+        let remaining = countdown["remainingMs"].as_i64().unwrap();
+        assert!(remaining > 0 && remaining <= 3000);
+    }
+    for ws in &mut sockets {
+        assert_eq!(next_event(ws, "start_quiz").await["scheduled"], true);
+    }
+}
+
+#[tokio::test]
+async fn session_history_filters_by_quiz_and_status_and_pages() {
+    let (base, client) = spawn_server().await;
+    let csrf = auth(&base, &client, "history_teacher").await;
+    let mut quiz_ids = Vec::new();
+    for _ in 0..2 {
+        let quiz = client
+            .post(format!("{}/api/v1/quizzes", base))
+            .headers(csrf_headers(&csrf))
+            .json(&sample_quiz_payload())
+            .send()
+            .await
+            .unwrap()
+            .json::<serde_json::Value>()
+            .await
+            .unwrap();
+        quiz_ids.push(quiz["quiz_id"].as_i64().unwrap());
+    }
+    let mut session_ids = Vec::new();
+    for quiz_id in [quiz_ids[0], quiz_ids[0], quiz_ids[0], quiz_ids[1]] {
+        let session = client
+            .post(format!("{}/api/v1/sessions", base))
+            .headers(csrf_headers(&csrf))
+            .json(&json!({"quizId": quiz_id, "gameMode": "classic"}))
+            .send()
+            .await
+            .unwrap()
+            .json::<serde_json::Value>()
+            .await
+            .unwrap();
+        session_ids.push(session["sessionId"].as_i64().unwrap());
+    }
+    let ended = format!("{}/api/v1/sessions/{}", base, session_ids[0]);
+    client.post(format!("{}/start", ended)).headers(csrf_headers(&csrf)).send().await.unwrap();
+    client.post(format!("{}/end", ended)).headers(csrf_headers(&csrf)).send().await.unwrap();
+
+    let list = |query: String| {
+        let client = client.clone();
+        let url = format!("{}/api/v1/sessions?{}", base, query);
+        async move { client.get(url).send().await.unwrap() }
+    };
+    let by_quiz = list(format!("quizId={}", quiz_ids[0])).await.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(by_quiz["total"], 3);
+    assert_eq!(by_quiz["items"][0]["id"], session_ids[2]);
+    assert!(by_quiz["items"][0]["createdAt"].is_string());
+    assert!(by_quiz["items"][0]["startedAt"].is_null());
+
+    let finished = list("status=finished".into()).await.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(finished["total"], 1);
+    let history = &finished["items"][0];
+    assert_eq!(history["id"], session_ids[0]);
+    assert!(history["startedAt"].is_string() && history["endedAt"].is_string());
+    assert!(history["averageScore"].is_null());
+
+    let page = list(format!("quizId={}&page=2&limit=2", quiz_ids[0])).await.json::<serde_json::Value>().await.unwrap();
+    assert_eq!((page["total"].clone(), page["page"].clone()), (json!(3), json!(2)));
+    assert_eq!(page["items"].as_array().unwrap().len(), 1);
+    assert_eq!(list("limit=0".into()).await.status(), reqwest::StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn ending_a_session_reveals_the_podium_before_end_quiz() {
+    let (base, client) = spawn_server().await;
+    let csrf = auth(&base, &client, "podium_teacher").await;
+    let quiz_id = client
+        .post(format!("{}/api/v1/quizzes", base))
+        .headers(csrf_headers(&csrf))
+        .json(&sample_quiz_payload())
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap()["quiz_id"]
+        .as_i64()
+        .unwrap();
+    let session = client
+        .post(format!("{}/api/v1/sessions", base))
+        .headers(csrf_headers(&csrf))
+        .json(&json!({"quizId": quiz_id, "gameMode": "classic"}))
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    let session_url = format!("{}/api/v1/sessions/{}", base, session["sessionId"]);
+    let ws_url = format!("{}/ws/sessions/{}", base.replace("http://", "ws://"), session["roomCode"].as_str().unwrap());
+    client.post(format!("{}/start", session_url)).headers(csrf_headers(&csrf)).send().await.unwrap();
+
+    let mut sockets = Vec::new();
+    for (nickname, answer) in [("Аня", "4"), ("Борис", "5")] {
+        let (mut ws, _) = tokio_tungstenite::connect_async(&ws_url).await.unwrap();
+        let join = json!({"event":"join_room","payload":{"nickname": nickname,"joinToken": session["joinToken"]}});
+        ws.send(Message::Text(join.to_string())).await.unwrap();
+        next_event(&mut ws, "joined").await;
+        ws.send(Message::Text(json!({"event":"request_question"}).to_string())).await.unwrap();
+        next_event(&mut ws, "question_push").await;
+        let submit = json!({"event":"answer_submit","payload":{"questionId":"q1","answer":{"text": answer}}});
+        ws.send(Message::Text(submit.to_string())).await.unwrap();
+        next_event(&mut ws, "answer_result").await;
+        sockets.push(ws);
+    }
+
+    client.post(format!("{}/end", session_url)).headers(csrf_headers(&csrf)).send().await.unwrap();
+    let ws = &mut sockets[1];
+    let podium = loop {
+        let Message::Text(text) = ws.next().await.unwrap().unwrap() else { continue };
+        let value: serde_json::Value = serde_json::from_str(&text).unwrap();
+        assert_ne!(value["event"], "end_quiz", "podium comes first");
+        if value["event"] == "podium" {
+            break value["payload"].clone();
+        }
+    };
+    // Revealed from the lowest place up.
+    let places = podium["places"].as_array().unwrap();
+    assert_eq!(places.len(), 2);
+    assert_eq!(places[0]["nickname"], "Борис");
+    assert_eq!(places[0]["rank"], 2);
+    assert_eq!(places[0]["revealAfterMs"], 0);
+    assert_eq!(places[1]["nickname"], "Аня");
+    assert_eq!(places[1]["rank"], 1);
+    assert_eq!(places[1]["correct"], 1);
+    assert!(places[1]["revealAfterMs"].as_u64().unwrap() > 0);
+    next_event(ws, "end_quiz").await;
+}
+
+#[tokio::test]
+async fn teachers_walk_the_class_through_a_review_after_the_game() {
+    use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+
+    let (base, client) = spawn_server().await;
+    auth(&base, &client, "review_teacher").await;
+    let login = client
+        .post(format!("{}/api/v1/auth/login", base))
+        .json(&json!({"login": "review_teacher", "password": "password123"}))
+        .send()
+        .await
+        .unwrap();
+    let cookie = |name: &str| login.cookies().find(|c| c.name() == name).map(|c| c.value().to_string()).unwrap();
+    let (sid, csrf) = (cookie("teacher_session"), cookie("csrf_token"));
+    let quiz_id = client
+        .post(format!("{}/api/v1/quizzes", base))
+        .headers(csrf_headers(&csrf))
+        .json(&sample_quiz_payload())
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap()["quiz_id"]
+        .as_i64()
+        .unwrap();
+    let session = client
+        .post(format!("{}/api/v1/sessions", base))
+        .headers(csrf_headers(&csrf))
+        .json(&json!({"quizId": quiz_id, "gameMode": "classic"}))
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    let session_url = format!("{}/api/v1/sessions/{}", base, session["sessionId"]);
+    let ws_url = format!("{}/ws/sessions/{}", base.replace("http://", "ws://"), session["roomCode"].as_str().unwrap());
+
+    let mut request = ws_url.as_str().into_client_request().unwrap();
+    request.headers_mut().insert("cookie", format!("teacher_session={}", sid).parse().unwrap());
+    let (mut teacher, _) = tokio_tungstenite::connect_async(request).await.unwrap();
+    teacher.send(Message::Text(json!({"event":"join_room","payload":{"role":"teacher","csrf": csrf}}).to_string())).await.unwrap();
+    next_event(&mut teacher, "waiting_room_update").await;
+    let (mut student, _) = tokio_tungstenite::connect_async(&ws_url).await.unwrap();
+    let join = json!({"event":"join_room","payload":{"nickname":"Оля","joinToken": session["joinToken"]}});
+    student.send(Message::Text(join.to_string())).await.unwrap();
+    next_event(&mut student, "joined").await;
+    client.post(format!("{}/start", session_url)).headers(csrf_headers(&csrf)).send().await.unwrap();
+    student.send(Message::Text(json!({"event":"request_question"}).to_string())).await.unwrap();
+    next_event(&mut student, "question_push").await;
+    let submit = json!({"event":"answer_submit","payload":{"questionId":"q1","answer":{"text":"4"}}});
+    student.send(Message::Text(submit.to_string())).await.unwrap();
+    next_event(&mut student, "answer_result").await;
+
+    let review_next = json!({"event":"review_next"}).to_string();
+    teacher.send(Message::Text(review_next.clone())).await.unwrap();
+    assert_eq!(next_event(&mut teacher, "error").await["code"], "invalid_state");
+    client
+        .post(format!("{}/end", session_url))
+        .headers(csrf_headers(&csrf))
+        .json(&json!({"reviewMinutes": 5}))
+        .send()
+        .await
+        .unwrap();
+    student.send(Message::Text(review_next.clone())).await.unwrap();
+    assert_eq!(next_event(&mut student, "error").await["code"], "forbidden");
+
+    teacher.send(Message::Text(review_next.clone())).await.unwrap();
+    let first = next_event(&mut student, "review_question").await;
+    assert_eq!(first["position"], 0);
+    assert_eq!(first["total"], 3);
+    assert_eq!(first["question"]["id"], "q1");
+    assert_eq!(first["correctAnswer"]["text"], "4");
+    assert_eq!((first["answered"].clone(), first["correct"].clone()), (json!(1), json!(1)));
+    for position in 1..3 {
+        teacher.send(Message::Text(review_next.clone())).await.unwrap();
+        let review = next_event(&mut student, "review_question").await;
+        assert_eq!(review["position"], position);
+        assert_eq!(review["noAnswer"], 1);
+    }
+    assert_eq!(next_event(&mut teacher, "review_question").await["position"], 0);
+    teacher.send(Message::Text(review_next)).await.unwrap();
+    assert_eq!(next_event(&mut teacher, "error").await["code"], "invalid_state");
+}
+
+#[tokio::test]
+async fn session_results_report_focus_loss_and_instant_answers() {
+    let (base, client) = spawn_server().await;
+    let csrf = auth(&base, &client, "integrity_teacher").await;
+    let quiz_id = client
+        .post(format!("{}/api/v1/quizzes", base))
+        .headers(csrf_headers(&csrf))
+        .json(&sample_quiz_payload())
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap()["quiz_id"]
+        .as_i64()
+        .unwrap();
+    let session = client
+        .post(format!("{}/api/v1/sessions", base))
+        .headers(csrf_headers(&csrf))
+        .json(&json!({"quizId": quiz_id, "gameMode": "classic"}))
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    let session_url = format!("{}/api/v1/sessions/{}", base, session["sessionId"]);
+    let ws_url = format!("{}/ws/sessions/{}", base.replace("http://", "ws://"), session["roomCode"].as_str().unwrap());
+    let (mut ws, _) = tokio_tungstenite::connect_async(&ws_url).await.unwrap();
+    let focus = |focused: bool| {
+        Message::Text(json!({"event":"focus_change","payload":{"focused": focused}}).to_string())
+    };
+    // Before joining there is no one to report for.
+    ws.send(focus(false)).await.unwrap();
+    assert_eq!(next_event(&mut ws, "error").await["code"], "not_joined");
+    let join = json!({"event":"join_room","payload":{"nickname":"Оля","joinToken": session["joinToken"]}});
+    ws.send(Message::Text(join.to_string())).await.unwrap();
+    next_event(&mut ws, "joined").await;
+    // The lobby does not count.
+    ws.send(focus(false)).await.unwrap();
+    ws.send(focus(true)).await.unwrap();
+    client.post(format!("{}/start", session_url)).headers(csrf_headers(&csrf)).send().await.unwrap();
+
+    ws.send(focus(false)).await.unwrap();
+    ws.send(focus(false)).await.unwrap();
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    ws.send(focus(true)).await.unwrap();
+    ws.send(Message::Text(json!({"event":"request_question"}).to_string())).await.unwrap();
+    next_event(&mut ws, "question_push").await;
+    let submit = json!({"event":"answer_submit","payload":{"questionId":"q1","answer":{"text":"4"}}});
+    ws.send(Message::Text(submit.to_string())).await.unwrap();
+    next_event(&mut ws, "answer_result").await;
+
+    let results = client.get(format!("{}/results", session_url)).send().await.unwrap();
+    let results = results.json::<serde_json::Value>().await.unwrap();
+    let integrity = &results["studentStats"][0]["integrity"];
+    assert_eq!(integrity["focusLost"], 1);
+    assert!(integrity["unfocusedMs"].as_i64().unwrap() >= 100);
+    assert_eq!(integrity["fastAnswers"], 1);
+    assert_eq!(integrity["longestFastStreak"], 1);
+    assert!(integrity["fastestMs"].as_i64().unwrap() < 1000);
+    assert!(results["quizzes"][0]["studentStats"][0].get("integrity").is_none());
+}
+
+#[tokio::test]
+async fn class_sessions_report_who_was_absent() {
+    let (base, client) = spawn_server().await;
+    let csrf = auth(&base, &client, "class_teacher").await;
+    let classes_url = format!("{}/api/v1/classes", base);
+    let unknown_student = json!({"name": "7Б", "roster": [{"nickname": "", "studentId": 999}]});
+    let res = client.post(&classes_url).headers(csrf_headers(&csrf)).json(&unknown_student).send().await.unwrap();
+    assert_eq!(res.status(), 400);
+    let twice = json!({"name": "7Б", "roster": [{"nickname": "Оля"}, {"nickname": " оля "}]});
+    let res = client.post(&classes_url).headers(csrf_headers(&csrf)).json(&twice).send().await.unwrap();
+    assert_eq!(res.status(), 400);
+
+    let roster = json!({"name": " 7Б ", "grade": 7, "roster": [{"nickname": "Оля"}, {"nickname": "Петя"}]});
+    let res = client.post(&classes_url).headers(csrf_headers(&csrf)).json(&roster).send().await.unwrap();
+    assert_eq!(res.status(), 201);
+    let class = res.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(class["name"], "7Б");
+    let class_url = format!("{}/{}", classes_url, class["id"]);
+    let renamed = json!({"name": "7А", "grade": 7, "roster": [{"nickname": "Оля"}, {"nickname": "Петя"}]});
+    let res = client.put(&class_url).headers(csrf_headers(&csrf)).json(&renamed).send().await.unwrap();
+    assert_eq!(res.json::<serde_json::Value>().await.unwrap()["name"], "7А");
+    let list = client.get(&classes_url).send().await.unwrap().json::<serde_json::Value>().await.unwrap();
+    assert_eq!(list["total"], 1);
+    assert_eq!(list["items"][0]["roster"].as_array().unwrap().len(), 2);
+
+    let quiz_id = client
+        .post(format!("{}/api/v1/quizzes", base))
+        .headers(csrf_headers(&csrf))
+        .json(&sample_quiz_payload())
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap()["quiz_id"]
+        .as_i64()
+        .unwrap();
+    let res = client
+        .post(format!("{}/api/v1/sessions", base))
+        .headers(csrf_headers(&csrf))
+        .json(&json!({"quizId": quiz_id, "gameMode": "classic", "classId": 999}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 404);
+    let session = client
+        .post(format!("{}/api/v1/sessions", base))
+        .headers(csrf_headers(&csrf))
+        .json(&json!({"quizId": quiz_id, "gameMode": "classic", "classId": class["id"]}))
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    assert_eq!(session["classId"], class["id"]);
+    let ws_url = format!("{}/ws/sessions/{}", base.replace("http://", "ws://"), session["roomCode"].as_str().unwrap());
+    for nickname in ["оля", "Гость"] {
+        let (mut ws, _) = tokio_tungstenite::connect_async(&ws_url).await.unwrap();
+        let join = json!({"event":"join_room","payload":{"nickname": nickname,"joinToken": session["joinToken"]}});
+        ws.send(Message::Text(join.to_string())).await.unwrap();
+        next_event(&mut ws, "joined").await;
+    }
+    // Deleting the class leaves the session's copy of the roster alone.
+    let res = client.delete(&class_url).headers(csrf_headers(&csrf)).send().await.unwrap();
+    assert_eq!(res.status(), 204);
+    assert_eq!(client.get(&class_url).send().await.unwrap().status(), 404);
+
+    let results = client
+        .get(format!("{}/api/v1/sessions/{}/results", base, session["sessionId"]))
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    let attendance = &results["attendance"];
+    assert_eq!(attendance["classId"], class["id"]);
+    assert_eq!(attendance["present"], json!([{"nickname": "Оля"}]));
+    assert_eq!(attendance["absent"], json!([{"nickname": "Петя"}]));
+    assert_eq!(attendance["guests"], json!(["Гость"]));
+}
+
+#[tokio::test]
+async fn answer_times_are_reported_per_question() {
+    let (base, client) = spawn_server().await;
+    let csrf = auth(&base, &client, "latency_teacher").await;
+    let quiz_id = client
+        .post(format!("{}/api/v1/quizzes", base))
+        .headers(csrf_headers(&csrf))
+        .json(&sample_quiz_payload())
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap()["quiz_id"]
+        .as_i64()
+        .unwrap();
+    let session = client
+        .post(format!("{}/api/v1/sessions", base))
+        .headers(csrf_headers(&csrf))
+        .json(&json!({"quizId": quiz_id, "gameMode": "classic"}))
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    let session_url = format!("{}/api/v1/sessions/{}", base, session["sessionId"]);
+    let ws_url = format!("{}/ws/sessions/{}", base.replace("http://", "ws://"), session["roomCode"].as_str().unwrap());
+    let mut sockets = Vec::new();
+    for nickname in ["Оля", "Петя"] {
+        let (mut ws, _) = tokio_tungstenite::connect_async(&ws_url).await.unwrap();
+        let join = json!({"event":"join_room","payload":{"nickname": nickname,"joinToken": session["joinToken"]}});
+        ws.send(Message::Text(join.to_string())).await.unwrap();
+        next_event(&mut ws, "joined").await;
+        sockets.push(ws);
+    }
+    client.post(format!("{}/start", session_url)).headers(csrf_headers(&csrf)).send().await.unwrap();
+
+    let submit = json!({"event":"answer_submit","payload":{"questionId":"q1","answer":{"text":"4"}}});
+    let mut stats = json!(null);
+    for (ws, think_ms) in sockets.iter_mut().zip([0, 200]) {
+        ws.send(Message::Text(json!({"event":"request_question"}).to_string())).await.unwrap();
+        next_event(ws, "question_push").await;
+        tokio::time::sleep(std::time::Duration::from_millis(think_ms)).await;
+        ws.send(Message::Text(submit.to_string())).await.unwrap();
+        next_event(ws, "answer_result").await;
+        stats = next_event(ws, "stats_update").await;
+    }
+    let live = &stats["questionTimes"][0];
+    assert_eq!(live["questionId"], "q1");
+    assert_eq!(live["answers"], 2);
+    assert!(live["p90Ms"].as_i64().unwrap() >= 200);
+    assert!(live["p50Ms"].as_i64().unwrap() < 200);
+
+    let results = client.get(format!("{}/results", session_url)).send().await.unwrap();
+    let results = results.json::<serde_json::Value>().await.unwrap();
+    let times = results["questionTimes"].as_array().unwrap();
+    assert_eq!(times.len(), 1);
+    assert_eq!(times[0]["quizId"], quiz_id);
+    assert!(times[0]["avgMs"].as_f64().unwrap() >= 100.0);
+}
+
+#[tokio::test]
+async fn item_analysis_flags_questions_strong_students_fail() {
+    let (base, client) = spawn_server().await;
+    let csrf = auth(&base, &client, "items_teacher").await;
+    let quiz_id = client
+        .post(format!("{}/api/v1/quizzes", base))
+        .headers(csrf_headers(&csrf))
+        .json(&sample_quiz_payload())
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap()["quiz_id"]
+        .as_i64()
+        .unwrap();
+    let analysis_url = format!("{}/api/v1/quizzes/{}/item-analysis", base, quiz_id);
+    let empty = client.get(&analysis_url).send().await.unwrap().json::<serde_json::Value>().await.unwrap();
+    assert_eq!(empty["respondents"], 0);
+    assert_eq!(empty["items"][0]["difficulty"], json!(null));
+
+    let session = client
+        .post(format!("{}/api/v1/sessions", base))
+        .headers(csrf_headers(&csrf))
+        .json(&json!({"quizId": quiz_id, "gameMode": "classic"}))
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    let ws_url = format!("{}/ws/sessions/{}", base.replace("http://", "ws://"), session["roomCode"].as_str().unwrap());
+    let mut sockets = Vec::new();
+    for nickname in ["Аня", "Боря", "Вера", "Гена", "Даша"] {
+        let (mut ws, _) = tokio_tungstenite::connect_async(&ws_url).await.unwrap();
+        let join = json!({"event":"join_room","payload":{"nickname": nickname,"joinToken": session["joinToken"]}});
+        ws.send(Message::Text(join.to_string())).await.unwrap();
+        next_event(&mut ws, "joined").await;
+        sockets.push(ws);
+    }
+    let start_url = format!("{}/api/v1/sessions/{}/start", base, session["sessionId"]);
+    client.post(start_url).headers(csrf_headers(&csrf)).send().await.unwrap();
+
+    // The three strongest students miss q2, which the two weakest get right: a suspicious answer key.
+    let strong = [json!({"text": "4"}), json!({"optionId": "o2"}), json!({"optionIds": ["o1", "o3"]})];
+    let weak = [json!({"text": "5"}), json!({"optionId": "o1"}), json!({"optionIds": ["o2"]})];
+    for (i, ws) in sockets.iter_mut().enumerate() {
+        let answers = if i < 3 { &strong } else { &weak };
+        for (question_id, answer) in ["q1", "q2", "q3"].into_iter().zip(answers) {
+            ws.send(Message::Text(json!({"event":"request_question"}).to_string())).await.unwrap();
+            next_event(ws, "question_push").await;
+            let submit = json!({"event":"answer_submit","payload":{"questionId": question_id,"answer": answer}});
+            ws.send(Message::Text(submit.to_string())).await.unwrap();
+            next_event(ws, "answer_result").await;
+        }
+    }
+
+    let analysis = client.get(&analysis_url).send().await.unwrap().json::<serde_json::Value>().await.unwrap();
+    assert_eq!(analysis["sessions"], 1);
+    assert_eq!(analysis["respondents"], 5);
+    let items = analysis["items"].as_array().unwrap();
+    assert_eq!(items[0]["questionId"], "q1");
+    assert_eq!(items[0]["respondents"], 5);
+    assert!((items[0]["difficulty"].as_f64().unwrap() - 0.6).abs() < 1e-9);
+    assert_eq!(items[0]["discrimination"], 1.0);
+    assert_eq!(items[0]["flags"], json!([]));
+    assert_eq!(items[1]["discrimination"], -1.0);
+    assert_eq!(items[1]["flags"], json!(["negative_discrimination"]));
+}
+
+#[tokio::test]
+async fn teachers_can_poll_the_live_dashboard_with_a_cursor() {
+    let (base, client) = spawn_server().await;
+    let csrf = auth(&base, &client, "polling_teacher").await;
+    let quiz_id = client
+        .post(format!("{}/api/v1/quizzes", base))
+        .headers(csrf_headers(&csrf))
+        .json(&sample_quiz_payload())
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap()["quiz_id"]
+        .as_i64()
+        .unwrap();
+    let session = client
+        .post(format!("{}/api/v1/sessions", base))
+        .headers(csrf_headers(&csrf))
+        .json(&json!({"quizId": quiz_id, "gameMode": "classic"}))
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    let session_url = format!("{}/api/v1/sessions/{}", base, session["sessionId"]);
+    let ws_url = format!("{}/ws/sessions/{}", base.replace("http://", "ws://"), session["roomCode"].as_str().unwrap());
+    let (mut ws, _) = tokio_tungstenite::connect_async(&ws_url).await.unwrap();
+    let join = json!({"event":"join_room","payload":{"nickname":"Оля","joinToken": session["joinToken"]}});
+    ws.send(Message::Text(join.to_string())).await.unwrap();
+    next_event(&mut ws, "joined").await;
+    client.post(format!("{}/start", session_url)).headers(csrf_headers(&csrf)).send().await.unwrap();
+    ws.send(Message::Text(json!({"event":"request_question"}).to_string())).await.unwrap();
+    next_event(&mut ws, "question_push").await;
+    let submit = json!({"event":"answer_submit","payload":{"questionId":"q1","answer":{"text":"4"}}});
+    ws.send(Message::Text(submit.to_string())).await.unwrap();
+    next_event(&mut ws, "leaderboard_update").await;
+
+    let live = client.get(format!("{}/live", session_url)).send().await.unwrap();
+    assert_eq!(live.status(), 200);
+    let live = live.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(live["status"], "active");
+    assert_eq!(live["participants"][0]["nickname"], "Оля");
+    assert_eq!(live["participants"][0]["questionIndex"], 1);
+    assert_eq!(live["participants"][0]["correct"], 1);
+    assert_eq!(live["stats"]["students"][0]["nickname"], "Оля");
+    assert_eq!(live["missedEvents"], false);
+    let events: Vec<_> = live["events"].as_array().unwrap().iter().map(|e| e["event"].as_str().unwrap()).collect();
+    assert!(events.contains(&"start_quiz"));
+    assert!(events.contains(&"stats_update"));
+    assert!(!events.contains(&"question_push"));
+    let cursor = live["cursor"].as_u64().unwrap();
+    assert_eq!(live["events"].as_array().unwrap().last().unwrap()["cursor"], cursor);
+
+    let again = client.get(format!("{}/live?cursor={}", session_url, cursor)).send().await.unwrap();
+    let again = again.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(again["events"], json!([]));
+    assert_eq!(again["cursor"], cursor);
+}
+
+#[tokio::test]
+async fn follow_up_quizzes_are_drafted_from_class_mistakes() {
+    let (base, client) = spawn_server().await;
+    let csrf = auth(&base, &client, "followup_teacher").await;
+    let quiz_id = client
+        .post(format!("{}/api/v1/quizzes", base))
+        .headers(csrf_headers(&csrf))
+        .json(&sample_quiz_payload())
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap()["quiz_id"]
+        .as_i64()
+        .unwrap();
+    let session = client
+        .post(format!("{}/api/v1/sessions", base))
+        .headers(csrf_headers(&csrf))
+        .json(&json!({"quizId": quiz_id, "gameMode": "classic"}))
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    let followup_url = format!("{}/api/v1/sessions/{}/followup-quiz", base, session["sessionId"]);
+    let res = client.post(&followup_url).headers(csrf_headers(&csrf)).json(&json!({})).send().await.unwrap();
+    assert_eq!(res.status(), 409);
+
+    let ws_url = format!("{}/ws/sessions/{}", base.replace("http://", "ws://"), session["roomCode"].as_str().unwrap());
+    let start_url = format!("{}/api/v1/sessions/{}/start", base, session["sessionId"]);
+    let mut sockets = Vec::new();
+    for nickname in ["Оля", "Петя"] {
+        let (mut ws, _) = tokio_tungstenite::connect_async(&ws_url).await.unwrap();
+        let join = json!({"event":"join_room","payload":{"nickname": nickname,"joinToken": session["joinToken"]}});
+        ws.send(Message::Text(join.to_string())).await.unwrap();
+        next_event(&mut ws, "joined").await;
+        sockets.push(ws);
+    }
+    client.post(start_url).headers(csrf_headers(&csrf)).send().await.unwrap();
+    // Both miss q1, only Петя misses q2.
+    for (ws, q2) in sockets.iter_mut().zip(["o1", "o2"]) {
+        for (question_id, answer) in [("q1", json!({"text": "5"})), ("q2", json!({"optionId": q2}))] {
+            ws.send(Message::Text(json!({"event":"request_question"}).to_string())).await.unwrap();
+            next_event(ws, "question_push").await;
+            let submit = json!({"event":"answer_submit","payload":{"questionId": question_id,"answer": answer}});
+            ws.send(Message::Text(submit.to_string())).await.unwrap();
+            next_event(ws, "answer_result").await;
+        }
+    }
+
+    let res = client
+        .post(&followup_url)
+        .headers(csrf_headers(&csrf))
+        .json(&json!({"questionCount": 0}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 400);
+    let res = client
+        .post(&followup_url)
+        .headers(csrf_headers(&csrf))
+        .json(&json!({"questionCount": 2}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 201);
+    let body = res.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(body["source"], "ai");
+    assert_eq!(body["gaps"][0]["questionId"], "q1");
+    assert_eq!(body["gaps"][0]["misses"], 2);
+    assert_eq!(body["gaps"][0]["answered"], 2);
+    assert_eq!(body["gaps"][1]["questionId"], "q2");
+    assert_eq!(body["gaps"][1]["misses"], 1);
+    assert_eq!(body["gaps"].as_array().unwrap().len(), 2);
+
+    let draft = client
+        .get(format!("{}/api/v1/quizzes/{}", base, body["quizId"]))
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    assert_eq!(draft["questions"].as_array().unwrap().len(), 2);
+    assert_ne!(body["quizId"], quiz_id);
+}
+
+#[tokio::test]
+async fn spectators_follow_the_leaderboard_without_seeing_questions() {
+    let (base, client) = spawn_server().await;
+    let csrf = auth(&base, &client, "spectator_teacher").await;
+    let create_quiz = client
+        .post(format!("{}/api/v1/quizzes", base))
+        .headers(csrf_headers(&csrf))
+        .json(&sample_quiz_payload())
+        .send()
+        .await
+        .unwrap();
+    let quiz_id = create_quiz.json::<serde_json::Value>().await.unwrap()["quiz_id"].as_i64().unwrap();
+    let session = client
+        .post(format!("{}/api/v1/sessions", base))
+        .headers(csrf_headers(&csrf))
+        .json(&json!({"quizId": quiz_id, "gameMode": "classic"}))
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    let session_id = session["sessionId"].as_i64().unwrap();
+    let room = session["roomCode"].as_str().unwrap().to_string();
+    let join_token = session["joinToken"].as_str().unwrap().to_string();
+    let ws_url = format!("{}/ws/sessions/{}", base.replace("http://", "ws://"), room);
+
+    let (mut projector, _) = tokio_tungstenite::connect_async(&ws_url).await.unwrap();
+    let watch = json!({"event":"join_room","payload":{"role":"spectator","joinToken": join_token}});
+    projector.send(Message::Text(watch.to_string())).await.unwrap();
+    assert_eq!(next_event(&mut projector, "waiting_room_update").await["participants"], json!([]));
+    assert_eq!(next_event(&mut projector, "leaderboard_update").await["top"], json!([]));
+
+    let (mut olya, _) = tokio_tungstenite::connect_async(&ws_url).await.unwrap();
+    let join = json!({"event":"join_room","payload":{"nickname":"Оля","joinToken": join_token}});
+    olya.send(Message::Text(join.to_string())).await.unwrap();
+    next_event(&mut olya, "joined").await;
+    client
+        .post(format!("{}/api/v1/sessions/{}/start", base, session_id))
+        .headers(csrf_headers(&csrf))
+        .send()
+        .await
+        .unwrap();
+    olya.send(Message::Text(json!({"event":"request_question"}).to_string())).await.unwrap();
+    next_event(&mut olya, "question_push").await;
+    let submit = json!({"event":"answer_submit","payload":{"questionId":"q1","answer":{"text":"4"}}});
+    olya.send(Message::Text(submit.to_string())).await.unwrap();
+    next_event(&mut olya, "answer_result").await;
+
+    let mut seen = Vec::new();
+    let leaderboard = loop {
+        let Message::Text(msg) = projector.next().await.unwrap().unwrap() else { continue };
+        let msg: serde_json::Value = serde_json::from_str(&msg).unwrap();
+        seen.push(msg["event"].as_str().unwrap().to_string());
+        if msg["event"] == "leaderboard_update" {
+            break msg["payload"].clone();
+        }
+    };
+    assert!(seen.contains(&"waiting_room_update".to_string()));
+    assert!(seen.contains(&"start_quiz".to_string()));
+    assert!(seen.contains(&"stats_update".to_string()));
+    assert!(!seen.iter().any(|e| e == "question_push" || e == "answer_result"));
+    let top = leaderboard["top"].as_array().unwrap();
+    assert_eq!(top.len(), 1);
+    assert_eq!((&top[0]["rank"], &top[0]["nickname"], &top[0]["score"]), (&json!(1), &json!("Оля"), &json!(1.0)));
+    assert_eq!((&top[0]["correct"], &top[0]["streak"]), (&json!(1), &json!(1)));
+    // Answered right after the push, so nearly the whole speed bonus of half a point.
+    let bonus = top[0]["bonus"].as_f64().unwrap();
+    assert!(bonus > 0.4 && bonus <= 0.5);
+    assert_eq!(top[0]["points"].as_f64().unwrap(), 1.0 + bonus);
+    assert_eq!(leaderboard["total"], 1);
+}
+
+#[tokio::test]
+async fn answer_results_reach_only_the_submitting_student() {
+    let (base, client) = spawn_server().await;
+    let csrf = auth(&base, &client, "ws_direct_teacher").await;
+    let create_quiz = client
+        .post(format!("{}/api/v1/quizzes", base))
+        .headers(csrf_headers(&csrf))
+        .json(&sample_quiz_payload())
+        .send()
+        .await
+        .unwrap();
+    let quiz_id = create_quiz.json::<serde_json::Value>().await.unwrap()["quiz_id"].as_i64().unwrap();
+    let session = client
+        .post(format!("{}/api/v1/sessions", base))
+        .headers(csrf_headers(&csrf))
+        .json(&json!({"quizId": quiz_id, "gameMode": "classic"}))
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    let session_id = session["sessionId"].as_i64().unwrap();
+    let room = session["roomCode"].as_str().unwrap().to_string();
+    let join_token = session["joinToken"].as_str().unwrap().to_string();
+    let ws_url = base.replace("http://", "ws://");
+    let (mut olya, _) = tokio_tungstenite::connect_async(format!("{}/ws/sessions/{}", ws_url, room)).await.unwrap();
+    let (mut petya, _) = tokio_tungstenite::connect_async(format!("{}/ws/sessions/{}", ws_url, room)).await.unwrap();
+    olya.send(Message::Text(json!({"event":"join_room","payload":{"nickname":"Оля","joinToken": join_token}}).to_string())).await.unwrap();
+    next_event(&mut olya, "waiting_room_update").await;
+    petya.send(Message::Text(json!({"event":"join_room","payload":{"nickname":"Петя","joinToken": join_token}}).to_string())).await.unwrap();
+    next_event(&mut petya, "waiting_room_update").await;
+    client
+        .post(format!("{}/api/v1/sessions/{}/start", base, session_id))
+        .headers(csrf_headers(&csrf))
+        .send()
+        .await
+        .unwrap();
+    next_event(&mut olya, "start_quiz").await;
+
+    olya.send(Message::Text(json!({"event":"request_question","payload":{}}).to_string())).await.unwrap();
+    next_event(&mut olya, "question_push").await;
+    let submit = json!({"event":"answer_submit","payload":{"questionId":"q1","answer":{"text":"4"}}}).to_string();
+    olya.send(Message::Text(submit)).await.unwrap();
+    assert_eq!(next_event(&mut olya, "answer_result").await["correct"], true);
+    next_event(&mut olya, "stats_update").await;
+
+    // The other student sees the class stats move but neither the question nor the result.
+    loop {
+        let Message::Text(msg) = petya.next().await.unwrap().unwrap() else { continue };
+        let msg: serde_json::Value = serde_json::from_str(&msg).unwrap();
+        assert_ne!(msg["event"], "question_push");
+        assert_ne!(msg["event"], "answer_result");
+        if msg["event"] == "stats_update" {
+            assert_eq!(msg["payload"]["students"][0]["nickname"], "Оля");
+            break;
+        }
+    }
+}
+
+#[tokio::test]
+async fn session_ws_start_submit_stats_end_results() {
+    let (base, client) = spawn_server().await;
+    let csrf = auth(&base, &client, "live_teacher").await;
+
+    let create_quiz = client
+        .post(format!("{}/api/v1/quizzes", base))
+        .headers(csrf_headers(&csrf))
+        .json(&sample_quiz_payload())
+        .send()
+        .await
+        .unwrap();
+    let quiz_id = create_quiz.json::<serde_json::Value>().await.unwrap()["quiz_id"].as_i64().unwrap();
+
+    let session = client
+        .post(format!("{}/api/v1/sessions", base))
+        .headers(csrf_headers(&csrf))
+        .json(&json!({"quizId": quiz_id, "gameMode": "platformer"}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(session.status(), 201);
+    let session_json = session.json::<serde_json::Value>().await.unwrap();
+    let session_id = session_json["sessionId"].as_i64().unwrap();
+    let room = session_json["roomCode"].as_str().unwrap().to_string();
+    let join_token = session_json["joinToken"].as_str().unwrap().to_string();
+
+    let ws_url = base.replace("http://", "ws://");
+    let (mut ws, _) = tokio_tungstenite::connect_async(format!("{}/ws/sessions/{}", ws_url, room))
+        .await
+        .unwrap();
+
+    ws.send(Message::Text(
+        json!({"event":"join_room","payload":{"role":"student","nickname":"Ира","joinToken": join_token}}).to_string(),
+    ))
+    .await
+    .unwrap();
+
+    next_event(&mut ws, "joined").await;
+
+    let started = client
+        .post(format!("{}/api/v1/sessions/{}/start", base, session_id))
+        .headers(csrf_headers(&csrf))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(started.status(), 200);
+
+    next_event(&mut ws, "start_quiz").await;
+
+    ws.send(Message::Text(
+        json!({
+            "event":"answer_submit",
+            "payload": {"questionId": "q1", "answer": {"text": "5"}}
+        })
+        .to_string(),
+    ))
+    .await
+    .unwrap();
+
+    let result = next_event(&mut ws, "answer_result").await;
+    assert_eq!(result["correct"], false);
+    let stats = next_event(&mut ws, "stats_update").await;
+    assert_eq!(stats["students"][0]["wrong"], 1);
+
+    let ended = client
+        .post(format!("{}/api/v1/sessions/{}/end", base, session_id))
+        .headers(csrf_headers(&csrf))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(ended.status(), 200);
+
+    let results = client
+        .get(format!("{}/api/v1/sessions/{}/results", base, session_id))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(results.status(), 200);
+    let r = results.text().await.unwrap();
+    assert!(r.contains("mistakesByStudent"));
+
+    let history = client
+        .get(format!("{}/api/v1/sessions", base))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(history.status(), 200);
+    let history_json = history.json::<serde_json::Value>().await.unwrap();
+    let listed = history_json["items"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|s| s["id"].as_i64() == Some(session_id))
+        .cloned()
+        .unwrap();
+    assert_eq!(listed["status"], "finished");
+}
+
+#[tokio::test]
+async fn timed_questions_and_points() {
+    let (base, client) = spawn_server().await;
+    let csrf = auth(&base, &client, "timed_teacher").await;
+    let mut quiz = sample_quiz_payload();
+    quiz["questions"][0]["timeLimitSecs"] = json!(20);
+    quiz["questions"][0]["points"] = json!(3);
+    quiz["questions"][0]["explanation"] = json!("Два плюс два — четыре");
+    let quiz_id = client
+        .post(format!("{}/api/v1/quizzes", base))
+        .headers(csrf_headers(&csrf))
+        .json(&quiz)
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap()["quiz_id"]
+        .as_i64()
+        .unwrap();
+    let session = client
+        .post(format!("{}/api/v1/sessions", base))
+        .headers(csrf_headers(&csrf))
+        .json(&json!({"quizId": quiz_id, "gameMode": "classic"}))
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    let session_id = session["sessionId"].as_i64().unwrap();
+    let room = session["roomCode"].as_str().unwrap().to_string();
+    let join_token = session["joinToken"].as_str().unwrap().to_string();
+
+    let ws_url = base.replace("http://", "ws://");
+    let (mut ws, _) = tokio_tungstenite::connect_async(format!("{}/ws/sessions/{}", ws_url, room))
+        .await
+        .unwrap();
+    ws.send(Message::Text(json!({"event":"join_room","payload":{"role":"student","nickname":"Оля","joinToken": join_token}}).to_string()))
+        .await
+        .unwrap();
+    next_event(&mut ws, "waiting_room_update").await;
+    client
+        .post(format!("{}/api/v1/sessions/{}/start", base, session_id))
+        .headers(csrf_headers(&csrf))
+        .send()
+        .await
+        .unwrap();
+    next_event(&mut ws, "start_quiz").await;
+
+    ws.send(Message::Text(json!({"event":"request_question","payload":{}}).to_string())).await.unwrap();
+    let pushed = next_event(&mut ws, "question_push").await;
+    assert_eq!(pushed["question"]["timeLimitSecs"], 20);
+    assert!(pushed["deadline"].is_string());
+    assert!((18_000..=22_000).contains(&pushed["remainingMs"].as_i64().unwrap()));
+    let submit = json!({"event":"answer_submit","payload":{"questionId":"q1","answer":{"text":"4"}}}).to_string();
+    ws.send(Message::Text(submit.clone())).await.unwrap();
+    let on_time = next_event(&mut ws, "answer_result").await;
+    assert_eq!(on_time["correct"], true);
+    assert_eq!(on_time["points"], 3.0);
+    assert_eq!(on_time["streak"], 1);
+    assert!(on_time["bonus"].as_f64().unwrap() > 1.0);
+    assert_eq!(on_time["explanation"], "Два плюс два — четыре");
+    let stats = next_event(&mut ws, "stats_update").await;
+    assert_eq!(stats["students"][0]["score"], 3.0);
+    assert_eq!(stats["students"][0]["points"].as_f64().unwrap(), 3.0 + on_time["bonus"].as_f64().unwrap());
+
+    // A repeat is not scored again: the first result comes back, marked as a duplicate.
+    let resubmit = json!({"event":"answer_submit","payload":{"questionId":"q1","answer":{"text":"4"}},"request_id":"again"});
+    ws.send(Message::Text(resubmit.to_string())).await.unwrap();
+    let repeat = loop {
+        let Message::Text(msg) = ws.next().await.unwrap().unwrap() else { continue };
+        let msg: serde_json::Value = serde_json::from_str(&msg).unwrap();
+        if msg["event"] == "answer_result" {
+            break msg;
+        }
+    };
+    assert_eq!(repeat["request_id"], "again");
+    assert_eq!(repeat["payload"]["duplicate"], true);
+    assert_eq!(repeat["payload"]["points"], 3.0);
+    ws.send(Message::Text(json!({"event":"request_stats"}).to_string())).await.unwrap();
+    assert_eq!(next_event(&mut ws, "stats_update").await["students"][0]["score"], 3.0);
+
+    // A timed question answered without being pushed is late.
+    let (mut late_ws, _) = tokio_tungstenite::connect_async(format!("{}/ws/sessions/{}", ws_url, room)).await.unwrap();
+    late_ws.send(Message::Text(json!({"event":"join_room","payload":{"nickname":"Вика","joinToken": join_token}}).to_string()))
+        .await
+        .unwrap();
+    next_event(&mut late_ws, "joined").await;
+    late_ws.send(Message::Text(submit)).await.unwrap();
+    let late = next_event(&mut late_ws, "answer_result").await;
+    assert_eq!(late["late"], true);
+    assert_eq!(late["correct"], false);
+    assert_eq!(late["points"], 0.0);
+    assert_eq!((&late["bonus"], &late["streak"]), (&json!(0.0), &json!(0)));
+
+    let invalid = client
+        .post(format!("{}/api/v1/quizzes", base))
+        .headers(csrf_headers(&csrf))
+        .json(&{
+            let mut q = sample_quiz_payload();
+            q["questions"][1]["points"] = json!(0);
+            q
+        })
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(invalid.status(), 400);
+}
+
+#[tokio::test]
+async fn quiz_settings_retries_reveal_and_passing() {
+    let (base, client) = spawn_server().await;
+    let csrf = auth(&base, &client, "settings_teacher").await;
+    let mut quiz = sample_quiz_payload();
+    quiz["settings"] = json!({"retries": 1, "passingPct": 50, "showCorrectAnswer": true, "shuffleOptions": true});
+    let quiz_id = client
+        .post(format!("{}/api/v1/quizzes", base))
+        .headers(csrf_headers(&csrf))
+        .json(&quiz)
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap()["quiz_id"]
+        .as_i64()
+        .unwrap();
+    let session = client
+        .post(format!("{}/api/v1/sessions", base))
+        .headers(csrf_headers(&csrf))
+        .json(&json!({"quizId": quiz_id, "gameMode": "classic"}))
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    let session_id = session["sessionId"].as_i64().unwrap();
+    let room = session["roomCode"].as_str().unwrap().to_string();
+    let join_token = session["joinToken"].as_str().unwrap().to_string();
+
+    let ws_url = base.replace("http://", "ws://");
+    let (mut ws, _) = tokio_tungstenite::connect_async(format!("{}/ws/sessions/{}", ws_url, room))
+        .await
+        .unwrap();
+    ws.send(Message::Text(json!({"event":"join_room","payload":{"role":"student","nickname":"Дима","joinToken": join_token}}).to_string()))
+        .await
+        .unwrap();
+    next_event(&mut ws, "waiting_room_update").await;
+    client
+        .post(format!("{}/api/v1/sessions/{}/start", base, session_id))
+        .headers(csrf_headers(&csrf))
+        .send()
+        .await
+        .unwrap();
+    next_event(&mut ws, "start_quiz").await;
+
+    let answer = |question: &str, answer: serde_json::Value| {
+        Message::Text(json!({"event":"answer_submit","payload":{"questionId": question, "answer": answer}}).to_string())
+    };
+    ws.send(answer("q1", json!({"text": "5"}))).await.unwrap();
+    let first = next_event(&mut ws, "answer_result").await;
+    assert_eq!(first["nextAction"], "retry");
+    assert_eq!(first["retriesLeft"], 0);
+    assert!(first.get("correctAnswer").is_none());
+    let stats = next_event(&mut ws, "stats_update").await;
+    assert_eq!(stats["students"][0]["wrong"], 0);
+
+    ws.send(answer("q1", json!({"text": "6"}))).await.unwrap();
+    let second = next_event(&mut ws, "answer_result").await;
+    assert_eq!(second["nextAction"], "continue");
+    assert_eq!(second["correctAnswer"], json!({"text": "4"}));
+
+    ws.send(Message::Text(json!({"event":"request_question","payload":{}}).to_string())).await.unwrap();
+    let pushed = next_event(&mut ws, "question_push").await;
+    assert_eq!(pushed["question"]["id"], "q2");
+    assert_eq!(pushed["question"]["options"].as_array().unwrap().len(), 2);
+    // The participant's order is fixed: asking again shows the options the same way.
+    ws.send(Message::Text(json!({"event":"request_question","payload":{}}).to_string())).await.unwrap();
+    let again = next_event(&mut ws, "question_push").await;
+    assert_eq!(again["question"]["options"], pushed["question"]["options"]);
+    ws.send(answer("q2", json!({"optionId": "o1"}))).await.unwrap();
+    assert_eq!(next_event(&mut ws, "answer_result").await["correct"], true);
+    ws.send(answer("q3", json!({"optionIds": ["o1", "o3"]}))).await.unwrap();
+    assert_eq!(next_event(&mut ws, "answer_result").await["correct"], true);
+
+    client
+        .post(format!("{}/api/v1/sessions/{}/end", base, session_id))
+        .headers(csrf_headers(&csrf))
+        .send()
+        .await
+        .unwrap();
+    let results = client
+        .get(format!("{}/api/v1/sessions/{}/results", base, session_id))
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    assert_eq!(results["studentStats"][0]["score"], 2.0);
+    assert_eq!(results["studentStats"][0]["wrong"], 1);
+    assert_eq!(results["studentStats"][0]["passed"], true);
+}
+
+#[tokio::test]
+async fn shuffle_seeds_in_results_and_export_replay_what_students_saw() {
+    use quiz_backend::models::{question_order, shuffle_options, Question};
+
+    std::env::set_var("ADMIN_LOGINS", "backup_admin");
+    let (base, client) = spawn_server().await;
+    let csrf = auth(&base, &client, "backup_admin").await;
+    let mut quiz = sample_quiz_payload();
+    quiz["settings"] = json!({"shuffleQuestions": true, "shuffleOptions": true});
+    let quiz_id = client
+        .post(format!("{}/api/v1/quizzes", base))
+        .headers(csrf_headers(&csrf))
+        .json(&quiz)
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap()["quiz_id"]
+        .as_i64()
+        .unwrap();
+    let session = client
+        .post(format!("{}/api/v1/sessions", base))
+        .headers(csrf_headers(&csrf))
+        .json(&json!({"quizId": quiz_id, "gameMode": "classic"}))
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    let session_url = format!("{}/api/v1/sessions/{}", base, session["sessionId"]);
+    let ws_url = format!("{}/ws/sessions/{}", base.replace("http://", "ws://"), session["roomCode"].as_str().unwrap());
+    let (mut ws, _) = tokio_tungstenite::connect_async(&ws_url).await.unwrap();
+    let join = json!({"event":"join_room","payload":{"nickname":"Дима","joinToken": session["joinToken"]}});
+    ws.send(Message::Text(join.to_string())).await.unwrap();
+    next_event(&mut ws, "joined").await;
+    client.post(format!("{}/start", session_url)).headers(csrf_headers(&csrf)).send().await.unwrap();
+    let mut shown = Vec::new();
+    for _ in 0..3 {
+        ws.send(Message::Text(json!({"event":"request_question"}).to_string())).await.unwrap();
+        let question = next_event(&mut ws, "question_push").await["question"].clone();
+        let skip = json!({"event":"answer_submit","payload":{"questionId": question["id"],"answer":{"text":"?"}}});
+        ws.send(Message::Text(skip.to_string())).await.unwrap();
+        next_event(&mut ws, "answer_result").await;
+        shown.push(question);
+    }
+    client.post(format!("{}/end", session_url)).headers(csrf_headers(&csrf)).send().await.unwrap();
+
+    let results = client.get(format!("{}/results", session_url)).send().await.unwrap();
+    let results = results.json::<serde_json::Value>().await.unwrap();
+    let seed: u64 = results["studentStats"][0]["shuffleSeed"].as_str().unwrap().parse().unwrap();
+    let questions: Vec<Question> = serde_json::from_value(quiz["questions"].clone()).unwrap();
+    for (position, index) in question_order(questions.len(), seed).into_iter().enumerate() {
+        let mut replayed = questions[index].clone();
+        shuffle_options(&mut replayed, seed);
+        assert_eq!(shown[position]["id"], replayed.id.as_str());
+        assert_eq!(shown[position]["options"], serde_json::to_value(&replayed).unwrap()["options"]);
+    }
+
+    let export = client.get(format!("{}/api/v1/admin/export", base)).send().await.unwrap();
+    let export = export.json::<serde_json::Value>().await.unwrap();
+    let archived = export["sessions"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|s| s["id"] == session["sessionId"])
+        .unwrap();
+    assert_eq!(archived["participants"]["Дима"]["shuffle_seed"].as_u64(), Some(seed));
+}
+
+#[tokio::test]
+async fn ai_hints_over_websocket_respect_budget() {
+    let (base, client) = spawn_server().await;
+    let csrf = auth(&base, &client, "hint_teacher").await;
+    let mut quiz = sample_quiz_payload();
+    quiz["settings"] = json!({"hintsPerStudent": 1});
+    let quiz_id = client
+        .post(format!("{}/api/v1/quizzes", base))
+        .headers(csrf_headers(&csrf))
+        .json(&quiz)
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap()["quiz_id"]
+        .as_i64()
+        .unwrap();
+    let session = client
+        .post(format!("{}/api/v1/sessions", base))
+        .headers(csrf_headers(&csrf))
+        .json(&json!({"quizId": quiz_id, "gameMode": "classic"}))
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    let session_id = session["sessionId"].as_i64().unwrap();
+    let room = session["roomCode"].as_str().unwrap().to_string();
+    let join_token = session["joinToken"].as_str().unwrap().to_string();
+
+    let ws_url = format!("{}/ws/sessions/{}", base.replace("http://", "ws://"), room);
+    let join = Message::Text(json!({"event":"join_room","payload":{"role":"student","nickname":"Оля","joinToken": join_token}}).to_string());
+    let hint = |question: &str| {
+        Message::Text(json!({"event":"request_hint","payload":{"questionId": question}}).to_string())
+    };
+    let (mut ws, _) = tokio_tungstenite::connect_async(&ws_url).await.unwrap();
+    ws.send(join).await.unwrap();
+    let resume_token = next_event(&mut ws, "joined").await["resumeToken"].clone();
+    ws.send(hint("q1")).await.unwrap();
+    assert_eq!(next_event(&mut ws, "hint_rejected").await["reason"], "not_active");
+
+    client
+        .post(format!("{}/api/v1/sessions/{}/start", base, session_id))
+        .headers(csrf_headers(&csrf))
+        .send()
+        .await
+        .unwrap();
+    next_event(&mut ws, "start_quiz").await;
+
+    ws.send(hint("q1")).await.unwrap();
+    let first = next_event(&mut ws, "hint_push").await;
+    assert_eq!(first["questionId"], "q1");
+    assert_eq!(first["hintsLeft"], 0);
+    let text = first["hint"].as_str().unwrap().to_string();
+    assert!(!text.is_empty() && !text.contains('4'));
+
+    // Asking again for the same question is free and served from the cache.
+    ws.send(hint("q1")).await.unwrap();
+    assert_eq!(next_event(&mut ws, "hint_push").await["hint"], text.as_str());
+    ws.send(hint("q2")).await.unwrap();
+    assert_eq!(next_event(&mut ws, "hint_rejected").await["reason"], "budget_exhausted");
+    ws.send(hint("q9")).await.unwrap();
+    assert_eq!(next_event(&mut ws, "hint_rejected").await["reason"], "unknown_question");
+
+    // A reconnect does not refill the budget.
+    ws.close(None).await.unwrap();
+    let (mut ws, _) = tokio_tungstenite::connect_async(&ws_url).await.unwrap();
+    let resume = json!({"event":"join_room","payload":{"joinToken": join_token,"resumeToken": resume_token}});
+    ws.send(Message::Text(resume.to_string())).await.unwrap();
+    next_event(&mut ws, "resumed").await;
+    ws.send(hint("q2")).await.unwrap();
+    assert_eq!(next_event(&mut ws, "hint_rejected").await["reason"], "budget_exhausted");
+}
+
+#[tokio::test]
+async fn student_code_login_and_history() {
+    let (base, client) = spawn_server().await;
+    let csrf = auth(&base, &client, "class_teacher").await;
+
+    let codes = client
+        .post(format!("{}/api/v1/students/codes", base))
+        .headers(csrf_headers(&csrf))
+        .json(&json!({"names": ["Маша", "Петя"]}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(codes.status(), 201);
+    let codes = codes.json::<serde_json::Value>().await.unwrap();
+    let code = codes["items"][0]["accessCode"].as_str().unwrap().to_string();
+
+    let student_client = reqwest::Client::new();
+    let login = student_client
+        .post(format!("{}/api/v1/students/login", base))
+        .json(&json!({"code": code.to_lowercase()}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(login.status(), 200);
+    let token = login.json::<serde_json::Value>().await.unwrap()["studentToken"]
+        .as_str()
+        .unwrap()
+        .to_string();
+
+    let create_quiz = client
+        .post(format!("{}/api/v1/quizzes", base))
+        .headers(csrf_headers(&csrf))
+        .json(&sample_quiz_payload())
+        .send()
+        .await
+        .unwrap();
+    let quiz_id = create_quiz.json::<serde_json::Value>().await.unwrap()["quiz_id"].as_i64().unwrap();
+    let session_json = client
+        .post(format!("{}/api/v1/sessions", base))
+        .headers(csrf_headers(&csrf))
+        .json(&json!({"quizId": quiz_id, "gameMode": "classic"}))
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    let session_id = session_json["sessionId"].as_i64().unwrap();
+    let room = session_json["roomCode"].as_str().unwrap().to_string();
+    let join_token = session_json["joinToken"].as_str().unwrap().to_string();
+
+    let ws_url = base.replace("http://", "ws://");
+    let (mut ws, _) = tokio_tungstenite::connect_async(format!("{}/ws/sessions/{}", ws_url, room))
+        .await
+        .unwrap();
+    ws.send(Message::Text(
+        json!({"event":"join_room","payload":{"role":"student","studentToken": token,"joinToken": join_token}}).to_string(),
+    ))
+    .await
+    .unwrap();
+    let waiting = ws.next().await.unwrap().unwrap().into_text().unwrap();
+    assert!(waiting.contains("Маша"));
+
+    let me = student_client
+        .get(format!("{}/api/v1/students/me", base))
+        .bearer_auth(&token)
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    assert_eq!(me["student"]["displayName"], "Маша");
+    assert_eq!(me["history"][0]["sessionId"], session_id);
+
+    let as_teacher = student_client
+        .get(format!("{}/api/v1/quizzes", base))
+        .bearer_auth(&token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(as_teacher.status(), 401);
+}
+
+#[tokio::test]
+async fn students_rejoining_mid_game_keep_their_place() {
+    let (base, client) = spawn_server().await;
+    let csrf = auth(&base, &client, "rejoin_teacher").await;
+    let codes = client
+        .post(format!("{}/api/v1/students/codes", base))
+        .headers(csrf_headers(&csrf))
+        .json(&json!({"names": ["Маша"]}))
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    let token = reqwest::Client::new()
+        .post(format!("{}/api/v1/students/login", base))
+        .json(&json!({"code": codes["items"][0]["accessCode"]}))
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap()["studentToken"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    let quiz_id = client
+        .post(format!("{}/api/v1/quizzes", base))
+        .headers(csrf_headers(&csrf))
+        .json(&sample_quiz_payload())
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap()["quiz_id"]
+        .as_i64()
+        .unwrap();
+    let session = client
+        .post(format!("{}/api/v1/sessions", base))
+        .headers(csrf_headers(&csrf))
+        .json(&json!({"quizId": quiz_id, "gameMode": "classic"}))
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    let session_url = format!("{}/api/v1/sessions/{}", base, session["sessionId"]);
+    let ws_url = format!("{}/ws/sessions/{}", base.replace("http://", "ws://"), session["roomCode"].as_str().unwrap());
+    let join = json!({"event":"join_room","payload":{"role":"student","studentToken": token,"joinToken": session["joinToken"]}});
+    let progress = || async {
+        let live = client.get(format!("{}/live", session_url)).send().await.unwrap();
+        let live = live.json::<serde_json::Value>().await.unwrap();
+        (live["participants"][0]["questionIndex"].clone(), live["participants"][0]["pendingQuestion"].clone())
+    };
+
+    let (mut ws, _) = tokio_tungstenite::connect_async(&ws_url).await.unwrap();
+    ws.send(Message::Text(join.to_string())).await.unwrap();
+    let resume_token = next_event(&mut ws, "joined").await["resumeToken"].clone();
+    client.post(format!("{}/start", session_url)).headers(csrf_headers(&csrf)).send().await.unwrap();
+    ws.send(Message::Text(json!({"event":"request_question"}).to_string())).await.unwrap();
+    assert_eq!(next_event(&mut ws, "question_push").await["question"]["id"], "q1");
+    let answer = json!({"event":"answer_submit","payload":{"questionId":"q1","answer":{"text":"4"}}});
+    ws.send(Message::Text(answer.to_string())).await.unwrap();
+    assert_eq!(next_event(&mut ws, "answer_result").await["correct"], true);
+    ws.send(Message::Text(json!({"event":"request_question"}).to_string())).await.unwrap();
+    assert_eq!(next_event(&mut ws, "question_push").await["question"]["id"], "q2");
+    let before = progress().await;
+    assert_eq!(before.1, "q2");
+    drop(ws);
+
+    // Back on the same seat with the student token: same resume token, same open question.
+    let (mut ws, _) = tokio_tungstenite::connect_async(&ws_url).await.unwrap();
+    ws.send(Message::Text(join.to_string())).await.unwrap();
+    assert_eq!(next_event(&mut ws, "joined").await["resumeToken"], resume_token);
+    assert_eq!(progress().await, before);
+    let answer = json!({"event":"answer_submit","payload":{"questionId":"q2","answer":{"optionId":"o1"}}});
+    ws.send(Message::Text(answer.to_string())).await.unwrap();
+    let result = next_event(&mut ws, "answer_result").await;
+    assert_eq!(result["correct"], true);
+    assert!(result.get("duplicate").is_none());
+    ws.send(Message::Text(json!({"event":"request_question"}).to_string())).await.unwrap();
+    assert_eq!(next_event(&mut ws, "question_push").await["question"]["id"], "q3");
+}
+
+#[tokio::test]
+async fn lti_endpoints_reject_unregistered_platforms_and_states() {
+    let (base, client) = spawn_server().await;
+
+    let login = client
+        .get(format!("{}/lti/login?iss=https://lms.example&login_hint=42", base))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(login.status(), 400);
+
+    let launch = client
+        .post(format!("{}/lti/launch", base))
+        .form(&[("id_token", "not.a.jwt"), ("state", "unknown")])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(launch.status(), 401);
+    let body = launch.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(body["error"]["code"], "UNAUTHORIZED");
+
+    let jwks = client.get(format!("{}/lti/jwks", base)).send().await.unwrap();
+    assert_eq!(jwks.status(), 404);
+}
+
+#[tokio::test]
+async fn prometheus_metrics_expose_http_counters() {
+    let (base, client) = spawn_server().await;
+
+    let health = client.get(format!("{}/health", base)).send().await.unwrap();
+    assert_eq!(health.status(), 200);
+
+    let metrics = client.get(format!("{}/metrics", base)).send().await.unwrap();
+    assert_eq!(metrics.status(), 200);
+    assert!(metrics.headers()["content-type"].to_str().unwrap().starts_with("text/plain"));
+    let body = metrics.text().await.unwrap();
+    assert!(body.contains("http_requests_total"));
+    assert!(body.contains("route=\"/health\""));
+}
+
+#[tokio::test]
+async fn request_id_is_echoed_and_used_in_errors() {
+    let (base, client) = spawn_server().await;
+    let login = json!({"login":"nobody-here","password":"wrong-password"});
+
+    let traced = client
+        .post(format!("{}/api/v1/auth/login", base))
+        .header("x-request-id", "lb-trace-42")
+        .json(&login)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(traced.status(), 401);
+    assert_eq!(traced.headers()["x-request-id"], "lb-trace-42");
+    let body = traced.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(body["error"]["request_id"], "lb-trace-42");
+
+    let generated = client.post(format!("{}/api/v1/auth/login", base)).json(&login).send().await.unwrap();
+    let header = generated.headers()["x-request-id"].to_str().unwrap().to_string();
+    assert!(!header.is_empty());
+    let body = generated.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(body["error"]["request_id"], header.as_str());
+
+    let health = client.get(format!("{}/health", base)).header("x-request-id", "bad id\t").send().await.unwrap();
+    assert_ne!(health.headers()["x-request-id"], "bad id\t");
+}
+
+#[tokio::test]
+async fn route_rate_limit_answers_with_retry_after() {
+    let (base, client) = spawn_server().await;
+    let request = || {
+        client
+            .post(format!("{}/api/v1/auth/password-reset", base))
+            .json(&json!({"login": "nobody-here"}))
+            .send()
+    };
+
+    for _ in 0..5 {
+        assert_ne!(request().await.unwrap().status(), 429);
+    }
+    let limited = request().await.unwrap();
+    assert_eq!(limited.status(), 429);
+    let retry_after = limited.headers()["retry-after"].to_str().unwrap().parse::<u64>().unwrap();
+    assert!((1..=60).contains(&retry_after));
+    let body = limited.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(body["error"]["code"], "RATE_LIMITED");
+
+    // Other routes keep their own counters.
+    let login = client
+        .post(format!("{}/api/v1/auth/login", base))
+        .json(&json!({"login": "nobody-here", "password": "wrong-password"}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(login.status(), 401);
+}
+
+#[test]
+fn cors_origins_must_be_bare_origins() {
+    use quiz_backend::web::CorsOrigins;
+
+    assert_eq!(CorsOrigins::parse_origin("https://quiz.example.org").as_deref(), Some("https://quiz.example.org"));
+    assert_eq!(CorsOrigins::parse_origin("http://localhost:5173").as_deref(), Some("http://localhost:5173"));
+    assert!(CorsOrigins::parse_origin("https://quiz.example.org/").is_none());
+    assert!(CorsOrigins::parse_origin("https://quiz.example.org/app").is_none());
+    assert!(CorsOrigins::parse_origin("quiz.example.org").is_none());
+    assert!(CorsOrigins::parse_origin("ftp://quiz.example.org").is_none());
+    assert!(CorsOrigins::parse_origin("*").is_none());
+}
+
+#[tokio::test]
+async fn cors_allows_only_configured_origins() {
+    let (base, client) = spawn_server().await;
+    let preflight = |origin: &'static str| {
+        client
+            .request(reqwest::Method::OPTIONS, format!("{}/api/v1/auth/login", base))
+            .header("origin", origin)
+            .header("access-control-request-method", "POST")
+            .send()
+    };
+
+    let allowed = preflight("http://localhost:5173").await.unwrap();
+    assert_eq!(allowed.headers()["access-control-allow-origin"], "http://localhost:5173");
+    assert_eq!(allowed.headers()["access-control-allow-credentials"], "true");
+
+    let denied = preflight("https://evil.example").await.unwrap();
+    assert!(denied.headers().get("access-control-allow-origin").is_none());
+}
+
+#[tokio::test]
+async fn json_rejections_use_the_error_format() {
+    let (base, client) = spawn_server().await;
+    let url = format!("{}/api/v1/auth/login", base);
+    let error_of = |resp: reqwest::Response| async move {
+        let status = resp.status();
+        (status, resp.json::<serde_json::Value>().await.unwrap()["error"].clone())
+    };
+
+    let malformed = client
+        .post(&url)
+        .header("content-type", "application/json")
+        .header("x-request-id", "json-check")
+        .body("{\"login\": ")
+        .send()
+        .await
+        .unwrap();
+    let (status, error) = error_of(malformed).await;
+    assert_eq!(status, 400);
+    assert_eq!(error["code"], "VALIDATION_ERROR");
+    assert_eq!(error["details"][0]["field"], "body");
+    assert_eq!(error["request_id"], "json-check");
+
+    let wrong_type = client.post(&url).json(&json!({"login": "teacher", "password": 42})).send().await.unwrap();
+    let (status, error) = error_of(wrong_type).await;
+    assert_eq!(status, 400);
+    assert_eq!(error["details"][0]["field"], "password");
+    assert!(error["details"][0]["issue"].as_str().unwrap().contains("invalid type"));
+
+    let plain = client.post(&url).body("login=teacher").send().await.unwrap();
+    let (status, error) = error_of(plain).await;
+    assert_eq!(status, 400);
+    assert_eq!(error["details"][0]["field"], "content-type");
+
+    let huge = json!({"login": "x".repeat(200 * 1024), "password": "secret-password"});
+    let too_large = client.post(&url).json(&huge).send().await.unwrap();
+    let (status, error) = error_of(too_large).await;
+    assert_eq!(status, 413);
+    assert_eq!(error["code"], "PAYLOAD_TOO_LARGE");
+}
+
+#[tokio::test]
+async fn quiz_reads_carry_etags_and_large_bodies_are_gzipped() {
+    let (base, client) = spawn_server().await;
+    let csrf = auth(&base, &client, "etag_teacher").await;
+    let mut quiz = sample_quiz_payload();
+    quiz["questions"] = json!((1..=10)
+        .map(|i| json!({"id": format!("q{i}"), "type": "open", "prompt": "Длинный вопрос ".repeat(60), "answer": {"text": "да"}}))
+        .collect::<Vec<_>>());
+    let id = client
+        .post(format!("{}/api/v1/quizzes", base))
+        .headers(csrf_headers(&csrf))
+        .json(&quiz)
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap()["quiz_id"]
+        .as_i64()
+        .unwrap();
+    let quiz_url = format!("{}/api/v1/quizzes/{}", base, id);
+
+    let first = client.get(&quiz_url).send().await.unwrap();
+    assert_eq!(first.status(), 200);
+    let tag = first.headers()["etag"].to_str().unwrap().to_string();
+    let cached = client.get(&quiz_url).header("if-none-match", &tag).send().await.unwrap();
+    assert_eq!(cached.status(), 304);
+    assert_eq!(cached.headers()["etag"], tag.as_str());
+
+    client
+        .post(format!("{}/api/v1/quizzes/{}/publish", base, id))
+        .headers(csrf_headers(&csrf))
+        .send()
+        .await
+        .unwrap();
+    let changed = client.get(&quiz_url).header("if-none-match", &tag).send().await.unwrap();
+    assert_eq!(changed.status(), 200);
+    assert_ne!(changed.headers()["etag"], tag.as_str());
+
+    // A change that is refused leaves cached copies valid.
+    let tag = changed.headers()["etag"].to_str().unwrap().to_string();
+    let intruder = reqwest::Client::builder().cookie_store(true).build().unwrap();
+    let intruder_csrf = auth(&base, &intruder, "etag_intruder").await;
+    let update = intruder.put(&quiz_url).headers(csrf_headers(&intruder_csrf)).json(&quiz).send().await.unwrap();
+    assert_eq!(update.status(), 403);
+    let delete = intruder.delete(&quiz_url).headers(csrf_headers(&intruder_csrf)).send().await.unwrap();
+    assert_eq!(delete.status(), 403);
+    let cached = client.get(&quiz_url).header("if-none-match", &tag).send().await.unwrap();
+    assert_eq!(cached.status(), 304);
+
+    let library_url = format!("{}/api/v1/library/quizzes", base);
+    let library = client.get(&library_url).send().await.unwrap();
+    let library_tag = library.headers()["etag"].to_str().unwrap().to_string();
+    let cached = client.get(&library_url).header("if-none-match", &library_tag).send().await.unwrap();
+    assert_eq!(cached.status(), 304);
+    let other_page = client.get(format!("{}?limit=1", library_url)).header("if-none-match", &library_tag).send().await;
+    assert_eq!(other_page.unwrap().status(), 200);
+
+    let gzipped = client.get(&quiz_url).header("accept-encoding", "gzip").send().await.unwrap();
+    assert_eq!(gzipped.headers()["content-encoding"], "gzip");
+    let compressed = gzipped.bytes().await.unwrap();
+    let mut decoded = String::new();
+    std::io::Read::read_to_string(&mut flate2::read::GzDecoder::new(&compressed[..]), &mut decoded).unwrap();
+    assert!(compressed.len() < decoded.len());
+    assert_eq!(serde_json::from_str::<serde_json::Value>(&decoded).unwrap()["id"], id);
+}
+
+#[tokio::test]
+async fn admin_stats_requires_admin_and_counts_content() {
+    std::env::set_var("ADMIN_LOGINS", "backup_admin");
+    let (base, client) = spawn_server().await;
+    let csrf = auth(&base, &client, "stats_teacher").await;
+    client
+        .post(format!("{}/api/v1/quizzes", base))
+        .headers(csrf_headers(&csrf))
+        .json(&sample_quiz_payload())
+        .send()
+        .await
+        .unwrap();
+    let forbidden = client.get(format!("{}/api/v1/admin/stats", base)).send().await.unwrap();
+    assert_eq!(forbidden.status(), 403);
+
+    auth(&base, &client, "backup_admin").await;
+    let stats = client.get(format!("{}/api/v1/admin/stats", base)).send().await.unwrap();
+    assert_eq!(stats.status(), 200);
+    let body = stats.json::<serde_json::Value>().await.unwrap();
+    assert!(body["teachers"].as_u64().unwrap() >= 2);
+    assert!(body["quizzes"]["total"].as_u64().unwrap() >= 1);
+    assert!(body["sessions"]["active"].is_number());
+    assert!(body["wsConnections"].is_number());
+    assert!(body["aiToday"]["requests"].is_number());
+    assert!(body["storage"]["snapshot"].is_string());
+    assert_eq!(body["storage"]["shared"], "memory");
+}
+
+#[tokio::test]
+async fn audit_log_records_sensitive_actions_for_admins() {
+    std::env::set_var("ADMIN_LOGINS", "backup_admin");
+    let (base, client) = spawn_server().await;
+    let failed = reqwest::Client::new()
+        .post(format!("{}/api/v1/auth/login", base))
+        .json(&json!({"login": "audit_teacher", "password": "wrong-password"}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(failed.status(), 401);
+    let csrf = auth(&base, &client, "audit_teacher").await;
+    let created = client
+        .post(format!("{}/api/v1/quizzes", base))
+        .headers(csrf_headers(&csrf))
+        .json(&sample_quiz_payload())
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    let id = created["quiz_id"].as_i64().unwrap();
+    client
+        .post(format!("{}/api/v1/quizzes/{}/publish", base, id))
+        .headers(csrf_headers(&csrf))
+        .header("x-request-id", "audit-publish-1")
+        .send()
+        .await
+        .unwrap();
+    client.delete(format!("{}/api/v1/quizzes/{}", base, id)).headers(csrf_headers(&csrf)).send().await.unwrap();
+
+    let forbidden = client.get(format!("{}/api/v1/admin/audit", base)).send().await.unwrap();
+    assert_eq!(forbidden.status(), 403);
+
+    auth(&base, &client, "backup_admin").await;
+    let quiz_actions = client
+        .get(format!("{}/api/v1/admin/audit?actor=audit_teacher&action=quiz", base))
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    let items = quiz_actions["items"].as_array().unwrap();
+    let actions: Vec<&str> = items.iter().map(|e| e["action"].as_str().unwrap()).collect();
+    assert_eq!(actions, ["quiz.delete", "quiz.publish"]);
+    assert_eq!(items[1]["target"], id.to_string());
+    assert_eq!(items[1]["requestId"], "audit-publish-1");
+
+    let logins = client
+        .get(format!("{}/api/v1/admin/audit?actor=audit_teacher&action=auth", base))
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    let actions: Vec<&str> = logins["items"].as_array().unwrap().iter().map(|e| e["action"].as_str().unwrap()).collect();
+    assert!(actions.contains(&"auth.login"));
+    assert!(actions.contains(&"auth.login_failed"));
+    assert_eq!(logins["count"], actions.len());
+}
+
+#[tokio::test]
+async fn api_v2_quiz_bodies_are_camel_case_and_v1_is_unchanged() {
+    let (base, client) = spawn_server().await;
+    let csrf = auth(&base, &client, "v2_teacher").await;
+    let created = client
+        .post(format!("{}/api/v2/quizzes", base))
+        .headers(csrf_headers(&csrf))
+        .json(&sample_quiz_payload())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(created.status(), 201);
+    let id = created.json::<serde_json::Value>().await.unwrap()["quizId"].as_i64().unwrap();
+
+    let quiz = client
+        .get(format!("{}/api/v2/quizzes/{}", base, id))
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    assert_eq!(quiz["isPublished"], false);
+    assert!(quiz["ownerTeacherId"].is_number());
+    assert!(quiz.get("is_published").is_none());
+
+    let list = client
+        .get(format!("{}/api/v2/quizzes", base))
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    assert_eq!(list["items"][0]["isPublished"], false);
+    assert!(list.get("nextCursor").is_some());
+
+    let updated = client
+        .put(format!("{}/api/v2/quizzes/{}", base, id))
+        .headers(csrf_headers(&csrf))
+        .json(&sample_quiz_payload())
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    assert_eq!(updated["quizId"], id);
+
+    let without_csrf = client.delete(format!("{}/api/v2/quizzes/{}", base, id)).send().await.unwrap();
+    assert_eq!(without_csrf.status(), 403);
+
+    let v1 = client
+        .get(format!("{}/api/v1/quizzes/{}", base, id))
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    assert_eq!(v1["is_published"], false);
+}
+
+#[tokio::test]
+async fn media_uploads_are_checked_deduplicated_and_served_by_signed_link() {
+    let (base, client) = spawn_server().await;
+    let csrf = auth(&base, &client, "media_teacher").await;
+    // Uploads land in MEDIA_DIR and outlive the test, so every run needs bytes of its own.
+    let run = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_nanos().to_string();
+    let png = [b"\x89PNG\r\n\x1a\n".as_slice(), run.as_bytes()].concat();
+    let upload = |bytes: Vec<u8>, mime: &str| {
+        let part = reqwest::multipart::Part::bytes(bytes).file_name("picture").mime_str(mime).unwrap();
+        client
+            .post(format!("{}/api/v1/media", base))
+            .headers(csrf_headers(&csrf))
+            .multipart(reqwest::multipart::Form::new().part("file", part))
+            .send()
+    };
+
+    let first = upload(png.clone(), "image/png").await.unwrap();
+    assert_eq!(first.status(), 201);
+    let first = first.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(first["contentType"], "image/png");
+    assert_eq!(first["size"], png.len());
+    let again = upload(png.clone(), "application/octet-stream").await.unwrap();
+    assert_eq!(again.status(), 200);
+    assert_eq!(again.json::<serde_json::Value>().await.unwrap()["id"], first["id"]);
+
+    let svg = upload(b"<svg xmlns=\"http://www.w3.org/2000/svg\"/>".to_vec(), "image/svg+xml").await.unwrap();
+    assert_eq!(svg.status(), 400);
+    let mislabeled = upload(png.clone(), "audio/mpeg").await.unwrap();
+    assert_eq!(mislabeled.status(), 400);
+    let body = mislabeled.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(body["error"]["details"][0]["field"], "file");
+
+    let url = first["url"].as_str().unwrap();
+    let download = reqwest::get(format!("{}{}", base, url)).await.unwrap();
+    assert_eq!(download.status(), 200);
+    assert_eq!(download.headers()["content-type"], "image/png");
+    assert_eq!(download.bytes().await.unwrap().as_ref(), png.as_slice());
+    let tampered = reqwest::get(format!("{}{}0", base, url)).await.unwrap();
+    assert_eq!(tampered.status(), 403);
+
+    let id = first["id"].as_str().unwrap();
+    let link = client.get(format!("{}/api/v1/media/{}/url", base, id)).send().await.unwrap();
+    assert!(link.json::<serde_json::Value>().await.unwrap()["url"].as_str().unwrap().starts_with("/api/v1/media/"));
+    let missing = client.get(format!("{}/api/v1/media/{}/url", base, "0".repeat(64))).send().await.unwrap();
+    assert_eq!(missing.status(), 404);
+}
+
+// Keeps what would have been mailed, failing the first `failures` attempts.
+struct RecordingSender {
+    sent: std::sync::Arc<std::sync::Mutex<Vec<quiz_backend::email::EmailMessage>>>,
+    failures: std::sync::Arc<std::sync::atomic::AtomicU32>,
+}
+
+#[async_trait::async_trait]
+impl quiz_backend::email::EmailSender for RecordingSender {
+    fn kind(&self) -> &'static str {
+        "recording"
+    }
+
+    async fn send(&self, message: &quiz_backend::email::EmailMessage) -> anyhow::Result<()> {
+        if self.failures.load(std::sync::atomic::Ordering::SeqCst) > 0 {
+            self.failures.fetch_sub(1, std::sync::atomic::Ordering::SeqCst);
+            anyhow::bail!("smtp server is down");
+        }
+        self.sent.lock().unwrap().push(message.clone());
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn emails_go_out_for_reset_codes_reminders_and_results() {
+    use quiz_backend::email::{EmailMessage, EmailSettings, Locale, Mailer};
+
+    let sent = Arc::new(std::sync::Mutex::new(Vec::<EmailMessage>::new()));
+    let failures = Arc::new(std::sync::atomic::AtomicU32::new(0));
+    let sender = RecordingSender { sent: sent.clone(), failures: failures.clone() };
+    let mailer = Arc::new(Mailer::new(
+        Arc::new(sender),
+        EmailSettings {
+            max_attempts: 3,
+            retry_base: std::time::Duration::from_millis(50),
+            queue_size: 10,
+            default_locale: Locale::Ru,
+            reminder_lead: chrono::Duration::milliseconds(1000),
+            results_summary: true,
+            base_url: Some("https://quiz.example.org".into()),
+        },
+    ));
+    let mut state = scratch_state();
+    state.email = mailer.clone();
+    state.reset_delivery = Arc::new(quiz_backend::password_reset::EmailDelivery { mailer });
+    state.restore().await.expect("restore");
+    let (base, client) = serve(state).await;
+    let csrf = auth(&base, &client, "email_teacher").await;
+    let wait_for = |subject: &'static str| {
+        let sent = sent.clone();
+        async move {
+            for _ in 0..100 {
+                if let Some(message) = sent.lock().unwrap().iter().find(|m| m.subject.contains(subject)) {
+                    return message.clone();
+                }
+                tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+            }
+            panic!("no email \"{}\" was sent", subject);
+        }
+    };
+
+    let settings = client.get(format!("{}/api/v1/auth/email", base)).send().await.unwrap();
+    assert_eq!(settings.json::<serde_json::Value>().await.unwrap(), json!({"email": null, "locale": "ru"}));
+    let invalid = client
+        .put(format!("{}/api/v1/auth/email", base))
+        .headers(csrf_headers(&csrf))
+        .json(&json!({"email": "teacher@example.org\r\nBcc: x@y.z", "locale": "de", "currentPassword": "password123"}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(invalid.status(), 400);
+    let body = invalid.json::<serde_json::Value>().await.unwrap();
+    let fields: Vec<_> = body["error"]["details"].as_array().unwrap().iter().map(|d| d["field"].clone()).collect();
+    assert_eq!(fields, vec![json!("email"), json!("locale")]);
+    let update = |password: &str| {
+        client
+            .put(format!("{}/api/v1/auth/email", base))
+            .headers(csrf_headers(&csrf))
+            .json(&json!({"email": "teacher@example.org", "locale": "en", "currentPassword": password}))
+            .send()
+    };
+    assert_eq!(update("wrong-password").await.unwrap().status(), 403);
+    let updated = update("password123").await.unwrap().json::<serde_json::Value>().await.unwrap();
+    assert_eq!(updated, json!({"email": "teacher@example.org", "locale": "en"}));
+
+    // A scheduled start is announced to the teacher ahead of time.
+    let quiz_id = client
+        .post(format!("{}/api/v1/quizzes", base))
+        .headers(csrf_headers(&csrf))
+        .json(&sample_quiz_payload())
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap()["quiz_id"]
+        .as_i64()
+        .unwrap();
+    let starts_at = (chrono::Utc::now() + chrono::Duration::milliseconds(1500)).to_rfc3339();
+    let session = client
+        .post(format!("{}/api/v1/sessions", base))
+        .headers(csrf_headers(&csrf))
+        .json(&json!({"quizId": quiz_id, "gameMode": "classic", "startsAt": starts_at}))
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    let session_id = session["sessionId"].as_i64().unwrap();
+    let room = session["roomCode"].as_str().unwrap();
+    let reminder = wait_for("starts soon").await;
+    assert_eq!(reminder.to, "teacher@example.org");
+    assert!(reminder.body.contains(&format!("Room code: {}", room)));
+    assert!(reminder.body.contains(&format!("https://quiz.example.org/teacher/sessions/{}/waiting", session_id)));
+
+    // Once the session is over the teacher gets its results.
+    let ws_url = format!("{}/ws/sessions/{}", base.replace("http://", "ws://"), room);
+    let (mut ws, _) = tokio_tungstenite::connect_async(&ws_url).await.unwrap();
+    let join = json!({"event":"join_room","payload":{"nickname":"Лиза","joinToken": session["joinToken"]}});
+    ws.send(Message::Text(join.to_string())).await.unwrap();
+    next_event(&mut ws, "start_quiz").await;
+    let submit = json!({"event":"answer_submit","payload":{"questionId":"q1","answer":{"text":"4"}}});
+    ws.send(Message::Text(submit.to_string())).await.unwrap();
+    assert_eq!(next_event(&mut ws, "answer_result").await["correct"], true);
+    let end = client
+        .post(format!("{}/api/v1/sessions/{}/end", base, session_id))
+        .headers(csrf_headers(&csrf))
+        .send()
+        .await
+        .unwrap();
+    assert!(end.status().is_success());
+    let summary = wait_for("Results of").await;
+    assert_eq!(summary.subject, "Results of \"Математика\"");
+    assert!(summary.body.contains("Participants: 1"));
+    assert!(summary.body.contains("1. Лиза"));
+
+    // The reset code goes by email even though the first delivery attempt fails.
+    failures.store(1, std::sync::atomic::Ordering::SeqCst);
+    let reset = client
+        .post(format!("{}/api/v1/auth/password-reset", base))
+        .json(&json!({"login": "email_teacher"}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(reset.status(), 202);
+    let message = wait_for("Password reset").await;
+    assert_eq!(failures.load(std::sync::atomic::Ordering::SeqCst), 0);
+    let code = message.body.lines().find_map(|l| l.strip_prefix("Code: ")).unwrap().to_string();
+    let confirm = client
+        .post(format!("{}/api/v1/auth/password-reset/confirm", base))
+        .json(&json!({"token": code, "newPassword": "password456"}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(confirm.status(), 204);
+}
+
+#[tokio::test]
+async fn obscene_nicknames_are_refused_and_open_answers_masked() {
+    use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+
+    let (base, client) = spawn_server().await;
+    auth(&base, &client, "profanity_teacher").await;
+    let login = client
+        .post(format!("{}/api/v1/auth/login", base))
+        .json(&json!({"login": "profanity_teacher", "password": "password123"}))
+        .send()
+        .await
+        .unwrap();
+    let cookie = |name: &str| login.cookies().find(|c| c.name() == name).map(|c| c.value().to_string()).unwrap();
+    let (sid, csrf) = (cookie("teacher_session"), cookie("csrf_token"));
+    let words_url = format!("{}/api/v1/moderation/words", base);
+    let words = client.get(&words_url).send().await.unwrap().json::<serde_json::Value>().await.unwrap();
+    assert_eq!(words, json!({"allow": [], "block": []}));
+    let invalid = client
+        .put(&words_url)
+        .headers(csrf_headers(&csrf))
+        .json(&json!({"allow": ["хер*"], "block": ["*"]}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(invalid.status(), 400);
+    assert_eq!(invalid.json::<serde_json::Value>().await.unwrap()["error"]["details"].as_array().unwrap().len(), 2);
+    let words = client
+        .put(&words_url)
+        .headers(csrf_headers(&csrf))
+        .json(&json!({"allow": ["Хер"], "block": ["шмурдяк*"]}))
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    assert_eq!(words, json!({"allow": ["хер"], "block": ["шмурдяк*"]}));
+
+    let quiz_id = client
+        .post(format!("{}/api/v1/quizzes", base))
+        .headers(csrf_headers(&csrf))
+        .json(&sample_quiz_payload())
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap()["quiz_id"]
+        .as_i64()
+        .unwrap();
+    let session = client
+        .post(format!("{}/api/v1/sessions", base))
+        .headers(csrf_headers(&csrf))
+        .json(&json!({"quizId": quiz_id, "gameMode": "classic"}))
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    let session_url = format!("{}/api/v1/sessions/{}", base, session["sessionId"]);
+    let ws_url = format!("{}/ws/sessions/{}", base.replace("http://", "ws://"), session["roomCode"].as_str().unwrap());
+    let join = |nickname: &str| {
+        let payload = json!({"nickname": nickname, "joinToken": session["joinToken"]});
+        Message::Text(json!({"event": "join_room", "payload": payload}).to_string())
+    };
+
+    let mut request = ws_url.as_str().into_client_request().unwrap();
+    request.headers_mut().insert("cookie", format!("teacher_session={}", sid).parse().unwrap());
+    let (mut teacher, _) = tokio_tungstenite::connect_async(request).await.unwrap();
+    teacher.send(Message::Text(json!({"event":"join_room","payload":{"role":"teacher","csrf": csrf}}).to_string())).await.unwrap();
+    next_event(&mut teacher, "waiting_room_update").await;
+    let (mut olya, _) = tokio_tungstenite::connect_async(&ws_url).await.unwrap();
+    for nickname in ["xyй", "F.u.c.k", "Шмурдяков"] {
+        olya.send(join(nickname)).await.unwrap();
+        let rejected = next_event(&mut olya, "join_rejected").await;
+        assert_eq!(rejected["reason"], "inappropriate_nickname", "{}", nickname);
+    }
+    olya.send(join("Оля")).await.unwrap();
+    next_event(&mut olya, "joined").await;
+    // The teacher allowed the word, say a student's surname.
+    let (mut kher, _) = tokio_tungstenite::connect_async(&ws_url).await.unwrap();
+    kher.send(join("Хер")).await.unwrap();
+    next_event(&mut kher, "joined").await;
+
+    client.post(format!("{}/start", session_url)).headers(csrf_headers(&csrf)).send().await.unwrap();
+    for (ws, text) in [(&mut olya, "4"), (&mut kher, "fuck")] {
+        ws.send(Message::Text(json!({"event":"request_question"}).to_string())).await.unwrap();
+        next_event(ws, "question_push").await;
+        let submit = json!({"event":"answer_submit","payload":{"questionId":"q1","answer":{"text": text}}});
+        ws.send(Message::Text(submit.to_string())).await.unwrap();
+        next_event(ws, "answer_result").await;
+    }
+    client
+        .post(format!("{}/end", session_url))
+        .headers(csrf_headers(&csrf))
+        .json(&json!({"reviewMinutes": 5}))
+        .send()
+        .await
+        .unwrap();
+    teacher.send(Message::Text(json!({"event":"review_next"}).to_string())).await.unwrap();
+    let review = next_event(&mut olya, "review_question").await;
+    assert_eq!(review["openAnswers"], json!([{"text": "4", "count": 1}, {"text": "****", "count": 1}]));
+}
+
+#[tokio::test]
+async fn restarts_replay_the_journal_over_the_snapshot() {
+    let dir = std::env::temp_dir().join(format!("quiz_journal_{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let journal_path = dir.join("state.journal");
+    let state = restart_in(&dir).await.unwrap();
+    let (base, client) = serve(state.clone()).await;
+    let csrf = auth(&base, &client, "journal_teacher").await;
+    let mut quiz = sample_quiz_payload();
+    let first_id = client
+        .post(format!("{}/api/v1/quizzes", base))
+        .headers(csrf_headers(&csrf))
+        .json(&quiz)
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap()["quiz_id"]
+        .as_i64()
+        .unwrap();
+    state.persist_core_data().await.unwrap();
+    assert_eq!(std::fs::read_to_string(&journal_path).unwrap(), "");
+
+    // These two changes reach only the journal.
+    quiz["title"] = json!("После снимка");
+    let update = client
+        .put(format!("{}/api/v1/quizzes/{}", base, first_id))
+        .headers(csrf_headers(&csrf))
+        .json(&quiz)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(update.status(), 200);
+    quiz["title"] = json!("Только в журнале");
+    let second_id = client
+        .post(format!("{}/api/v1/quizzes", base))
+        .headers(csrf_headers(&csrf))
+        .json(&quiz)
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap()["quiz_id"]
+        .as_i64()
+        .unwrap();
+    // A crash in the middle of an append leaves half a line at the end.
+    let mut journal = std::fs::read_to_string(&journal_path).unwrap();
+    journal.push_str(r#"{"seq":999,"at":"2026-"#);
+    std::fs::write(&journal_path, &journal).unwrap();
+
+    let (base, client) = serve(restart_in(&dir).await.unwrap()).await;
+    let csrf = auth(&base, &client, "journal_teacher").await;
+    for (id, title) in [(first_id, "После снимка"), (second_id, "Только в журнале")] {
+        let quiz = client.get(format!("{}/api/v1/quizzes/{}", base, id)).send().await.unwrap();
+        assert_eq!(quiz.status(), 200);
+        assert_eq!(quiz.json::<serde_json::Value>().await.unwrap()["title"], title);
+    }
+    // The torn tail is cut off, so the next entry starts on a line of its own.
+    let create = client
+        .post(format!("{}/api/v1/quizzes", base))
+        .headers(csrf_headers(&csrf))
+        .json(&sample_quiz_payload())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(create.status(), 201);
+    let journal = std::fs::read_to_string(&journal_path).unwrap();
+    assert!(journal.lines().all(|line| serde_json::from_str::<serde_json::Value>(line).is_ok()));
+
+    // Damage before the last line is not a torn write: startup refuses instead of skipping changes.
+    std::fs::write(&journal_path, format!("not json\n{}", journal)).unwrap();
+    let err = restart_in(&dir).await.err().expect("damaged journal is rejected");
+    assert!(err.to_string().contains("line 1"), "{}", err);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn playlists_run_quizzes_in_order_and_only_playable_ones() {
+    let (base, client) = spawn_server().await;
+    let other = reqwest::Client::builder().cookie_store(true).build().unwrap();
+    let other_csrf = auth(&base, &other, "playlist_neighbour").await;
+    let csrf = auth(&base, &client, "playlist_teacher").await;
+    let create = |client: &reqwest::Client, csrf: &str, title: &str| {
+        let mut quiz = sample_quiz_payload();
+        quiz["title"] = json!(title);
+        client.post(format!("{}/api/v1/quizzes", base)).headers(csrf_headers(csrf)).json(&quiz).send()
+    };
+    let mut own = Vec::new();
+    for title in ["Разминка", "Основной", "Exit ticket"] {
+        let created = create(&client, &csrf, title).await.unwrap().json::<serde_json::Value>().await.unwrap();
+        own.push(created["quiz_id"].as_i64().unwrap());
+    }
+    let foreign_id = |resp: serde_json::Value| resp["quiz_id"].as_i64().unwrap();
+    let private = foreign_id(create(&other, &other_csrf, "Чужой").await.unwrap().json().await.unwrap());
+    let published =
+        foreign_id(create(&other, &other_csrf, "Из библиотеки").await.unwrap().json().await.unwrap());
+    other
+        .post(format!("{}/api/v1/quizzes/{}/publish", base, published))
+        .headers(csrf_headers(&other_csrf))
+        .send()
+        .await
+        .unwrap();
+    let create_session = |playlist: Vec<i64>| {
+        client
+            .post(format!("{}/api/v1/sessions", base))
+            .headers(csrf_headers(&csrf))
+            .json(&json!({"quizId": own[0], "gameMode": "classic", "playlist": playlist}))
+            .send()
+    };
+
+    // Someone else's private quiz cannot be slipped in behind one of your own.
+    let rejected = create_session(vec![own[1], private]).await.unwrap();
+    assert_eq!(rejected.status(), 403);
+    assert_eq!(create_session(vec![own[1], 999_999]).await.unwrap().status(), 404);
+    assert_eq!(create_session(vec![published]).await.unwrap().status(), 201);
+
+    let session = create_session(vec![own[2], own[1]]).await.unwrap();
+    assert_eq!(session.status(), 201);
+    let session = session.json::<serde_json::Value>().await.unwrap();
+    let session_id = session["sessionId"].as_i64().unwrap();
+    let ws_url = format!("{}/ws/sessions/{}", base.replace("http://", "ws://"), session["roomCode"].as_str().unwrap());
+    let (mut ws, _) = tokio_tungstenite::connect_async(&ws_url).await.unwrap();
+    let join = json!({"event":"join_room","payload":{"nickname":"Оля","joinToken": session["joinToken"]}});
+    ws.send(Message::Text(join.to_string())).await.unwrap();
+    next_event(&mut ws, "joined").await;
+
+    for (position, quiz_id) in [(1, own[2]), (2, own[1])] {
+        let next = client
+            .post(format!("{}/api/v1/sessions/{}/next-quiz", base, session_id))
+            .headers(csrf_headers(&csrf))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(next.status(), 200);
+        let next = next.json::<serde_json::Value>().await.unwrap();
+        assert_eq!(next, json!({"quizId": quiz_id, "position": position, "total": 3}));
+        let pushed = next_event(&mut ws, "next_quiz").await;
+        assert_eq!((pushed["quizId"].clone(), pushed["position"].clone()), (json!(quiz_id), json!(position)));
+    }
+    let past_the_end = client
+        .post(format!("{}/api/v1/sessions/{}/next-quiz", base, session_id))
+        .headers(csrf_headers(&csrf))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(past_the_end.status(), 409);
+    let foreign = other
+        .post(format!("{}/api/v1/sessions/{}/next-quiz", base, session_id))
+        .headers(csrf_headers(&other_csrf))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(foreign.status(), 403);
+}
+
+#[tokio::test]
+async fn sqlite_storage_keeps_teachers_and_quizzes_across_restarts() {
+    use quiz_backend::storage::SqliteStore;
+
+    let dir = std::env::temp_dir().join(format!("quiz_sqlite_{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let url = format!("sqlite://{}", dir.join("state.db").display());
+    let sqlite = || -> Arc<dyn SnapshotStore> { Arc::new(SqliteStore::connect_lazy(&url).unwrap()) };
+    // No journal: whatever survives has gone through SQLite.
+    let state = restart_with(sqlite(), None).await.unwrap();
+    assert_eq!(state.store.kind(), "sqlite");
+    let (base, client) = serve(state.clone()).await;
+    let csrf = auth(&base, &client, "sqlite_teacher").await;
+    let mut quiz = sample_quiz_payload();
+    let quiz_id = client
+        .post(format!("{}/api/v1/quizzes", base))
+        .headers(csrf_headers(&csrf))
+        .json(&quiz)
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap()["quiz_id"]
+        .as_i64()
+        .unwrap();
+    client
+        .post(format!("{}/api/v1/quizzes/{}/publish", base, quiz_id))
+        .headers(csrf_headers(&csrf))
+        .send()
+        .await
+        .unwrap();
+    state.persist_core_data().await.unwrap();
+
+    let state = restart_with(sqlite(), None).await.unwrap();
+    let (base, client) = serve(state.clone()).await;
+    let csrf = auth(&base, &client, "sqlite_teacher").await;
+    let stored = client.get(format!("{}/api/v1/quizzes/{}", base, quiz_id)).send().await.unwrap();
+    assert_eq!(stored.status(), 200);
+    let stored = stored.json::<serde_json::Value>().await.unwrap();
+    assert_eq!((stored["title"].clone(), stored["is_published"].clone()), (json!("Математика"), json!(true)));
+    assert_eq!(stored["questions"], quiz["questions"]);
+    // Rows already in the database are updated in place and deleted ones go away.
+    quiz["title"] = json!("Математика, 2-я редакция");
+    let update = client
+        .put(format!("{}/api/v1/quizzes/{}", base, quiz_id))
+        .headers(csrf_headers(&csrf))
+        .json(&quiz)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(update.status(), 200);
+    let second_id = client
+        .post(format!("{}/api/v1/quizzes", base))
+        .headers(csrf_headers(&csrf))
+        .json(&sample_quiz_payload())
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap()["quiz_id"]
+        .as_i64()
+        .unwrap();
+    let delete = client
+        .delete(format!("{}/api/v1/quizzes/{}", base, second_id))
+        .headers(csrf_headers(&csrf))
+        .send()
+        .await
+        .unwrap();
+    assert!(delete.status().is_success());
+    state.persist_core_data().await.unwrap();
+
+    let (base, client) = serve(restart_with(sqlite(), None).await.unwrap()).await;
+    auth(&base, &client, "sqlite_teacher").await;
+    let stored = client.get(format!("{}/api/v1/quizzes/{}", base, quiz_id)).send().await.unwrap();
+    let stored = stored.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(stored["title"], "Математика, 2-я редакция");
+    let deleted = client.get(format!("{}/api/v1/quizzes/{}", base, second_id)).send().await.unwrap();
+    assert_eq!(deleted.status(), 404);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn snapshot_writes_are_debounced_and_replace_the_file() {
+    use std::time::Duration;
+
+    let dir = std::env::temp_dir().join(format!("quiz_debounce_{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("state.json");
+    let store = || JsonFileStore { path: path.display().to_string(), backups: 2 };
+    let mut state = build_state().expect("state");
+    state.store = Arc::new(store());
+    state.journal = None;
+    state.persist_debounce = Duration::from_millis(1500);
+    state.restore().await.unwrap();
+    let (base, client) = serve(state).await;
+    let snapshot_quizzes = |path: &std::path::Path| {
+        let raw = std::fs::read_to_string(path).unwrap();
+        serde_json::from_str::<serde_json::Value>(&raw).unwrap()["quizzes"].as_object().unwrap().len()
+    };
+    let wait_for = |path: std::path::PathBuf| async move {
+        for _ in 0..100 {
+            if path.exists() {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        panic!("{} was never written", path.display());
+    };
+
+    // A burst of changes inside the debounce window ends up in one write.
+    let csrf = auth(&base, &client, "debounce_teacher").await;
+    let mut ids = Vec::new();
+    for _ in 0..3 {
+        let created = client
+            .post(format!("{}/api/v1/quizzes", base))
+            .headers(csrf_headers(&csrf))
+            .json(&sample_quiz_payload())
+            .send()
+            .await
+            .unwrap();
+        ids.push(created.json::<serde_json::Value>().await.unwrap()["quiz_id"].as_i64().unwrap());
+    }
+    assert!(!path.exists(), "the snapshot was written before the debounce window ran out");
+    wait_for(path.clone()).await;
+    assert_eq!(snapshot_quizzes(&path), 3);
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert!(!dir.join("state.json.1").exists(), "the burst was written more than once");
+
+    // The next write keeps the previous file as a backup and leaves no temp file behind.
+    client
+        .delete(format!("{}/api/v1/quizzes/{}", base, ids[0]))
+        .headers(csrf_headers(&csrf))
+        .send()
+        .await
+        .unwrap();
+    wait_for(dir.join("state.json.1")).await;
+    assert_eq!(snapshot_quizzes(&dir.join("state.json.1")), 3);
+    // The backup is copied just before the new file is renamed into place.
+    for _ in 0..50 {
+        if snapshot_quizzes(&path) == 2 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert_eq!(snapshot_quizzes(&path), 2);
+    assert!(!dir.join("state.json.tmp").exists());
+
+    let (base, client) = serve(restart_with(Arc::new(store()), None).await.unwrap()).await;
+    auth(&base, &client, "debounce_teacher").await;
+    for (id, status) in [(ids[0], 404), (ids[1], 200), (ids[2], 200)] {
+        let quiz = client.get(format!("{}/api/v1/quizzes/{}", base, id)).send().await.unwrap();
+        assert_eq!(quiz.status(), status);
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn storage_failures_reach_clients_without_their_details() {
+    let dir = std::env::temp_dir().join(format!("quiz_storage_failure_{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("blocker").join("state.json");
+    let mut state = scratch_state();
+    state.store = Arc::new(JsonFileStore { path: path.display().to_string(), backups: 0 });
+    state.journal = None;
+    state.persist_debounce = std::time::Duration::from_millis(10);
+    state.restore().await.unwrap();
+    // A file where the snapshot directory should be, so every write fails.
+    std::fs::write(dir.join("blocker"), b"").unwrap();
+    let (base, client) = serve(state).await;
+    let csrf = auth(&base, &client, "storage_failure_teacher").await;
+    tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+
+    let resp = client
+        .post(format!("{}/api/v1/quizzes", base))
+        .headers(csrf_headers(&csrf))
+        .json(&sample_quiz_payload())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 503);
+    let body = resp.text().await.unwrap();
+    let error = serde_json::from_str::<serde_json::Value>(&body).unwrap()["error"].clone();
+    assert_eq!(error["code"], "STORAGE_UNAVAILABLE");
+    assert_eq!(error["message"], "storage error");
+    assert!(!error["request_id"].as_str().unwrap().is_empty());
+    assert!(!body.contains("blocker") && !body.contains("os error"));
+}
+
+#[tokio::test]
+async fn students_look_back_at_their_answers_during_the_review_window() {
+    let mut state = scratch_state();
+    state.restore().await.expect("restore");
+    let (base, client) = serve(state.clone()).await;
+    let csrf = auth(&base, &client, "review_window_teacher").await;
+    let quiz_id = client
+        .post(format!("{}/api/v1/quizzes", base))
+        .headers(csrf_headers(&csrf))
+        .json(&sample_quiz_payload())
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap()["quiz_id"]
+        .as_i64()
+        .unwrap();
+    let session = client
+        .post(format!("{}/api/v1/sessions", base))
+        .headers(csrf_headers(&csrf))
+        .json(&json!({"quizId": quiz_id, "gameMode": "classic"}))
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    let session_id = session["sessionId"].as_i64().unwrap();
+    let ws_url = format!("{}/ws/sessions/{}", base.replace("http://", "ws://"), session["roomCode"].as_str().unwrap());
+    let (mut ws, _) = tokio_tungstenite::connect_async(&ws_url).await.unwrap();
+    let join = json!({"event":"join_room","payload":{"nickname":"Оля","joinToken": session["joinToken"]}});
+    ws.send(Message::Text(join.to_string())).await.unwrap();
+    next_event(&mut ws, "joined").await;
+    client
+        .post(format!("{}/api/v1/sessions/{}/start", base, session_id))
+        .headers(csrf_headers(&csrf))
+        .send()
+        .await
+        .unwrap();
+    next_event(&mut ws, "start_quiz").await;
+    let submit = |question: &str, answer: serde_json::Value| {
+        Message::Text(json!({"event":"answer_submit","payload":{"questionId": question, "answer": answer}}).to_string())
+    };
+    ws.send(submit("q1", json!({"text": "4"}))).await.unwrap();
+    assert_eq!(next_event(&mut ws, "answer_result").await["correct"], true);
+
+    let ended = client
+        .post(format!("{}/api/v1/sessions/{}/end", base, session_id))
+        .headers(csrf_headers(&csrf))
+        .json(&json!({"reviewMinutes": 5}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(ended.status(), 200);
+    let ended = ended.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(ended["status"], "review");
+    let review_until = ended["reviewUntil"].as_str().unwrap().to_string();
+    assert_eq!(next_event(&mut ws, "end_quiz").await["reviewUntil"], review_until.as_str());
+
+    // Answers are frozen: a late one is not recorded and does not show up in the review.
+    ws.send(submit("q2", json!({"optionId": "o1"}))).await.unwrap();
+    ws.send(Message::Text(json!({"event":"review_request","payload":{}}).to_string())).await.unwrap();
+    let review = next_event(&mut ws, "review_data").await;
+    assert_eq!(review["reviewUntil"], review_until.as_str());
+    let answers = review["answers"].as_array().unwrap();
+    assert_eq!(answers.len(), 1);
+    assert_eq!((answers[0]["questionId"].clone(), answers[0]["correct"].clone()), (json!("q1"), json!(true)));
+    assert_eq!(answers[0]["answer"], json!({"text": "4"}));
+
+    // When the window closes the session is finished and the room goes away.
+    state.close_review(session_id).await;
+    assert_eq!(next_event(&mut ws, "review_closed").await["sessionId"], session_id);
+    let sessions = client.get(format!("{}/api/v1/sessions", base)).send().await.unwrap();
+    let sessions = sessions.json::<serde_json::Value>().await.unwrap();
+    let listed = sessions["items"].as_array().unwrap().iter().find(|s| s["id"] == session_id).unwrap().clone();
+    assert_eq!(listed["status"], "finished");
+    let (mut late, _) = tokio_tungstenite::connect_async(&ws_url).await.unwrap();
+    assert!(matches!(late.next().await, None | Some(Err(_)) | Some(Ok(Message::Close(_)))));
+}
+
+#[tokio::test]
+async fn teacher_sessions_survive_restarts_slide_and_are_evicted() {
+    let dir = std::env::temp_dir().join(format!("quiz_teacher_sessions_{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let state = restart_in(&dir).await.unwrap();
+    let (base, client) = serve(state.clone()).await;
+    auth(&base, &client, "session_restart_teacher").await;
+    let login = client
+        .post(format!("{}/api/v1/auth/login", base))
+        .json(&json!({"login": "session_restart_teacher", "password": "password123"}))
+        .send()
+        .await
+        .unwrap();
+    let sid = login.cookies().find(|c| c.name() == "teacher_session").unwrap().value().to_string();
+    state.persist_core_data().await.unwrap();
+
+    // The cookie keeps working against a restarted server; cookies are not bound to the port.
+    let state = restart_in(&dir).await.unwrap();
+    let (base, _) = serve(state.clone()).await;
+    let me = client.get(format!("{}/api/v1/auth/me", base)).send().await.unwrap();
+    assert_eq!(me.status(), 200);
+
+    // Using the session moves its expiry forward, and the renewal is kept.
+    let mut session = state.teacher_session(&sid).await.unwrap();
+    session.expires_at = chrono::Utc::now() + chrono::Duration::seconds(30);
+    state.shared.put_teacher_session(&sid, &session).await.unwrap();
+    assert_eq!(client.get(format!("{}/api/v1/auth/me", base)).send().await.unwrap().status(), 200);
+    let renewed = state.teacher_session(&sid).await.unwrap().expires_at;
+    assert!(renewed > chrono::Utc::now() + state.session_ttl - chrono::Duration::minutes(1));
+    let state = restart_in(&dir).await.unwrap();
+    assert_eq!(state.teacher_session(&sid).await.unwrap().expires_at, renewed);
+
+    // An expired session is refused straight away and evicted by the sweeper, whose first pass on
+    // startup may already have run by now.
+    let (base, _) = serve(state.clone()).await;
+    session.expires_at = chrono::Utc::now() - chrono::Duration::seconds(1);
+    state.shared.put_teacher_session(&sid, &session).await.unwrap();
+    assert_eq!(client.get(format!("{}/api/v1/auth/me", base)).send().await.unwrap().status(), 401);
+    state.shared.evict_expired_sessions().await.unwrap();
+    assert!(state.shared.get_teacher_session(&sid).await.unwrap().is_none());
+    state.persist_core_data().await.unwrap();
+    let state = restart_in(&dir).await.unwrap();
+    assert!(state.shared.get_teacher_session(&sid).await.unwrap().is_none());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+// Two instances behind one shared store. Runs against Redis when TEST_REDIS_URL points at one, and
+// against the in-memory store of the first instance otherwise.
+#[tokio::test]
+async fn instances_share_teacher_sessions_and_rate_limits() {
+    use quiz_backend::shared::{RedisSharedStore, SharedStore};
+
+    let dirs: Vec<_> =
+        (0..2).map(|_| std::env::temp_dir().join(format!("quiz_shared_{}", uuid::Uuid::new_v4()))).collect();
+    for dir in &dirs {
+        std::fs::create_dir_all(dir).unwrap();
+    }
+    let mut first = restart_in(&dirs[0]).await.unwrap();
+    let mut second = restart_in(&dirs[1]).await.unwrap();
+    let shared: Arc<dyn SharedStore> = match std::env::var("TEST_REDIS_URL") {
+        Ok(url) => {
+            let prefix = format!("quiz-test-{}:", uuid::Uuid::new_v4());
+            Arc::new(RedisSharedStore::connect(&url, &prefix).await.expect("redis"))
+        }
+        Err(_) => first.shared.clone(),
+    };
+    first.shared = shared.clone();
+    second.shared = shared;
+    let (first_base, client) = serve(first).await;
+    let (second_base, _) = serve(second).await;
+    let csrf = auth(&first_base, &client, "shared_store_teacher").await;
+
+    // A session started on one instance is honoured by the other, CSRF token included.
+    let own_sessions = |base: &str| client.get(format!("{}/api/v1/sessions", base)).send();
+    assert_eq!(own_sessions(&second_base).await.unwrap().status(), 200);
+    let logout = client
+        .post(format!("{}/api/v1/auth/logout", second_base))
+        .headers(csrf_headers(&csrf))
+        .send()
+        .await
+        .unwrap();
+    assert!(logout.status().is_success());
+    assert_eq!(own_sessions(&first_base).await.unwrap().status(), 401);
+
+    // Requests spread over both instances count against one limit.
+    let reset = |base: &str| {
+        client.post(format!("{}/api/v1/auth/password-reset", base)).json(&json!({"login": "nobody-here"})).send()
+    };
+    for base in [&first_base, &second_base, &first_base, &second_base, &first_base] {
+        assert_ne!(reset(base).await.unwrap().status(), 429);
+    }
+    assert_eq!(reset(&second_base).await.unwrap().status(), 429);
+    for dir in dirs {
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
+
+// Needs an empty MySQL database: set TEST_DATABASE_URL to run it, otherwise it is skipped.
+#[tokio::test]
+async fn local_snapshot_is_imported_into_mysql_once() {
+    use quiz_backend::storage::import_snapshot_into_mysql;
+
+    let Ok(url) = std::env::var("TEST_DATABASE_URL") else {
+        eprintln!("TEST_DATABASE_URL is not set, skipping the MySQL import test");
+        return;
+    };
+    let pool = sqlx::mysql::MySqlPoolOptions::new().max_connections(2).connect(&url).await.unwrap();
+    sqlx::migrate!("./migrations").run(&pool).await.unwrap();
+    let dir = std::env::temp_dir().join(format!("quiz_mysql_import_{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let state = restart_in(&dir).await.unwrap();
+    let (base, client) = serve(state.clone()).await;
+    let csrf = auth(&base, &client, "mysql_import_teacher").await;
+    let mut ids = Vec::new();
+    for title in ["Дроби", "Проценты"] {
+        let mut quiz = sample_quiz_payload();
+        quiz["title"] = json!(title);
+        let created = client
+            .post(format!("{}/api/v1/quizzes", base))
+            .headers(csrf_headers(&csrf))
+            .json(&quiz)
+            .send()
+            .await
+            .unwrap();
+        ids.push(created.json::<serde_json::Value>().await.unwrap()["quiz_id"].as_i64().unwrap());
+    }
+    client
+        .post(format!("{}/api/v1/quizzes/{}/publish", base, ids[0]))
+        .headers(csrf_headers(&csrf))
+        .send()
+        .await
+        .unwrap();
+
+    let source = state.store.location();
+    let snapshot = state.db.snapshot().await;
+    assert_eq!(import_snapshot_into_mysql(&pool, &source, &snapshot).await.unwrap(), Some((1, 2)));
+    let login: String =
+        sqlx::query_scalar("SELECT t.login FROM teachers t JOIN quizzes q ON q.owner_teacher_id = t.id WHERE q.id = ?")
+            .bind(ids[1])
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+    assert_eq!(login, "mysql_import_teacher");
+    let title: String =
+        sqlx::query_scalar("SELECT title FROM quizzes WHERE id = ?").bind(ids[1]).fetch_one(&pool).await.unwrap();
+    assert_eq!(title, "Проценты");
+    let questions: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM quiz_questions WHERE quiz_id = ?")
+        .bind(ids[0])
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(questions, sample_quiz_payload()["questions"].as_array().unwrap().len() as i64);
+    let published: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM quiz_publications WHERE quiz_id IN (?, ?)")
+        .bind(ids[0])
+        .bind(ids[1])
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(published, 1);
+
+    // The next startup finds the import recorded and leaves MySQL alone, even with new local data.
+    client
+        .post(format!("{}/api/v1/quizzes", base))
+        .headers(csrf_headers(&csrf))
+        .json(&sample_quiz_payload())
+        .send()
+        .await
+        .unwrap();
+    let snapshot = state.db.snapshot().await;
+    assert_eq!(import_snapshot_into_mysql(&pool, &source, &snapshot).await.unwrap(), None);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn feature_usage_is_counted_per_day_only_when_enabled() {
+    std::env::set_var("ADMIN_LOGINS", "backup_admin");
+    let dir = std::env::temp_dir().join(format!("quiz_usage_stats_{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let mut state = restart_in(&dir).await.unwrap();
+    state.usage_stats_enabled = true;
+    let (base, client) = serve(state.clone()).await;
+    let csrf = auth(&base, &client, "usage_teacher").await;
+    let mut quiz_ids = Vec::new();
+    for _ in 0..2 {
+        let created = client
+            .post(format!("{}/api/v1/quizzes", base))
+            .headers(csrf_headers(&csrf))
+            .json(&sample_quiz_payload())
+            .send()
+            .await
+            .unwrap();
+        quiz_ids.push(created.json::<serde_json::Value>().await.unwrap()["quiz_id"].as_i64().unwrap());
+    }
+    let start_session = |base: String, csrf: &str, playlist: Vec<i64>| {
+        client
+            .post(format!("{}/api/v1/sessions", base))
+            .headers(csrf_headers(csrf))
+            .json(&json!({"quizId": quiz_ids[0], "gameMode": "classic", "playlist": playlist}))
+            .send()
+    };
+    assert_eq!(start_session(base.clone(), &csrf, vec![]).await.unwrap().status(), 201);
+    assert_eq!(start_session(base.clone(), &csrf, vec![quiz_ids[1]]).await.unwrap().status(), 201);
+    let usage = |base: String| async move {
+        let admin = reqwest::Client::builder().cookie_store(true).build().unwrap();
+        auth(&base, &admin, "backup_admin").await;
+        let stats = admin.get(format!("{}/api/v1/admin/usage-stats?days=7", base)).send().await.unwrap();
+        assert_eq!(stats.status(), 200);
+        stats.json::<serde_json::Value>().await.unwrap()
+    };
+    let forbidden = client.get(format!("{}/api/v1/admin/usage-stats", base)).send().await.unwrap();
+    assert_eq!(forbidden.status(), 403);
+    let stats = usage(base.clone()).await;
+    assert_eq!(stats["enabled"], true);
+    assert_eq!(stats["totals"], json!({"game_mode:classic": 2, "playlist_session": 1}));
+    let today = chrono::Utc::now().date_naive().to_string();
+    assert_eq!(stats["items"], json!([{"date": today, "features": {"game_mode:classic": 2, "playlist_session": 1}}]));
+    state.persist_core_data().await.unwrap();
+
+    // Counters outlive a restart; with collection off nothing new is added.
+    let (base, _) = serve(restart_in(&dir).await.unwrap()).await;
+    let csrf = auth(&base, &client, "usage_teacher").await;
+    assert_eq!(start_session(base.clone(), &csrf, vec![]).await.unwrap().status(), 201);
+    let stats = usage(base).await;
+    assert_eq!(stats["enabled"], false);
+    assert_eq!(stats["totals"], json!({"game_mode:classic": 2, "playlist_session": 1}));
+    std::fs::remove_dir_all(&dir).unwrap();
 }
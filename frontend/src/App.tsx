@@ -4,7 +4,7 @@ import { Navigate, Route, Routes, useNavigate, useParams, useSearchParams, Link
 import { motion } from 'framer-motion'
 import { QRCodeSVG } from 'qrcode.react'
 import { api } from './lib/api'
-import { connectRoom, sendWs } from './lib/ws'
+import { connectRoom, joinAsStudent, sendWs, trackResume } from './lib/ws'
 import type { Question, Quiz, WsEnvelope } from './types'
 import { GameCanvas } from './components/GameCanvas'
 import { QuestionCard } from './components/QuestionCard'
@@ -99,8 +99,8 @@ function DashboardPage() {
   }, [])
 
   async function startSession(quizId: number, mode: 'platformer' | 'shooter' | 'classic') {
-    const response = (await api.createSession(quizId, mode)) as { sessionId: number; roomCode: string }
-    navigate(`/teacher/sessions/${response.sessionId}/waiting?room=${response.roomCode}`)
+    const response = (await api.createSession(quizId, mode)) as { sessionId: number; roomCode: string; joinToken: string }
+    navigate(`/teacher/sessions/${response.sessionId}/waiting?room=${response.roomCode}&token=${response.joinToken}`)
   }
 
   return shell(
@@ -628,6 +628,7 @@ function TeacherWaitingPage() {
   const { id } = useParams()
   const [sp] = useSearchParams()
   const room = sp.get('room') ?? ''
+  const token = sp.get('token') ?? ''
   const [participants, setParticipants] = useState<string[]>([])
   const navigate = useNavigate()
 
@@ -652,7 +653,7 @@ function TeacherWaitingPage() {
     <div className="grid gap-4 md:grid-cols-2">
       <div className="rounded-2xl bg-white/90 p-4 shadow">
         <p className="text-sm">Комната: <b>{room}</b></p>
-        <QRCodeSVG value={`${window.location.origin}/join?room=${room}`} className="mt-3" />
+        <QRCodeSVG value={`${window.location.origin}/join?room=${room}&token=${token}`} className="mt-3" />
         <button className="mt-4 rounded bg-emerald-900 px-4 py-2 text-white" onClick={start}>Запустить квиз</button>
       </div>
       <div className="rounded-2xl bg-white/90 p-4 shadow">
@@ -823,6 +824,7 @@ function JoinPage() {
     const initial = sp.get('room')
     if (initial) setRoom(initial)
   }, [sp])
+  const token = sp.get('token') ?? ''
 
   function join() {
     if (room.trim().length < 3) {
@@ -833,8 +835,13 @@ function JoinPage() {
       setError('Ник должен содержать минимум 2 символа')
       return
     }
+    if (!token) {
+      setError('Откройте ссылку или QR-код учителя: по одному коду комнаты войти нельзя')
+      return
+    }
     setError('')
     localStorage.setItem('student_nickname', nickname.trim())
+    localStorage.setItem('student_join_token', token)
     navigate(`/wait/${room.trim().toUpperCase()}`)
   }
 
@@ -854,10 +861,12 @@ function StudentWaitPage() {
   const { roomCode } = useParams()
   const navigate = useNavigate()
   const nickname = useMemo(() => localStorage.getItem('student_nickname') ?? '', [])
+  const joinToken = useMemo(() => localStorage.getItem('student_join_token') ?? '', [])
 
   useEffect(() => {
     if (!roomCode || !nickname) return
     const ws = connectRoom(roomCode, (msg) => {
+      trackResume(ws, roomCode, nickname, joinToken, msg)
       if (msg.event === 'start_quiz') {
         const payload = msg.payload as { gameMode?: string }
         if (payload.gameMode) {
@@ -866,9 +875,9 @@ function StudentWaitPage() {
         navigate(`/play/${roomCode}`)
       }
     })
-    ws.onopen = () => sendWs(ws, 'join_room', { role: 'student', nickname })
+    ws.onopen = () => joinAsStudent(ws, roomCode, nickname, joinToken)
     return () => ws.close()
-  }, [roomCode, nickname, navigate])
+  }, [roomCode, nickname, joinToken, navigate])
 
   return shell('Ожидание старта', <div className="rounded-2xl bg-white/90 p-4 shadow">Вы подключены как <b>{nickname}</b>. Ждите запуск учителем.</div>)
 }
@@ -876,6 +885,7 @@ function StudentWaitPage() {
 function StudentPlayPage() {
   const { roomCode } = useParams()
   const nickname = useMemo(() => localStorage.getItem('student_nickname') ?? '', [])
+  const joinToken = useMemo(() => localStorage.getItem('student_join_token') ?? '', [])
   const [socket, setSocket] = useState<WebSocket | null>(null)
   const initialMode = (() => {
     const raw = localStorage.getItem('session_game_mode')
@@ -945,6 +955,15 @@ function StudentPlayPage() {
   useEffect(() => {
     if (!roomCode || !nickname) return
     const ws = connectRoom(roomCode, (msg) => {
+      trackResume(ws, roomCode, nickname, joinToken, msg)
+      if (msg.event === 'resumed') {
+        const payload = msg.payload as { question: Question | null }
+        if (payload.question) setQuestion(payload.question)
+      }
+      if (msg.event === 'state_sync') {
+        const payload = msg.payload as { participant?: { question: Question | null } }
+        if (payload.participant?.question) setQuestion(payload.participant.question)
+      }
       if (msg.event === 'question_push') {
         setAwaitingNextQuestion(false)
         setQuestion((msg.payload as { question: Question }).question)
@@ -969,14 +988,14 @@ function StudentPlayPage() {
       if (msg.event === 'end_quiz') navigate(`/done/${roomCode}`)
     })
     ws.onopen = () => {
-      sendWs(ws, 'join_room', { role: 'student', nickname })
+      joinAsStudent(ws, roomCode, nickname, joinToken)
       if (mode === 'classic') {
         setTimeout(() => sendWs(ws, 'request_question', { reason: 'level_up' }), 150)
       }
     }
     setSocket(ws)
     return () => ws.close()
-  }, [roomCode, nickname, navigate, mode])
+  }, [roomCode, nickname, joinToken, navigate, mode])
 
   const triggerQuestion = (reason: 'death' | 'level_up') => {
     if (mode !== 'classic' && mobileView && portrait) return
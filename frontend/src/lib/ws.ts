@@ -18,3 +18,22 @@ export function sendWs(socket: WebSocket, event: string, payload: Record<string,
     socket.send(JSON.stringify({ event, payload }))
   }
 }
+
+const resumeKey = (roomCode: string) => `student_resume_token:${roomCode}`
+
+// Comes back as the same participant when the room handed out a resume token earlier.
+export function joinAsStudent(socket: WebSocket, roomCode: string, nickname: string, joinToken: string) {
+  const resumeToken = localStorage.getItem(resumeKey(roomCode)) ?? undefined
+  sendWs(socket, 'join_room', { role: 'student', nickname, joinToken, resumeToken })
+}
+
+// Keeps the resume token current and falls back to a plain join when the server no longer knows it.
+export function trackResume(socket: WebSocket, roomCode: string, nickname: string, joinToken: string, msg: WsEnvelope) {
+  if (msg.event === 'joined' || msg.event === 'resumed') {
+    localStorage.setItem(resumeKey(roomCode), (msg.payload as { resumeToken: string }).resumeToken)
+  }
+  if (msg.event === 'join_rejected' && (msg.payload as { reason?: string }).reason === 'invalid_resume_token') {
+    localStorage.removeItem(resumeKey(roomCode))
+    sendWs(socket, 'join_room', { role: 'student', nickname, joinToken })
+  }
+}
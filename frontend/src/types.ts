@@ -1,4 +1,6 @@
-export type QuestionType = 'open' | 'single' | 'multi'
+export type Difficulty = 'easy' | 'medium' | 'hard'
+
+export type QuestionType = 'open' | 'single' | 'multi' | 'ordering' | 'numeric' | 'cloze'
 
 export type QuizOption = {
   id: string
@@ -6,9 +8,18 @@ export type QuizOption = {
 }
 
 export type AnswerKey =
-  | { text: string }
+  | { text: string; accepted?: string[]; maxTypos?: number }
   | { optionId: string }
   | { optionIds: string[] }
+  | { order: string[] }
+  | {
+      value: number
+      tolerance?: number
+      relativeTolerance?: number
+      unit?: string
+      unitFactors?: Record<string, number>
+    }
+  | { blanks: string[][] }
 
 export type Question = {
   id: string
@@ -16,12 +27,32 @@ export type Question = {
   prompt: string
   options?: QuizOption[]
   answer: AnswerKey
+  timeLimitSecs?: number
+  points?: number
+  difficulty?: Difficulty
+  tags?: string[]
+  explanation?: string
+}
+
+export type QuizSettings = {
+  shuffleQuestions?: boolean
+  shuffleOptions?: boolean
+  retries?: number
+  passingPct?: number
+  showCorrectAnswer?: boolean
+  hintsPerStudent?: number
 }
 
 export type Quiz = {
   title: string
   description?: string
   questions: Question[]
+  difficulty?: Difficulty
+  tags?: string[]
+  settings?: QuizSettings
+  subject?: string
+  grade?: number
+  language?: string
 }
 
 export type WsEnvelope = {